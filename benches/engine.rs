@@ -0,0 +1,139 @@
+use coding_test::bench::{self, Dataset};
+use coding_test::engine::PaymentsEngine;
+use coding_test::source::{CsvSource, TransactionSource};
+use coding_test::transaction::Transaction;
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+
+const ROWS: u32 = 10_000;
+const CLIENTS: u32 = 100;
+
+fn parse(input: &str) -> Vec<Transaction> {
+    let mut source = CsvSource::new(input.as_bytes());
+    let mut transactions = Vec::new();
+    while let Some(transaction) = source.next() {
+        transactions.push(transaction.expect("benchmark input is valid"));
+    }
+    transactions
+}
+
+fn deposits(first_tx: u32) -> String {
+    let mut input = String::from("type,client,tx,amount\n");
+    for tx in first_tx..first_tx + ROWS {
+        input.push_str(&format!("deposit,{},{},10.5\n", tx % CLIENTS + 1, tx));
+    }
+    input
+}
+
+fn rows(tx_type: &str) -> String {
+    let mut input = String::from("type,client,tx,amount\n");
+    for tx in 1..=ROWS {
+        let amount = if tx_type == "withdrawal" { "1.25" } else { "" };
+        input.push_str(&format!(
+            "{},{},{},{}\n",
+            tx_type,
+            tx % CLIENTS + 1,
+            tx,
+            amount
+        ));
+    }
+    input
+}
+
+fn engine_with(input: &str) -> PaymentsEngine {
+    let mut engine = PaymentsEngine::default();
+    for transaction in parse(input) {
+        engine.process_transaction(transaction).unwrap();
+    }
+    engine
+}
+
+// Transactions are parsed in setup, so only applying them to accounts is measured.
+fn bench_hot_paths(c: &mut Criterion) {
+    let mut group = c.benchmark_group("hot_paths");
+    group.throughput(Throughput::Elements(u64::from(ROWS)));
+
+    let input = deposits(1);
+    group.bench_function("deposit", |b| {
+        b.iter_batched(
+            || (PaymentsEngine::default(), parse(&input)),
+            |(mut engine, transactions)| {
+                for transaction in transactions {
+                    engine.process_transaction(transaction).unwrap();
+                }
+                engine
+            },
+            BatchSize::LargeInput,
+        )
+    });
+
+    let withdrawals = rows("withdrawal");
+    group.bench_function("withdrawal", |b| {
+        b.iter_batched(
+            || (engine_with(&deposits(ROWS + 1)), parse(&withdrawals)),
+            |(mut engine, transactions)| {
+                for transaction in transactions {
+                    engine.process_transaction(transaction).unwrap();
+                }
+                engine
+            },
+            BatchSize::LargeInput,
+        )
+    });
+
+    let disputes = rows("dispute");
+    group.bench_function("dispute", |b| {
+        b.iter_batched(
+            || (engine_with(&input), parse(&disputes)),
+            |(mut engine, transactions)| {
+                for transaction in transactions {
+                    engine.process_transaction(transaction).unwrap();
+                }
+                engine
+            },
+            BatchSize::LargeInput,
+        )
+    });
+
+    let resolves = rows("resolve");
+    group.bench_function("resolve", |b| {
+        b.iter_batched(
+            || {
+                let mut engine = engine_with(&input);
+                for transaction in parse(&disputes) {
+                    engine.process_transaction(transaction).unwrap();
+                }
+                (engine, parse(&resolves))
+            },
+            |(mut engine, transactions)| {
+                for transaction in transactions {
+                    engine.process_transaction(transaction).unwrap();
+                }
+                engine
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+}
+
+// Parsing and processing of a synthetic dataset, as the binary does it.
+fn bench_dataset(c: &mut Criterion) {
+    let dataset = Dataset::new(u64::from(ROWS));
+    let mut input = Vec::new();
+    bench::generate(&mut input, &dataset).unwrap();
+
+    let mut group = c.benchmark_group("dataset");
+    group.throughput(Throughput::Elements(dataset.rows));
+    group.bench_function("process_csv", |b| {
+        b.iter(|| {
+            let mut engine = PaymentsEngine::default();
+            engine.process_csv(input.as_slice()).unwrap();
+            engine
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_hot_paths, bench_dataset);
+criterion_main!(benches);
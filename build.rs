@@ -0,0 +1,30 @@
+// Code of the gRPC service is generated from `proto/payments.proto` with `grpc` feature. The file is
+// parsed in Rust, so building doesn't need `protoc`.
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    #[cfg(feature = "grpc")]
+    if let Err(err) = grpc::compile() {
+        panic!("can not compile proto/payments.proto: {}", err);
+    }
+}
+
+#[cfg(feature = "grpc")]
+mod grpc {
+    use prost::Message;
+    use std::error::Error;
+
+    const PROTO: &str = "proto/payments.proto";
+
+    pub fn compile() -> Result<(), Box<dyn Error>> {
+        println!("cargo:rerun-if-changed={}", PROTO);
+        let parsed = protobuf_parse::Parser::new()
+            .pure()
+            .include("proto")
+            .input(PROTO)
+            .file_descriptor_set()?;
+        let encoded = protobuf::Message::write_to_bytes(&parsed)?;
+        let descriptors = prost_types::FileDescriptorSet::decode(encoded.as_slice())?;
+        tonic_build::configure().compile_fds(descriptors)?;
+        Ok(())
+    }
+}
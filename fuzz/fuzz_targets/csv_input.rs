@@ -0,0 +1,15 @@
+#![no_main]
+
+use coding_test::engine::PaymentsEngine;
+use libfuzzer_sys::fuzz_target;
+
+// Arbitrary bytes are read as a CSV file, deserialized into transactions and applied. Invalid
+// records and rejected transactions are expected, panics are not, and accounts have to stay
+// consistent.
+fuzz_target!(|data: &[u8]| {
+    let mut engine = PaymentsEngine::default();
+    let _ = engine.process_csv(data);
+    for account in engine.accounts().values() {
+        assert_eq!(account.total, account.available + account.held);
+    }
+});
@@ -0,0 +1,37 @@
+/* C interface of the payments engine, built with `cdylib` feature. */
+#ifndef PAYMENTS_ENGINE_H
+#define PAYMENTS_ENGINE_H
+
+#include <stddef.h>
+#include <stdint.h>
+#include <sys/types.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define PAYMENTS_OK 0
+#define PAYMENTS_ERROR -1
+
+typedef struct PaymentsEngine PaymentsEngine;
+
+/* Creates an engine with default policies. Returns NULL if it can't be created. */
+PaymentsEngine *payments_engine_new(void);
+
+/* Processes a CSV document of `len` bytes with a header row. Invalid rows and rejected
+ * transactions are skipped. Returns PAYMENTS_OK, or PAYMENTS_ERROR if input can't be read. */
+int32_t payments_engine_process_csv(PaymentsEngine *engine, const uint8_t *data, size_t len);
+
+/* Writes accounts as a NUL-terminated JSON array into `buffer` of `capacity` bytes if it fits.
+ * Returns the length of the JSON without the NUL, so it may be called with a NULL buffer first,
+ * or PAYMENTS_ERROR. */
+ssize_t payments_engine_accounts_json(const PaymentsEngine *engine, char *buffer, size_t capacity);
+
+/* Frees an engine. NULL is ignored. */
+void payments_engine_free(PaymentsEngine *engine);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -1,56 +1,352 @@
-use crate::transaction::{Deposit, Withdrawal};
-
+use crate::decimal_format;
+use crate::dispute_policy::DisputePolicy;
+use crate::engine_config::EngineConfig;
+use crate::error::TransactionError;
+use crate::limits::{Limits, SECONDS_PER_DAY};
+use crate::overflow::{self, OverflowPolicy};
+use crate::period::Period;
+use crate::statemachine::{Effect, Event};
+use crate::transaction::{Adjust, Annotate, Deposit, Lock, Unlock, Withdrawal, WriteOff};
+
+use crate::amount::Amount;
+use anyhow::bail;
 use log::{error, info, warn};
-use rust_decimal::prelude::*;
-use serde::{Serialize, Serializer};
-
-fn to_decimal_number<S>(num: &Decimal, s: S) -> Result<S::Ok, S::Error>
-where
-    S: Serializer,
-{
-    let str = format!("{:.4}", num);
-    s.serialize_str(&str)
-}
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::ops::{Deref, Index, IndexMut};
+use std::str::FromStr;
 
-#[derive(Serialize)]
+#[derive(Clone, Serialize)]
 pub struct Account {
     #[serde(rename(serialize = "client"))]
     id: u16,
-    #[serde(serialize_with = "to_decimal_number")]
-    pub available: Decimal,
-    #[serde(serialize_with = "to_decimal_number")]
-    pub held: Decimal,
-    #[serde(serialize_with = "to_decimal_number")]
-    pub total: Decimal,
-    pub locked: bool,
+    #[serde(serialize_with = "decimal_format::serialize")]
+    available: Amount,
+    #[serde(serialize_with = "decimal_format::serialize")]
+    held: Amount,
+    #[serde(serialize_with = "decimal_format::serialize")]
+    total: Amount,
+    locked: bool,
+    // Part of held funds from disputed withdrawals, the rest is from disputed deposits. They are
+    // booked to different liability accounts.
+    #[serde(skip_serializing)]
+    held_withdrawals: Amount,
+    #[serde(skip_serializing)]
+    transactions: Transactions,
+    #[serde(skip_serializing)]
+    adjustments: Vec<Adjustment>,
+    // Time of the latest transaction for the account, used to find dormant accounts.
+    #[serde(skip_serializing)]
+    last_activity: u64,
+    // Sum of withdrawals on the day `withdrawal_day`, used by the daily withdrawal limit.
     #[serde(skip_serializing)]
-    pub transactions: Vec<DepositedTransaction>,
+    withdrawn_today: Amount,
+    #[serde(skip_serializing)]
+    withdrawal_day: u64,
+}
+
+// Balances and lock of an account at one moment, for example before a transaction is applied.
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct BalanceSnapshot {
+    pub available: Amount,
+    pub held: Amount,
+    pub total: Amount,
+    pub locked: bool,
 }
 
-#[derive(Clone, Copy, PartialEq)]
-enum DepositedTransactionStatus {
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub enum DepositedTransactionStatus {
     Accepted,
     Dispute,
     Resolved,
     Chargeback,
+    Represented,
 }
 
-#[derive(Clone, Copy)]
+impl DepositedTransactionStatus {
+    pub fn name(&self) -> &'static str {
+        match self {
+            DepositedTransactionStatus::Accepted => "accepted",
+            DepositedTransactionStatus::Dispute => "dispute",
+            DepositedTransactionStatus::Resolved => "resolved",
+            DepositedTransactionStatus::Chargeback => "chargeback",
+            DepositedTransactionStatus::Represented => "represented",
+        }
+    }
+}
+
+// Status is given by its name, for example `dispute`.
+impl FromStr for DepositedTransactionStatus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<DepositedTransactionStatus, String> {
+        match s {
+            "accepted" => Ok(DepositedTransactionStatus::Accepted),
+            "dispute" => Ok(DepositedTransactionStatus::Dispute),
+            "resolved" => Ok(DepositedTransactionStatus::Resolved),
+            "chargeback" => Ok(DepositedTransactionStatus::Chargeback),
+            "represented" => Ok(DepositedTransactionStatus::Represented),
+            _ => Err(format!("unknown transaction status: {}", s)),
+        }
+    }
+}
+
+// Which way funds of a recorded transaction went. Withdrawals are recorded only if their disputes
+// are enabled, see `EngineConfig`.
+#[derive(Clone, Copy, PartialEq, Debug, Default, Serialize, Deserialize)]
+pub enum Direction {
+    #[default]
+    Deposit,
+    Withdrawal,
+}
+
+impl Direction {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Direction::Deposit => "deposit",
+            Direction::Withdrawal => "withdrawal",
+        }
+    }
+}
+
+// Transactions stored before withdrawals were recorded are all deposits.
+#[derive(Clone, Serialize, Deserialize)]
 pub struct DepositedTransaction {
     tx_id: u32,
-    amount: Decimal,
+    amount: Amount,
     status: DepositedTransactionStatus,
+    period: Option<Period>,
+    #[serde(default)]
+    direction: Direction,
+    // Time of the transaction by the configured clock, used by dispute window.
+    #[serde(default)]
+    time: u64,
+    // Part of the transaction held by its latest dispute and part of that charged back, none if
+    // it's the whole amount.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    disputed: Option<Amount>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    charged_back: Option<Amount>,
+    // Time of the latest dispute, used to resolve disputes which stay open too long.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    disputed_at: Option<u64>,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub enum AdjustmentKind {
+    WriteOff,
+    Lock,
+    Unlock,
+    Adjust,
+    Annotate,
+}
+
+impl AdjustmentKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            AdjustmentKind::WriteOff => "write_off",
+            AdjustmentKind::Lock => "lock",
+            AdjustmentKind::Unlock => "unlock",
+            AdjustmentKind::Adjust => "adjust",
+            AdjustmentKind::Annotate => "annotate",
+        }
+    }
+}
+
+// Change made by an admin transaction instead of by a client. Admin transactions which don't move
+// funds (lock, unlock, annotate) are kept with a zero amount.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Adjustment {
+    tx_id: u32,
+    amount: Amount,
+    kind: AdjustmentKind,
+}
+
+impl Adjustment {
+    pub fn amount(&self) -> Amount {
+        self.amount
+    }
+
+    pub fn kind(&self) -> AdjustmentKind {
+        self.kind
+    }
+}
+
+impl DepositedTransaction {
+    pub fn tx_id(&self) -> u32 {
+        self.tx_id
+    }
+
+    pub fn amount(&self) -> Amount {
+        self.amount
+    }
+
+    pub fn status(&self) -> DepositedTransactionStatus {
+        self.status
+    }
+
+    pub fn period(&self) -> Option<&Period> {
+        self.period.as_ref()
+    }
+
+    pub fn direction(&self) -> Direction {
+        self.direction
+    }
+
+    // Amount held by the latest dispute, which may be only a part of the transaction.
+    pub fn disputed_amount(&self) -> Amount {
+        self.disputed.unwrap_or(self.amount)
+    }
+
+    // Amount reversed by a chargeback, the rest of the disputed amount was released.
+    pub fn charged_back_amount(&self) -> Amount {
+        self.charged_back.unwrap_or_else(|| self.disputed_amount())
+    }
+}
+
+// Stored transactions of an account in the order they were made, indexed by their ids so disputes
+// don't scan all transactions of the account. Ids are unique in valid input, transactions with a
+// repeated id are found by a scan.
+#[derive(Clone, Default)]
+pub struct Transactions {
+    entries: Vec<DepositedTransaction>,
+    // Sequence number of the first transaction with an id. Sequence numbers keep counting
+    // transactions removed from the front, so oldest transactions are removed without reindexing.
+    index: HashMap<u32, usize>,
+    removed: usize,
+    repeated: HashSet<u32>,
+}
+
+impl Transactions {
+    pub fn push(&mut self, transaction: DepositedTransaction) {
+        if self.index.contains_key(&transaction.tx_id) {
+            self.repeated.insert(transaction.tx_id);
+        } else {
+            self.index
+                .insert(transaction.tx_id, self.removed + self.entries.len());
+        }
+        self.entries.push(transaction);
+    }
+
+    pub fn find(&self, tx_id: u32) -> Option<&DepositedTransaction> {
+        self.index
+            .get(&tx_id)
+            .map(|sequence| &self.entries[sequence - self.removed])
+    }
+
+    // Positions of all transactions with an id, in order.
+    fn positions(&self, tx_id: u32) -> Vec<usize> {
+        if !self.repeated.contains(&tx_id) {
+            return self
+                .index
+                .get(&tx_id)
+                .map(|sequence| sequence - self.removed)
+                .into_iter()
+                .collect();
+        }
+        (0..self.entries.len())
+            .filter(|position| self.entries[*position].tx_id == tx_id)
+            .collect()
+    }
+
+    pub fn get_mut(&mut self, position: usize) -> Option<&mut DepositedTransaction> {
+        self.entries.get_mut(position)
+    }
+
+    pub fn remove(&mut self, position: usize) -> DepositedTransaction {
+        let transaction = self.entries.remove(position);
+        self.reindex();
+        transaction
+    }
+
+    // Removes `count` oldest transactions.
+    pub fn drain_oldest(&mut self, count: usize) -> Vec<DepositedTransaction> {
+        let drained: Vec<DepositedTransaction> = self.entries.drain(..count).collect();
+        self.removed += count;
+        if drained
+            .iter()
+            .any(|transaction| self.repeated.contains(&transaction.tx_id))
+        {
+            self.reindex();
+        } else {
+            for transaction in &drained {
+                self.index.remove(&transaction.tx_id);
+            }
+        }
+        drained
+    }
+
+    pub fn take_all(&mut self) -> Vec<DepositedTransaction> {
+        self.index.clear();
+        self.repeated.clear();
+        self.removed = 0;
+        std::mem::take(&mut self.entries)
+    }
+
+    fn reindex(&mut self) {
+        for transaction in self.take_all() {
+            self.push(transaction);
+        }
+    }
+}
+
+impl From<Vec<DepositedTransaction>> for Transactions {
+    fn from(entries: Vec<DepositedTransaction>) -> Transactions {
+        let mut transactions = Transactions::default();
+        for transaction in entries {
+            transactions.push(transaction);
+        }
+        transactions
+    }
+}
+
+impl Deref for Transactions {
+    type Target = [DepositedTransaction];
+
+    fn deref(&self) -> &[DepositedTransaction] {
+        &self.entries
+    }
+}
+
+impl<'a> IntoIterator for &'a Transactions {
+    type Item = &'a DepositedTransaction;
+    type IntoIter = std::slice::Iter<'a, DepositedTransaction>;
+
+    fn into_iter(self) -> std::slice::Iter<'a, DepositedTransaction> {
+        self.entries.iter()
+    }
+}
+
+impl Index<usize> for Transactions {
+    type Output = DepositedTransaction;
+
+    fn index(&self, position: usize) -> &DepositedTransaction {
+        &self.entries[position]
+    }
+}
+
+// Ids of transactions are private to this module, so a transaction changed in place keeps its
+// position in the index.
+impl IndexMut<usize> for Transactions {
+    fn index_mut(&mut self, position: usize) -> &mut DepositedTransaction {
+        &mut self.entries[position]
+    }
 }
 
 impl Account {
     pub fn new(id: u16) -> Account {
         Account {
             id,
-            available: Decimal::from(0),
-            held: Decimal::from(0),
-            total: Decimal::from(0),
+            available: Amount::from(0),
+            held: Amount::from(0),
+            total: Amount::from(0),
             locked: false,
-            transactions: Vec::new(),
+            held_withdrawals: Amount::from(0),
+            transactions: Transactions::default(),
+            adjustments: Vec::new(),
+            last_activity: 0,
+            withdrawn_today: Amount::from(0),
+            withdrawal_day: 0,
         }
     }
 
@@ -58,53 +354,360 @@ impl Account {
         self.id
     }
 
+    // Copy of balances and lock of the account without its transactions, to find out whether a
+    // transaction can be applied without changing the account.
+    pub fn trial_copy(&self) -> Account {
+        let mut copy = Account::new(self.id);
+        copy.available = self.available;
+        copy.held = self.held;
+        copy.total = self.total;
+        copy.locked = self.locked;
+        copy.held_withdrawals = self.held_withdrawals;
+        copy.last_activity = self.last_activity;
+        copy.withdrawn_today = self.withdrawn_today;
+        copy.withdrawal_day = self.withdrawal_day;
+        copy
+    }
+
+    pub fn available(&self) -> Amount {
+        self.available
+    }
+
+    pub fn held(&self) -> Amount {
+        self.held
+    }
+
+    pub fn total(&self) -> Amount {
+        self.total
+    }
+
     pub fn is_locked(&self) -> bool {
         self.locked
     }
 
+    pub fn balances(&self) -> BalanceSnapshot {
+        BalanceSnapshot {
+            available: self.available,
+            held: self.held,
+            total: self.total,
+            locked: self.locked,
+        }
+    }
+
+    // Sets balances and lock kept outside of the engine, like a saved state. Balances are changed
+    // only by transactions otherwise, so total is always the sum of available and held funds.
+    pub fn restore_balances(&mut self, balances: BalanceSnapshot) -> anyhow::Result<()> {
+        if balances.available.checked_add(balances.held) != Some(balances.total) {
+            bail!(
+                "total of account {} is not a sum of available and held funds",
+                self.id
+            );
+        }
+        self.available = balances.available;
+        self.held = balances.held;
+        self.total = balances.total;
+        self.locked = balances.locked;
+        Ok(())
+    }
+
+    pub fn held_withdrawals(&self) -> Amount {
+        self.held_withdrawals
+    }
+
+    // Sets the part of held funds from disputed withdrawals kept outside of the engine. It can't
+    // be more than held funds.
+    pub fn restore_held_withdrawals(&mut self, held_withdrawals: Amount) -> anyhow::Result<()> {
+        if held_withdrawals.is_sign_negative() || held_withdrawals > self.held {
+            bail!(
+                "held withdrawals of account {} are not a part of held funds",
+                self.id
+            );
+        }
+        self.held_withdrawals = held_withdrawals;
+        Ok(())
+    }
+
+    pub fn last_activity(&self) -> u64 {
+        self.last_activity
+    }
+
+    // Records the time of a transaction for the account, or of the latest one kept outside of the
+    // engine.
+    pub fn set_last_activity(&mut self, time: u64) {
+        self.last_activity = time;
+    }
+
+    pub fn withdrawn_today(&self) -> Amount {
+        self.withdrawn_today
+    }
+
+    pub fn withdrawal_day(&self) -> u64 {
+        self.withdrawal_day
+    }
+
+    // Sets the sum of withdrawals on a day kept outside of the engine, like a saved state.
+    pub fn restore_withdrawn_today(&mut self, withdrawn_today: Amount, withdrawal_day: u64) {
+        self.withdrawn_today = withdrawn_today;
+        self.withdrawal_day = withdrawal_day;
+    }
+
+    pub fn transactions(&self) -> &Transactions {
+        &self.transactions
+    }
+
+    // Stored transactions may be moved elsewhere or evicted, which doesn't change balances.
+    pub fn transactions_mut(&mut self) -> &mut Transactions {
+        &mut self.transactions
+    }
+
     pub fn add_transaction(&mut self, transaction: DepositedTransaction) {
         self.transactions.push(transaction);
     }
 
-    pub fn deposit(&mut self, deposit: &Deposit) -> bool {
+    pub fn find_transaction(&self, tx_id: u32) -> Option<&DepositedTransaction> {
+        self.transactions.find(tx_id)
+    }
+
+    // Tags transactions stored by the transaction `tx_id` with the period it was applied in.
+    // Transactions which already have one, like a disputed deposit, keep the period they were
+    // made in.
+    pub fn tag_period(&mut self, tx_id: u32, period: &Period) {
+        for position in self.transactions.positions(tx_id) {
+            let transaction = &mut self.transactions[position];
+            if transaction.period.is_none() {
+                transaction.period = Some(period.clone());
+            }
+        }
+    }
+
+    pub fn adjustments(&self) -> &[Adjustment] {
+        &self.adjustments
+    }
+
+    // Sets adjustments kept outside of the engine, like a saved state. Adjustments are recorded
+    // only by admin transactions otherwise.
+    pub fn restore_adjustments(&mut self, adjustments: Vec<Adjustment>) {
+        self.adjustments = adjustments;
+    }
+
+    pub fn find_adjustment(&self, tx_id: u32) -> Option<&Adjustment> {
+        self.adjustments
+            .iter()
+            .find(|adjustment| adjustment.tx_id == tx_id)
+    }
+
+    // Ids of disputed transactions whose dispute stayed open longer than the policy allows.
+    pub fn expired_disputes(&self, policy: &DisputePolicy, now: u64) -> Vec<u32> {
+        // Without held funds there are no open disputes, so transactions aren't scanned.
+        if policy.resolve_after.is_none() || self.held.is_zero() {
+            return Vec::new();
+        }
+        self.transactions
+            .iter()
+            .filter(|transaction| transaction.status == DepositedTransactionStatus::Dispute)
+            .filter(|transaction| {
+                transaction
+                    .disputed_at
+                    .is_some_and(|disputed_at| policy.is_dispute_expired(disputed_at, now))
+            })
+            .map(|transaction| transaction.tx_id)
+            .collect()
+    }
+
+    pub fn has_zero_balance(&self) -> bool {
+        self.available.is_zero() && self.held.is_zero() && self.total.is_zero()
+    }
+
+    pub fn has_negative_balance(&self) -> bool {
+        self.available.is_sign_negative() || self.total.is_sign_negative()
+    }
+
+    // Part of held funds from disputed deposits.
+    pub fn held_deposits(&self) -> Amount {
+        self.held - self.held_withdrawals
+    }
+
+    // Funds owed by the client beyond available funds, for example after a dispute held more than
+    // was available. Later deposits pay it off first.
+    pub fn debt(&self) -> Amount {
+        if self.available.is_sign_negative() {
+            -self.available
+        } else {
+            Amount::from(0)
+        }
+    }
+
+    fn is_valid_deposit(&self, deposit: &Deposit) -> Result<(), TransactionError> {
         if self.id != deposit.client_id {
             error!(
                 "tx: {} has invalid account id: {}. account id: {}",
                 deposit.tx_id, deposit.client_id, self.id
             );
-            return false;
+            return Err(TransactionError::WrongAccount {
+                client: deposit.client_id,
+                account: self.id,
+            });
         }
 
         if deposit.amount.is_sign_negative() || deposit.amount.is_zero() {
             warn!("tx: {} has zero or negative balance inside", deposit.tx_id);
-            return false;
+            return Err(TransactionError::InvalidAmount);
         }
 
-        // In this place an overflow could occurs so it is checked.
-        // Discussion is needed if error message is enough of maybe a panic should be thrown.
-        if self.total.checked_add(deposit.amount).is_none() {
-            error!("account {} total amount overflow", self.id);
-            return false;
+        Ok(())
+    }
+
+    // Checks that a deposit which increases the total balance by `increase` keeps it within the
+    // balance limit.
+    fn check_balance_limit(
+        &self,
+        deposit: &Deposit,
+        increase: Amount,
+        limits: &Limits,
+    ) -> Result<(), TransactionError> {
+        match limits.max_balance {
+            Some(max_balance)
+                if self
+                    .total
+                    .checked_add(increase)
+                    .is_none_or(|total| total > max_balance) =>
+            {
+                warn!(
+                    "tx: {} would bring account {} above the balance limit",
+                    deposit.tx_id, self.id
+                );
+                Err(TransactionError::BalanceLimit)
+            }
+            _ => Ok(()),
         }
-        self.total += deposit.amount;
-        self.available += deposit.amount;
+    }
+
+    pub fn deposit(&mut self, deposit: &Deposit) -> Result<(), TransactionError> {
+        self.deposit_with(deposit, &EngineConfig::default())
+    }
+
+    // Applies a deposit by policies of an engine.
+    pub fn deposit_with(
+        &mut self,
+        deposit: &Deposit,
+        config: &EngineConfig,
+    ) -> Result<(), TransactionError> {
+        self.is_valid_deposit(deposit)?;
+        self.check_balance_limit(deposit, deposit.amount, &config.limits)?;
+
+        // In this place an overflow could occurs so it is handled by the overflow policy.
+        let (total, available) = match (
+            overflow::add(config.overflow_policy, self.total, deposit.amount),
+            overflow::add(config.overflow_policy, self.available, deposit.amount),
+        ) {
+            (Some(total), Some(available)) => (total, available),
+            _ => {
+                error!("account {} total amount overflow", self.id);
+                return Err(TransactionError::Overflow);
+            }
+        };
+        self.total = total;
+        self.available = available;
 
         self.add_transaction(DepositedTransaction {
             tx_id: deposit.tx_id,
             amount: deposit.amount,
             status: DepositedTransactionStatus::Accepted,
+            period: None,
+            direction: Direction::Deposit,
+            time: self.last_activity,
+            disputed: None,
+            charged_back: None,
+            disputed_at: None,
         });
 
-        true
+        Ok(())
+    }
+
+    // Whether all positive deposits fit into balances, so they can be applied together.
+    pub fn fits_deposits(&self, deposits: &[&Deposit]) -> bool {
+        let sum = deposits
+            .iter()
+            .filter(|deposit| deposit.amount > Amount::from(0))
+            .try_fold(Amount::from(0), |sum, deposit| {
+                sum.checked_add(deposit.amount)
+            });
+        sum.and_then(|sum| {
+            self.total
+                .checked_add(sum)
+                .zip(self.available.checked_add(sum))
+        })
+        .is_some()
+    }
+
+    // Applies many deposits with a single balance update, each stored with its time. Returns
+    // which deposits were accepted or `None` if the sum of deposits would overflow, then nothing
+    // is changed.
+    pub fn deposit_coalesced(
+        &mut self,
+        deposits: &[(&Deposit, u64)],
+        config: &EngineConfig,
+    ) -> Option<Vec<Result<(), TransactionError>>> {
+        let mut accepted: Vec<Result<(), TransactionError>> = deposits
+            .iter()
+            .map(|(deposit, _)| self.is_valid_deposit(deposit))
+            .collect();
+
+        let mut sum = Amount::from(0);
+        for ((deposit, _), accepted) in deposits.iter().zip(&mut accepted) {
+            if accepted.is_ok() {
+                let increase = sum.checked_add(deposit.amount)?;
+                // Deposits after one which would break the balance limit may still fit.
+                *accepted = self.check_balance_limit(deposit, increase, &config.limits);
+                if accepted.is_ok() {
+                    sum = increase;
+                }
+            }
+        }
+        let total = self.total.checked_add(sum)?;
+        let available = self.available.checked_add(sum)?;
+        self.total = total;
+        self.available = available;
+
+        for ((deposit, time), accepted) in deposits.iter().zip(&accepted) {
+            if accepted.is_ok() {
+                self.add_transaction(DepositedTransaction {
+                    tx_id: deposit.tx_id,
+                    amount: deposit.amount,
+                    status: DepositedTransactionStatus::Accepted,
+                    period: None,
+                    direction: Direction::Deposit,
+                    time: *time,
+                    disputed: None,
+                    charged_back: None,
+                    disputed_at: None,
+                });
+            }
+        }
+
+        Some(accepted)
+    }
+
+    pub fn withdrawal(&mut self, withdrawal: &Withdrawal) -> Result<(), TransactionError> {
+        self.withdrawal_with(withdrawal, &EngineConfig::default())
     }
 
-    pub fn withdrawal(&mut self, withdrawal: &Withdrawal) -> bool {
+    // Applies a withdrawal by policies of an engine.
+    pub fn withdrawal_with(
+        &mut self,
+        withdrawal: &Withdrawal,
+        config: &EngineConfig,
+    ) -> Result<(), TransactionError> {
+        let limits = &config.limits;
         if self.id != withdrawal.client_id {
             error!(
                 "tx: {} has invalid account id: {}. account id: {}",
                 withdrawal.tx_id, withdrawal.client_id, self.id
             );
-            return false;
+            return Err(TransactionError::WrongAccount {
+                client: withdrawal.client_id,
+                account: self.id,
+            });
         }
 
         if withdrawal.amount.is_sign_negative() || withdrawal.amount.is_zero() {
@@ -112,91 +715,590 @@ impl Account {
                 "tx: {} has zero or negative balance inside",
                 withdrawal.tx_id
             );
-            return false;
+            return Err(TransactionError::InvalidAmount);
+        }
+
+        if limits
+            .max_withdrawal
+            .is_some_and(|max_withdrawal| withdrawal.amount > max_withdrawal)
+        {
+            warn!(
+                "tx: {} is above the withdrawal limit of account {}",
+                withdrawal.tx_id, self.id
+            );
+            return Err(TransactionError::WithdrawalLimit);
+        }
+        let day = self.last_activity / SECONDS_PER_DAY;
+        let withdrawn_today = if self.withdrawal_day == day {
+            self.withdrawn_today + withdrawal.amount
+        } else {
+            withdrawal.amount
+        };
+        if limits
+            .daily_withdrawals
+            .is_some_and(|daily_withdrawals| withdrawn_today > daily_withdrawals)
+        {
+            warn!(
+                "tx: {} would exceed the daily withdrawal limit of account {}",
+                withdrawal.tx_id, self.id
+            );
+            return Err(TransactionError::DailyWithdrawalLimit);
         }
 
         if self.available < withdrawal.amount {
             warn!("account: {} has insufficient funds available", self.id);
-            return false;
+            return Err(TransactionError::InsufficientFunds);
         }
 
         self.available -= withdrawal.amount;
         self.total -= withdrawal.amount;
+        self.withdrawn_today = withdrawn_today;
+        self.withdrawal_day = day;
+
+        if config.dispute_withdrawals {
+            self.add_transaction(DepositedTransaction {
+                tx_id: withdrawal.tx_id,
+                amount: withdrawal.amount,
+                status: DepositedTransactionStatus::Accepted,
+                period: None,
+                direction: Direction::Withdrawal,
+                time: self.last_activity,
+                disputed: None,
+                charged_back: None,
+                disputed_at: None,
+            });
+        }
 
-        true
+        Ok(())
     }
 
-    pub fn set_transaction_as_dispute(&mut self, tx_id: u32) -> bool {
-        info!("tx: {} setting as in dispute mode", tx_id);
+    // Writes off funds owed by client so available balance is zeroed. The written off amount is
+    // booked as a loss.
+    pub fn write_off(&mut self, write_off: &WriteOff) -> Result<(), TransactionError> {
+        if self.id != write_off.client_id {
+            error!(
+                "tx: {} has invalid account id: {}. account id: {}",
+                write_off.tx_id, write_off.client_id, self.id
+            );
+            return Err(TransactionError::WrongAccount {
+                client: write_off.client_id,
+                account: self.id,
+            });
+        }
 
-        for transaction in &mut self.transactions {
-            if transaction.tx_id == tx_id
-                && transaction.status == DepositedTransactionStatus::Accepted
-                && self.available >= transaction.amount
-            {
-                transaction.status = DepositedTransactionStatus::Dispute;
-                self.available -= transaction.amount;
-                self.held += transaction.amount;
+        if !self.available.is_sign_negative() || self.available.is_zero() {
+            warn!("account: {} has no negative balance to write off", self.id);
+            return Err(TransactionError::NoNegativeBalance);
+        }
+
+        let amount = -self.available;
+        self.available += amount;
+        self.total += amount;
+
+        self.add_adjustment(write_off.tx_id, amount, AdjustmentKind::WriteOff);
+
+        info!("account: {} written off {}", self.id, amount);
+        Ok(())
+    }
 
-                info!("tx: {} successfully set as in dispute mode", tx_id);
-                return true;
+    // Takes a fee of an applied transaction from available funds, see `fees`.
+    pub fn charge_fee(
+        &mut self,
+        fee: Amount,
+        policy: OverflowPolicy,
+    ) -> Result<(), TransactionError> {
+        if fee.is_zero() {
+            return Ok(());
+        }
+
+        let (total, available) = match (
+            overflow::sub(policy, self.total, fee),
+            overflow::sub(policy, self.available, fee),
+        ) {
+            (Some(total), Some(available)) => (total, available),
+            _ => {
+                error!("account {} available amount overflow", self.id);
+                return Err(TransactionError::Overflow);
             }
+        };
+        self.total = total;
+        self.available = available;
+
+        info!("account: {} charged fee {}", self.id, fee);
+        Ok(())
+    }
+
+    fn add_adjustment(&mut self, tx_id: u32, amount: Amount, kind: AdjustmentKind) {
+        self.adjustments.push(Adjustment {
+            tx_id,
+            amount,
+            kind,
+        });
+    }
+
+    fn is_own_transaction(&self, client_id: u16, tx_id: u32) -> Result<(), TransactionError> {
+        if self.id != client_id {
+            error!(
+                "tx: {} has invalid account id: {}. account id: {}",
+                tx_id, client_id, self.id
+            );
+            return Err(TransactionError::WrongAccount {
+                client: client_id,
+                account: self.id,
+            });
         }
 
-        warn!("tx: {} is not found, is already in dispute mode or account has insufficient funds available", tx_id);
-        false
+        Ok(())
     }
 
-    pub fn set_transaction_as_resolved(&mut self, tx_id: u32) -> bool {
-        info!("tx: {} setting as in resolved mode", tx_id);
+    pub fn lock(&mut self, lock: &Lock) -> Result<(), TransactionError> {
+        self.is_own_transaction(lock.client_id, lock.tx_id)?;
 
-        for transaction in &mut self.transactions {
-            if transaction.tx_id == tx_id
-                && transaction.status == DepositedTransactionStatus::Dispute
-                && self.held >= transaction.amount
-            {
-                // Currently it's not possible to dispute transaction multiple times. If this is
-                // a wanted behavior then transaction status should be set to  DepositedTransactionStatus::Accepted
-                transaction.status = DepositedTransactionStatus::Resolved;
-                self.available += transaction.amount;
-                self.held -= transaction.amount;
-
-                info!("tx: {} successfully set as in resolved mode", tx_id);
-                return true;
+        if self.locked {
+            warn!("account: {} is already locked", self.id);
+            return Err(TransactionError::AlreadyLocked);
+        }
+
+        self.locked = true;
+        self.add_adjustment(lock.tx_id, Amount::from(0), AdjustmentKind::Lock);
+
+        info!("account: {} locked", self.id);
+        Ok(())
+    }
+
+    pub fn unlock(&mut self, unlock: &Unlock) -> Result<(), TransactionError> {
+        self.is_own_transaction(unlock.client_id, unlock.tx_id)?;
+
+        if !self.locked {
+            warn!("account: {} is not locked", self.id);
+            return Err(TransactionError::NotLocked);
+        }
+
+        self.locked = false;
+        self.add_adjustment(unlock.tx_id, Amount::from(0), AdjustmentKind::Unlock);
+
+        info!("account: {} unlocked", self.id);
+        Ok(())
+    }
+
+    // Corrects available balance by a signed amount. A correction can't take more funds than
+    // there are available.
+    pub fn adjust(&mut self, adjust: &Adjust) -> Result<(), TransactionError> {
+        self.adjust_with(adjust, &EngineConfig::default())
+    }
+
+    // Corrects available balance by policies of an engine.
+    pub fn adjust_with(
+        &mut self,
+        adjust: &Adjust,
+        config: &EngineConfig,
+    ) -> Result<(), TransactionError> {
+        self.is_own_transaction(adjust.client_id, adjust.tx_id)?;
+
+        if adjust.amount.is_zero() {
+            warn!("tx: {} has zero amount inside", adjust.tx_id);
+            return Err(TransactionError::InvalidAmount);
+        }
+
+        if self.available + adjust.amount < Amount::from(0) {
+            warn!("account: {} has insufficient funds available", self.id);
+            return Err(TransactionError::InsufficientFunds);
+        }
+
+        let (total, available) = match (
+            overflow::add(config.overflow_policy, self.total, adjust.amount),
+            overflow::add(config.overflow_policy, self.available, adjust.amount),
+        ) {
+            (Some(total), Some(available)) => (total, available),
+            _ => {
+                error!("account {} total amount overflow", self.id);
+                return Err(TransactionError::Overflow);
+            }
+        };
+        self.total = total;
+        self.available = available;
+        self.add_adjustment(adjust.tx_id, adjust.amount, AdjustmentKind::Adjust);
+
+        info!("account: {} adjusted by {}", self.id, adjust.amount);
+        Ok(())
+    }
+
+    // Annotation doesn't change an account, it only leaves a note in the audit log.
+    pub fn annotate(&mut self, annotate: &Annotate) -> Result<(), TransactionError> {
+        self.is_own_transaction(annotate.client_id, annotate.tx_id)?;
+
+        self.add_adjustment(annotate.tx_id, Amount::from(0), AdjustmentKind::Annotate);
+        Ok(())
+    }
+
+    // Applies a change of balances by a dispute, resolve or chargeback of a transaction.
+    fn apply_effect(
+        &mut self,
+        tx_id: u32,
+        direction: Direction,
+        effect: Effect,
+        amount: Amount,
+        policy: OverflowPolicy,
+    ) -> Result<(), TransactionError> {
+        match (direction, effect) {
+            // A disputed withdrawal is held and counted in total again until it's resolved,
+            // then it's taken out of total, or charged back, then it's returned to available
+            // funds.
+            (Direction::Withdrawal, Effect::Hold) => {
+                match (
+                    overflow::add(policy, self.held, amount),
+                    overflow::add(policy, self.total, amount),
+                ) {
+                    (Some(held), Some(total)) => {
+                        self.held = held;
+                        self.total = total;
+                        self.held_withdrawals += amount;
+                    }
+                    _ => {
+                        error!("account {} total amount overflow", self.id);
+                        return Err(TransactionError::Overflow);
+                    }
+                }
+            }
+            (Direction::Withdrawal, Effect::Release) => {
+                match (
+                    overflow::sub(policy, self.held, amount),
+                    overflow::sub(policy, self.total, amount),
+                ) {
+                    (Some(held), Some(total)) => {
+                        self.held = held;
+                        self.total = total;
+                        self.held_withdrawals -= amount;
+                    }
+                    _ => {
+                        error!("account {} total amount overflow", self.id);
+                        return Err(TransactionError::Overflow);
+                    }
+                }
+            }
+            (Direction::Withdrawal, Effect::Reverse) => {
+                match (
+                    overflow::sub(policy, self.held, amount),
+                    overflow::add(policy, self.available, amount),
+                ) {
+                    (Some(held), Some(available)) => {
+                        self.held = held;
+                        self.available = available;
+                        self.held_withdrawals -= amount;
+                        self.locked = true;
+                    }
+                    _ => {
+                        error!("account {} available amount overflow", self.id);
+                        return Err(TransactionError::Overflow);
+                    }
+                }
+            }
+            (Direction::Deposit, Effect::Hold) => {
+                match (
+                    overflow::sub(policy, self.available, amount),
+                    overflow::add(policy, self.held, amount),
+                ) {
+                    (Some(available), Some(held)) => {
+                        self.available = available;
+                        self.held = held;
+                        if self.available.is_sign_negative() {
+                            warn!(
+                                "tx: {} dispute leaves account {} owing {}",
+                                tx_id, self.id, -available
+                            );
+                        }
+                    }
+                    _ => {
+                        error!("account {} held amount overflow", self.id);
+                        return Err(TransactionError::Overflow);
+                    }
+                }
+            }
+            // A resolved transaction may be disputed again only if dispute policy allows it.
+            (Direction::Deposit, Effect::Release) => {
+                match (
+                    overflow::add(policy, self.available, amount),
+                    overflow::sub(policy, self.held, amount),
+                ) {
+                    (Some(available), Some(held)) => {
+                        self.available = available;
+                        self.held = held;
+                    }
+                    _ => {
+                        error!("account {} available amount overflow", self.id);
+                        return Err(TransactionError::Overflow);
+                    }
+                }
+            }
+            (Direction::Deposit, Effect::Reverse) => {
+                match (
+                    overflow::sub(policy, self.held, amount),
+                    overflow::sub(policy, self.total, amount),
+                ) {
+                    (Some(held), Some(total)) => {
+                        self.held = held;
+                        self.total = total;
+                        self.locked = true;
+                    }
+                    _ => {
+                        error!("account {} total amount overflow", self.id);
+                        return Err(TransactionError::Overflow);
+                    }
+                }
+            }
+            // A representment undoes a chargeback, so funds of a deposit are credited again and
+            // funds of a withdrawal are taken again.
+            (Direction::Deposit, Effect::Recredit) => {
+                match (
+                    overflow::add(policy, self.available, amount),
+                    overflow::add(policy, self.total, amount),
+                ) {
+                    (Some(available), Some(total)) => {
+                        self.available = available;
+                        self.total = total;
+                    }
+                    _ => {
+                        error!("account {} total amount overflow", self.id);
+                        return Err(TransactionError::Overflow);
+                    }
+                }
+            }
+            (Direction::Withdrawal, Effect::Recredit) => {
+                match (
+                    overflow::sub(policy, self.available, amount),
+                    overflow::sub(policy, self.total, amount),
+                ) {
+                    (Some(available), Some(total)) => {
+                        self.available = available;
+                        self.total = total;
+                    }
+                    _ => {
+                        error!("account {} total amount overflow", self.id);
+                        return Err(TransactionError::Overflow);
+                    }
+                }
             }
         }
 
-        warn!("tx: {} is not found, is not in dispute mode or account has insufficient held funds available", tx_id);
-        false
+        Ok(())
+    }
+
+    pub fn set_transaction_as_dispute(&mut self, tx_id: u32) -> Result<(), TransactionError> {
+        self.apply_event(tx_id, Event::Dispute, None)
+    }
+
+    // Disputes only a part of a transaction, the rest of it stays available.
+    pub fn set_transaction_as_partial_dispute(
+        &mut self,
+        tx_id: u32,
+        amount: Amount,
+    ) -> Result<(), TransactionError> {
+        self.apply_event(tx_id, Event::Dispute, Some(amount))
+    }
+
+    pub fn set_transaction_as_resolved(&mut self, tx_id: u32) -> Result<(), TransactionError> {
+        self.apply_event(tx_id, Event::Resolve, None)
+    }
+
+    pub fn set_transaction_as_chargeback(&mut self, tx_id: u32) -> Result<(), TransactionError> {
+        self.apply_event(tx_id, Event::Chargeback, None)
+    }
+
+    pub fn set_transaction_as_represented(&mut self, tx_id: u32) -> Result<(), TransactionError> {
+        self.apply_event(tx_id, Event::Representment, None)
     }
 
-    pub fn set_transaction_as_chargeback(&mut self, tx_id: u32) -> bool {
-        info!("tx: {} setting as in chargeback mode", tx_id);
+    // Charges back only a part of a disputed amount, the rest of it is released.
+    pub fn set_transaction_as_partial_chargeback(
+        &mut self,
+        tx_id: u32,
+        amount: Amount,
+    ) -> Result<(), TransactionError> {
+        self.apply_event(tx_id, Event::Chargeback, Some(amount))
+    }
 
-        for transaction in &mut self.transactions {
-            if transaction.tx_id == tx_id
-                && transaction.status == DepositedTransactionStatus::Dispute
-                && self.held >= transaction.amount
+    // Moves a deposited transaction along the dispute state machine, as allowed by the dispute
+    // policy, and applies effect of the transition to balances.
+    fn apply_event(
+        &mut self,
+        tx_id: u32,
+        event: Event,
+        part: Option<Amount>,
+    ) -> Result<(), TransactionError> {
+        self.apply_event_with(tx_id, event, part, &EngineConfig::default())
+    }
+
+    // Applies a dispute event by policies of an engine.
+    pub fn apply_event_with(
+        &mut self,
+        tx_id: u32,
+        event: Event,
+        part: Option<Amount>,
+        config: &EngineConfig,
+    ) -> Result<(), TransactionError> {
+        let policy = &config.dispute_policy;
+        info!("tx: {} applying {}", tx_id, event.name());
+
+        // Why the event was rejected, if it was.
+        let mut rejected_state = None;
+        let mut insufficient_funds = false;
+        let mut expired = false;
+        let mut invalid_part = false;
+        let mut exceeding_part = false;
+        for position in self.transactions.positions(tx_id) {
+            let transaction = &self.transactions[position];
+            let transition = match policy.transition(transaction.status, event) {
+                Some(transition) => transition,
+                None => {
+                    rejected_state = Some(transaction.status);
+                    continue;
+                }
+            };
+            // Last activity is the time of the event being applied.
+            if event == Event::Dispute
+                && !policy.is_within_window(transaction.time, self.last_activity)
             {
-                transaction.status = DepositedTransactionStatus::Chargeback;
-                self.held -= transaction.amount;
-                self.total -= transaction.amount;
-                self.locked = true;
+                expired = true;
+                continue;
+            }
+            // A dispute holds a part of the transaction and a chargeback reverses a part of the
+            // disputed amount.
+            let direction = transaction.direction;
+            let limit = match transition.effect {
+                Effect::Hold => transaction.amount,
+                Effect::Release | Effect::Reverse => transaction.disputed_amount(),
+                Effect::Recredit => transaction.charged_back_amount(),
+            };
+            let amount = part.unwrap_or(limit);
+            if amount.is_sign_negative() || amount.is_zero() {
+                invalid_part = true;
+                continue;
+            }
+            if amount > limit {
+                exceeding_part = true;
+                continue;
+            }
+            // Funds of a withdrawal already left the account, so its dispute holds the amount
+            // without taking it from available funds.
+            let (funds, needed) = match transition.effect {
+                Effect::Hold if direction == Direction::Withdrawal => (amount, amount),
+                Effect::Hold if policy.hold_beyond_available => (amount, amount),
+                Effect::Hold => (self.available, amount),
+                Effect::Release | Effect::Reverse => (self.held, limit),
+                Effect::Recredit if direction == Direction::Withdrawal => (self.available, amount),
+                Effect::Recredit => (amount, amount),
+            };
+            if funds < needed {
+                insufficient_funds = true;
+                continue;
+            }
 
-                info!("tx: {} successfully set as in chargeback mode", tx_id);
-                return true;
+            self.apply_effect(
+                tx_id,
+                direction,
+                transition.effect,
+                amount,
+                config.overflow_policy,
+            )?;
+            if transition.effect == Effect::Reverse && amount < limit {
+                // The part of the dispute which isn't charged back is returned to the client.
+                self.apply_effect(
+                    tx_id,
+                    direction,
+                    Effect::Release,
+                    limit - amount,
+                    config.overflow_policy,
+                )?;
+            }
+            let now = self.last_activity;
+            let transaction = &mut self.transactions[position];
+            let partial = (amount < limit).then_some(amount);
+            match transition.effect {
+                Effect::Hold => {
+                    transaction.disputed = partial;
+                    transaction.charged_back = None;
+                    transaction.disputed_at = Some(now);
+                }
+                Effect::Reverse => transaction.charged_back = partial,
+                Effect::Release | Effect::Recredit => {}
+            }
+            transaction.status = transition.to;
+            let status = transaction.status;
+            // The account stays locked while any other of its transactions is charged back.
+            if transition.effect == Effect::Recredit
+                && policy.unlock_on_representment
+                && self
+                    .transactions
+                    .iter()
+                    .all(|transaction| transaction.status != DepositedTransactionStatus::Chargeback)
+            {
+                info!("account: {} unlocked by representment", self.id);
+                self.locked = false;
             }
+
+            info!("tx: {} successfully set as {}", tx_id, status.name());
+            return Ok(());
         }
 
-        warn!("tx: {} is not found, is not in dispute mode or account has insufficient held funds available", tx_id);
-        false
+        match rejected_state {
+            _ if exceeding_part => {
+                warn!(
+                    "tx: {} can not {} more than the disputed amount in account {}",
+                    tx_id,
+                    event.name(),
+                    self.id
+                );
+                Err(TransactionError::ExceedsDisputed)
+            }
+            _ if invalid_part => {
+                warn!(
+                    "tx: {} can not {} a zero or negative amount in account {}",
+                    tx_id,
+                    event.name(),
+                    self.id
+                );
+                Err(TransactionError::InvalidAmount)
+            }
+            _ if expired => {
+                warn!(
+                    "tx: {} can not be disputed after dispute window in account {}",
+                    tx_id, self.id
+                );
+                Err(TransactionError::DisputeWindowExpired)
+            }
+            _ if insufficient_funds => {
+                warn!(
+                    "tx: {} account {} has insufficient funds to {}",
+                    tx_id,
+                    self.id,
+                    event.name()
+                );
+                Err(TransactionError::InsufficientFunds)
+            }
+            Some(state) => {
+                warn!(
+                    "tx: {} can not {} a transaction in {} state",
+                    tx_id,
+                    event.name(),
+                    state.name()
+                );
+                Err(TransactionError::InvalidState { state, event })
+            }
+            None => {
+                warn!("tx: {} is not found in account {}", tx_id, self.id);
+                Err(TransactionError::UnknownTransaction)
+            }
+        }
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::statemachine;
     use crate::transaction;
+    use std::str::FromStr;
+    use std::sync::Arc;
 
     #[test]
     fn test_create_new_account() {
@@ -217,6 +1319,46 @@ mod test {
         assert!(!account.is_locked());
     }
 
+    #[test]
+    fn test_restore_balances() {
+        let mut account = Account::new(12345);
+        let balances = BalanceSnapshot {
+            available: Amount::from_str("1.5").unwrap(),
+            held: Amount::from(2),
+            total: Amount::from_str("3.5").unwrap(),
+            locked: true,
+        };
+        assert!(account.restore_balances(balances).is_ok());
+        assert_eq!(account.balances(), balances);
+        assert_eq!(account.total(), account.available() + account.held());
+
+        let unbalanced = BalanceSnapshot {
+            total: Amount::from(4),
+            ..balances
+        };
+        assert!(account.restore_balances(unbalanced).is_err());
+        assert_eq!(account.balances(), balances);
+    }
+
+    #[test]
+    fn test_restore_held_withdrawals() {
+        let mut account = Account::new(12345);
+        let balances = BalanceSnapshot {
+            available: Amount::from(1),
+            held: Amount::from(2),
+            total: Amount::from(3),
+            locked: false,
+        };
+        assert!(account.restore_balances(balances).is_ok());
+        assert!(account.restore_held_withdrawals(Amount::from(2)).is_ok());
+        assert_eq!(account.held_withdrawals(), Amount::from(2));
+        assert_eq!(account.held_deposits(), Amount::from(0));
+
+        assert!(account.restore_held_withdrawals(Amount::from(3)).is_err());
+        assert!(account.restore_held_withdrawals(Amount::from(-1)).is_err());
+        assert_eq!(account.held_withdrawals(), Amount::from(2));
+    }
+
     #[test]
     fn test_add_transaction() {
         let mut account = Account::new(12345);
@@ -224,15 +1366,21 @@ mod test {
 
         let transaction = DepositedTransaction {
             tx_id: 123456789,
-            amount: Decimal::from_str("12345.6789").unwrap(),
+            amount: Amount::from_str("12345.6789").unwrap(),
             status: DepositedTransactionStatus::Accepted,
+            period: None,
+            direction: Direction::Deposit,
+            time: 0,
+            disputed: None,
+            charged_back: None,
+            disputed_at: None,
         };
         account.add_transaction(transaction);
         assert_eq!(account.transactions.len(), 1);
 
-        let transaction = account.transactions.get(0).unwrap();
+        let transaction = account.transactions.first().unwrap();
         assert_eq!(transaction.tx_id, 123456789);
-        assert_eq!(transaction.amount, Decimal::from_str("12345.6789").unwrap());
+        assert_eq!(transaction.amount, Amount::from_str("12345.6789").unwrap());
         assert!(transaction.status == DepositedTransactionStatus::Accepted);
     }
 
@@ -243,16 +1391,50 @@ mod test {
         let deposit = transaction::Deposit {
             client_id: 12345,
             tx_id: 22334455,
-            amount: Decimal::from_str("12345.6789").unwrap(),
+            amount: Amount::from_str("12345.6789").unwrap(),
         };
-        assert!(account.deposit(&deposit));
-        assert_eq!(account.available, Decimal::from_str("12345.6789").unwrap());
-        assert_eq!(account.held, Decimal::from_str("0").unwrap());
-        assert_eq!(account.total, Decimal::from_str("12345.6789").unwrap());
+        assert!(account.deposit(&deposit).is_ok());
+        assert_eq!(account.available, Amount::from_str("12345.6789").unwrap());
+        assert_eq!(account.held, Amount::from_str("0").unwrap());
+        assert_eq!(account.total, Amount::from_str("12345.6789").unwrap());
         assert!(!account.is_locked());
         assert_eq!(account.transactions.len(), 1);
     }
 
+    #[test]
+    fn test_transactions_index() {
+        let mut account = Account::new(12345);
+        for tx_id in [1, 2, 3, 2, 4] {
+            let deposit = transaction::Deposit {
+                client_id: 12345,
+                tx_id,
+                amount: Amount::from(tx_id),
+            };
+            assert!(account.deposit(&deposit).is_ok());
+        }
+        assert_eq!(
+            account.find_transaction(3).unwrap().amount(),
+            Amount::from(3)
+        );
+        assert_eq!(account.transactions.positions(2), vec![1, 3]);
+
+        let drained = account.transactions.drain_oldest(1);
+        assert_eq!(drained[0].tx_id(), 1);
+        assert!(account.find_transaction(1).is_none());
+        assert_eq!(account.transactions.positions(4), vec![3]);
+        account.transactions.remove(1);
+        assert_eq!(account.transactions.positions(2), vec![0, 1]);
+        assert!(account.set_transaction_as_dispute(4).is_ok());
+        assert_eq!(
+            account.find_transaction(4).unwrap().status(),
+            DepositedTransactionStatus::Dispute
+        );
+        // A repeated id is disputed once for each of its transactions.
+        assert!(account.set_transaction_as_dispute(2).is_ok());
+        assert!(account.set_transaction_as_dispute(2).is_ok());
+        assert!(account.set_transaction_as_dispute(2).is_err());
+    }
+
     #[test]
     fn test_deposit_invalid_account_id() {
         let mut account = Account::new(12345);
@@ -260,12 +1442,12 @@ mod test {
         let deposit = transaction::Deposit {
             client_id: 12346,
             tx_id: 22334455,
-            amount: Decimal::from_str("12345.6789").unwrap(),
+            amount: Amount::from_str("12345.6789").unwrap(),
         };
-        assert!(!account.deposit(&deposit));
-        assert_eq!(account.available, Decimal::from_str("0").unwrap());
-        assert_eq!(account.held, Decimal::from_str("0").unwrap());
-        assert_eq!(account.total, Decimal::from_str("0").unwrap());
+        assert!(account.deposit(&deposit).is_err());
+        assert_eq!(account.available, Amount::from_str("0").unwrap());
+        assert_eq!(account.held, Amount::from_str("0").unwrap());
+        assert_eq!(account.total, Amount::from_str("0").unwrap());
         assert!(!account.is_locked());
         assert_eq!(account.transactions.len(), 0);
     }
@@ -277,12 +1459,12 @@ mod test {
         let deposit = transaction::Deposit {
             client_id: 12345,
             tx_id: 22334455,
-            amount: Decimal::from_str("-0.01").unwrap(),
+            amount: Amount::from_str("-0.01").unwrap(),
         };
-        assert!(!account.deposit(&deposit));
-        assert_eq!(account.available, Decimal::from_str("0").unwrap());
-        assert_eq!(account.held, Decimal::from_str("0").unwrap());
-        assert_eq!(account.total, Decimal::from_str("0").unwrap());
+        assert!(account.deposit(&deposit).is_err());
+        assert_eq!(account.available, Amount::from_str("0").unwrap());
+        assert_eq!(account.held, Amount::from_str("0").unwrap());
+        assert_eq!(account.total, Amount::from_str("0").unwrap());
         assert!(!account.is_locked());
         assert_eq!(account.transactions.len(), 0);
     }
@@ -294,12 +1476,12 @@ mod test {
         let deposit = transaction::Deposit {
             client_id: 12345,
             tx_id: 22334455,
-            amount: Decimal::from_str("0").unwrap(),
+            amount: Amount::from_str("0").unwrap(),
         };
-        assert!(!account.deposit(&deposit));
-        assert_eq!(account.available, Decimal::from_str("0").unwrap());
-        assert_eq!(account.held, Decimal::from_str("0").unwrap());
-        assert_eq!(account.total, Decimal::from_str("0").unwrap());
+        assert!(account.deposit(&deposit).is_err());
+        assert_eq!(account.available, Amount::from_str("0").unwrap());
+        assert_eq!(account.held, Amount::from_str("0").unwrap());
+        assert_eq!(account.total, Amount::from_str("0").unwrap());
         assert!(!account.is_locked());
         assert_eq!(account.transactions.len(), 0);
     }
@@ -311,17 +1493,17 @@ mod test {
         let deposit = transaction::Deposit {
             client_id: 12345,
             tx_id: 22334455,
-            amount: Decimal::MAX,
+            amount: Amount::MAX,
         };
-        assert!(account.deposit(&deposit));
+        assert!(account.deposit(&deposit).is_ok());
         assert_eq!(account.transactions.len(), 1);
 
         let deposit = transaction::Deposit {
             client_id: 12345,
             tx_id: 22334456,
-            amount: Decimal::from_str("1").unwrap(),
+            amount: Amount::from_str("1").unwrap(),
         };
-        assert!(!account.deposit(&deposit));
+        assert!(account.deposit(&deposit).is_err());
         assert_eq!(account.transactions.len(), 1);
     }
 
@@ -332,19 +1514,19 @@ mod test {
         let deposit = transaction::Deposit {
             client_id: 12345,
             tx_id: 22334455,
-            amount: Decimal::from_str("12345.6789").unwrap(),
+            amount: Amount::from_str("12345.6789").unwrap(),
         };
-        assert!(account.deposit(&deposit));
+        assert!(account.deposit(&deposit).is_ok());
 
         let withdrawal = transaction::Withdrawal {
             client_id: 12345,
             tx_id: 22334456,
-            amount: Decimal::from_str("125.9999").unwrap(),
+            amount: Amount::from_str("125.9999").unwrap(),
         };
-        assert!(account.withdrawal(&withdrawal));
-        assert_eq!(account.available, Decimal::from_str("12219.679").unwrap());
-        assert_eq!(account.held, Decimal::from_str("0").unwrap());
-        assert_eq!(account.total, Decimal::from_str("12219.679").unwrap());
+        assert!(account.withdrawal(&withdrawal).is_ok());
+        assert_eq!(account.available, Amount::from_str("12219.679").unwrap());
+        assert_eq!(account.held, Amount::from_str("0").unwrap());
+        assert_eq!(account.total, Amount::from_str("12219.679").unwrap());
         assert!(!account.is_locked());
         assert_eq!(account.transactions.len(), 1);
     }
@@ -356,19 +1538,19 @@ mod test {
         let deposit = transaction::Deposit {
             client_id: 12345,
             tx_id: 22334455,
-            amount: Decimal::from_str("12345.6789").unwrap(),
+            amount: Amount::from_str("12345.6789").unwrap(),
         };
-        assert!(account.deposit(&deposit));
+        assert!(account.deposit(&deposit).is_ok());
 
         let withdrawal = transaction::Withdrawal {
             client_id: 12346,
             tx_id: 22334456,
-            amount: Decimal::from_str("125.9999").unwrap(),
+            amount: Amount::from_str("125.9999").unwrap(),
         };
-        assert!(!account.withdrawal(&withdrawal));
-        assert_eq!(account.available, Decimal::from_str("12345.6789").unwrap());
-        assert_eq!(account.held, Decimal::from_str("0").unwrap());
-        assert_eq!(account.total, Decimal::from_str("12345.6789").unwrap());
+        assert!(account.withdrawal(&withdrawal).is_err());
+        assert_eq!(account.available, Amount::from_str("12345.6789").unwrap());
+        assert_eq!(account.held, Amount::from_str("0").unwrap());
+        assert_eq!(account.total, Amount::from_str("12345.6789").unwrap());
         assert!(!account.is_locked());
         assert_eq!(account.transactions.len(), 1);
     }
@@ -380,19 +1562,19 @@ mod test {
         let deposit = transaction::Deposit {
             client_id: 12345,
             tx_id: 22334455,
-            amount: Decimal::from_str("12345.6789").unwrap(),
+            amount: Amount::from_str("12345.6789").unwrap(),
         };
-        assert!(account.deposit(&deposit));
+        assert!(account.deposit(&deposit).is_ok());
 
         let withdrawal = transaction::Withdrawal {
             client_id: 12345,
             tx_id: 22334456,
-            amount: Decimal::from_str("-100").unwrap(),
+            amount: Amount::from_str("-100").unwrap(),
         };
-        assert!(!account.withdrawal(&withdrawal));
-        assert_eq!(account.available, Decimal::from_str("12345.6789").unwrap());
-        assert_eq!(account.held, Decimal::from_str("0").unwrap());
-        assert_eq!(account.total, Decimal::from_str("12345.6789").unwrap());
+        assert!(account.withdrawal(&withdrawal).is_err());
+        assert_eq!(account.available, Amount::from_str("12345.6789").unwrap());
+        assert_eq!(account.held, Amount::from_str("0").unwrap());
+        assert_eq!(account.total, Amount::from_str("12345.6789").unwrap());
         assert!(!account.is_locked());
         assert_eq!(account.transactions.len(), 1);
     }
@@ -404,19 +1586,19 @@ mod test {
         let deposit = transaction::Deposit {
             client_id: 12345,
             tx_id: 22334455,
-            amount: Decimal::from_str("12345.6789").unwrap(),
+            amount: Amount::from_str("12345.6789").unwrap(),
         };
-        assert!(account.deposit(&deposit));
+        assert!(account.deposit(&deposit).is_ok());
 
         let withdrawal = transaction::Withdrawal {
             client_id: 12345,
             tx_id: 22334456,
-            amount: Decimal::from_str("0").unwrap(),
+            amount: Amount::from_str("0").unwrap(),
         };
-        assert!(!account.withdrawal(&withdrawal));
-        assert_eq!(account.available, Decimal::from_str("12345.6789").unwrap());
-        assert_eq!(account.held, Decimal::from_str("0").unwrap());
-        assert_eq!(account.total, Decimal::from_str("12345.6789").unwrap());
+        assert!(account.withdrawal(&withdrawal).is_err());
+        assert_eq!(account.available, Amount::from_str("12345.6789").unwrap());
+        assert_eq!(account.held, Amount::from_str("0").unwrap());
+        assert_eq!(account.total, Amount::from_str("12345.6789").unwrap());
         assert!(!account.is_locked());
         assert_eq!(account.transactions.len(), 1);
     }
@@ -428,23 +1610,113 @@ mod test {
         let deposit = transaction::Deposit {
             client_id: 12345,
             tx_id: 22334455,
-            amount: Decimal::from_str("100.2222").unwrap(),
+            amount: Amount::from_str("100.2222").unwrap(),
         };
-        assert!(account.deposit(&deposit));
+        assert!(account.deposit(&deposit).is_ok());
 
         let withdrawal = transaction::Withdrawal {
             client_id: 12345,
             tx_id: 22334456,
-            amount: Decimal::from_str("100.2223").unwrap(),
+            amount: Amount::from_str("100.2223").unwrap(),
         };
-        assert!(!account.withdrawal(&withdrawal));
-        assert_eq!(account.available, Decimal::from_str("100.2222").unwrap());
-        assert_eq!(account.held, Decimal::from_str("0").unwrap());
-        assert_eq!(account.total, Decimal::from_str("100.2222").unwrap());
+        assert_eq!(
+            account.withdrawal(&withdrawal),
+            Err(TransactionError::InsufficientFunds)
+        );
+        assert_eq!(account.available, Amount::from_str("100.2222").unwrap());
+        assert_eq!(account.held, Amount::from_str("0").unwrap());
+        assert_eq!(account.total, Amount::from_str("100.2222").unwrap());
         assert!(!account.is_locked());
         assert_eq!(account.transactions.len(), 1);
     }
 
+    #[test]
+    fn test_rejection_errors() {
+        let mut account = Account::new(12345);
+
+        let deposit = transaction::Deposit {
+            client_id: 54321,
+            tx_id: 22334455,
+            amount: Amount::from(1),
+        };
+        assert_eq!(
+            account.deposit(&deposit),
+            Err(TransactionError::WrongAccount {
+                client: 54321,
+                account: 12345
+            })
+        );
+        assert_eq!(
+            account.set_transaction_as_dispute(22334455),
+            Err(TransactionError::UnknownTransaction)
+        );
+
+        let unlock = transaction::Unlock {
+            client_id: 12345,
+            tx_id: 22334456,
+        };
+        assert_eq!(account.unlock(&unlock), Err(TransactionError::NotLocked));
+    }
+
+    #[test]
+    fn test_write_off_success() {
+        let mut account = Account::new(12345);
+        account.available = Amount::from_str("-100.5").unwrap();
+        account.held = Amount::from_str("20").unwrap();
+        account.total = Amount::from_str("-80.5").unwrap();
+        assert!(account.has_negative_balance());
+
+        let write_off = transaction::WriteOff {
+            client_id: 12345,
+            tx_id: 22334455,
+        };
+        assert!(account.write_off(&write_off).is_ok());
+        assert_eq!(account.available, Amount::from_str("0").unwrap());
+        assert_eq!(account.held, Amount::from_str("20").unwrap());
+        assert_eq!(account.total, Amount::from_str("20").unwrap());
+        assert!(!account.has_negative_balance());
+
+        let adjustment = account.find_adjustment(22334455).unwrap();
+        assert_eq!(adjustment.amount(), Amount::from_str("100.5").unwrap());
+        assert_eq!(adjustment.kind(), AdjustmentKind::WriteOff);
+    }
+
+    #[test]
+    fn test_write_off_no_negative_balance() {
+        let mut account = Account::new(12345);
+
+        let deposit = transaction::Deposit {
+            client_id: 12345,
+            tx_id: 22334455,
+            amount: Amount::from_str("12345.6789").unwrap(),
+        };
+        assert!(account.deposit(&deposit).is_ok());
+
+        let write_off = transaction::WriteOff {
+            client_id: 12345,
+            tx_id: 22334456,
+        };
+        assert!(account.write_off(&write_off).is_err());
+        assert_eq!(account.available, Amount::from_str("12345.6789").unwrap());
+        assert_eq!(account.total, Amount::from_str("12345.6789").unwrap());
+        assert_eq!(account.adjustments.len(), 0);
+    }
+
+    #[test]
+    fn test_write_off_invalid_account_id() {
+        let mut account = Account::new(12345);
+        account.available = Amount::from_str("-1").unwrap();
+        account.total = Amount::from_str("-1").unwrap();
+
+        let write_off = transaction::WriteOff {
+            client_id: 12346,
+            tx_id: 22334455,
+        };
+        assert!(account.write_off(&write_off).is_err());
+        assert_eq!(account.available, Amount::from_str("-1").unwrap());
+        assert_eq!(account.adjustments.len(), 0);
+    }
+
     #[test]
     fn test_dispute_success() {
         let mut account = Account::new(12345);
@@ -452,14 +1724,14 @@ mod test {
         let deposit = transaction::Deposit {
             client_id: 12345,
             tx_id: 22334455,
-            amount: Decimal::from_str("12345.6789").unwrap(),
+            amount: Amount::from_str("12345.6789").unwrap(),
         };
-        assert!(account.deposit(&deposit));
+        assert!(account.deposit(&deposit).is_ok());
 
-        assert!(account.set_transaction_as_dispute(22334455));
-        assert_eq!(account.available, Decimal::from_str("0").unwrap());
-        assert_eq!(account.held, Decimal::from_str("12345.6789").unwrap());
-        assert_eq!(account.total, Decimal::from_str("12345.6789").unwrap());
+        assert!(account.set_transaction_as_dispute(22334455).is_ok());
+        assert_eq!(account.available, Amount::from_str("0").unwrap());
+        assert_eq!(account.held, Amount::from_str("12345.6789").unwrap());
+        assert_eq!(account.total, Amount::from_str("12345.6789").unwrap());
         assert!(!account.is_locked());
         assert_eq!(account.transactions.len(), 1);
     }
@@ -471,14 +1743,14 @@ mod test {
         let deposit = transaction::Deposit {
             client_id: 12345,
             tx_id: 22334455,
-            amount: Decimal::from_str("12345.6789").unwrap(),
+            amount: Amount::from_str("12345.6789").unwrap(),
         };
-        assert!(account.deposit(&deposit));
+        assert!(account.deposit(&deposit).is_ok());
 
-        assert!(!account.set_transaction_as_dispute(22334456));
-        assert_eq!(account.available, Decimal::from_str("12345.6789").unwrap());
-        assert_eq!(account.held, Decimal::from_str("0").unwrap());
-        assert_eq!(account.total, Decimal::from_str("12345.6789").unwrap());
+        assert!(account.set_transaction_as_dispute(22334456).is_err());
+        assert_eq!(account.available, Amount::from_str("12345.6789").unwrap());
+        assert_eq!(account.held, Amount::from_str("0").unwrap());
+        assert_eq!(account.total, Amount::from_str("12345.6789").unwrap());
         assert!(!account.is_locked());
         assert_eq!(account.transactions.len(), 1);
     }
@@ -490,16 +1762,16 @@ mod test {
         let deposit = transaction::Deposit {
             client_id: 12345,
             tx_id: 22334455,
-            amount: Decimal::from_str("12345.6789").unwrap(),
+            amount: Amount::from_str("12345.6789").unwrap(),
         };
-        assert!(account.deposit(&deposit));
+        assert!(account.deposit(&deposit).is_ok());
 
-        account.available -= Decimal::from_str("0.0001").unwrap();
+        account.available -= Amount::from_str("0.0001").unwrap();
 
-        assert!(!account.set_transaction_as_dispute(22334455));
-        assert_eq!(account.available, Decimal::from_str("12345.6788").unwrap());
-        assert_eq!(account.held, Decimal::from_str("0").unwrap());
-        assert_eq!(account.total, Decimal::from_str("12345.6789").unwrap());
+        assert!(account.set_transaction_as_dispute(22334455).is_err());
+        assert_eq!(account.available, Amount::from_str("12345.6788").unwrap());
+        assert_eq!(account.held, Amount::from_str("0").unwrap());
+        assert_eq!(account.total, Amount::from_str("12345.6789").unwrap());
         assert!(!account.is_locked());
         assert_eq!(account.transactions.len(), 1);
     }
@@ -511,17 +1783,17 @@ mod test {
         let deposit = transaction::Deposit {
             client_id: 12345,
             tx_id: 22334455,
-            amount: Decimal::from_str("12345.6789").unwrap(),
+            amount: Amount::from_str("12345.6789").unwrap(),
         };
-        assert!(account.deposit(&deposit));
+        assert!(account.deposit(&deposit).is_ok());
 
-        let mut transaction = account.transactions.get_mut(0).unwrap();
+        let transaction = account.transactions.get_mut(0).unwrap();
         transaction.status = DepositedTransactionStatus::Dispute;
 
-        assert!(!account.set_transaction_as_dispute(22334455));
-        assert_eq!(account.available, Decimal::from_str("12345.6789").unwrap());
-        assert_eq!(account.held, Decimal::from_str("0").unwrap());
-        assert_eq!(account.total, Decimal::from_str("12345.6789").unwrap());
+        assert!(account.set_transaction_as_dispute(22334455).is_err());
+        assert_eq!(account.available, Amount::from_str("12345.6789").unwrap());
+        assert_eq!(account.held, Amount::from_str("0").unwrap());
+        assert_eq!(account.total, Amount::from_str("12345.6789").unwrap());
         assert!(!account.is_locked());
         assert_eq!(account.transactions.len(), 1);
     }
@@ -533,15 +1805,15 @@ mod test {
         let deposit = transaction::Deposit {
             client_id: 12345,
             tx_id: 22334455,
-            amount: Decimal::from_str("12345.6789").unwrap(),
+            amount: Amount::from_str("12345.6789").unwrap(),
         };
-        assert!(account.deposit(&deposit));
-        assert!(account.set_transaction_as_dispute(22334455));
+        assert!(account.deposit(&deposit).is_ok());
+        assert!(account.set_transaction_as_dispute(22334455).is_ok());
 
-        assert!(account.set_transaction_as_resolved(22334455));
-        assert_eq!(account.available, Decimal::from_str("12345.6789").unwrap());
-        assert_eq!(account.held, Decimal::from_str("0").unwrap());
-        assert_eq!(account.total, Decimal::from_str("12345.6789").unwrap());
+        assert!(account.set_transaction_as_resolved(22334455).is_ok());
+        assert_eq!(account.available, Amount::from_str("12345.6789").unwrap());
+        assert_eq!(account.held, Amount::from_str("0").unwrap());
+        assert_eq!(account.total, Amount::from_str("12345.6789").unwrap());
         assert!(!account.is_locked());
         assert_eq!(account.transactions.len(), 1);
     }
@@ -553,15 +1825,15 @@ mod test {
         let deposit = transaction::Deposit {
             client_id: 12345,
             tx_id: 22334455,
-            amount: Decimal::from_str("12345.6789").unwrap(),
+            amount: Amount::from_str("12345.6789").unwrap(),
         };
-        assert!(account.deposit(&deposit));
-        assert!(account.set_transaction_as_dispute(22334455));
+        assert!(account.deposit(&deposit).is_ok());
+        assert!(account.set_transaction_as_dispute(22334455).is_ok());
 
-        assert!(!account.set_transaction_as_resolved(22334456));
-        assert_eq!(account.available, Decimal::from_str("0").unwrap());
-        assert_eq!(account.held, Decimal::from_str("12345.6789").unwrap());
-        assert_eq!(account.total, Decimal::from_str("12345.6789").unwrap());
+        assert!(account.set_transaction_as_resolved(22334456).is_err());
+        assert_eq!(account.available, Amount::from_str("0").unwrap());
+        assert_eq!(account.held, Amount::from_str("12345.6789").unwrap());
+        assert_eq!(account.total, Amount::from_str("12345.6789").unwrap());
         assert!(!account.is_locked());
         assert_eq!(account.transactions.len(), 1);
     }
@@ -573,17 +1845,17 @@ mod test {
         let deposit = transaction::Deposit {
             client_id: 12345,
             tx_id: 22334455,
-            amount: Decimal::from_str("12345.6789").unwrap(),
+            amount: Amount::from_str("12345.6789").unwrap(),
         };
-        assert!(account.deposit(&deposit));
-        assert!(account.set_transaction_as_dispute(22334455));
+        assert!(account.deposit(&deposit).is_ok());
+        assert!(account.set_transaction_as_dispute(22334455).is_ok());
 
-        account.held -= Decimal::from_str("0.0001").unwrap();
+        account.held -= Amount::from_str("0.0001").unwrap();
 
-        assert!(!account.set_transaction_as_resolved(22334455));
-        assert_eq!(account.available, Decimal::from_str("0").unwrap());
-        assert_eq!(account.held, Decimal::from_str("12345.6788").unwrap());
-        assert_eq!(account.total, Decimal::from_str("12345.6789").unwrap());
+        assert!(account.set_transaction_as_resolved(22334455).is_err());
+        assert_eq!(account.available, Amount::from_str("0").unwrap());
+        assert_eq!(account.held, Amount::from_str("12345.6788").unwrap());
+        assert_eq!(account.total, Amount::from_str("12345.6789").unwrap());
         assert!(!account.is_locked());
         assert_eq!(account.transactions.len(), 1);
     }
@@ -595,18 +1867,18 @@ mod test {
         let deposit = transaction::Deposit {
             client_id: 12345,
             tx_id: 22334455,
-            amount: Decimal::from_str("12345.6789").unwrap(),
+            amount: Amount::from_str("12345.6789").unwrap(),
         };
-        assert!(account.deposit(&deposit));
-        assert!(account.set_transaction_as_dispute(22334455));
+        assert!(account.deposit(&deposit).is_ok());
+        assert!(account.set_transaction_as_dispute(22334455).is_ok());
 
-        let mut transaction = account.transactions.get_mut(0).unwrap();
+        let transaction = account.transactions.get_mut(0).unwrap();
         transaction.status = DepositedTransactionStatus::Accepted;
 
-        assert!(!account.set_transaction_as_resolved(22334455));
-        assert_eq!(account.available, Decimal::from_str("0").unwrap());
-        assert_eq!(account.held, Decimal::from_str("12345.6789").unwrap());
-        assert_eq!(account.total, Decimal::from_str("12345.6789").unwrap());
+        assert!(account.set_transaction_as_resolved(22334455).is_err());
+        assert_eq!(account.available, Amount::from_str("0").unwrap());
+        assert_eq!(account.held, Amount::from_str("12345.6789").unwrap());
+        assert_eq!(account.total, Amount::from_str("12345.6789").unwrap());
         assert!(!account.is_locked());
         assert_eq!(account.transactions.len(), 1);
     }
@@ -618,15 +1890,15 @@ mod test {
         let deposit = transaction::Deposit {
             client_id: 12345,
             tx_id: 22334455,
-            amount: Decimal::from_str("12345.6789").unwrap(),
+            amount: Amount::from_str("12345.6789").unwrap(),
         };
-        assert!(account.deposit(&deposit));
-        assert!(account.set_transaction_as_dispute(22334455));
+        assert!(account.deposit(&deposit).is_ok());
+        assert!(account.set_transaction_as_dispute(22334455).is_ok());
 
-        assert!(account.set_transaction_as_chargeback(22334455));
-        assert_eq!(account.available, Decimal::from_str("0").unwrap());
-        assert_eq!(account.held, Decimal::from_str("0").unwrap());
-        assert_eq!(account.total, Decimal::from_str("0").unwrap());
+        assert!(account.set_transaction_as_chargeback(22334455).is_ok());
+        assert_eq!(account.available, Amount::from_str("0").unwrap());
+        assert_eq!(account.held, Amount::from_str("0").unwrap());
+        assert_eq!(account.total, Amount::from_str("0").unwrap());
         assert!(account.is_locked());
         assert_eq!(account.transactions.len(), 1);
     }
@@ -638,15 +1910,15 @@ mod test {
         let deposit = transaction::Deposit {
             client_id: 12345,
             tx_id: 22334455,
-            amount: Decimal::from_str("12345.6789").unwrap(),
+            amount: Amount::from_str("12345.6789").unwrap(),
         };
-        assert!(account.deposit(&deposit));
-        assert!(account.set_transaction_as_dispute(22334455));
+        assert!(account.deposit(&deposit).is_ok());
+        assert!(account.set_transaction_as_dispute(22334455).is_ok());
 
-        assert!(!account.set_transaction_as_chargeback(22334456));
-        assert_eq!(account.available, Decimal::from_str("0").unwrap());
-        assert_eq!(account.held, Decimal::from_str("12345.6789").unwrap());
-        assert_eq!(account.total, Decimal::from_str("12345.6789").unwrap());
+        assert!(account.set_transaction_as_chargeback(22334456).is_err());
+        assert_eq!(account.available, Amount::from_str("0").unwrap());
+        assert_eq!(account.held, Amount::from_str("12345.6789").unwrap());
+        assert_eq!(account.total, Amount::from_str("12345.6789").unwrap());
         assert!(!account.is_locked());
         assert_eq!(account.transactions.len(), 1);
     }
@@ -658,17 +1930,17 @@ mod test {
         let deposit = transaction::Deposit {
             client_id: 12345,
             tx_id: 22334455,
-            amount: Decimal::from_str("12345.6789").unwrap(),
+            amount: Amount::from_str("12345.6789").unwrap(),
         };
-        assert!(account.deposit(&deposit));
-        assert!(account.set_transaction_as_dispute(22334455));
+        assert!(account.deposit(&deposit).is_ok());
+        assert!(account.set_transaction_as_dispute(22334455).is_ok());
 
-        account.held -= Decimal::from_str("0.0001").unwrap();
+        account.held -= Amount::from_str("0.0001").unwrap();
 
-        assert!(!account.set_transaction_as_chargeback(22334455));
-        assert_eq!(account.available, Decimal::from_str("0").unwrap());
-        assert_eq!(account.held, Decimal::from_str("12345.6788").unwrap());
-        assert_eq!(account.total, Decimal::from_str("12345.6789").unwrap());
+        assert!(account.set_transaction_as_chargeback(22334455).is_err());
+        assert_eq!(account.available, Amount::from_str("0").unwrap());
+        assert_eq!(account.held, Amount::from_str("12345.6788").unwrap());
+        assert_eq!(account.total, Amount::from_str("12345.6789").unwrap());
         assert!(!account.is_locked());
         assert_eq!(account.transactions.len(), 1);
     }
@@ -680,19 +1952,485 @@ mod test {
         let deposit = transaction::Deposit {
             client_id: 12345,
             tx_id: 22334455,
-            amount: Decimal::from_str("12345.6789").unwrap(),
+            amount: Amount::from_str("12345.6789").unwrap(),
         };
-        assert!(account.deposit(&deposit));
-        assert!(account.set_transaction_as_dispute(22334455));
+        assert!(account.deposit(&deposit).is_ok());
+        assert!(account.set_transaction_as_dispute(22334455).is_ok());
 
-        let mut transaction = account.transactions.get_mut(0).unwrap();
+        let transaction = account.transactions.get_mut(0).unwrap();
         transaction.status = DepositedTransactionStatus::Accepted;
 
-        assert!(!account.set_transaction_as_chargeback(22334455));
-        assert_eq!(account.available, Decimal::from_str("0").unwrap());
-        assert_eq!(account.held, Decimal::from_str("12345.6789").unwrap());
-        assert_eq!(account.total, Decimal::from_str("12345.6789").unwrap());
+        assert!(account.set_transaction_as_chargeback(22334455).is_err());
+        assert_eq!(account.available, Amount::from_str("0").unwrap());
+        assert_eq!(account.held, Amount::from_str("12345.6789").unwrap());
+        assert_eq!(account.total, Amount::from_str("12345.6789").unwrap());
         assert!(!account.is_locked());
         assert_eq!(account.transactions.len(), 1);
     }
+
+    #[test]
+    fn test_partial_dispute_and_chargeback() {
+        let mut account = Account::new(12345);
+        let deposit = transaction::Deposit {
+            client_id: 12345,
+            tx_id: 1,
+            amount: Amount::from(100),
+        };
+        assert!(account.deposit(&deposit).is_ok());
+
+        assert_eq!(
+            account.set_transaction_as_partial_dispute(1, Amount::from(101)),
+            Err(TransactionError::ExceedsDisputed)
+        );
+        assert_eq!(
+            account.set_transaction_as_partial_dispute(1, Amount::from(0)),
+            Err(TransactionError::InvalidAmount)
+        );
+        assert!(account
+            .set_transaction_as_partial_dispute(1, Amount::from(40))
+            .is_ok());
+        assert_eq!(account.available, Amount::from(60));
+        assert_eq!(account.held, Amount::from(40));
+
+        // Only a part of the disputed amount may be charged back, the rest is released.
+        assert_eq!(
+            account.set_transaction_as_partial_chargeback(1, Amount::from(50)),
+            Err(TransactionError::ExceedsDisputed)
+        );
+        assert!(account
+            .set_transaction_as_partial_chargeback(1, Amount::from(30))
+            .is_ok());
+        assert_eq!(account.available, Amount::from(70));
+        assert!(account.held.is_zero());
+        assert_eq!(account.total, Amount::from(70));
+        assert!(account.is_locked());
+        let transaction = account.find_transaction(1).unwrap();
+        assert_eq!(transaction.disputed_amount(), Amount::from(40));
+        assert_eq!(transaction.charged_back_amount(), Amount::from(30));
+    }
+
+    #[test]
+    fn test_lock_and_unlock() {
+        let mut account = Account::new(12345);
+
+        let lock = transaction::Lock {
+            client_id: 12345,
+            tx_id: 1,
+        };
+        assert!(account.lock(&lock).is_ok());
+        assert!(account.is_locked());
+        assert!(account.lock(&lock).is_err());
+
+        let unlock = transaction::Unlock {
+            client_id: 12345,
+            tx_id: 2,
+        };
+        assert!(account.unlock(&unlock).is_ok());
+        assert!(!account.is_locked());
+        assert!(account.unlock(&unlock).is_err());
+
+        assert_eq!(account.adjustments.len(), 2);
+        assert_eq!(
+            account.find_adjustment(1).unwrap().kind(),
+            AdjustmentKind::Lock
+        );
+        assert_eq!(
+            account.find_adjustment(2).unwrap().kind(),
+            AdjustmentKind::Unlock
+        );
+    }
+
+    #[test]
+    fn test_adjust() {
+        let mut account = Account::new(12345);
+
+        let adjust = transaction::Adjust {
+            client_id: 12345,
+            tx_id: 1,
+            amount: Amount::from_str("10.5").unwrap(),
+        };
+        assert!(account.adjust(&adjust).is_ok());
+        assert_eq!(account.available, Amount::from_str("10.5").unwrap());
+        assert_eq!(account.total, Amount::from_str("10.5").unwrap());
+
+        let adjust = transaction::Adjust {
+            client_id: 12345,
+            tx_id: 2,
+            amount: Amount::from_str("-0.5").unwrap(),
+        };
+        assert!(account.adjust(&adjust).is_ok());
+        assert_eq!(account.available, Amount::from_str("10").unwrap());
+        assert_eq!(
+            account.find_adjustment(2).unwrap().amount(),
+            Amount::from_str("-0.5").unwrap()
+        );
+
+        let adjust = transaction::Adjust {
+            client_id: 12345,
+            tx_id: 3,
+            amount: Amount::from_str("-10.01").unwrap(),
+        };
+        assert!(account.adjust(&adjust).is_err());
+        assert_eq!(account.available, Amount::from_str("10").unwrap());
+        assert_eq!(account.adjustments.len(), 2);
+    }
+
+    #[test]
+    fn test_annotate_does_not_change_balance() {
+        let mut account = Account::new(12345);
+
+        let annotate = transaction::Annotate {
+            client_id: 12345,
+            tx_id: 1,
+        };
+        assert!(account.annotate(&annotate).is_ok());
+        assert_eq!(account.available, Amount::from(0));
+        assert_eq!(
+            account.find_adjustment(1).unwrap().kind(),
+            AdjustmentKind::Annotate
+        );
+
+        let annotate = transaction::Annotate {
+            client_id: 1,
+            tx_id: 2,
+        };
+        assert!(account.annotate(&annotate).is_err());
+    }
+
+    #[test]
+    fn test_disputes_follow_transition_table() {
+        for state in statemachine::STATES {
+            for event in statemachine::EVENTS {
+                let mut account = Account::new(1);
+                let deposit = transaction::Deposit {
+                    client_id: 1,
+                    tx_id: 1,
+                    amount: Amount::from(10),
+                };
+                assert!(account.deposit(&deposit).is_ok());
+                // Balances are consistent with the state, so only the table decides.
+                account.transactions[0].status = state;
+                if state == DepositedTransactionStatus::Dispute {
+                    account.available = Amount::from(0);
+                    account.held = Amount::from(10);
+                }
+
+                let applied = match event {
+                    Event::Dispute => account.set_transaction_as_dispute(1),
+                    Event::Resolve => account.set_transaction_as_resolved(1),
+                    Event::Chargeback => account.set_transaction_as_chargeback(1),
+                    Event::Representment => account.set_transaction_as_represented(1),
+                };
+                let transition = statemachine::transition(state, event);
+                match transition {
+                    Some(_) => assert_eq!(applied, Ok(())),
+                    None => assert_eq!(
+                        applied,
+                        Err(TransactionError::InvalidState { state, event })
+                    ),
+                }
+                let expected = transition.map_or(state, |transition| transition.to);
+                assert_eq!(account.transactions[0].status, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn test_representment_reverses_chargeback() {
+        let mut account = Account::new(1);
+        for tx_id in 1..=2 {
+            let deposit = transaction::Deposit {
+                client_id: 1,
+                tx_id,
+                amount: Amount::from(10),
+            };
+            assert!(account.deposit(&deposit).is_ok());
+            assert!(account.set_transaction_as_dispute(tx_id).is_ok());
+            assert!(account.set_transaction_as_chargeback(tx_id).is_ok());
+        }
+        assert!(account.total.is_zero());
+
+        let config = EngineConfig {
+            dispute_policy: DisputePolicy {
+                unlock_on_representment: true,
+                ..DisputePolicy::default()
+            },
+            ..EngineConfig::default()
+        };
+        assert!(account
+            .apply_event_with(1, Event::Representment, None, &config)
+            .is_ok());
+        assert_eq!(account.available, Amount::from(10));
+        assert_eq!(account.total, Amount::from(10));
+        // The other chargeback keeps the account locked.
+        assert!(account.is_locked());
+        assert!(account
+            .apply_event_with(1, Event::Representment, None, &config)
+            .is_err());
+
+        assert!(account
+            .apply_event_with(2, Event::Representment, None, &config)
+            .is_ok());
+        assert_eq!(account.total, Amount::from(20));
+        assert!(!account.is_locked());
+        assert_eq!(
+            account.find_transaction(2).unwrap().status(),
+            DepositedTransactionStatus::Represented
+        );
+    }
+
+    #[test]
+    fn test_dispute_policy_rules() {
+        let mut account = Account::new(1);
+        account.last_activity = 100;
+        assert!(account
+            .deposit(&transaction::Deposit {
+                client_id: 1,
+                tx_id: 1,
+                amount: Amount::from(10),
+            })
+            .is_ok());
+        account.available = Amount::from(3);
+        account.total = Amount::from(3);
+
+        let config = EngineConfig {
+            dispute_policy: DisputePolicy {
+                redispute_resolved: true,
+                hold_beyond_available: true,
+                window: Some(60),
+                ..DisputePolicy::default()
+            },
+            ..EngineConfig::default()
+        };
+        assert!(account
+            .apply_event_with(1, Event::Dispute, None, &EngineConfig::default())
+            .is_err());
+        assert!(account
+            .apply_event_with(1, Event::Dispute, None, &config)
+            .is_ok());
+        assert_eq!(account.available, Amount::from(-7));
+        assert_eq!(account.held, Amount::from(10));
+        assert_eq!(account.debt(), Amount::from(7));
+
+        assert!(account
+            .apply_event_with(1, Event::Resolve, None, &config)
+            .is_ok());
+        assert_eq!(account.debt(), Amount::from(0));
+        assert!(account
+            .apply_event_with(1, Event::Dispute, None, &EngineConfig::default())
+            .is_err());
+        account.last_activity = 161;
+        assert!(account
+            .apply_event_with(1, Event::Dispute, None, &config)
+            .is_err());
+        account.last_activity = 160;
+        assert!(account
+            .apply_event_with(1, Event::Dispute, None, &config)
+            .is_ok());
+        assert_eq!(
+            account.find_transaction(1).unwrap().status(),
+            DepositedTransactionStatus::Dispute
+        );
+    }
+
+    #[test]
+    fn test_expired_disputes() {
+        let mut account = account_with_withdrawal();
+        let config = EngineConfig {
+            dispute_policy: DisputePolicy {
+                hold_beyond_available: true,
+                resolve_after: Some(60),
+                ..DisputePolicy::default()
+            },
+            ..EngineConfig::default()
+        };
+        let policy = &config.dispute_policy;
+        assert!(account.expired_disputes(policy, 1000).is_empty());
+
+        account.last_activity = 100;
+        assert!(account
+            .apply_event_with(1, Event::Dispute, None, &config)
+            .is_ok());
+        account.last_activity = 130;
+        assert!(account
+            .apply_event_with(2, Event::Dispute, None, &config)
+            .is_ok());
+        assert!(account.expired_disputes(policy, 160).is_empty());
+        assert_eq!(account.expired_disputes(policy, 161), vec![1]);
+        assert_eq!(account.expired_disputes(policy, 191), vec![1, 2]);
+        assert!(account
+            .expired_disputes(&DisputePolicy::default(), 191)
+            .is_empty());
+
+        assert!(account.set_transaction_as_resolved(1).is_ok());
+        assert_eq!(account.expired_disputes(policy, 191), vec![2]);
+    }
+
+    // Account with 10 deposited and 4 withdrawn, the withdrawal recorded for disputes.
+    fn account_with_withdrawal() -> Account {
+        let mut account = Account::new(1);
+        assert!(account
+            .deposit(&transaction::Deposit {
+                client_id: 1,
+                tx_id: 1,
+                amount: Amount::from(10),
+            })
+            .is_ok());
+        assert!(account
+            .withdrawal(&transaction::Withdrawal {
+                client_id: 1,
+                tx_id: 2,
+                amount: Amount::from(4),
+            })
+            .is_ok());
+        account.add_transaction(DepositedTransaction {
+            tx_id: 2,
+            amount: Amount::from(4),
+            status: DepositedTransactionStatus::Accepted,
+            period: None,
+            direction: Direction::Withdrawal,
+            time: 0,
+            disputed: None,
+            charged_back: None,
+            disputed_at: None,
+        });
+        account
+    }
+
+    #[test]
+    fn test_withdrawal_dispute_and_resolve() {
+        let mut account = account_with_withdrawal();
+        // Disputed funds are held even if nothing is available.
+        account.available = Amount::from(0);
+        account.total = Amount::from(0);
+
+        assert!(account.set_transaction_as_dispute(2).is_ok());
+        assert_eq!(account.available, Amount::from(0));
+        assert_eq!(account.held, Amount::from(4));
+        assert_eq!(account.total, Amount::from(4));
+
+        assert!(account.set_transaction_as_resolved(2).is_ok());
+        assert_eq!(account.available, Amount::from(0));
+        assert_eq!(account.held, Amount::from(0));
+        assert_eq!(account.total, Amount::from(0));
+        assert!(!account.is_locked());
+    }
+
+    #[test]
+    fn test_withdrawal_chargeback_returns_funds() {
+        let mut account = account_with_withdrawal();
+
+        assert!(account.set_transaction_as_dispute(2).is_ok());
+        assert!(account.set_transaction_as_chargeback(2).is_ok());
+        assert_eq!(account.available, Amount::from(10));
+        assert_eq!(account.held, Amount::from(0));
+        assert_eq!(account.total, Amount::from(10));
+        assert!(account.is_locked());
+        assert_eq!(
+            account.find_transaction(2).unwrap().status(),
+            DepositedTransactionStatus::Chargeback
+        );
+    }
+
+    #[test]
+    fn test_held_funds_by_origin() {
+        let mut account = account_with_withdrawal();
+        // Enough funds are available to dispute the deposit too.
+        account.available = Amount::from(10);
+        account.total = Amount::from(10);
+
+        assert!(account.set_transaction_as_dispute(2).is_ok());
+        assert!(account.set_transaction_as_dispute(1).is_ok());
+        assert_eq!(account.held, Amount::from(14));
+        assert_eq!(account.held_deposits(), Amount::from(10));
+        assert_eq!(account.held_withdrawals, Amount::from(4));
+
+        assert!(account.set_transaction_as_resolved(2).is_ok());
+        assert_eq!(account.held_deposits(), Amount::from(10));
+        assert_eq!(account.held_withdrawals, Amount::from(0));
+    }
+
+    #[test]
+    fn test_withdrawal_limits() {
+        let config = EngineConfig {
+            limits: Limits {
+                max_withdrawal: Some(Amount::from(5)),
+                daily_withdrawals: Some(Amount::from(8)),
+                ..Limits::default()
+            },
+            ..EngineConfig::default()
+        };
+        let withdrawal = |tx_id, amount| transaction::Withdrawal {
+            client_id: 1,
+            tx_id,
+            amount: Amount::from(amount),
+        };
+        let mut account = Account::new(1);
+        account.available = Amount::from(20);
+        account.total = Amount::from(20);
+        account.last_activity = SECONDS_PER_DAY + 10;
+
+        assert_eq!(
+            account.withdrawal_with(&withdrawal(1, 6), &config),
+            Err(TransactionError::WithdrawalLimit)
+        );
+        assert!(account.withdrawal_with(&withdrawal(2, 5), &config).is_ok());
+        assert_eq!(
+            account.withdrawal_with(&withdrawal(3, 4), &config),
+            Err(TransactionError::DailyWithdrawalLimit)
+        );
+        assert!(account.withdrawal_with(&withdrawal(4, 3), &config).is_ok());
+        assert_eq!(account.available, Amount::from(12));
+
+        // Withdrawals of the next day have their own limit.
+        account.last_activity = 2 * SECONDS_PER_DAY;
+        assert!(account.withdrawal_with(&withdrawal(5, 4), &config).is_ok());
+        assert_eq!(account.withdrawn_today, Amount::from(4));
+    }
+
+    #[test]
+    fn test_balance_limit() {
+        let config = EngineConfig {
+            limits: Limits {
+                max_balance: Some(Amount::from(10)),
+                ..Limits::default()
+            },
+            ..EngineConfig::default()
+        };
+        let deposit = |tx_id, amount| transaction::Deposit {
+            client_id: 1,
+            tx_id,
+            amount: Amount::from(amount),
+        };
+        let mut account = Account::new(1);
+        assert!(account.deposit_with(&deposit(1, 7), &config).is_ok());
+        assert_eq!(
+            account.deposit_with(&deposit(2, 4), &config),
+            Err(TransactionError::BalanceLimit)
+        );
+        assert!(account.deposit_with(&deposit(3, 3), &config).is_ok());
+        assert_eq!(account.total, Amount::from(10));
+        assert!(account.find_transaction(2).is_none());
+    }
+
+    #[test]
+    fn test_tag_period() {
+        let january: Period = Arc::from("2024-01");
+        let february: Period = Arc::from("2024-02");
+        let mut account = Account::new(1);
+        let deposit = transaction::Deposit {
+            client_id: 1,
+            tx_id: 1,
+            amount: Amount::from(10),
+        };
+        assert!(account.deposit(&deposit).is_ok());
+        assert!(account.find_transaction(1).unwrap().period().is_none());
+
+        account.tag_period(1, &january);
+        assert!(account.set_transaction_as_dispute(1).is_ok());
+        account.tag_period(1, &february);
+        account.tag_period(2, &february);
+        let transaction = account.find_transaction(1).unwrap();
+        assert_eq!(transaction.period(), Some(&january));
+    }
 }
@@ -0,0 +1,840 @@
+use crate::amount::Amount;
+
+use anyhow::{anyhow, bail, Context};
+use log::error;
+use serde::Serialize;
+use std::collections::{HashSet, VecDeque};
+use std::ffi::OsString;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::mem;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::str::FromStr;
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+// Alert rules are evaluated after every processed row, so anomalies of a batch run are reported
+// while it runs instead of being found in logs afterwards. A rule fires once when its condition
+// starts to hold and again only after the condition cleared in between.
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum AlertRule {
+    // More than `max_percent` of the last `window` rows are applied chargebacks.
+    ChargebackRate { max_percent: f64, window: u64 },
+    // Held funds of an account are above `max`.
+    HeldFunds { max: Amount },
+    // More than `max_locks` accounts were locked within the last `window` rows.
+    LockRate { max_locks: usize, window: u64 },
+    // Every applied chargeback.
+    Chargeback,
+    // Every locked account.
+    Lock,
+}
+
+impl AlertRule {
+    pub fn name(&self) -> &'static str {
+        match self {
+            AlertRule::ChargebackRate { .. } => "chargeback-rate",
+            AlertRule::HeldFunds { .. } => "held-funds",
+            AlertRule::LockRate { .. } => "lock-rate",
+            AlertRule::Chargeback => "chargeback",
+            AlertRule::Lock => "lock",
+        }
+    }
+}
+
+fn parse_field<T: FromStr>(rule: &str, value: Option<&str>) -> anyhow::Result<T> {
+    value
+        .and_then(|value| value.parse().ok())
+        .ok_or_else(|| anyhow!("invalid alert rule: {}", rule))
+}
+
+// Rule is given as `chargeback-rate:<percent>:<rows>`, `held-funds:<amount>`,
+// `lock-rate:<locks>:<rows>`, `chargeback` or `lock`.
+impl FromStr for AlertRule {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<AlertRule> {
+        let mut fields = s.split(':');
+        let rule = match fields.next() {
+            Some("chargeback-rate") => AlertRule::ChargebackRate {
+                max_percent: parse_field(s, fields.next())?,
+                window: parse_field(s, fields.next())?,
+            },
+            Some("held-funds") => AlertRule::HeldFunds {
+                max: parse_field(s, fields.next())?,
+            },
+            Some("lock-rate") => AlertRule::LockRate {
+                max_locks: parse_field(s, fields.next())?,
+                window: parse_field(s, fields.next())?,
+            },
+            Some("chargeback") => AlertRule::Chargeback,
+            Some("lock") => AlertRule::Lock,
+            _ => bail!("unknown alert rule: {}", s),
+        };
+        if fields.next().is_some() {
+            bail!("invalid alert rule: {}", s);
+        }
+        if let AlertRule::ChargebackRate { window: 0, .. } | AlertRule::LockRate { window: 0, .. } =
+            rule
+        {
+            bail!("window of alert rule must be at least 1 row: {}", s);
+        }
+
+        Ok(rule)
+    }
+}
+
+#[derive(Serialize, PartialEq, Debug)]
+pub struct Alert {
+    pub rule: &'static str,
+    // Number of the row after which the rule fired, counted from 1.
+    pub row: u64,
+    // Client the alert is about, if it's about a single one.
+    pub client: Option<u16>,
+    // Transaction the alert is about, if it's about a single one.
+    pub tx: Option<u32>,
+    pub message: String,
+}
+
+// Destination of fired alerts.
+pub trait Notifier: Send {
+    fn notify(&mut self, alert: &Alert) -> anyhow::Result<()>;
+}
+
+pub struct StderrNotifier;
+
+impl Notifier for StderrNotifier {
+    fn notify(&mut self, alert: &Alert) -> anyhow::Result<()> {
+        eprintln!("alert: {} (row {})", alert.message, alert.row);
+        Ok(())
+    }
+}
+
+// Appends every alert to a file as a JSON line.
+pub struct FileNotifier {
+    file: File,
+}
+
+impl FileNotifier {
+    pub fn open(path: &str) -> anyhow::Result<FileNotifier> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("can not open alert file {:?}", path))?;
+        Ok(FileNotifier { file })
+    }
+}
+
+impl Notifier for FileNotifier {
+    fn notify(&mut self, alert: &Alert) -> anyhow::Result<()> {
+        let mut line = serde_json::to_vec(alert)?;
+        line.push(b'\n');
+        self.file.write_all(&line)?;
+        Ok(())
+    }
+}
+
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Posts every alert as a JSON object to a plain HTTP endpoint, for example a local relay into a
+// chat or paging system.
+pub struct WebhookNotifier {
+    host: String,
+    path: String,
+}
+
+impl WebhookNotifier {
+    pub fn new(url: &str) -> anyhow::Result<WebhookNotifier> {
+        let address = match url.strip_prefix("http://") {
+            None => bail!("webhook URL must start with http://: {}", url),
+            Some(address) => address,
+        };
+        let (host, path) = match address.find('/') {
+            None => (address, "/"),
+            Some(index) => address.split_at(index),
+        };
+        if host.is_empty() {
+            bail!("webhook URL has no host: {}", url);
+        }
+        let host = if host.contains(':') {
+            host.to_string()
+        } else {
+            format!("{}:80", host)
+        };
+
+        Ok(WebhookNotifier {
+            host,
+            path: path.to_string(),
+        })
+    }
+}
+
+impl WebhookNotifier {
+    // Every address of the host is tried, each within the timeout, so an unreachable host doesn't
+    // hang delivery for the system's connect timeout.
+    fn connect(&self) -> anyhow::Result<TcpStream> {
+        let mut last_error = None;
+        for address in self.host.to_socket_addrs()? {
+            match TcpStream::connect_timeout(&address, WEBHOOK_TIMEOUT) {
+                Ok(stream) => return Ok(stream),
+                Err(err) => last_error = Some(err),
+            }
+        }
+        match last_error {
+            None => bail!("webhook host {} has no address", self.host),
+            Some(err) => Err(err.into()),
+        }
+    }
+}
+
+impl Notifier for WebhookNotifier {
+    fn notify(&mut self, alert: &Alert) -> anyhow::Result<()> {
+        let body = serde_json::to_string(alert)?;
+        let mut stream = self
+            .connect()
+            .with_context(|| format!("can not connect to webhook {}", self.host))?;
+        stream.set_read_timeout(Some(WEBHOOK_TIMEOUT))?;
+        stream.set_write_timeout(Some(WEBHOOK_TIMEOUT))?;
+        write!(
+            stream,
+            "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\n\
+             Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+            self.path,
+            self.host,
+            body.len(),
+            body
+        )?;
+
+        let mut status = String::new();
+        BufReader::new(stream).read_line(&mut status)?;
+        match status.split_whitespace().nth(1) {
+            Some(code) if code.starts_with('2') => Ok(()),
+            _ => bail!("webhook {} answered {:?}", self.host, status.trim_end()),
+        }
+    }
+}
+
+// Alerts which notifiers failed to take after all attempts, appended as JSON lines with the error,
+// so they can be delivered later.
+pub struct DeadLetters {
+    file: File,
+}
+
+#[derive(Serialize)]
+struct DeadLetter<'a> {
+    error: String,
+    alert: &'a Alert,
+}
+
+impl DeadLetters {
+    pub fn open(path: &OsString) -> anyhow::Result<DeadLetters> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("can not open dead letter file {:?}", path))?;
+        Ok(DeadLetters { file })
+    }
+
+    fn write(&mut self, alert: &Alert, error: &anyhow::Error) -> anyhow::Result<()> {
+        let letter = DeadLetter {
+            error: format!("{:#}", error),
+            alert,
+        };
+        let mut line = serde_json::to_vec(&letter)?;
+        line.push(b'\n');
+        self.file.write_all(&line)?;
+        Ok(())
+    }
+}
+
+// Notifier is given as `stderr`, `file:<path>` or `webhook:<http url>`.
+pub fn parse_notifier(s: &str) -> anyhow::Result<Box<dyn Notifier>> {
+    if s == "stderr" {
+        return Ok(Box::new(StderrNotifier));
+    }
+    if let Some(path) = s.strip_prefix("file:") {
+        return Ok(Box::new(FileNotifier::open(path)?));
+    }
+    if let Some(url) = s.strip_prefix("webhook:") {
+        return Ok(Box::new(WebhookNotifier::new(url)?));
+    }
+    bail!("unknown notifier: {}", s)
+}
+
+// Row numbers of events within the last `size` rows.
+struct Window {
+    size: u64,
+    events: VecDeque<u64>,
+}
+
+impl Window {
+    fn new(size: u64) -> Window {
+        Window {
+            size,
+            events: VecDeque::new(),
+        }
+    }
+
+    // Moves the window to end at `row` and returns a number of events in it.
+    fn count(&mut self, row: u64, event: bool) -> usize {
+        if event {
+            self.events.push_back(row);
+        }
+        while let Some(first) = self.events.front() {
+            if first + self.size > row {
+                break;
+            }
+            self.events.pop_front();
+        }
+        self.events.len()
+    }
+}
+
+// Outcome of a processed row which alert rules look at.
+pub struct Observation {
+    pub client: u16,
+    pub tx: u32,
+    // An applied chargeback.
+    pub chargeback: bool,
+    // The account was locked by the row.
+    pub locked: bool,
+    // Held funds of the account after the row.
+    pub held: Amount,
+}
+
+// Delay before the first retry of a failed delivery, doubled for every next one.
+const RETRY_DELAY: Duration = Duration::from_millis(100);
+// Alerts waiting for delivery. A bounded queue keeps alerts of a long outage of a notifier from
+// growing memory without end, alerts which don't fit are kept as dead letters.
+const DELIVERY_QUEUE: usize = 1024;
+
+enum RuleState {
+    Window { window: Window, firing: bool },
+    Clients(HashSet<u16>),
+    // Rules which fire on every event.
+    Events,
+}
+
+// Notifiers with their retries and dead letters, which deliver alerts on a thread of their own, so
+// a slow or unreachable notifier and delays between retries don't hold up processing.
+struct Delivery {
+    notifiers: Vec<Box<dyn Notifier>>,
+    retries: u32,
+    dead_letters: Option<Arc<Mutex<DeadLetters>>>,
+}
+
+impl Delivery {
+    fn run(mut self, receiver: Receiver<Alert>) {
+        for alert in receiver {
+            self.deliver(&alert);
+        }
+    }
+
+    fn deliver(&mut self, alert: &Alert) {
+        for notifier in &mut self.notifiers {
+            let mut delivered = notifier.notify(alert);
+            let mut delay = RETRY_DELAY;
+            for _ in 0..self.retries {
+                if delivered.is_ok() {
+                    break;
+                }
+                thread::sleep(delay);
+                delay *= 2;
+                delivered = notifier.notify(alert);
+            }
+            let err = match delivered {
+                Ok(()) => continue,
+                Err(err) => err,
+            };
+            reject(self.dead_letters.as_ref(), alert, err);
+        }
+    }
+}
+
+// Logs an alert which can't be delivered and keeps it as a dead letter.
+fn reject(dead_letters: Option<&Arc<Mutex<DeadLetters>>>, alert: &Alert, err: anyhow::Error) {
+    error!("can not deliver {} alert: {:#}", alert.rule, err);
+    let dead_letters = match dead_letters {
+        None => return,
+        Some(dead_letters) => dead_letters,
+    };
+    let written = match dead_letters.lock() {
+        Ok(mut dead_letters) => dead_letters.write(alert, &err),
+        Err(_) => Err(anyhow!("dead letters are poisoned")),
+    };
+    if let Err(err) = written {
+        error!(
+            "can not write dead letter of {} alert: {:#}",
+            alert.rule, err
+        );
+    }
+}
+
+enum Deliverer {
+    // Nothing fired yet, so the thread isn't started.
+    Waiting(Delivery),
+    Started {
+        sender: SyncSender<Alert>,
+        handle: JoinHandle<()>,
+        // Shared with the thread, as alerts which don't fit into the queue are kept here.
+        dead_letters: Option<Arc<Mutex<DeadLetters>>>,
+    },
+    Stopped,
+}
+
+pub struct Alerts {
+    rules: Vec<(AlertRule, RuleState)>,
+    rows: u64,
+    deliverer: Deliverer,
+}
+
+impl Alerts {
+    pub fn new(rules: Vec<AlertRule>, notifiers: Vec<Box<dyn Notifier>>) -> Alerts {
+        let rules = rules
+            .into_iter()
+            .map(|rule| {
+                let state = match &rule {
+                    AlertRule::ChargebackRate { window, .. }
+                    | AlertRule::LockRate { window, .. } => RuleState::Window {
+                        window: Window::new(*window),
+                        firing: false,
+                    },
+                    AlertRule::HeldFunds { .. } => RuleState::Clients(HashSet::new()),
+                    AlertRule::Chargeback | AlertRule::Lock => RuleState::Events,
+                };
+                (rule, state)
+            })
+            .collect();
+
+        Alerts {
+            rules,
+            rows: 0,
+            deliverer: Deliverer::Waiting(Delivery {
+                notifiers,
+                retries: 0,
+                dead_letters: None,
+            }),
+        }
+    }
+
+    // A failed delivery is tried again `retries` times, with a growing delay between attempts.
+    pub fn set_retries(&mut self, retries: u32) {
+        if let Deliverer::Waiting(delivery) = &mut self.deliverer {
+            delivery.retries = retries;
+        }
+    }
+
+    pub fn set_dead_letters(&mut self, dead_letters: DeadLetters) {
+        if let Deliverer::Waiting(delivery) = &mut self.deliverer {
+            delivery.dead_letters = Some(Arc::new(Mutex::new(dead_letters)));
+        }
+    }
+
+    // Hands an alert over to the delivery thread, which is started with the first alert.
+    fn send(&mut self, alert: Alert) {
+        self.deliverer = match mem::replace(&mut self.deliverer, Deliverer::Stopped) {
+            Deliverer::Waiting(delivery) => start(delivery),
+            deliverer => deliverer,
+        };
+        match &self.deliverer {
+            Deliverer::Started {
+                sender,
+                dead_letters,
+                ..
+            } => match sender.try_send(alert) {
+                Ok(()) => {}
+                Err(TrySendError::Full(alert)) => reject(
+                    dead_letters.as_ref(),
+                    &alert,
+                    anyhow!("delivery queue is full"),
+                ),
+                Err(TrySendError::Disconnected(alert)) => {
+                    reject(dead_letters.as_ref(), &alert, anyhow!("delivery stopped"))
+                }
+            },
+            _ => error!("can not deliver {} alert, delivery stopped", alert.rule),
+        }
+    }
+
+    // Waits until alerts handed over so far are delivered or kept as dead letters. Alerts are
+    // finished when they are dropped too.
+    pub fn finish(&mut self) {
+        if let Deliverer::Started { sender, handle, .. } =
+            mem::replace(&mut self.deliverer, Deliverer::Stopped)
+        {
+            drop(sender);
+            if handle.join().is_err() {
+                error!("alert delivery thread panicked");
+            }
+        }
+    }
+
+    // Evaluates all rules after a row and notifies about the ones which started to fire. Alerts
+    // are delivered by another thread, and ones which can't be delivered are logged and kept as
+    // dead letters, so a broken notifier doesn't stop or slow down processing.
+    pub fn observe(&mut self, observation: &Observation) {
+        self.rows += 1;
+        let row = self.rows;
+
+        let mut fired = Vec::new();
+        for (rule, state) in &mut self.rules {
+            let name = rule.name();
+            let alert = match (rule, state) {
+                (
+                    AlertRule::ChargebackRate {
+                        max_percent,
+                        window: size,
+                    },
+                    RuleState::Window { window, firing },
+                ) => {
+                    let chargebacks = window.count(row, observation.chargeback);
+                    // The rate is evaluated only once the window is full.
+                    let percent = chargebacks as f64 * 100.0 / *size as f64;
+                    let holds = row >= *size && percent > *max_percent;
+                    fire_once(firing, holds).then(|| Alert {
+                        rule: name,
+                        row,
+                        client: None,
+                        tx: None,
+                        message: format!(
+                            "{} chargebacks ({:.2}%) in the last {} rows",
+                            chargebacks, percent, size
+                        ),
+                    })
+                }
+                (
+                    AlertRule::LockRate {
+                        max_locks,
+                        window: size,
+                    },
+                    RuleState::Window { window, firing },
+                ) => {
+                    let locks = window.count(row, observation.locked);
+                    fire_once(firing, locks > *max_locks).then(|| Alert {
+                        rule: name,
+                        row,
+                        client: None,
+                        tx: None,
+                        message: format!("{} accounts locked in the last {} rows", locks, size),
+                    })
+                }
+                (AlertRule::HeldFunds { max }, RuleState::Clients(clients)) => {
+                    let mut firing = clients.contains(&observation.client);
+                    let alert = fire_once(&mut firing, observation.held > *max).then(|| Alert {
+                        rule: name,
+                        row,
+                        client: Some(observation.client),
+                        tx: None,
+                        message: format!(
+                            "account {} holds {} which is above {}",
+                            observation.client, observation.held, max
+                        ),
+                    });
+                    if firing {
+                        clients.insert(observation.client);
+                    } else {
+                        clients.remove(&observation.client);
+                    }
+                    alert
+                }
+                (AlertRule::Chargeback, RuleState::Events) => {
+                    observation.chargeback.then(|| Alert {
+                        rule: name,
+                        row,
+                        client: Some(observation.client),
+                        tx: Some(observation.tx),
+                        message: format!(
+                            "chargeback of tx {} executed on account {}",
+                            observation.tx, observation.client
+                        ),
+                    })
+                }
+                (AlertRule::Lock, RuleState::Events) => observation.locked.then(|| Alert {
+                    rule: name,
+                    row,
+                    client: Some(observation.client),
+                    tx: Some(observation.tx),
+                    message: format!(
+                        "account {} locked by tx {}",
+                        observation.client, observation.tx
+                    ),
+                }),
+                _ => None,
+            };
+            fired.extend(alert);
+        }
+
+        for alert in fired {
+            self.send(alert);
+        }
+    }
+}
+
+fn start(delivery: Delivery) -> Deliverer {
+    let (sender, receiver) = mpsc::sync_channel(DELIVERY_QUEUE);
+    let dead_letters = delivery.dead_letters.clone();
+    match thread::Builder::new()
+        .name(String::from("alerts"))
+        .spawn(move || delivery.run(receiver))
+    {
+        Ok(handle) => Deliverer::Started {
+            sender,
+            handle,
+            dead_letters,
+        },
+        Err(err) => {
+            error!("can not start alert delivery thread: {}", err);
+            Deliverer::Stopped
+        }
+    }
+}
+
+impl Drop for Alerts {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
+
+// Updates whether a rule is firing and returns true when it just started to fire.
+fn fire_once(firing: &mut bool, holds: bool) -> bool {
+    let started = holds && !*firing;
+    *firing = holds;
+    started
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    struct MemoryNotifier(Arc<Mutex<Vec<Alert>>>);
+
+    impl Notifier for MemoryNotifier {
+        fn notify(&mut self, alert: &Alert) -> anyhow::Result<()> {
+            self.0.lock().unwrap().push(Alert {
+                rule: alert.rule,
+                row: alert.row,
+                client: alert.client,
+                tx: alert.tx,
+                message: alert.message.clone(),
+            });
+            Ok(())
+        }
+    }
+
+    fn observation(client: u16, chargeback: bool, held: i64) -> Observation {
+        Observation {
+            client,
+            tx: 1,
+            chargeback,
+            locked: chargeback,
+            held: Amount::from(held),
+        }
+    }
+
+    #[test]
+    fn test_parse_rules() {
+        assert_eq!(
+            "chargeback-rate:5:1000".parse::<AlertRule>().unwrap(),
+            AlertRule::ChargebackRate {
+                max_percent: 5.0,
+                window: 1000
+            }
+        );
+        assert_eq!(
+            "held-funds:10000".parse::<AlertRule>().unwrap(),
+            AlertRule::HeldFunds {
+                max: Amount::from(10000)
+            }
+        );
+        assert!("lock-rate:3".parse::<AlertRule>().is_err());
+        assert!("lock-rate:3:0".parse::<AlertRule>().is_err());
+        assert!("held-funds:1:2".parse::<AlertRule>().is_err());
+        assert_eq!("lock".parse::<AlertRule>().unwrap(), AlertRule::Lock);
+        assert!("chargeback:1".parse::<AlertRule>().is_err());
+        assert!("spike".parse::<AlertRule>().is_err());
+    }
+
+    #[test]
+    fn test_parse_notifiers() {
+        assert!(parse_notifier("stderr").is_ok());
+        assert!(parse_notifier("webhook:http://localhost:8080/alerts").is_ok());
+        assert!(parse_notifier("webhook:https://example.com").is_err());
+        assert!(parse_notifier("pager").is_err());
+
+        // Nothing listens on port 1, so the connection is refused.
+        let mut webhook = WebhookNotifier::new("http://127.0.0.1:1/alerts").unwrap();
+        let alert = Alert {
+            rule: "lock",
+            row: 1,
+            client: Some(1),
+            tx: Some(1),
+            message: String::from("account 1 locked by tx 1"),
+        };
+        assert!(webhook.notify(&alert).is_err());
+    }
+
+    #[test]
+    fn test_rules_fire_once() {
+        let fired = Arc::new(Mutex::new(Vec::new()));
+        let mut alerts = Alerts::new(
+            vec![
+                "chargeback-rate:10:10".parse().unwrap(),
+                "held-funds:100".parse().unwrap(),
+                "lock-rate:1:5".parse().unwrap(),
+            ],
+            vec![Box::new(MemoryNotifier(fired.clone()))],
+        );
+
+        for _ in 0..8 {
+            alerts.observe(&observation(1, false, 0));
+        }
+        alerts.observe(&observation(2, true, 0));
+        alerts.observe(&observation(3, true, 200));
+        alerts.observe(&observation(3, false, 300));
+        alerts.observe(&observation(3, false, 0));
+        alerts.observe(&observation(3, false, 200));
+        alerts.finish();
+
+        let fired = fired.lock().unwrap();
+        let rules: Vec<(&str, u64)> = fired.iter().map(|alert| (alert.rule, alert.row)).collect();
+        assert_eq!(
+            rules,
+            vec![
+                ("chargeback-rate", 10),
+                ("held-funds", 10),
+                ("lock-rate", 10),
+                ("held-funds", 13)
+            ]
+        );
+        assert_eq!(fired[1].client, Some(3));
+    }
+
+    #[test]
+    fn test_event_rules_fire_every_time() {
+        let fired = Arc::new(Mutex::new(Vec::new()));
+        let mut alerts = Alerts::new(
+            vec![AlertRule::Chargeback, AlertRule::Lock],
+            vec![Box::new(MemoryNotifier(fired.clone()))],
+        );
+        alerts.observe(&observation(1, true, 0));
+        alerts.observe(&observation(2, false, 0));
+        alerts.observe(&observation(3, true, 0));
+        alerts.finish();
+
+        let fired = fired.lock().unwrap();
+        let rules: Vec<(&str, Option<u16>)> = fired
+            .iter()
+            .map(|alert| (alert.rule, alert.client))
+            .collect();
+        assert_eq!(
+            rules,
+            vec![
+                ("chargeback", Some(1)),
+                ("lock", Some(1)),
+                ("chargeback", Some(3)),
+                ("lock", Some(3))
+            ]
+        );
+        assert_eq!(fired[0].tx, Some(1));
+    }
+
+    // Delivers an alert only once it's let through.
+    struct BlockedNotifier(mpsc::Receiver<()>, Arc<Mutex<u32>>);
+
+    impl Notifier for BlockedNotifier {
+        fn notify(&mut self, _: &Alert) -> anyhow::Result<()> {
+            self.0.recv()?;
+            *self.1.lock().unwrap() += 1;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_delivery_does_not_block_processing() {
+        let (release, blocked) = mpsc::channel();
+        let delivered = Arc::new(Mutex::new(0));
+        let mut alerts = Alerts::new(
+            vec![AlertRule::Lock],
+            vec![Box::new(BlockedNotifier(blocked, delivered.clone()))],
+        );
+        alerts.observe(&observation(1, true, 0));
+        alerts.observe(&observation(2, true, 0));
+        assert_eq!(*delivered.lock().unwrap(), 0);
+
+        release.send(()).unwrap();
+        release.send(()).unwrap();
+        alerts.finish();
+        assert_eq!(*delivered.lock().unwrap(), 2);
+    }
+
+    #[test]
+    fn test_alerts_beyond_queue_are_dead_letters() {
+        let path = std::env::temp_dir().join(format!(
+            "coding_test_queue_dead_letters_{}.jsonl",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        let (release, blocked) = mpsc::channel();
+        let delivered = Arc::new(Mutex::new(0));
+        let mut alerts = Alerts::new(
+            vec![AlertRule::Lock],
+            vec![Box::new(BlockedNotifier(blocked, delivered.clone()))],
+        );
+        alerts.set_dead_letters(DeadLetters::open(&path.clone().into_os_string()).unwrap());
+        // The thread takes at most one alert while the notifier is blocked.
+        let fired = DELIVERY_QUEUE + 2;
+        for client in 0..fired {
+            alerts.observe(&observation(client as u16, true, 0));
+        }
+        for _ in 0..fired {
+            release.send(()).unwrap();
+        }
+        alerts.finish();
+
+        let letters = std::fs::read_to_string(&path).unwrap();
+        let dead = letters.lines().count();
+        assert!(dead >= 1);
+        assert_eq!(*delivered.lock().unwrap() as usize + dead, fired);
+        let letter: serde_json::Value =
+            serde_json::from_str(letters.lines().last().unwrap()).unwrap();
+        assert_eq!(letter["error"], "delivery queue is full");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    struct FailingNotifier(Arc<Mutex<u32>>);
+
+    impl Notifier for FailingNotifier {
+        fn notify(&mut self, _: &Alert) -> anyhow::Result<()> {
+            *self.0.lock().unwrap() += 1;
+            bail!("unreachable")
+        }
+    }
+
+    #[test]
+    fn test_retries_and_dead_letters() {
+        let path = std::env::temp_dir().join("coding_test_dead_letters.jsonl");
+        let _ = std::fs::remove_file(&path);
+        let attempts = Arc::new(Mutex::new(0));
+        let mut alerts = Alerts::new(
+            vec![AlertRule::Lock],
+            vec![Box::new(FailingNotifier(attempts.clone()))],
+        );
+        alerts.set_retries(2);
+        alerts.set_dead_letters(DeadLetters::open(&path.clone().into_os_string()).unwrap());
+        alerts.observe(&observation(4, true, 0));
+        alerts.finish();
+
+        assert_eq!(*attempts.lock().unwrap(), 3);
+        let letters = std::fs::read_to_string(&path).unwrap();
+        let letter: serde_json::Value = serde_json::from_str(letters.trim_end()).unwrap();
+        assert_eq!(letter["error"], "unreachable");
+        assert_eq!(letter["alert"]["rule"], "lock");
+        assert_eq!(letter["alert"]["client"], 4);
+    }
+}
@@ -0,0 +1,335 @@
+use rust_decimal::RoundingStrategy;
+use serde::de::{self, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::iter::Sum;
+use std::ops::{Add, AddAssign, Div, Mul, Neg, Sub, SubAssign};
+use std::str::FromStr;
+
+// Numeric type of all amounts. By default it's `rust_decimal::Decimal` (28 significant digits).
+// Deployments which prefer a plain integer arithmetic or need more integer digits may select
+// `FixedAmount` with `fixed-point` feature. Account logic only uses operations both types have.
+#[cfg(not(feature = "fixed-point"))]
+pub type Amount = rust_decimal::Decimal;
+#[cfg(feature = "fixed-point")]
+pub type Amount = FixedAmount;
+
+// Inputs have at most four places past the decimal.
+pub const FIXED_SCALE: u32 = 4;
+const FIXED_ONE: i128 = 10_000;
+
+// Fixed-point amount with four decimal places kept in an `i128`, so it holds up to 34 integer
+// digits. Digits past the fourth decimal place are truncated, also in multiplication and division.
+// Input amounts are rounded by the precision policy before they are parsed.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default, Debug)]
+pub struct FixedAmount(i128);
+
+impl FixedAmount {
+    pub const MAX: FixedAmount = FixedAmount(i128::MAX);
+    pub const MIN: FixedAmount = FixedAmount(i128::MIN);
+
+    pub fn is_zero(&self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn is_sign_negative(&self) -> bool {
+        self.0 < 0
+    }
+
+    pub fn is_sign_positive(&self) -> bool {
+        self.0 >= 0
+    }
+
+    pub fn abs(&self) -> FixedAmount {
+        FixedAmount(self.0.abs())
+    }
+
+    pub fn checked_add(self, other: FixedAmount) -> Option<FixedAmount> {
+        self.0.checked_add(other.0).map(FixedAmount)
+    }
+
+    pub fn checked_sub(self, other: FixedAmount) -> Option<FixedAmount> {
+        self.0.checked_sub(other.0).map(FixedAmount)
+    }
+
+    // Rounds to `dp` decimal places like `rust_decimal`, for the strategies outputs use.
+    pub fn round_dp_with_strategy(self, dp: u32, strategy: RoundingStrategy) -> FixedAmount {
+        if dp >= FIXED_SCALE {
+            return self;
+        }
+        let unit = 10i128.pow(FIXED_SCALE - dp);
+        let (quotient, remainder) = (self.0 / unit, (self.0 % unit).abs());
+        let away = match strategy {
+            RoundingStrategy::MidpointAwayFromZero => remainder * 2 >= unit,
+            RoundingStrategy::MidpointNearestEven => {
+                remainder * 2 > unit || (remainder * 2 == unit && quotient % 2 != 0)
+            }
+            _ => false,
+        };
+        let quotient = match (away, self.0 < 0) {
+            (false, _) => quotient,
+            (true, false) => quotient + 1,
+            (true, true) => quotient - 1,
+        };
+        // Amounts within a unit of the limits can't be rounded away from zero.
+        quotient.checked_mul(unit).map_or(self, FixedAmount)
+    }
+}
+
+macro_rules! fixed_from_int {
+    ($($int:ty),*) => {
+        $(
+            impl From<$int> for FixedAmount {
+                fn from(num: $int) -> FixedAmount {
+                    FixedAmount(i128::from(num) * FIXED_ONE)
+                }
+            }
+        )*
+    };
+}
+
+fixed_from_int!(i8, i16, i32, i64, u8, u16, u32, u64);
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct ParseAmountError;
+
+impl fmt::Display for ParseAmountError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("invalid amount")
+    }
+}
+
+impl std::error::Error for ParseAmountError {}
+
+impl FromStr for FixedAmount {
+    type Err = ParseAmountError;
+
+    fn from_str(s: &str) -> Result<FixedAmount, ParseAmountError> {
+        let (negative, digits) = match s.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, s.strip_prefix('+').unwrap_or(s)),
+        };
+        let (integer, fraction) = digits.split_once('.').unwrap_or((digits, ""));
+        if (integer.is_empty() && fraction.is_empty())
+            || !integer.bytes().all(|b| b.is_ascii_digit())
+            || !fraction.bytes().all(|b| b.is_ascii_digit())
+        {
+            return Err(ParseAmountError);
+        }
+
+        let mut value: i128 = 0;
+        for b in integer.bytes() {
+            value = value
+                .checked_mul(10)
+                .and_then(|value| value.checked_add(i128::from(b - b'0')))
+                .ok_or(ParseAmountError)?;
+        }
+        let mut scaled = value.checked_mul(FIXED_ONE).ok_or(ParseAmountError)?;
+        let mut unit = FIXED_ONE;
+        for b in fraction.bytes().take(FIXED_SCALE as usize) {
+            unit /= 10;
+            scaled += i128::from(b - b'0') * unit;
+        }
+
+        Ok(FixedAmount(if negative { -scaled } else { scaled }))
+    }
+}
+
+// Honours precision like `rust_decimal`, digits past the precision are truncated.
+impl fmt::Display for FixedAmount {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let integer = (self.0 / FIXED_ONE).unsigned_abs();
+        let fraction = format!("{:04}", (self.0 % FIXED_ONE).unsigned_abs());
+        let fraction = match f.precision() {
+            Some(precision) if precision <= fraction.len() => fraction[..precision].to_string(),
+            Some(precision) => format!("{:0<width$}", fraction, width = precision),
+            None => fraction.trim_end_matches('0').to_string(),
+        };
+
+        let sign = if self.0 < 0 { "-" } else { "" };
+        if fraction.is_empty() {
+            write!(f, "{}{}", sign, integer)
+        } else {
+            write!(f, "{}{}.{}", sign, integer, fraction)
+        }
+    }
+}
+
+impl Add for FixedAmount {
+    type Output = FixedAmount;
+
+    fn add(self, other: FixedAmount) -> FixedAmount {
+        FixedAmount(self.0 + other.0)
+    }
+}
+
+impl Sub for FixedAmount {
+    type Output = FixedAmount;
+
+    fn sub(self, other: FixedAmount) -> FixedAmount {
+        FixedAmount(self.0 - other.0)
+    }
+}
+
+impl Mul for FixedAmount {
+    type Output = FixedAmount;
+
+    fn mul(self, other: FixedAmount) -> FixedAmount {
+        FixedAmount(self.0 * other.0 / FIXED_ONE)
+    }
+}
+
+impl Div for FixedAmount {
+    type Output = FixedAmount;
+
+    fn div(self, other: FixedAmount) -> FixedAmount {
+        FixedAmount(self.0 * FIXED_ONE / other.0)
+    }
+}
+
+impl Neg for FixedAmount {
+    type Output = FixedAmount;
+
+    fn neg(self) -> FixedAmount {
+        FixedAmount(-self.0)
+    }
+}
+
+impl AddAssign for FixedAmount {
+    fn add_assign(&mut self, other: FixedAmount) {
+        self.0 += other.0;
+    }
+}
+
+impl SubAssign for FixedAmount {
+    fn sub_assign(&mut self, other: FixedAmount) {
+        self.0 -= other.0;
+    }
+}
+
+impl Sum for FixedAmount {
+    fn sum<I: Iterator<Item = FixedAmount>>(iter: I) -> FixedAmount {
+        iter.fold(FixedAmount(0), Add::add)
+    }
+}
+
+impl<'a> Sum<&'a FixedAmount> for FixedAmount {
+    fn sum<I: Iterator<Item = &'a FixedAmount>>(iter: I) -> FixedAmount {
+        iter.copied().sum()
+    }
+}
+
+impl Serialize for FixedAmount {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.collect_str(self)
+    }
+}
+
+struct FixedAmountVisitor;
+
+impl<'de> Visitor<'de> for FixedAmountVisitor {
+    type Value = FixedAmount;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a decimal number")
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<FixedAmount, E> {
+        FixedAmount::from_str(v.trim()).map_err(E::custom)
+    }
+
+    fn visit_i64<E: de::Error>(self, v: i64) -> Result<FixedAmount, E> {
+        Ok(FixedAmount::from(v))
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> Result<FixedAmount, E> {
+        Ok(FixedAmount::from(v))
+    }
+
+    // CSV fields of flattened structs are guessed as floats.
+    fn visit_f64<E: de::Error>(self, v: f64) -> Result<FixedAmount, E> {
+        self.visit_str(&v.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for FixedAmount {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<FixedAmount, D::Error> {
+        d.deserialize_any(FixedAmountVisitor)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn fixed(s: &str) -> FixedAmount {
+        FixedAmount::from_str(s).unwrap()
+    }
+
+    #[test]
+    fn test_parse_and_display() {
+        assert_eq!(fixed("1.5").to_string(), "1.5");
+        assert_eq!(fixed("-12.34567").to_string(), "-12.3456");
+        assert_eq!(fixed("-0.5").to_string(), "-0.5");
+        assert_eq!(fixed("100").to_string(), "100");
+        assert_eq!(format!("{:.2}", fixed("2.999")), "2.99");
+        assert_eq!(format!("{:.6}", fixed("2.5")), "2.500000");
+        assert!(FixedAmount::from_str("1.2.3").is_err());
+        assert!(FixedAmount::from_str("").is_err());
+        assert!(FixedAmount::from_str("abc").is_err());
+    }
+
+    #[test]
+    fn test_more_than_28_digits() {
+        let large = fixed("1234567890123456789012345678901.5");
+        assert_eq!(
+            (large + large).to_string(),
+            "2469135780246913578024691357803"
+        );
+        assert!(FixedAmount::MAX.checked_add(fixed("0.0001")).is_none());
+    }
+
+    #[test]
+    fn test_round_dp() {
+        let half_up =
+            |s, dp| fixed(s).round_dp_with_strategy(dp, RoundingStrategy::MidpointAwayFromZero);
+        let half_even =
+            |s, dp| fixed(s).round_dp_with_strategy(dp, RoundingStrategy::MidpointNearestEven);
+        assert_eq!(half_up("1.225", 2), fixed("1.23"));
+        assert_eq!(half_up("-1.225", 2), fixed("-1.23"));
+        assert_eq!(half_even("1.225", 2), fixed("1.22"));
+        assert_eq!(half_even("-1.235", 2), fixed("-1.24"));
+        assert_eq!(half_even("1.5", 0), fixed("2"));
+        assert_eq!(half_even("2.5", 0), fixed("2"));
+        assert_eq!(half_even("2.5", 6), fixed("2.5"));
+        assert_eq!(half_up("0.0001", 3), fixed("0"));
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        assert_eq!(fixed("1.5") + fixed("2.25"), fixed("3.75"));
+        assert_eq!(fixed("1.5") - fixed("2.25"), fixed("-0.75"));
+        assert_eq!(fixed("1.5") * fixed("2"), fixed("3"));
+        assert_eq!(fixed("1") / fixed("3"), fixed("0.3333"));
+        assert_eq!(
+            vec![fixed("1"), fixed("2.5")]
+                .into_iter()
+                .sum::<FixedAmount>(),
+            fixed("3.5")
+        );
+        assert!(fixed("-1").abs() > fixed("0.5"));
+    }
+
+    #[test]
+    fn test_deserialize_from_csv() {
+        #[derive(Deserialize)]
+        struct Row {
+            amount: FixedAmount,
+        }
+
+        let mut reader = csv::Reader::from_reader("amount\n1.5\n2\n".as_bytes());
+        let rows: Vec<Row> = reader.deserialize().map(Result::unwrap).collect();
+        assert_eq!(rows[0].amount, fixed("1.5"));
+        assert_eq!(rows[1].amount, fixed("2"));
+    }
+}
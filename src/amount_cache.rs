@@ -0,0 +1,188 @@
+use crate::amount::Amount;
+use crate::precision::{self, PrecisionPolicy};
+
+use serde::de::{self, Deserializer, Visitor};
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::fmt;
+
+// Amounts past this many distinct values are parsed without caching, so a feed of mostly unique
+// amounts doesn't grow the cache.
+pub const CAPACITY: usize = 256;
+
+#[derive(Default)]
+struct Cache {
+    // Policy the amounts were parsed by. Engines on one thread may read by different ones, so the
+    // cache is cleared when the policy changes.
+    policy: PrecisionPolicy,
+    // CSV fields of flattened structs are guessed as floats, so they are cached by their bits.
+    floats: HashMap<u64, Amount>,
+    texts: HashMap<Box<str>, Amount>,
+}
+
+thread_local! {
+    // Real-world feeds are dominated by a few distinct amounts (subscription prices, fixed fees),
+    // so parsed amounts may be cached by their raw value. The cache is off by default and turned
+    // on by the config of the engine reading input on this thread, see `EngineConfig::enter`.
+    static ENABLED: Cell<bool> = const { Cell::new(false) };
+    static CACHE: RefCell<Cache> = RefCell::new(Cache::default());
+}
+
+// Turns the cache on or off on this thread and returns whether it was on.
+pub(crate) fn replace_enabled(enabled: bool) -> bool {
+    ENABLED.with(|cell| cell.replace(enabled))
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.with(Cell::get)
+}
+
+fn clear_if_stale(cache: &mut Cache) {
+    let policy = PrecisionPolicy::current();
+    if cache.policy != policy {
+        *cache = Cache {
+            policy,
+            ..Cache::default()
+        };
+    }
+}
+
+// Number of amounts cached by the current thread.
+pub fn len() -> usize {
+    CACHE.with(|cache| {
+        let cache = cache.borrow();
+        cache.floats.len() + cache.texts.len()
+    })
+}
+
+// Amounts are parsed from text by the precision policy, floats from their shortest representation.
+fn parse<E: de::Error>(value: &str) -> Result<Amount, E> {
+    precision::parse(value).map_err(E::custom)
+}
+
+fn cached_float<E: de::Error>(value: f64) -> Result<Amount, E> {
+    CACHE.with(|cache| {
+        let mut cache = cache.borrow_mut();
+        clear_if_stale(&mut cache);
+        if let Some(amount) = cache.floats.get(&value.to_bits()) {
+            return Ok(*amount);
+        }
+        let amount = parse(&value.to_string())?;
+        if cache.floats.len() + cache.texts.len() < CAPACITY {
+            cache.floats.insert(value.to_bits(), amount);
+        }
+        Ok(amount)
+    })
+}
+
+fn cached_text<E: de::Error>(value: &str) -> Result<Amount, E> {
+    CACHE.with(|cache| {
+        let mut cache = cache.borrow_mut();
+        clear_if_stale(&mut cache);
+        if let Some(amount) = cache.texts.get(value) {
+            return Ok(*amount);
+        }
+        let amount = parse(value)?;
+        if cache.floats.len() + cache.texts.len() < CAPACITY {
+            cache.texts.insert(value.into(), amount);
+        }
+        Ok(amount)
+    })
+}
+
+struct AmountVisitor;
+
+impl<'de> Visitor<'de> for AmountVisitor {
+    type Value = Amount;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a decimal number")
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<Amount, E> {
+        match is_enabled() {
+            true => cached_text(v),
+            false => parse(v),
+        }
+    }
+
+    fn visit_f64<E: de::Error>(self, v: f64) -> Result<Amount, E> {
+        match is_enabled() {
+            true => cached_float(v),
+            false => parse(&v.to_string()),
+        }
+    }
+
+    // Integers are cheap to convert, they are not cached.
+    fn visit_i64<E: de::Error>(self, v: i64) -> Result<Amount, E> {
+        Ok(Amount::from(v))
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> Result<Amount, E> {
+        Ok(Amount::from(v))
+    }
+}
+
+// Parses an input amount given as text, like `deserialize` does.
+pub fn parse_text(value: &str) -> Result<Amount, String> {
+    match is_enabled() {
+        true => cached_text::<de::value::Error>(value),
+        false => parse::<de::value::Error>(value),
+    }
+    .map_err(|error| error.to_string())
+}
+
+// Deserializes an input amount by the precision policy, through the cache if it's enabled.
+pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Amount, D::Error> {
+    d.deserialize_any(AmountVisitor)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::engine_config::EngineConfig;
+    use std::str::FromStr;
+
+    #[derive(serde::Deserialize)]
+    struct Row {
+        #[serde(deserialize_with = "deserialize")]
+        amount: Amount,
+    }
+
+    fn parse(value: &str) -> Amount {
+        let json = format!("{{\"amount\": {}}}", value);
+        serde_json::from_str::<Row>(&json).unwrap().amount
+    }
+
+    #[test]
+    fn test_cached_amounts() {
+        // Tests run on their own threads, so the cache starts empty.
+        let _config = EngineConfig {
+            amount_cache: true,
+            ..EngineConfig::default()
+        }
+        .enter();
+        assert_eq!(parse("\"9.99\""), Amount::from_str("9.99").unwrap());
+        assert_eq!(parse("\"9.99\""), Amount::from_str("9.99").unwrap());
+        assert_eq!(parse("1.5"), Amount::from_str("1.5").unwrap());
+        assert_eq!(parse("1.5"), Amount::from_str("1.5").unwrap());
+        assert_eq!(parse("3"), Amount::from(3));
+        assert_eq!(len(), 2);
+        assert!(serde_json::from_str::<Row>("{\"amount\": \"abc\"}").is_err());
+
+        for cents in 0..CAPACITY * 2 {
+            parse(&format!("\"{}.01\"", cents));
+        }
+        assert_eq!(len(), CAPACITY);
+
+        // Amounts cached by another precision policy are not used.
+        let _rejecting = EngineConfig {
+            amount_cache: true,
+            precision_policy: PrecisionPolicy::Reject,
+            ..EngineConfig::default()
+        }
+        .enter();
+        assert!(serde_json::from_str::<Row>("{\"amount\": \"1.00005\"}").is_err());
+        assert_eq!(len(), 0);
+    }
+}
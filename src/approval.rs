@@ -0,0 +1,261 @@
+use crate::account::Account;
+use crate::authorization::Authorization;
+use crate::transaction::{Transaction, TransactionType};
+
+use crate::amount::Amount;
+use log::{info, warn};
+use std::collections::BTreeMap;
+
+// Unapproved adjustments expire after a day.
+pub const DEFAULT_APPROVAL_EXPIRY: u64 = 24 * 60 * 60;
+
+struct PendingAdjustment {
+    transaction: Transaction,
+    submitted_at: u64,
+}
+
+// Maker-checker workflow for large adjustments. An adjustment above a threshold is kept pending
+// until a different operator approves it with an `approve` transaction referencing it, only then
+// funds move. Pending adjustments which are not approved in time expire.
+pub struct Approvals {
+    threshold: Option<Amount>,
+    expiry: u64,
+    pending: BTreeMap<(u16, u32), PendingAdjustment>,
+}
+
+impl Approvals {
+    pub fn new(threshold: Option<Amount>, expiry: u64) -> Approvals {
+        Approvals {
+            threshold,
+            expiry,
+            pending: BTreeMap::new(),
+        }
+    }
+
+    fn needs_approval(&self, transaction: &Transaction) -> bool {
+        match (&transaction.tx_type, self.threshold) {
+            (TransactionType::Adjust(adjust), Some(threshold)) => adjust.amount.abs() > threshold,
+            _ => false,
+        }
+    }
+
+    fn expire(&mut self, now: u64) {
+        let expiry = self.expiry;
+        self.pending.retain(|(client_id, tx_id), pending| {
+            let expired = pending.submitted_at.saturating_add(expiry) < now;
+            if expired {
+                warn!(
+                    "adjustment tx: {} for account {} was not approved in time. it expired.",
+                    tx_id, client_id
+                );
+            }
+            !expired
+        });
+    }
+
+    // Returns a transaction which should be applied now. Large adjustments are kept pending and
+    // an approval by an operator allowed to make the adjustment on the account releases it.
+    pub fn review(
+        &mut self,
+        transaction: Transaction,
+        now: u64,
+        authorization: &Authorization,
+        account: &Account,
+    ) -> Option<Transaction> {
+        self.expire(now);
+
+        if self.needs_approval(&transaction) {
+            if let TransactionType::Adjust(adjust) = &transaction.tx_type {
+                let key = (adjust.client_id, adjust.tx_id);
+                if self.pending.contains_key(&key) {
+                    warn!(
+                        "adjustment tx: {} is already pending approval. ignoring processing tx.",
+                        adjust.tx_id
+                    );
+                    return None;
+                }
+                info!(
+                    "adjustment tx: {} for account {} is pending approval",
+                    adjust.tx_id, adjust.client_id
+                );
+                self.pending.insert(
+                    key,
+                    PendingAdjustment {
+                        transaction,
+                        submitted_at: now,
+                    },
+                );
+            }
+            return None;
+        }
+
+        let approve = match &transaction.tx_type {
+            TransactionType::Approve(approve) => approve,
+            _ => return Some(transaction),
+        };
+
+        let key = (approve.client_id, approve.tx_id);
+        let pending = match self.pending.get(&key) {
+            None => {
+                warn!(
+                    "tx: {} is not pending approval. ignoring processing tx.",
+                    approve.tx_id
+                );
+                return None;
+            }
+            Some(pending) => &pending.transaction,
+        };
+        if pending.operator == transaction.operator {
+            warn!(
+                "tx: {} can not be approved by the operator who submitted it.",
+                approve.tx_id
+            );
+            return None;
+        }
+        if !authorization.authorize_approval(&transaction, pending, account) {
+            warn!(
+                "tx: {} can not be approved by an operator without the role it requires.",
+                approve.tx_id
+            );
+            return None;
+        }
+
+        self.pending.remove(&key).map(|pending| {
+            let mut released = pending.transaction;
+            released.approved_by = transaction.operator;
+            info!("adjustment tx: {} approved", approve.tx_id);
+            released
+        })
+    }
+
+    // Adjustment of an account which is waiting for an approval.
+    pub fn pending(&self, client_id: u16, tx_id: u32) -> Option<&Transaction> {
+        self.pending
+            .get(&(client_id, tx_id))
+            .map(|pending| &pending.transaction)
+    }
+
+    // Adjustments which are still waiting for an approval at the end of input.
+    pub fn report_pending(&self) {
+        for (client_id, tx_id) in self.pending.keys() {
+            warn!(
+                "adjustment tx: {} for account {} is still pending approval",
+                tx_id, client_id
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::authorization::Role;
+    use crate::transaction::{Adjust, Approve};
+    use std::collections::HashMap;
+
+    fn admin(tx_type: TransactionType, operator: &str) -> Transaction {
+        Transaction {
+            tx_type,
+            timestamp: None,
+            operator: Some(String::from(operator)),
+            reason: Some(String::from("remediation")),
+            approved_by: None,
+        }
+    }
+
+    fn adjust(tx_id: u32, amount: i64) -> TransactionType {
+        TransactionType::Adjust(Adjust {
+            client_id: 1,
+            tx_id,
+            amount: Amount::from(amount),
+        })
+    }
+
+    fn approve(tx_id: u32) -> TransactionType {
+        TransactionType::Approve(Approve {
+            client_id: 1,
+            tx_id,
+        })
+    }
+
+    // Reviews a transaction when every operator is allowed to do anything.
+    fn review(
+        approvals: &mut Approvals,
+        transaction: Transaction,
+        now: u64,
+    ) -> Option<Transaction> {
+        approvals.review(transaction, now, &Authorization::new(), &Account::new(1))
+    }
+
+    #[test]
+    fn test_small_adjustment_is_applied_immediately() {
+        let mut approvals = Approvals::new(Some(Amount::from(100)), DEFAULT_APPROVAL_EXPIRY);
+        assert!(review(&mut approvals, admin(adjust(1, 100), "alice"), 0).is_some());
+    }
+
+    #[test]
+    fn test_large_adjustment_waits_for_approval() {
+        let mut approvals = Approvals::new(Some(Amount::from(100)), DEFAULT_APPROVAL_EXPIRY);
+        assert!(review(&mut approvals, admin(adjust(1, -500), "alice"), 0).is_none());
+
+        // Maker can't approve own adjustment.
+        assert!(review(&mut approvals, admin(approve(1), "alice"), 10).is_none());
+
+        let released = review(&mut approvals, admin(approve(1), "bob"), 20).unwrap();
+        assert!(matches!(released.tx_type, TransactionType::Adjust(_)));
+        assert_eq!(released.operator.as_deref(), Some("alice"));
+        assert_eq!(released.approved_by.as_deref(), Some("bob"));
+
+        // Approval is used only once.
+        assert!(review(&mut approvals, admin(approve(1), "carol"), 30).is_none());
+    }
+
+    #[test]
+    fn test_junior_can_not_approve_senior_adjustment() {
+        let mut operators = HashMap::new();
+        operators.insert(String::from("alice"), Role::Senior);
+        operators.insert(String::from("bob"), Role::Junior);
+        operators.insert(String::from("carol"), Role::Senior);
+        let authorization = Authorization::with_operators(operators, Some(Amount::from(1000)));
+        let account = Account::new(1);
+
+        let mut approvals = Approvals::new(Some(Amount::from(100)), DEFAULT_APPROVAL_EXPIRY);
+        let maker = admin(adjust(1, 5000), "alice");
+        assert!(approvals
+            .review(maker, 0, &authorization, &account)
+            .is_none());
+        let junior = admin(approve(1), "bob");
+        assert!(approvals
+            .review(junior, 10, &authorization, &account)
+            .is_none());
+        assert!(approvals.pending(1, 1).is_some());
+
+        let senior = admin(approve(1), "carol");
+        let released = approvals.review(senior, 20, &authorization, &account);
+        assert_eq!(released.unwrap().approved_by.as_deref(), Some("carol"));
+
+        // Adjustments a junior may make may be approved by a junior.
+        let maker = admin(adjust(2, 500), "alice");
+        assert!(approvals
+            .review(maker, 30, &authorization, &account)
+            .is_none());
+        let junior = admin(approve(2), "bob");
+        assert!(approvals
+            .review(junior, 40, &authorization, &account)
+            .is_some());
+    }
+
+    #[test]
+    fn test_unapproved_adjustment_expires() {
+        let mut approvals = Approvals::new(Some(Amount::from(100)), 60);
+        assert!(review(&mut approvals, admin(adjust(1, 500), "alice"), 0).is_none());
+        assert!(review(&mut approvals, admin(approve(1), "bob"), 61).is_none());
+        assert!(approvals.pending.is_empty());
+    }
+
+    #[test]
+    fn test_no_approvals_without_threshold() {
+        let mut approvals = Approvals::new(None, DEFAULT_APPROVAL_EXPIRY);
+        assert!(review(&mut approvals, admin(adjust(1, 1000000), "alice"), 0).is_some());
+    }
+}
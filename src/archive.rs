@@ -0,0 +1,423 @@
+use crate::account::{Account, Adjustment, BalanceSnapshot, DepositedTransaction, Transactions};
+use crate::migration::{self, Migrate, Migration, MigrationOptions, MigrationReport};
+use crate::store::{AccountStore, CompactionReport, StoreStats};
+
+use crate::amount::Amount;
+use anyhow::{bail, Context};
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+// Extension of files which are being written.
+const TEMPORARY_EXTENSION: &str = "tmp";
+// File with the version of the archive format, it's missing in archives written before versions
+// were recorded.
+const VERSION_FILE: &str = "VERSION";
+
+static MIGRATIONS: [Migration<Archive>; 1] = [Migration {
+    version: 1,
+    description: "rewrite archived accounts with defaults of added fields",
+    apply: Archive::rewrite_accounts,
+}];
+
+// Accounts without activity for 90 days are dormant.
+pub const DEFAULT_DORMANT_AFTER: u64 = 90 * 24 * 60 * 60;
+
+// Complete state of an archived account, including deposited transactions which may still be
+// disputed after the account is rehydrated.
+#[derive(Serialize, Deserialize)]
+struct ArchivedAccount {
+    client: u16,
+    available: Amount,
+    held: Amount,
+    total: Amount,
+    locked: bool,
+    last_activity: u64,
+    transactions: Vec<DepositedTransaction>,
+    adjustments: Vec<Adjustment>,
+}
+
+impl ArchivedAccount {
+    fn from_account(account: &Account) -> ArchivedAccount {
+        ArchivedAccount {
+            client: account.id(),
+            available: account.available(),
+            held: account.held(),
+            total: account.total(),
+            locked: account.is_locked(),
+            last_activity: account.last_activity(),
+            transactions: account.transactions().to_vec(),
+            adjustments: account.adjustments().to_vec(),
+        }
+    }
+
+    fn into_account(self) -> anyhow::Result<Account> {
+        let mut account = Account::new(self.client);
+        account.restore_balances(BalanceSnapshot {
+            available: self.available,
+            held: self.held,
+            total: self.total,
+            locked: self.locked,
+        })?;
+        account.set_last_activity(self.last_activity);
+        *account.transactions_mut() = Transactions::from(self.transactions);
+        account.restore_adjustments(self.adjustments);
+        Ok(account)
+    }
+}
+
+// Cold storage of dormant accounts. Every archived account is kept in its own JSON file, so the
+// active accounts stay small and an archived account is rehydrated when a new transaction for it
+// arrives.
+pub struct Archive {
+    dir: PathBuf,
+    archived: HashSet<u16>,
+}
+
+impl Archive {
+    // Opens an archive and migrates it to the current format.
+    pub fn open(dir: &OsString) -> anyhow::Result<Archive> {
+        let mut archive = Archive::load(dir)?;
+        migration::migrate(&mut archive, &MigrationOptions::default())?;
+        Ok(archive)
+    }
+
+    pub fn migrate(dir: &OsString, options: &MigrationOptions) -> anyhow::Result<MigrationReport> {
+        migration::migrate(&mut Archive::load(dir)?, options)
+    }
+
+    fn load(dir: &OsString) -> anyhow::Result<Archive> {
+        let dir = PathBuf::from(dir);
+        fs::create_dir_all(&dir)
+            .with_context(|| format!("can not create archive directory {:?}", dir))?;
+
+        let mut archived = HashSet::new();
+        for entry in
+            fs::read_dir(&dir).with_context(|| format!("can not read archive {:?}", dir))?
+        {
+            let path = entry?.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some("json") {
+                continue;
+            }
+            if let Some(client) = path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .and_then(|stem| stem.parse().ok())
+            {
+                archived.insert(client);
+            }
+        }
+
+        Ok(Archive { dir, archived })
+    }
+
+    fn path(&self, client_id: u16) -> PathBuf {
+        self.dir.join(format!("{}.json", client_id))
+    }
+
+    fn read(&self, client_id: u16) -> anyhow::Result<ArchivedAccount> {
+        let path = self.path(client_id);
+        let file =
+            File::open(&path).with_context(|| format!("can not open archive file {:?}", path))?;
+        serde_json::from_reader(BufReader::new(file))
+            .with_context(|| format!("can not read archive file {:?}", path))
+    }
+
+    // An account is written into a temporary file first and renamed, so an interrupted write
+    // never leaves a partial account behind.
+    fn write(&self, archived: &ArchivedAccount) -> anyhow::Result<()> {
+        let path = self.path(archived.client);
+        let temporary = path.with_extension(TEMPORARY_EXTENSION);
+        let file = File::create(&temporary)
+            .with_context(|| format!("can not create archive file {:?}", temporary))?;
+        let mut writer = BufWriter::new(file);
+        serde_json::to_writer(&mut writer, archived)
+            .with_context(|| format!("can not write archive file {:?}", temporary))?;
+        writer
+            .flush()
+            .with_context(|| format!("can not write archive file {:?}", temporary))?;
+        fs::rename(&temporary, &path)
+            .with_context(|| format!("can not rename archive file {:?}", temporary))
+    }
+
+    fn rewrite_accounts(&mut self) -> anyhow::Result<()> {
+        for client_id in self.clients() {
+            self.write(&self.read(client_id)?)?;
+        }
+        Ok(())
+    }
+
+    fn disk_bytes(&self) -> anyhow::Result<u64> {
+        let mut bytes = 0;
+        for entry in fs::read_dir(&self.dir)
+            .with_context(|| format!("can not read archive {:?}", self.dir))?
+        {
+            bytes += entry?.metadata()?.len();
+        }
+        Ok(bytes)
+    }
+}
+
+impl Migrate for Archive {
+    fn name(&self) -> &'static str {
+        "archive"
+    }
+
+    fn version(&self) -> anyhow::Result<u32> {
+        let path = self.dir.join(VERSION_FILE);
+        if !path.exists() {
+            return Ok(0);
+        }
+        fs::read_to_string(&path)
+            .with_context(|| format!("can not read archive version {:?}", path))?
+            .trim()
+            .parse()
+            .with_context(|| format!("invalid archive version in {:?}", path))
+    }
+
+    fn set_version(&mut self, version: u32) -> anyhow::Result<()> {
+        let path = self.dir.join(VERSION_FILE);
+        fs::write(&path, format!("{}\n", version))
+            .with_context(|| format!("can not write archive version {:?}", path))
+    }
+
+    // Copies accounts and the version file, temporary files are left out.
+    fn backup(&self, path: &Path) -> anyhow::Result<()> {
+        if path.exists() {
+            bail!("backup {:?} already exists", path);
+        }
+        fs::create_dir_all(path)?;
+        for client_id in self.clients() {
+            let file_name = format!("{}.json", client_id);
+            fs::copy(self.dir.join(&file_name), path.join(&file_name))?;
+        }
+        let version = self.dir.join(VERSION_FILE);
+        if version.exists() {
+            fs::copy(version, path.join(VERSION_FILE))?;
+        }
+        Ok(())
+    }
+
+    fn migrations() -> &'static [Migration<Archive>] {
+        &MIGRATIONS
+    }
+}
+
+impl AccountStore for Archive {
+    fn contains(&self, client_id: u16) -> bool {
+        self.archived.contains(&client_id)
+    }
+
+    // Takes an account out of the archive, so it's active again.
+    fn take(&mut self, client_id: u16) -> anyhow::Result<Option<Account>> {
+        if !self.contains(client_id) {
+            return Ok(None);
+        }
+
+        let account = self.read(client_id)?.into_account()?;
+        let path = self.path(client_id);
+        fs::remove_file(&path)
+            .with_context(|| format!("can not remove archive file {:?}", path))?;
+        self.archived.remove(&client_id);
+        info!("account: {} rehydrated from archive", client_id);
+
+        Ok(Some(account))
+    }
+
+    fn put(&mut self, account: &Account) -> anyhow::Result<()> {
+        self.write(&ArchivedAccount::from_account(account))?;
+        self.archived.insert(account.id());
+        info!("account: {} archived", account.id());
+
+        Ok(())
+    }
+
+    fn get(&self, client_id: u16) -> anyhow::Result<Option<Account>> {
+        if !self.contains(client_id) {
+            return Ok(None);
+        }
+
+        Ok(Some(self.read(client_id)?.into_account()?))
+    }
+
+    fn clients(&self) -> Vec<u16> {
+        let mut clients: Vec<u16> = self.archived.iter().copied().collect();
+        clients.sort_unstable();
+        clients
+    }
+
+    fn len(&self) -> usize {
+        self.archived.len()
+    }
+
+    fn stats(&self) -> anyhow::Result<StoreStats> {
+        let mut stats = StoreStats {
+            accounts: self.archived.len(),
+            disk_bytes: self.disk_bytes()?,
+            ..StoreStats::default()
+        };
+        for client_id in &self.archived {
+            let archived = self.read(*client_id)?;
+            stats.transactions += archived.transactions.len();
+            stats.adjustments += archived.adjustments.len();
+        }
+
+        Ok(stats)
+    }
+
+    // Rewrites every account in the current format and removes files which are not accounts,
+    // for example leftovers of interrupted writes.
+    fn compact(&mut self) -> anyhow::Result<CompactionReport> {
+        let mut report = CompactionReport {
+            disk_bytes_before: self.disk_bytes()?,
+            ..CompactionReport::default()
+        };
+
+        for client_id in self.clients() {
+            self.write(&self.read(client_id)?)?;
+            report.rewritten_accounts += 1;
+        }
+
+        for entry in fs::read_dir(&self.dir)
+            .with_context(|| format!("can not read archive {:?}", self.dir))?
+        {
+            let path = entry?.path();
+            if path.extension().and_then(|ext| ext.to_str()) == Some(TEMPORARY_EXTENSION) {
+                fs::remove_file(&path)
+                    .with_context(|| format!("can not remove archive file {:?}", path))?;
+                report.removed_files += 1;
+            }
+        }
+
+        report.disk_bytes_after = self.disk_bytes()?;
+        info!(
+            "archive compacted from {} to {} bytes",
+            report.disk_bytes_before, report.disk_bytes_after
+        );
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::transaction::Deposit;
+
+    fn archive_dir(name: &str) -> OsString {
+        let dir = std::env::temp_dir().join(format!("coding_test_archive_{}", name));
+        let _ = fs::remove_dir_all(&dir);
+        dir.into_os_string()
+    }
+
+    #[test]
+    fn test_archive_survives_reopening() {
+        let dir = archive_dir("rehydrate");
+        let mut archive = Archive::open(&dir).unwrap();
+
+        let mut account = Account::new(7);
+        assert!(account
+            .deposit(&Deposit {
+                client_id: 7,
+                tx_id: 1,
+                amount: Amount::from(10),
+            })
+            .is_ok());
+        let balances = BalanceSnapshot {
+            available: Amount::from(0),
+            total: account.held(),
+            locked: true,
+            ..account.balances()
+        };
+        account.restore_balances(balances).unwrap();
+        archive.put(&account).unwrap();
+        assert_eq!(archive.len(), 1);
+
+        let mut archive = Archive::open(&dir).unwrap();
+        assert!(archive.contains(7));
+        assert_eq!(archive.clients(), vec![7]);
+        assert!(archive.get(7).unwrap().unwrap().is_locked());
+        assert!(archive.contains(7));
+
+        let account = archive.take(7).unwrap().unwrap();
+        assert_eq!(account.id(), 7);
+        assert!(account.is_locked());
+        assert!(account.find_transaction(1).is_some());
+        assert!(!archive.contains(7));
+        assert!(archive.is_empty());
+        assert!(archive.take(7).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_stats_and_compaction() {
+        let dir = archive_dir("compact");
+        let mut archive = Archive::open(&dir).unwrap();
+        for client_id in 1..=3 {
+            let mut account = Account::new(client_id);
+            assert!(account
+                .deposit(&Deposit {
+                    client_id,
+                    tx_id: client_id.into(),
+                    amount: Amount::from(10),
+                })
+                .is_ok());
+            archive.put(&account).unwrap();
+        }
+        // Leftover of an interrupted write.
+        fs::write(PathBuf::from(&dir).join("4.tmp"), "{\"client\":").unwrap();
+
+        let stats = archive.stats().unwrap();
+        assert_eq!(stats.accounts, 3);
+        assert_eq!(stats.transactions, 3);
+        assert_eq!(stats.adjustments, 0);
+
+        let report = archive.compact().unwrap();
+        assert_eq!(report.rewritten_accounts, 3);
+        assert_eq!(report.removed_files, 1);
+        assert_eq!(report.disk_bytes_before, stats.disk_bytes);
+        assert!(report.disk_bytes_after < report.disk_bytes_before);
+        assert!(archive.take(2).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_unversioned_archive_is_migrated() {
+        let dir = archive_dir("migrate");
+        fs::create_dir_all(&dir).unwrap();
+        // Account written before transactions had a direction and a time.
+        fs::write(
+            PathBuf::from(&dir).join("5.json"),
+            "{\"client\":5,\"available\":\"10\",\"held\":\"0\",\"total\":\"10\",\
+             \"locked\":false,\"last_activity\":0,\"transactions\":[],\"adjustments\":[]}",
+        )
+        .unwrap();
+
+        let dry_run = MigrationOptions {
+            dry_run: true,
+            ..MigrationOptions::default()
+        };
+        let report = Archive::migrate(&dir, &dry_run).unwrap();
+        assert_eq!((report.from_version, report.to_version), (0, 1));
+        assert!(!report.applied);
+        assert!(!PathBuf::from(&dir).join(VERSION_FILE).exists());
+
+        let backup = std::env::temp_dir().join("coding_test_archive_migrate_backup");
+        let _ = fs::remove_dir_all(&backup);
+        let options = MigrationOptions {
+            backup: Some(backup.clone()),
+            ..MigrationOptions::default()
+        };
+        assert!(Archive::migrate(&dir, &options).unwrap().applied);
+        assert!(backup.join("5.json").exists());
+
+        let mut archive = Archive::open(&dir).unwrap();
+        assert_eq!(archive.version().unwrap(), 1);
+        assert_eq!(
+            archive.take(5).unwrap().unwrap().available(),
+            Amount::from(10)
+        );
+        assert!(!Archive::migrate(&dir, &options).unwrap().applied);
+    }
+}
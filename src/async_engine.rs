@@ -0,0 +1,123 @@
+use crate::engine::PaymentsEngine;
+use crate::source::{self, SourceError};
+
+use anyhow::Context;
+use csv::{ReaderBuilder, StringRecord, Trim};
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+
+// Parses a single CSV line. There is no async CSV reader, so input is split into lines first and
+// quoted fields can't contain line breaks.
+fn parse_record(line: &str) -> csv::Result<Option<StringRecord>> {
+    let mut reader = ReaderBuilder::new()
+        .has_headers(false)
+        .trim(Trim::All)
+        .from_reader(line.as_bytes());
+    let mut record = StringRecord::new();
+    Ok(reader.read_record(&mut record)?.then_some(record))
+}
+
+// Processes transactions in CSV format from an async source (socket, object storage) without
+// blocking a thread while waiting for input. Transactions are still applied one by one.
+pub async fn process_payments_async<R>(engine: &mut PaymentsEngine, input: R) -> anyhow::Result<()>
+where
+    R: AsyncRead + Unpin,
+{
+    let mut lines = BufReader::new(input).lines();
+    let mut headers: Option<StringRecord> = None;
+    let mut line_number = 0;
+
+    while let Some(line) = lines.next_line().await.context("can not read input")? {
+        line_number += 1;
+        let record = match parse_record(&line) {
+            Ok(Some(record)) => record,
+            Ok(None) => continue,
+            Err(error) => {
+                engine.skip_invalid("line", line_number, error.to_string(), Some(line))?;
+                continue;
+            }
+        };
+        let headers = match &headers {
+            None => {
+                headers = Some(record);
+                continue;
+            }
+            Some(headers) => headers,
+        };
+
+        // The config is entered only between awaits, as the task may move to another thread.
+        let _config = engine.config().enter();
+        let transaction = match source::from_csv(line_number, headers, &record) {
+            Ok(transaction) => transaction,
+            Err(SourceError::Invalid(message)) => {
+                let record = record.iter().collect::<Vec<_>>().join(",");
+                engine.skip_invalid("line", line_number, message, Some(record))?;
+                continue;
+            }
+            Err(SourceError::Failed(error)) => return Err(error),
+        };
+        engine
+            .process_transaction_at(line_number, transaction)
+            .with_context(|| format!("can not process line {}", line_number))?;
+    }
+
+    engine.flush()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::amount::Amount;
+    use std::str::FromStr;
+
+    #[tokio::test]
+    async fn test_process_payments_async() {
+        let input = "type, client, tx, amount\n\
+                     deposit, 1, 1, 2.5\n\
+                     \n\
+                     withdrawal, 1, 2, 1.0\n\
+                     deposit, 2, 3\n\
+                     deposit, 2, 4, 3\n";
+        let mut engine = PaymentsEngine::default();
+        process_payments_async(&mut engine, input.as_bytes())
+            .await
+            .unwrap();
+
+        let accounts = engine.accounts();
+        assert_eq!(accounts.len(), 2);
+        assert_eq!(
+            accounts.get(&1).unwrap().available(),
+            Amount::from_str("1.5").unwrap()
+        );
+        assert_eq!(accounts.get(&2).unwrap().available(), Amount::from(3));
+    }
+
+    #[tokio::test]
+    async fn test_rejections_have_lines() {
+        let input = "type, client, tx, amount\n\
+                     deposit, 1, 1, 2.5\n\
+                     deposit, 2, 3\n\
+                     withdrawal, 1, 2, 5\n\
+                     \"deposit, 1, 4, 1\n";
+        let mut engine = PaymentsEngine::default();
+        engine.keep_rejected();
+        process_payments_async(&mut engine, input.as_bytes())
+            .await
+            .unwrap();
+
+        assert_eq!(engine.rows(), 4);
+        assert_eq!(engine.rejections().counts().get("invalid_record"), Some(&2));
+        let rejected: Vec<(u64, &str)> = engine
+            .take_rejected()
+            .iter()
+            .map(|rejection| (rejection.line, rejection.code))
+            .collect();
+        assert_eq!(
+            rejected,
+            vec![
+                (3, "invalid_record"),
+                (4, "insufficient_funds"),
+                (5, "invalid_record")
+            ]
+        );
+    }
+}
@@ -0,0 +1,274 @@
+use crate::account::Account;
+use crate::authorization::Role;
+use crate::decimal_format;
+use crate::output;
+use crate::transaction::{Transaction, TransactionType};
+
+use crate::amount::Amount;
+use anyhow::Context;
+use serde::Serialize;
+use std::ffi::OsString;
+use std::fs::File;
+
+#[derive(Serialize)]
+pub struct AuditEntry {
+    client: u16,
+    tx: u32,
+    action: &'static str,
+    #[serde(serialize_with = "decimal_format::serialize")]
+    amount: Amount,
+    operator: String,
+    // Role is empty if operators are not configured.
+    role: &'static str,
+    reason: String,
+    // Second operator who approved a large adjustment, if it needed an approval.
+    approved_by: String,
+}
+
+// Trail of admin transactions which changed client accounts. It's only written if a file for it
+// is given, otherwise entries are dropped.
+pub struct AuditTrail {
+    writer: Option<csv::Writer<File>>,
+}
+
+impl AuditTrail {
+    pub fn new() -> AuditTrail {
+        AuditTrail { writer: None }
+    }
+
+    pub fn create(file_path: &OsString) -> anyhow::Result<AuditTrail> {
+        Ok(AuditTrail {
+            writer: Some(output::csv_writer(File::create(file_path).with_context(
+                || format!("can not create audit log {:?}", file_path),
+            )?)),
+        })
+    }
+
+    pub fn entry(
+        transaction: &Transaction,
+        account: &Account,
+        role: Option<Role>,
+    ) -> Option<AuditEntry> {
+        let tx_id = match &transaction.tx_type {
+            TransactionType::WriteOff(write_off) => write_off.tx_id,
+            TransactionType::Lock(lock) => lock.tx_id,
+            TransactionType::Unlock(unlock) => unlock.tx_id,
+            TransactionType::Adjust(adjust) => adjust.tx_id,
+            TransactionType::Annotate(annotate) => annotate.tx_id,
+            _ => return None,
+        };
+
+        account.find_adjustment(tx_id).map(|adjustment| AuditEntry {
+            client: account.id(),
+            tx: tx_id,
+            action: adjustment.kind().name(),
+            amount: adjustment.amount(),
+            operator: transaction.operator.clone().unwrap_or_default(),
+            role: role.map(|role| role.name()).unwrap_or_default(),
+            reason: transaction.reason.clone().unwrap_or_default(),
+            approved_by: transaction.approved_by.clone().unwrap_or_default(),
+        })
+    }
+
+    // Records an already applied transaction if it is an admin one.
+    pub fn record(
+        &mut self,
+        transaction: &Transaction,
+        account: &Account,
+        role: Option<Role>,
+    ) -> anyhow::Result<()> {
+        if let Some(writer) = &mut self.writer {
+            if let Some(entry) = AuditTrail::entry(transaction, account, role) {
+                writer.serialize(entry)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    // Records a transaction flagged by a policy instead of an applied admin transaction.
+    fn record_flagged(
+        &mut self,
+        transaction: &Transaction,
+        account: &Account,
+        action: &'static str,
+        amount: Amount,
+    ) -> anyhow::Result<()> {
+        if let Some(writer) = &mut self.writer {
+            writer.serialize(AuditEntry {
+                client: account.id(),
+                tx: transaction.tx_id(),
+                action,
+                amount,
+                operator: transaction.operator.clone().unwrap_or_default(),
+                role: "",
+                reason: transaction.reason.clone().unwrap_or_default(),
+                approved_by: transaction.approved_by.clone().unwrap_or_default(),
+            })?;
+        }
+
+        Ok(())
+    }
+
+    // Records a transaction whose balances were saturated by overflow policy.
+    pub fn record_saturated(
+        &mut self,
+        transaction: &Transaction,
+        account: &Account,
+    ) -> anyhow::Result<()> {
+        self.record_flagged(transaction, account, "overflow_saturated", account.total())
+    }
+
+    // Records a dispute of a charged back transaction escalated by charged back policy, with the
+    // amount of the charged back deposit.
+    pub fn record_escalated(
+        &mut self,
+        transaction: &Transaction,
+        account: &Account,
+    ) -> anyhow::Result<()> {
+        let amount = account
+            .find_transaction(transaction.tx_id())
+            .map(|deposited| deposited.charged_back_amount())
+            .unwrap_or_default();
+        self.record_flagged(transaction, account, "charged_back_dispute", amount)
+    }
+
+    // Records a transaction which increased debt of an account by the amount, like a dispute held
+    // beyond available funds by dispute policy.
+    pub fn record_debt(
+        &mut self,
+        transaction: &Transaction,
+        account: &Account,
+        amount: Amount,
+    ) -> anyhow::Result<()> {
+        self.record_flagged(transaction, account, "debt_incurred", amount)
+    }
+
+    pub fn flush(&mut self) -> anyhow::Result<()> {
+        if let Some(writer) = &mut self.writer {
+            writer.flush()?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for AuditTrail {
+    fn default() -> AuditTrail {
+        AuditTrail::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::account::BalanceSnapshot;
+    use crate::transaction::{Annotate, Chargeback, Deposit, Dispute, Process, Unlock, WriteOff};
+    use std::str::FromStr;
+
+    fn admin(tx_type: TransactionType) -> Transaction {
+        Transaction {
+            tx_type,
+            timestamp: None,
+            operator: Some(String::from("ops")),
+            reason: Some(String::from("remediation")),
+            approved_by: None,
+        }
+    }
+
+    #[test]
+    fn test_entry_for_write_off() {
+        let mut account = Account::new(12345);
+        account
+            .restore_balances(BalanceSnapshot {
+                available: Amount::from_str("-10").unwrap(),
+                total: Amount::from_str("-10").unwrap(),
+                ..BalanceSnapshot::default()
+            })
+            .unwrap();
+
+        let transaction = admin(TransactionType::WriteOff(WriteOff {
+            client_id: 12345,
+            tx_id: 22334455,
+        }));
+        assert!(transaction.tx_type.process(&mut account).is_ok());
+
+        let entry = AuditTrail::entry(&transaction, &account, Some(Role::Senior)).unwrap();
+        assert_eq!(entry.client, 12345);
+        assert_eq!(entry.tx, 22334455);
+        assert_eq!(entry.action, "write_off");
+        assert_eq!(entry.amount, Amount::from_str("10").unwrap());
+        assert_eq!(entry.operator, "ops");
+        assert_eq!(entry.role, "senior");
+        assert_eq!(entry.reason, "remediation");
+    }
+
+    #[test]
+    fn test_entry_for_annotation() {
+        let mut account = Account::new(12345);
+
+        let transaction = admin(TransactionType::Annotate(Annotate {
+            client_id: 12345,
+            tx_id: 22334455,
+        }));
+        assert!(transaction.tx_type.process(&mut account).is_ok());
+
+        let entry = AuditTrail::entry(&transaction, &account, None).unwrap();
+        assert_eq!(entry.action, "annotate");
+        assert_eq!(entry.amount, Amount::from(0));
+        assert_eq!(entry.role, "");
+    }
+
+    #[test]
+    fn test_entry_for_reinstated_account() {
+        let mut account = Account::new(12345);
+        let deposit = |tx_id| {
+            TransactionType::Deposit(Deposit {
+                client_id: 12345,
+                tx_id,
+                amount: Amount::from_str("10").unwrap(),
+            })
+        };
+        assert!(deposit(1).process(&mut account).is_ok());
+        let dispute = TransactionType::Dispute(Dispute {
+            client_id: 12345,
+            tx_id: 1,
+            amount: None,
+        });
+        assert!(dispute.process(&mut account).is_ok());
+        let chargeback = TransactionType::Chargeback(Chargeback {
+            client_id: 12345,
+            tx_id: 1,
+            amount: None,
+        });
+        assert!(chargeback.process(&mut account).is_ok());
+        assert!(deposit(2).process(&mut account).is_err());
+
+        let transaction = admin(TransactionType::Unlock(Unlock {
+            client_id: 12345,
+            tx_id: 3,
+        }));
+        assert!(transaction.tx_type.process(&mut account).is_ok());
+        let entry = AuditTrail::entry(&transaction, &account, None).unwrap();
+        assert_eq!(entry.action, "unlock");
+        assert_eq!(entry.reason, "remediation");
+
+        // A reinstated account is operated again.
+        assert!(deposit(2).process(&mut account).is_ok());
+        assert_eq!(account.available(), Amount::from_str("10").unwrap());
+    }
+
+    #[test]
+    fn test_no_entry_for_client_transaction() {
+        let mut account = Account::new(12345);
+
+        let transaction = admin(TransactionType::Deposit(Deposit {
+            client_id: 12345,
+            tx_id: 22334455,
+            amount: Amount::from_str("10").unwrap(),
+        }));
+        assert!(transaction.tx_type.process(&mut account).is_ok());
+
+        assert!(AuditTrail::entry(&transaction, &account, None).is_none());
+    }
+}
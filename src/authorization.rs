@@ -0,0 +1,271 @@
+use crate::account::Account;
+use crate::transaction::{Transaction, TransactionType};
+
+use crate::amount::Amount;
+use anyhow::Context;
+use log::warn;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fs::File;
+
+// Roles are ordered, a role is allowed to do everything a lower one is.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Junior,
+    Senior,
+}
+
+impl Role {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Role::Junior => "junior",
+            Role::Senior => "senior",
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct OperatorRow {
+    operator: String,
+    role: Role,
+}
+
+// Authorization levels of operators making admin transactions. Without a list of operators every
+// operator is allowed to do anything, so existing admin files keep working.
+pub struct Authorization {
+    operators: Option<HashMap<String, Role>>,
+    senior_threshold: Option<Amount>,
+}
+
+impl Authorization {
+    pub fn new() -> Authorization {
+        Authorization {
+            operators: None,
+            senior_threshold: None,
+        }
+    }
+
+    // Reads operators from a CSV file with `operator` and `role` columns. Admin transactions
+    // moving more than `senior_threshold` require a senior operator.
+    pub fn load(
+        file_path: &OsString,
+        senior_threshold: Option<Amount>,
+    ) -> anyhow::Result<Authorization> {
+        let file = File::open(file_path)
+            .with_context(|| format!("can not open operators file {:?}", file_path))?;
+        let mut reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(file);
+
+        let mut operators = HashMap::new();
+        for result in reader.deserialize() {
+            let row: OperatorRow =
+                result.with_context(|| format!("invalid operators file {:?}", file_path))?;
+            operators.insert(row.operator, row.role);
+        }
+
+        Ok(Authorization::with_operators(operators, senior_threshold))
+    }
+
+    pub fn with_operators(
+        operators: HashMap<String, Role>,
+        senior_threshold: Option<Amount>,
+    ) -> Authorization {
+        Authorization {
+            operators: Some(operators),
+            senior_threshold,
+        }
+    }
+
+    pub fn role(&self, operator: &str) -> Option<Role> {
+        self.operators
+            .as_ref()
+            .and_then(|operators| operators.get(operator).copied())
+    }
+
+    // Amount of funds an admin transaction would move on an account.
+    fn amount(tx_type: &TransactionType, account: &Account) -> Amount {
+        match tx_type {
+            TransactionType::Adjust(adjust) => adjust.amount.abs(),
+            TransactionType::WriteOff(_) if account.available().is_sign_negative() => {
+                -account.available()
+            }
+            _ => Amount::from(0),
+        }
+    }
+
+    pub fn required_role(&self, tx_type: &TransactionType, account: &Account) -> Role {
+        match self.senior_threshold {
+            Some(threshold) if Authorization::amount(tx_type, account) > threshold => Role::Senior,
+            _ => Role::Junior,
+        }
+    }
+
+    // Checks if an operator of an admin transaction is allowed to make it. Client transactions
+    // are always allowed.
+    pub fn authorize(&self, transaction: &Transaction, account: &Account) -> bool {
+        if !transaction.tx_type.is_admin() || self.operators.is_none() {
+            return true;
+        }
+
+        let required = self.required_role(&transaction.tx_type, account);
+        self.allows(transaction, required, account)
+    }
+
+    // Checks if an operator of an approval is allowed to make the transaction it approves, so an
+    // approval needs the same role as the approved transaction.
+    pub fn authorize_approval(
+        &self,
+        approval: &Transaction,
+        approved: &Transaction,
+        account: &Account,
+    ) -> bool {
+        if self.operators.is_none() {
+            return true;
+        }
+
+        let required = self.required_role(&approved.tx_type, account);
+        self.allows(approval, required, account)
+    }
+
+    fn allows(&self, transaction: &Transaction, required: Role, account: &Account) -> bool {
+        let operator = transaction.operator.as_deref().unwrap_or_default();
+        let role = match self.role(operator) {
+            None => {
+                warn!(
+                    "operator {} is not known. ignoring admin transaction for account {}.",
+                    operator,
+                    account.id()
+                );
+                return false;
+            }
+            Some(role) => role,
+        };
+
+        if role < required {
+            warn!(
+                "operator {} has role {} but {} is required. ignoring admin transaction for account {}.",
+                operator,
+                role.name(),
+                required.name(),
+                account.id()
+            );
+            return false;
+        }
+
+        true
+    }
+}
+
+impl Default for Authorization {
+    fn default() -> Authorization {
+        Authorization::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::transaction::{Adjust, Annotate, Approve, Deposit};
+    use std::str::FromStr;
+
+    fn authorization() -> Authorization {
+        let mut operators = HashMap::new();
+        operators.insert(String::from("alice"), Role::Junior);
+        operators.insert(String::from("bob"), Role::Senior);
+        Authorization {
+            operators: Some(operators),
+            senior_threshold: Some(Amount::from(100)),
+        }
+    }
+
+    fn admin(tx_type: TransactionType, operator: &str) -> Transaction {
+        Transaction {
+            tx_type,
+            timestamp: None,
+            operator: Some(String::from(operator)),
+            reason: Some(String::from("remediation")),
+            approved_by: None,
+        }
+    }
+
+    fn adjust(amount: &str) -> TransactionType {
+        TransactionType::Adjust(Adjust {
+            client_id: 1,
+            tx_id: 1,
+            amount: Amount::from_str(amount).unwrap(),
+        })
+    }
+
+    #[test]
+    fn test_everything_allowed_without_operators() {
+        let account = Account::new(1);
+        let transaction = admin(adjust("1000000"), "anyone");
+        assert!(Authorization::new().authorize(&transaction, &account));
+    }
+
+    #[test]
+    fn test_unknown_operator_is_rejected() {
+        let account = Account::new(1);
+        let transaction = admin(
+            TransactionType::Annotate(Annotate {
+                client_id: 1,
+                tx_id: 1,
+            }),
+            "mallory",
+        );
+        assert!(!authorization().authorize(&transaction, &account));
+    }
+
+    #[test]
+    fn test_large_adjustment_requires_senior() {
+        let account = Account::new(1);
+        let authorization = authorization();
+
+        assert!(authorization.authorize(&admin(adjust("100"), "alice"), &account));
+        assert!(!authorization.authorize(&admin(adjust("-100.01"), "alice"), &account));
+        assert!(authorization.authorize(&admin(adjust("-100.01"), "bob"), &account));
+    }
+
+    #[test]
+    fn test_large_adjustment_requires_senior_approver() {
+        let account = Account::new(1);
+        let authorization = authorization();
+        let approval = |operator| {
+            admin(
+                TransactionType::Approve(Approve {
+                    client_id: 1,
+                    tx_id: 1,
+                }),
+                operator,
+            )
+        };
+
+        let small = admin(adjust("100"), "carol");
+        assert!(authorization.authorize_approval(&approval("alice"), &small, &account));
+        let large = admin(adjust("500"), "carol");
+        assert!(!authorization.authorize_approval(&approval("alice"), &large, &account));
+        assert!(authorization.authorize_approval(&approval("bob"), &large, &account));
+        assert!(!authorization.authorize_approval(&approval("mallory"), &small, &account));
+        assert!(Authorization::new().authorize_approval(&approval("mallory"), &large, &account));
+    }
+
+    #[test]
+    fn test_client_transaction_needs_no_operator() {
+        let account = Account::new(1);
+        let transaction = Transaction {
+            tx_type: TransactionType::Deposit(Deposit {
+                client_id: 1,
+                tx_id: 1,
+                amount: Amount::from(1000),
+            }),
+            timestamp: None,
+            operator: None,
+            reason: None,
+            approved_by: None,
+        };
+        assert!(authorization().authorize(&transaction, &account));
+    }
+}
@@ -0,0 +1,320 @@
+use anyhow::{bail, Context};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::process::{self, Child, ExitStatus, Stdio};
+use std::time::{Duration, Instant};
+
+// Rows of the generated dataset if no other number is given.
+pub const DEFAULT_ROWS: u64 = 1_000_000;
+pub const DEFAULT_CLIENTS: u64 = 1000;
+// Every binary is run this many times and its fastest run is reported, so a single slow run
+// caused by the host doesn't decide the comparison.
+pub const RUNS: usize = 3;
+
+// Share of rows which aren't disputes or their settlements and are withdrawals, the rest of them
+// are deposits.
+const WITHDRAWAL_RATIO: f64 = 0.3;
+
+// Small xorshift generator, so the same number of rows always gives the same dataset without
+// pulling in a random number crate.
+struct Generator(u64);
+
+impl Generator {
+    fn next(&mut self, below: u64) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0 % below
+    }
+
+    // Number between 0 and 1.
+    fn ratio(&mut self) -> f64 {
+        self.next(1 << 53) as f64 / (1u64 << 53) as f64
+    }
+}
+
+// Shape of a synthetic dataset.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Dataset {
+    pub rows: u64,
+    pub clients: u64,
+    // Share of rows which dispute an earlier deposit. Every dispute is settled later by a resolve
+    // or a chargeback, so about as many rows again are settlements.
+    pub dispute_ratio: f64,
+    // Share of disputes which end with a chargeback instead of a resolve.
+    pub chargeback_ratio: f64,
+}
+
+impl Dataset {
+    pub fn new(rows: u64) -> Dataset {
+        Dataset {
+            rows,
+            clients: DEFAULT_CLIENTS,
+            dispute_ratio: 0.05,
+            chargeback_ratio: 0.2,
+        }
+    }
+}
+
+// Writes a dataset of deposits, withdrawals, disputes, resolves and chargebacks of many clients.
+// Disputes reference earlier deposits and are settled only while they are open, so they are applied
+// instead of being ignored.
+pub fn generate<W: Write>(output: W, dataset: &Dataset) -> anyhow::Result<()> {
+    let mut writer = BufWriter::new(output);
+    let mut generator = Generator(0x2545_f491_4f6c_dd1d);
+    let mut deposits: Vec<(u64, u64)> = Vec::new();
+    let mut disputes: Vec<(u64, u64)> = Vec::new();
+
+    writeln!(writer, "type,client,tx,amount")?;
+    for tx in 1..=dataset.rows {
+        let client = generator.next(dataset.clients) + 1;
+        let kind = generator.ratio();
+        if kind < dataset.dispute_ratio && !deposits.is_empty() {
+            let deposit = deposits.swap_remove(generator.next(deposits.len() as u64) as usize);
+            writeln!(writer, "dispute,{},{},", deposit.0, deposit.1)?;
+            disputes.push(deposit);
+        } else if kind < 2.0 * dataset.dispute_ratio && !disputes.is_empty() {
+            let (client, deposit) =
+                disputes.swap_remove(generator.next(disputes.len() as u64) as usize);
+            let tx_type = match generator.ratio() < dataset.chargeback_ratio {
+                true => "chargeback",
+                false => "resolve",
+            };
+            writeln!(writer, "{},{},{},", tx_type, client, deposit)?;
+        } else if generator.ratio() < WITHDRAWAL_RATIO {
+            let amount = format!("{}.{:04}", generator.next(100), generator.next(10000));
+            writeln!(writer, "withdrawal,{},{},{}", client, tx, amount)?;
+        } else {
+            let amount = format!("{}.{:04}", generator.next(1000), generator.next(10000));
+            writeln!(writer, "deposit,{},{},{}", client, tx, amount)?;
+            deposits.push((client, tx));
+        }
+    }
+    writer.flush()?;
+
+    Ok(())
+}
+
+// Fastest run of a binary over the dataset.
+#[derive(Serialize)]
+pub struct Measurement {
+    pub binary: String,
+    pub wall_time_secs: f64,
+    // Peak resident memory in kilobytes, it's only known on Unix.
+    pub peak_memory_kb: Option<u64>,
+    pub output_sha256: String,
+}
+
+#[derive(Serialize)]
+pub struct Comparison {
+    pub rows: u64,
+    pub current: Measurement,
+    pub baseline: Measurement,
+    pub outputs_equal: bool,
+}
+
+fn change(current: f64, baseline: f64) -> String {
+    if baseline == 0.0 {
+        return String::from("-");
+    }
+    format!("{:+.1}%", (current - baseline) / baseline * 100.0)
+}
+
+impl Comparison {
+    pub fn to_markdown(&self) -> String {
+        let memory = |measurement: &Measurement| {
+            measurement
+                .peak_memory_kb
+                .map_or(String::from("-"), |kb| format!("{} kB", kb))
+        };
+        let memory_change = match (self.current.peak_memory_kb, self.baseline.peak_memory_kb) {
+            (Some(current), Some(baseline)) => change(current as f64, baseline as f64),
+            _ => String::from("-"),
+        };
+
+        let mut report = String::new();
+        report.push_str("# Benchmark comparison\n\n");
+        report.push_str(&format!(
+            "Dataset of {} generated rows, fastest of {} runs.\n\n",
+            self.rows, RUNS
+        ));
+        report.push_str("| | Current | Baseline | Change |\n");
+        report.push_str("|---|---|---|---|\n");
+        report.push_str(&format!(
+            "| Binary | `{}` | `{}` | |\n",
+            self.current.binary, self.baseline.binary
+        ));
+        report.push_str(&format!(
+            "| Wall time | {:.3} s | {:.3} s | {} |\n",
+            self.current.wall_time_secs,
+            self.baseline.wall_time_secs,
+            change(self.current.wall_time_secs, self.baseline.wall_time_secs)
+        ));
+        report.push_str(&format!(
+            "| Peak memory | {} | {} | {} |\n\n",
+            memory(&self.current),
+            memory(&self.baseline),
+            memory_change
+        ));
+        report.push_str(if self.outputs_equal {
+            "Outputs are equal.\n"
+        } else {
+            "**Outputs differ.**\n"
+        });
+        report
+    }
+}
+
+// Waits for a child and returns its peak resident memory in kilobytes.
+#[cfg(unix)]
+fn wait(child: Child) -> anyhow::Result<(ExitStatus, Option<u64>)> {
+    use std::os::unix::process::ExitStatusExt;
+
+    let mut status = 0;
+    // Safety: usage is a plain C struct which `wait4` fills in.
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+    if unsafe { libc::wait4(child.id() as libc::pid_t, &mut status, 0, &mut usage) } < 0 {
+        return Err(std::io::Error::last_os_error()).context("can not wait for benchmark run");
+    }
+    // Linux reports kilobytes, macOS bytes.
+    let max_rss = usage.ru_maxrss as u64;
+    let peak_memory_kb = if cfg!(target_os = "macos") {
+        max_rss / 1024
+    } else {
+        max_rss
+    };
+
+    Ok((ExitStatus::from_raw(status), Some(peak_memory_kb)))
+}
+
+#[cfg(not(unix))]
+fn wait(mut child: Child) -> anyhow::Result<(ExitStatus, Option<u64>)> {
+    Ok((
+        child.wait().context("can not wait for benchmark run")?,
+        None,
+    ))
+}
+
+// Runs a binary as `<binary> <input.csv>` with its accounts output written into a file.
+fn measure(binary: &Path, input: &Path, output: &Path) -> anyhow::Result<Measurement> {
+    let mut fastest: Option<Duration> = None;
+    let mut peak_memory_kb = None;
+    for _ in 0..RUNS {
+        let started = Instant::now();
+        let child = process::Command::new(binary)
+            .arg(input)
+            .env("RUST_LOG", "off")
+            .stdin(Stdio::null())
+            .stdout(File::create(output)?)
+            .stderr(Stdio::null())
+            .spawn()
+            .with_context(|| format!("can not run {:?}", binary))?;
+        let (status, memory) = wait(child)?;
+        let wall_time = started.elapsed();
+        if !status.success() {
+            bail!("{:?} exited with {}", binary, status);
+        }
+        fastest = Some(fastest.map_or(wall_time, |fastest| fastest.min(wall_time)));
+        peak_memory_kb = peak_memory_kb.max(memory);
+    }
+
+    let digest = Sha256::digest(fs::read(output)?);
+    Ok(Measurement {
+        binary: binary.display().to_string(),
+        wall_time_secs: fastest.unwrap_or_default().as_secs_f64(),
+        peak_memory_kb,
+        output_sha256: hex::encode(digest),
+    })
+}
+
+// Runs the current and a baseline binary over the same generated dataset.
+pub fn compare(current: &Path, baseline: &Path, dataset: &Dataset) -> anyhow::Result<Comparison> {
+    let dir = std::env::temp_dir().join(format!("coding_test_bench_{}", process::id()));
+    fs::create_dir_all(&dir).with_context(|| format!("can not create directory {:?}", dir))?;
+
+    let input = dir.join("transactions.csv");
+    generate(File::create(&input)?, dataset).context("can not generate dataset")?;
+    let current = measure(current, &input, &dir.join("current.csv"))?;
+    let baseline = measure(baseline, &input, &dir.join("baseline.csv"))?;
+    let _ = fs::remove_dir_all(&dir);
+
+    Ok(Comparison {
+        rows: dataset.rows,
+        outputs_equal: current.output_sha256 == baseline.output_sha256,
+        current,
+        baseline,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::engine::PaymentsEngine;
+
+    #[test]
+    fn test_generated_dataset() {
+        let mut first = Vec::new();
+        generate(&mut first, &Dataset::new(1000)).unwrap();
+        let mut second = Vec::new();
+        generate(&mut second, &Dataset::new(1000)).unwrap();
+        assert_eq!(first, second);
+
+        let mut engine = PaymentsEngine::default();
+        assert!(engine.process_csv(first.as_slice()).is_ok());
+        assert!(engine
+            .accounts()
+            .values()
+            .any(|account| !account.held().is_zero() || account.is_locked()));
+    }
+
+    #[test]
+    fn test_dataset_ratios() {
+        let dataset = Dataset {
+            rows: 10000,
+            clients: 10,
+            dispute_ratio: 0.1,
+            chargeback_ratio: 1.0,
+        };
+        let mut output = Vec::new();
+        generate(&mut output, &dataset).unwrap();
+        let output = String::from_utf8(output).unwrap();
+
+        let count = |tx_type: &str| {
+            output
+                .lines()
+                .filter(|line| line.starts_with(tx_type))
+                .count()
+        };
+        assert_eq!(output.lines().count(), 10001);
+        assert!((900..1100).contains(&count("dispute")));
+        assert_eq!(count("resolve"), 0);
+        assert!(count("chargeback") <= count("dispute"));
+        assert!(output
+            .lines()
+            .skip(1)
+            .all(|line| (1..=10).contains(&line.split(',').nth(1).unwrap().parse().unwrap())));
+    }
+
+    #[test]
+    fn test_markdown_report() {
+        let measurement = |wall_time_secs, output_sha256: &str| Measurement {
+            binary: String::from("payments"),
+            wall_time_secs,
+            peak_memory_kb: Some(2000),
+            output_sha256: output_sha256.to_string(),
+        };
+        let comparison = Comparison {
+            rows: 10,
+            current: measurement(1.5, "a"),
+            baseline: measurement(2.0, "b"),
+            outputs_equal: false,
+        };
+        let report = comparison.to_markdown();
+        assert!(report.contains("| Wall time | 1.500 s | 2.000 s | -25.0% |"));
+        assert!(report.contains("| Peak memory | 2000 kB | 2000 kB | +0.0% |"));
+        assert!(report.contains("**Outputs differ.**"));
+    }
+}
@@ -0,0 +1,397 @@
+use crate::account::{Account, BalanceSnapshot};
+use crate::amount::Amount;
+use crate::transaction::{Transaction, TransactionType};
+
+use anyhow::{bail, Context};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+
+// Change of an account made by an accepted transaction. Amounts are changes of balances, kept
+// exactly as decimal strings like in the event log.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct Change {
+    pub client: u16,
+    // Number of changes of the account in the stream, starting at 1, so consumers can drop
+    // duplicates and notice gaps.
+    pub version: u64,
+    pub tx: u32,
+    #[serde(rename = "type")]
+    pub tx_type: String,
+    // What happened to the account, like `funds_held` for a dispute.
+    pub event: String,
+    pub available: Amount,
+    pub held: Amount,
+    pub total: Amount,
+    // Lock state of the account after the transaction.
+    pub locked: bool,
+    pub before: AccountState,
+    pub after: AccountState,
+}
+
+// Balances and lock state of an account before or after a change.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub struct AccountState {
+    pub available: Amount,
+    pub held: Amount,
+    pub total: Amount,
+    pub locked: bool,
+}
+
+impl From<BalanceSnapshot> for AccountState {
+    fn from(balances: BalanceSnapshot) -> AccountState {
+        AccountState {
+            available: balances.available,
+            held: balances.held,
+            total: balances.total,
+            locked: balances.locked,
+        }
+    }
+}
+
+// Legs of a transfer are applied as a withdrawal and a deposit, and annotations change nothing.
+fn event_of(tx_type: &TransactionType) -> &'static str {
+    match tx_type {
+        TransactionType::Deposit(_) => "deposit_applied",
+        TransactionType::Withdrawal(_) => "withdrawal_applied",
+        TransactionType::Dispute(_) => "funds_held",
+        TransactionType::Resolve(_) => "funds_released",
+        TransactionType::Chargeback(_) => "chargeback_executed",
+        TransactionType::Representment(_) => "chargeback_reversed",
+        TransactionType::WriteOff(_) => "debt_written_off",
+        TransactionType::Lock(_) => "account_locked",
+        TransactionType::Unlock(_) => "account_unlocked",
+        TransactionType::Adjust(_) | TransactionType::Approve(_) => "adjustment_applied",
+        TransactionType::Annotate(_) => "annotated",
+        TransactionType::Transfer(_) => "transfer_applied",
+    }
+}
+
+// Anything changes are streamed to. Changes of an account are sent in the order they happened.
+pub trait ChangeSink: Send {
+    fn send(&mut self, change: &Change) -> anyhow::Result<()>;
+
+    // Makes sent changes durable or delivered.
+    fn flush(&mut self) -> anyhow::Result<()>;
+}
+
+// Changes appended to a file, one JSON object per line.
+pub struct JsonlSink {
+    writer: BufWriter<File>,
+}
+
+impl JsonlSink {
+    pub fn open(path: &str) -> anyhow::Result<JsonlSink> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("can not open change stream file {:?}", path))?;
+        Ok(JsonlSink {
+            writer: BufWriter::new(file),
+        })
+    }
+}
+
+impl ChangeSink for JsonlSink {
+    fn send(&mut self, change: &Change) -> anyhow::Result<()> {
+        serde_json::to_writer(&mut self.writer, change)?;
+        self.writer.write_all(b"\n")?;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> anyhow::Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "kafka")]
+mod kafka {
+    use super::{Change, ChangeSink};
+
+    use anyhow::{bail, Context};
+    use log::error;
+    use rdkafka::config::ClientConfig;
+    use rdkafka::error::{KafkaError, RDKafkaErrorCode};
+    use rdkafka::producer::{BaseProducer, BaseRecord, DeliveryResult, Producer, ProducerContext};
+    use rdkafka::ClientContext;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::time::Duration;
+
+    // Time to wait for outstanding messages when the stream is flushed.
+    const FLUSH_TIMEOUT: Duration = Duration::from_secs(30);
+
+    // Counts messages which couldn't be delivered, so a flush can fail on them.
+    #[derive(Default)]
+    pub struct DeliveryContext {
+        failed: AtomicU64,
+    }
+
+    impl ClientContext for DeliveryContext {}
+
+    impl ProducerContext for DeliveryContext {
+        type DeliveryOpaque = ();
+
+        fn delivery(&self, result: &DeliveryResult<'_>, _: ()) {
+            if let Err((error, _)) = result {
+                error!("can not deliver change to Kafka: {}", error);
+                self.failed.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    // Changes produced to a Kafka topic as JSON messages keyed by client id, so changes of an
+    // account land in one partition and keep their order.
+    pub struct KafkaSink {
+        producer: BaseProducer<DeliveryContext>,
+        topic: String,
+    }
+
+    impl KafkaSink {
+        pub fn connect(brokers: &str, topic: &str) -> anyhow::Result<KafkaSink> {
+            let producer = ClientConfig::new()
+                .set("bootstrap.servers", brokers)
+                .set("enable.idempotence", "true")
+                .create_with_context(DeliveryContext::default())
+                .context("can not create Kafka producer")?;
+            Ok(KafkaSink {
+                producer,
+                topic: topic.to_string(),
+            })
+        }
+    }
+
+    impl ChangeSink for KafkaSink {
+        fn send(&mut self, change: &Change) -> anyhow::Result<()> {
+            let payload = serde_json::to_string(change)?;
+            let key = change.client.to_string();
+            loop {
+                let record = BaseRecord::to(&self.topic).key(&key).payload(&payload);
+                match self.producer.send(record) {
+                    Ok(()) => break,
+                    // Local queue is full, so delivered messages are waited for.
+                    Err((KafkaError::MessageProduction(RDKafkaErrorCode::QueueFull), _)) => {
+                        self.producer.poll(Duration::from_millis(100));
+                    }
+                    Err((error, _)) => {
+                        return Err(error).context("can not send change to Kafka");
+                    }
+                }
+            }
+            self.producer.poll(Duration::ZERO);
+            Ok(())
+        }
+
+        fn flush(&mut self) -> anyhow::Result<()> {
+            self.producer
+                .flush(FLUSH_TIMEOUT)
+                .context("can not flush changes to Kafka")?;
+            let failed = self.producer.context().failed.swap(0, Ordering::Relaxed);
+            if failed > 0 {
+                bail!("{} changes were not delivered to Kafka", failed);
+            }
+            Ok(())
+        }
+    }
+}
+
+#[cfg(feature = "kafka")]
+pub use kafka::KafkaSink;
+
+// Sink is given as `file:<path>` or as `kafka:<brokers>/<topic>` with brokers separated by commas.
+pub fn open_sink(s: &str) -> anyhow::Result<Box<dyn ChangeSink>> {
+    if let Some(path) = s.strip_prefix("file:") {
+        return Ok(Box::new(JsonlSink::open(path)?));
+    }
+    if let Some(target) = s.strip_prefix("kafka:") {
+        let (brokers, topic) = match target.rsplit_once('/') {
+            Some((brokers, topic)) if !brokers.is_empty() && !topic.is_empty() => (brokers, topic),
+            _ => bail!("expected kafka:<brokers>/<topic>, got {}", s),
+        };
+        #[cfg(feature = "kafka")]
+        return Ok(Box::new(KafkaSink::connect(brokers, topic)?));
+        #[cfg(not(feature = "kafka"))]
+        bail!(
+            "streaming changes to Kafka topic {} at {} requires kafka feature",
+            topic,
+            brokers
+        );
+    }
+    bail!("unknown change stream: {}", s)
+}
+
+// Stream of account changes with versions of accounts.
+pub struct ChangeStream {
+    sink: Box<dyn ChangeSink>,
+    versions: HashMap<u16, u64>,
+}
+
+impl ChangeStream {
+    pub fn new(sink: Box<dyn ChangeSink>) -> ChangeStream {
+        ChangeStream {
+            sink,
+            versions: HashMap::new(),
+        }
+    }
+
+    // Sends a change made by an accepted transaction, unless it changed neither balances nor lock
+    // state of the account (like an annotation).
+    pub fn record(
+        &mut self,
+        transaction: &Transaction,
+        before: BalanceSnapshot,
+        account: &Account,
+    ) -> anyhow::Result<()> {
+        let before = AccountState::from(before);
+        let after = AccountState::from(account.balances());
+        if before == after {
+            return Ok(());
+        }
+        self.send(transaction, account.id(), before, after)
+    }
+
+    // Sends a change of a deposit applied together with other deposits of the account. `before`
+    // are balances after the previous deposit of the batch.
+    pub fn record_deposit(
+        &mut self,
+        transaction: &Transaction,
+        amount: Amount,
+        before: BalanceSnapshot,
+        account: &Account,
+    ) -> anyhow::Result<()> {
+        let before = AccountState::from(before);
+        let after = AccountState {
+            available: before.available + amount,
+            total: before.total + amount,
+            ..before
+        };
+        self.send(transaction, account.id(), before, after)
+    }
+
+    fn send(
+        &mut self,
+        transaction: &Transaction,
+        client: u16,
+        before: AccountState,
+        after: AccountState,
+    ) -> anyhow::Result<()> {
+        let version = self.versions.entry(client).or_default();
+        *version += 1;
+        self.sink.send(&Change {
+            client,
+            version: *version,
+            tx: transaction.tx_id(),
+            tx_type: transaction.tx_type.name().to_string(),
+            event: event_of(&transaction.tx_type).to_string(),
+            available: after.available - before.available,
+            held: after.held - before.held,
+            total: after.total - before.total,
+            locked: after.locked,
+            before,
+            after,
+        })
+    }
+
+    pub fn flush(&mut self) -> anyhow::Result<()> {
+        self.sink.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::engine::PaymentsEngine;
+    use std::ffi::OsString;
+    use std::str::FromStr;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Default)]
+    struct SharedSink(Arc<Mutex<Vec<Change>>>);
+
+    impl ChangeSink for SharedSink {
+        fn send(&mut self, change: &Change) -> anyhow::Result<()> {
+            self.0.lock().unwrap().push(change.clone());
+            Ok(())
+        }
+
+        fn flush(&mut self) -> anyhow::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_changes_follow_accounts() {
+        for coalesce_deposits in [false, true] {
+            let sink = SharedSink::default();
+            let mut engine = PaymentsEngine::default();
+            engine.set_coalesce_deposits(coalesce_deposits);
+            engine.set_changes(ChangeStream::new(Box::new(sink.clone())));
+            assert!(engine
+                .process_file(&OsString::from("transactions_3.csv"))
+                .is_ok());
+
+            let changes = sink.0.lock().unwrap();
+            for (client_id, account) in engine.accounts() {
+                let changes: Vec<&Change> = changes
+                    .iter()
+                    .filter(|change| change.client == *client_id)
+                    .collect();
+                let versions: Vec<u64> = changes.iter().map(|change| change.version).collect();
+                assert_eq!(versions, (1..=changes.len() as u64).collect::<Vec<_>>());
+                let total: Amount = changes.iter().map(|change| change.total).sum();
+                assert_eq!(total, account.total());
+                let held: Amount = changes.iter().map(|change| change.held).sum();
+                assert_eq!(held, account.held());
+                // Every change continues from the state the previous one left.
+                for pair in changes.windows(2) {
+                    assert_eq!(pair[0].after, pair[1].before);
+                }
+                assert_eq!(
+                    changes.last().unwrap().after,
+                    AccountState::from(account.balances())
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_open_sink() {
+        assert!(open_sink("kafka:/changes").is_err());
+        assert!(open_sink("kafka:localhost:9092").is_err());
+        assert!(open_sink("stdout").is_err());
+
+        let path = std::env::temp_dir().join("coding_test_changes.jsonl");
+        let _ = std::fs::remove_file(&path);
+        let mut sink = open_sink(&format!("file:{}", path.display())).unwrap();
+        let change = Change {
+            client: 1,
+            version: 1,
+            tx: 2,
+            tx_type: String::from("deposit"),
+            event: String::from("deposit_applied"),
+            available: Amount::from_str("1.5").unwrap(),
+            held: Amount::default(),
+            total: Amount::from_str("1.5").unwrap(),
+            locked: false,
+            before: AccountState {
+                available: Amount::default(),
+                held: Amount::default(),
+                total: Amount::default(),
+                locked: false,
+            },
+            after: AccountState {
+                available: Amount::from_str("1.5").unwrap(),
+                held: Amount::default(),
+                total: Amount::from_str("1.5").unwrap(),
+                locked: false,
+            },
+        };
+        sink.send(&change).unwrap();
+        sink.flush().unwrap();
+
+        let line = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(serde_json::from_str::<Change>(&line).unwrap(), change);
+    }
+}
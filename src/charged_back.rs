@@ -0,0 +1,83 @@
+use crate::error::TransactionError;
+
+use log::{debug, error, warn};
+use std::str::FromStr;
+
+// Charged back transactions are final, so a dispute referencing one is always rejected. The policy
+// decides how loudly, as it may be a producer retrying an old dispute or a sign of fraud.
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub enum ChargedBackPolicy {
+    // The dispute is dropped without a warning.
+    Ignore,
+    // The dispute is logged as a warning with `REJECTION_CODE`.
+    #[default]
+    Reject,
+    // The dispute is logged as an error and recorded into the audit log.
+    Escalate,
+}
+
+// Code of a rejected dispute of a charged back transaction in logs.
+pub const REJECTION_CODE: &str = "charged_back";
+
+// Policy is given as `ignore`, `reject` or `escalate`.
+impl FromStr for ChargedBackPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<ChargedBackPolicy, String> {
+        match s {
+            "ignore" => Ok(ChargedBackPolicy::Ignore),
+            "reject" => Ok(ChargedBackPolicy::Reject),
+            "escalate" => Ok(ChargedBackPolicy::Escalate),
+            _ => Err(format!("unknown charged back dispute policy: {}", s)),
+        }
+    }
+}
+
+// Logs a rejected dispute of a charged back transaction as loudly as the policy says.
+pub fn dispute_rejected(policy: ChargedBackPolicy, client_id: u16, tx_id: u32) {
+    match policy {
+        ChargedBackPolicy::Ignore => {
+            debug!("tx: {} is charged back, dispute ignored", tx_id)
+        }
+        ChargedBackPolicy::Reject => warn!(
+            "tx: {} dispute rejected [{}]: transaction of account {} is charged back",
+            tx_id, REJECTION_CODE, client_id
+        ),
+        ChargedBackPolicy::Escalate => error!(
+            "tx: {} dispute escalated [{}]: transaction of account {} is charged back",
+            tx_id, REJECTION_CODE, client_id
+        ),
+    }
+}
+
+// Whether a transaction with the result is a dispute of a charged back transaction which the policy
+// escalates, so it's recorded into the audit log.
+pub fn is_escalated(policy: ChargedBackPolicy, result: &Result<(), TransactionError>) -> bool {
+    policy == ChargedBackPolicy::Escalate && *result == Err(TransactionError::ChargedBack)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_policy() {
+        assert_eq!(
+            "escalate".parse::<ChargedBackPolicy>(),
+            Ok(ChargedBackPolicy::Escalate)
+        );
+        assert!("retry".parse::<ChargedBackPolicy>().is_err());
+    }
+
+    #[test]
+    fn test_escalated_dispute() {
+        let rejected = Err(TransactionError::ChargedBack);
+        assert!(!is_escalated(ChargedBackPolicy::Reject, &rejected));
+        assert!(is_escalated(ChargedBackPolicy::Escalate, &rejected));
+        assert!(!is_escalated(ChargedBackPolicy::Escalate, &Ok(())));
+        assert!(!is_escalated(
+            ChargedBackPolicy::Escalate,
+            &Err(TransactionError::AccountLocked)
+        ));
+    }
+}
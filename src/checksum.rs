@@ -0,0 +1,304 @@
+use crate::account::Account;
+use crate::amount::{Amount, FIXED_SCALE};
+use crate::decimal_format::{DecimalFormat, Rounding};
+
+use anyhow::{anyhow, bail, Context};
+use hmac::{Hmac, Mac};
+use rust_decimal::RoundingStrategy;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::{self, Write};
+
+type HmacSha256 = Hmac<Sha256>;
+
+// Checksum of an output kept in a sidecar file, so consumers can detect a truncated or modified
+// output. The signature is an HMAC-SHA256 with a shared key and it's only present if a key is
+// given.
+#[derive(PartialEq, Debug)]
+pub struct OutputDigest {
+    pub sha256: String,
+    pub signature: Option<String>,
+}
+
+// Writer which hashes everything written through it.
+pub struct HashingWriter<W: Write> {
+    inner: W,
+    sha256: Sha256,
+    hmac: Option<HmacSha256>,
+}
+
+impl<W: Write> HashingWriter<W> {
+    pub fn new(inner: W, signing_key: Option<&[u8]>) -> HashingWriter<W> {
+        HashingWriter {
+            inner,
+            sha256: Sha256::new(),
+            // HMAC accepts a key of any length.
+            hmac: signing_key.map(|key| HmacSha256::new_from_slice(key).unwrap()),
+        }
+    }
+
+    pub fn digest(self) -> OutputDigest {
+        OutputDigest {
+            sha256: hex::encode(self.sha256.finalize()),
+            signature: self
+                .hmac
+                .map(|hmac| hex::encode(hmac.finalize().into_bytes())),
+        }
+    }
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.sha256.update(&buf[..written]);
+        if let Some(hmac) = &mut self.hmac {
+            hmac.update(&buf[..written]);
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl OutputDigest {
+    pub fn save(&self, file_path: &OsString) -> anyhow::Result<()> {
+        let mut content = format!("sha256 {}\n", self.sha256);
+        if let Some(signature) = &self.signature {
+            content.push_str(&format!("hmac-sha256 {}\n", signature));
+        }
+        fs::write(file_path, content)
+            .with_context(|| format!("can not write checksum file {:?}", file_path))
+    }
+
+    pub fn load(file_path: &OsString) -> anyhow::Result<OutputDigest> {
+        let content = fs::read_to_string(file_path)
+            .with_context(|| format!("can not read checksum file {:?}", file_path))?;
+
+        let mut sha256 = None;
+        let mut signature = None;
+        for line in content.lines() {
+            match line.split_once(' ') {
+                Some(("sha256", value)) => sha256 = Some(value.trim().to_string()),
+                Some(("hmac-sha256", value)) => signature = Some(value.trim().to_string()),
+                _ => bail!("invalid line in checksum file: {}", line),
+            }
+        }
+
+        Ok(OutputDigest {
+            sha256: sha256.ok_or_else(|| anyhow!("checksum file has no sha256"))?,
+            signature,
+        })
+    }
+}
+
+pub fn read_signing_key(file_path: &OsString) -> anyhow::Result<Vec<u8>> {
+    let key =
+        fs::read(file_path).with_context(|| format!("can not read signing key {:?}", file_path))?;
+    Ok(key.trim_ascii().to_vec())
+}
+
+// Checks an output file against its checksum file and signature, if the output was signed.
+pub fn verify_output(
+    file_path: &OsString,
+    checksum_path: &OsString,
+    signing_key: Option<&[u8]>,
+) -> anyhow::Result<()> {
+    let expected = OutputDigest::load(checksum_path)?;
+    if expected.signature.is_some() && signing_key.is_none() {
+        bail!("output is signed, a signing key is needed to verify it");
+    }
+    if expected.signature.is_none() && signing_key.is_some() {
+        bail!("output is not signed");
+    }
+
+    let mut file = File::open(file_path)
+        .with_context(|| format!("can not open output file {:?}", file_path))?;
+    let mut writer = HashingWriter::new(io::sink(), signing_key);
+    io::copy(&mut file, &mut writer)
+        .with_context(|| format!("can not read output file {:?}", file_path))?;
+
+    if hex::encode(writer.sha256.finalize()) != expected.sha256 {
+        bail!(
+            "sha256 of {:?} does not match, it is truncated or modified",
+            file_path
+        );
+    }
+    if let (Some(hmac), Some(signature)) = (writer.hmac, &expected.signature) {
+        let signature = hex::decode(signature).context("invalid signature in checksum file")?;
+        hmac.verify_slice(&signature)
+            .map_err(|_| anyhow!("signature of {:?} does not match", file_path))?;
+    }
+
+    Ok(())
+}
+
+// Balance in the canonical form, with four decimal places and without a negative zero. It's
+// written by a decimal format, as `rust_decimal` can't format the largest amounts with a precision.
+fn canonical(amount: Amount) -> String {
+    let format = DecimalFormat {
+        scale: FIXED_SCALE,
+        trim_trailing_zeros: false,
+        rounding: Rounding::HalfEven,
+    };
+    let amount = amount.round_dp_with_strategy(FIXED_SCALE, RoundingStrategy::MidpointNearestEven);
+    let mut buffer = String::new();
+    if amount.is_zero() {
+        format.write(&Amount::default(), &mut buffer);
+    } else {
+        format.write(&amount, &mut buffer);
+    }
+    buffer
+}
+
+// Hash of final accounts which doesn't depend on output format, decimal format or the order in
+// which accounts were processed, so runs can be compared by a single string. Accounts are hashed
+// sorted by client id, one `client,available,held,total,locked` line each.
+pub fn state_hash(accounts: &HashMap<u16, Account>) -> String {
+    let mut sorted: Vec<&Account> = accounts.values().collect();
+    sorted.sort_unstable_by_key(|account| account.id());
+
+    let mut sha256 = Sha256::new();
+    for account in sorted {
+        sha256.update(format!(
+            "{},{},{},{},{}\n",
+            account.id(),
+            canonical(account.available()),
+            canonical(account.held()),
+            canonical(account.total()),
+            account.is_locked()
+        ));
+    }
+    hex::encode(sha256.finalize())
+}
+
+pub fn save_state_hash(
+    accounts: &HashMap<u16, Account>,
+    file_path: &OsString,
+) -> anyhow::Result<()> {
+    fs::write(file_path, format!("{}\n", state_hash(accounts)))
+        .with_context(|| format!("can not write state hash {:?}", file_path))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::account::BalanceSnapshot;
+    use std::str::FromStr;
+
+    fn temp_path(name: &str) -> OsString {
+        std::env::temp_dir()
+            .join(format!("coding_test_checksum_{}", name))
+            .into_os_string()
+    }
+
+    fn write_output(name: &str, content: &[u8], key: Option<&[u8]>) -> (OsString, OsString) {
+        let output = temp_path(&format!("{}.csv", name));
+        let checksum = temp_path(&format!("{}.sha256", name));
+
+        let mut writer = HashingWriter::new(File::create(&output).unwrap(), key);
+        writer.write_all(content).unwrap();
+        writer.digest().save(&checksum).unwrap();
+        (output, checksum)
+    }
+
+    #[test]
+    fn test_digest_of_known_content() {
+        let mut writer = HashingWriter::new(Vec::new(), None);
+        writer.write_all(b"abc").unwrap();
+        assert_eq!(
+            writer.digest().sha256,
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
+    #[test]
+    fn test_verify_unmodified_output() {
+        let (output, checksum) = write_output("unmodified", b"client\n1\n", None);
+        assert!(verify_output(&output, &checksum, None).is_ok());
+    }
+
+    #[test]
+    fn test_verify_truncated_output() {
+        let (output, checksum) = write_output("truncated", b"client\n1\n2\n", None);
+        fs::write(&output, b"client\n1\n").unwrap();
+        assert!(verify_output(&output, &checksum, None).is_err());
+    }
+
+    #[test]
+    fn test_verify_signed_output() {
+        let key: &[u8] = b"secret";
+        let (output, checksum) = write_output("signed", b"client\n1\n", Some(key));
+
+        assert!(verify_output(&output, &checksum, Some(key)).is_ok());
+        assert!(verify_output(&output, &checksum, Some(b"other")).is_err());
+        assert!(verify_output(&output, &checksum, None).is_err());
+    }
+
+    #[test]
+    fn test_load_saved_digest() {
+        let digest = OutputDigest {
+            sha256: String::from("00ff"),
+            signature: Some(String::from("aa")),
+        };
+        let path = temp_path("saved.sha256");
+        digest.save(&path).unwrap();
+        assert_eq!(OutputDigest::load(&path).unwrap(), digest);
+    }
+
+    #[test]
+    fn test_state_hash_is_canonical() {
+        let account = |client_id, available: &str| {
+            let available = Amount::from_str(available).unwrap();
+            let mut account = Account::new(client_id);
+            account
+                .restore_balances(BalanceSnapshot {
+                    available,
+                    total: available,
+                    ..BalanceSnapshot::default()
+                })
+                .unwrap();
+            account
+        };
+        let mut accounts = HashMap::new();
+        accounts.insert(1, account(1, "10.5"));
+        accounts.insert(2, account(2, "0"));
+        let mut other = HashMap::new();
+        other.insert(2, account(2, "-0.0000"));
+        other.insert(1, account(1, "10.500"));
+        assert_eq!(state_hash(&accounts), state_hash(&other));
+
+        other.insert(1, account(1, "10.5001"));
+        assert_ne!(state_hash(&accounts), state_hash(&other));
+        other.insert(1, account(1, "10.5"));
+        let locked = BalanceSnapshot {
+            locked: true,
+            ..BalanceSnapshot::default()
+        };
+        other.get_mut(&2).unwrap().restore_balances(locked).unwrap();
+        assert_ne!(state_hash(&accounts), state_hash(&other));
+    }
+
+    #[test]
+    fn test_state_hash_of_largest_balance() {
+        let mut account = Account::new(1);
+        account
+            .restore_balances(BalanceSnapshot {
+                available: Amount::MAX,
+                total: Amount::MAX,
+                ..BalanceSnapshot::default()
+            })
+            .unwrap();
+        let mut accounts = HashMap::new();
+        accounts.insert(1, account);
+        assert_eq!(state_hash(&accounts).len(), 64);
+
+        let written = canonical(Amount::MAX);
+        let (integer, fraction) = written.split_once('.').unwrap();
+        assert!(Amount::MAX.to_string().starts_with(integer));
+        assert_eq!(fraction.len(), 4);
+    }
+}
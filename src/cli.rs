@@ -0,0 +1,2479 @@
+use crate::config::Config;
+use coding_test::account::DepositedTransactionStatus;
+use coding_test::alert::AlertRule;
+use coding_test::amount::Amount;
+use coding_test::approval::DEFAULT_APPROVAL_EXPIRY;
+use coding_test::archive::DEFAULT_DORMANT_AFTER;
+use coding_test::bench::{self, Dataset};
+use coding_test::charged_back::ChargedBackPolicy;
+use coding_test::clock::{ClockKind, MIN_REPLAY_SPEED};
+use coding_test::db_input::{self, Database};
+use coding_test::decimal_format::{DecimalFormat, Rounding};
+use coding_test::dispute_policy::DisputePolicy;
+use coding_test::engine::InputFormat;
+use coding_test::entry_limit::{EntryLimit, EvictionStrategy};
+use coding_test::fraud::FraudRule;
+use coding_test::limits::Limits;
+use coding_test::locked_policy::LockedPolicy;
+use coding_test::output::OutputFormat;
+use coding_test::overflow::OverflowPolicy;
+use coding_test::precision::PrecisionPolicy;
+use coding_test::query::AccountQuery;
+use coding_test::reorder::Reorder;
+use coding_test::schema::SchemaPolicy;
+
+use anyhow::{anyhow, bail};
+use std::collections::HashSet;
+use std::ffi::OsString;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::str::FromStr;
+
+#[derive(PartialEq, Debug)]
+pub enum Command {
+    // Process transactions and output final state of client accounts.
+    Process,
+    // Process transactions and output trial balance of internal accounts.
+    TrialBalance,
+    // Process transactions, archive dormant accounts and output remaining active accounts.
+    Archive,
+    // Check an accounts output against its checksum file.
+    VerifyOutput,
+    // Check links and hashes of an audit chain and print its digest.
+    AuditVerify,
+    // Process transactions without output and report records which would be rejected.
+    Verify,
+    // Run conformance vectors against an implementation or export them.
+    Conformance,
+    // Process transactions and print the stored deposit with the given transaction id.
+    Lookup,
+    // Report size of the account store.
+    StoreStats,
+    // Compact the account store.
+    StoreCompact,
+    // Run an SQL query against the SQLite store and print its rows.
+    StoreSql,
+    // Print stored accounts matching a query of any store.
+    StoreQuery,
+    // Migrate stores to the current format.
+    StoreMigrate,
+    // Print the dispute state machine as JSON.
+    StateMachine,
+    // Rebuild accounts from an event log and output them.
+    Replay,
+    // Process transactions and output stored deposits of a client.
+    ExportLedger,
+    // Process transactions and print balances and stored transactions of a client.
+    Account,
+    // Compare this binary with a previous build over a generated dataset.
+    BenchCompare,
+    // Output a synthetic dataset of transactions.
+    Generate,
+    // Serve the engine over gRPC.
+    Serve,
+    // Process transactions and output complete state of the engine.
+    ExportState,
+    // Output accounts of a state exported by export-state command.
+    ImportState,
+    // Process files appearing in a directory and keep the engine state between them.
+    Watch,
+}
+
+pub struct Options {
+    pub command: Command,
+    // The first of input files.
+    pub file_path: OsString,
+    // Input files processed one after another as one stream, with globs expanded. It's only the
+    // input file with commands that don't process transactions.
+    pub input_files: Vec<OsString>,
+    pub period: String,
+    pub period_report: Option<OsString>,
+    pub collections_report: Option<OsString>,
+    pub held_report: Option<OsString>,
+    pub audit_log: Option<OsString>,
+    pub event_log: Option<OsString>,
+    pub audit_chain: Option<OsString>,
+    // Sink of account changes, opened when processing starts.
+    pub cdc: Option<String>,
+    pub rejections: Option<OsString>,
+    pub run_report: Option<OsString>,
+    pub summary: bool,
+    pub progress: bool,
+    // Input file is mapped into memory instead of being read.
+    pub mmap: bool,
+    // Accounts output of a previous run which the engine starts from.
+    pub state_in: Option<OsString>,
+    pub held_interest_rate: Option<Amount>,
+    pub interest_report: Option<OsString>,
+    pub fee_schedule: Option<OsString>,
+    pub fee_report: Option<OsString>,
+    pub fraud_rules: Vec<FraudRule>,
+    pub fraud_report: Option<OsString>,
+    pub decimal_format: DecimalFormat,
+    pub clock: ClockKind,
+    pub archive_dir: Option<OsString>,
+    // Parsing of arguments rejects it without sled feature.
+    #[cfg_attr(not(feature = "sled"), allow(dead_code))]
+    pub history_dir: Option<OsString>,
+    // Parsing of arguments rejects it without sqlite feature.
+    #[cfg_attr(not(feature = "sqlite"), allow(dead_code))]
+    pub sqlite_store: Option<OsString>,
+    pub dormant_after: u64,
+    pub entry_limit: Option<EntryLimit>,
+    pub operators: Option<OsString>,
+    pub senior_threshold: Option<Amount>,
+    pub approval_threshold: Option<Amount>,
+    pub approval_expiry: u64,
+    pub checksum: Option<OsString>,
+    pub state_hash: Option<OsString>,
+    // Engine state written when processing stops, after all input or a shutdown.
+    pub checkpoint: Option<OsString>,
+    pub signing_key_file: Option<OsString>,
+    pub implementation: Option<OsString>,
+    pub export_dir: Option<OsString>,
+    pub workers: usize,
+    pub hot_clients: HashSet<u16>,
+    pub shard_report: Option<OsString>,
+    pub overflow_policy: OverflowPolicy,
+    pub precision_policy: PrecisionPolicy,
+    pub schema_policy: SchemaPolicy,
+    pub charged_back_policy: ChargedBackPolicy,
+    pub dispute_policy: DisputePolicy,
+    pub locked_policy: LockedPolicy,
+    pub limits: Limits,
+    pub alert_rules: Vec<AlertRule>,
+    // Notifiers are created when processing starts, alerts go to standard error if none is given.
+    pub notifiers: Vec<String>,
+    pub notify_retries: u32,
+    // Alerts which notifiers failed to take are appended here.
+    pub dead_letter: Option<OsString>,
+    pub coalesce_deposits: bool,
+    pub any_transaction_opens_account: bool,
+    pub check_invariants: bool,
+    pub amount_cache: bool,
+    pub dispute_withdrawals: bool,
+    pub max_rows_per_sec: Option<u32>,
+    pub nice: bool,
+    pub reorder: Option<Reorder>,
+    pub input_format: InputFormat,
+    // Threads parsing CSV input, transactions are still applied by one.
+    pub parse_threads: usize,
+    // Transactions are read from a database table instead of the input file if it's given.
+    pub input_db: Option<Database>,
+    pub input_table: String,
+    pub sequence_column: String,
+    pub output_format: OutputFormat,
+    pub lookup_tx: Option<u32>,
+    pub export_client: Option<u16>,
+    // Deposits with any status are exported if it's empty.
+    pub export_statuses: Vec<DepositedTransactionStatus>,
+    // Parsing of arguments requires --sqlite-store for it, which is rejected without sqlite feature.
+    #[cfg_attr(not(feature = "sqlite"), allow(dead_code))]
+    pub sql: Option<String>,
+    pub account_query: Option<AccountQuery>,
+    // Dataset of generate and bench-compare commands.
+    pub dataset: Dataset,
+    // Comparison is printed as markdown if no report file is given.
+    pub bench_report: Option<OsString>,
+    // Pending migrations are only reported, and processed accounts are printed as a diff against
+    // the state they continue from instead of being output.
+    pub dry_run: bool,
+    // Stores are copied into this directory before they are migrated.
+    pub backup_dir: Option<OsString>,
+    // Parsing of arguments rejects serve command without grpc feature.
+    #[cfg_attr(not(feature = "grpc"), allow(dead_code))]
+    pub listen: SocketAddr,
+    // Directory of processed files of watch command, inside the watched one by default.
+    pub processed_dir: Option<OsString>,
+}
+
+fn next_value<I>(args: &mut I, name: &str) -> anyhow::Result<OsString>
+where
+    I: Iterator<Item = OsString>,
+{
+    match args.next() {
+        None => Err(anyhow!("expected a value for {}", name)),
+        Some(value) => Ok(value),
+    }
+}
+
+fn next_string<I>(args: &mut I, name: &str) -> anyhow::Result<String>
+where
+    I: Iterator<Item = OsString>,
+{
+    match next_value(args, name)?.into_string() {
+        Err(_) => Err(anyhow!("value for {} is not valid unicode", name)),
+        Ok(value) => Ok(value),
+    }
+}
+
+fn next_number<I, T>(args: &mut I, name: &str) -> anyhow::Result<T>
+where
+    I: Iterator<Item = OsString>,
+    T: FromStr,
+{
+    match next_string(args, name)?.parse() {
+        Err(_) => Err(anyhow!("value for {} is not a number", name)),
+        Ok(value) => Ok(value),
+    }
+}
+
+// List of client ids separated by commas, for example `1,7,42`.
+fn next_clients<I>(args: &mut I, name: &str) -> anyhow::Result<HashSet<u16>>
+where
+    I: Iterator<Item = OsString>,
+{
+    next_string(args, name)?
+        .split(',')
+        .map(|client| {
+            client
+                .trim()
+                .parse()
+                .map_err(|_| anyhow!("value for {} is not a list of client ids", name))
+        })
+        .collect()
+}
+
+// List of transaction statuses separated by commas, for example `dispute,chargeback`.
+fn next_statuses<I>(args: &mut I, name: &str) -> anyhow::Result<Vec<DepositedTransactionStatus>>
+where
+    I: Iterator<Item = OsString>,
+{
+    next_string(args, name)?
+        .split(',')
+        .map(|status| status.trim().parse().map_err(|err: String| anyhow!(err)))
+        .collect()
+}
+
+// Transactions are read from standard input if no input file is given or it is `-`.
+pub const STDIN: &str = "-";
+
+// Address of the gRPC server if no other is given.
+const DEFAULT_LISTEN: &str = "127.0.0.1:50051";
+
+// If no period is given the input file name (without extension) is used as a run id, so for
+// example daily files are reported as separate periods.
+fn default_period(file_path: &OsString) -> String {
+    if file_path == STDIN {
+        return String::from("default");
+    }
+    match Path::new(file_path).file_stem() {
+        None => String::from("default"),
+        Some(stem) => stem.to_string_lossy().into_owned(),
+    }
+}
+
+// Patterns which aren't existing files are expanded into matching files in alphabetical order,
+// for shells which don't expand them or when a quoted pattern is given.
+fn expand_input_files(patterns: Vec<OsString>) -> anyhow::Result<Vec<OsString>> {
+    let mut input_files = Vec::new();
+    for pattern in patterns {
+        let glob = match pattern.to_str() {
+            Some(glob) if glob.contains(['*', '?', '[']) && !Path::new(glob).exists() => glob,
+            _ => {
+                input_files.push(pattern);
+                continue;
+            }
+        };
+        let matched = input_files.len();
+        for path in
+            glob::glob(glob).map_err(|err| anyhow!("invalid input pattern {}: {}", glob, err))?
+        {
+            input_files.push(path?.into_os_string());
+        }
+        if input_files.len() == matched {
+            bail!("no input files match {}", glob);
+        }
+    }
+    Ok(input_files)
+}
+
+pub fn parse_args<I>(args: I) -> anyhow::Result<Options>
+where
+    I: IntoIterator<Item = OsString>,
+{
+    let mut args = args.into_iter().peekable();
+    let mut command = Command::Process;
+    match args.peek().and_then(|arg| arg.to_str()) {
+        Some("trial-balance") => command = Command::TrialBalance,
+        Some("archive") => command = Command::Archive,
+        Some("verify-output") => command = Command::VerifyOutput,
+        Some("verify") => command = Command::Verify,
+        Some("conformance") => command = Command::Conformance,
+        Some("lookup") => command = Command::Lookup,
+        Some("state-machine") => command = Command::StateMachine,
+        Some("replay") => command = Command::Replay,
+        Some("export-ledger") => command = Command::ExportLedger,
+        Some("account") => command = Command::Account,
+        Some("bench-compare") => command = Command::BenchCompare,
+        Some("generate") => command = Command::Generate,
+        Some("serve") => command = Command::Serve,
+        Some("export-state") => command = Command::ExportState,
+        Some("import-state") => command = Command::ImportState,
+        Some("watch") => command = Command::Watch,
+        Some("audit") => {
+            args.next();
+            command = match args.peek().and_then(|arg| arg.to_str()) {
+                Some("verify") => Command::AuditVerify,
+                _ => bail!("audit command requires verify"),
+            };
+        }
+        Some("store") => {
+            args.next();
+            command = match args.peek().and_then(|arg| arg.to_str()) {
+                Some("stats") => Command::StoreStats,
+                Some("compact") => Command::StoreCompact,
+                Some("sql") => Command::StoreSql,
+                Some("query") => Command::StoreQuery,
+                Some("migrate") => Command::StoreMigrate,
+                _ => bail!("store command requires stats, compact, sql, query or migrate"),
+            };
+        }
+        _ => {}
+    }
+    if command != Command::Process {
+        args.next();
+    }
+    // Options of a config file are parsed first, so options given on command line override them.
+    let mut args: Vec<OsString> = args.collect();
+    if let Some(index) = args.iter().position(|arg| arg == "--config") {
+        if index + 1 == args.len() {
+            bail!("expected a value for --config");
+        }
+        let config_file = args.remove(index + 1);
+        args.remove(index);
+        let mut config_args = Config::load(&config_file)?.to_args();
+        config_args.append(&mut args);
+        args = config_args;
+    }
+    let mut args = args.into_iter();
+    let is_query_command = command == Command::StoreSql || command == Command::StoreQuery;
+    let is_store_command = command == Command::StoreStats
+        || command == Command::StoreCompact
+        || command == Command::StoreMigrate
+        || is_query_command;
+
+    let mut file_path = None;
+    let mut more_files = Vec::new();
+    let mut period = None;
+    let mut period_report = None;
+    let mut collections_report = None;
+    let mut held_report = None;
+    let mut audit_log = None;
+    let mut event_log = None;
+    let mut audit_chain = None;
+    let mut cdc = None;
+    let mut emit_changes = None;
+    let mut rejections = None;
+    let mut run_report = None;
+    let mut summary = false;
+    let mut progress = false;
+    let mut mmap = false;
+    let mut state_in = None;
+    let mut held_interest_rate = None;
+    let mut interest_report = None;
+    let mut fee_schedule = None;
+    let mut fee_report = None;
+    let mut fraud_rules = Vec::new();
+    let mut fraud_report = None;
+    let mut decimal_format = DecimalFormat::default();
+    let mut clock = ClockKind::Replay;
+    let mut replay_speed: Option<f64> = None;
+    let mut archive_dir = None;
+    let mut sqlite_store = None;
+    let mut history_dir = None;
+    let mut dormant_after = DEFAULT_DORMANT_AFTER;
+    let mut entry_limit = None;
+    let mut operators = None;
+    let mut senior_threshold = None;
+    let mut approval_threshold = None;
+    let mut approval_expiry = DEFAULT_APPROVAL_EXPIRY;
+    let mut checksum = None;
+    let mut state_hash = None;
+    let mut checkpoint = None;
+    let mut signing_key_file = None;
+    let mut implementation = None;
+    let mut export_dir = None;
+    let mut workers = 1;
+    let mut hot_clients = HashSet::new();
+    let mut shard_report = None;
+    let mut overflow_policy = OverflowPolicy::Reject;
+    let mut precision_policy = PrecisionPolicy::Round;
+    let mut schema_policy = SchemaPolicy::Lenient;
+    let mut charged_back_policy = ChargedBackPolicy::Reject;
+    let mut dispute_policy = DisputePolicy::default();
+    let mut locked_policy = LockedPolicy::default();
+    let mut limits = Limits::default();
+    let mut alert_rules = Vec::new();
+    let mut notifiers = Vec::new();
+    let mut notify_retries = None;
+    let mut dead_letter = None;
+    let mut coalesce_deposits = false;
+    let mut any_transaction_opens_account = false;
+    let mut check_invariants = false;
+    let mut amount_cache = false;
+    let mut dispute_withdrawals = false;
+    let mut max_rows_per_sec = None;
+    let mut nice = false;
+    let mut reorder = None;
+    let mut output_format = OutputFormat::Csv;
+    let mut lookup_tx = None;
+    let mut export_client = None;
+    let mut export_statuses = Vec::new();
+    let mut input_format = None;
+    let mut parse_threads = 1;
+    let mut input_db = None;
+    let mut input_table = None;
+    let mut sequence_column = None;
+    let mut dataset = Dataset::new(bench::DEFAULT_ROWS);
+    let mut bench_report = None;
+    let mut dry_run = false;
+    let mut backup_dir = None;
+    let mut listen = None;
+    let mut processed_dir = None;
+
+    while let Some(arg) = args.next() {
+        match arg.to_str() {
+            Some("--period") => period = Some(next_string(&mut args, "--period")?),
+            Some("--period-report") => {
+                period_report = Some(next_value(&mut args, "--period-report")?)
+            }
+            Some("--collections-report") => {
+                collections_report = Some(next_value(&mut args, "--collections-report")?)
+            }
+            Some("--held-report") => held_report = Some(next_value(&mut args, "--held-report")?),
+            Some("--audit-log") => audit_log = Some(next_value(&mut args, "--audit-log")?),
+            Some("--event-log") => event_log = Some(next_value(&mut args, "--event-log")?),
+            Some("--audit-chain") => audit_chain = Some(next_value(&mut args, "--audit-chain")?),
+            Some("--cdc") => cdc = Some(next_string(&mut args, "--cdc")?),
+            Some("--emit-changes") => {
+                emit_changes = Some(next_string(&mut args, "--emit-changes")?)
+            }
+            Some("--rejections") => rejections = Some(next_value(&mut args, "--rejections")?),
+            Some("--run-report") => run_report = Some(next_value(&mut args, "--run-report")?),
+            Some("--summary") => summary = true,
+            Some("--progress") => progress = true,
+            Some("--mmap") => mmap = true,
+            Some("--state-in") => state_in = Some(next_value(&mut args, "--state-in")?),
+            Some("--held-interest-rate") => {
+                held_interest_rate = Some(next_number(&mut args, "--held-interest-rate")?)
+            }
+            Some("--interest-report") => {
+                interest_report = Some(next_value(&mut args, "--interest-report")?)
+            }
+            Some("--fee-schedule") => fee_schedule = Some(next_value(&mut args, "--fee-schedule")?),
+            Some("--fee-report") => fee_report = Some(next_value(&mut args, "--fee-report")?),
+            Some("--fraud-rule") => {
+                fraud_rules.push(next_string(&mut args, "--fraud-rule")?.parse()?)
+            }
+            Some("--fraud-report") => fraud_report = Some(next_value(&mut args, "--fraud-report")?),
+            Some("--output-scale") => {
+                decimal_format.scale = next_number(&mut args, "--output-scale")?
+            }
+            Some("--trim-trailing-zeros") => decimal_format.trim_trailing_zeros = true,
+            Some("--output-rounding") => {
+                decimal_format.rounding =
+                    match next_string(&mut args, "--output-rounding")?.parse::<Rounding>() {
+                        Err(err) => bail!(err),
+                        Ok(rounding) => rounding,
+                    }
+            }
+            Some("--clock") => {
+                clock = match next_string(&mut args, "--clock")?.parse::<ClockKind>() {
+                    Err(err) => bail!(err),
+                    Ok(clock) => clock,
+                }
+            }
+            Some("--replay-speed") => {
+                replay_speed = Some(next_number(&mut args, "--replay-speed")?)
+            }
+            Some("--archive-dir") => archive_dir = Some(next_value(&mut args, "--archive-dir")?),
+            Some("--history-dir") => history_dir = Some(next_value(&mut args, "--history-dir")?),
+            Some("--sqlite-store") => sqlite_store = Some(next_value(&mut args, "--sqlite-store")?),
+            Some("--dormant-after") => dormant_after = next_number(&mut args, "--dormant-after")?,
+            Some("--entry-limit") => {
+                entry_limit = match next_string(&mut args, "--entry-limit")?.parse::<EntryLimit>() {
+                    Err(err) => bail!(err),
+                    Ok(entry_limit) => Some(entry_limit),
+                }
+            }
+            Some("--operators") => operators = Some(next_value(&mut args, "--operators")?),
+            Some("--senior-threshold") => {
+                senior_threshold = Some(next_number(&mut args, "--senior-threshold")?)
+            }
+            Some("--approval-threshold") => {
+                approval_threshold = Some(next_number(&mut args, "--approval-threshold")?)
+            }
+            Some("--approval-expiry") => {
+                approval_expiry = next_number(&mut args, "--approval-expiry")?
+            }
+            Some("--checksum") => checksum = Some(next_value(&mut args, "--checksum")?),
+            Some("--state-hash") => state_hash = Some(next_value(&mut args, "--state-hash")?),
+            Some("--checkpoint") => checkpoint = Some(next_value(&mut args, "--checkpoint")?),
+            Some("--signing-key-file") => {
+                signing_key_file = Some(next_value(&mut args, "--signing-key-file")?)
+            }
+            Some("--implementation") => {
+                implementation = Some(next_value(&mut args, "--implementation")?)
+            }
+            Some("--export") => export_dir = Some(next_value(&mut args, "--export")?),
+            Some("--rows") => dataset.rows = next_number(&mut args, "--rows")?,
+            Some("--clients") => dataset.clients = next_number(&mut args, "--clients")?,
+            Some("--dispute-ratio") => {
+                dataset.dispute_ratio = next_number(&mut args, "--dispute-ratio")?
+            }
+            Some("--chargeback-ratio") => {
+                dataset.chargeback_ratio = next_number(&mut args, "--chargeback-ratio")?
+            }
+            Some("--report") => bench_report = Some(next_value(&mut args, "--report")?),
+            Some("--processed-dir") => {
+                processed_dir = Some(next_value(&mut args, "--processed-dir")?)
+            }
+            Some("--listen") => {
+                listen = match next_string(&mut args, "--listen")?.parse::<SocketAddr>() {
+                    Err(_) => bail!("value for --listen is not an address"),
+                    Ok(listen) => Some(listen),
+                }
+            }
+            Some("--dry-run") => dry_run = true,
+            Some("--backup") => backup_dir = Some(next_value(&mut args, "--backup")?),
+            Some("--parse-threads") => parse_threads = next_number(&mut args, "--parse-threads")?,
+            Some("--workers") => workers = next_number(&mut args, "--workers")?,
+            Some("--hot-clients") => hot_clients = next_clients(&mut args, "--hot-clients")?,
+            Some("--shard-report") => shard_report = Some(next_value(&mut args, "--shard-report")?),
+            Some("--coalesce-deposits") => coalesce_deposits = true,
+            Some("--any-transaction-opens-account") => any_transaction_opens_account = true,
+            Some("--check-invariants") => check_invariants = true,
+            Some("--amount-cache") => amount_cache = true,
+            Some("--dispute-withdrawals") => dispute_withdrawals = true,
+            Some("--max-rows-per-sec") => {
+                max_rows_per_sec = Some(next_number(&mut args, "--max-rows-per-sec")?)
+            }
+            Some("--nice") => nice = true,
+            Some("--reorder") => {
+                reorder = match next_string(&mut args, "--reorder")?.parse::<Reorder>() {
+                    Err(err) => bail!(err),
+                    Ok(reorder) => Some(reorder),
+                }
+            }
+            Some("--tx") => lookup_tx = Some(next_number(&mut args, "--tx")?),
+            Some("--client") => export_client = Some(next_number(&mut args, "--client")?),
+            Some("--status") => export_statuses = next_statuses(&mut args, "--status")?,
+            Some("--input-format") => {
+                input_format =
+                    match next_string(&mut args, "--input-format")?.parse::<InputFormat>() {
+                        Err(err) => bail!(err),
+                        Ok(input_format) => Some(input_format),
+                    }
+            }
+            Some("--input-db") => {
+                input_db = match next_string(&mut args, "--input-db")?.parse::<Database>() {
+                    Err(err) => bail!(err),
+                    Ok(database) => Some(database),
+                }
+            }
+            Some("--input-table") => input_table = Some(next_string(&mut args, "--input-table")?),
+            Some("--sequence-column") => {
+                sequence_column = Some(next_string(&mut args, "--sequence-column")?)
+            }
+            Some("--output-format") => {
+                output_format =
+                    match next_string(&mut args, "--output-format")?.parse::<OutputFormat>() {
+                        Err(err) => bail!(err),
+                        Ok(output_format) => output_format,
+                    }
+            }
+            Some("--overflow-policy") => {
+                overflow_policy =
+                    match next_string(&mut args, "--overflow-policy")?.parse::<OverflowPolicy>() {
+                        Err(err) => bail!(err),
+                        Ok(overflow_policy) => overflow_policy,
+                    }
+            }
+            Some("--alert") => alert_rules.push(next_string(&mut args, "--alert")?.parse()?),
+            Some("--notify") => notifiers.push(next_string(&mut args, "--notify")?),
+            Some("--notify-retries") => {
+                notify_retries = Some(next_number(&mut args, "--notify-retries")?)
+            }
+            Some("--dead-letter") => dead_letter = Some(next_value(&mut args, "--dead-letter")?),
+            Some("--precision-policy") => {
+                precision_policy =
+                    match next_string(&mut args, "--precision-policy")?.parse::<PrecisionPolicy>() {
+                        Err(err) => bail!(err),
+                        Ok(precision_policy) => precision_policy,
+                    }
+            }
+            Some("--schema-policy") => {
+                schema_policy =
+                    match next_string(&mut args, "--schema-policy")?.parse::<SchemaPolicy>() {
+                        Err(err) => bail!(err),
+                        Ok(schema_policy) => schema_policy,
+                    }
+            }
+            Some("--charged-back-disputes") => {
+                charged_back_policy = match next_string(&mut args, "--charged-back-disputes")?
+                    .parse::<ChargedBackPolicy>()
+                {
+                    Err(err) => bail!(err),
+                    Ok(charged_back_policy) => charged_back_policy,
+                }
+            }
+            Some("--dispute-policy") => {
+                dispute_policy =
+                    match next_string(&mut args, "--dispute-policy")?.parse::<DisputePolicy>() {
+                        Err(err) => bail!(err),
+                        Ok(dispute_policy) => dispute_policy,
+                    }
+            }
+            Some("--locked-policy") => {
+                locked_policy =
+                    match next_string(&mut args, "--locked-policy")?.parse::<LockedPolicy>() {
+                        Err(err) => bail!(err),
+                        Ok(locked_policy) => locked_policy,
+                    }
+            }
+            Some("--limits") => {
+                limits = match next_string(&mut args, "--limits")?.parse::<Limits>() {
+                    Err(err) => bail!(err),
+                    Ok(limits) => limits,
+                }
+            }
+            Some("--config") => bail!("--config may be given only once"),
+            Some(flag) if flag.starts_with("--") => {
+                bail!("unknown option: {}", flag)
+            }
+            _ if file_path.is_none() => file_path = Some(arg),
+            _ => more_files.push(arg),
+        }
+    }
+
+    // A file of changes is a change stream with a file sink.
+    if let Some(path) = emit_changes {
+        if cdc.is_some() {
+            bail!("--cdc and --emit-changes can not be used together");
+        }
+        cdc = Some(format!("file:{}", path));
+    }
+    let reads_transactions = matches!(
+        command,
+        Command::Process
+            | Command::TrialBalance
+            | Command::Archive
+            | Command::Lookup
+            | Command::ExportLedger
+            | Command::Account
+            | Command::ExportState
+            | Command::Verify
+    );
+    if input_db.is_some() && !reads_transactions {
+        bail!("--input-db is supported only for commands processing transactions");
+    }
+    if cdc.is_some() && !reads_transactions {
+        bail!("--cdc is supported only for commands processing transactions");
+    }
+    if audit_chain.is_some() && !reads_transactions {
+        bail!("--audit-chain is supported only for commands processing transactions");
+    }
+    if run_report.is_some() && !reads_transactions {
+        bail!("--run-report is supported only for commands processing transactions");
+    }
+    if summary && !reads_transactions {
+        bail!("--summary is supported only for commands processing transactions");
+    }
+    if state_in.is_some() && !reads_transactions && command != Command::Serve {
+        bail!("--state-in is supported only for commands processing transactions and serve");
+    }
+    if input_db.is_none() && (input_table.is_some() || sequence_column.is_some()) {
+        bail!("--input-table and --sequence-column require --input-db");
+    }
+    if input_db.is_some() && input_format.is_some() {
+        bail!("--input-format is not supported with --input-db");
+    }
+    // Store queries are given instead of an input file.
+    let query = match file_path
+        .take_if(|_| is_query_command)
+        .map(OsString::into_string)
+    {
+        Some(Err(_)) => bail!("query is not valid unicode"),
+        Some(Ok(query)) => query,
+        None if command == Command::StoreSql => bail!("store sql command requires a query"),
+        None => String::new(),
+    };
+    let sql = (command == Command::StoreSql).then(|| query.clone());
+    let account_query = match command {
+        Command::StoreQuery => Some(query.parse::<AccountQuery>().map_err(|err| anyhow!(err))?),
+        _ => None,
+    };
+    // Conformance vectors bring their own input files, store, state machine and generate commands
+    // don't read any.
+    let file_path = match file_path {
+        Some(_) if input_db.is_some() => bail!("--input-db can not be used with an input file"),
+        None if input_db.is_some() => OsString::new(),
+        None if command == Command::Conformance
+            || command == Command::StateMachine
+            || command == Command::Serve
+            || command == Command::Generate
+            || is_store_command =>
+        {
+            OsString::new()
+        }
+        Some(_) if is_store_command => bail!("store commands don't take an input file"),
+        Some(_)
+            if command == Command::StateMachine
+                || command == Command::Serve
+                || command == Command::Generate =>
+        {
+            bail!("state-machine, serve and generate commands don't take an input file")
+        }
+        None if command == Command::VerifyOutput
+            || command == Command::AuditVerify
+            || command == Command::Watch
+            || command == Command::Replay
+            || command == Command::BenchCompare
+            || command == Command::ImportState =>
+        {
+            bail!("expected 1 argument, but none given")
+        }
+        None => OsString::from(STDIN),
+        Some(file_path) => file_path,
+    };
+    // Only commands processing transactions take several input files.
+    let input_files = if reads_transactions && input_db.is_none() && file_path != STDIN {
+        let mut patterns = vec![file_path.clone()];
+        patterns.append(&mut more_files);
+        expand_input_files(patterns)?
+    } else {
+        vec![file_path.clone()]
+    };
+    if let Some(arg) = more_files.first() {
+        bail!("unexpected argument: {:?}", arg);
+    }
+    if input_files.len() > 1 && input_files.iter().any(|input_file| input_file == STDIN) {
+        bail!("standard input can not be one of several input files");
+    }
+    let file_path = input_files[0].clone();
+    let input_table = input_table.unwrap_or_else(|| db_input::DEFAULT_TABLE.to_string());
+    let sequence_column =
+        sequence_column.unwrap_or_else(|| db_input::DEFAULT_SEQUENCE_COLUMN.to_string());
+    // Transactions of a database are reported under the table name.
+    let period = period.unwrap_or_else(|| match input_db {
+        Some(_) => input_table.clone(),
+        None => default_period(&file_path),
+    });
+    if interest_report.is_some() && held_interest_rate.is_none() {
+        bail!("--interest-report requires --held-interest-rate");
+    }
+    if fraud_report.is_some() && fraud_rules.is_empty() {
+        bail!("--fraud-report requires --fraud-rule");
+    }
+    if fee_report.is_some() && fee_schedule.is_none() {
+        bail!("--fee-report requires --fee-schedule");
+    }
+    if senior_threshold.is_some() && operators.is_none() {
+        bail!("--senior-threshold requires --operators");
+    }
+    if signing_key_file.is_some() && checksum.is_none() {
+        bail!("--signing-key-file requires --checksum");
+    }
+    if matches!(
+        command,
+        Command::TrialBalance | Command::ExportState | Command::Verify
+    ) && checksum.is_some()
+    {
+        bail!("--checksum is supported only for accounts output");
+    }
+    if output_format != OutputFormat::Csv
+        && command != Command::Process
+        && command != Command::Archive
+        && command != Command::Replay
+        && command != Command::ImportState
+        && command != Command::ExportLedger
+        && command != Command::StoreQuery
+    {
+        bail!("--output-format is supported only for accounts output and ledger export");
+    }
+    if !matches!(
+        command,
+        Command::Process | Command::Archive | Command::Replay | Command::ImportState
+    ) && state_hash.is_some()
+    {
+        bail!("--state-hash is supported only for accounts output");
+    }
+    if command == Command::VerifyOutput && checksum.is_none() {
+        bail!("verify-output command requires --checksum");
+    }
+    #[cfg(not(feature = "sqlite"))]
+    if sqlite_store.is_some() {
+        bail!("--sqlite-store requires sqlite feature");
+    }
+    #[cfg(not(feature = "sled"))]
+    if history_dir.is_some() {
+        bail!("--history-dir requires sled feature");
+    }
+    // Transactions are spilled into the history, which keeps all of them without a limit. Without
+    // a history directory they are spilled into a temporary file.
+    let spills =
+        entry_limit.map(|entry_limit| entry_limit.strategy) == Some(EvictionStrategy::Spill);
+    if !spills && entry_limit.is_some() && history_dir.is_some() {
+        bail!("only spill strategy of --entry-limit is supported with --history-dir");
+    }
+    if matches!(
+        command,
+        Command::Lookup | Command::ExportLedger | Command::Account
+    ) && (history_dir.is_some() || spills)
+    {
+        bail!(
+            "lookup, export-ledger and account commands are not supported with --history-dir or \
+             spilling"
+        );
+    }
+    if command == Command::ExportState && spills && history_dir.is_none() {
+        bail!("export-state command requires --history-dir with spill strategy of --entry-limit");
+    }
+    if checkpoint.is_some()
+        && !matches!(
+            command,
+            Command::Process | Command::Archive | Command::Serve | Command::Watch
+        )
+    {
+        bail!("--checkpoint is supported only for process, archive, serve and watch commands");
+    }
+    if checkpoint.is_some() && spills && history_dir.is_none() {
+        bail!("--checkpoint requires --history-dir with spill strategy of --entry-limit");
+    }
+    if command == Command::ExportLedger && export_client.is_none() {
+        bail!("export-ledger command requires --client");
+    }
+    if command == Command::Account && export_client.is_none() {
+        bail!("account command requires --client");
+    }
+    if command != Command::ExportLedger && command != Command::Account && export_client.is_some() {
+        bail!("--client is supported only for export-ledger and account commands");
+    }
+    if command != Command::ExportLedger && !export_statuses.is_empty() {
+        bail!("--status is supported only for export-ledger command");
+    }
+    if archive_dir.is_some() && sqlite_store.is_some() {
+        bail!("--archive-dir and --sqlite-store can not be used together");
+    }
+    let has_store = archive_dir.is_some() || sqlite_store.is_some();
+    // Verified input doesn't change any state kept after the run.
+    if command == Command::Verify
+        && (has_store || history_dir.is_some() || event_log.is_some() || cdc.is_some())
+    {
+        bail!("verify command is not supported with a store, --history-dir, --event-log or --cdc");
+    }
+    if command == Command::Verify && workers > 1 {
+        bail!("verify command is not supported with more than 1 worker");
+    }
+    if command == Command::Archive && !has_store {
+        bail!("archive command requires --archive-dir or --sqlite-store");
+    }
+    // Transaction history is versioned as well, so it can be migrated without an account store.
+    if command == Command::StoreMigrate {
+        if !has_store && history_dir.is_none() {
+            bail!("store migrate command requires --archive-dir, --sqlite-store or --history-dir");
+        }
+    } else if is_store_command && !has_store {
+        bail!("store commands require --archive-dir or --sqlite-store");
+    }
+    if command != Command::StoreMigrate && backup_dir.is_some() {
+        bail!("--backup is supported only for store migrate command");
+    }
+    if dry_run && command != Command::StoreMigrate && command != Command::Process {
+        bail!("--dry-run is supported only for store migrate command and processing");
+    }
+    // A dry run of processing doesn't change any state kept after the run.
+    if dry_run && command == Command::Process {
+        if has_store || history_dir.is_some() || event_log.is_some() || cdc.is_some() {
+            bail!("--dry-run is not supported with a store, --history-dir, --event-log or --cdc");
+        }
+        if checksum.is_some() || output_format != OutputFormat::Csv {
+            bail!("--checksum and --output-format are not supported with --dry-run");
+        }
+    }
+    if command == Command::StoreSql && sqlite_store.is_none() {
+        bail!("store sql command requires --sqlite-store");
+    }
+    if command == Command::BenchCompare && file_path == STDIN {
+        bail!("bench-compare command requires a baseline binary");
+    }
+    if command != Command::BenchCompare && bench_report.is_some() {
+        bail!("--report is supported only for bench-compare command");
+    }
+    if command != Command::BenchCompare
+        && command != Command::Generate
+        && dataset != Dataset::new(bench::DEFAULT_ROWS)
+    {
+        bail!(
+            "--rows, --clients, --dispute-ratio and --chargeback-ratio are supported only for \
+             generate and bench-compare commands"
+        );
+    }
+    if dataset.rows == 0 {
+        bail!("--rows must be at least 1");
+    }
+    // Client ids are 16-bit.
+    if dataset.clients == 0 || dataset.clients > u64::from(u16::MAX) {
+        bail!("--clients must be between 1 and {}", u16::MAX);
+    }
+    // Every dispute is settled by another row.
+    if !(0.0..=0.5).contains(&dataset.dispute_ratio) {
+        bail!("--dispute-ratio must be between 0 and 0.5");
+    }
+    if !(0.0..=1.0).contains(&dataset.chargeback_ratio) {
+        bail!("--chargeback-ratio must be between 0 and 1");
+    }
+    if command == Command::ImportState && file_path == STDIN {
+        bail!("import-state command requires a state file");
+    }
+    if command == Command::Replay && (file_path == STDIN || event_log.is_some()) {
+        bail!("replay command reads an event log file and doesn't write one");
+    }
+    #[cfg(not(feature = "grpc"))]
+    if command == Command::Serve {
+        bail!("serve command requires grpc feature");
+    }
+    if command != Command::Serve && listen.is_some() {
+        bail!("--listen is supported only for serve command");
+    }
+    // All streams share one engine.
+    if command == Command::Serve && workers > 1 {
+        bail!("serve command is not supported with more than 1 worker");
+    }
+    if command == Command::Watch && (workers > 1 || parse_threads > 1) {
+        bail!("watch command is not supported with more than 1 worker or parse thread");
+    }
+    if command != Command::Watch && processed_dir.is_some() {
+        bail!("--processed-dir is supported only for watch command");
+    }
+    if command == Command::Lookup && lookup_tx.is_none() {
+        bail!("lookup command requires --tx");
+    }
+    if command != Command::Lookup && lookup_tx.is_some() {
+        bail!("--tx is supported only for lookup command");
+    }
+    // Standard input is read as CSV unless a format is given.
+    let input_format = match input_format {
+        Some(input_format) => input_format,
+        None if file_path == STDIN => InputFormat::Csv,
+        None => {
+            let input_format = InputFormat::from_path(&file_path);
+            if input_files
+                .iter()
+                .any(|input_file| InputFormat::from_path(input_file) != input_format)
+            {
+                bail!("input files have different formats, --input-format should be given");
+            }
+            input_format
+        }
+    };
+    #[cfg(feature = "parquet")]
+    if input_format == InputFormat::Parquet && file_path == STDIN {
+        bail!("Parquet input can not be read from standard input");
+    }
+    #[cfg(feature = "parquet")]
+    if input_format == InputFormat::Parquet && progress {
+        bail!("--progress is not supported for Parquet input");
+    }
+    // Size of standard input or a database table isn't known up front.
+    if progress && (!reads_transactions || input_db.is_some() || file_path == STDIN) {
+        bail!("--progress is supported only for commands processing an input file");
+    }
+    #[cfg(feature = "parquet")]
+    if input_format == InputFormat::Parquet && mmap {
+        bail!("--mmap is not supported for Parquet input");
+    }
+    if mmap && (!reads_transactions || input_db.is_some() || file_path == STDIN) {
+        bail!("--mmap is supported only for commands processing an input file");
+    }
+    if max_rows_per_sec == Some(0) {
+        bail!("--max-rows-per-sec must be at least 1");
+    }
+    if !notifiers.is_empty() && alert_rules.is_empty() {
+        bail!("--notify requires --alert");
+    }
+    if notify_retries.is_some() && alert_rules.is_empty() {
+        bail!("--notify-retries requires --alert");
+    }
+    if dead_letter.is_some() && alert_rules.is_empty() {
+        bail!("--dead-letter requires --alert");
+    }
+    if workers == 0 {
+        bail!("--workers must be at least 1");
+    }
+    if parse_threads == 0 {
+        bail!("--parse-threads must be at least 1");
+    }
+    if parse_threads > 1 {
+        if workers > 1 {
+            bail!("--parse-threads is not supported with more than 1 worker");
+        }
+        if input_format != InputFormat::Csv || input_db.is_some() {
+            bail!("--parse-threads is supported only for CSV input");
+        }
+    }
+    if workers > 1 {
+        if audit_log.is_some() {
+            bail!("--audit-log is not supported with more than 1 worker");
+        }
+        if archive_dir.is_some() {
+            bail!("--archive-dir is not supported with more than 1 worker");
+        }
+        if sqlite_store.is_some() {
+            bail!("--sqlite-store is not supported with more than 1 worker");
+        }
+        if history_dir.is_some() {
+            bail!("--history-dir is not supported with more than 1 worker");
+        }
+        if event_log.is_some() {
+            bail!("--event-log is not supported with more than 1 worker");
+        }
+        if audit_chain.is_some() {
+            bail!("--audit-chain is not supported with more than 1 worker");
+        }
+        if cdc.is_some() {
+            bail!("--cdc is not supported with more than 1 worker");
+        }
+        if rejections.is_some() {
+            bail!("--rejections is not supported with more than 1 worker");
+        }
+        if state_in.is_some() {
+            bail!("--state-in is not supported with more than 1 worker");
+        }
+        if !alert_rules.is_empty() {
+            bail!("--alert is not supported with more than 1 worker");
+        }
+        if input_format != InputFormat::Csv || input_db.is_some() {
+            bail!("only CSV input is supported with more than 1 worker");
+        }
+    } else {
+        if !hot_clients.is_empty() {
+            bail!("--hot-clients requires more than 1 worker");
+        }
+        if shard_report.is_some() {
+            bail!("--shard-report requires more than 1 worker");
+        }
+    }
+    if let Some(speed) = replay_speed {
+        if clock != ClockKind::Replay {
+            bail!("--replay-speed requires replay clock");
+        }
+        if !(speed >= MIN_REPLAY_SPEED && speed.is_finite()) {
+            bail!("--replay-speed must be at least {}", MIN_REPLAY_SPEED);
+        }
+        clock = ClockKind::PacedReplay(speed);
+    }
+
+    Ok(Options {
+        command,
+        file_path,
+        input_files,
+        period,
+        period_report,
+        collections_report,
+        held_report,
+        audit_log,
+        event_log,
+        audit_chain,
+        cdc,
+        rejections,
+        run_report,
+        summary,
+        progress,
+        mmap,
+        state_in,
+        held_interest_rate,
+        interest_report,
+        fee_schedule,
+        fee_report,
+        fraud_rules,
+        fraud_report,
+        decimal_format,
+        clock,
+        archive_dir,
+        history_dir,
+        sqlite_store,
+        dormant_after,
+        entry_limit,
+        operators,
+        senior_threshold,
+        approval_threshold,
+        approval_expiry,
+        checksum,
+        state_hash,
+        checkpoint,
+        signing_key_file,
+        implementation,
+        export_dir,
+        workers,
+        hot_clients,
+        shard_report,
+        overflow_policy,
+        precision_policy,
+        schema_policy,
+        charged_back_policy,
+        dispute_policy,
+        locked_policy,
+        limits,
+        alert_rules,
+        notifiers,
+        notify_retries: notify_retries.unwrap_or(0),
+        dead_letter,
+        coalesce_deposits,
+        any_transaction_opens_account,
+        check_invariants,
+        amount_cache,
+        dispute_withdrawals,
+        max_rows_per_sec,
+        nice,
+        reorder,
+        input_format,
+        parse_threads,
+        input_db,
+        input_table,
+        sequence_column,
+        output_format,
+        lookup_tx,
+        export_client,
+        export_statuses,
+        sql,
+        account_query,
+        dataset,
+        bench_report,
+        dry_run,
+        backup_dir,
+        listen: match listen {
+            Some(listen) => listen,
+            None => DEFAULT_LISTEN.parse()?,
+        },
+        processed_dir,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use coding_test::reorder::ReorderKey;
+    use std::fs;
+
+    fn args(args: &[&str]) -> Vec<OsString> {
+        args.iter().map(OsString::from).collect()
+    }
+
+    #[test]
+    fn test_parse_file_path() {
+        let options = parse_args(args(&["transactions_1.csv"])).unwrap();
+        assert_eq!(options.command, Command::Process);
+        assert_eq!(options.file_path, "transactions_1.csv");
+        assert_eq!(options.period, "transactions_1");
+        assert!(options.period_report.is_none());
+    }
+
+    #[test]
+    fn test_parse_period_options() {
+        let options = parse_args(args(&[
+            "--period",
+            "2022-05",
+            "transactions_1.csv",
+            "--period-report",
+            "periods.csv",
+        ]))
+        .unwrap();
+        assert_eq!(options.file_path, "transactions_1.csv");
+        assert_eq!(options.period, "2022-05");
+        assert_eq!(options.period_report.unwrap(), "periods.csv");
+    }
+
+    #[test]
+    fn test_parse_report_options() {
+        let options = parse_args(args(&[
+            "transactions_1.csv",
+            "--collections-report",
+            "collections.csv",
+            "--held-report",
+            "held.csv",
+            "--audit-log",
+            "audit.csv",
+        ]))
+        .unwrap();
+        assert_eq!(options.collections_report.unwrap(), "collections.csv");
+        assert_eq!(options.held_report.unwrap(), "held.csv");
+        assert_eq!(options.audit_log.unwrap(), "audit.csv");
+    }
+
+    #[test]
+    fn test_parse_interest_options() {
+        let options = parse_args(args(&[
+            "transactions_1.csv",
+            "--held-interest-rate",
+            "0.025",
+            "--interest-report",
+            "interest.csv",
+        ]))
+        .unwrap();
+        assert_eq!(
+            options.held_interest_rate.unwrap(),
+            Amount::from_str("0.025").unwrap()
+        );
+        assert_eq!(options.interest_report.unwrap(), "interest.csv");
+    }
+
+    #[test]
+    fn test_parse_fee_options() {
+        let options = parse_args(args(&[
+            "transactions_1.csv",
+            "--fee-schedule",
+            "fees.csv",
+            "--fee-report",
+            "fee_report.csv",
+        ]))
+        .unwrap();
+        assert_eq!(options.fee_schedule.unwrap(), "fees.csv");
+        assert_eq!(options.fee_report.unwrap(), "fee_report.csv");
+
+        assert!(parse_args(args(&["transactions_1.csv", "--fee-report", "f.csv"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_fraud_options() {
+        let options = parse_args(args(&[
+            "transactions_1.csv",
+            "--fraud-rule",
+            "withdrawals:3:10",
+            "--fraud-rule",
+            "deposit-withdrawal",
+            "--fraud-report",
+            "fraud.csv",
+        ]))
+        .unwrap();
+        assert_eq!(
+            options.fraud_rules,
+            vec![
+                FraudRule::Withdrawals { max: 3, window: 10 },
+                FraudRule::DepositWithdrawal
+            ]
+        );
+        assert_eq!(options.fraud_report.unwrap(), "fraud.csv");
+
+        assert!(parse_args(args(&["transactions_1.csv", "--fraud-rule", "velocity"])).is_err());
+        assert!(parse_args(args(&["transactions_1.csv", "--fraud-report", "fraud.csv"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_output_format_options() {
+        let options = parse_args(args(&["transactions_1.csv"])).unwrap();
+        assert_eq!(options.decimal_format, DecimalFormat::default());
+
+        let options = parse_args(args(&[
+            "transactions_1.csv",
+            "--output-scale",
+            "2",
+            "--trim-trailing-zeros",
+            "--output-rounding",
+            "half-even",
+        ]))
+        .unwrap();
+        assert_eq!(options.decimal_format.scale, 2);
+        assert!(options.decimal_format.trim_trailing_zeros);
+        assert_eq!(options.decimal_format.rounding, Rounding::HalfEven);
+        assert!(parse_args(args(&["transactions_1.csv", "--output-rounding", "up"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_clock_option() {
+        let options = parse_args(args(&["transactions_1.csv"])).unwrap();
+        assert_eq!(options.clock, ClockKind::Replay);
+
+        let options = parse_args(args(&["transactions_1.csv", "--clock", "system"])).unwrap();
+        assert_eq!(options.clock, ClockKind::System);
+
+        assert!(parse_args(args(&["transactions_1.csv", "--clock", "never"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_replay_speed() {
+        let options = parse_args(args(&["transactions_1.csv", "--replay-speed", "60"])).unwrap();
+        assert_eq!(options.clock, ClockKind::PacedReplay(60.0));
+
+        assert!(parse_args(args(&["transactions_1.csv", "--replay-speed", "0"])).is_err());
+        assert!(parse_args(args(&["transactions_1.csv", "--replay-speed", "1e-320"])).is_err());
+        assert!(parse_args(args(&[
+            "transactions_1.csv",
+            "--clock",
+            "system",
+            "--replay-speed",
+            "60"
+        ]))
+        .is_err());
+    }
+
+    #[test]
+    fn test_parse_interest_report_without_rate() {
+        assert!(parse_args(args(&["transactions_1.csv", "--interest-report", "i.csv"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_invalid_number() {
+        assert!(parse_args(args(&["transactions_1.csv", "--held-interest-rate", "x"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_trial_balance_command() {
+        let options = parse_args(args(&["trial-balance", "transactions_1.csv"])).unwrap();
+        assert_eq!(options.command, Command::TrialBalance);
+        assert_eq!(options.file_path, "transactions_1.csv");
+    }
+
+    #[test]
+    fn test_parse_archive_command() {
+        let options = parse_args(args(&[
+            "archive",
+            "transactions_1.csv",
+            "--archive-dir",
+            "archive",
+            "--dormant-after",
+            "3600",
+        ]))
+        .unwrap();
+        assert_eq!(options.command, Command::Archive);
+        assert_eq!(options.archive_dir, Some(OsString::from("archive")));
+        assert_eq!(options.dormant_after, 3600);
+
+        assert!(parse_args(args(&["archive", "transactions_1.csv"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_history_dir() {
+        let result = parse_args(args(&["transactions_1.csv", "--history-dir", "history"]));
+        #[cfg(feature = "sled")]
+        assert_eq!(result.unwrap().history_dir, Some(OsString::from("history")));
+        #[cfg(not(feature = "sled"))]
+        assert!(result.is_err());
+
+        assert!(parse_args(args(&[
+            "lookup",
+            "transactions_1.csv",
+            "--tx",
+            "1",
+            "--history-dir",
+            "history"
+        ]))
+        .is_err());
+    }
+
+    #[test]
+    fn test_parse_sqlite_store() {
+        let result = parse_args(args(&[
+            "archive",
+            "transactions_1.csv",
+            "--sqlite-store",
+            "accounts.db",
+        ]));
+        #[cfg(feature = "sqlite")]
+        assert_eq!(
+            result.unwrap().sqlite_store,
+            Some(OsString::from("accounts.db"))
+        );
+        #[cfg(not(feature = "sqlite"))]
+        assert!(result.is_err());
+
+        #[cfg(feature = "sqlite")]
+        {
+            let options = parse_args(args(&[
+                "store",
+                "sql",
+                "SELECT client FROM accounts",
+                "--sqlite-store",
+                "accounts.db",
+            ]))
+            .unwrap();
+            assert_eq!(options.command, Command::StoreSql);
+            assert_eq!(options.sql.as_deref(), Some("SELECT client FROM accounts"));
+        }
+        assert!(parse_args(args(&["store", "sql", "--sqlite-store", "accounts.db"])).is_err());
+
+        assert!(parse_args(args(&[
+            "store",
+            "stats",
+            "--archive-dir",
+            "archive",
+            "--sqlite-store",
+            "accounts.db"
+        ]))
+        .is_err());
+    }
+
+    #[test]
+    fn test_parse_authorization_options() {
+        let options = parse_args(args(&[
+            "admin_1.json",
+            "--operators",
+            "operators.csv",
+            "--senior-threshold",
+            "1000",
+        ]))
+        .unwrap();
+        assert_eq!(options.operators, Some(OsString::from("operators.csv")));
+        assert_eq!(options.senior_threshold, Some(Amount::from(1000)));
+
+        assert!(parse_args(args(&["admin_1.json", "--senior-threshold", "1000"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_approval_options() {
+        let options = parse_args(args(&["admin_1.json"])).unwrap();
+        assert_eq!(options.approval_threshold, None);
+        assert_eq!(options.approval_expiry, DEFAULT_APPROVAL_EXPIRY);
+
+        let options = parse_args(args(&[
+            "admin_1.json",
+            "--approval-threshold",
+            "5000",
+            "--approval-expiry",
+            "3600",
+        ]))
+        .unwrap();
+        assert_eq!(options.approval_threshold, Some(Amount::from(5000)));
+        assert_eq!(options.approval_expiry, 3600);
+    }
+
+    #[test]
+    fn test_parse_checksum_options() {
+        let options = parse_args(args(&[
+            "transactions_1.csv",
+            "--checksum",
+            "accounts.sha256",
+            "--signing-key-file",
+            "key",
+        ]))
+        .unwrap();
+        assert_eq!(options.checksum, Some(OsString::from("accounts.sha256")));
+        assert_eq!(options.signing_key_file, Some(OsString::from("key")));
+
+        assert!(parse_args(args(&["transactions_1.csv", "--signing-key-file", "key"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_state_hash_option() {
+        let options = parse_args(args(&[
+            "transactions_1.csv",
+            "--state-hash",
+            "state.sha256",
+            "--workers",
+            "4",
+        ]))
+        .unwrap();
+        assert_eq!(options.state_hash, Some(OsString::from("state.sha256")));
+        assert!(parse_args(args(&[
+            "replay",
+            "events.ndjson",
+            "--state-hash",
+            "state.sha256"
+        ]))
+        .is_ok());
+
+        assert!(parse_args(args(&[
+            "trial-balance",
+            "transactions_1.csv",
+            "--state-hash",
+            "state.sha256"
+        ]))
+        .is_err());
+    }
+
+    #[test]
+    fn test_parse_verify_output_command() {
+        let options = parse_args(args(&[
+            "verify-output",
+            "accounts.csv",
+            "--checksum",
+            "accounts.sha256",
+        ]))
+        .unwrap();
+        assert_eq!(options.command, Command::VerifyOutput);
+        assert_eq!(options.file_path, "accounts.csv");
+
+        assert!(parse_args(args(&["verify-output", "accounts.csv"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_audit_chain_options() {
+        let options = parse_args(args(&[
+            "transactions_1.csv",
+            "--audit-chain",
+            "chain.ndjson",
+        ]))
+        .unwrap();
+        assert_eq!(options.audit_chain, Some(OsString::from("chain.ndjson")));
+
+        let options = parse_args(args(&["audit", "verify", "chain.ndjson"])).unwrap();
+        assert_eq!(options.command, Command::AuditVerify);
+        assert_eq!(options.file_path, "chain.ndjson");
+
+        assert!(parse_args(args(&["audit", "verify"])).is_err());
+        assert!(parse_args(args(&["audit", "check", "chain.ndjson"])).is_err());
+        assert!(parse_args(args(&[
+            "audit",
+            "verify",
+            "chain.ndjson",
+            "--audit-chain",
+            "other.ndjson"
+        ]))
+        .is_err());
+        assert!(parse_args(args(&[
+            "transactions_1.csv",
+            "--audit-chain",
+            "chain.ndjson",
+            "--workers",
+            "2"
+        ]))
+        .is_err());
+    }
+
+    #[test]
+    fn test_parse_verify_command() {
+        let options = parse_args(args(&["verify", "transactions_1.csv"])).unwrap();
+        assert_eq!(options.command, Command::Verify);
+        assert_eq!(options.file_path, "transactions_1.csv");
+
+        assert!(parse_args(args(&[
+            "verify",
+            "transactions_1.csv",
+            "--archive-dir",
+            "a"
+        ]))
+        .is_err());
+        assert!(parse_args(args(&["verify", "transactions_1.csv", "--event-log", "e"])).is_err());
+        assert!(parse_args(args(&["verify", "transactions_1.csv", "--workers", "2"])).is_err());
+        assert!(parse_args(args(&[
+            "verify",
+            "transactions_1.csv",
+            "--checksum",
+            "accounts.sha256"
+        ]))
+        .is_err());
+    }
+
+    #[test]
+    fn test_parse_conformance_command() {
+        let options = parse_args(args(&["conformance"])).unwrap();
+        assert_eq!(options.command, Command::Conformance);
+        assert!(options.implementation.is_none());
+
+        let options = parse_args(args(&[
+            "conformance",
+            "--implementation",
+            "./sister",
+            "--export",
+            "out",
+        ]))
+        .unwrap();
+        assert_eq!(options.implementation, Some(OsString::from("./sister")));
+        assert_eq!(options.export_dir, Some(OsString::from("out")));
+    }
+
+    #[test]
+    fn test_parse_store_commands() {
+        let options = parse_args(args(&["store", "stats", "--archive-dir", "archive"])).unwrap();
+        assert_eq!(options.command, Command::StoreStats);
+        assert_eq!(options.archive_dir, Some(OsString::from("archive")));
+
+        let options = parse_args(args(&["store", "compact", "--archive-dir", "archive"])).unwrap();
+        assert_eq!(options.command, Command::StoreCompact);
+
+        let options = parse_args(args(&[
+            "store",
+            "query",
+            "locked=true",
+            "--archive-dir",
+            "archive",
+        ]))
+        .unwrap();
+        assert_eq!(options.command, Command::StoreQuery);
+        assert_eq!(options.account_query, "locked=true".parse().ok());
+        assert!(parse_args(args(&["store", "query", "--archive-dir", "archive"])).is_ok());
+        assert!(parse_args(args(&[
+            "store",
+            "query",
+            "locked",
+            "--archive-dir",
+            "archive"
+        ]))
+        .is_err());
+        assert!(parse_args(args(&[
+            "store",
+            "sql",
+            "SELECT * FROM accounts",
+            "--archive-dir",
+            "archive"
+        ]))
+        .is_err());
+
+        assert!(parse_args(args(&["store", "stats"])).is_err());
+        assert!(parse_args(args(&[
+            "store",
+            "stats",
+            "--archive-dir",
+            "archive",
+            "--dry-run"
+        ]))
+        .is_err());
+        assert!(parse_args(args(&["store", "--archive-dir", "archive"])).is_err());
+        assert!(parse_args(args(&[
+            "store",
+            "stats",
+            "transactions_1.csv",
+            "--archive-dir",
+            "archive"
+        ]))
+        .is_err());
+    }
+
+    #[test]
+    fn test_parse_serve_command() {
+        let options = parse_args(args(&["serve", "--listen", "0.0.0.0:9000"]));
+        #[cfg(feature = "grpc")]
+        {
+            let options = options.unwrap();
+            assert_eq!(options.command, Command::Serve);
+            assert_eq!(options.listen, "0.0.0.0:9000".parse().unwrap());
+            assert!(parse_args(args(&["serve", "--listen", "localhost"])).is_err());
+            assert!(parse_args(args(&["serve", "transactions_1.csv"])).is_err());
+        }
+        #[cfg(not(feature = "grpc"))]
+        assert!(options.is_err());
+        assert!(parse_args(args(&["transactions_1.csv", "--listen", "0.0.0.0:9000"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_run_report() {
+        let options =
+            parse_args(args(&["transactions_1.csv", "--run-report", "run.json"])).unwrap();
+        assert_eq!(options.run_report, Some(OsString::from("run.json")));
+        assert!(parse_args(args(&["state-machine", "--run-report", "run.json"])).is_err());
+
+        let options = parse_args(args(&["transactions_1.csv", "--summary"])).unwrap();
+        assert!(options.summary);
+        assert!(parse_args(args(&["state-machine", "--summary"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_progress() {
+        let options = parse_args(args(&["transactions_1.csv", "--progress"])).unwrap();
+        assert!(options.progress);
+        assert!(parse_args(args(&["--progress"])).is_err());
+        assert!(parse_args(args(&["verify-output", "accounts.csv", "--progress"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_input_files() {
+        let options = parse_args(args(&[
+            "transactions_1.csv",
+            "transactions_3.csv",
+            "--period",
+            "day",
+        ]))
+        .unwrap();
+        assert_eq!(options.file_path, "transactions_1.csv");
+        assert_eq!(
+            options.input_files,
+            vec![
+                OsString::from("transactions_1.csv"),
+                OsString::from("transactions_3.csv")
+            ]
+        );
+        let options = parse_args(args(&["transactions_[32].csv"])).unwrap();
+        assert_eq!(
+            options.input_files,
+            vec![
+                OsString::from("transactions_2.csv"),
+                OsString::from("transactions_3.csv")
+            ]
+        );
+        assert_eq!(options.period, "transactions_2");
+        assert_eq!(parse_args(args(&[])).unwrap().input_files, vec![STDIN]);
+
+        assert!(parse_args(args(&["transactions_9*.csv"])).is_err());
+        assert!(parse_args(args(&["transactions_1.csv", "-"])).is_err());
+        assert!(parse_args(args(&["transactions_1.csv", "events.json"])).is_err());
+        assert!(parse_args(args(&["replay", "events.ndjson", "other.ndjson"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_checkpoint() {
+        let options =
+            parse_args(args(&["transactions_1.csv", "--checkpoint", "state.json"])).unwrap();
+        assert_eq!(options.checkpoint, Some(OsString::from("state.json")));
+        let options = parse_args(args(&["watch", "drops", "--checkpoint", "state.json"])).unwrap();
+        assert_eq!(options.checkpoint, Some(OsString::from("state.json")));
+        assert!(parse_args(args(&[
+            "verify",
+            "transactions_1.csv",
+            "--checkpoint",
+            "s.json"
+        ]))
+        .is_err());
+        assert!(parse_args(args(&[
+            "transactions_1.csv",
+            "--checkpoint",
+            "state.json",
+            "--entry-limit",
+            "10:spill"
+        ]))
+        .is_err());
+    }
+
+    #[test]
+    fn test_parse_watch() {
+        let options = parse_args(args(&["watch", "drops"])).unwrap();
+        assert_eq!(options.command, Command::Watch);
+        assert_eq!(options.file_path, "drops");
+        assert!(options.processed_dir.is_none());
+        let options = parse_args(args(&["watch", "drops", "--processed-dir", "archive"])).unwrap();
+        assert_eq!(options.processed_dir, Some(OsString::from("archive")));
+        assert!(parse_args(args(&["watch"])).is_err());
+        assert!(parse_args(args(&["watch", "drops", "--workers", "2"])).is_err());
+        assert!(parse_args(args(&["transactions_1.csv", "--processed-dir", "archive"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_mmap() {
+        let options = parse_args(args(&["transactions_1.csv", "--mmap"])).unwrap();
+        assert!(options.mmap);
+        assert!(!parse_args(args(&["transactions_1.csv"])).unwrap().mmap);
+        assert!(parse_args(args(&["--mmap"])).is_err());
+        assert!(parse_args(args(&["replay", "events.ndjson", "--mmap"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_state_commands() {
+        let options = parse_args(args(&["export-state", "transactions_1.csv"])).unwrap();
+        assert_eq!(options.command, Command::ExportState);
+        assert!(parse_args(args(&["export-state", "-", "--output-format", "json"])).is_err());
+        let options = parse_args(args(&["import-state", "state.json"])).unwrap();
+        assert_eq!(options.command, Command::ImportState);
+        assert_eq!(options.file_path, OsString::from("state.json"));
+        assert!(parse_args(args(&["import-state"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_state_in() {
+        let options =
+            parse_args(args(&["transactions_2.csv", "--state-in", "accounts.csv"])).unwrap();
+        assert_eq!(options.state_in, Some(OsString::from("accounts.csv")));
+        assert!(parse_args(args(&["state-machine", "--state-in", "accounts.csv"])).is_err());
+        assert!(parse_args(args(&[
+            "transactions_2.csv",
+            "--state-in",
+            "accounts.csv",
+            "--workers",
+            "2"
+        ]))
+        .is_err());
+    }
+
+    #[test]
+    fn test_parse_config() {
+        let config = std::env::temp_dir().join("coding_test_config.toml");
+        fs::write(
+            &config,
+            "dispute_policy = \"locked-accounts\"\noutput_scale = 2\nworkers = 4\n",
+        )
+        .unwrap();
+        let config = config.to_str().unwrap();
+
+        let options = parse_args(args(&["transactions_1.csv", "--config", config])).unwrap();
+        assert!(options.dispute_policy.locked_accounts);
+        assert_eq!(options.decimal_format.scale, 2);
+        assert_eq!(options.workers, 4);
+        assert_eq!(options.file_path, OsString::from("transactions_1.csv"));
+
+        let options = parse_args(args(&["--config", config, "--workers", "2"])).unwrap();
+        assert_eq!(options.workers, 2);
+        assert_eq!(options.decimal_format.scale, 2);
+
+        assert!(parse_args(args(&["--config", config, "--config", config])).is_err());
+        assert!(parse_args(args(&["--config", "missing.toml"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_store_migrate_command() {
+        let options = parse_args(args(&[
+            "store",
+            "migrate",
+            "--archive-dir",
+            "archive",
+            "--backup",
+            "backup",
+        ]))
+        .unwrap();
+        assert_eq!(options.command, Command::StoreMigrate);
+        assert_eq!(options.backup_dir, Some(OsString::from("backup")));
+        assert!(!options.dry_run);
+
+        let options = parse_args(args(&[
+            "store",
+            "migrate",
+            "--dry-run",
+            "--archive-dir",
+            "archive",
+        ]))
+        .unwrap();
+        assert!(options.dry_run);
+        assert!(parse_args(args(&["store", "migrate", "--dry-run"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_dry_run_of_processing() {
+        let options = parse_args(args(&[
+            "transactions_2.csv",
+            "--state-in",
+            "accounts_1.csv",
+            "--dry-run",
+        ]))
+        .unwrap();
+        assert_eq!(options.command, Command::Process);
+        assert!(options.dry_run);
+
+        assert!(parse_args(args(&["trial-balance", "transactions_1.csv", "--dry-run"])).is_err());
+        assert!(parse_args(args(&[
+            "transactions_1.csv",
+            "--dry-run",
+            "--event-log",
+            "events.jsonl"
+        ]))
+        .is_err());
+        assert!(parse_args(args(&[
+            "transactions_1.csv",
+            "--dry-run",
+            "--output-format",
+            "json"
+        ]))
+        .is_err());
+    }
+
+    #[test]
+    fn test_parse_bench_compare_command() {
+        let options = parse_args(args(&["bench-compare", "./previous", "--rows", "1000"])).unwrap();
+        assert_eq!(options.command, Command::BenchCompare);
+        assert_eq!(options.file_path, OsString::from("./previous"));
+        assert_eq!(options.dataset.rows, 1000);
+        assert!(parse_args(args(&["bench-compare"])).is_err());
+        assert!(parse_args(args(&["transactions_1.csv", "--rows", "1000"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_generate_command() {
+        let options = parse_args(args(&[
+            "generate",
+            "--rows",
+            "500",
+            "--clients",
+            "20",
+            "--dispute-ratio",
+            "0.1",
+            "--chargeback-ratio",
+            "0.5",
+        ]))
+        .unwrap();
+        assert_eq!(options.command, Command::Generate);
+        assert_eq!(
+            options.dataset,
+            Dataset {
+                rows: 500,
+                clients: 20,
+                dispute_ratio: 0.1,
+                chargeback_ratio: 0.5
+            }
+        );
+        assert!(parse_args(args(&["generate", "transactions_1.csv"])).is_err());
+        assert!(parse_args(args(&["generate", "--clients", "70000"])).is_err());
+        assert!(parse_args(args(&["generate", "--dispute-ratio", "0.6"])).is_err());
+        assert!(parse_args(args(&["generate", "--chargeback-ratio", "-1"])).is_err());
+        assert!(parse_args(args(&["generate", "--report", "bench.md"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_rejections_option() {
+        let options = parse_args(args(&[
+            "transactions_1.csv",
+            "--rejections",
+            "rejected.csv",
+        ]))
+        .unwrap();
+        assert_eq!(options.rejections, Some(OsString::from("rejected.csv")));
+        assert!(parse_args(args(&[
+            "transactions_1.csv",
+            "--rejections",
+            "rejected.csv",
+            "--workers",
+            "2"
+        ]))
+        .is_err());
+    }
+
+    #[test]
+    fn test_parse_event_log_options() {
+        let options = parse_args(args(&[
+            "transactions_1.csv",
+            "--event-log",
+            "events.ndjson",
+        ]))
+        .unwrap();
+        assert_eq!(options.event_log, Some(OsString::from("events.ndjson")));
+
+        let options = parse_args(args(&["replay", "events.ndjson"])).unwrap();
+        assert_eq!(options.command, Command::Replay);
+        assert_eq!(options.file_path, "events.ndjson");
+
+        assert!(parse_args(args(&["replay"])).is_err());
+        assert!(parse_args(args(&["replay", "-"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_cdc_options() {
+        let options = parse_args(args(&[
+            "transactions_1.csv",
+            "--cdc",
+            "kafka:localhost:9092/account-changes",
+        ]))
+        .unwrap();
+        assert_eq!(
+            options.cdc,
+            Some(String::from("kafka:localhost:9092/account-changes"))
+        );
+
+        assert!(parse_args(args(&[
+            "replay",
+            "events.ndjson",
+            "--cdc",
+            "file:changes.jsonl"
+        ]))
+        .is_err());
+        assert!(parse_args(args(&[
+            "transactions_1.csv",
+            "--cdc",
+            "file:changes.jsonl",
+            "--workers",
+            "2"
+        ]))
+        .is_err());
+
+        let options = parse_args(args(&[
+            "transactions_1.csv",
+            "--emit-changes",
+            "changes.jsonl",
+        ]))
+        .unwrap();
+        assert_eq!(options.cdc, Some(String::from("file:changes.jsonl")));
+        assert!(parse_args(args(&[
+            "transactions_1.csv",
+            "--emit-changes",
+            "changes.jsonl",
+            "--cdc",
+            "file:other.jsonl"
+        ]))
+        .is_err());
+    }
+
+    #[test]
+    fn test_parse_export_ledger_command() {
+        let options = parse_args(args(&[
+            "export-ledger",
+            "transactions_1.csv",
+            "--client",
+            "3",
+            "--status",
+            "dispute,chargeback",
+            "--output-format",
+            "json",
+        ]))
+        .unwrap();
+        assert_eq!(options.command, Command::ExportLedger);
+        assert_eq!(options.export_client, Some(3));
+        assert_eq!(
+            options.export_statuses,
+            vec![
+                DepositedTransactionStatus::Dispute,
+                DepositedTransactionStatus::Chargeback
+            ]
+        );
+        assert_eq!(options.output_format, OutputFormat::Json);
+
+        assert!(parse_args(args(&["export-ledger", "transactions_1.csv"])).is_err());
+        assert!(parse_args(args(&[
+            "export-ledger",
+            "transactions_1.csv",
+            "--client",
+            "3",
+            "--status",
+            "open"
+        ]))
+        .is_err());
+        assert!(parse_args(args(&["transactions_1.csv", "--client", "3"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_account_command() {
+        let options =
+            parse_args(args(&["account", "transactions_1.csv", "--client", "42"])).unwrap();
+        assert_eq!(options.command, Command::Account);
+        assert_eq!(options.export_client, Some(42));
+
+        assert!(parse_args(args(&["account", "transactions_1.csv"])).is_err());
+        assert!(parse_args(args(&[
+            "account",
+            "transactions_1.csv",
+            "--client",
+            "42",
+            "--status",
+            "dispute"
+        ]))
+        .is_err());
+    }
+
+    #[test]
+    fn test_parse_state_machine_command() {
+        let options = parse_args(args(&["state-machine"])).unwrap();
+        assert_eq!(options.command, Command::StateMachine);
+        assert!(parse_args(args(&["state-machine", "transactions_1.csv"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_lookup_command() {
+        let options = parse_args(args(&["lookup", "transactions_1.csv", "--tx", "3"])).unwrap();
+        assert_eq!(options.command, Command::Lookup);
+        assert_eq!(options.lookup_tx, Some(3));
+
+        assert!(parse_args(args(&["lookup", "transactions_1.csv"])).is_err());
+        assert!(parse_args(args(&["transactions_1.csv", "--tx", "3"])).is_err());
+        assert!(parse_args(args(&["lookup", "transactions_1.csv", "--tx", "x"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_workers_option() {
+        let options = parse_args(args(&["transactions_1.csv"])).unwrap();
+        assert_eq!(options.workers, 1);
+
+        let options = parse_args(args(&["transactions_1.csv", "--workers", "8"])).unwrap();
+        assert_eq!(options.workers, 8);
+
+        assert!(parse_args(args(&["transactions_1.csv", "--workers", "0"])).is_err());
+        assert!(parse_args(args(&[
+            "transactions_1.csv",
+            "--workers",
+            "2",
+            "--audit-log",
+            "audit.csv"
+        ]))
+        .is_err());
+        assert!(parse_args(args(&["admin_1.json", "--workers", "2"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_hot_clients_and_shard_report() {
+        let options = parse_args(args(&[
+            "transactions_1.csv",
+            "--workers",
+            "4",
+            "--hot-clients",
+            "7, 42",
+            "--shard-report",
+            "shards.csv",
+        ]))
+        .unwrap();
+        assert_eq!(options.hot_clients, HashSet::from([7, 42]));
+        assert_eq!(options.shard_report, Some(OsString::from("shards.csv")));
+
+        assert!(parse_args(args(&["transactions_1.csv", "--hot-clients", "7"])).is_err());
+        assert!(parse_args(args(&["transactions_1.csv", "--shard-report", "s.csv"])).is_err());
+        assert!(parse_args(args(&[
+            "transactions_1.csv",
+            "--workers",
+            "4",
+            "--hot-clients",
+            "7,x"
+        ]))
+        .is_err());
+    }
+
+    #[test]
+    fn test_parse_overflow_policy() {
+        let options = parse_args(args(&["transactions_1.csv"])).unwrap();
+        assert_eq!(options.overflow_policy, OverflowPolicy::Reject);
+
+        let options =
+            parse_args(args(&["transactions_1.csv", "--overflow-policy", "abort"])).unwrap();
+        assert_eq!(options.overflow_policy, OverflowPolicy::Abort);
+
+        assert!(parse_args(args(&["transactions_1.csv", "--overflow-policy", "wrap"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_alert_options() {
+        let options = parse_args(args(&[
+            "transactions_1.csv",
+            "--alert",
+            "held-funds:1000",
+            "--alert",
+            "lock-rate:5:100",
+            "--notify",
+            "file:alerts.ndjson",
+        ]))
+        .unwrap();
+        assert_eq!(options.alert_rules.len(), 2);
+        assert_eq!(options.notifiers, vec![String::from("file:alerts.ndjson")]);
+
+        assert!(parse_args(args(&["transactions_1.csv", "--alert", "lock-rate"])).is_err());
+        assert!(parse_args(args(&["transactions_1.csv", "--notify", "stderr"])).is_err());
+
+        let options = parse_args(args(&[
+            "transactions_1.csv",
+            "--alert",
+            "chargeback",
+            "--notify-retries",
+            "3",
+            "--dead-letter",
+            "dead.ndjson",
+        ]))
+        .unwrap();
+        assert_eq!(options.notify_retries, 3);
+        assert_eq!(options.dead_letter, Some(OsString::from("dead.ndjson")));
+        assert!(parse_args(args(&[
+            "transactions_1.csv",
+            "--dead-letter",
+            "dead.ndjson"
+        ]))
+        .is_err());
+        assert!(parse_args(args(&["transactions_1.csv", "--notify-retries", "3"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_dispute_policy() {
+        let options = parse_args(args(&["transactions_1.csv"])).unwrap();
+        assert_eq!(options.dispute_policy, DisputePolicy::default());
+
+        let options = parse_args(args(&[
+            "transactions_1.csv",
+            "--dispute-policy",
+            "locked-accounts,window:3600",
+        ]))
+        .unwrap();
+        assert!(options.dispute_policy.locked_accounts);
+        assert_eq!(options.dispute_policy.window, Some(3600));
+
+        assert!(parse_args(args(&["transactions_1.csv", "--dispute-policy", "never"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_locked_policy() {
+        let options = parse_args(args(&["transactions_1.csv"])).unwrap();
+        assert_eq!(options.locked_policy, LockedPolicy::default());
+
+        let options = parse_args(args(&[
+            "transactions_1.csv",
+            "--locked-policy",
+            "deposit,resolve",
+        ]))
+        .unwrap();
+        assert!(options.locked_policy.deposits);
+        assert!(options.locked_policy.resolves);
+        assert!(!options.locked_policy.withdrawals);
+
+        assert!(parse_args(args(&["transactions_1.csv", "--locked-policy", "lock"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_limits() {
+        let options = parse_args(args(&["transactions_1.csv"])).unwrap();
+        assert_eq!(options.limits, Limits::default());
+
+        let options = parse_args(args(&[
+            "transactions_1.csv",
+            "--limits",
+            "max-withdrawal:1000,daily-withdrawals:5000",
+        ]))
+        .unwrap();
+        assert_eq!(options.limits.max_withdrawal, Some(Amount::from(1000)));
+        assert_eq!(options.limits.daily_withdrawals, Some(Amount::from(5000)));
+        assert_eq!(options.limits.max_balance, None);
+
+        assert!(parse_args(args(&["transactions_1.csv", "--limits", "max-balance"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_precision_policy() {
+        let options = parse_args(args(&["transactions_1.csv"])).unwrap();
+        assert_eq!(options.precision_policy, PrecisionPolicy::Round);
+
+        let options = parse_args(args(&[
+            "transactions_1.csv",
+            "--precision-policy",
+            "reject",
+        ]))
+        .unwrap();
+        assert_eq!(options.precision_policy, PrecisionPolicy::Reject);
+        assert!(parse_args(args(&[
+            "transactions_1.csv",
+            "--precision-policy",
+            "truncate"
+        ]))
+        .is_err());
+    }
+
+    #[test]
+    fn test_parse_schema_policy() {
+        let options = parse_args(args(&["transactions_1.csv"])).unwrap();
+        assert_eq!(options.schema_policy, SchemaPolicy::Lenient);
+
+        let options =
+            parse_args(args(&["transactions_1.csv", "--schema-policy", "strict"])).unwrap();
+        assert_eq!(options.schema_policy, SchemaPolicy::Strict);
+        assert!(parse_args(args(&["transactions_1.csv", "--schema-policy", "off"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_charged_back_policy() {
+        let options = parse_args(args(&["transactions_1.csv"])).unwrap();
+        assert_eq!(options.charged_back_policy, ChargedBackPolicy::Reject);
+
+        let options = parse_args(args(&[
+            "transactions_1.csv",
+            "--charged-back-disputes",
+            "escalate",
+        ]))
+        .unwrap();
+        assert_eq!(options.charged_back_policy, ChargedBackPolicy::Escalate);
+
+        assert!(parse_args(args(&[
+            "transactions_1.csv",
+            "--charged-back-disputes",
+            "retry"
+        ]))
+        .is_err());
+    }
+
+    #[test]
+    fn test_parse_parse_threads() {
+        let options = parse_args(args(&["transactions_1.csv"])).unwrap();
+        assert_eq!(options.parse_threads, 1);
+        let options = parse_args(args(&["transactions_1.csv", "--parse-threads", "4"])).unwrap();
+        assert_eq!(options.parse_threads, 4);
+
+        assert!(parse_args(args(&["transactions_1.csv", "--parse-threads", "0"])).is_err());
+        assert!(parse_args(args(&["transactions.json", "--parse-threads", "4"])).is_err());
+        assert!(parse_args(args(&[
+            "transactions_1.csv",
+            "--parse-threads",
+            "4",
+            "--workers",
+            "2"
+        ]))
+        .is_err());
+    }
+
+    #[test]
+    fn test_parse_input_format() {
+        let options = parse_args(args(&["transactions_1.csv"])).unwrap();
+        assert_eq!(options.input_format, InputFormat::Csv);
+
+        let options = parse_args(args(&["admin_1.json"])).unwrap();
+        assert_eq!(options.input_format, InputFormat::Json);
+
+        let options = parse_args(args(&["-", "--input-format", "ndjson"])).unwrap();
+        assert_eq!(options.input_format, InputFormat::Ndjson);
+
+        assert!(parse_args(args(&["-", "--input-format", "xml"])).is_err());
+        assert!(parse_args(args(&["-", "--input-format", "ndjson", "--workers", "2"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_coalesce_deposits() {
+        let options = parse_args(args(&["transactions_1.csv"])).unwrap();
+        assert!(!options.coalesce_deposits);
+
+        let options = parse_args(args(&["transactions_1.csv", "--coalesce-deposits"])).unwrap();
+        assert!(options.coalesce_deposits);
+    }
+
+    #[test]
+    fn test_parse_entry_limit() {
+        let options = parse_args(args(&[
+            "transactions_1.csv",
+            "--entry-limit",
+            "1000:compact",
+        ]))
+        .unwrap();
+        assert_eq!(options.entry_limit, "1000:compact".parse().ok());
+
+        assert!(parse_args(args(&["transactions_1.csv", "--entry-limit", "1000"])).is_err());
+        assert!(parse_args(args(&["transactions_1.csv", "--entry-limit", "1000:spill"])).is_ok());
+        assert!(parse_args(args(&["export-state", "-", "--entry-limit", "1000:spill"])).is_err());
+        let result = parse_args(args(&[
+            "transactions_1.csv",
+            "--entry-limit",
+            "1000:spill",
+            "--history-dir",
+            "history",
+        ]));
+        #[cfg(feature = "sled")]
+        assert!(result.is_ok());
+        #[cfg(not(feature = "sled"))]
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_parse_any_transaction_opens_account() {
+        let options = parse_args(args(&["transactions_1.csv"])).unwrap();
+        assert!(!options.any_transaction_opens_account);
+
+        let options = parse_args(args(&[
+            "transactions_1.csv",
+            "--any-transaction-opens-account",
+        ]))
+        .unwrap();
+        assert!(options.any_transaction_opens_account);
+    }
+
+    #[test]
+    fn test_parse_check_invariants() {
+        let options = parse_args(args(&["transactions_1.csv"])).unwrap();
+        assert!(!options.check_invariants);
+
+        let options = parse_args(args(&["transactions_1.csv", "--check-invariants"])).unwrap();
+        assert!(options.check_invariants);
+    }
+
+    #[test]
+    fn test_parse_backfill_options() {
+        let options = parse_args(args(&["transactions_1.csv"])).unwrap();
+        assert_eq!(options.max_rows_per_sec, None);
+        assert!(!options.nice);
+
+        let options = parse_args(args(&[
+            "transactions_1.csv",
+            "--max-rows-per-sec",
+            "5000",
+            "--nice",
+        ]))
+        .unwrap();
+        assert_eq!(options.max_rows_per_sec, Some(5000));
+        assert!(options.nice);
+
+        assert!(parse_args(args(&["transactions_1.csv", "--max-rows-per-sec", "0"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_reorder() {
+        let options = parse_args(args(&["transactions_1.csv"])).unwrap();
+        assert_eq!(options.reorder, None);
+
+        let options =
+            parse_args(args(&["transactions_1.csv", "--reorder", "timestamp:100"])).unwrap();
+        assert_eq!(
+            options.reorder,
+            Some(Reorder {
+                key: ReorderKey::Timestamp,
+                window: 100
+            })
+        );
+
+        assert!(parse_args(args(&["transactions_1.csv", "--reorder", "timestamp"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_output_format() {
+        let options = parse_args(args(&["transactions_1.csv"])).unwrap();
+        assert_eq!(options.output_format, OutputFormat::Csv);
+
+        let options = parse_args(args(&["transactions_1.csv", "--output-format", "json"])).unwrap();
+        assert_eq!(options.output_format, OutputFormat::Json);
+
+        assert!(parse_args(args(&["transactions_1.csv", "--output-format", "xml"])).is_err());
+        assert!(parse_args(args(&[
+            "trial-balance",
+            "transactions_1.csv",
+            "--output-format",
+            "json"
+        ]))
+        .is_err());
+    }
+
+    #[test]
+    fn test_parse_amount_cache() {
+        let options = parse_args(args(&["transactions_1.csv"])).unwrap();
+        assert!(!options.amount_cache);
+
+        let options = parse_args(args(&["transactions_1.csv", "--amount-cache"])).unwrap();
+        assert!(options.amount_cache);
+    }
+
+    #[test]
+    fn test_parse_dispute_withdrawals() {
+        let options = parse_args(args(&["transactions_1.csv"])).unwrap();
+        assert!(!options.dispute_withdrawals);
+
+        let options = parse_args(args(&["transactions_1.csv", "--dispute-withdrawals"])).unwrap();
+        assert!(options.dispute_withdrawals);
+    }
+
+    #[test]
+    fn test_parse_stdin_input() {
+        let options = parse_args(args(&[])).unwrap();
+        assert_eq!(options.file_path, STDIN);
+        assert_eq!(options.period, "default");
+
+        let options = parse_args(args(&["-", "--period", "day1"])).unwrap();
+        assert_eq!(options.file_path, STDIN);
+        assert_eq!(options.period, "day1");
+    }
+
+    #[test]
+    fn test_parse_input_db() {
+        let options = parse_args(args(&[
+            "--input-db",
+            "sqlite:events.db",
+            "--input-table",
+            "ledger",
+        ]))
+        .unwrap();
+        assert_eq!(
+            options.input_db,
+            Some(Database::Sqlite(String::from("events.db")))
+        );
+        assert_eq!(options.input_table, "ledger");
+        assert_eq!(options.sequence_column, db_input::DEFAULT_SEQUENCE_COLUMN);
+        assert_eq!(options.period, "ledger");
+
+        assert!(parse_args(args(&[
+            "transactions_1.csv",
+            "--input-db",
+            "sqlite:events.db"
+        ]))
+        .is_err());
+        assert!(parse_args(args(&["--input-table", "ledger"])).is_err());
+        assert!(parse_args(args(&["--input-db", "mysql://localhost/events"])).is_err());
+        assert!(parse_args(args(&[
+            "replay",
+            "--input-db",
+            "postgres://localhost/events"
+        ]))
+        .is_err());
+    }
+
+    #[test]
+    fn test_parse_missing_file_path() {
+        assert!(parse_args(args(&["verify-output", "--checksum", "accounts.sha256"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_missing_option_value() {
+        assert!(parse_args(args(&["transactions_1.csv", "--period"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_unknown_option() {
+        assert!(parse_args(args(&["transactions_1.csv", "--unknown"])).is_err());
+    }
+}
@@ -0,0 +1,241 @@
+use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+// Source of time for time based features. Time is Unix time in seconds. Clocks are sent to
+// worker threads when processing is sharded.
+pub trait Clock: Send {
+    fn now(&self) -> u64;
+
+    // Called with a timestamp of every processed row so a clock may follow time of the input.
+    fn observe(&mut self, _timestamp: Option<u64>) {}
+}
+
+// Wall clock time, used when transactions are processed as they happen.
+pub struct SystemClock;
+
+// Time driven by row timestamps, so processing a historical file is deterministic. Rows without
+// a timestamp keep the time of the latest timestamped row.
+pub struct ReplayClock {
+    now: u64,
+}
+
+// Slowest replay speed, a second of the file takes about 12 days.
+pub const MIN_REPLAY_SPEED: f64 = 1e-6;
+
+// Replay clock which also paces processing, so a historical file is processed in accelerated real
+// time. With speed 60 an hour between two rows takes a minute of wall clock time.
+pub struct PacedReplayClock {
+    replay: ReplayClock,
+    speed: f64,
+    // Timestamp of the first row and wall clock time when it was processed.
+    start: Option<(u64, Instant)>,
+}
+
+// Time which never changes. It's useful for reproducible runs and tests.
+pub struct FixedClock {
+    now: u64,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ClockKind {
+    Replay,
+    PacedReplay(f64),
+    System,
+    Fixed(u64),
+}
+
+impl ClockKind {
+    pub fn create(&self) -> Box<dyn Clock> {
+        match self {
+            ClockKind::Replay => Box::new(ReplayClock::new()),
+            ClockKind::PacedReplay(speed) => Box::new(PacedReplayClock::new(*speed)),
+            ClockKind::System => Box::new(SystemClock),
+            ClockKind::Fixed(now) => Box::new(FixedClock::new(*now)),
+        }
+    }
+}
+
+// Clock is given as `replay`, `system` or a fixed Unix time in seconds.
+impl FromStr for ClockKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<ClockKind, String> {
+        match s {
+            "replay" => Ok(ClockKind::Replay),
+            "system" => Ok(ClockKind::System),
+            _ => match s.parse() {
+                Ok(now) => Ok(ClockKind::Fixed(now)),
+                Err(_) => Err(format!("unknown clock: {}", s)),
+            },
+        }
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> u64 {
+        match SystemTime::now().duration_since(UNIX_EPOCH) {
+            Ok(duration) => duration.as_secs(),
+            Err(_) => 0,
+        }
+    }
+}
+
+impl ReplayClock {
+    pub fn new() -> ReplayClock {
+        ReplayClock { now: 0 }
+    }
+}
+
+impl Default for ReplayClock {
+    fn default() -> ReplayClock {
+        ReplayClock::new()
+    }
+}
+
+impl Clock for ReplayClock {
+    fn now(&self) -> u64 {
+        self.now
+    }
+
+    // Time never goes backwards, even if rows are slightly out of order.
+    fn observe(&mut self, timestamp: Option<u64>) {
+        if let Some(timestamp) = timestamp {
+            self.now = self.now.max(timestamp);
+        }
+    }
+}
+
+impl PacedReplayClock {
+    pub fn new(speed: f64) -> PacedReplayClock {
+        PacedReplayClock {
+            replay: ReplayClock::new(),
+            speed,
+            start: None,
+        }
+    }
+
+    // How long to wait before a row with a timestamp is processed, given the wall clock time
+    // which already elapsed since the first row.
+    fn delay(&self, timestamp: u64, elapsed: Duration) -> Duration {
+        match self.start {
+            None => Duration::ZERO,
+            Some((first, _)) => {
+                let target = timestamp.saturating_sub(first) as f64 / self.speed;
+                // A gap too long for a duration is waited for as long as possible.
+                Duration::try_from_secs_f64(target)
+                    .unwrap_or(Duration::MAX)
+                    .saturating_sub(elapsed)
+            }
+        }
+    }
+}
+
+impl Clock for PacedReplayClock {
+    fn now(&self) -> u64 {
+        self.replay.now()
+    }
+
+    fn observe(&mut self, timestamp: Option<u64>) {
+        if let Some(timestamp) = timestamp {
+            match self.start {
+                None => self.start = Some((timestamp, Instant::now())),
+                Some((_, started)) => thread::sleep(self.delay(timestamp, started.elapsed())),
+            }
+        }
+        self.replay.observe(timestamp);
+    }
+}
+
+impl FixedClock {
+    pub fn new(now: u64) -> FixedClock {
+        FixedClock { now }
+    }
+}
+
+impl Clock for FixedClock {
+    fn now(&self) -> u64 {
+        self.now
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_clock_kind() {
+        assert_eq!(ClockKind::from_str("replay"), Ok(ClockKind::Replay));
+        assert_eq!(ClockKind::from_str("system"), Ok(ClockKind::System));
+        assert_eq!(ClockKind::from_str("1000"), Ok(ClockKind::Fixed(1000)));
+        assert!(ClockKind::from_str("yesterday").is_err());
+    }
+
+    #[test]
+    fn test_create_clock() {
+        assert_eq!(ClockKind::Fixed(1000).create().now(), 1000);
+        assert_eq!(ClockKind::Replay.create().now(), 0);
+    }
+
+    #[test]
+    fn test_system_clock_is_after_epoch() {
+        assert!(SystemClock.now() > 0);
+    }
+
+    #[test]
+    fn test_replay_clock_follows_row_timestamps() {
+        let mut clock = ReplayClock::new();
+        assert_eq!(clock.now(), 0);
+
+        clock.observe(Some(100));
+        assert_eq!(clock.now(), 100);
+
+        clock.observe(None);
+        assert_eq!(clock.now(), 100);
+
+        clock.observe(Some(50));
+        assert_eq!(clock.now(), 100);
+
+        clock.observe(Some(200));
+        assert_eq!(clock.now(), 200);
+    }
+
+    #[test]
+    fn test_paced_replay_clock_delay() {
+        let mut clock = PacedReplayClock::new(60.0);
+        assert_eq!(clock.delay(3600, Duration::ZERO), Duration::ZERO);
+
+        clock.observe(Some(3600));
+        assert_eq!(clock.now(), 3600);
+
+        // An hour later in the file is a minute later in wall clock time.
+        assert_eq!(
+            clock.delay(7200, Duration::from_secs(10)),
+            Duration::from_secs(50)
+        );
+        assert_eq!(clock.delay(7200, Duration::from_secs(70)), Duration::ZERO);
+        assert_eq!(clock.delay(1800, Duration::ZERO), Duration::ZERO);
+
+        let clock = PacedReplayClock {
+            start: clock.start,
+            ..PacedReplayClock::new(MIN_REPLAY_SPEED)
+        };
+        assert_eq!(clock.delay(u64::MAX, Duration::ZERO), Duration::MAX);
+    }
+
+    #[test]
+    fn test_paced_replay_clock_follows_row_timestamps() {
+        let mut clock = PacedReplayClock::new(1000.0);
+        clock.observe(Some(100));
+        clock.observe(None);
+        clock.observe(Some(101));
+        assert_eq!(clock.now(), 101);
+    }
+
+    #[test]
+    fn test_fixed_clock_ignores_row_timestamps() {
+        let mut clock = FixedClock::new(1000);
+        clock.observe(Some(2000));
+        assert_eq!(clock.now(), 1000);
+    }
+}
@@ -0,0 +1,163 @@
+use anyhow::Context;
+use serde::Deserialize;
+use std::ffi::OsString;
+use std::fs;
+
+// Engine policies kept in a TOML file instead of being passed as options. Keys are names of the
+// options without leading dashes and with underscores, for example `dispute_policy` for
+// `--dispute-policy`, and values are parsed the same way as on command line.
+#[derive(Deserialize, Default, PartialEq, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    dispute_policy: Option<String>,
+    locked_policy: Option<String>,
+    limits: Option<String>,
+    charged_back_disputes: Option<String>,
+    dispute_withdrawals: Option<bool>,
+    any_transaction_opens_account: Option<bool>,
+    overflow_policy: Option<String>,
+    precision_policy: Option<String>,
+    schema_policy: Option<String>,
+    coalesce_deposits: Option<bool>,
+    amount_cache: Option<bool>,
+    output_scale: Option<u32>,
+    trim_trailing_zeros: Option<bool>,
+    output_rounding: Option<String>,
+    output_format: Option<String>,
+    workers: Option<usize>,
+    parse_threads: Option<usize>,
+    hot_clients: Option<Vec<u16>>,
+    archive_dir: Option<String>,
+    sqlite_store: Option<String>,
+    history_dir: Option<String>,
+    dormant_after: Option<u64>,
+    entry_limit: Option<String>,
+}
+
+fn push_value(args: &mut Vec<OsString>, name: &str, value: Option<impl ToString>) {
+    if let Some(value) = value {
+        args.push(OsString::from(name));
+        args.push(OsString::from(value.to_string()));
+    }
+}
+
+fn push_flag(args: &mut Vec<OsString>, name: &str, value: Option<bool>) {
+    if value == Some(true) {
+        args.push(OsString::from(name));
+    }
+}
+
+impl Config {
+    pub fn load(file_path: &OsString) -> anyhow::Result<Config> {
+        let text = fs::read_to_string(file_path)
+            .with_context(|| format!("can not read config file {:?}", file_path))?;
+        toml::from_str(&text).with_context(|| format!("invalid config file {:?}", file_path))
+    }
+
+    // Options set in the config. They are parsed before options of command line, which override
+    // them.
+    pub fn to_args(&self) -> Vec<OsString> {
+        let mut args = Vec::new();
+        push_value(&mut args, "--dispute-policy", self.dispute_policy.as_ref());
+        push_value(&mut args, "--locked-policy", self.locked_policy.as_ref());
+        push_value(&mut args, "--limits", self.limits.as_ref());
+        push_value(
+            &mut args,
+            "--charged-back-disputes",
+            self.charged_back_disputes.as_ref(),
+        );
+        push_flag(&mut args, "--dispute-withdrawals", self.dispute_withdrawals);
+        push_flag(
+            &mut args,
+            "--any-transaction-opens-account",
+            self.any_transaction_opens_account,
+        );
+        push_value(
+            &mut args,
+            "--overflow-policy",
+            self.overflow_policy.as_ref(),
+        );
+        push_value(
+            &mut args,
+            "--precision-policy",
+            self.precision_policy.as_ref(),
+        );
+        push_value(&mut args, "--schema-policy", self.schema_policy.as_ref());
+        push_flag(&mut args, "--coalesce-deposits", self.coalesce_deposits);
+        push_flag(&mut args, "--amount-cache", self.amount_cache);
+        push_value(&mut args, "--output-scale", self.output_scale);
+        push_flag(&mut args, "--trim-trailing-zeros", self.trim_trailing_zeros);
+        push_value(
+            &mut args,
+            "--output-rounding",
+            self.output_rounding.as_ref(),
+        );
+        push_value(&mut args, "--output-format", self.output_format.as_ref());
+        push_value(&mut args, "--workers", self.workers);
+        push_value(&mut args, "--parse-threads", self.parse_threads);
+        push_value(
+            &mut args,
+            "--hot-clients",
+            self.hot_clients.as_ref().map(|clients| {
+                clients
+                    .iter()
+                    .map(u16::to_string)
+                    .collect::<Vec<_>>()
+                    .join(",")
+            }),
+        );
+        push_value(&mut args, "--archive-dir", self.archive_dir.as_ref());
+        push_value(&mut args, "--sqlite-store", self.sqlite_store.as_ref());
+        push_value(&mut args, "--history-dir", self.history_dir.as_ref());
+        push_value(&mut args, "--dormant-after", self.dormant_after);
+        push_value(&mut args, "--entry-limit", self.entry_limit.as_ref());
+        args
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_config_to_args() {
+        let config: Config = toml::from_str(
+            "dispute_policy = \"locked-accounts,window:86400\"\n\
+             locked_policy = \"deposit,resolve\"\n\
+             dispute_withdrawals = true\n\
+             coalesce_deposits = false\n\
+             output_scale = 2\n\
+             workers = 4\n\
+             hot_clients = [1, 7]\n",
+        )
+        .unwrap();
+        let args: Vec<String> = config
+            .to_args()
+            .into_iter()
+            .map(|arg| arg.into_string().unwrap())
+            .collect();
+        assert_eq!(
+            args,
+            vec![
+                "--dispute-policy",
+                "locked-accounts,window:86400",
+                "--locked-policy",
+                "deposit,resolve",
+                "--dispute-withdrawals",
+                "--output-scale",
+                "2",
+                "--workers",
+                "4",
+                "--hot-clients",
+                "1,7",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unknown_key_is_rejected() {
+        assert!(toml::from_str::<Config>("wokers = 4").is_err());
+        assert!(toml::from_str::<Config>("workers = \"4\"").is_err());
+        assert_eq!(toml::from_str::<Config>("").unwrap(), Config::default());
+    }
+}
@@ -0,0 +1,197 @@
+use crate::amount::Amount;
+use anyhow::{anyhow, bail, Context};
+use log::info;
+use serde::de::{self, Deserializer};
+use serde::Deserialize;
+use std::ffi::OsString;
+use std::fs;
+use std::path::Path;
+use std::process;
+use std::str::FromStr;
+
+// Canonical input and expected accounts output. Arguments select a policy preset and they are
+// passed to an implementation together with the input file.
+pub struct Vector {
+    pub name: &'static str,
+    pub args: &'static [&'static str],
+    pub input: &'static str,
+    pub expected: &'static str,
+}
+
+macro_rules! vector {
+    ($name:literal, $args:expr) => {
+        Vector {
+            name: $name,
+            args: $args,
+            input: include_str!(concat!("../conformance/", $name, "/input.csv")),
+            expected: include_str!(concat!("../conformance/", $name, "/expected.csv")),
+        }
+    };
+}
+
+pub const VECTORS: &[Vector] = &[
+    vector!("deposits_and_withdrawals", &[]),
+    vector!("disputes", &[]),
+    vector!("locked_account", &[]),
+    vector!("invalid_rows", &[]),
+    vector!("admin_operations", &[]),
+    vector!("maker_checker", &["--approval-threshold", "100"]),
+    vector!(
+        "dispute_policy",
+        &[
+            "--dispute-policy",
+            "hold-beyond-available,redispute-resolved"
+        ]
+    ),
+    vector!("locked_policy", &["--locked-policy", "deposit,withdrawal"]),
+    vector!(
+        "charged_back_disputes",
+        &["--charged-back-disputes", "escalate"]
+    ),
+    vector!("withdrawal_disputes", &["--dispute-withdrawals"]),
+    vector!("limits", &["--limits", "max-withdrawal:50,max-balance:200"]),
+    // Amounts at the limit of 96-bit decimals, implementations with a wider range don't overflow.
+    vector!("overflow", &["--overflow-policy", "saturate"]),
+];
+
+// Implementations may order rows and format numbers differently, so outputs are compared by
+// values of accounts sorted by client.
+#[derive(Deserialize, PartialEq, Debug)]
+struct AccountRow {
+    client: u16,
+    #[serde(deserialize_with = "amount")]
+    available: Amount,
+    #[serde(deserialize_with = "amount")]
+    held: Amount,
+    #[serde(deserialize_with = "amount")]
+    total: Amount,
+    locked: bool,
+}
+
+// Trailing zeros are dropped, as the largest amounts padded to the output scale have more digits
+// than a decimal holds.
+fn amount<'de, D: Deserializer<'de>>(d: D) -> Result<Amount, D::Error> {
+    let text = String::deserialize(d)?;
+    let text = match text.contains('.') {
+        true => text.trim_end_matches('0').trim_end_matches('.'),
+        false => &text,
+    };
+    Amount::from_str(text).map_err(de::Error::custom)
+}
+
+fn parse_accounts(output: &str) -> anyhow::Result<Vec<AccountRow>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(output.as_bytes());
+    let mut rows = reader
+        .deserialize()
+        .collect::<Result<Vec<AccountRow>, csv::Error>>()?;
+    rows.sort_by_key(|row| row.client);
+    Ok(rows)
+}
+
+impl Vector {
+    // Checks output of an implementation for this vector.
+    pub fn check(&self, output: &str) -> anyhow::Result<()> {
+        let expected = parse_accounts(self.expected).context("invalid expected output")?;
+        let actual = parse_accounts(output).context("invalid accounts output")?;
+        if expected.len() != actual.len() {
+            bail!(
+                "expected {} accounts, but got {}",
+                expected.len(),
+                actual.len()
+            );
+        }
+        for (expected, actual) in expected.iter().zip(&actual) {
+            if expected != actual {
+                bail!("expected {:?}, but got {:?}", expected, actual);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// Writes all vectors into a directory, so they can be used without this binary.
+pub fn export(dir: &OsString) -> anyhow::Result<()> {
+    for vector in VECTORS {
+        let vector_dir = Path::new(dir).join(vector.name);
+        fs::create_dir_all(&vector_dir)
+            .with_context(|| format!("can not create directory {:?}", vector_dir))?;
+        fs::write(vector_dir.join("input.csv"), vector.input)?;
+        fs::write(vector_dir.join("expected.csv"), vector.expected)?;
+        fs::write(vector_dir.join("args"), vector.args.join(" "))?;
+    }
+
+    Ok(())
+}
+
+// Runs an implementation for every vector as `<implementation> <input.csv> <args>` and checks
+// accounts it writes to standard output. Returns a number of failed vectors.
+pub fn run(implementation: &Path) -> anyhow::Result<usize> {
+    let dir = std::env::temp_dir().join(format!("coding_test_conformance_{}", process::id()));
+    fs::create_dir_all(&dir).with_context(|| format!("can not create directory {:?}", dir))?;
+
+    let mut failed = 0;
+    for vector in VECTORS {
+        let input = dir.join(format!("{}.csv", vector.name));
+        fs::write(&input, vector.input)?;
+
+        let output = process::Command::new(implementation)
+            .arg(&input)
+            .args(vector.args)
+            .output()
+            .with_context(|| format!("can not run implementation {:?}", implementation))?;
+        let result = if output.status.success() {
+            vector.check(&String::from_utf8_lossy(&output.stdout))
+        } else {
+            Err(anyhow!("implementation exited with {}", output.status))
+        };
+
+        match result {
+            Ok(()) => println!("PASS {}", vector.name),
+            Err(err) => {
+                println!("FAIL {}: {:#}", vector.name, err);
+                failed += 1;
+            }
+        }
+    }
+    info!("{} of {} conformance vectors failed", failed, VECTORS.len());
+    let _ = fs::remove_dir_all(&dir);
+
+    Ok(failed)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_expected_outputs_are_valid() {
+        for vector in VECTORS {
+            assert!(parse_accounts(vector.expected).is_ok(), "{}", vector.name);
+        }
+    }
+
+    #[test]
+    fn test_check_ignores_order_and_formatting() {
+        let vector = &VECTORS[0];
+        let output = "client,available,held,total,locked\n\
+                      2,2,0,2,false\n\
+                      1,1.50,0.0,1.5,false\n";
+        assert!(vector.check(output).is_ok());
+    }
+
+    #[test]
+    fn test_check_detects_differences() {
+        let vector = &VECTORS[0];
+        let output = "client,available,held,total,locked\n\
+                      1,1.5,0,1.5,false\n";
+        assert!(vector.check(output).is_err());
+
+        let output = "client,available,held,total,locked\n\
+                      1,1.5,0,1.5,true\n\
+                      2,2,0,2,false\n";
+        assert!(vector.check(output).is_err());
+    }
+}
@@ -0,0 +1,330 @@
+use crate::source::{self, SourceError, TransactionSource};
+use crate::transaction::Transaction;
+
+use anyhow::bail;
+use serde_json::Value;
+use std::str::FromStr;
+use std::vec;
+
+// Table and sequence column read if no other are given.
+pub const DEFAULT_TABLE: &str = "transactions";
+pub const DEFAULT_SEQUENCE_COLUMN: &str = "seq";
+
+// Rows are read in pages, so a large table is never loaded at once.
+const PAGE_SIZE: usize = 1000;
+
+// Database with a table of transactions, given as `sqlite:<path>` or as a PostgreSQL connection
+// URL `postgres://...`.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Database {
+    Sqlite(String),
+    Postgres(String),
+}
+
+impl FromStr for Database {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Database, String> {
+        if let Some(path) = s.strip_prefix("sqlite:") {
+            if path.is_empty() {
+                return Err(String::from("SQLite database path is missing"));
+            }
+            return Ok(Database::Sqlite(path.to_string()));
+        }
+        if s.starts_with("postgres://") || s.starts_with("postgresql://") {
+            return Ok(Database::Postgres(s.to_string()));
+        }
+        Err(format!("unknown database: {}", s))
+    }
+}
+
+// Table and column names are put into queries as they are, so only plain names (optionally with a
+// schema) are accepted.
+fn check_identifier(name: &str) -> anyhow::Result<()> {
+    let valid = name.split('.').count() <= 2
+        && name.split('.').all(|part| {
+            part.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+                && part.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        });
+    if !valid {
+        bail!("invalid table or column name: {:?}", name);
+    }
+    Ok(())
+}
+
+// Reads rows following a sequence value, ordered by it. Every row is a JSON object with column
+// names as keys, so it's deserialized like a JSON transaction.
+pub trait PageReader {
+    fn read_page(&mut self, after: i64) -> anyhow::Result<Vec<(i64, Value)>>;
+}
+
+// Transactions from a database table, ordered by an integer sequence column. Position is the
+// sequence value of a row.
+pub struct DbSource {
+    reader: Box<dyn PageReader>,
+    rows: vec::IntoIter<(i64, Value)>,
+    sequence: i64,
+    last_page: bool,
+}
+
+impl DbSource {
+    pub fn new(reader: Box<dyn PageReader>) -> DbSource {
+        DbSource {
+            reader,
+            rows: Vec::new().into_iter(),
+            sequence: i64::MIN,
+            last_page: false,
+        }
+    }
+}
+
+impl TransactionSource for DbSource {
+    fn next(&mut self) -> Option<Result<Transaction, SourceError>> {
+        if self.rows.len() == 0 {
+            if self.last_page {
+                return None;
+            }
+            let rows = match self.reader.read_page(self.sequence) {
+                Ok(rows) => rows,
+                Err(error) => {
+                    self.last_page = true;
+                    return Some(Err(SourceError::Failed(error)));
+                }
+            };
+            self.last_page = rows.len() < PAGE_SIZE;
+            self.rows = rows.into_iter();
+        }
+
+        let (sequence, row) = self.rows.next()?;
+        self.sequence = sequence;
+        Some(source::from_json(self.position(), &row))
+    }
+
+    fn position(&self) -> u64 {
+        self.sequence.max(0) as u64
+    }
+
+    fn unit(&self) -> &'static str {
+        "sequence"
+    }
+}
+
+// Opens a source of transactions in a table of a database.
+pub fn open(database: &Database, table: &str, sequence_column: &str) -> anyhow::Result<DbSource> {
+    check_identifier(table)?;
+    check_identifier(sequence_column)?;
+
+    match database {
+        #[cfg(feature = "sqlite")]
+        Database::Sqlite(path) => {
+            let reader = SqliteReader::open(path, table, sequence_column)?;
+            Ok(DbSource::new(Box::new(reader)))
+        }
+        #[cfg(not(feature = "sqlite"))]
+        Database::Sqlite(_) => bail!("reading from SQLite requires sqlite feature"),
+        #[cfg(feature = "postgres")]
+        Database::Postgres(url) => {
+            let reader = PostgresReader::connect(url, table, sequence_column)?;
+            Ok(DbSource::new(Box::new(reader)))
+        }
+        #[cfg(not(feature = "postgres"))]
+        Database::Postgres(_) => bail!("reading from PostgreSQL requires postgres feature"),
+    }
+}
+
+#[cfg(feature = "sqlite")]
+pub struct SqliteReader {
+    connection: rusqlite::Connection,
+    query: String,
+    sequence_column: String,
+}
+
+#[cfg(feature = "sqlite")]
+impl SqliteReader {
+    pub fn open(path: &str, table: &str, sequence_column: &str) -> anyhow::Result<SqliteReader> {
+        use anyhow::Context;
+
+        let connection =
+            rusqlite::Connection::open_with_flags(path, rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY)
+                .with_context(|| format!("can not open SQLite database {:?}", path))?;
+        Ok(SqliteReader {
+            connection,
+            query: format!(
+                "SELECT * FROM {} WHERE {} > ?1 ORDER BY {} LIMIT {}",
+                table, sequence_column, sequence_column, PAGE_SIZE
+            ),
+            sequence_column: sequence_column.to_string(),
+        })
+    }
+}
+
+// Null columns are left out like missing CSV fields and blobs aren't valid in any column.
+#[cfg(feature = "sqlite")]
+impl PageReader for SqliteReader {
+    fn read_page(&mut self, after: i64) -> anyhow::Result<Vec<(i64, Value)>> {
+        use rusqlite::types::ValueRef;
+
+        let mut statement = self.connection.prepare_cached(&self.query)?;
+        let names: Vec<String> = statement
+            .column_names()
+            .iter()
+            .map(|name| name.to_string())
+            .collect();
+        let sequence_index = statement.column_index(&self.sequence_column)?;
+
+        let mut rows = statement.query([after])?;
+        let mut page = Vec::new();
+        while let Some(row) = rows.next()? {
+            let mut object = serde_json::Map::new();
+            for (index, name) in names.iter().enumerate() {
+                let value = match row.get_ref(index)? {
+                    ValueRef::Null | ValueRef::Blob(_) => continue,
+                    ValueRef::Integer(value) => Value::from(value),
+                    ValueRef::Real(value) => Value::from(value),
+                    ValueRef::Text(text) => Value::from(String::from_utf8_lossy(text)),
+                };
+                object.insert(name.clone(), value);
+            }
+            page.push((row.get(sequence_index)?, Value::Object(object)));
+        }
+
+        Ok(page)
+    }
+}
+
+#[cfg(feature = "postgres")]
+pub struct PostgresReader {
+    client: postgres::Client,
+    query: String,
+}
+
+#[cfg(feature = "postgres")]
+impl PostgresReader {
+    pub fn connect(
+        url: &str,
+        table: &str,
+        sequence_column: &str,
+    ) -> anyhow::Result<PostgresReader> {
+        use anyhow::Context;
+
+        let client = postgres::Client::connect(url, postgres::NoTls)
+            .context("can not connect to PostgreSQL database")?;
+        Ok(PostgresReader {
+            client,
+            query: format!(
+                "SELECT t.{}::bigint, row_to_json(t)::text FROM {} t WHERE t.{} > $1::bigint
+                 ORDER BY t.{} LIMIT {}",
+                sequence_column, table, sequence_column, sequence_column, PAGE_SIZE
+            ),
+        })
+    }
+}
+
+// Rows are converted to JSON by the server, so numeric amounts come as exact decimal literals.
+#[cfg(feature = "postgres")]
+impl PageReader for PostgresReader {
+    fn read_page(&mut self, after: i64) -> anyhow::Result<Vec<(i64, Value)>> {
+        let mut page = Vec::new();
+        for row in self.client.query(self.query.as_str(), &[&after])? {
+            let json: String = row.try_get(1)?;
+            page.push((row.try_get(0)?, serde_json::from_str(&json)?));
+        }
+
+        Ok(page)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_database() {
+        assert_eq!(
+            "sqlite:events.db".parse::<Database>(),
+            Ok(Database::Sqlite(String::from("events.db")))
+        );
+        assert_eq!(
+            "postgres://payments@localhost/events".parse::<Database>(),
+            Ok(Database::Postgres(String::from(
+                "postgres://payments@localhost/events"
+            )))
+        );
+        assert!("sqlite:".parse::<Database>().is_err());
+        assert!("mysql://localhost/events".parse::<Database>().is_err());
+    }
+
+    #[test]
+    fn test_check_identifier() {
+        assert!(check_identifier("transactions").is_ok());
+        assert!(check_identifier("ledger.transactions_2").is_ok());
+        assert!(check_identifier("transactions; DROP TABLE accounts").is_err());
+        assert!(check_identifier("1st").is_err());
+        assert!(check_identifier("").is_err());
+    }
+
+    // Pages of rows with sequence values from a vector.
+    struct VecReader(Vec<(i64, Value)>);
+
+    impl PageReader for VecReader {
+        fn read_page(&mut self, after: i64) -> anyhow::Result<Vec<(i64, Value)>> {
+            Ok(self
+                .0
+                .iter()
+                .filter(|(sequence, _)| *sequence > after)
+                .take(PAGE_SIZE)
+                .cloned()
+                .collect())
+        }
+    }
+
+    #[test]
+    fn test_db_source_reads_all_pages() {
+        let rows = (1..=PAGE_SIZE as i64 * 2 + 1)
+            .map(|sequence| {
+                let row = serde_json::json!({
+                    "seq": sequence,
+                    "type": "deposit",
+                    "client": 1,
+                    "tx": sequence,
+                    "amount": "1.5",
+                });
+                (sequence * 10, row)
+            })
+            .collect();
+        let mut source = DbSource::new(Box::new(VecReader(rows)));
+
+        let mut count = 0;
+        while let Some(transaction) = source.next() {
+            count += 1;
+            assert_eq!(transaction.unwrap().tx_id(), count);
+            assert_eq!(source.position(), count as u64 * 10);
+        }
+        assert_eq!(count as usize, PAGE_SIZE * 2 + 1);
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn test_sqlite_source() {
+        let path = std::env::temp_dir().join("coding_test_db_input.db");
+        let _ = std::fs::remove_file(&path);
+        let connection = rusqlite::Connection::open(&path).unwrap();
+        connection
+            .execute_batch(
+                "CREATE TABLE transactions (seq INTEGER PRIMARY KEY, type TEXT, client INTEGER,
+                     tx INTEGER, amount TEXT);
+                 INSERT INTO transactions VALUES (3, 'dispute', 1, 1, NULL);
+                 INSERT INTO transactions VALUES (1, 'deposit', 1, 1, '1.2345');
+                 INSERT INTO transactions VALUES (2, 'deposit', 1, 2, 'abc');",
+            )
+            .unwrap();
+
+        let database = Database::Sqlite(path.to_string_lossy().into_owned());
+        let mut source = open(&database, "transactions", "seq").unwrap();
+        let mut results = Vec::new();
+        while let Some(transaction) = source.next() {
+            results.push((source.position(), transaction.is_ok()));
+        }
+        assert_eq!(results, vec![(1, true), (2, false), (3, true)]);
+    }
+}
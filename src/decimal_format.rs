@@ -0,0 +1,243 @@
+use crate::amount::Amount;
+use rust_decimal::RoundingStrategy;
+use serde::{ser, Serialize, Serializer};
+use serde_json::value::RawValue;
+use std::cell::{Cell, RefCell};
+use std::fmt::Write;
+use std::iter;
+use std::str::FromStr;
+
+// All decimal numbers in outputs (accounts, reports, audit trail) are formatted
+// here, so the formatting rules live in exactly one place.
+pub const DEFAULT_SCALE: u32 = 4;
+
+thread_local! {
+    // Format of the engine writing outputs on this thread, see `EngineConfig::enter`.
+    static FORMAT: Cell<DecimalFormat> = Cell::new(DecimalFormat::default());
+    // Serialization happens for every output row so the same buffer is reused instead of
+    // allocating a new string for every number.
+    static BUFFER: RefCell<String> = RefCell::new(String::with_capacity(64));
+}
+
+// How digits past the scale are dropped, as downstream systems reconcile differently.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Rounding {
+    Truncate,
+    // Half away from zero.
+    HalfUp,
+    // Half to even, also known as banker's rounding.
+    HalfEven,
+}
+
+// Rounding is given as `truncate`, `half-up` or `half-even`.
+impl FromStr for Rounding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Rounding, String> {
+        match s {
+            "truncate" => Ok(Rounding::Truncate),
+            "half-up" => Ok(Rounding::HalfUp),
+            "half-even" => Ok(Rounding::HalfEven),
+            _ => Err(format!("unknown rounding: {}", s)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct DecimalFormat {
+    pub scale: u32,
+    pub trim_trailing_zeros: bool,
+    pub rounding: Rounding,
+}
+
+impl DecimalFormat {
+    // Format used by `serialize` on this thread.
+    pub fn current() -> DecimalFormat {
+        FORMAT.with(Cell::get)
+    }
+
+    // Makes the format used by `serialize` on this thread and returns the previous one.
+    pub(crate) fn replace_current(self) -> DecimalFormat {
+        FORMAT.with(|format| format.replace(self))
+    }
+
+    pub fn write(&self, num: &Amount, buffer: &mut String) {
+        buffer.clear();
+        // Formatting truncates digits past the scale, so other modes round first. Writing into a
+        // string can't fail.
+        let strategy = match self.rounding {
+            Rounding::Truncate => None,
+            Rounding::HalfUp => Some(RoundingStrategy::MidpointAwayFromZero),
+            Rounding::HalfEven => Some(RoundingStrategy::MidpointNearestEven),
+        };
+        let num = match strategy {
+            None => *num,
+            Some(strategy) => num.round_dp_with_strategy(self.scale, strategy),
+        };
+        let _ = write!(buffer, "{}", num);
+
+        // Digits are cut or padded to the scale here, as `rust_decimal` can't format amounts near
+        // its largest one with a precision.
+        let scale = self.scale as usize;
+        let (integer_len, fraction_len) = match buffer.find('.') {
+            None => (buffer.len(), 0),
+            Some(dot) => (dot, buffer.len() - dot - 1),
+        };
+        if fraction_len > scale {
+            buffer.truncate(integer_len + if scale == 0 { 0 } else { 1 + scale });
+        } else {
+            if fraction_len == 0 && scale > 0 {
+                buffer.push('.');
+            }
+            buffer.extend(iter::repeat_n('0', scale - fraction_len));
+        }
+
+        if self.trim_trailing_zeros && buffer.contains('.') {
+            let trimmed_len = buffer.trim_end_matches('0').trim_end_matches('.').len();
+            buffer.truncate(trimmed_len);
+        }
+    }
+}
+
+impl Default for DecimalFormat {
+    fn default() -> DecimalFormat {
+        DecimalFormat {
+            scale: DEFAULT_SCALE,
+            trim_trailing_zeros: false,
+            rounding: Rounding::Truncate,
+        }
+    }
+}
+
+// Adapter for `#[serde(serialize_with = "decimal_format::serialize")]`.
+pub fn serialize<S>(num: &Amount, s: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    BUFFER.with(|buffer| {
+        let mut buffer = buffer.borrow_mut();
+        DecimalFormat::current().write(num, &mut buffer);
+        s.serialize_str(&buffer)
+    })
+}
+
+// Adapter for JSON outputs. Numbers are formatted the same way, but written as JSON numbers so
+// consumers don't have to parse strings.
+pub fn serialize_json_number<S>(num: &Amount, s: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    let number = BUFFER.with(|buffer| {
+        let mut buffer = buffer.borrow_mut();
+        DecimalFormat::current().write(num, &mut buffer);
+        RawValue::from_string(buffer.clone())
+    });
+    number.map_err(ser::Error::custom)?.serialize(s)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::str::FromStr;
+
+    fn format(format: DecimalFormat, num: &str) -> String {
+        let mut buffer = String::new();
+        format.write(&Amount::from_str(num).unwrap(), &mut buffer);
+        buffer
+    }
+
+    #[test]
+    fn test_default_format() {
+        let default = DecimalFormat::default();
+        assert_eq!(format(default, "1.5"), "1.5000");
+        assert_eq!(format(default, "0"), "0.0000");
+        assert_eq!(format(default, "-12.34567"), "-12.3456");
+    }
+
+    #[test]
+    fn test_configured_scale() {
+        let format_2 = DecimalFormat {
+            scale: 2,
+            ..DecimalFormat::default()
+        };
+        assert_eq!(format(format_2, "1.5"), "1.50");
+        assert_eq!(format(format_2, "100"), "100.00");
+
+        let format_0 = DecimalFormat {
+            scale: 0,
+            ..DecimalFormat::default()
+        };
+        assert_eq!(format(format_0, "100.99"), "100");
+    }
+
+    #[test]
+    fn test_trim_trailing_zeros() {
+        let trimmed = DecimalFormat {
+            trim_trailing_zeros: true,
+            ..DecimalFormat::default()
+        };
+        assert_eq!(format(trimmed, "1.5"), "1.5");
+        assert_eq!(format(trimmed, "100"), "100");
+        assert_eq!(format(trimmed, "0"), "0");
+        assert_eq!(format(trimmed, "10.0100"), "10.01");
+    }
+
+    #[test]
+    fn test_rounding() {
+        let rounded = |rounding| DecimalFormat {
+            scale: 2,
+            trim_trailing_zeros: false,
+            rounding,
+        };
+        let truncate = rounded(Rounding::Truncate);
+        let half_up = rounded(Rounding::HalfUp);
+        let half_even = rounded(Rounding::HalfEven);
+        assert_eq!(format(truncate, "1.239"), "1.23");
+        assert_eq!(format(half_up, "1.239"), "1.24");
+        assert_eq!(format(half_up, "1.225"), "1.23");
+        assert_eq!(format(half_up, "-1.225"), "-1.23");
+        assert_eq!(format(half_even, "1.225"), "1.22");
+        assert_eq!(format(half_even, "1.235"), "1.24");
+        assert_eq!(format(half_even, "-1.2251"), "-1.23");
+        assert_eq!(format(half_even, "0.005"), "0.00");
+        assert!("banker".parse::<Rounding>().is_err());
+    }
+
+    #[test]
+    fn test_largest_amounts() {
+        let mut buffer = String::new();
+        for amount in [Amount::MAX, Amount::MIN] {
+            DecimalFormat::default().write(&amount, &mut buffer);
+            let (integer, fraction) = buffer.split_once('.').unwrap();
+            assert!(amount.to_string().starts_with(integer));
+            assert_eq!(fraction.len(), 4);
+        }
+    }
+
+    #[test]
+    fn test_buffer_is_reused() {
+        let mut buffer = String::from("previous content");
+        DecimalFormat::default().write(&Amount::from(1), &mut buffer);
+        assert_eq!(buffer, "1.0000");
+    }
+
+    #[test]
+    fn test_serialize_with_current_format() {
+        #[derive(serde::Serialize)]
+        struct Row {
+            #[serde(serialize_with = "serialize")]
+            amount: Amount,
+        }
+
+        let mut writer = csv::Writer::from_writer(vec![]);
+        writer
+            .serialize(Row {
+                amount: Amount::from_str("2.5").unwrap(),
+            })
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(writer.into_inner().unwrap()).unwrap(),
+            "amount\n2.5000\n"
+        );
+    }
+}
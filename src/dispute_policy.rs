@@ -0,0 +1,155 @@
+use crate::account::DepositedTransactionStatus;
+use crate::statemachine::{self, Effect, Event, Transition};
+
+use std::str::FromStr;
+
+// Rules of disputes which differ between payment partners. The default policy is the dispute state
+// machine as it is: a transaction may be disputed once, only with enough available funds, only on
+// an unlocked account, at any time and disputes stay open until they are resolved or charged back.
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct DisputePolicy {
+    // A resolved transaction may be disputed again.
+    pub redispute_resolved: bool,
+    // A dispute holds funds even if fewer are available, so available funds go negative.
+    pub hold_beyond_available: bool,
+    // Disputes, resolves and chargebacks are applied to locked accounts too.
+    pub locked_accounts: bool,
+    // Seconds after a transaction within which it may be disputed, by the configured clock.
+    pub window: Option<u64>,
+    // Seconds after which an open dispute is resolved on its own, by the configured clock.
+    pub resolve_after: Option<u64>,
+    // A representment unlocks the account if none of its transactions stays charged back.
+    pub unlock_on_representment: bool,
+}
+
+// Transition added by `redispute_resolved`.
+pub const REDISPUTE: Transition = Transition {
+    from: DepositedTransactionStatus::Resolved,
+    event: Event::Dispute,
+    to: DepositedTransactionStatus::Dispute,
+    effect: Effect::Hold,
+};
+
+impl DisputePolicy {
+    // Transition of the state machine allowed by the policy.
+    pub fn transition(
+        &self,
+        from: DepositedTransactionStatus,
+        event: Event,
+    ) -> Option<&'static Transition> {
+        statemachine::transition(from, event).or_else(|| {
+            (self.redispute_resolved && REDISPUTE.from == from && REDISPUTE.event == event)
+                .then_some(&REDISPUTE)
+        })
+    }
+
+    // Whether a transaction made at `time` may still be disputed at `now`.
+    pub fn is_within_window(&self, time: u64, now: u64) -> bool {
+        self.window
+            .is_none_or(|window| now.saturating_sub(time) <= window)
+    }
+
+    // Whether a dispute opened at `disputed_at` expired and should be resolved at `now`.
+    pub fn is_dispute_expired(&self, disputed_at: u64, now: u64) -> bool {
+        self.resolve_after
+            .is_some_and(|resolve_after| now.saturating_sub(disputed_at) > resolve_after)
+    }
+}
+
+// Policy is given as a list of rules separated by commas, for example
+// `redispute-resolved,locked-accounts,window:2592000,resolve-after:604800`. Rules which aren't given keep defaults.
+impl FromStr for DisputePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<DisputePolicy, String> {
+        let mut policy = DisputePolicy::default();
+        for rule in s.split(',').map(str::trim) {
+            match rule {
+                "redispute-resolved" => policy.redispute_resolved = true,
+                "hold-beyond-available" => policy.hold_beyond_available = true,
+                "locked-accounts" => policy.locked_accounts = true,
+                "unlock-on-representment" => policy.unlock_on_representment = true,
+                _ => match (
+                    rule.strip_prefix("window:").map(u64::from_str),
+                    rule.strip_prefix("resolve-after:").map(u64::from_str),
+                ) {
+                    (Some(Ok(window)), _) => policy.window = Some(window),
+                    (_, Some(Ok(resolve_after))) => policy.resolve_after = Some(resolve_after),
+                    _ => return Err(format!("unknown dispute policy rule: {}", rule)),
+                },
+            }
+        }
+        Ok(policy)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_policy() {
+        assert!("".parse::<DisputePolicy>().is_err());
+        assert_eq!(
+            "redispute-resolved, window:86400".parse::<DisputePolicy>(),
+            Ok(DisputePolicy {
+                redispute_resolved: true,
+                window: Some(86400),
+                ..DisputePolicy::default()
+            })
+        );
+        assert_eq!(
+            "resolve-after:604800"
+                .parse::<DisputePolicy>()
+                .map(|policy| policy.resolve_after),
+            Ok(Some(604800))
+        );
+        assert!("window:day".parse::<DisputePolicy>().is_err());
+        assert!("resolve-after:".parse::<DisputePolicy>().is_err());
+        assert!("redispute".parse::<DisputePolicy>().is_err());
+    }
+
+    #[test]
+    fn test_policy_transitions() {
+        let policy = DisputePolicy::default();
+        assert!(policy
+            .transition(DepositedTransactionStatus::Resolved, Event::Dispute)
+            .is_none());
+
+        let policy = DisputePolicy {
+            redispute_resolved: true,
+            ..DisputePolicy::default()
+        };
+        let transition = policy
+            .transition(DepositedTransactionStatus::Resolved, Event::Dispute)
+            .unwrap();
+        assert_eq!(transition.to, DepositedTransactionStatus::Dispute);
+        assert!(policy
+            .transition(DepositedTransactionStatus::Chargeback, Event::Dispute)
+            .is_none());
+    }
+
+    #[test]
+    fn test_dispute_window() {
+        assert!(DisputePolicy::default().is_within_window(0, u64::MAX));
+
+        let policy = DisputePolicy {
+            window: Some(60),
+            ..DisputePolicy::default()
+        };
+        assert!(policy.is_within_window(100, 160));
+        assert!(!policy.is_within_window(100, 161));
+    }
+
+    #[test]
+    fn test_dispute_expiry() {
+        assert!(!DisputePolicy::default().is_dispute_expired(0, u64::MAX));
+
+        let policy = DisputePolicy {
+            resolve_after: Some(60),
+            ..DisputePolicy::default()
+        };
+        assert!(!policy.is_dispute_expired(100, 160));
+        assert!(policy.is_dispute_expired(100, 161));
+    }
+}
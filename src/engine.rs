@@ -0,0 +1,2540 @@
+use crate::account::{Account, BalanceSnapshot};
+use crate::alert::{Alerts, Observation};
+use crate::amount::Amount;
+use crate::approval::{Approvals, DEFAULT_APPROVAL_EXPIRY};
+use crate::audit::AuditTrail;
+use crate::authorization::Authorization;
+use crate::cdc::ChangeStream;
+use crate::charged_back;
+use crate::clock::{Clock, ReplayClock};
+use crate::decimal_format;
+use crate::engine_config::EngineConfig;
+use crate::entry_limit::{self, EntryLimit, EvictionStrategy};
+use crate::error::{Failure, TransactionError};
+use crate::event_log::{Event, EventLog};
+use crate::fees::{self, Fees};
+use crate::fraud::FraudFlags;
+use crate::hash_chain::HashChain;
+use crate::history::{self, TransactionHistory};
+use crate::interest::HeldInterest;
+use crate::invariants;
+use crate::ledger::{Ledger, LedgerAccount};
+use crate::overflow::{self, OverflowPolicy};
+use crate::parallel_csv::ParallelCsvSource;
+use crate::period::{Period, Periods};
+use crate::rejections::{self, Rejection, RejectionReport, Rejections};
+use crate::reorder::{Reorder, ReorderingSource};
+use crate::shutdown::{Shutdown, StoppingSource};
+use crate::snapshot::{AccountViews, AccountsSnapshot};
+use crate::source::{CsvSource, JsonSource, NdjsonSource, SourceError, TransactionSource};
+use crate::state::{AccountState, EngineState, STATE_VERSION};
+use crate::store::AccountStore;
+use crate::throttle::Throttle;
+use crate::transaction::{Deposit, Process, Resolve, Transaction, TransactionType};
+
+use anyhow::{anyhow, bail, Context};
+use log::{error, info, warn};
+use serde::Serialize;
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::Read;
+use std::mem;
+use std::path::Path;
+use std::str::FromStr;
+
+// Books kept next to client accounts. Every applied transaction is recorded into them.
+pub struct Books {
+    pub periods: Periods,
+    pub ledger: Ledger,
+    pub audit: AuditTrail,
+    pub interest: Option<HeldInterest>,
+    pub fees: Option<Fees>,
+    pub fraud: Option<FraudFlags>,
+}
+
+impl Books {
+    pub fn new(period: &str) -> Books {
+        Books {
+            periods: Periods::new(period),
+            ledger: Ledger::new(),
+            audit: AuditTrail::new(),
+            interest: None,
+            fees: None,
+            fraud: None,
+        }
+    }
+
+    // Adds books kept for other clients, for example by another shard.
+    pub fn merge(&mut self, other: Books) {
+        self.periods.merge(other.periods);
+        self.ledger.merge(other.ledger);
+        match (self.interest.as_mut(), other.interest) {
+            (Some(interest), Some(other)) => interest.merge(other),
+            (None, other) => self.interest = other,
+            (Some(_), None) => {}
+        }
+        match (self.fees.as_mut(), other.fees) {
+            (Some(fees), Some(other)) => fees.merge(other),
+            (None, other) => self.fees = other,
+            (Some(_), None) => {}
+        }
+        match (self.fraud.as_mut(), other.fraud) {
+            (Some(fraud), Some(other)) => fraud.merge(other),
+            (None, other) => self.fraud = other,
+            (Some(_), None) => {}
+        }
+    }
+}
+
+// Controls over admin transactions: who may make them and which need a second approval.
+pub struct Controls {
+    pub authorization: Authorization,
+    pub approvals: Approvals,
+}
+
+impl Controls {
+    pub fn new() -> Controls {
+        Controls {
+            authorization: Authorization::new(),
+            approvals: Approvals::new(None, DEFAULT_APPROVAL_EXPIRY),
+        }
+    }
+}
+
+impl Default for Controls {
+    fn default() -> Controls {
+        Controls::new()
+    }
+}
+
+// Number of CSV rows read before they are applied when deposits are coalesced.
+const BATCH_SIZE: usize = 1024;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum InputFormat {
+    Csv,
+    // An array of objects with the same fields as CSV columns. It's meant for admin operations
+    // prepared by other tools.
+    Json,
+    // One JSON object per line, as emitted by upstream systems.
+    Ndjson,
+    // Columns with the same names as CSV columns. It can only be read from a file.
+    #[cfg(feature = "parquet")]
+    Parquet,
+}
+
+impl InputFormat {
+    // Format of a file is guessed from its extension, CSV is the default.
+    pub fn from_path(file_path: &OsString) -> InputFormat {
+        match Path::new(file_path)
+            .extension()
+            .and_then(|ext| ext.to_str())
+        {
+            Some("json") => InputFormat::Json,
+            Some("ndjson") | Some("jsonl") => InputFormat::Ndjson,
+            #[cfg(feature = "parquet")]
+            Some("parquet") => InputFormat::Parquet,
+            _ => InputFormat::Csv,
+        }
+    }
+}
+
+// Format is given as `csv`, `json` or `ndjson`.
+impl FromStr for InputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<InputFormat, String> {
+        match s {
+            "csv" => Ok(InputFormat::Csv),
+            "json" => Ok(InputFormat::Json),
+            "ndjson" => Ok(InputFormat::Ndjson),
+            #[cfg(feature = "parquet")]
+            "parquet" => Ok(InputFormat::Parquet),
+            _ => Err(format!("unknown input format: {}", s)),
+        }
+    }
+}
+
+// Stored deposit or withdrawal found by its transaction id.
+#[derive(Serialize, PartialEq, Debug)]
+pub struct TransactionInfo {
+    pub client: u16,
+    pub tx: u32,
+    #[serde(serialize_with = "decimal_format::serialize")]
+    pub amount: Amount,
+    pub status: &'static str,
+    #[serde(rename = "type")]
+    pub tx_type: &'static str,
+}
+
+// Opens an account on the first transaction of its client, whatever the transaction is.
+// Records opening of an account into the event log and audit chain.
+fn log_open(
+    event_log: &mut Option<EventLog>,
+    hash_chain: &mut Option<HashChain>,
+    client_id: u16,
+) -> anyhow::Result<()> {
+    if let Some(event_log) = event_log {
+        event_log.append_open(client_id)?;
+    }
+    if let Some(hash_chain) = hash_chain {
+        hash_chain.append(Event::open(client_id))?;
+    }
+    Ok(())
+}
+
+// Records an applied transaction into the event log and audit chain, after opening of its account
+// if the transaction `opened` it.
+fn log_applied(
+    event_log: &mut Option<EventLog>,
+    hash_chain: &mut Option<HashChain>,
+    opened: bool,
+    transaction: &Transaction,
+    period: &Period,
+    before: BalanceSnapshot,
+    account: &Account,
+) -> anyhow::Result<()> {
+    if opened {
+        log_open(event_log, hash_chain, account.id())?;
+    }
+    if let Some(event_log) = event_log {
+        event_log.append(transaction, period, before, account)?;
+    }
+    if let Some(hash_chain) = hash_chain {
+        hash_chain.append(Event::applied(transaction, period, before, account))?;
+    }
+    Ok(())
+}
+
+// Deposit admitted into coalesced deposits with its input line, the time it's stored with and its
+// accounting period.
+type AdmittedDeposit = (u64, Transaction, u64, Period);
+
+// Records deposits applied together into the event log and audit chain, after opening of their
+// account if they `opened` it.
+fn log_deposits(
+    event_log: &mut Option<EventLog>,
+    hash_chain: &mut Option<HashChain>,
+    opened: bool,
+    deposits: &[AdmittedDeposit],
+    accepted: &[Result<(), TransactionError>],
+    account: &Account,
+) -> anyhow::Result<()> {
+    if opened {
+        log_open(event_log, hash_chain, account.id())?;
+    }
+    for ((_, deposit, _, period), accepted) in deposits.iter().zip(accepted) {
+        if let (Ok(()), TransactionType::Deposit(amount)) = (accepted, &deposit.tx_type) {
+            if let Some(event_log) = event_log {
+                event_log.append_deposit(deposit, period, amount.amount, account)?;
+            }
+            if let Some(hash_chain) = hash_chain {
+                hash_chain.append(Event::deposit(deposit, period, amount.amount, account))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// Checks invariants of an account after a transaction, see `invariants`. A broken invariant fails
+// processing if checks are turned on and fails debug builds anyway.
+fn check_invariants(
+    enabled: bool,
+    transaction: &Transaction,
+    applied: bool,
+    before: &BalanceSnapshot,
+    account: &Account,
+    config: &EngineConfig,
+) -> anyhow::Result<()> {
+    if enabled {
+        invariants::check(&transaction.tx_type, applied, before, account, config).map_err(
+            |violation| {
+                anyhow!(
+                    "tx: {} broke an invariant of account {}: {}",
+                    transaction.tx_id(),
+                    account.id(),
+                    violation
+                )
+                .context(Failure::Internal)
+            },
+        )
+    } else {
+        debug_assert_eq!(
+            invariants::check(&transaction.tx_type, applied, before, account, config),
+            Ok(()),
+            "tx: {} broke an invariant of account {}",
+            transaction.tx_id(),
+            account.id()
+        );
+        Ok(())
+    }
+}
+
+// Moves stored transactions out of an account after a transaction was applied: all of them into the
+// history if there is one, or only those above the entry limit.
+fn release_entries(
+    history: &mut Option<Box<dyn TransactionHistory>>,
+    entry_limit: Option<EntryLimit>,
+    evicted: &mut HashSet<(u16, u32)>,
+    account: &mut Account,
+) -> anyhow::Result<()> {
+    let entry_limit = match (entry_limit, history.as_deref_mut()) {
+        (None, Some(history)) => return history::offload(history, account),
+        (None, None) => return Ok(()),
+        (Some(entry_limit), _) => entry_limit,
+    };
+
+    let client_id = account.id();
+    let evicted_transactions = entry_limit.evict(account);
+    match (entry_limit.strategy, history.as_deref_mut()) {
+        (EvictionStrategy::Spill, Some(history)) => {
+            for transaction in &evicted_transactions {
+                history.put(client_id, transaction)?;
+            }
+        }
+        (EvictionStrategy::Spill, None) => {
+            bail!("spilling transactions requires a transaction history")
+        }
+        (EvictionStrategy::Reject, _) => evicted.extend(
+            evicted_transactions
+                .iter()
+                .map(|transaction| (client_id, transaction.tx_id())),
+        ),
+        (EvictionStrategy::Compact, _) => {}
+    }
+
+    Ok(())
+}
+
+// Payments engine owns client accounts and applies transactions to them one by one.
+pub struct PaymentsEngine {
+    // In real world application this data won't be stored in memory (because we could have a lot
+    // of data) but in some database or even database + partially in memory to have a quick access.
+    accounts: HashMap<u16, Account>,
+    books: Books,
+    controls: Controls,
+    clock: Box<dyn Clock>,
+    store: Option<Box<dyn AccountStore>>,
+    history: Option<Box<dyn TransactionHistory>>,
+    entry_limit: Option<EntryLimit>,
+    // Transactions dropped by the entry limit, so transactions referencing them are rejected.
+    evicted: HashSet<(u16, u32)>,
+    config: EngineConfig,
+    coalesce_deposits: bool,
+    any_transaction_opens_account: bool,
+    check_invariants: bool,
+    throttle: Option<Throttle>,
+    reorder: Option<Reorder>,
+    shutdown: Option<Shutdown>,
+    alerts: Option<Alerts>,
+    event_log: Option<EventLog>,
+    hash_chain: Option<HashChain>,
+    changes: Option<ChangeStream>,
+    rejections: Rejections,
+    // Records read from input, including invalid and rejected ones.
+    rows: u64,
+    // Valid records read from input by their type.
+    rows_by_type: BTreeMap<&'static str, u64>,
+    // Position in input of the transaction being processed, used in the rejections report.
+    position: u64,
+    // Client of every stored deposit, so a transaction can be found without knowing its client.
+    tx_clients: HashMap<u32, u16>,
+    // Ids of deposits, withdrawals, adjustments and transfers, kept only if duplicates are
+    // rejected.
+    tx_ids: Option<HashSet<u32>>,
+    // Balances of accounts shared with snapshots, kept once the first snapshot is taken.
+    views: Option<AccountViews>,
+}
+
+impl PaymentsEngine {
+    pub fn new(books: Books, controls: Controls, clock: Box<dyn Clock>) -> PaymentsEngine {
+        PaymentsEngine {
+            accounts: HashMap::new(),
+            books,
+            controls,
+            clock,
+            store: None,
+            history: None,
+            entry_limit: None,
+            evicted: HashSet::new(),
+            config: EngineConfig::default(),
+            coalesce_deposits: false,
+            any_transaction_opens_account: false,
+            check_invariants: false,
+            throttle: None,
+            shutdown: None,
+            reorder: None,
+            alerts: None,
+            event_log: None,
+            hash_chain: None,
+            changes: None,
+            rejections: Rejections::default(),
+            rows: 0,
+            rows_by_type: BTreeMap::new(),
+            position: 0,
+            tx_clients: HashMap::new(),
+            tx_ids: None,
+            views: None,
+        }
+    }
+
+    // Accounts which are not in memory are taken from the store when a transaction for them
+    // arrives, for example dormant accounts from the archive.
+    pub fn set_store(&mut self, store: Box<dyn AccountStore>) {
+        self.store = Some(store);
+    }
+
+    // Deposits are kept in the history instead of in accounts. Transactions can't be found by
+    // their id alone then.
+    pub fn set_history(&mut self, history: Box<dyn TransactionHistory>) {
+        self.history = Some(history);
+    }
+
+    // Limits transactions stored in an account. With a history only transactions above the limit
+    // are moved into it instead of all of them.
+    pub fn set_entry_limit(&mut self, entry_limit: EntryLimit) {
+        self.entry_limit = Some(entry_limit);
+    }
+
+    // Policies by which transactions are applied, see `EngineConfig`.
+    pub fn set_config(&mut self, config: EngineConfig) {
+        self.config = config;
+    }
+
+    pub fn config(&self) -> &EngineConfig {
+        &self.config
+    }
+
+    // Consecutive deposits of the same client read in one batch update balances once.
+    pub fn set_coalesce_deposits(&mut self, coalesce_deposits: bool) {
+        self.coalesce_deposits = coalesce_deposits;
+    }
+
+    // Only deposits and credits by operators open accounts by default. Every transaction of an
+    // unknown client opens its account if it's turned on, even a rejected one, which was the
+    // original behavior.
+    pub fn set_any_transaction_opens_account(&mut self, any_transaction_opens_account: bool) {
+        self.any_transaction_opens_account = any_transaction_opens_account;
+    }
+
+    // Invariants of an account are checked after every transaction in debug builds. Turned on,
+    // they are checked in any build and a broken one stops processing with an error.
+    pub fn set_check_invariants(&mut self, check_invariants: bool) {
+        self.check_invariants = check_invariants;
+    }
+
+    // A deposit, withdrawal, adjustment or transfer with an id used by any of them before is
+    // rejected instead of being applied. Ids are kept for the whole run, so it's meant for checks
+    // of input files.
+    pub fn set_reject_duplicates(&mut self, reject_duplicates: bool) {
+        self.tx_ids = reject_duplicates.then(HashSet::new);
+    }
+
+    // Limits rows read from sources, for example for backfills on a shared host.
+    pub fn set_throttle(&mut self, throttle: Throttle) {
+        self.throttle = Some(throttle);
+    }
+
+    // Sources are read until a shutdown is requested, transactions read until then are applied.
+    pub fn set_shutdown(&mut self, shutdown: Shutdown) {
+        self.shutdown = Some(shutdown);
+    }
+
+    // Rows of sources are sorted within the window of the reorder before they are applied.
+    pub fn set_reorder(&mut self, reorder: Reorder) {
+        self.reorder = Some(reorder);
+    }
+
+    // Alert rules are evaluated after every applied row.
+    pub fn set_alerts(&mut self, alerts: Alerts) {
+        self.alerts = Some(alerts);
+    }
+
+    // Every accepted transaction is appended to the event log before it's recorded into books.
+    pub fn set_event_log(&mut self, event_log: EventLog) {
+        self.event_log = Some(event_log);
+    }
+
+    // Every opened account and accepted transaction is chained by hashes, like in the event log.
+    pub fn set_hash_chain(&mut self, hash_chain: HashChain) {
+        self.hash_chain = Some(hash_chain);
+    }
+
+    pub fn hash_chain(&self) -> Option<&HashChain> {
+        self.hash_chain.as_ref()
+    }
+
+    // Every change of an account made by an accepted transaction is streamed as it happens.
+    pub fn set_changes(&mut self, changes: ChangeStream) {
+        self.changes = Some(changes);
+    }
+
+    // Every skipped or failed record is written into the rejections report with its position in
+    // input and a reason code.
+    pub fn set_rejections(&mut self, rejections: RejectionReport) {
+        self.rejections.set_report(rejections);
+    }
+
+    // Positions of records processed from now on are in `file_path`, when several files are
+    // processed one after another.
+    pub fn set_input_file(&mut self, file_path: &OsString) {
+        self.rejections
+            .set_input_file(file_path.to_string_lossy().into_owned());
+    }
+
+    // Starts from accounts of a previous run instead of from no accounts, see `state`.
+    pub fn seed_accounts(&mut self, accounts: Vec<Account>) -> anyhow::Result<()> {
+        for account in accounts {
+            let client_id = account.id();
+            match self.accounts.entry(client_id) {
+                Entry::Occupied(_) => bail!("client {} already has an account", account.id()),
+                Entry::Vacant(entry) => entry.insert(account),
+            };
+            self.update_view(client_id);
+        }
+
+        Ok(())
+    }
+
+    // Complete state of the engine, which `import_state` restores without any loss. Accounts in
+    // the store and deposits in the history stay there.
+    pub fn export_state(&self) -> EngineState {
+        let mut accounts: Vec<AccountState> = self
+            .accounts
+            .values()
+            .map(AccountState::from_account)
+            .collect();
+        accounts.sort_by_key(|account| account.client);
+        let mut evicted: Vec<(u16, u32)> = self.evicted.iter().copied().collect();
+        evicted.sort();
+
+        EngineState {
+            version: STATE_VERSION,
+            now: self.clock.now(),
+            accounts,
+            evicted,
+            tx_clients: self
+                .tx_clients
+                .iter()
+                .map(|(tx, client)| (*tx, *client))
+                .collect(),
+        }
+    }
+
+    pub fn import_state(&mut self, state: EngineState) -> anyhow::Result<()> {
+        if state.version != STATE_VERSION {
+            bail!(
+                "state has version {} but this build supports only version {}",
+                state.version,
+                STATE_VERSION
+            );
+        }
+        self.clock.observe(Some(state.now));
+        self.seed_accounts(
+            state
+                .accounts
+                .into_iter()
+                .map(AccountState::into_account)
+                .collect::<anyhow::Result<_>>()?,
+        )?;
+        self.evicted.extend(state.evicted);
+        self.tx_clients.extend(state.tx_clients);
+
+        Ok(())
+    }
+
+    pub fn accounts(&self) -> &HashMap<u16, Account> {
+        &self.accounts
+    }
+
+    pub fn into_accounts(self) -> HashMap<u16, Account> {
+        self.accounts
+    }
+
+    // Finds a stored deposit or withdrawal of any client by its transaction id.
+    pub fn find_transaction(&self, tx_id: u32) -> Option<TransactionInfo> {
+        let client_id = self.tx_clients.get(&tx_id)?;
+        let transaction = self.accounts.get(client_id)?.find_transaction(tx_id)?;
+        Some(TransactionInfo {
+            client: *client_id,
+            tx: tx_id,
+            amount: transaction.amount(),
+            status: transaction.status().name(),
+            tx_type: transaction.direction().name(),
+        })
+    }
+
+    // Stored deposits and withdrawals of a client in the order they were made.
+    pub fn client_transactions(&self, client_id: u16) -> Vec<TransactionInfo> {
+        let account = match self.accounts.get(&client_id) {
+            None => return Vec::new(),
+            Some(account) => account,
+        };
+        account
+            .transactions()
+            .iter()
+            .map(|transaction| TransactionInfo {
+                client: client_id,
+                tx: transaction.tx_id(),
+                amount: transaction.amount(),
+                status: transaction.status().name(),
+                tx_type: transaction.direction().name(),
+            })
+            .collect()
+    }
+
+    pub fn books(&self) -> &Books {
+        &self.books
+    }
+
+    pub fn now(&self) -> u64 {
+        self.clock.now()
+    }
+
+    fn rehydrate(&mut self, client_id: u16) -> anyhow::Result<()> {
+        if let (Some(store), Entry::Vacant(entry)) =
+            (self.store.as_mut(), self.accounts.entry(client_id))
+        {
+            if let Some(account) = store.take(client_id).context(Failure::Unavailable)? {
+                for transaction in account.transactions() {
+                    self.tx_clients.insert(transaction.tx_id(), client_id);
+                }
+                entry.insert(account);
+                self.update_view(client_id);
+            }
+        }
+
+        Ok(())
+    }
+
+    // Keeps the view of an account up to date, once snapshots are taken.
+    fn update_view(&mut self, client_id: u16) {
+        if let Some(views) = self.views.as_mut() {
+            views.update(client_id, self.accounts.get(&client_id));
+        }
+    }
+
+    // Point-in-time copy of balances of all accounts in memory. The first snapshot indexes all
+    // accounts, later ones only share what changed since, so a snapshot may be exported while the
+    // engine keeps applying transactions.
+    pub fn snapshot_accounts(&mut self) -> AccountsSnapshot {
+        self.views
+            .get_or_insert_with(|| AccountViews::new(self.accounts.values()))
+            .snapshot()
+    }
+
+    // Counts a skipped or failed transaction and records it into the rejections report, if there
+    // is one.
+    fn reject(
+        &mut self,
+        transaction: &Transaction,
+        code: &'static str,
+        reason: String,
+    ) -> anyhow::Result<()> {
+        self.rejections
+            .record(&Rejection::of(self.position, transaction, code, reason))
+    }
+
+    // Applies a single transaction to accounts and records it into books.
+    pub fn process_transaction(&mut self, transaction: Transaction) -> anyhow::Result<()> {
+        let _config = self.config.enter();
+        // Accounts are taken from a store before anything is recorded, so a transaction which
+        // failed on an unavailable store may be submitted again.
+        match &transaction.tx_type {
+            TransactionType::Transfer(transfer) => {
+                self.rehydrate(transfer.from_client)?;
+                self.rehydrate(transfer.to_client)?;
+            }
+            _ => self.rehydrate(transaction.client_id())?,
+        }
+        self.rows += 1;
+        *self
+            .rows_by_type
+            .entry(transaction.tx_type.name())
+            .or_default() += 1;
+        self.clock.observe(transaction.timestamp);
+        if self.is_duplicate(&transaction)? {
+            return Ok(());
+        }
+        self.resolve_expired_disputes(&transaction)?;
+        if let TransactionType::Transfer(_) = transaction.tx_type {
+            return self.process_transfer(transaction);
+        }
+        self.apply(transaction, true)
+    }
+
+    // Rejects a transaction which creates a transaction id that was already used, if duplicates
+    // are rejected.
+    fn is_duplicate(&mut self, transaction: &Transaction) -> anyhow::Result<bool> {
+        if let Some(tx_ids) = self.tx_ids.as_mut() {
+            let creates = matches!(
+                transaction.tx_type,
+                TransactionType::Deposit(_)
+                    | TransactionType::Withdrawal(_)
+                    | TransactionType::Adjust(_)
+                    | TransactionType::Transfer(_)
+            );
+            if creates && !tx_ids.insert(transaction.tx_id()) {
+                warn!(
+                    "tx: {} is ignored as its id is duplicated",
+                    transaction.tx_id()
+                );
+                self.reject(
+                    transaction,
+                    rejections::DUPLICATE_TRANSACTION,
+                    format!("transaction {} already exists", transaction.tx_id()),
+                )?;
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    // Applies a single transaction read at `position` of input, which is used in reports.
+    pub fn process_transaction_at(
+        &mut self,
+        position: u64,
+        transaction: Transaction,
+    ) -> anyhow::Result<()> {
+        self.position = position;
+        self.process_transaction(transaction)
+    }
+
+    // Resolves disputes of the transaction's account which stayed open longer than the dispute
+    // policy allows, before the transaction is applied. They are resolved like by a resolve row,
+    // so funds released by them are booked too.
+    fn resolve_expired_disputes(&mut self, transaction: &Transaction) -> anyhow::Result<()> {
+        let policy = self.config.dispute_policy;
+        if policy.resolve_after.is_none() {
+            return Ok(());
+        }
+        let client_id = transaction.client_id();
+        self.rehydrate(client_id)?;
+        let expired = match self.accounts.get(&client_id) {
+            None => return Ok(()),
+            Some(account) => account.expired_disputes(&policy, self.clock.now()),
+        };
+        for tx_id in expired {
+            info!(
+                "tx: {} dispute expired in account {}. resolving it.",
+                tx_id, client_id
+            );
+            let resolve = Transaction {
+                tx_type: TransactionType::Resolve(Resolve { client_id, tx_id }),
+                timestamp: transaction.timestamp,
+                operator: None,
+                reason: None,
+                approved_by: None,
+            };
+            self.apply(resolve, false)?;
+        }
+
+        Ok(())
+    }
+
+    // Applies both legs of a transfer or none of them. Legs are tried on copies of their accounts
+    // first, so a leg which would fail leaves both accounts as they were.
+    fn process_transfer(&mut self, transaction: Transaction) -> anyhow::Result<()> {
+        let (debit, credit) = match &transaction.tx_type {
+            TransactionType::Transfer(transfer) => transfer.legs(),
+            _ => return self.apply(transaction, true),
+        };
+        self.rehydrate(debit.client_id)?;
+        self.rehydrate(credit.client_id)?;
+        let mut from = match self.accounts.get(&debit.client_id) {
+            None => {
+                warn!(
+                    "tx: {} is ignored as client {} has no account",
+                    debit.tx_id, debit.client_id
+                );
+                return self.reject(
+                    &transaction,
+                    rejections::NO_ACCOUNT,
+                    format!("client {} has no account", debit.client_id),
+                );
+            }
+            Some(account) => account.trial_copy(),
+        };
+        // The receiving account is opened by the transfer if it doesn't exist yet.
+        let mut to = match self.accounts.get(&credit.client_id) {
+            None => Account::new(credit.client_id),
+            Some(account) => account.trial_copy(),
+        };
+        overflow::take_overflowed();
+        let tried = if debit.client_id == credit.client_id {
+            Err(TransactionError::SameAccount)
+        } else {
+            debit
+                .process_with(&mut from, &self.config)
+                .and_then(|_| credit.process_with(&mut to, &self.config))
+        };
+        // An overflow saturated by the overflow policy is reported when the leg is applied.
+        overflow::take_overflowed();
+        if let Err(error) = tried {
+            info!(
+                "tx: {} rejected [{}]: {}",
+                transaction.tx_id(),
+                error.code(),
+                error
+            );
+            return self.reject(&transaction, error.code(), error.to_string());
+        }
+
+        let leg = |tx_type| Transaction {
+            tx_type,
+            timestamp: transaction.timestamp,
+            operator: transaction.operator.clone(),
+            reason: transaction.reason.clone(),
+            approved_by: None,
+        };
+        let rejected = self.rejections.total();
+        self.apply(leg(TransactionType::Withdrawal(debit)), false)?;
+        self.apply(leg(TransactionType::Deposit(credit)), false)?;
+        if self.rejections.total() != rejected {
+            bail!(
+                "transfer {} was applied only partially",
+                transaction.tx_id()
+            );
+        }
+
+        Ok(())
+    }
+
+    // Legs of a transfer are applied without fees, as they were checked without them.
+    fn apply(&mut self, transaction: Transaction, charge_fees: bool) -> anyhow::Result<()> {
+        if !self.has_admin_fields(&transaction)? {
+            return Ok(());
+        }
+
+        let client_id = transaction.client_id();
+        self.rehydrate(client_id)?;
+
+        // Transactions of an unknown client which can't open its account are ignored instead of
+        // adding an empty account to the output.
+        if !self.any_transaction_opens_account
+            && !transaction.tx_type.opens_account()
+            && !self.accounts.contains_key(&client_id)
+        {
+            warn!(
+                "tx: {} is ignored as client {} has no account",
+                transaction.tx_id(),
+                client_id
+            );
+            return self.reject(
+                &transaction,
+                rejections::NO_ACCOUNT,
+                format!("client {} has no account", client_id),
+            );
+        }
+        // A new account is kept only if the transaction is applied to it, so a rejected deposit
+        // doesn't add an empty account to the output.
+        let opened = !self.accounts.contains_key(&client_id);
+        let applied = self.apply_to_account(transaction, charge_fees, opened)?;
+        if opened && !applied {
+            if self.any_transaction_opens_account {
+                log_open(&mut self.event_log, &mut self.hash_chain, client_id)?;
+            } else {
+                self.accounts.remove(&client_id);
+            }
+        }
+        self.update_view(client_id);
+
+        Ok(())
+    }
+
+    // Rejects an admin transaction without an operator or a reason.
+    fn has_admin_fields(&mut self, transaction: &Transaction) -> anyhow::Result<bool> {
+        if transaction.has_admin_fields() {
+            return Ok(true);
+        }
+        self.reject(
+            transaction,
+            rejections::MISSING_ADMIN_FIELDS,
+            String::from("admin transaction has no operator or reason"),
+        )?;
+        Ok(false)
+    }
+
+    // Checks that an operator is allowed to make a transaction on the account of its client,
+    // which must exist, and holds adjustments which need an approval. Returns the transaction
+    // which should be applied now.
+    fn admit(&mut self, transaction: Transaction) -> anyhow::Result<Option<Transaction>> {
+        let account = &self.accounts[&transaction.client_id()];
+        if !self.controls.authorization.authorize(&transaction, account) {
+            self.reject(
+                &transaction,
+                rejections::UNAUTHORIZED,
+                String::from("operator is not authorized"),
+            )?;
+            return Ok(None);
+        }
+        // Adjustments waiting for an approval aren't rejected, only approvals which can't be
+        // applied are.
+        let rejected_approval =
+            matches!(transaction.tx_type, TransactionType::Approve(_)).then(|| {
+                Rejection::of(
+                    self.position,
+                    &transaction,
+                    rejections::APPROVAL_REJECTED,
+                    String::from("adjustment can not be approved"),
+                )
+            });
+        let account = &self.accounts[&transaction.client_id()];
+        let admitted = self.controls.approvals.review(
+            transaction,
+            self.clock.now(),
+            &self.controls.authorization,
+            account,
+        );
+        if let (None, Some(rejection)) = (&admitted, rejected_approval) {
+            self.rejections.record(&rejection)?;
+        }
+        Ok(admitted)
+    }
+
+    // Applies a transaction to the account of its client, opening it if `opened`. Returns whether
+    // the transaction was applied.
+    fn apply_to_account(
+        &mut self,
+        transaction: Transaction,
+        charge_fees: bool,
+        opened: bool,
+    ) -> anyhow::Result<bool> {
+        let client_id = transaction.client_id();
+        let now = self.clock.now();
+        self.accounts
+            .entry(client_id)
+            .or_insert_with(|| Account::new(client_id))
+            .set_last_activity(now);
+        let transaction = match self.admit(transaction)? {
+            None => return Ok(false),
+            Some(transaction) => transaction,
+        };
+        let account = self
+            .accounts
+            .get_mut(&client_id)
+            .context("admitted transaction has no account")?;
+
+        if let TransactionType::Dispute(_)
+        | TransactionType::Resolve(_)
+        | TransactionType::Chargeback(_)
+        | TransactionType::Representment(_) = &transaction.tx_type
+        {
+            if self.evicted.contains(&(client_id, transaction.tx_id())) {
+                warn!(
+                    "tx: {} {} rejected [{}]: transaction of account {} was evicted",
+                    transaction.tx_id(),
+                    transaction.tx_type.name(),
+                    entry_limit::REJECTION_CODE,
+                    client_id
+                );
+                return self
+                    .reject(
+                        &transaction,
+                        entry_limit::REJECTION_CODE,
+                        String::from("transaction was evicted"),
+                    )
+                    .map(|_| false);
+            }
+            if let Some(history) = self.history.as_deref() {
+                history::restore(history, account, transaction.tx_id())?;
+            }
+            // A transaction of another client is never looked up in the claimed account.
+            if let Some(&owner) = self.tx_clients.get(&transaction.tx_id()) {
+                if owner != client_id && account.find_transaction(transaction.tx_id()).is_none() {
+                    let error = TransactionError::ClientMismatch {
+                        client: client_id,
+                        owner,
+                    };
+                    info!(
+                        "tx: {} rejected [{}]: {}",
+                        transaction.tx_id(),
+                        error.code(),
+                        error
+                    );
+                    return self
+                        .reject(&transaction, error.code(), error.to_string())
+                        .map(|_| false);
+                }
+            }
+        }
+
+        let period = self.books.periods.period_of(transaction.timestamp);
+        overflow::take_overflowed();
+        let was_locked = account.is_locked();
+        let before = account.balances();
+        let debt = account.debt();
+        let fee = match self.books.fees.as_ref() {
+            Some(fees) if charge_fees => fees.fee(&transaction.tx_type, account),
+            _ => Amount::from(0),
+        };
+        // The account keeps the change only after its event is flushed into the event log, so the
+        // log never misses a change an account holds. It's put back from a copy if the log fails.
+        let original =
+            (self.event_log.is_some() || self.hash_chain.is_some()).then(|| account.clone());
+        let result = fees::process(&transaction.tx_type, account, fee, &self.config);
+        if result.is_ok() {
+            account.tag_period(transaction.tx_id(), &period);
+        }
+        if let Err(error) = result {
+            info!(
+                "tx: {} rejected [{}]: {}",
+                transaction.tx_id(),
+                error.code(),
+                error
+            );
+        }
+        let processed = result.is_ok();
+        let escalated = charged_back::is_escalated(self.config.charged_back_policy, &result);
+        let overflowed = overflow::take_overflowed();
+        let logged =
+            if processed && !(overflowed && self.config.overflow_policy == OverflowPolicy::Abort) {
+                log_applied(
+                    &mut self.event_log,
+                    &mut self.hash_chain,
+                    opened,
+                    &transaction,
+                    &period,
+                    before,
+                    account,
+                )
+            } else {
+                Ok(())
+            };
+        if let Err(error) = logged {
+            if let Some(original) = original {
+                *account = original;
+            }
+            return Err(error);
+        }
+        if overflowed {
+            match self.config.overflow_policy {
+                OverflowPolicy::Reject => {}
+                OverflowPolicy::Saturate => {
+                    warn!(
+                        "tx: {} saturated balances of account {}",
+                        transaction.tx_id(),
+                        client_id
+                    );
+                    self.books.audit.record_saturated(&transaction, account)?;
+                }
+                OverflowPolicy::Abort => {
+                    return Err(anyhow!(
+                        "tx: {} overflowed balances of account {}",
+                        transaction.tx_id(),
+                        client_id
+                    )
+                    .context(Failure::InvalidInput))
+                }
+            }
+        }
+        if escalated {
+            self.books.audit.record_escalated(&transaction, account)?;
+        }
+        // Saturated balances break invariants by the overflow policy.
+        if !overflowed {
+            check_invariants(
+                self.check_invariants,
+                &transaction,
+                processed,
+                &before,
+                account,
+                &self.config,
+            )?;
+        }
+        if processed {
+            if account.debt() > debt {
+                let increase = account.debt() - debt;
+                self.books
+                    .audit
+                    .record_debt(&transaction, account, increase)?;
+            }
+            if let Some(changes) = self.changes.as_mut() {
+                changes.record(&transaction, before, account)?;
+            }
+            match (&transaction.tx_type, &self.history) {
+                (TransactionType::Deposit(deposit), None) => {
+                    self.tx_clients.insert(deposit.tx_id, client_id);
+                }
+                (TransactionType::Withdrawal(withdrawal), None)
+                    if self.config.dispute_withdrawals =>
+                {
+                    self.tx_clients.insert(withdrawal.tx_id, client_id);
+                }
+                _ => {}
+            }
+            let role = transaction
+                .operator
+                .as_deref()
+                .and_then(|operator| self.controls.authorization.role(operator));
+            self.books
+                .periods
+                .record(&period, &transaction.tx_type, account);
+            self.books.ledger.record(&transaction.tx_type, account);
+            if !fee.is_zero() {
+                self.books.ledger.post(
+                    LedgerAccount::ClientAvailable,
+                    LedgerAccount::FeeRevenue,
+                    fee,
+                );
+                if let Some(fees) = self.books.fees.as_mut() {
+                    fees.record(&transaction.tx_type, client_id, fee);
+                }
+            }
+            self.books.audit.record(&transaction, account, role)?;
+        }
+        if let Some(interest) = self.books.interest.as_mut() {
+            interest.record(account, self.clock.now());
+        }
+        if let Some(fraud) = self.books.fraud.as_mut() {
+            fraud.observe(self.position, &transaction, processed, account);
+        }
+        if let Some(alerts) = self.alerts.as_mut() {
+            alerts.observe(&Observation {
+                client: client_id,
+                tx: transaction.tx_id(),
+                chargeback: processed
+                    && matches!(transaction.tx_type, TransactionType::Chargeback(_)),
+                locked: !was_locked && account.is_locked(),
+                held: account.held(),
+            });
+        }
+        release_entries(
+            &mut self.history,
+            self.entry_limit,
+            &mut self.evicted,
+            account,
+        )?;
+        if let Err(error) = result {
+            self.reject(&transaction, error.code(), error.to_string())?;
+        }
+
+        Ok(processed)
+    }
+
+    pub fn process_file(&mut self, file_path: &OsString) -> anyhow::Result<()> {
+        self.process_file_as(file_path, InputFormat::from_path(file_path))
+    }
+
+    pub fn process_file_as(
+        &mut self,
+        file_path: &OsString,
+        format: InputFormat,
+    ) -> anyhow::Result<()> {
+        let file = File::open(file_path)
+            .with_context(|| format!("can not open input file {:?}", file_path))?;
+
+        let processed = match format {
+            #[cfg(feature = "parquet")]
+            InputFormat::Parquet => self.process_parquet(file),
+            _ => self.process_input(file, format),
+        };
+        processed.with_context(|| format!("can not process input file {:?}", file_path))
+    }
+
+    pub fn process_input<R: Read>(&mut self, input: R, format: InputFormat) -> anyhow::Result<()> {
+        match format {
+            InputFormat::Csv => self.process_csv(input),
+            InputFormat::Json => self.process_json(input),
+            InputFormat::Ndjson => self.process_ndjson(input),
+            #[cfg(feature = "parquet")]
+            InputFormat::Parquet => bail!("Parquet input can only be read from a file"),
+        }
+    }
+
+    pub fn process_json<R: Read>(&mut self, input: R) -> anyhow::Result<()> {
+        let mut source = JsonSource::new(input)
+            .context("can not parse JSON input")
+            .context(Failure::InvalidInput)?;
+        self.process_source(&mut source)
+    }
+
+    // Amounts in JSON are deserialized from their shortest representation, so amounts with four
+    // decimal places are read exactly.
+    pub fn process_ndjson<R: Read>(&mut self, input: R) -> anyhow::Result<()> {
+        self.process_source(&mut NdjsonSource::new(input))
+    }
+
+    #[cfg(feature = "parquet")]
+    pub fn process_parquet(&mut self, file: File) -> anyhow::Result<()> {
+        self.process_source(&mut crate::parquet_input::ParquetSource::new(file)?)
+    }
+
+    // Processes transactions in CSV format from any reader, for example standard input.
+    pub fn process_csv<R: Read>(&mut self, input: R) -> anyhow::Result<()> {
+        self.process_source(&mut CsvSource::new(input))
+    }
+
+    // Processes transactions in CSV format parsed by `threads` threads.
+    pub fn process_csv_parallel<R: Read>(
+        &mut self,
+        input: R,
+        threads: usize,
+    ) -> anyhow::Result<()> {
+        self.process_source(&mut ParallelCsvSource::new(input, threads)?)
+    }
+
+    // Processes all transactions of a source. Invalid records are skipped, but if the source
+    // fails processing stops.
+    pub fn process_source<S: TransactionSource + ?Sized>(
+        &mut self,
+        source: &mut S,
+    ) -> anyhow::Result<()> {
+        let _config = self.config.enter();
+        match self.shutdown.clone() {
+            None => self.process_reordered(source),
+            Some(shutdown) => self.process_reordered(&mut StoppingSource::new(source, shutdown)),
+        }
+    }
+
+    fn process_reordered<S: TransactionSource + ?Sized>(
+        &mut self,
+        source: &mut S,
+    ) -> anyhow::Result<()> {
+        match self.reorder {
+            None => self.process_records(source),
+            Some(reorder) => self.process_records(&mut ReorderingSource::new(source, reorder)),
+        }
+    }
+
+    fn process_records<S: TransactionSource + ?Sized>(
+        &mut self,
+        source: &mut S,
+    ) -> anyhow::Result<()> {
+        let mut batch = Vec::new();
+        while let Some(result) = source.next() {
+            if let Some(throttle) = &mut self.throttle {
+                throttle.wait();
+            }
+            let position = source.position();
+            let transaction = match result {
+                Ok(transaction) => transaction,
+                Err(SourceError::Invalid(message)) => {
+                    self.skip_invalid(source.unit(), position, message, source.record())?;
+                    continue;
+                }
+                Err(SourceError::Failed(error)) => {
+                    return Err(error.context(format!(
+                        "can not read {} {}",
+                        source.unit(),
+                        position
+                    )))
+                }
+            };
+
+            if !self.coalesce_deposits {
+                self.position = position;
+                self.process_transaction(transaction)
+                    .with_context(|| format!("can not process {} {}", source.unit(), position))?;
+                continue;
+            }
+            batch.push((position, transaction));
+            if batch.len() == BATCH_SIZE {
+                let full = mem::replace(&mut batch, Vec::with_capacity(BATCH_SIZE));
+                self.apply_batch(full, source.unit())?;
+            }
+        }
+        self.apply_batch(batch, source.unit())?;
+
+        self.flush()
+    }
+
+    // Applies a batch of transactions in order. With coalescing of deposits turned on, consecutive
+    // deposits of the same client update balances once.
+    pub fn process_batch(&mut self, batch: Vec<(u64, Transaction)>) -> anyhow::Result<()> {
+        let _config = self.config.enter();
+        self.apply_batch(batch, "line")
+    }
+
+    fn apply_batch(&mut self, batch: Vec<(u64, Transaction)>, unit: &str) -> anyhow::Result<()> {
+        let mut batch = batch.into_iter().peekable();
+        while let Some((line, transaction)) = batch.next() {
+            let is_deposit = |transaction: &Transaction| {
+                matches!(transaction.tx_type, TransactionType::Deposit(_))
+            };
+            if !self.coalesce_deposits || !is_deposit(&transaction) {
+                self.position = line;
+                self.process_transaction(transaction)
+                    .with_context(|| format!("can not process {} {}", unit, line))?;
+                continue;
+            }
+
+            let client_id = transaction.client_id();
+            let mut deposits = vec![(line, transaction)];
+            while let Some((_, next)) = batch.peek() {
+                if !is_deposit(next) || next.client_id() != client_id {
+                    break;
+                }
+                if let Some(next) = batch.next() {
+                    deposits.push(next);
+                }
+            }
+            self.process_deposits(deposits)
+                .with_context(|| format!("can not process deposits from {} {}", unit, line))?;
+        }
+
+        Ok(())
+    }
+
+    // Applies consecutive deposits of one client with a single balance update. Every deposit
+    // still goes through the checks of `process_transaction` and `apply` and is stored in account
+    // and recorded into books on its own. Expiry of disputes depends on time between deposits, so
+    // with it, on locked accounts and for sums which would overflow deposits are applied row by
+    // row. Deposits carry no fees.
+    fn process_deposits(&mut self, deposits: Vec<(u64, Transaction)>) -> anyhow::Result<()> {
+        let client_id = deposits[0].1.client_id();
+        self.rehydrate(client_id)?;
+        let amounts: Vec<&Deposit> = deposits
+            .iter()
+            .filter_map(|(_, transaction)| match &transaction.tx_type {
+                TransactionType::Deposit(deposit) => Some(deposit),
+                _ => None,
+            })
+            .collect();
+        let coalesced = deposits.len() > 1
+            && self.config.dispute_policy.resolve_after.is_none()
+            && self.accounts.get(&client_id).is_none_or(|account| {
+                account.fits_deposits(&amounts)
+                    && (!account.is_locked() || self.config.locked_policy.deposits)
+            });
+        if !coalesced {
+            for (line, deposit) in deposits {
+                self.position = line;
+                self.process_transaction(deposit)?;
+            }
+            return Ok(());
+        }
+
+        self.rows += deposits.len() as u64;
+        *self.rows_by_type.entry("deposit").or_default() += deposits.len() as u64;
+        let opened = !self.accounts.contains_key(&client_id);
+        self.accounts
+            .entry(client_id)
+            .or_insert_with(|| Account::new(client_id));
+        // Deposits which pass the checks, with the time they are stored with.
+        let mut admitted = Vec::with_capacity(deposits.len());
+        for (line, deposit) in deposits {
+            self.position = line;
+            self.clock.observe(deposit.timestamp);
+            if self.is_duplicate(&deposit)? || !self.has_admin_fields(&deposit)? {
+                continue;
+            }
+            if let Some(deposit) = self.admit(deposit)? {
+                let period = self.books.periods.period_of(deposit.timestamp);
+                admitted.push((line, deposit, self.clock.now(), period));
+            }
+        }
+
+        let account = self
+            .accounts
+            .get_mut(&client_id)
+            .context("coalesced deposits have no account")?;
+        // Like a single transaction, the account keeps the deposits only after their events are
+        // flushed into the event log.
+        let original =
+            (self.event_log.is_some() || self.hash_chain.is_some()).then(|| account.clone());
+        account.set_last_activity(self.clock.now());
+        // Balances after every deposit of the batch are rebuilt for the change stream.
+        let before = account.balances();
+        let mut balances = before;
+        let amounts: Vec<(&Deposit, u64)> = admitted
+            .iter()
+            .filter_map(|(_, transaction, time, _)| match &transaction.tx_type {
+                TransactionType::Deposit(deposit) => Some((deposit, *time)),
+                _ => None,
+            })
+            .collect();
+        let accepted = account.deposit_coalesced(&amounts, &self.config);
+        for ((_, deposit, _, period), accepted) in admitted.iter().zip(accepted.iter().flatten()) {
+            if accepted.is_ok() {
+                account.tag_period(deposit.tx_id(), period);
+            }
+        }
+        let applied = accepted
+            .as_ref()
+            .is_some_and(|accepted| accepted.iter().any(Result::is_ok));
+        let logged = match &accepted {
+            Some(accepted) if applied => log_deposits(
+                &mut self.event_log,
+                &mut self.hash_chain,
+                opened,
+                &admitted,
+                accepted,
+                account,
+            ),
+            _ => Ok(()),
+        };
+        if let Err(error) = logged {
+            if let Some(original) = original {
+                *account = original;
+            }
+            return Err(error);
+        }
+        let accepted = accepted.context("coalesced deposits overflowed")?;
+        if !admitted.is_empty() {
+            check_invariants(
+                self.check_invariants,
+                &admitted[0].1,
+                applied,
+                &before,
+                account,
+                &self.config,
+            )?;
+        }
+
+        for ((line, deposit, time, period), accepted) in admitted.iter().zip(accepted) {
+            self.position = *line;
+            match &accepted {
+                Ok(()) => {
+                    if let (Some(changes), TransactionType::Deposit(amount)) =
+                        (self.changes.as_mut(), &deposit.tx_type)
+                    {
+                        changes.record_deposit(deposit, amount.amount, balances, account)?;
+                        balances.available += amount.amount;
+                        balances.total += amount.amount;
+                    }
+                    if self.history.is_none() {
+                        self.tx_clients.insert(deposit.tx_id(), client_id);
+                    }
+                    let role = deposit
+                        .operator
+                        .as_deref()
+                        .and_then(|operator| self.controls.authorization.role(operator));
+                    self.books.periods.record(period, &deposit.tx_type, account);
+                    self.books.ledger.record(&deposit.tx_type, account);
+                    self.books.audit.record(deposit, account, role)?;
+                }
+                Err(error) => info!(
+                    "tx: {} rejected [{}]: {}",
+                    deposit.tx_id(),
+                    error.code(),
+                    error
+                ),
+            }
+            if let Some(interest) = self.books.interest.as_mut() {
+                interest.record(account, *time);
+            }
+            if let Some(fraud) = self.books.fraud.as_mut() {
+                fraud.observe(*line, deposit, accepted.is_ok(), account);
+            }
+            if let Some(alerts) = self.alerts.as_mut() {
+                alerts.observe(&Observation {
+                    client: client_id,
+                    tx: deposit.tx_id(),
+                    chargeback: false,
+                    locked: false,
+                    held: account.held(),
+                });
+            }
+            if let Err(error) = accepted {
+                self.rejections.record(&Rejection::of(
+                    *line,
+                    deposit,
+                    error.code(),
+                    error.to_string(),
+                ))?;
+            }
+        }
+        release_entries(
+            &mut self.history,
+            self.entry_limit,
+            &mut self.evicted,
+            account,
+        )?;
+        if opened && !applied {
+            if self.any_transaction_opens_account {
+                log_open(&mut self.event_log, &mut self.hash_chain, client_id)?;
+            } else {
+                self.accounts.remove(&client_id);
+            }
+        }
+        self.update_view(client_id);
+
+        Ok(())
+    }
+
+    // Flushes the audit log, transaction history, event log, audit chain, change stream and
+    // rejections report and reports adjustments which are still waiting for an approval.
+    pub fn flush(&mut self) -> anyhow::Result<()> {
+        let _config = self.config.enter();
+        self.books
+            .audit
+            .flush()
+            .context("can not flush audit log")?;
+        if let Some(history) = self.history.as_mut() {
+            history
+                .flush()
+                .context("can not flush transaction history")?;
+        }
+        if let Some(event_log) = self.event_log.as_mut() {
+            event_log.flush().context("can not flush event log")?;
+        }
+        if let Some(hash_chain) = self.hash_chain.as_mut() {
+            hash_chain.flush().context("can not flush audit chain")?;
+        }
+        if let Some(changes) = self.changes.as_mut() {
+            changes.flush().context("can not flush change stream")?;
+        }
+        self.rejections
+            .flush()
+            .context("can not flush rejections report")?;
+        self.controls.approvals.report_pending();
+
+        Ok(())
+    }
+
+    // Takes over accounts and books of an engine which processed other clients.
+    pub fn merge(&mut self, other: PaymentsEngine) {
+        let clients: Vec<u16> = other.accounts.keys().copied().collect();
+        self.accounts.extend(other.accounts);
+        for client_id in clients {
+            self.update_view(client_id);
+        }
+        self.tx_clients.extend(other.tx_clients);
+        self.books.merge(other.books);
+        self.rows += other.rows;
+        for (tx_type, rows) in other.rows_by_type {
+            *self.rows_by_type.entry(tx_type).or_default() += rows;
+        }
+        self.rejections.merge(other.rejections);
+    }
+
+    // Records read from input so far, including invalid and rejected ones.
+    pub fn rows(&self) -> u64 {
+        self.rows
+    }
+
+    pub fn rows_by_type(&self) -> &BTreeMap<&'static str, u64> {
+        &self.rows_by_type
+    }
+
+    pub fn rejections(&self) -> &Rejections {
+        &self.rejections
+    }
+
+    // Keeps rejections of transactions until they are taken with `take_rejected`.
+    pub fn keep_rejected(&mut self) {
+        self.rejections.keep_recent();
+    }
+
+    pub fn take_rejected(&mut self) -> Vec<Rejection> {
+        self.rejections.take_recent()
+    }
+
+    // Counts a record which can't be deserialized into a transaction and records it into the
+    // rejections report, if there is one.
+    pub fn reject_invalid(
+        &mut self,
+        position: u64,
+        reason: String,
+        record: Option<String>,
+    ) -> anyhow::Result<()> {
+        self.rows += 1;
+        self.rejections
+            .record(&Rejection::invalid(position, reason, record))
+    }
+
+    // Logs a record which can't be deserialized into a transaction and rejects it. Sources read
+    // outside of the engine, like async input, skip invalid records here too.
+    pub fn skip_invalid(
+        &mut self,
+        unit: &str,
+        position: u64,
+        message: String,
+        record: Option<String>,
+    ) -> anyhow::Result<()> {
+        let file = match self.rejections.input_file() {
+            None => String::new(),
+            Some(input_file) => format!(" of {:?}", input_file),
+        };
+        error!(
+            "can not deserialize transaction at {} {}{}: {}. record: {}. skipping it.",
+            unit,
+            position,
+            file,
+            message,
+            record.as_deref().unwrap_or("-")
+        );
+        self.reject_invalid(position, message, record)
+    }
+
+    // Counts invalid records which were skipped before they reached the engine, for example by a
+    // sharded engine.
+    pub fn count_invalid(&mut self, records: u64) {
+        self.rows += records;
+        self.rejections.count(rejections::INVALID_RECORD, records);
+    }
+
+    // Moves zero balance accounts without activity for `dormant_after` seconds into the store.
+    // Returns a number of moved accounts.
+    pub fn archive_dormant(&mut self, dormant_after: u64) -> anyhow::Result<usize> {
+        let now = self.clock.now();
+        let store = match self.store.as_mut() {
+            None => return Ok(0),
+            Some(store) => store,
+        };
+
+        let mut dormant: Vec<u16> = self
+            .accounts
+            .values()
+            .filter(|account| {
+                account.has_zero_balance()
+                    && account.last_activity().saturating_add(dormant_after) <= now
+            })
+            .map(|account| account.id())
+            .collect();
+        dormant.sort_unstable();
+
+        let accounts: Vec<&Account> = dormant
+            .iter()
+            .map(|client_id| &self.accounts[client_id])
+            .collect();
+        store.put_all(&accounts)?;
+        for client_id in &dormant {
+            self.accounts.remove(client_id);
+            if let Some(views) = self.views.as_mut() {
+                views.update(*client_id, None);
+            }
+        }
+
+        Ok(dormant.len())
+    }
+}
+
+impl Default for PaymentsEngine {
+    fn default() -> PaymentsEngine {
+        PaymentsEngine::new(
+            Books::new("default"),
+            Controls::new(),
+            Box::new(ReplayClock::new()),
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::amount::Amount;
+    use crate::archive::Archive;
+    use crate::dispute_policy::DisputePolicy;
+    use crate::limits::Limits;
+    use crate::locked_policy::LockedPolicy;
+    use crate::precision::PrecisionPolicy;
+    use crate::schema::SchemaPolicy;
+    use crate::source::MemorySource;
+    use proptest::prelude::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_process_payments_1() {
+        let mut engine = PaymentsEngine::new(
+            Books::new("test"),
+            Controls::new(),
+            Box::new(ReplayClock::new()),
+        );
+        assert!(engine
+            .process_file(&OsString::from("transactions_1.csv"))
+            .is_ok());
+        let accounts = engine.accounts();
+        assert_eq!(accounts.len(), 2);
+
+        let account = accounts.get(&1).unwrap();
+        assert_eq!(account.available(), Amount::from_str("1.5").unwrap());
+        assert_eq!(account.held(), Amount::from_str("0").unwrap());
+        assert_eq!(account.total(), Amount::from_str("1.5").unwrap());
+        assert!(!account.is_locked());
+
+        let account = accounts.get(&2).unwrap();
+        assert_eq!(account.available(), Amount::from_str("2").unwrap());
+        assert_eq!(account.held(), Amount::from_str("0").unwrap());
+        assert_eq!(account.total(), Amount::from_str("2").unwrap());
+        assert!(!account.is_locked());
+    }
+
+    #[test]
+    fn test_process_payments_2() {
+        let mut engine = PaymentsEngine::new(
+            Books::new("test"),
+            Controls::new(),
+            Box::new(ReplayClock::new()),
+        );
+        assert!(engine
+            .process_file(&OsString::from("transactions_2.csv"))
+            .is_ok());
+        let accounts = engine.accounts();
+        assert_eq!(accounts.len(), 5);
+
+        let account = accounts.get(&1).unwrap();
+        assert_eq!(account.available(), Amount::from_str("1231.744").unwrap());
+        assert_eq!(account.held(), Amount::from_str("0").unwrap());
+        assert_eq!(account.total(), Amount::from_str("1231.744").unwrap());
+        assert!(!account.is_locked());
+
+        let account = accounts.get(&2).unwrap();
+        assert_eq!(account.available(), Amount::from_str("37.2624").unwrap());
+        assert_eq!(account.held(), Amount::from_str("0").unwrap());
+        assert_eq!(account.total(), Amount::from_str("37.2624").unwrap());
+        assert!(!account.is_locked());
+
+        let account = accounts.get(&3).unwrap();
+        assert_eq!(account.available(), Amount::from_str("249.8589").unwrap());
+        assert_eq!(account.held(), Amount::from_str("0").unwrap());
+        assert_eq!(account.total(), Amount::from_str("249.8589").unwrap());
+        assert!(!account.is_locked());
+
+        let account = accounts.get(&4).unwrap();
+        assert_eq!(account.available(), Amount::from_str("200.2442").unwrap());
+        assert_eq!(account.held(), Amount::from_str("0").unwrap());
+        assert_eq!(account.total(), Amount::from_str("200.2442").unwrap());
+        assert!(!account.is_locked());
+
+        let account = accounts.get(&5).unwrap();
+        assert_eq!(account.available(), Amount::from_str("616.7601").unwrap());
+        assert_eq!(account.held(), Amount::from_str("0").unwrap());
+        assert_eq!(account.total(), Amount::from_str("616.7601").unwrap());
+        assert!(!account.is_locked());
+    }
+
+    #[test]
+    fn test_process_csv_from_reader() {
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,2.5\n\
+                     withdrawal,1,2,1.0\n";
+        let mut engine = PaymentsEngine::default();
+        assert!(engine.process_csv(input.as_bytes()).is_ok());
+
+        let account = engine.accounts().get(&1).unwrap();
+        assert_eq!(account.available(), Amount::from_str("1.5").unwrap());
+    }
+
+    #[test]
+    fn test_engines_have_own_config() {
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,10\n\
+                     withdrawal,1,2,6\n\
+                     withdrawal,1,3,2\n\
+                     dispute,1,1,\n\
+                     dispute,1,3,\n\
+                     deposit,2,4,5\n\
+                     dispute,2,4,\n\
+                     chargeback,2,4,\n\
+                     deposit,2,5,3\n";
+        let mut configured = PaymentsEngine::default();
+        configured.set_config(EngineConfig {
+            limits: Limits {
+                max_withdrawal: Some(Amount::from(5)),
+                ..Limits::default()
+            },
+            dispute_policy: DisputePolicy {
+                hold_beyond_available: true,
+                ..DisputePolicy::default()
+            },
+            dispute_withdrawals: true,
+            locked_policy: LockedPolicy {
+                deposits: true,
+                ..LockedPolicy::default()
+            },
+            ..EngineConfig::default()
+        });
+        let mut engine = PaymentsEngine::default();
+        assert!(configured.process_csv(input.as_bytes()).is_ok());
+        assert!(engine.process_csv(input.as_bytes()).is_ok());
+
+        let balances = |engine: &PaymentsEngine| {
+            let account = &engine.accounts()[&1];
+            (account.available(), account.held())
+        };
+        assert_eq!(balances(&configured), (Amount::from(-2), Amount::from(12)));
+        assert_eq!(balances(&engine), (Amount::from(2), Amount::from(0)));
+        // A locked account takes deposits only by the locked policy.
+        assert_eq!(configured.accounts()[&2].total(), Amount::from(3));
+        assert!(engine.accounts()[&2].total().is_zero());
+
+        // An overflow stops only processing by the overflow policy.
+        let input = format!(
+            "type,client,tx,amount\ndeposit,3,6,{}\ndeposit,3,7,1\n",
+            Amount::MAX
+        );
+        let mut aborting = PaymentsEngine::default();
+        aborting.set_config(EngineConfig {
+            overflow_policy: OverflowPolicy::Abort,
+            ..EngineConfig::default()
+        });
+        assert!(aborting.process_csv(input.as_bytes()).is_err());
+        assert!(PaymentsEngine::default()
+            .process_csv(input.as_bytes())
+            .is_ok());
+
+        // Amounts are read by the precision policy of the engine reading them.
+        let input = "type,client,tx,amount\ndeposit,4,8,1.00005\n";
+        let mut rejecting = PaymentsEngine::default();
+        rejecting.set_config(EngineConfig {
+            precision_policy: PrecisionPolicy::Reject,
+            ..EngineConfig::default()
+        });
+        assert!(rejecting.process_csv(input.as_bytes()).is_ok());
+        assert!(rejecting.accounts().is_empty());
+        let mut engine = PaymentsEngine::default();
+        assert!(engine.process_csv(input.as_bytes()).is_ok());
+        assert_eq!(engine.accounts()[&4].total(), Amount::from(1));
+
+        // Fields a transaction type doesn't use are checked by the schema policy of the engine.
+        let input = "type,client,tx,amount\ndeposit,5,9,1\ndispute,5,9,\nresolve,5,9,1\n";
+        let mut strict = PaymentsEngine::default();
+        strict.set_config(EngineConfig {
+            schema_policy: SchemaPolicy::Strict,
+            ..EngineConfig::default()
+        });
+        assert!(strict.process_csv(input.as_bytes()).is_ok());
+        assert_eq!(strict.accounts()[&5].held(), Amount::from(1));
+        let mut engine = PaymentsEngine::default();
+        assert!(engine.process_csv(input.as_bytes()).is_ok());
+        assert!(engine.accounts()[&5].held().is_zero());
+    }
+
+    #[test]
+    fn test_process_source() {
+        // Fails after the given transactions, like a dropped connection.
+        struct FailingSource(MemorySource);
+
+        impl TransactionSource for FailingSource {
+            fn next(&mut self) -> Option<Result<Transaction, SourceError>> {
+                Some(
+                    self.0
+                        .next()
+                        .unwrap_or_else(|| Err(SourceError::Failed(anyhow::anyhow!("closed")))),
+                )
+            }
+
+            fn position(&self) -> u64 {
+                self.0.position()
+            }
+        }
+
+        let transactions = |input: &str| -> Vec<Transaction> {
+            input
+                .lines()
+                .map(|line| serde_json::from_str(line).unwrap())
+                .collect()
+        };
+        let input = r#"{"type": "deposit", "client": 1, "tx": 1, "amount": "2.5"}
+{"type": "withdrawal", "client": 1, "tx": 2, "amount": "1.0"}"#;
+
+        let mut engine = PaymentsEngine::default();
+        let mut source = MemorySource::from(transactions(input));
+        assert!(engine.process_source(&mut source).is_ok());
+        let account = engine.accounts().get(&1).unwrap();
+        assert_eq!(account.available(), Amount::from_str("1.5").unwrap());
+
+        let mut engine = PaymentsEngine::default();
+        let mut source = FailingSource(MemorySource::from(transactions(input)));
+        assert!(engine.process_source(&mut source).is_err());
+        assert_eq!(engine.accounts().len(), 1);
+    }
+
+    #[test]
+    fn test_process_ndjson() {
+        let input = r#"{"type": "deposit", "client": 1, "tx": 1, "amount": 1.2345}
+{"type": "deposit", "client": 1, "tx": 2, "amount": "0.0001"}
+
+{"type": "withdrawal", "client": 1, "tx": 3}
+{"type": "dispute", "client": 1, "tx": 1}
+"#;
+        let mut engine = PaymentsEngine::default();
+        assert!(engine
+            .process_input(input.as_bytes(), InputFormat::Ndjson)
+            .is_ok());
+
+        let account = engine.accounts().get(&1).unwrap();
+        assert_eq!(account.total(), Amount::from_str("1.2346").unwrap());
+        assert_eq!(account.held(), Amount::from_str("1.2345").unwrap());
+    }
+
+    #[test]
+    fn test_input_format_from_path() {
+        assert_eq!(
+            InputFormat::from_path(&OsString::from("admin_1.json")),
+            InputFormat::Json
+        );
+        assert_eq!(
+            InputFormat::from_path(&OsString::from("feed.ndjson")),
+            InputFormat::Ndjson
+        );
+        assert_eq!(
+            InputFormat::from_path(&OsString::from("transactions_1.csv")),
+            InputFormat::Csv
+        );
+    }
+
+    #[test]
+    fn test_archive_dormant_zero_balance_accounts() {
+        let dir = std::env::temp_dir().join("coding_test_engine_archive");
+        let _ = std::fs::remove_dir_all(&dir);
+        let mut engine = PaymentsEngine::default();
+        engine.set_store(Box::new(Archive::open(&dir.into_os_string()).unwrap()));
+
+        let input = "type,client,tx,amount,timestamp\n\
+                     deposit,1,1,5.0,10\n\
+                     deposit,2,2,5.0,10\n\
+                     withdrawal,2,3,5.0,10\n\
+                     deposit,3,4,5.0,10\n\
+                     withdrawal,3,5,5.0,10\n\
+                     deposit,3,6,0.0,1000\n";
+        assert!(engine.process_csv(input.as_bytes()).is_ok());
+
+        // Only client 2 has zero balance and no activity for 500 seconds.
+        assert_eq!(engine.archive_dormant(500).unwrap(), 1);
+        assert!(!engine.accounts().contains_key(&2));
+        assert_eq!(engine.accounts().len(), 2);
+
+        // A new transaction takes the account out of the archive.
+        let input = "type,client,tx,amount\ndeposit,2,7,1.0\n";
+        assert!(engine.process_csv(input.as_bytes()).is_ok());
+        assert_eq!(engine.accounts()[&2].available(), Amount::from(1));
+        assert!(engine.accounts()[&2].find_transaction(2).is_some());
+    }
+
+    #[test]
+    fn test_find_transaction() {
+        let mut engine = PaymentsEngine::default();
+        assert!(engine
+            .process_file(&OsString::from("transactions_3.csv"))
+            .is_ok());
+
+        let found = engine.find_transaction(1).unwrap();
+        assert_eq!(found.client, 1);
+        assert_eq!(found.status, "accepted");
+
+        let disputed = engine
+            .accounts()
+            .values()
+            .flat_map(|account| account.transactions())
+            .find(|transaction| transaction.status().name() == "dispute")
+            .map(|transaction| transaction.tx_id())
+            .unwrap();
+        assert_eq!(engine.find_transaction(disputed).unwrap().status, "dispute");
+        assert!(engine.find_transaction(999999).is_none());
+    }
+
+    #[test]
+    fn test_history_keeps_deposits_out_of_accounts() {
+        let input = std::fs::read_to_string("transactions_3.csv").unwrap();
+        let mut expected = PaymentsEngine::default();
+        assert!(expected.process_csv(input.as_bytes()).is_ok());
+
+        let mut engine = PaymentsEngine::default();
+        engine.set_coalesce_deposits(true);
+        engine.set_history(Box::new(history::MemoryHistory::default()));
+        assert!(engine.process_csv(input.as_bytes()).is_ok());
+
+        assert_eq!(engine.accounts().len(), expected.accounts().len());
+        for (client_id, account) in engine.accounts() {
+            let expected = &expected.accounts()[client_id];
+            assert_eq!(account.available(), expected.available());
+            assert_eq!(account.held(), expected.held());
+            assert_eq!(account.total(), expected.total());
+            assert_eq!(account.is_locked(), expected.is_locked());
+            assert!(account.transactions().is_empty());
+        }
+        assert!(engine.books().ledger.is_balanced());
+    }
+
+    #[test]
+    fn test_entry_limit() {
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,1.0\n\
+                     deposit,1,2,2.0\n\
+                     deposit,1,3,3.0\n\
+                     dispute,1,1,\n\
+                     dispute,1,3,\n";
+
+        let mut engine = PaymentsEngine::default();
+        engine.set_entry_limit("2:spill".parse().unwrap());
+        engine.set_history(Box::new(history::MemoryHistory::default()));
+        assert!(engine.process_csv(input.as_bytes()).is_ok());
+        let account = &engine.accounts()[&1];
+        assert_eq!(account.held(), Amount::from(4));
+        assert_eq!(account.transactions().len(), 2);
+
+        let mut engine = PaymentsEngine::default();
+        engine.set_entry_limit("2:reject".parse().unwrap());
+        assert!(engine.process_csv(input.as_bytes()).is_ok());
+        let account = &engine.accounts()[&1];
+        assert_eq!(account.held(), Amount::from(3));
+        assert!(account.find_transaction(1).is_none());
+
+        let mut engine = PaymentsEngine::default();
+        engine.set_entry_limit("2:spill".parse().unwrap());
+        assert!(engine.process_csv(input.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_coalesced_deposits() {
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,0.0001\n\
+                     deposit,1,2,0.0002\n\
+                     deposit,1,3,-1\n\
+                     deposit,1,4,0.0003\n\
+                     dispute,1,2,\n\
+                     deposit,2,5,1\n\
+                     deposit,2,6,1\n";
+        let mut engine = PaymentsEngine::default();
+        engine.set_coalesce_deposits(true);
+        assert!(engine.process_csv(input.as_bytes()).is_ok());
+
+        let account = engine.accounts().get(&1).unwrap();
+        assert_eq!(account.total(), Amount::from_str("0.0006").unwrap());
+        assert_eq!(account.held(), Amount::from_str("0.0002").unwrap());
+        assert_eq!(account.transactions().len(), 3);
+        assert_eq!(engine.accounts().get(&2).unwrap().total(), Amount::from(2));
+
+        let totals = engine.books().periods.totals.get("default").unwrap();
+        assert_eq!(totals.deposit_count, 5);
+        assert!(engine.books().ledger.is_balanced());
+        assert!(engine.books().ledger.matches_accounts(engine.accounts()));
+    }
+
+    #[test]
+    fn test_coalesced_deposits_match_row_by_row() {
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,5\n\
+                     deposit,1,2,1\n\
+                     deposit,1,2,1\n\
+                     deposit,1,3,-1\n\
+                     withdrawal,1,4,2\n\
+                     deposit,2,1,3\n\
+                     deposit,2,5,4\n\
+                     deposit,3,1,2\n\
+                     deposit,3,6,0\n\
+                     dispute,1,2,\n\
+                     chargeback,1,2,\n\
+                     deposit,1,7,1\n\
+                     deposit,1,8,1\n";
+        let process = |coalesce_deposits| {
+            let mut books = Books::new("test");
+            books.fees = Some(
+                Fees::read("type,flat,percent\nwithdrawal,1,10\nchargeback,5,\n".as_bytes())
+                    .unwrap(),
+            );
+            let mut engine =
+                PaymentsEngine::new(books, Controls::new(), Box::new(ReplayClock::new()));
+            engine.set_reject_duplicates(true);
+            engine.set_coalesce_deposits(coalesce_deposits);
+            assert!(engine.process_csv(input.as_bytes()).is_ok());
+            let mut output = Vec::new();
+            assert!(crate::output::save_accounts(engine.accounts(), &mut output).is_ok());
+            let transactions: Vec<_> = engine.accounts()[&1]
+                .transactions()
+                .iter()
+                .map(|transaction| {
+                    (
+                        transaction.tx_id(),
+                        transaction.amount(),
+                        transaction.status(),
+                    )
+                })
+                .collect();
+            (
+                String::from_utf8(output).unwrap(),
+                engine.rejections().counts().clone(),
+                transactions,
+            )
+        };
+        let coalesced = process(true);
+        assert_eq!(coalesced, process(false));
+        assert_eq!(coalesced.1.get("duplicate_transaction"), Some(&3));
+        assert!(!coalesced.0.contains("\n3,"));
+    }
+
+    // Writes to /dev/full fail, like an event log on a full disk.
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_failed_event_log_keeps_account() {
+        for coalesce_deposits in [false, true] {
+            let mut engine = PaymentsEngine::default();
+            engine.set_coalesce_deposits(coalesce_deposits);
+            assert!(engine
+                .process_csv("type,client,tx,amount\ndeposit,1,1,10\n".as_bytes())
+                .is_ok());
+
+            engine.set_event_log(EventLog::open(&OsString::from("/dev/full")).unwrap());
+            let input = "type,client,tx,amount\n\
+                         deposit,1,2,5\n\
+                         deposit,1,3,5\n";
+            assert!(engine.process_csv(input.as_bytes()).is_err());
+
+            let account = engine.accounts().get(&1).unwrap();
+            assert_eq!(account.total(), Amount::from(10));
+            assert_eq!(account.available(), Amount::from(10));
+            assert!(account.find_transaction(2).is_none());
+            assert!(account.find_transaction(3).is_none());
+        }
+    }
+
+    #[test]
+    fn test_process_payments_3() {
+        let mut engine = PaymentsEngine::new(
+            Books::new("test"),
+            Controls::new(),
+            Box::new(ReplayClock::new()),
+        );
+        assert!(engine
+            .process_file(&OsString::from("transactions_3.csv"))
+            .is_ok());
+        let accounts = engine.accounts();
+        assert_eq!(accounts.len(), 5);
+
+        let account = accounts.get(&1).unwrap();
+        assert_eq!(account.available(), Amount::from_str("50").unwrap());
+        assert_eq!(account.held(), Amount::from_str("200").unwrap());
+        assert_eq!(account.total(), Amount::from_str("250").unwrap());
+        assert!(!account.is_locked());
+
+        let account = accounts.get(&2).unwrap();
+        assert_eq!(account.available(), Amount::from_str("250").unwrap());
+        assert_eq!(account.held(), Amount::from_str("0").unwrap());
+        assert_eq!(account.total(), Amount::from_str("250").unwrap());
+        assert!(!account.is_locked());
+
+        let account = accounts.get(&3).unwrap();
+        assert_eq!(account.available(), Amount::from_str("50").unwrap());
+        assert_eq!(account.held(), Amount::from_str("0").unwrap());
+        assert_eq!(account.total(), Amount::from_str("50").unwrap());
+        assert!(account.is_locked());
+
+        let account = accounts.get(&4).unwrap();
+        assert_eq!(account.available(), Amount::from_str("250").unwrap());
+        assert_eq!(account.held(), Amount::from_str("0").unwrap());
+        assert_eq!(account.total(), Amount::from_str("250").unwrap());
+        assert!(!account.is_locked());
+
+        let account = accounts.get(&5).unwrap();
+        assert_eq!(account.available(), Amount::from_str("100").unwrap());
+        assert_eq!(account.held(), Amount::from_str("0").unwrap());
+        assert_eq!(account.total(), Amount::from_str("100").unwrap());
+        assert!(!account.is_locked());
+    }
+
+    #[test]
+    fn test_books_of_payments_3() {
+        let mut engine = PaymentsEngine::new(
+            Books::new("test"),
+            Controls::new(),
+            Box::new(ReplayClock::new()),
+        );
+        assert!(engine
+            .process_file(&OsString::from("transactions_3.csv"))
+            .is_ok());
+
+        let totals = engine.books().periods.totals.get("test").unwrap();
+        assert_eq!(totals.deposits, Amount::from_str("1500").unwrap());
+        assert_eq!(totals.deposit_count, 10);
+        assert_eq!(totals.withdrawals, Amount::from_str("400").unwrap());
+        assert_eq!(totals.withdrawal_count, 5);
+        assert_eq!(totals.chargebacks, Amount::from_str("200").unwrap());
+        assert_eq!(totals.chargeback_count, 1);
+
+        assert!(engine.books().ledger.is_balanced());
+        assert!(engine.books().ledger.matches_accounts(engine.accounts()));
+    }
+
+    #[test]
+    fn test_transactions_are_tagged_with_periods() {
+        // Rows with a timestamp belong to its month, others to the run period.
+        let input = "type,client,tx,amount,timestamp\n\
+                     deposit,1,1,10,1704067200\n\
+                     deposit,1,4,20,1704067200\n\
+                     withdrawal,1,2,3,1706745600\n\
+                     dispute,1,1,,1709251200\n\
+                     chargeback,1,1,,1709251200\n\
+                     deposit,2,3,5,\n";
+        for coalesce_deposits in [false, true] {
+            let event_log = std::env::temp_dir().join(format!(
+                "coding_test_period_events_{}_{}.ndjson",
+                std::process::id(),
+                coalesce_deposits
+            ));
+            let mut engine = PaymentsEngine::new(
+                Books::new("run"),
+                Controls::new(),
+                Box::new(ReplayClock::new()),
+            );
+            engine.set_config(EngineConfig {
+                dispute_withdrawals: true,
+                ..EngineConfig::default()
+            });
+            engine.set_coalesce_deposits(coalesce_deposits);
+            engine.set_event_log(EventLog::open(&event_log.clone().into_os_string()).unwrap());
+            assert!(engine.process_csv(input.as_bytes()).is_ok());
+
+            let accounts = engine.accounts();
+            let period = |client, tx_id| {
+                let account: &Account = &accounts[&client];
+                account.find_transaction(tx_id).unwrap().period().cloned()
+            };
+            assert_eq!(period(1, 1).as_deref(), Some("2024-01"));
+            assert_eq!(period(1, 4).as_deref(), Some("2024-01"));
+            assert_eq!(period(1, 2).as_deref(), Some("2024-02"));
+            assert_eq!(period(2, 3).as_deref(), Some("run"));
+
+            let totals = &engine.books().periods.totals;
+            assert_eq!(totals["2024-01"].deposit_count, 2);
+            assert_eq!(totals["2024-02"].withdrawal_count, 1);
+            assert_eq!(totals["2024-03"].chargebacks, Amount::from(10));
+            assert_eq!(totals["run"].deposits, Amount::from(5));
+
+            let events = std::fs::read_to_string(&event_log).unwrap();
+            std::fs::remove_file(&event_log).unwrap();
+            let periods: Vec<String> = events
+                .lines()
+                .map(|line| serde_json::from_str::<Event>(line).unwrap())
+                .filter(|event| event.tx_type != crate::event_log::OPEN)
+                .map(|event| format!("{} {}", event.tx_type, event.period.unwrap()))
+                .collect();
+            assert_eq!(
+                periods,
+                [
+                    "deposit 2024-01",
+                    "deposit 2024-01",
+                    "withdrawal 2024-02",
+                    "dispute 2024-03",
+                    "chargeback 2024-03",
+                    "deposit run",
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn test_process_admin_operations() {
+        let mut engine = PaymentsEngine::new(
+            Books::new("test"),
+            Controls::new(),
+            Box::new(ReplayClock::new()),
+        );
+        assert!(engine.process_file(&OsString::from("admin_1.json")).is_ok());
+        let accounts = engine.accounts();
+        // Client 2 has no deposit, so its annotation doesn't open an account.
+        assert_eq!(accounts.len(), 1);
+
+        // Deposit to a locked account is rejected and adjustment without operator is ignored.
+        let account = accounts.get(&1).unwrap();
+        assert_eq!(account.available(), Amount::from_str("97.5").unwrap());
+        assert_eq!(account.total(), Amount::from_str("97.5").unwrap());
+        assert!(!account.is_locked());
+        assert_eq!(account.adjustments().len(), 3);
+
+        assert!(engine.books().ledger.is_balanced());
+        assert!(engine.books().ledger.matches_accounts(accounts));
+    }
+
+    #[test]
+    fn test_only_deposits_open_accounts() {
+        let input = "type,client,tx,amount\n\
+                     dispute,1,1,\n\
+                     withdrawal,2,2,1.0\n\
+                     deposit,3,3,1.0\n\
+                     chargeback,3,4,\n";
+        let mut engine = PaymentsEngine::default();
+        assert!(engine.process_csv(input.as_bytes()).is_ok());
+        assert_eq!(engine.accounts().keys().collect::<Vec<_>>(), vec![&3]);
+
+        let mut engine = PaymentsEngine::default();
+        engine.set_any_transaction_opens_account(true);
+        assert!(engine.process_csv(input.as_bytes()).is_ok());
+        assert_eq!(engine.accounts().len(), 3);
+        assert!(engine.accounts()[&1].has_zero_balance());
+    }
+
+    #[test]
+    fn test_rejected_deposit_opens_no_account() {
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,1.0\n\
+                     deposit,2,2,0\n\
+                     deposit,3,3,-1.0\n";
+        let mut engine = PaymentsEngine::default();
+        assert!(engine.process_csv(input.as_bytes()).is_ok());
+        assert_eq!(engine.accounts().keys().collect::<Vec<_>>(), vec![&1]);
+        assert_eq!(engine.rejections().total(), 2);
+
+        let mut output = Vec::new();
+        assert!(crate::output::save_accounts(engine.accounts(), &mut output).is_ok());
+        let output = String::from_utf8(output).unwrap();
+        assert_eq!(output.lines().count(), 2);
+        assert!(!output.contains("\n2,") && !output.contains("\n3,"));
+    }
+
+    #[test]
+    fn test_snapshot_accounts_while_processing() {
+        let mut engine = PaymentsEngine::default();
+        assert!(engine
+            .process_csv("type,client,tx,amount\ndeposit,1,1,10\n".as_bytes())
+            .is_ok());
+        let snapshot = engine.snapshot_accounts();
+
+        let input = "type,client,tx,amount\n\
+                     withdrawal,1,2,4\n\
+                     deposit,2,3,1\n";
+        assert!(engine.process_csv(input.as_bytes()).is_ok());
+        assert_eq!(snapshot.len(), 1);
+        assert_eq!(snapshot.get(1).unwrap().available, Amount::from(10));
+
+        let snapshot = engine.snapshot_accounts();
+        assert_eq!(snapshot.len(), 2);
+        assert_eq!(snapshot.get(1).unwrap().available, Amount::from(6));
+        assert_eq!(snapshot.get(2).unwrap().total, Amount::from(1));
+    }
+
+    #[test]
+    fn test_dispute_of_another_client() {
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,10\n\
+                     deposit,2,2,5\n\
+                     dispute,2,1,\n\
+                     chargeback,2,1,\n\
+                     dispute,1,1,\n\
+                     dispute,2,3,\n";
+        let mut engine = PaymentsEngine::default();
+        assert!(engine.process_csv(input.as_bytes()).is_ok());
+        assert_eq!(engine.accounts()[&1].held(), Amount::from(10));
+        assert!(engine.accounts()[&2].held().is_zero());
+        let counts = engine.rejections().counts();
+        assert_eq!(counts.get("client_mismatch"), Some(&2));
+        assert_eq!(counts.get("unknown_transaction"), Some(&1));
+    }
+
+    #[test]
+    fn test_transfer() {
+        let input = "type,client,tx,amount,from_client,to_client\n\
+                     deposit,1,1,10,,\n\
+                     transfer,,2,4,1,2\n\
+                     transfer,,3,7,1,2\n\
+                     transfer,,4,1,1,1\n\
+                     transfer,,5,1,3,1\n\
+                     dispute,2,2,,,\n";
+        let mut engine = PaymentsEngine::default();
+        assert!(engine.process_csv(input.as_bytes()).is_ok());
+        let accounts = engine.accounts();
+        assert_eq!(accounts[&1].available(), Amount::from(6));
+        // The deposit leg of a transfer is disputed like any other deposit.
+        assert_eq!(accounts[&2].held(), Amount::from(4));
+        assert_eq!(accounts[&2].total(), Amount::from(4));
+        assert_eq!(engine.rows(), 6);
+        let counts = engine.rejections().counts();
+        assert_eq!(counts.get("insufficient_funds"), Some(&1));
+        assert_eq!(counts.get("same_account"), Some(&1));
+        assert_eq!(counts.get(rejections::NO_ACCOUNT), Some(&1));
+
+        // A locked receiving account rejects the whole transfer.
+        let input = "type,client,tx,amount,from_client,to_client\n\
+                     deposit,1,1,10,,\n\
+                     deposit,2,2,5,,\n\
+                     dispute,2,2,,,\n\
+                     chargeback,2,2,,,\n\
+                     transfer,,3,4,1,2\n";
+        let mut engine = PaymentsEngine::default();
+        assert!(engine.process_csv(input.as_bytes()).is_ok());
+        assert_eq!(engine.accounts()[&1].available(), Amount::from(10));
+        assert!(engine.accounts()[&2].total().is_zero());
+        assert_eq!(engine.rejections().counts().get("account_locked"), Some(&1));
+    }
+
+    #[test]
+    fn test_reject_duplicates() {
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,10\n\
+                     deposit,2,1,5\n\
+                     withdrawal,1,1,2\n\
+                     dispute,1,1,\n";
+        let mut engine = PaymentsEngine::default();
+        assert!(engine.process_csv(input.as_bytes()).is_ok());
+        assert_eq!(engine.accounts()[&2].total(), Amount::from(5));
+
+        let mut engine = PaymentsEngine::default();
+        engine.set_reject_duplicates(true);
+        assert!(engine.process_csv(input.as_bytes()).is_ok());
+        assert_eq!(engine.accounts()[&1].held(), Amount::from(10));
+        assert!(!engine.accounts().contains_key(&2));
+        assert_eq!(
+            engine
+                .rejections()
+                .counts()
+                .get(rejections::DUPLICATE_TRANSACTION),
+            Some(&2)
+        );
+    }
+
+    #[test]
+    fn test_fees() {
+        let mut books = Books::new("default");
+        books.fees = Some(
+            Fees::read("type,flat,percent\nwithdrawal,1,10\nchargeback,5,\n".as_bytes()).unwrap(),
+        );
+        let mut engine = PaymentsEngine::new(books, Controls::new(), Box::new(ReplayClock::new()));
+        let input = "type,client,tx,amount,from_client,to_client\n\
+                     deposit,1,1,100,,\n\
+                     withdrawal,1,2,10,,\n\
+                     withdrawal,1,3,80,,\n\
+                     transfer,,4,10,1,2\n\
+                     dispute,2,4,,,\n\
+                     chargeback,2,4,,,\n";
+        assert!(engine.process_csv(input.as_bytes()).is_ok());
+        let accounts = engine.accounts();
+        // A withdrawal of 10 costs 2 and the one of 80 can't pay its fee of 9.
+        assert_eq!(accounts[&1].available(), Amount::from(78));
+        assert_eq!(
+            engine.rejections().counts().get("insufficient_funds"),
+            Some(&1)
+        );
+        // Transfers aren't charged, a chargeback fee may leave the account owing funds.
+        assert_eq!(accounts[&2].available(), Amount::from(-5));
+        assert_eq!(accounts[&2].total(), Amount::from(-5));
+
+        let ledger = &engine.books().ledger;
+        assert!(ledger.is_balanced());
+        assert!(ledger.matches_accounts(accounts));
+        assert_eq!(
+            ledger.credit_balance(LedgerAccount::FeeRevenue),
+            Amount::from(7)
+        );
+    }
+
+    #[test]
+    fn test_partial_chargeback_is_booked() {
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,100\n\
+                     dispute,1,1,40\n\
+                     chargeback,1,1,25.5\n\
+                     deposit,2,2,10\n\
+                     dispute,2,2,\n\
+                     resolve,2,2,\n";
+        let mut engine = PaymentsEngine::default();
+        assert!(engine.process_csv(input.as_bytes()).is_ok());
+        assert_eq!(engine.rejections().total(), 0);
+        let accounts = engine.accounts();
+        assert_eq!(accounts[&1].total(), Amount::from_str("74.5").unwrap());
+        assert_eq!(accounts[&2].available(), Amount::from(10));
+
+        let books = engine.books();
+        assert!(books.ledger.is_balanced());
+        assert!(books.ledger.matches_accounts(accounts));
+        assert_eq!(
+            books
+                .ledger
+                .credit_balance(LedgerAccount::ChargebacksPayable),
+            Amount::from_str("25.5").unwrap()
+        );
+        let totals = books.periods.totals.get("default").unwrap();
+        assert_eq!(totals.chargebacks, Amount::from_str("25.5").unwrap());
+    }
+
+    #[test]
+    fn test_representment_is_booked() {
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,100\n\
+                     dispute,1,1,40\n\
+                     chargeback,1,1,25\n\
+                     representment,1,1,\n\
+                     representment,1,1,\n";
+        let mut engine = PaymentsEngine::default();
+        assert!(engine.process_csv(input.as_bytes()).is_ok());
+        // Only the charged back part is credited again, the account stays locked by default.
+        let account = &engine.accounts()[&1];
+        assert_eq!(account.available(), Amount::from(100));
+        assert_eq!(account.total(), Amount::from(100));
+        assert!(account.is_locked());
+        assert_eq!(engine.rejections().counts().get("invalid_state"), Some(&1));
+
+        let ledger = &engine.books().ledger;
+        assert!(ledger.is_balanced());
+        assert!(ledger.matches_accounts(engine.accounts()));
+        assert!(ledger
+            .credit_balance(LedgerAccount::ChargebacksPayable)
+            .is_zero());
+    }
+
+    #[test]
+    fn test_reordered_dispute_before_deposit() {
+        let input = "type,client,tx,amount,timestamp\n\
+                     deposit,1,1,10,100\n\
+                     dispute,1,2,,300\n\
+                     deposit,1,2,5,200\n";
+        let mut engine = PaymentsEngine::default();
+        assert!(engine.process_csv(input.as_bytes()).is_ok());
+        assert_eq!(engine.accounts()[&1].held(), Amount::from(0));
+
+        let mut engine = PaymentsEngine::default();
+        engine.set_reorder("timestamp:2".parse().unwrap());
+        assert!(engine.process_csv(input.as_bytes()).is_ok());
+        let account = &engine.accounts()[&1];
+        assert_eq!(account.held(), Amount::from(5));
+        assert_eq!(account.total(), Amount::from(15));
+    }
+
+    // Balances, held funds and lock of an account, `None` if it doesn't exist.
+    fn snapshot(engine: &PaymentsEngine, client_id: u16) -> Option<(Amount, Amount, Amount, bool)> {
+        engine.accounts().get(&client_id).map(|account| {
+            (
+                account.available(),
+                account.held(),
+                account.total(),
+                account.is_locked(),
+            )
+        })
+    }
+
+    // Rows of a few clients with a few transaction ids, so disputes, resolves and chargebacks
+    // often refer to existing deposits and ids are sometimes reused.
+    fn rows() -> impl Strategy<Value = Vec<String>> {
+        let tx_types = ["deposit", "withdrawal", "dispute", "resolve", "chargeback"];
+        prop::collection::vec((0..tx_types.len(), 1..=3u16, 1..=12u32, 0..=5000u32), 1..80)
+            .prop_map(move |rows| {
+                rows.into_iter()
+                    .map(|(tx_type, client, tx, cents)| match tx_types[tx_type] {
+                        tx_type @ ("deposit" | "withdrawal") => format!(
+                            "{},{},{},{}.{:02}",
+                            tx_type,
+                            client,
+                            tx,
+                            cents / 100,
+                            cents % 100
+                        ),
+                        tx_type => format!("{},{},{},", tx_type, client, tx),
+                    })
+                    .collect()
+            })
+    }
+
+    proptest! {
+        #[test]
+        fn test_invariants_hold_after_every_transaction(rows in rows()) {
+            let input = format!("type,client,tx,amount\n{}\n", rows.join("\n"));
+            let mut source = CsvSource::new(input.as_bytes());
+            let mut engine = PaymentsEngine::default();
+            while let Some(transaction) = source.next() {
+                let transaction = transaction.unwrap();
+                let client_id = transaction.client_id();
+                let before = snapshot(&engine, client_id);
+                let rejected = engine.rejections().total();
+                engine.process_transaction(transaction).unwrap();
+                let after = snapshot(&engine, client_id);
+
+                for account in engine.accounts().values() {
+                    prop_assert_eq!(account.total(), account.available() + account.held());
+                    prop_assert!(!account.held().is_sign_negative());
+                }
+                if engine.rejections().total() != rejected {
+                    prop_assert_eq!(before, after);
+                }
+                if let (Some(before), Some(after)) = (before, after) {
+                    if before.3 {
+                        prop_assert!(after.3);
+                        prop_assert!(after.0 >= before.0 && after.2 >= before.2);
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,135 @@
+use crate::amount_cache;
+use crate::charged_back::ChargedBackPolicy;
+use crate::decimal_format::DecimalFormat;
+use crate::dispute_policy::DisputePolicy;
+use crate::limits::Limits;
+use crate::locked_policy::LockedPolicy;
+use crate::overflow::OverflowPolicy;
+use crate::precision::PrecisionPolicy;
+use crate::schema::SchemaPolicy;
+
+// Policies by which an engine applies transactions to its accounts. Every engine has its own, so
+// engines in one process, for example sessions of a server or engines of library users, never
+// share them. They should be set before any transaction is processed.
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct EngineConfig {
+    pub limits: Limits,
+    pub dispute_policy: DisputePolicy,
+    // Withdrawals are recorded and may be disputed like deposits only if it's turned on, as most
+    // feeds dispute deposits only and every recorded withdrawal is kept in memory or in history.
+    // It's off by default, then a dispute of a withdrawal is rejected as one of an unknown
+    // transaction.
+    pub dispute_withdrawals: bool,
+    pub locked_policy: LockedPolicy,
+    pub charged_back_policy: ChargedBackPolicy,
+    pub overflow_policy: OverflowPolicy,
+    // Format of amounts in outputs, the policy for precision of amounts in inputs and the policy
+    // for fields of input records. They are used by serde adapters and sources, which can't be
+    // given them, so they are read from the config entered on the thread that reads or writes,
+    // see `enter`.
+    pub decimal_format: DecimalFormat,
+    pub precision_policy: PrecisionPolicy,
+    pub schema_policy: SchemaPolicy,
+    // Whether parsed amounts are cached by their raw value, see `amount_cache`.
+    pub amount_cache: bool,
+}
+
+impl EngineConfig {
+    // Part of the config used when transactions are read and accounts are written.
+    pub fn scope(&self) -> Scope {
+        Scope {
+            decimal_format: self.decimal_format,
+            precision_policy: self.precision_policy,
+            schema_policy: self.schema_policy,
+            amount_cache: self.amount_cache,
+        }
+    }
+
+    // Makes the config used by reads and writes on this thread until the guard is dropped.
+    pub fn enter(&self) -> Entered {
+        self.scope().enter()
+    }
+}
+
+// Part of a config entered on a thread. It's copied to threads that read or write for an engine,
+// for example jobs parsing chunks of input.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Scope {
+    decimal_format: DecimalFormat,
+    precision_policy: PrecisionPolicy,
+    schema_policy: SchemaPolicy,
+    amount_cache: bool,
+}
+
+impl Scope {
+    // Scope entered on this thread, or the default one.
+    pub fn current() -> Scope {
+        Scope {
+            decimal_format: DecimalFormat::current(),
+            precision_policy: PrecisionPolicy::current(),
+            schema_policy: SchemaPolicy::current(),
+            amount_cache: amount_cache::is_enabled(),
+        }
+    }
+
+    pub fn enter(self) -> Entered {
+        Entered {
+            previous: self.replace(),
+        }
+    }
+
+    fn replace(self) -> Scope {
+        Scope {
+            decimal_format: self.decimal_format.replace_current(),
+            precision_policy: self.precision_policy.replace_current(),
+            schema_policy: self.schema_policy.replace_current(),
+            amount_cache: amount_cache::replace_enabled(self.amount_cache),
+        }
+    }
+}
+
+// Restores the previously entered scope when it's dropped, so an engine called by another one on
+// the same thread leaves its config as it was.
+#[must_use]
+pub struct Entered {
+    previous: Scope,
+}
+
+impl Drop for Entered {
+    fn drop(&mut self) {
+        self.previous.replace();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::amount::Amount;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_entered_config_is_restored() {
+        let config = EngineConfig {
+            decimal_format: DecimalFormat {
+                scale: 2,
+                ..DecimalFormat::default()
+            },
+            precision_policy: PrecisionPolicy::Reject,
+            ..EngineConfig::default()
+        };
+        let mut buffer = String::new();
+
+        {
+            let _config = config.enter();
+            assert_eq!(Scope::current(), config.scope());
+            DecimalFormat::current().write(&Amount::from_str("1.5").unwrap(), &mut buffer);
+            assert_eq!(buffer, "1.50");
+            assert!(crate::precision::parse("1.00001").is_err());
+
+            let _nested = EngineConfig::default().enter();
+            assert_eq!(Scope::current(), EngineConfig::default().scope());
+        }
+        assert_eq!(Scope::current(), EngineConfig::default().scope());
+        assert!(crate::precision::parse("1.00001").is_ok());
+    }
+}
@@ -0,0 +1,156 @@
+use crate::account::{Account, DepositedTransaction, DepositedTransactionStatus};
+
+use std::str::FromStr;
+
+// A single client with millions of rows would keep all of its deposits in memory, as any of them
+// may still be disputed. A limit of stored transactions per account decides what happens to the
+// oldest ones once an account holds more of them.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum EvictionStrategy {
+    // Oldest transactions are moved into the transaction history and brought back when a dispute,
+    // resolve or chargeback references them.
+    Spill,
+    // Oldest transactions are dropped and a dispute, resolve or chargeback referencing one is
+    // rejected with `REJECTION_CODE`.
+    Reject,
+    // Oldest resolved and charged back transactions are dropped, as they can't be disputed anymore.
+    // Transactions which may still be disputed are always kept, even above the limit.
+    Compact,
+}
+
+// Code of a rejected dispute, resolve or chargeback of an evicted transaction in logs.
+pub const REJECTION_CODE: &str = "evicted";
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct EntryLimit {
+    pub max_entries: usize,
+    pub strategy: EvictionStrategy,
+}
+
+impl EntryLimit {
+    // Removes transactions above the limit from an account and returns them, oldest first.
+    pub fn evict(&self, account: &mut Account) -> Vec<DepositedTransaction> {
+        let excess = account
+            .transactions()
+            .len()
+            .saturating_sub(self.max_entries);
+        if excess == 0 {
+            return Vec::new();
+        }
+
+        match self.strategy {
+            EvictionStrategy::Spill | EvictionStrategy::Reject => {
+                account.transactions_mut().drain_oldest(excess)
+            }
+            EvictionStrategy::Compact => {
+                let mut evicted = Vec::new();
+                let mut index = 0;
+                while evicted.len() < excess && index < account.transactions().len() {
+                    match account.transactions()[index].status() {
+                        DepositedTransactionStatus::Resolved
+                        | DepositedTransactionStatus::Chargeback
+                        | DepositedTransactionStatus::Represented => {
+                            evicted.push(account.transactions_mut().remove(index))
+                        }
+                        _ => index += 1,
+                    }
+                }
+                evicted
+            }
+        }
+    }
+}
+
+// Limit is given as `<entries>:<strategy>` with strategy `spill`, `reject` or `compact`, for
+// example `100000:compact`.
+impl FromStr for EntryLimit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<EntryLimit, String> {
+        let (max_entries, strategy) = s
+            .split_once(':')
+            .ok_or_else(|| format!("expected <entries>:<strategy>, got {}", s))?;
+        let max_entries = match max_entries.parse() {
+            Ok(max_entries) if max_entries > 0 => max_entries,
+            _ => return Err(format!("invalid number of entries: {}", max_entries)),
+        };
+        let strategy = match strategy {
+            "spill" => EvictionStrategy::Spill,
+            "reject" => EvictionStrategy::Reject,
+            "compact" => EvictionStrategy::Compact,
+            _ => return Err(format!("unknown eviction strategy: {}", strategy)),
+        };
+        Ok(EntryLimit {
+            max_entries,
+            strategy,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::amount::Amount;
+    use crate::transaction::Deposit;
+
+    fn account_with_deposits(count: u32) -> Account {
+        let mut account = Account::new(1);
+        for tx_id in 1..=count {
+            assert!(account
+                .deposit(&Deposit {
+                    client_id: 1,
+                    tx_id,
+                    amount: Amount::from(1),
+                })
+                .is_ok());
+        }
+        account
+    }
+
+    fn tx_ids(transactions: &[DepositedTransaction]) -> Vec<u32> {
+        transactions
+            .iter()
+            .map(|transaction| transaction.tx_id())
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_entry_limit() {
+        assert_eq!(
+            "1000:spill".parse::<EntryLimit>(),
+            Ok(EntryLimit {
+                max_entries: 1000,
+                strategy: EvictionStrategy::Spill
+            })
+        );
+        assert!("0:reject".parse::<EntryLimit>().is_err());
+        assert!("1000".parse::<EntryLimit>().is_err());
+        assert!("1000:drop".parse::<EntryLimit>().is_err());
+    }
+
+    #[test]
+    fn test_evict_oldest() {
+        let limit: EntryLimit = "3:reject".parse().unwrap();
+        let mut account = account_with_deposits(5);
+        assert_eq!(tx_ids(&limit.evict(&mut account)), vec![1, 2]);
+        assert_eq!(tx_ids(account.transactions()), vec![3, 4, 5]);
+        assert!(limit.evict(&mut account).is_empty());
+    }
+
+    #[test]
+    fn test_compact_keeps_disputable_transactions() {
+        let limit: EntryLimit = "2:compact".parse().unwrap();
+        let mut account = account_with_deposits(4);
+        assert!(account.set_transaction_as_dispute(3).is_ok());
+        assert!(account.set_transaction_as_resolved(3).is_ok());
+        assert!(account.set_transaction_as_dispute(4).is_ok());
+        assert!(account.set_transaction_as_chargeback(4).is_ok());
+
+        assert_eq!(tx_ids(&limit.evict(&mut account)), vec![3, 4]);
+        assert_eq!(tx_ids(account.transactions()), vec![1, 2]);
+
+        let mut account = account_with_deposits(4);
+        assert!(limit.evict(&mut account).is_empty());
+        assert_eq!(account.transactions().len(), 4);
+    }
+}
@@ -0,0 +1,106 @@
+use crate::account::DepositedTransactionStatus;
+use crate::charged_back;
+use crate::statemachine::Event;
+
+use thiserror::Error;
+
+// Reason why a transaction wasn't applied to an account. The account is left unchanged.
+#[derive(Error, Clone, Copy, PartialEq, Debug)]
+pub enum TransactionError {
+    #[error("transaction of client {client} can not be applied to account {account}")]
+    WrongAccount { client: u16, account: u16 },
+    #[error("amount is zero or negative")]
+    InvalidAmount,
+    // A part of a dispute or a chargeback larger than what it refers to.
+    #[error("amount exceeds the transaction or its disputed amount")]
+    ExceedsDisputed,
+    #[error("account is locked")]
+    AccountLocked,
+    #[error("account is already locked")]
+    AlreadyLocked,
+    #[error("account is not locked")]
+    NotLocked,
+    #[error("insufficient funds")]
+    InsufficientFunds,
+    #[error("account has no negative balance")]
+    NoNegativeBalance,
+    #[error("balances would overflow")]
+    Overflow,
+    #[error("transaction is not found")]
+    UnknownTransaction,
+    #[error("transaction belongs to client {owner}, not to client {client}")]
+    ClientMismatch { client: u16, owner: u16 },
+    #[error("can not {} a transaction in {} state", .event.name(), .state.name())]
+    InvalidState {
+        state: DepositedTransactionStatus,
+        event: Event,
+    },
+    #[error("transaction is charged back")]
+    ChargedBack,
+    #[error("dispute window has passed")]
+    DisputeWindowExpired,
+    #[error("transaction is not pending approval")]
+    NotPendingApproval,
+    #[error("transfer is from and to the same account")]
+    SameAccount,
+    #[error("withdrawal is above the withdrawal limit")]
+    WithdrawalLimit,
+    #[error("withdrawals would exceed the daily withdrawal limit")]
+    DailyWithdrawalLimit,
+    #[error("balance would exceed the balance limit")]
+    BalanceLimit,
+}
+
+impl TransactionError {
+    // Stable code of the error for logs and reports.
+    pub fn code(&self) -> &'static str {
+        match self {
+            TransactionError::WrongAccount { .. } => "wrong_account",
+            TransactionError::InvalidAmount => "invalid_amount",
+            TransactionError::ExceedsDisputed => "exceeds_disputed",
+            TransactionError::AccountLocked => "account_locked",
+            TransactionError::AlreadyLocked => "already_locked",
+            TransactionError::NotLocked => "not_locked",
+            TransactionError::InsufficientFunds => "insufficient_funds",
+            TransactionError::NoNegativeBalance => "no_negative_balance",
+            TransactionError::Overflow => "overflow",
+            TransactionError::UnknownTransaction => "unknown_transaction",
+            TransactionError::ClientMismatch { .. } => "client_mismatch",
+            TransactionError::InvalidState { .. } => "invalid_state",
+            TransactionError::ChargedBack => charged_back::REJECTION_CODE,
+            TransactionError::DisputeWindowExpired => "dispute_window_expired",
+            TransactionError::NotPendingApproval => "not_pending_approval",
+            TransactionError::SameAccount => "same_account",
+            TransactionError::WithdrawalLimit => "withdrawal_limit",
+            TransactionError::DailyWithdrawalLimit => "daily_withdrawal_limit",
+            TransactionError::BalanceLimit => "balance_limit",
+        }
+    }
+}
+
+// Kind of failure of a whole run, attached to an error as its context, so the binary can exit with
+// a code telling it apart. Errors without a kind are told apart by their causes.
+#[derive(Error, Clone, Copy, PartialEq, Debug)]
+pub enum Failure {
+    #[error("invalid arguments")]
+    Usage,
+    // Input can't be parsed or breaks a policy which aborts processing.
+    #[error("invalid input")]
+    InvalidInput,
+    // A panic or a broken invariant of the engine.
+    #[error("internal error")]
+    Internal,
+    #[error("interrupted by a shutdown signal")]
+    Interrupted,
+    // A store of accounts can't be read, the same transaction may be applied later.
+    #[error("store is unavailable")]
+    Unavailable,
+}
+
+impl Failure {
+    // Whether a transaction which failed this way may be submitted again. Transactions rejected
+    // by account logic, for example for insufficient funds or a duplicate id, are rejected again.
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, Failure::Unavailable)
+    }
+}
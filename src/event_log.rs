@@ -0,0 +1,236 @@
+use crate::account::{Account, BalanceSnapshot};
+use crate::period::Period;
+use crate::transaction::Transaction;
+
+use crate::amount::Amount;
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+
+// Type of an event written when an account is opened, before the first event of the account.
+pub const OPEN: &str = "open";
+
+// An opened account or an accepted transaction with changes it made to balances of the account.
+// Amounts are kept exactly as decimal strings, independently of the output format.
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct Event {
+    #[serde(rename = "type")]
+    pub tx_type: String,
+    pub client: u16,
+    pub tx: u32,
+    pub available: Amount,
+    pub held: Amount,
+    pub total: Amount,
+    // Lock state of the account after the transaction.
+    pub locked: bool,
+    // Accounting period of the transaction, none for an opened account.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub period: Option<Period>,
+}
+
+impl Event {
+    pub fn open(client_id: u16) -> Event {
+        Event {
+            tx_type: OPEN.to_string(),
+            client: client_id,
+            tx: 0,
+            available: Amount::default(),
+            held: Amount::default(),
+            total: Amount::default(),
+            locked: false,
+            period: None,
+        }
+    }
+
+    // An accepted transaction with the difference between balances before it and now.
+    pub fn applied(
+        transaction: &Transaction,
+        period: &Period,
+        before: BalanceSnapshot,
+        account: &Account,
+    ) -> Event {
+        Event {
+            tx_type: transaction.tx_type.name().to_string(),
+            client: account.id(),
+            tx: transaction.tx_id(),
+            available: account.available() - before.available,
+            held: account.held() - before.held,
+            total: account.total() - before.total,
+            locked: account.is_locked(),
+            period: Some(period.clone()),
+        }
+    }
+
+    // A deposit applied together with other deposits of the account, so its change of balances
+    // is its amount.
+    pub fn deposit(
+        transaction: &Transaction,
+        period: &Period,
+        amount: Amount,
+        account: &Account,
+    ) -> Event {
+        Event {
+            tx_type: transaction.tx_type.name().to_string(),
+            client: account.id(),
+            tx: transaction.tx_id(),
+            available: amount,
+            held: Amount::default(),
+            total: amount,
+            locked: account.is_locked(),
+            period: Some(period.clone()),
+        }
+    }
+}
+
+// Append-only log of accepted transactions, one JSON event per line. Every event is flushed
+// before its account takes the change, so accounts can always be rebuilt from it with `replay`,
+// even after a crash.
+pub struct EventLog {
+    writer: BufWriter<File>,
+}
+
+impl EventLog {
+    // Events are appended to an existing log, so a log may cover several runs with the same
+    // archive or store.
+    pub fn open(file_path: &OsString) -> anyhow::Result<EventLog> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(file_path)
+            .with_context(|| format!("can not open event log {:?}", file_path))?;
+        Ok(EventLog {
+            writer: BufWriter::new(file),
+        })
+    }
+
+    pub fn append_open(&mut self, client_id: u16) -> anyhow::Result<()> {
+        self.write(&Event::open(client_id))
+    }
+
+    pub fn append(
+        &mut self,
+        transaction: &Transaction,
+        period: &Period,
+        before: BalanceSnapshot,
+        account: &Account,
+    ) -> anyhow::Result<()> {
+        self.write(&Event::applied(transaction, period, before, account))
+    }
+
+    pub fn append_deposit(
+        &mut self,
+        transaction: &Transaction,
+        period: &Period,
+        amount: Amount,
+        account: &Account,
+    ) -> anyhow::Result<()> {
+        self.write(&Event::deposit(transaction, period, amount, account))
+    }
+
+    fn write(&mut self, event: &Event) -> anyhow::Result<()> {
+        serde_json::to_writer(&mut self.writer, event)?;
+        self.writer.write_all(b"\n")?;
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> anyhow::Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+// Rebuilds accounts purely from an event log.
+pub fn replay(file_path: &OsString) -> anyhow::Result<HashMap<u16, Account>> {
+    let file =
+        File::open(file_path).with_context(|| format!("can not open event log {:?}", file_path))?;
+
+    let mut accounts = HashMap::new();
+    for (index, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.with_context(|| format!("can not read event log {:?}", file_path))?;
+        if line.is_empty() {
+            continue;
+        }
+        let event: Event = serde_json::from_str(&line)
+            .with_context(|| format!("invalid event on line {} of event log", index + 1))?;
+
+        let account = accounts
+            .entry(event.client)
+            .or_insert_with(|| Account::new(event.client));
+        let balances = account.balances();
+        account
+            .restore_balances(BalanceSnapshot {
+                available: balances.available + event.available,
+                held: balances.held + event.held,
+                total: balances.total + event.total,
+                locked: event.locked,
+            })
+            .with_context(|| format!("invalid event on line {} of event log", index + 1))?;
+    }
+
+    Ok(accounts)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::engine::PaymentsEngine;
+    use crate::transaction::{Deposit, TransactionType};
+
+    #[test]
+    fn test_replay_rebuilds_accounts() {
+        let path = std::env::temp_dir().join("coding_test_event_log.ndjson");
+        let _ = std::fs::remove_file(&path);
+        let path = path.into_os_string();
+
+        let mut engine = PaymentsEngine::default();
+        engine.set_coalesce_deposits(true);
+        engine.set_event_log(EventLog::open(&path).unwrap());
+        assert!(engine
+            .process_file(&OsString::from("transactions_3.csv"))
+            .is_ok());
+
+        let replayed = replay(&path).unwrap();
+        assert_eq!(replayed.len(), engine.accounts().len());
+        for (client_id, account) in engine.accounts() {
+            let replayed = &replayed[client_id];
+            assert_eq!(replayed.available(), account.available());
+            assert_eq!(replayed.held(), account.held());
+            assert_eq!(replayed.total(), account.total());
+            assert_eq!(replayed.is_locked(), account.is_locked());
+        }
+    }
+
+    #[test]
+    fn test_events_are_flushed_when_written() {
+        let path = std::env::temp_dir().join(format!(
+            "coding_test_event_log_flushed_{}.ndjson",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        let path = path.into_os_string();
+
+        let mut engine = PaymentsEngine::default();
+        engine.set_event_log(EventLog::open(&path).unwrap());
+        let transaction = Transaction {
+            tx_type: TransactionType::Deposit(Deposit {
+                client_id: 1,
+                tx_id: 1,
+                amount: Amount::from(2),
+            }),
+            timestamp: None,
+            operator: None,
+            reason: None,
+            approved_by: None,
+        };
+        assert!(engine.process_transaction(transaction).is_ok());
+
+        // Nothing flushed the engine, the event was flushed when it was written.
+        let replayed = replay(&path).unwrap();
+        assert_eq!(replayed[&1].total(), engine.accounts()[&1].total());
+        let _ = std::fs::remove_file(&path);
+    }
+}
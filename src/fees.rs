@@ -0,0 +1,203 @@
+use crate::account::Account;
+use crate::decimal_format;
+use crate::engine_config::EngineConfig;
+use crate::error::TransactionError;
+use crate::output;
+use crate::transaction::{Process, TransactionType};
+
+use crate::amount::Amount;
+use anyhow::{bail, Context};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::Read;
+
+// Fee of one transaction type, a flat amount and a percentage of the transaction amount.
+#[derive(Clone, Copy, Default, PartialEq, Debug)]
+pub struct Fee {
+    pub flat: Amount,
+    pub percent: Amount,
+}
+
+impl Fee {
+    pub fn of(&self, amount: Amount) -> Amount {
+        self.flat + amount * self.percent / Amount::from(100)
+    }
+}
+
+#[derive(Deserialize)]
+struct FeeRow {
+    #[serde(rename = "type")]
+    tx_type: String,
+    flat: Option<Amount>,
+    percent: Option<Amount>,
+}
+
+#[derive(Clone, Copy, Default)]
+struct ChargedFees {
+    withdrawal: Amount,
+    chargeback: Amount,
+}
+
+#[derive(Serialize)]
+struct FeeReportRow {
+    client: u16,
+    #[serde(serialize_with = "decimal_format::serialize")]
+    withdrawal_fees: Amount,
+    #[serde(serialize_with = "decimal_format::serialize")]
+    chargeback_fees: Amount,
+    #[serde(serialize_with = "decimal_format::serialize")]
+    total_fees: Amount,
+}
+
+// Fees charged on withdrawals and chargebacks. A fee is taken from available funds of the
+// account as a part of the transaction it's charged for, so it never shows up as a transaction
+// of its own which could be disputed.
+pub struct Fees {
+    withdrawal: Option<Fee>,
+    chargeback: Option<Fee>,
+    charged: HashMap<u16, ChargedFees>,
+}
+
+impl Fees {
+    pub fn new(withdrawal: Option<Fee>, chargeback: Option<Fee>) -> Fees {
+        Fees {
+            withdrawal,
+            chargeback,
+            charged: HashMap::new(),
+        }
+    }
+
+    // Reads a fee schedule with `type`, `flat` and `percent` columns, one row per transaction
+    // type. Missing amounts are zero.
+    pub fn read<R: Read>(input: R) -> anyhow::Result<Fees> {
+        let mut reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(input);
+        let mut fees = Fees::new(None, None);
+        for (index, row) in reader.deserialize::<FeeRow>().enumerate() {
+            // Line 1 is the header.
+            let line = index + 2;
+            let row = row.with_context(|| format!("invalid fee on line {}", line))?;
+            let fee = Fee {
+                flat: row.flat.unwrap_or_default(),
+                percent: row.percent.unwrap_or_default(),
+            };
+            if fee.flat.is_sign_negative() || fee.percent.is_sign_negative() {
+                bail!("fee on line {} is negative", line);
+            }
+            let schedule = match row.tx_type.as_str() {
+                "withdrawal" => &mut fees.withdrawal,
+                "chargeback" => &mut fees.chargeback,
+                other => bail!("fees can not be charged on {} on line {}", other, line),
+            };
+            if schedule.replace(fee).is_some() {
+                bail!("fee of {} is repeated on line {}", row.tx_type, line);
+            }
+        }
+
+        Ok(fees)
+    }
+
+    pub fn load(file_path: &OsString) -> anyhow::Result<Fees> {
+        let file = File::open(file_path)
+            .with_context(|| format!("can not open fee schedule {:?}", file_path))?;
+        Fees::read(file).with_context(|| format!("can not read fee schedule {:?}", file_path))
+    }
+
+    // Fee of a transaction about to be applied to an account. A chargeback fee is a percentage of
+    // the charged back amount.
+    pub fn fee(&self, tx_type: &TransactionType, account: &Account) -> Amount {
+        match tx_type {
+            TransactionType::Withdrawal(withdrawal) => self
+                .withdrawal
+                .map_or(Amount::from(0), |fee| fee.of(withdrawal.amount)),
+            TransactionType::Chargeback(chargeback) => {
+                match (self.chargeback, account.find_transaction(chargeback.tx_id)) {
+                    (Some(fee), Some(transaction)) => fee.of(chargeback
+                        .amount
+                        .unwrap_or_else(|| transaction.disputed_amount())),
+                    _ => Amount::from(0),
+                }
+            }
+            _ => Amount::from(0),
+        }
+    }
+
+    pub fn record(&mut self, tx_type: &TransactionType, client_id: u16, fee: Amount) {
+        let charged = self.charged.entry(client_id).or_default();
+        match tx_type {
+            TransactionType::Withdrawal(_) => charged.withdrawal += fee,
+            TransactionType::Chargeback(_) => charged.chargeback += fee,
+            _ => {}
+        }
+    }
+
+    // Adds fees charged on other accounts, for example by another shard.
+    pub fn merge(&mut self, other: Fees) {
+        self.charged.extend(other.charged);
+    }
+
+    pub fn save_report(&self, file_path: &OsString) -> anyhow::Result<()> {
+        let charged: BTreeMap<&u16, &ChargedFees> = self.charged.iter().collect();
+        let rows = charged.into_iter().map(|(client, charged)| FeeReportRow {
+            client: *client,
+            withdrawal_fees: charged.withdrawal,
+            chargeback_fees: charged.chargeback,
+            total_fees: charged.withdrawal + charged.chargeback,
+        });
+        output::save_report(rows, file_path, "fee report")
+    }
+}
+
+// Applies a transaction and charges its fee. A withdrawal needs available funds for its amount
+// and its fee, while a chargeback fee is charged even if it leaves the account owing funds.
+pub fn process(
+    tx_type: &TransactionType,
+    account: &mut Account,
+    fee: Amount,
+    config: &EngineConfig,
+) -> Result<(), TransactionError> {
+    if let TransactionType::Withdrawal(withdrawal) = tx_type {
+        if (!account.is_locked() || config.locked_policy.withdrawals)
+            && account.available() >= withdrawal.amount
+            && account.available() - withdrawal.amount < fee
+        {
+            warn!(
+                "account: {} has insufficient funds available for a fee",
+                account.id()
+            );
+            return Err(TransactionError::InsufficientFunds);
+        }
+    }
+    tx_type.process_with(account, config)?;
+    account.charge_fee(fee, config.overflow_policy)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_read_fee_schedule() {
+        let fees =
+            Fees::read("type,flat,percent\nwithdrawal,0.5,1\nchargeback,15,\n".as_bytes()).unwrap();
+        assert_eq!(
+            fees.withdrawal.unwrap().of(Amount::from(200)),
+            Amount::from_str("2.5").unwrap()
+        );
+        assert_eq!(
+            fees.chargeback.unwrap().of(Amount::from(200)),
+            Amount::from(15)
+        );
+
+        assert!(Fees::read("type,flat,percent\ndeposit,1,\n".as_bytes()).is_err());
+        assert!(Fees::read("type,flat,percent\nwithdrawal,-1,\n".as_bytes()).is_err());
+        assert!(
+            Fees::read("type,flat,percent\nwithdrawal,1,\nwithdrawal,2,\n".as_bytes()).is_err()
+        );
+    }
+}
@@ -0,0 +1,150 @@
+use crate::engine::PaymentsEngine;
+use crate::output;
+use crate::sink::JsonSink;
+
+use log::error;
+use std::os::raw::c_char;
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::slice;
+
+// C interface of the engine for services embedding it instead of running the binary, declared in
+// `include/payments_engine.h`. Errors and panics never cross the boundary, they are logged and
+// reported by return values. An engine whose processing panicked shouldn't be used any more.
+
+pub const PAYMENTS_OK: i32 = 0;
+pub const PAYMENTS_ERROR: i32 = -1;
+
+/// Creates an engine with default policies. Returns null if it can't be created.
+#[no_mangle]
+pub extern "C" fn payments_engine_new() -> *mut PaymentsEngine {
+    panic::catch_unwind(|| Box::into_raw(Box::new(PaymentsEngine::default())))
+        .unwrap_or(ptr::null_mut())
+}
+
+/// Processes a CSV document of `len` bytes with a header row, like an input file. Invalid rows and
+/// rejected transactions are skipped. Returns `PAYMENTS_OK`, or `PAYMENTS_ERROR` if input can't be
+/// read.
+///
+/// # Safety
+///
+/// `engine` must be returned by `payments_engine_new` and not freed yet, and `data` must point to
+/// `len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn payments_engine_process_csv(
+    engine: *mut PaymentsEngine,
+    data: *const u8,
+    len: usize,
+) -> i32 {
+    let engine = match engine.as_mut() {
+        None => return PAYMENTS_ERROR,
+        Some(engine) => engine,
+    };
+    let input = match (data.is_null(), len) {
+        (_, 0) => &[][..],
+        (true, _) => return PAYMENTS_ERROR,
+        (false, _) => slice::from_raw_parts(data, len),
+    };
+    match panic::catch_unwind(AssertUnwindSafe(|| engine.process_csv(input))) {
+        Ok(Ok(())) => PAYMENTS_OK,
+        Ok(Err(err)) => {
+            error!("can not process CSV input: {:#}", err);
+            PAYMENTS_ERROR
+        }
+        Err(_) => PAYMENTS_ERROR,
+    }
+}
+
+/// Writes accounts as a JSON array into `buffer` of `capacity` bytes, like accounts output in JSON
+/// format. Returns the length of the JSON without the terminating NUL, and the JSON is written only
+/// if `capacity` is larger than that, so it may be called with a null buffer first to size it.
+/// Returns `PAYMENTS_ERROR` if accounts can't be serialized.
+///
+/// # Safety
+///
+/// `engine` must be returned by `payments_engine_new` and not freed yet, and `buffer` must point to
+/// `capacity` writable bytes or be null.
+#[no_mangle]
+pub unsafe extern "C" fn payments_engine_accounts_json(
+    engine: *const PaymentsEngine,
+    buffer: *mut c_char,
+    capacity: usize,
+) -> isize {
+    let engine = match engine.as_ref() {
+        None => return PAYMENTS_ERROR as isize,
+        Some(engine) => engine,
+    };
+    let json = panic::catch_unwind(AssertUnwindSafe(|| {
+        let _config = engine.config().enter();
+        let mut json = Vec::new();
+        output::write_accounts(engine.accounts(), &mut JsonSink::new(&mut json)).map(|_| json)
+    }));
+    let json = match json {
+        Ok(Ok(json)) => json,
+        Ok(Err(err)) => {
+            error!("can not serialize accounts: {:#}", err);
+            return PAYMENTS_ERROR as isize;
+        }
+        Err(_) => return PAYMENTS_ERROR as isize,
+    };
+    if !buffer.is_null() && capacity > json.len() {
+        ptr::copy_nonoverlapping(json.as_ptr(), buffer.cast::<u8>(), json.len());
+        *buffer.add(json.len()) = 0;
+    }
+    json.len() as isize
+}
+
+/// Frees an engine. Null is ignored.
+///
+/// # Safety
+///
+/// `engine` must be returned by `payments_engine_new` and not freed yet, and it can't be used
+/// afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn payments_engine_free(engine: *mut PaymentsEngine) {
+    if !engine.is_null() {
+        drop(Box::from_raw(engine));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_engine_over_ffi() {
+        let input = "type,client,tx,amount\ndeposit,1,1,10\nwithdrawal,1,2,2.5\ndeposit,2,3,1\n";
+        unsafe {
+            let engine = payments_engine_new();
+            assert!(!engine.is_null());
+            assert_eq!(
+                payments_engine_process_csv(engine, input.as_ptr(), input.len()),
+                PAYMENTS_OK
+            );
+            assert_eq!(
+                payments_engine_process_csv(engine, ptr::null(), 1),
+                PAYMENTS_ERROR
+            );
+
+            let len = payments_engine_accounts_json(engine, ptr::null_mut(), 0);
+            assert!(len > 0);
+            let mut buffer = vec![1 as c_char; len as usize + 1];
+            assert_eq!(
+                payments_engine_accounts_json(engine, buffer.as_mut_ptr(), buffer.len()),
+                len
+            );
+            let json = std::ffi::CStr::from_ptr(buffer.as_ptr()).to_str().unwrap();
+            let accounts: serde_json::Value = serde_json::from_str(json).unwrap();
+            assert_eq!(accounts.as_array().unwrap().len(), 2);
+            assert_eq!(accounts[0]["client"], 1);
+            payments_engine_free(engine);
+        }
+        unsafe {
+            assert_eq!(
+                payments_engine_process_csv(ptr::null_mut(), input.as_ptr(), input.len()),
+                PAYMENTS_ERROR
+            );
+            payments_engine_free(ptr::null_mut());
+        }
+    }
+}
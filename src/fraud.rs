@@ -0,0 +1,255 @@
+use crate::account::Account;
+use crate::output;
+use crate::transaction::{Transaction, TransactionType};
+
+use crate::amount::Amount;
+use anyhow::{anyhow, bail};
+use log::warn;
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::ffi::OsString;
+use std::str::FromStr;
+
+// Fraud rules watch activity of every client within the stream and flag suspicious transactions.
+// Unlike alerts, which are about the whole engine, flags are about single transactions and are
+// kept for a report written next to the accounts output.
+#[derive(Clone, PartialEq, Debug)]
+pub enum FraudRule {
+    // More than `max` withdrawals among the last `window` rows of a client. Rejected withdrawals
+    // count too, as repeated attempts are a signal as well.
+    Withdrawals { max: usize, window: usize },
+    // A withdrawal which empties the account right after a deposit.
+    DepositWithdrawal,
+}
+
+impl FraudRule {
+    pub fn name(&self) -> &'static str {
+        match self {
+            FraudRule::Withdrawals { .. } => "withdrawals",
+            FraudRule::DepositWithdrawal => "deposit-withdrawal",
+        }
+    }
+}
+
+fn parse_field<T: FromStr>(rule: &str, value: Option<&str>) -> anyhow::Result<T> {
+    value
+        .and_then(|value| value.parse().ok())
+        .ok_or_else(|| anyhow!("invalid fraud rule: {}", rule))
+}
+
+// Rule is given as `withdrawals:<withdrawals>:<rows>` or `deposit-withdrawal`.
+impl FromStr for FraudRule {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<FraudRule> {
+        let mut fields = s.split(':');
+        let rule = match fields.next() {
+            Some("withdrawals") => FraudRule::Withdrawals {
+                max: parse_field(s, fields.next())?,
+                window: parse_field(s, fields.next())?,
+            },
+            Some("deposit-withdrawal") => FraudRule::DepositWithdrawal,
+            _ => bail!("unknown fraud rule: {}", s),
+        };
+        if fields.next().is_some() {
+            bail!("invalid fraud rule: {}", s);
+        }
+        if let FraudRule::Withdrawals { window: 0, .. } = rule {
+            bail!("window of fraud rule must be at least 1 row: {}", s);
+        }
+
+        Ok(rule)
+    }
+}
+
+#[derive(Serialize, Clone, PartialEq, Debug)]
+pub struct FraudFlag {
+    // Position of the flagged transaction in input.
+    pub line: u64,
+    pub client: u16,
+    pub tx: u32,
+    pub rule: &'static str,
+    pub detail: String,
+}
+
+// Recent activity of a client.
+#[derive(Default)]
+struct Activity {
+    // Whether each of the last rows of the client was a withdrawal, the newest last.
+    withdrawals: VecDeque<bool>,
+    // Amount of the deposit applied by the previous row of the client, if it was one.
+    deposit: Option<Amount>,
+}
+
+pub struct FraudFlags {
+    rules: Vec<FraudRule>,
+    // Rows of a client kept for the rule with the largest window.
+    window: usize,
+    activity: HashMap<u16, Activity>,
+    flags: Vec<FraudFlag>,
+}
+
+impl FraudFlags {
+    pub fn new(rules: Vec<FraudRule>) -> FraudFlags {
+        let window = rules
+            .iter()
+            .map(|rule| match rule {
+                FraudRule::Withdrawals { window, .. } => *window,
+                FraudRule::DepositWithdrawal => 0,
+            })
+            .max()
+            .unwrap_or_default();
+        FraudFlags {
+            rules,
+            window,
+            activity: HashMap::new(),
+            flags: Vec::new(),
+        }
+    }
+
+    // Evaluates all rules after a row of a client was processed, `applied` tells whether it
+    // changed the account.
+    pub fn observe(
+        &mut self,
+        line: u64,
+        transaction: &Transaction,
+        applied: bool,
+        account: &Account,
+    ) {
+        let client = account.id();
+        let activity = self.activity.entry(client).or_default();
+        let withdrawal = match &transaction.tx_type {
+            TransactionType::Withdrawal(withdrawal) => Some(withdrawal.amount),
+            _ => None,
+        };
+        activity.withdrawals.push_back(withdrawal.is_some());
+        if activity.withdrawals.len() > self.window {
+            activity.withdrawals.pop_front();
+        }
+
+        for rule in &self.rules {
+            let detail = match (rule, withdrawal) {
+                (FraudRule::Withdrawals { max, window }, Some(_)) => {
+                    let withdrawals = activity
+                        .withdrawals
+                        .iter()
+                        .rev()
+                        .take(*window)
+                        .filter(|withdrawal| **withdrawal)
+                        .count();
+                    (withdrawals > *max).then(|| {
+                        format!(
+                            "{} withdrawals in the last {} rows of the client",
+                            withdrawals, window
+                        )
+                    })
+                }
+                (FraudRule::DepositWithdrawal, Some(amount)) => match activity.deposit {
+                    Some(deposit) if applied && account.available() <= Amount::from(0) => {
+                        Some(format!(
+                            "withdrawal of {} emptied the account right after a deposit of {}",
+                            amount, deposit
+                        ))
+                    }
+                    _ => None,
+                },
+                _ => None,
+            };
+            if let Some(detail) = detail {
+                warn!(
+                    "tx: {} of account {} flagged by fraud rule {}: {}",
+                    transaction.tx_id(),
+                    client,
+                    rule.name(),
+                    detail
+                );
+                self.flags.push(FraudFlag {
+                    line,
+                    client,
+                    tx: transaction.tx_id(),
+                    rule: rule.name(),
+                    detail,
+                });
+            }
+        }
+
+        activity.deposit = match &transaction.tx_type {
+            TransactionType::Deposit(deposit) if applied => Some(deposit.amount),
+            _ => None,
+        };
+    }
+
+    pub fn flags(&self) -> &[FraudFlag] {
+        &self.flags
+    }
+
+    // Adds flags of other clients, for example from another shard.
+    pub fn merge(&mut self, other: FraudFlags) {
+        self.activity.extend(other.activity);
+        self.flags.extend(other.flags);
+        self.flags.sort_by_key(|flag| flag.line);
+    }
+
+    pub fn save_report(&self, file_path: &OsString) -> anyhow::Result<()> {
+        output::save_report(&self.flags, file_path, "fraud report")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::source::{CsvSource, TransactionSource};
+    use crate::transaction::Process;
+
+    #[test]
+    fn test_parse_fraud_rule() {
+        assert_eq!(
+            "withdrawals:3:10".parse::<FraudRule>().unwrap(),
+            FraudRule::Withdrawals { max: 3, window: 10 }
+        );
+        assert_eq!(
+            "deposit-withdrawal".parse::<FraudRule>().unwrap(),
+            FraudRule::DepositWithdrawal
+        );
+        assert!("withdrawals:3".parse::<FraudRule>().is_err());
+        assert!("withdrawals:3:0".parse::<FraudRule>().is_err());
+        assert!("deposit-withdrawal:1".parse::<FraudRule>().is_err());
+        assert!("velocity".parse::<FraudRule>().is_err());
+    }
+
+    #[test]
+    fn test_flags() {
+        let mut fraud = FraudFlags::new(vec![
+            FraudRule::Withdrawals { max: 1, window: 3 },
+            FraudRule::DepositWithdrawal,
+        ]);
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,10\n\
+                     withdrawal,1,2,1\n\
+                     withdrawal,1,3,1\n\
+                     deposit,1,4,5\n\
+                     withdrawal,1,5,13\n\
+                     withdrawal,1,6,1\n";
+        let mut source = CsvSource::new(input.as_bytes());
+        let mut account = Account::new(1);
+        while let Some(Ok(transaction)) = source.next() {
+            let applied = transaction.tx_type.process(&mut account).is_ok();
+            fraud.observe(source.position(), &transaction, applied, &account);
+        }
+
+        let flagged: Vec<(u32, &str)> = fraud
+            .flags()
+            .iter()
+            .map(|flag| (flag.tx, flag.rule))
+            .collect();
+        assert_eq!(
+            flagged,
+            vec![
+                (3, "withdrawals"),
+                (5, "withdrawals"),
+                (5, "deposit-withdrawal"),
+                (6, "withdrawals"),
+            ]
+        );
+    }
+}
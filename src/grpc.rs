@@ -0,0 +1,374 @@
+use crate::account::Account;
+use crate::amount::Amount;
+use crate::decimal_format::DecimalFormat;
+use crate::engine::PaymentsEngine;
+use crate::error::Failure;
+use crate::rejections::{self, Rejection};
+use crate::shutdown::{Shutdown, SHUTDOWN_POLL};
+use crate::transaction::Transaction;
+
+use anyhow::Context;
+use log::{error, info};
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, PoisonError};
+use tonic::transport::Server;
+use tonic::{Request, Response, Status, Streaming};
+
+// Messages and service traits generated from `proto/payments.proto` by the build script.
+pub mod proto {
+    tonic::include_proto!("payments.v1");
+}
+
+use proto::payments_server::{Payments, PaymentsServer};
+
+// A message is converted the same way as a row of database input, so it's deserialized by the
+// same rules as CSV input and an invalid message is rejected like an invalid row.
+fn to_transaction(message: proto::Transaction) -> Result<Transaction, String> {
+    let mut object = Map::new();
+    object.insert(String::from("type"), Value::from(message.r#type));
+    object.insert(String::from("client"), Value::from(message.client));
+    object.insert(String::from("tx"), Value::from(message.tx));
+    if let Some(amount) = message.amount {
+        object.insert(String::from("amount"), Value::from(amount));
+    }
+    if let Some(timestamp) = message.timestamp {
+        object.insert(String::from("timestamp"), Value::from(timestamp));
+    }
+    if let Some(operator) = message.operator {
+        object.insert(String::from("operator"), Value::from(operator));
+    }
+    if let Some(reason) = message.reason {
+        object.insert(String::from("reason"), Value::from(reason));
+    }
+    if let Some(from_client) = message.from_client {
+        object.insert(String::from("from_client"), Value::from(from_client));
+    }
+    if let Some(to_client) = message.to_client {
+        object.insert(String::from("to_client"), Value::from(to_client));
+    }
+    serde_json::from_value(Value::Object(object)).map_err(|error| error.to_string())
+}
+
+fn format_amount(format: &DecimalFormat, amount: &Amount) -> String {
+    let mut text = String::new();
+    format.write(amount, &mut text);
+    text
+}
+
+fn to_message(account: &Account, format: DecimalFormat) -> proto::Account {
+    proto::Account {
+        client: account.id().into(),
+        available: format_amount(&format, &account.available()),
+        held: format_amount(&format, &account.held()),
+        total: format_amount(&format, &account.total()),
+        locked: account.is_locked(),
+    }
+}
+
+fn to_rejection(message: u64, rejection: Rejection) -> proto::Rejection {
+    proto::Rejection {
+        message,
+        client: rejection.client.map(u32::from),
+        tx: rejection.tx,
+        code: rejection.code.to_string(),
+        reason: rejection.reason,
+        retryable: false,
+    }
+}
+
+// Service sharing one engine between all calls. Every transaction is applied while the engine is
+// locked, so transactions of concurrent streams are interleaved but never applied partially.
+pub struct PaymentsService {
+    engine: Arc<Mutex<PaymentsEngine>>,
+}
+
+impl PaymentsService {
+    pub fn new(engine: Arc<Mutex<PaymentsEngine>>) -> PaymentsService {
+        PaymentsService { engine }
+    }
+}
+
+// An engine is poisoned if processing of a transaction panicked, its accounts can't be trusted
+// anymore then.
+fn poisoned<T>(_: PoisonError<T>) -> Status {
+    Status::internal("engine failed while processing a transaction")
+}
+
+#[tonic::async_trait]
+impl Payments for PaymentsService {
+    async fn submit_transactions(
+        &self,
+        request: Request<Streaming<proto::Transaction>>,
+    ) -> Result<Response<proto::SubmitSummary>, Status> {
+        let mut stream = request.into_inner();
+        // Messages are numbered within their stream like lines of an input file. Rejections are
+        // taken per message, as other streams may be applied at the same time.
+        let mut received = 0;
+        let mut rejected = Vec::new();
+        while let Some(message) = stream.message().await? {
+            received += 1;
+            let mut engine = self.engine.lock().map_err(poisoned)?;
+            engine.keep_rejected();
+            let (client, tx) = (message.client, message.tx);
+            // Amounts of a message are read by the config of the engine.
+            let _config = engine.config().enter();
+            let processed = match to_transaction(message) {
+                Ok(transaction) => engine.process_batch(vec![(received, transaction)]),
+                Err(reason) => engine.reject_invalid(received, reason, None),
+            };
+            rejected.extend(
+                engine
+                    .take_rejected()
+                    .into_iter()
+                    .map(|rejection| to_rejection(received, rejection)),
+            );
+            match processed {
+                Ok(()) => {}
+                // Nothing was recorded for the transaction, so a producer may submit it again.
+                Err(err)
+                    if err
+                        .downcast_ref::<Failure>()
+                        .is_some_and(Failure::is_retryable) =>
+                {
+                    error!("can not process message {}: {:#}", received, err);
+                    rejected.push(proto::Rejection {
+                        message: received,
+                        client: Some(client),
+                        tx: Some(tx),
+                        code: rejections::STORE_UNAVAILABLE.to_string(),
+                        reason: format!("{:#}", err),
+                        retryable: true,
+                    });
+                }
+                Err(err) => {
+                    error!("can not process message {}: {:#}", received, err);
+                    return Err(Status::internal(format!("{:#}", err)));
+                }
+            }
+        }
+
+        self.engine
+            .lock()
+            .map_err(poisoned)?
+            .flush()
+            .map_err(|err| Status::internal(format!("{:#}", err)))?;
+        let mut rejections: BTreeMap<String, u64> = BTreeMap::new();
+        for rejection in &rejected {
+            *rejections.entry(rejection.code.clone()).or_default() += 1;
+        }
+        info!(
+            "stream of {} transactions applied, {} rejected",
+            received,
+            rejected.len()
+        );
+
+        Ok(Response::new(proto::SubmitSummary {
+            received,
+            applied: received - rejected.len() as u64,
+            rejected: rejected.len() as u64,
+            rejections: rejections.into_iter().collect(),
+            rejected_transactions: rejected,
+        }))
+    }
+
+    async fn get_account(
+        &self,
+        request: Request<proto::GetAccountRequest>,
+    ) -> Result<Response<proto::Account>, Status> {
+        let client = request.into_inner().client;
+        let client_id = u16::try_from(client)
+            .map_err(|_| Status::invalid_argument(format!("invalid client id {}", client)))?;
+        let engine = self.engine.lock().map_err(poisoned)?;
+        match engine.accounts().get(&client_id) {
+            None => Err(Status::not_found(format!(
+                "client {} has no account",
+                client
+            ))),
+            Some(account) => Ok(Response::new(to_message(
+                account,
+                engine.config().decimal_format,
+            ))),
+        }
+    }
+}
+
+// Serves until a shutdown is requested. The server then stops accepting streams and returns once
+// streams in progress are finished.
+pub async fn serve(
+    engine: Arc<Mutex<PaymentsEngine>>,
+    address: SocketAddr,
+    shutdown: Shutdown,
+) -> anyhow::Result<()> {
+    info!("gRPC server listening on {}", address);
+    let requested = async move {
+        while !shutdown.requested() {
+            tokio::time::sleep(SHUTDOWN_POLL).await;
+        }
+        info!("shutdown requested, waiting for streams in progress");
+    };
+    Server::builder()
+        .add_service(PaymentsServer::new(PaymentsService::new(engine)))
+        .serve_with_shutdown(address, requested)
+        .await
+        .with_context(|| format!("gRPC server on {} failed", address))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::store::{AccountStore, CompactionReport, StoreStats};
+    use anyhow::bail;
+    use proto::payments_client::PaymentsClient;
+    use tokio::net::TcpListener;
+    use tokio_stream::wrappers::TcpListenerStream;
+
+    fn transaction(tx_type: &str, tx: u32, amount: Option<&str>) -> proto::Transaction {
+        proto::Transaction {
+            r#type: tx_type.to_string(),
+            client: 1,
+            tx,
+            amount: amount.map(str::to_string),
+            ..proto::Transaction::default()
+        }
+    }
+
+    // Store whose accounts can never be read, like a database which is down.
+    struct UnavailableStore;
+
+    impl AccountStore for UnavailableStore {
+        fn contains(&self, _: u16) -> bool {
+            true
+        }
+
+        fn take(&mut self, _: u16) -> anyhow::Result<Option<Account>> {
+            bail!("connection refused")
+        }
+
+        fn put(&mut self, _: &Account) -> anyhow::Result<()> {
+            bail!("connection refused")
+        }
+
+        fn get(&self, _: u16) -> anyhow::Result<Option<Account>> {
+            bail!("connection refused")
+        }
+
+        fn clients(&self) -> Vec<u16> {
+            Vec::new()
+        }
+
+        fn len(&self) -> usize {
+            0
+        }
+
+        fn stats(&self) -> anyhow::Result<StoreStats> {
+            bail!("connection refused")
+        }
+
+        fn compact(&mut self) -> anyhow::Result<CompactionReport> {
+            bail!("connection refused")
+        }
+    }
+
+    async fn connect(
+        engine: Arc<Mutex<PaymentsEngine>>,
+    ) -> PaymentsClient<tonic::transport::Channel> {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(
+            Server::builder()
+                .add_service(PaymentsServer::new(PaymentsService::new(engine)))
+                .serve_with_incoming(TcpListenerStream::new(listener)),
+        );
+
+        PaymentsClient::connect(format!("http://{}", address))
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_submit_and_get_account() {
+        let engine = Arc::new(Mutex::new(PaymentsEngine::default()));
+        let mut client = connect(engine.clone()).await;
+        let transactions = vec![
+            transaction("deposit", 1, Some("10.5")),
+            transaction("withdrawal", 2, Some("20")),
+            transaction("deposit", 3, Some("x")),
+            transaction("dispute", 1, None),
+        ];
+        let summary = client
+            .submit_transactions(tokio_stream::iter(transactions))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(
+            (summary.received, summary.applied, summary.rejected),
+            (4, 2, 2)
+        );
+        assert_eq!(summary.rejections.get("insufficient_funds"), Some(&1));
+        assert_eq!(summary.rejections.get("invalid_record"), Some(&1));
+        let rejected: Vec<_> = summary
+            .rejected_transactions
+            .iter()
+            .map(|rejection| {
+                (
+                    rejection.message,
+                    rejection.code.as_str(),
+                    rejection.retryable,
+                )
+            })
+            .collect();
+        assert_eq!(
+            rejected,
+            vec![
+                (2, "insufficient_funds", false),
+                (3, "invalid_record", false)
+            ]
+        );
+
+        let account = client
+            .get_account(proto::GetAccountRequest { client: 1 })
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(account.held, "10.5000");
+        assert_eq!(account.total, "10.5000");
+        let missing = client
+            .get_account(proto::GetAccountRequest { client: 2 })
+            .await
+            .unwrap_err();
+        assert_eq!(missing.code(), tonic::Code::NotFound);
+        assert_eq!(engine.lock().unwrap().accounts().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_unavailable_store_is_retryable() {
+        let mut engine = PaymentsEngine::default();
+        engine.set_store(Box::new(UnavailableStore));
+        engine.set_reject_duplicates(true);
+        let engine = Arc::new(Mutex::new(engine));
+        let mut client = connect(engine.clone()).await;
+
+        let transactions = vec![transaction("deposit", 1, Some("10"))];
+        let summary = client
+            .submit_transactions(tokio_stream::iter(transactions.clone()))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!((summary.applied, summary.rejected), (0, 1));
+        let rejection = &summary.rejected_transactions[0];
+        assert_eq!(rejection.code, "store_unavailable");
+        assert_eq!((rejection.client, rejection.tx), (Some(1), Some(1)));
+        assert!(rejection.retryable);
+
+        // The failed transaction isn't recorded, so it isn't a duplicate when it's retried.
+        let summary = client
+            .submit_transactions(tokio_stream::iter(transactions))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(summary.rejections.get("store_unavailable"), Some(&1));
+        assert_eq!(summary.rejections.get("duplicate_transaction"), None);
+    }
+}
@@ -0,0 +1,224 @@
+use crate::event_log::Event;
+
+use anyhow::{bail, Context};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::PathBuf;
+
+// Hash a chain starts from, so the first entry links to something as well.
+pub const GENESIS: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+// An event of the event log with the hash of the entry before it and its own hash over both, so
+// any changed, removed or reordered entry breaks every hash after it.
+#[derive(Serialize, Deserialize)]
+struct ChainEntry {
+    seq: u64,
+    prev: String,
+    hash: String,
+    event: Event,
+}
+
+// Number of entries and the digest of a chain, kept next to it in `<chain>.head` and rewritten on
+// every flush. Entries cut from the end of a chain leave it valid up to the cut, so they are only
+// detected against the head.
+#[derive(Serialize, Deserialize)]
+struct ChainHead {
+    entries: u64,
+    digest: String,
+}
+
+fn head_path(file_path: &OsString) -> PathBuf {
+    let mut head_path = file_path.clone();
+    head_path.push(".head");
+    PathBuf::from(head_path)
+}
+
+fn entry_hash(prev: &str, event: &Event) -> anyhow::Result<String> {
+    let mut sha256 = Sha256::new();
+    sha256.update(prev.as_bytes());
+    sha256.update(b"\n");
+    sha256.update(serde_json::to_vec(event)?);
+    Ok(hex::encode(sha256.finalize()))
+}
+
+// Tamper-evident log of accepted transactions, one JSON entry per line. The hash of the last entry
+// is a digest of the whole sequence of events, so the same input always ends with the same digest
+// and a different one can't reach it.
+pub struct HashChain {
+    writer: BufWriter<File>,
+    head_path: PathBuf,
+    seq: u64,
+    last: String,
+}
+
+impl HashChain {
+    // A chain covers one run, an existing file is replaced.
+    pub fn create(file_path: &OsString) -> anyhow::Result<HashChain> {
+        let file = File::create(file_path)
+            .with_context(|| format!("can not create audit chain {:?}", file_path))?;
+        let chain = HashChain {
+            writer: BufWriter::new(file),
+            head_path: head_path(file_path),
+            seq: 0,
+            last: GENESIS.to_string(),
+        };
+        chain.write_head()?;
+        Ok(chain)
+    }
+
+    // The head is replaced by a rename, so it's never seen half written.
+    fn write_head(&self) -> anyhow::Result<()> {
+        let head = ChainHead {
+            entries: self.seq,
+            digest: self.last.clone(),
+        };
+        let mut temp_path = self.head_path.clone().into_os_string();
+        temp_path.push(".tmp");
+        fs::write(&temp_path, serde_json::to_vec(&head)?)
+            .with_context(|| format!("can not write audit chain head {:?}", temp_path))?;
+        fs::rename(&temp_path, &self.head_path)
+            .with_context(|| format!("can not write audit chain head {:?}", self.head_path))?;
+        Ok(())
+    }
+
+    pub fn append(&mut self, event: Event) -> anyhow::Result<()> {
+        self.seq += 1;
+        let hash = entry_hash(&self.last, &event)?;
+        let entry = ChainEntry {
+            seq: self.seq,
+            prev: std::mem::replace(&mut self.last, hash.clone()),
+            hash,
+            event,
+        };
+        serde_json::to_writer(&mut self.writer, &entry)?;
+        self.writer.write_all(b"\n")?;
+        Ok(())
+    }
+
+    // Hash of the last entry.
+    pub fn digest(&self) -> &str {
+        &self.last
+    }
+
+    pub fn entries(&self) -> u64 {
+        self.seq
+    }
+
+    // Entries are flushed before the head, so a head never records entries missing in the chain.
+    pub fn flush(&mut self) -> anyhow::Result<()> {
+        self.writer.flush()?;
+        self.write_head()
+    }
+}
+
+// Checks links and hashes of all entries of a chain and that they end where its head says, and
+// returns the number of entries and the digest of the chain.
+pub fn verify(file_path: &OsString) -> anyhow::Result<(u64, String)> {
+    let file = File::open(file_path)
+        .with_context(|| format!("can not open audit chain {:?}", file_path))?;
+
+    let mut seq = 0;
+    let mut last = GENESIS.to_string();
+    for (index, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.with_context(|| format!("can not read audit chain {:?}", file_path))?;
+        if line.is_empty() {
+            continue;
+        }
+        let entry: ChainEntry = serde_json::from_str(&line)
+            .with_context(|| format!("invalid entry on line {} of audit chain", index + 1))?;
+
+        seq += 1;
+        if entry.seq != seq {
+            bail!(
+                "entry {} is missing, line {} has entry {}",
+                seq,
+                index + 1,
+                entry.seq
+            );
+        }
+        if entry.prev != last {
+            bail!("entry {} doesn't link to the entry before it", seq);
+        }
+        if entry.hash != entry_hash(&entry.prev, &entry.event)? {
+            bail!("entry {} doesn't match its hash", seq);
+        }
+        last = entry.hash;
+    }
+
+    let head_path = head_path(file_path);
+    let head = fs::read(&head_path)
+        .with_context(|| format!("can not read audit chain head {:?}", head_path))?;
+    let head: ChainHead = serde_json::from_slice(&head)
+        .with_context(|| format!("invalid audit chain head {:?}", head_path))?;
+    if head.entries != seq {
+        bail!(
+            "chain has {} entries, but its head records {}",
+            seq,
+            head.entries
+        );
+    }
+    if head.digest != last {
+        bail!("digest of the chain doesn't match its head");
+    }
+
+    Ok((seq, last))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::engine::PaymentsEngine;
+    use std::fs;
+
+    fn process(file_path: &OsString, input: &str) -> String {
+        let mut engine = PaymentsEngine::default();
+        engine.set_hash_chain(HashChain::create(file_path).unwrap());
+        assert!(engine.process_csv(input.as_bytes()).is_ok());
+        assert!(engine.flush().is_ok());
+        engine.hash_chain().unwrap().digest().to_string()
+    }
+
+    #[test]
+    fn test_chain_verifies_and_detects_tampering() {
+        // Runs of tests for other features may run at the same time.
+        let path = std::env::temp_dir().join(format!(
+            "coding_test_audit_chain_{}.ndjson",
+            std::process::id()
+        ));
+        let path = path.into_os_string();
+        let input = "type,client,tx,amount\ndeposit,1,1,10\nwithdrawal,1,2,2.5\ndeposit,2,3,1\n";
+
+        let digest = process(&path, input);
+        assert_ne!(digest, GENESIS);
+        // Opened accounts and accepted transactions.
+        assert_eq!(verify(&path).unwrap(), (5, digest.clone()));
+        assert_eq!(process(&path, input), digest);
+
+        let other = "type,client,tx,amount\ndeposit,1,1,10\nwithdrawal,1,2,2.6\ndeposit,2,3,1\n";
+        assert_ne!(process(&path, other), digest);
+
+        process(&path, input);
+        let chain = fs::read_to_string(&path).unwrap();
+        fs::write(&path, chain.replacen("\"-2.5\"", "\"-2.6\"", 1)).unwrap();
+        assert!(verify(&path).is_err());
+
+        let mut lines: Vec<&str> = chain.lines().collect();
+        lines.remove(1);
+        fs::write(&path, lines.join("\n")).unwrap();
+        assert!(verify(&path).is_err());
+
+        // Entries cut from the end still link, but don't reach the head.
+        let lines: Vec<&str> = chain.lines().collect();
+        fs::write(&path, lines[..lines.len() - 1].join("\n")).unwrap();
+        assert!(verify(&path).is_err());
+        fs::write(&path, chain).unwrap();
+        assert_eq!(verify(&path).unwrap(), (5, digest));
+
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(head_path(&path)).unwrap();
+        assert!(verify(&path).is_err());
+    }
+}
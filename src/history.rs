@@ -0,0 +1,75 @@
+use crate::account::{Account, DepositedTransaction};
+
+use std::collections::BTreeMap;
+
+// History of deposited transactions kept outside of accounts, keyed by client and transaction id.
+// Deposits are moved into it after every transaction and a deposit is brought back into its
+// account only when a dispute, resolve or chargeback references it, so memory stays flat however
+// many transactions are processed.
+pub trait TransactionHistory: Send {
+    fn get(&self, client_id: u16, tx_id: u32) -> anyhow::Result<Option<DepositedTransaction>>;
+
+    // Stores a deposit, replacing an earlier state of it.
+    fn put(&mut self, client_id: u16, transaction: &DepositedTransaction) -> anyhow::Result<()>;
+
+    // Number of stored deposits.
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Makes stored deposits durable.
+    fn flush(&mut self) -> anyhow::Result<()>;
+}
+
+// History in memory, for tests and library users which only need deposits out of accounts.
+#[derive(Default)]
+pub struct MemoryHistory {
+    transactions: BTreeMap<(u16, u32), DepositedTransaction>,
+}
+
+impl TransactionHistory for MemoryHistory {
+    fn get(&self, client_id: u16, tx_id: u32) -> anyhow::Result<Option<DepositedTransaction>> {
+        Ok(self.transactions.get(&(client_id, tx_id)).cloned())
+    }
+
+    fn put(&mut self, client_id: u16, transaction: &DepositedTransaction) -> anyhow::Result<()> {
+        self.transactions
+            .insert((client_id, transaction.tx_id()), transaction.clone());
+        Ok(())
+    }
+
+    fn len(&self) -> usize {
+        self.transactions.len()
+    }
+
+    fn flush(&mut self) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+// Brings a referenced deposit into its account, unless it's already there.
+pub fn restore(
+    history: &dyn TransactionHistory,
+    account: &mut Account,
+    tx_id: u32,
+) -> anyhow::Result<()> {
+    if account.find_transaction(tx_id).is_none() {
+        if let Some(transaction) = history.get(account.id(), tx_id)? {
+            account.add_transaction(transaction);
+        }
+    }
+
+    Ok(())
+}
+
+// Moves all deposits of an account into history.
+pub fn offload(history: &mut dyn TransactionHistory, account: &mut Account) -> anyhow::Result<()> {
+    let client_id = account.id();
+    for transaction in account.transactions_mut().take_all() {
+        history.put(client_id, &transaction)?;
+    }
+
+    Ok(())
+}
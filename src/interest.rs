@@ -0,0 +1,139 @@
+use crate::account::Account;
+use crate::decimal_format;
+use crate::output;
+
+use crate::amount::Amount;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::ffi::OsString;
+
+const SECONDS_PER_YEAR: u64 = 365 * 24 * 60 * 60;
+
+struct AccruedInterest {
+    held: Amount,
+    since: u64,
+    accrued: Amount,
+}
+
+#[derive(Serialize)]
+struct InterestReportRow {
+    client: u16,
+    #[serde(serialize_with = "decimal_format::serialize")]
+    held: Amount,
+    #[serde(serialize_with = "decimal_format::serialize")]
+    accrued_interest: Amount,
+}
+
+// Simple (not compounded) interest on funds held during disputes. Interest is accrued between
+// times given by a clock, so with a replay clock it is accrued between row timestamps.
+pub struct HeldInterest {
+    annual_rate: Amount,
+    accounts: HashMap<u16, AccruedInterest>,
+    last_timestamp: Option<u64>,
+}
+
+impl HeldInterest {
+    pub fn new(annual_rate: Amount) -> HeldInterest {
+        HeldInterest {
+            annual_rate,
+            accounts: HashMap::new(),
+            last_timestamp: None,
+        }
+    }
+
+    fn interest(&self, held: Amount, seconds: u64) -> Amount {
+        held * self.annual_rate * Amount::from(seconds) / Amount::from(SECONDS_PER_YEAR)
+    }
+
+    // Must be called after a transaction was processed for an account, so held funds from that
+    // moment on accrue interest.
+    pub fn record(&mut self, account: &Account, timestamp: u64) {
+        self.last_timestamp = self.last_timestamp.max(Some(timestamp));
+
+        let interest = match self.accounts.get(&account.id()) {
+            None => Amount::from(0),
+            Some(accrued) => self.interest(accrued.held, timestamp.saturating_sub(accrued.since)),
+        };
+        let accrued = self
+            .accounts
+            .entry(account.id())
+            .or_insert_with(|| AccruedInterest {
+                held: Amount::from(0),
+                since: timestamp,
+                accrued: Amount::from(0),
+            });
+        accrued.accrued += interest;
+        accrued.since = timestamp;
+        accrued.held = account.held();
+    }
+
+    // Accrued but unpaid interest of an account up to the latest seen timestamp.
+    pub fn accrued(&self, client_id: u16) -> Amount {
+        match self.accounts.get(&client_id) {
+            None => Amount::from(0),
+            Some(accrued) => {
+                let until = self.last_timestamp.unwrap_or(accrued.since);
+                accrued.accrued + self.interest(accrued.held, until.saturating_sub(accrued.since))
+            }
+        }
+    }
+
+    // Adds interest accrued on other accounts, for example by another shard.
+    pub fn merge(&mut self, other: HeldInterest) {
+        self.accounts.extend(other.accounts);
+        self.last_timestamp = self.last_timestamp.max(other.last_timestamp);
+    }
+
+    pub fn save_report(&self, file_path: &OsString) -> anyhow::Result<()> {
+        let mut clients: Vec<&u16> = self.accounts.keys().collect();
+        clients.sort();
+
+        let rows = clients.into_iter().map(|client| InterestReportRow {
+            client: *client,
+            held: self.accounts[client].held,
+            accrued_interest: self.accrued(*client),
+        });
+        output::save_report(rows, file_path, "interest report")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::account::BalanceSnapshot;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_no_interest_without_held_funds() {
+        let mut interest = HeldInterest::new(Amount::from_str("0.05").unwrap());
+        let account = Account::new(1);
+
+        interest.record(&account, 0);
+        interest.record(&account, SECONDS_PER_YEAR);
+        assert_eq!(interest.accrued(1), Amount::from(0));
+    }
+
+    #[test]
+    fn test_interest_accrued_on_held_funds() {
+        let mut interest = HeldInterest::new(Amount::from_str("0.05").unwrap());
+        let mut account = Account::new(1);
+
+        let held = |held| BalanceSnapshot {
+            held: Amount::from_str(held).unwrap(),
+            total: Amount::from_str(held).unwrap(),
+            ..BalanceSnapshot::default()
+        };
+        account.restore_balances(held("1000")).unwrap();
+        interest.record(&account, 0);
+
+        // Half a year later a half of held funds is released.
+        account.restore_balances(held("500")).unwrap();
+        interest.record(&account, SECONDS_PER_YEAR / 2);
+        assert_eq!(interest.accrued(1), Amount::from_str("25").unwrap());
+
+        // Interest keeps accruing until the latest timestamp seen on any account.
+        let other = Account::new(2);
+        interest.record(&other, SECONDS_PER_YEAR);
+        assert_eq!(interest.accrued(1), Amount::from_str("37.5").unwrap());
+    }
+}
@@ -0,0 +1,186 @@
+use crate::account::{Account, BalanceSnapshot};
+use crate::amount::Amount;
+use crate::engine_config::EngineConfig;
+use crate::transaction::TransactionType;
+
+// Whether a transaction of the type may change balances of a locked account.
+fn allowed_on_locked(tx_type: &TransactionType, config: &EngineConfig) -> bool {
+    let policy = config.locked_policy;
+    let disputes = config.dispute_policy.locked_accounts;
+    match tx_type {
+        TransactionType::Deposit(_) => policy.deposits,
+        TransactionType::Withdrawal(_) => policy.withdrawals,
+        TransactionType::Dispute(_) => policy.disputes || disputes,
+        TransactionType::Resolve(_) => policy.resolves || disputes,
+        TransactionType::Chargeback(_) => policy.chargebacks || disputes,
+        // Representments and admin transactions are processed on locked accounts.
+        TransactionType::Representment(_)
+        | TransactionType::WriteOff(_)
+        | TransactionType::Adjust(_) => true,
+        TransactionType::Lock(_)
+        | TransactionType::Unlock(_)
+        | TransactionType::Annotate(_)
+        | TransactionType::Approve(_)
+        | TransactionType::Transfer(_) => false,
+    }
+}
+
+// Whether a transaction of the type may lock or unlock an account.
+fn changes_lock(tx_type: &TransactionType, locked: bool) -> bool {
+    if locked {
+        matches!(
+            tx_type,
+            TransactionType::Lock(_) | TransactionType::Chargeback(_)
+        )
+    } else {
+        matches!(
+            tx_type,
+            TransactionType::Unlock(_) | TransactionType::Representment(_)
+        )
+    }
+}
+
+// Checks an account after a transaction was applied to it, or rejected if `applied` is false,
+// against its balances and lock `before` the transaction, by policies of the engine. A violation
+// is a bug of the engine, as transactions keep total a sum of available and held funds, never hold
+// negative funds, leave an account unchanged when they are rejected and change a locked account
+// only if it accepts them.
+pub fn check(
+    tx_type: &TransactionType,
+    applied: bool,
+    before: &BalanceSnapshot,
+    account: &Account,
+    config: &EngineConfig,
+) -> Result<(), String> {
+    let after = account.balances();
+    if after.available.checked_add(after.held) != Some(after.total) {
+        return Err(format!(
+            "total {} is not a sum of available {} and held {} funds",
+            after.total, after.available, after.held
+        ));
+    }
+    if after.held < Amount::from(0) {
+        return Err(format!("held funds {} are negative", after.held));
+    }
+    if !applied && after != *before {
+        return Err(String::from("rejected transaction changed the account"));
+    }
+
+    let balances_changed = after.available != before.available
+        || after.held != before.held
+        || after.total != before.total;
+    if before.locked && balances_changed && !allowed_on_locked(tx_type, config) {
+        return Err(format!(
+            "{} changed balances of a locked account",
+            tx_type.name()
+        ));
+    }
+    if after.locked != before.locked && !changes_lock(tx_type, after.locked) {
+        return Err(format!(
+            "{} {} the account",
+            tx_type.name(),
+            if after.locked { "locked" } else { "unlocked" }
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::transaction::{Deposit, Lock, Process};
+
+    fn deposit(tx_id: u32, amount: i64) -> TransactionType {
+        TransactionType::Deposit(Deposit {
+            client_id: 1,
+            tx_id,
+            amount: Amount::from(amount),
+        })
+    }
+
+    #[test]
+    fn test_transactions_keep_invariants() {
+        let mut account = Account::new(1);
+        let before = account.balances();
+        let tx_type = deposit(1, 10);
+        assert!(tx_type.process(&mut account).is_ok());
+        assert_eq!(
+            check(&tx_type, true, &before, &account, &EngineConfig::default()),
+            Ok(())
+        );
+
+        let lock = TransactionType::Lock(Lock {
+            client_id: 1,
+            tx_id: 2,
+        });
+        let before = account.balances();
+        assert!(lock.process(&mut account).is_ok());
+        assert_eq!(
+            check(&lock, true, &before, &account, &EngineConfig::default()),
+            Ok(())
+        );
+
+        let before = account.balances();
+        let tx_type = deposit(3, 5);
+        assert!(tx_type.process(&mut account).is_err());
+        assert_eq!(
+            check(&tx_type, false, &before, &account, &EngineConfig::default()),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_violations() {
+        let mut account = Account::new(1);
+        let before = account.balances();
+        assert!(deposit(1, 10).process(&mut account).is_ok());
+        assert_eq!(
+            check(
+                &deposit(1, 10),
+                false,
+                &before,
+                &account,
+                &EngineConfig::default()
+            ),
+            Err(String::from("rejected transaction changed the account"))
+        );
+
+        let locked = BalanceSnapshot {
+            locked: true,
+            ..before
+        };
+        assert_eq!(
+            check(
+                &deposit(1, 10),
+                true,
+                &locked,
+                &account,
+                &EngineConfig::default()
+            ),
+            Err(String::from("deposit changed balances of a locked account"))
+        );
+        account
+            .restore_balances(BalanceSnapshot {
+                locked: true,
+                ..account.balances()
+            })
+            .unwrap();
+        let before = account.balances();
+        account
+            .restore_balances(BalanceSnapshot {
+                locked: false,
+                ..before
+            })
+            .unwrap();
+        assert_eq!(
+            check(
+                &deposit(2, 1),
+                true,
+                &before,
+                &account,
+                &EngineConfig::default()
+            ),
+            Err(String::from("deposit unlocked the account"))
+        );
+    }
+}
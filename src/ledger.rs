@@ -0,0 +1,510 @@
+use crate::account::{Account, Direction};
+use crate::decimal_format;
+use crate::output;
+use crate::overflow;
+use crate::transaction::TransactionType;
+
+use crate::amount::Amount;
+use anyhow::bail;
+use log::info;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+
+// Internal accounts of double-entry bookkeeping. Every applied client transaction is posted as
+// a debit to one internal account and a credit to another so the books always balance.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum LedgerAccount {
+    // Funds received from (or paid to) payment networks.
+    Cash,
+    // Funds owed to clients which they can freely use.
+    ClientAvailable,
+    // Funds owed to clients which are held because of disputes.
+    ClientHeld,
+    // Funds which were charged back and are owed to payment networks.
+    ChargebacksPayable,
+    // Funds owed by clients which were written off as uncollectable.
+    WriteOffLosses,
+    // Funds credited to (or debited from) clients by admin corrections.
+    AdminAdjustments,
+    // Fees charged to clients on their transactions.
+    FeeRevenue,
+}
+
+#[derive(Clone, Copy, Default)]
+struct LedgerTotals {
+    debit: Amount,
+    credit: Amount,
+}
+
+#[derive(Serialize)]
+pub struct TrialBalanceRow {
+    account: &'static str,
+    #[serde(serialize_with = "decimal_format::serialize")]
+    pub debit: Amount,
+    #[serde(serialize_with = "decimal_format::serialize")]
+    pub credit: Amount,
+}
+
+pub struct Ledger {
+    totals: BTreeMap<LedgerAccount, LedgerTotals>,
+    // Set when a total didn't fit into an amount and was saturated, then books may not balance.
+    saturated: bool,
+}
+
+impl LedgerAccount {
+    pub fn name(&self) -> &'static str {
+        match self {
+            LedgerAccount::Cash => "cash",
+            LedgerAccount::ClientAvailable => "client_available",
+            LedgerAccount::ClientHeld => "client_held",
+            LedgerAccount::ChargebacksPayable => "chargebacks_payable",
+            LedgerAccount::WriteOffLosses => "write_off_losses",
+            LedgerAccount::AdminAdjustments => "admin_adjustments",
+            LedgerAccount::FeeRevenue => "fee_revenue",
+        }
+    }
+}
+
+impl Ledger {
+    pub fn new() -> Ledger {
+        Ledger {
+            totals: BTreeMap::new(),
+            saturated: false,
+        }
+    }
+
+    // Adds to a total of the books. A transaction is already applied to its account then, so a
+    // total which doesn't fit is saturated and the books are flagged instead of rejecting it.
+    fn add(total: &mut Amount, amount: Amount, saturated: &mut bool) {
+        match total.checked_add(amount) {
+            Some(sum) => *total = sum,
+            None => {
+                *total = overflow::saturating_add(*total, amount);
+                *saturated = true;
+            }
+        }
+    }
+
+    pub fn post(&mut self, debit: LedgerAccount, credit: LedgerAccount, amount: Amount) {
+        let totals = self.totals.entry(debit).or_default();
+        Ledger::add(&mut totals.debit, amount, &mut self.saturated);
+        let totals = self.totals.entry(credit).or_default();
+        Ledger::add(&mut totals.credit, amount, &mut self.saturated);
+    }
+
+    pub fn merge(&mut self, other: Ledger) {
+        self.saturated |= other.saturated;
+        for (account, totals) in other.totals {
+            let merged = self.totals.entry(account).or_default();
+            Ledger::add(&mut merged.debit, totals.debit, &mut self.saturated);
+            Ledger::add(&mut merged.credit, totals.credit, &mut self.saturated);
+        }
+    }
+
+    // Posts an already applied transaction. Disputes, resolves, chargebacks and write-offs don't
+    // carry an amount so it's taken from the referenced deposit, withdrawal or adjustment stored
+    // in account. Funds of a disputed withdrawal are claimed back from payment networks, so they
+    // are held against chargebacks payable and paid out to the client on chargeback. A dispute
+    // or a chargeback of a part of a transaction posts only that part, the rest of a partially
+    // charged back dispute is released.
+    pub fn record(&mut self, tx_type: &TransactionType, account: &Account) {
+        let referenced = |tx_id| {
+            account.find_transaction(tx_id).map(|transaction| {
+                (
+                    transaction.disputed_amount(),
+                    transaction.charged_back_amount(),
+                    transaction.direction(),
+                )
+            })
+        };
+
+        match tx_type {
+            TransactionType::Deposit(deposit) => self.post(
+                LedgerAccount::Cash,
+                LedgerAccount::ClientAvailable,
+                deposit.amount,
+            ),
+            TransactionType::Withdrawal(withdrawal) => self.post(
+                LedgerAccount::ClientAvailable,
+                LedgerAccount::Cash,
+                withdrawal.amount,
+            ),
+            TransactionType::Dispute(dispute) => match referenced(dispute.tx_id) {
+                Some((amount, _, Direction::Deposit)) => self.post(
+                    LedgerAccount::ClientAvailable,
+                    LedgerAccount::ClientHeld,
+                    amount,
+                ),
+                Some((amount, _, Direction::Withdrawal)) => self.post(
+                    LedgerAccount::ChargebacksPayable,
+                    LedgerAccount::ClientHeld,
+                    amount,
+                ),
+                None => {}
+            },
+            TransactionType::Resolve(resolve) => match referenced(resolve.tx_id) {
+                Some((amount, _, Direction::Deposit)) => self.post(
+                    LedgerAccount::ClientHeld,
+                    LedgerAccount::ClientAvailable,
+                    amount,
+                ),
+                Some((amount, _, Direction::Withdrawal)) => self.post(
+                    LedgerAccount::ClientHeld,
+                    LedgerAccount::ChargebacksPayable,
+                    amount,
+                ),
+                None => {}
+            },
+            TransactionType::Chargeback(chargeback) => match referenced(chargeback.tx_id) {
+                Some((disputed, amount, Direction::Deposit)) => {
+                    self.post(
+                        LedgerAccount::ClientHeld,
+                        LedgerAccount::ChargebacksPayable,
+                        amount,
+                    );
+                    if amount < disputed {
+                        self.post(
+                            LedgerAccount::ClientHeld,
+                            LedgerAccount::ClientAvailable,
+                            disputed - amount,
+                        );
+                    }
+                }
+                Some((disputed, amount, Direction::Withdrawal)) => {
+                    self.post(
+                        LedgerAccount::ClientHeld,
+                        LedgerAccount::ClientAvailable,
+                        amount,
+                    );
+                    if amount < disputed {
+                        self.post(
+                            LedgerAccount::ClientHeld,
+                            LedgerAccount::ChargebacksPayable,
+                            disputed - amount,
+                        );
+                    }
+                }
+                None => {}
+            },
+            // A representment takes back funds charged back from a deposit from chargebacks
+            // payable, or returns to them funds of a charged back withdrawal.
+            TransactionType::Representment(representment) => {
+                match referenced(representment.tx_id) {
+                    Some((_, amount, Direction::Deposit)) => self.post(
+                        LedgerAccount::ChargebacksPayable,
+                        LedgerAccount::ClientAvailable,
+                        amount,
+                    ),
+                    Some((_, amount, Direction::Withdrawal)) => self.post(
+                        LedgerAccount::ClientAvailable,
+                        LedgerAccount::ChargebacksPayable,
+                        amount,
+                    ),
+                    None => {}
+                }
+            }
+            TransactionType::WriteOff(write_off) => {
+                if let Some(adjustment) = account.find_adjustment(write_off.tx_id) {
+                    self.post(
+                        LedgerAccount::WriteOffLosses,
+                        LedgerAccount::ClientAvailable,
+                        adjustment.amount(),
+                    );
+                }
+            }
+            // Positive adjustment credits client funds, a negative one debits them.
+            TransactionType::Adjust(adjust) => {
+                if adjust.amount.is_sign_negative() {
+                    self.post(
+                        LedgerAccount::ClientAvailable,
+                        LedgerAccount::AdminAdjustments,
+                        -adjust.amount,
+                    );
+                } else {
+                    self.post(
+                        LedgerAccount::AdminAdjustments,
+                        LedgerAccount::ClientAvailable,
+                        adjust.amount,
+                    );
+                }
+            }
+            TransactionType::Lock(_)
+            | TransactionType::Unlock(_)
+            | TransactionType::Annotate(_)
+            | TransactionType::Approve(_)
+            | TransactionType::Transfer(_) => {}
+        }
+    }
+
+    // Balance of an internal account as credits minus debits, which is a natural sign for
+    // liabilities (client funds).
+    pub fn credit_balance(&self, account: LedgerAccount) -> Amount {
+        match self.totals.get(&account) {
+            None => Amount::from(0),
+            Some(totals) => totals.credit - totals.debit,
+        }
+    }
+
+    pub fn trial_balance(&self) -> Vec<TrialBalanceRow> {
+        self.totals
+            .iter()
+            .map(|(account, totals)| TrialBalanceRow {
+                account: account.name(),
+                debit: totals.debit,
+                credit: totals.credit,
+            })
+            .collect()
+    }
+
+    pub fn is_saturated(&self) -> bool {
+        self.saturated
+    }
+
+    // Debits equal credits only while no total was saturated, as each side saturates on its own.
+    // Sums which don't fit into an amount can't be compared, so the books aren't balanced then.
+    pub fn is_balanced(&self) -> bool {
+        let sum = |side: fn(&LedgerTotals) -> Amount| {
+            self.totals
+                .values()
+                .try_fold(Amount::from(0), |sum, totals| sum.checked_add(side(totals)))
+        };
+        match (sum(|totals| totals.debit), sum(|totals| totals.credit)) {
+            (Some(debits), Some(credits)) => debits == credits,
+            _ => false,
+        }
+    }
+
+    // Checks that client liabilities in the books match the sum of client account balances.
+    pub fn matches_accounts(&self, accounts: &HashMap<u16, Account>) -> bool {
+        // Balances of accounts may not fit into a sum, which then can't match saturated books.
+        let sum = |balance: fn(&Account) -> Amount| {
+            accounts.values().try_fold(Amount::from(0), |sum, account| {
+                sum.checked_add(balance(account))
+            })
+        };
+        let (Some(available), Some(held)) = (sum(Account::available), sum(Account::held)) else {
+            return false;
+        };
+
+        self.credit_balance(LedgerAccount::ClientAvailable) == available
+            && self.credit_balance(LedgerAccount::ClientHeld) == held
+    }
+
+    pub fn save_trial_balance(&self, accounts: &HashMap<u16, Account>) -> anyhow::Result<()> {
+        let rows = self.trial_balance();
+
+        let total = TrialBalanceRow {
+            account: "total",
+            debit: rows
+                .iter()
+                .map(|row| row.debit)
+                .fold(Amount::from(0), overflow::saturating_add),
+            credit: rows
+                .iter()
+                .map(|row| row.credit)
+                .fold(Amount::from(0), overflow::saturating_add),
+        };
+        output::save_rows(rows.iter().chain(Some(&total)), io::stdout())?;
+
+        if self.saturated {
+            bail!("trial balance totals overflowed and were saturated");
+        }
+        if !self.is_balanced() {
+            bail!("trial balance debits do not equal credits");
+        }
+        if !self.matches_accounts(accounts) {
+            bail!("trial balance client funds do not match client accounts");
+        }
+        info!("trial balance debits equal credits and match client accounts");
+
+        Ok(())
+    }
+}
+
+impl Default for Ledger {
+    fn default() -> Ledger {
+        Ledger::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::account::BalanceSnapshot;
+    use crate::transaction::{Adjust, Chargeback, Deposit, Dispute, Process, Withdrawal};
+    use std::str::FromStr;
+
+    #[test]
+    fn test_new_ledger_is_balanced() {
+        let ledger = Ledger::new();
+        assert!(ledger.is_balanced());
+        assert!(ledger.trial_balance().is_empty());
+    }
+
+    #[test]
+    fn test_post_is_balanced() {
+        let mut ledger = Ledger::new();
+        ledger.post(
+            LedgerAccount::Cash,
+            LedgerAccount::ClientAvailable,
+            Amount::from_str("10.5").unwrap(),
+        );
+
+        assert!(ledger.is_balanced());
+        assert_eq!(
+            ledger.credit_balance(LedgerAccount::ClientAvailable),
+            Amount::from_str("10.5").unwrap()
+        );
+        assert_eq!(
+            ledger.credit_balance(LedgerAccount::Cash),
+            Amount::from_str("-10.5").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_record_transactions_matches_accounts() {
+        let mut accounts = HashMap::new();
+        let mut ledger = Ledger::new();
+        let account = accounts.entry(1).or_insert_with(|| Account::new(1));
+
+        let transactions = vec![
+            TransactionType::Deposit(Deposit {
+                client_id: 1,
+                tx_id: 1,
+                amount: Amount::from_str("100").unwrap(),
+            }),
+            TransactionType::Deposit(Deposit {
+                client_id: 1,
+                tx_id: 2,
+                amount: Amount::from_str("50").unwrap(),
+            }),
+            TransactionType::Withdrawal(Withdrawal {
+                client_id: 1,
+                tx_id: 3,
+                amount: Amount::from_str("25").unwrap(),
+            }),
+            TransactionType::Dispute(Dispute {
+                client_id: 1,
+                tx_id: 2,
+                amount: None,
+            }),
+            TransactionType::Chargeback(Chargeback {
+                client_id: 1,
+                tx_id: 2,
+                amount: None,
+            }),
+        ];
+        for tx_type in &transactions {
+            assert!(tx_type.process(account).is_ok());
+            ledger.record(tx_type, account);
+        }
+
+        assert!(ledger.is_balanced());
+        assert!(ledger.matches_accounts(&accounts));
+        assert_eq!(
+            ledger.credit_balance(LedgerAccount::ChargebacksPayable),
+            Amount::from_str("50").unwrap()
+        );
+        assert_eq!(ledger.trial_balance().len(), 4);
+    }
+
+    #[test]
+    fn test_record_adjustments_matches_accounts() {
+        let mut accounts = HashMap::new();
+        let mut ledger = Ledger::new();
+        let account = accounts.entry(1).or_insert_with(|| Account::new(1));
+
+        let transactions = vec![
+            TransactionType::Adjust(Adjust {
+                client_id: 1,
+                tx_id: 1,
+                amount: Amount::from_str("10").unwrap(),
+            }),
+            TransactionType::Adjust(Adjust {
+                client_id: 1,
+                tx_id: 2,
+                amount: Amount::from_str("-4").unwrap(),
+            }),
+        ];
+        for tx_type in &transactions {
+            assert!(tx_type.process(account).is_ok());
+            ledger.record(tx_type, account);
+        }
+
+        assert!(ledger.is_balanced());
+        assert!(ledger.matches_accounts(&accounts));
+        assert_eq!(
+            ledger.credit_balance(LedgerAccount::AdminAdjustments),
+            Amount::from_str("-6").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_ledger_does_not_match_accounts() {
+        let mut accounts = HashMap::new();
+        let ledger = Ledger::new();
+        let account = accounts.entry(1).or_insert_with(|| Account::new(1));
+        let available = Amount::from_str("1").unwrap();
+        account
+            .restore_balances(BalanceSnapshot {
+                available,
+                total: available,
+                ..BalanceSnapshot::default()
+            })
+            .unwrap();
+
+        assert!(ledger.is_balanced());
+        assert!(!ledger.matches_accounts(&accounts));
+    }
+
+    #[test]
+    fn test_largest_balances_do_not_match() {
+        let mut accounts = HashMap::new();
+        let mut ledger = Ledger::new();
+        for client_id in 1..=2 {
+            let account = accounts
+                .entry(client_id)
+                .or_insert_with(|| Account::new(client_id));
+            account
+                .restore_balances(BalanceSnapshot {
+                    available: Amount::MAX,
+                    total: Amount::MAX,
+                    ..BalanceSnapshot::default()
+                })
+                .unwrap();
+            ledger.post(
+                LedgerAccount::Cash,
+                LedgerAccount::ClientAvailable,
+                Amount::MAX,
+            );
+        }
+
+        assert!(ledger.is_saturated());
+        assert!(!ledger.matches_accounts(&accounts));
+    }
+
+    #[test]
+    fn test_saturated_ledger_is_not_balanced() {
+        let mut ledger = Ledger::new();
+        ledger.post(
+            LedgerAccount::Cash,
+            LedgerAccount::ClientAvailable,
+            Amount::MAX,
+        );
+        assert!(!ledger.is_saturated());
+        assert!(ledger.is_balanced());
+
+        ledger.post(
+            LedgerAccount::Cash,
+            LedgerAccount::FeeRevenue,
+            Amount::from(1),
+        );
+        assert!(ledger.is_saturated());
+        assert!(!ledger.is_balanced());
+
+        let mut merged = Ledger::new();
+        merged.merge(ledger);
+        assert!(merged.is_saturated());
+    }
+}
@@ -0,0 +1,67 @@
+pub mod account;
+pub mod alert;
+pub mod amount;
+pub mod amount_cache;
+pub mod approval;
+pub mod archive;
+#[cfg(feature = "async")]
+pub mod async_engine;
+pub mod audit;
+pub mod authorization;
+pub mod bench;
+pub mod cdc;
+pub mod charged_back;
+pub mod checksum;
+pub mod clock;
+pub mod conformance;
+pub mod db_input;
+pub mod decimal_format;
+pub mod dispute_policy;
+pub mod engine;
+pub mod engine_config;
+pub mod entry_limit;
+pub mod error;
+pub mod event_log;
+pub mod fees;
+#[cfg(feature = "cdylib")]
+pub mod ffi;
+pub mod fraud;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod hash_chain;
+pub mod history;
+pub mod interest;
+pub mod invariants;
+pub mod ledger;
+pub mod limits;
+pub mod locked_policy;
+pub mod migration;
+pub mod output;
+pub mod overflow;
+pub mod parallel_csv;
+#[cfg(feature = "parquet")]
+pub mod parquet_input;
+pub mod period;
+pub mod precision;
+pub mod progress;
+pub mod query;
+pub mod rejections;
+pub mod reorder;
+pub mod run_report;
+pub mod schema;
+pub mod shard;
+pub mod shutdown;
+pub mod sink;
+#[cfg(feature = "sled")]
+pub mod sled_history;
+pub mod snapshot;
+pub mod source;
+pub mod spill;
+#[cfg(feature = "sqlite")]
+pub mod sqlite_store;
+pub mod state;
+pub mod statemachine;
+pub mod store;
+pub mod throttle;
+pub mod transaction;
+pub mod watch;
@@ -0,0 +1,67 @@
+use crate::amount::Amount;
+
+use std::str::FromStr;
+
+// Days of the daily withdrawal limit are days of Unix time by the configured clock.
+pub const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+// Limits of money movement enforced by accounts. A withdrawal or a deposit which would break one
+// is rejected and the account stays as it was. There are no limits by default. Every engine has
+// its own limits in its `EngineConfig`.
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct Limits {
+    // Largest amount of a single withdrawal.
+    pub max_withdrawal: Option<Amount>,
+    // Largest sum of withdrawals of an account within a day.
+    pub daily_withdrawals: Option<Amount>,
+    // Largest total balance a deposit may bring an account to.
+    pub max_balance: Option<Amount>,
+}
+
+// Limits are given as a list of limits separated by commas, for example
+// `max-withdrawal:1000,daily-withdrawals:5000,max-balance:100000`. Limits which aren't given
+// aren't enforced.
+impl FromStr for Limits {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Limits, String> {
+        let mut limits = Limits::default();
+        for limit in s.split(',').map(str::trim) {
+            let (name, amount) = limit
+                .split_once(':')
+                .ok_or_else(|| format!("limit has no amount: {}", limit))?;
+            let amount = match Amount::from_str(amount) {
+                Ok(amount) if !amount.is_sign_negative() => amount,
+                _ => return Err(format!("invalid amount of limit: {}", limit)),
+            };
+            match name {
+                "max-withdrawal" => limits.max_withdrawal = Some(amount),
+                "daily-withdrawals" => limits.daily_withdrawals = Some(amount),
+                "max-balance" => limits.max_balance = Some(amount),
+                _ => return Err(format!("unknown limit: {}", name)),
+            }
+        }
+        Ok(limits)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_limits() {
+        assert_eq!(
+            "max-withdrawal:1000, max-balance:2500.5".parse::<Limits>(),
+            Ok(Limits {
+                max_withdrawal: Some(Amount::from(1000)),
+                max_balance: Some(Amount::from_str("2500.5").unwrap()),
+                ..Limits::default()
+            })
+        );
+        assert!("".parse::<Limits>().is_err());
+        assert!("max-withdrawal".parse::<Limits>().is_err());
+        assert!("max-withdrawal:-1".parse::<Limits>().is_err());
+        assert!("min-balance:10".parse::<Limits>().is_err());
+    }
+}
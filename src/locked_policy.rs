@@ -0,0 +1,63 @@
+use std::str::FromStr;
+
+// Transactions a locked account still accepts. By default a locked account is frozen: deposits,
+// withdrawals, disputes, resolves and chargebacks are all rejected. Freezing incoming funds isn't
+// always desired, so for example deposits and resolves may be allowed while withdrawals stay
+// blocked.
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct LockedPolicy {
+    pub deposits: bool,
+    pub withdrawals: bool,
+    pub disputes: bool,
+    pub resolves: bool,
+    pub chargebacks: bool,
+}
+
+// Policy is given as a list of transaction types allowed on locked accounts separated by commas,
+// for example `deposit,resolve`, or `none`.
+impl FromStr for LockedPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<LockedPolicy, String> {
+        let mut policy = LockedPolicy::default();
+        if s.trim() == "none" {
+            return Ok(policy);
+        }
+        for tx_type in s.split(',').map(str::trim) {
+            match tx_type {
+                "deposit" => policy.deposits = true,
+                "withdrawal" => policy.withdrawals = true,
+                "dispute" => policy.disputes = true,
+                "resolve" => policy.resolves = true,
+                "chargeback" => policy.chargebacks = true,
+                _ => {
+                    return Err(format!(
+                        "transaction type can not be allowed on locked accounts: {}",
+                        tx_type
+                    ))
+                }
+            }
+        }
+        Ok(policy)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_locked_policy() {
+        assert_eq!("none".parse::<LockedPolicy>(), Ok(LockedPolicy::default()));
+        assert_eq!(
+            "deposit, resolve".parse::<LockedPolicy>(),
+            Ok(LockedPolicy {
+                deposits: true,
+                resolves: true,
+                ..LockedPolicy::default()
+            })
+        );
+        assert!("".parse::<LockedPolicy>().is_err());
+        assert!("unlock".parse::<LockedPolicy>().is_err());
+    }
+}
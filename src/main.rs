@@ -1,185 +1,869 @@
-mod account;
-mod transaction;
-
-use crate::account::Account;
-use crate::transaction::Process;
-use crate::transaction::Transaction;
-
-use csv::{ReaderBuilder, Trim};
-use log::error;
-use std::collections::HashMap;
-use std::error::Error;
-use std::ffi::OsString;
-use std::fs::File;
-use std::{env, io};
-
-fn get_file_path() -> Result<OsString, Box<dyn Error>> {
-    match env::args_os().nth(1) {
-        None => Err(From::from("expected 1 argument, but none given")),
-        Some(file_path) => Ok(file_path),
+mod cli;
+mod config;
+
+use crate::cli::{Command, Options};
+use coding_test::alert::{self, Alerts, DeadLetters, StderrNotifier};
+use coding_test::approval::Approvals;
+use coding_test::archive::Archive;
+use coding_test::audit::AuditTrail;
+use coding_test::authorization::Authorization;
+use coding_test::bench;
+use coding_test::cdc::{self, ChangeStream};
+use coding_test::checksum;
+use coding_test::conformance;
+use coding_test::db_input;
+use coding_test::engine::{Books, Controls, PaymentsEngine};
+use coding_test::engine_config::EngineConfig;
+use coding_test::entry_limit::EvictionStrategy;
+use coding_test::error::Failure;
+use coding_test::event_log::{self, EventLog};
+use coding_test::fees::Fees;
+use coding_test::fraud::FraudFlags;
+#[cfg(feature = "grpc")]
+use coding_test::grpc;
+use coding_test::hash_chain::{self, HashChain};
+use coding_test::interest::HeldInterest;
+use coding_test::migration::{MigrationOptions, MigrationReport};
+use coding_test::output;
+use coding_test::progress::Progress;
+use coding_test::rejections::RejectionReport;
+use coding_test::run_report::RunReport;
+use coding_test::shard::{self, ShardedEngine};
+use coding_test::shutdown::{Shutdown, StoppingReader};
+#[cfg(feature = "sled")]
+use coding_test::sled_history::SledHistory;
+use coding_test::spill::SpillFile;
+#[cfg(feature = "sqlite")]
+use coding_test::sqlite_store::{self, SqliteStore};
+use coding_test::state;
+use coding_test::statemachine;
+use coding_test::store::AccountStore;
+use coding_test::throttle::{self, Throttle};
+use coding_test::watch::DirectoryWatch;
+
+use anyhow::{anyhow, bail, Context};
+use log::info;
+use memmap2::Mmap;
+use std::ffi::{OsStr, OsString};
+use std::fs::{self, File};
+use std::io::{BufWriter, Read};
+use std::path::{Path, PathBuf};
+#[cfg(feature = "grpc")]
+use std::sync::{Arc, Mutex};
+use std::{env, io, panic, process};
+
+fn engine_config(options: &Options) -> EngineConfig {
+    EngineConfig {
+        limits: options.limits,
+        dispute_policy: options.dispute_policy,
+        dispute_withdrawals: options.dispute_withdrawals,
+        locked_policy: options.locked_policy,
+        charged_back_policy: options.charged_back_policy,
+        overflow_policy: options.overflow_policy,
+        decimal_format: options.decimal_format,
+        precision_policy: options.precision_policy,
+        schema_policy: options.schema_policy,
+        amount_cache: options.amount_cache,
+    }
+}
+
+fn create_engine(options: &Options) -> anyhow::Result<PaymentsEngine> {
+    let mut books = Books::new(&options.period);
+    if let Some(audit_log) = &options.audit_log {
+        books.audit = AuditTrail::create(audit_log)?;
+    }
+    books.interest = options.held_interest_rate.map(HeldInterest::new);
+    books.fees = options.fee_schedule.as_ref().map(Fees::load).transpose()?;
+    if !options.fraud_rules.is_empty() {
+        books.fraud = Some(FraudFlags::new(options.fraud_rules.clone()));
+    }
+    let mut controls = Controls::new();
+    if let Some(operators) = &options.operators {
+        controls.authorization = Authorization::load(operators, options.senior_threshold)?;
+    }
+    controls.approvals = Approvals::new(options.approval_threshold, options.approval_expiry);
+    let mut engine = PaymentsEngine::new(books, controls, options.clock.create());
+    engine.set_config(engine_config(options));
+    if let Some(state_in) = &options.state_in {
+        state::restore(&mut engine, state_in)?;
+    }
+    // Verification checks duplicated ids of transactions, so every deposit is processed alone.
+    if options.command == Command::Verify {
+        engine.set_reject_duplicates(true);
+    } else {
+        engine.set_coalesce_deposits(options.coalesce_deposits);
+    }
+    engine.set_any_transaction_opens_account(options.any_transaction_opens_account);
+    engine.set_check_invariants(options.check_invariants);
+    if let Some(entry_limit) = options.entry_limit {
+        engine.set_entry_limit(entry_limit);
+    }
+    if let Some(store) = open_store(options)? {
+        engine.set_store(store);
+    }
+    if let Some(event_log) = &options.event_log {
+        engine.set_event_log(EventLog::open(event_log)?);
+    }
+    if let Some(audit_chain) = &options.audit_chain {
+        engine.set_hash_chain(HashChain::create(audit_chain)?);
+    }
+    if let Some(cdc) = &options.cdc {
+        engine.set_changes(ChangeStream::new(cdc::open_sink(cdc)?));
     }
+    if let Some(rejections) = &options.rejections {
+        engine.set_rejections(RejectionReport::create(rejections)?);
+    } else if options.command == Command::Verify {
+        engine.set_rejections(RejectionReport::stdout());
+    }
+    if !options.alert_rules.is_empty() {
+        let mut notifiers = Vec::new();
+        for notifier in &options.notifiers {
+            notifiers.push(alert::parse_notifier(notifier)?);
+        }
+        if notifiers.is_empty() {
+            notifiers.push(Box::new(StderrNotifier));
+        }
+        let mut alerts = Alerts::new(options.alert_rules.clone(), notifiers);
+        alerts.set_retries(options.notify_retries);
+        if let Some(dead_letter) = &options.dead_letter {
+            alerts.set_dead_letters(DeadLetters::open(dead_letter)?);
+        }
+        engine.set_alerts(alerts);
+    }
+    #[cfg(feature = "sled")]
+    if let Some(history_dir) = &options.history_dir {
+        engine.set_history(Box::new(SledHistory::open(history_dir)?));
+    }
+    // Parsing of arguments makes sure spilled transactions go into a history directory only if
+    // it's given.
+    if options.history_dir.is_none()
+        && options.entry_limit.map(|entry_limit| entry_limit.strategy)
+            == Some(EvictionStrategy::Spill)
+    {
+        engine.set_history(Box::new(SpillFile::create()?));
+    }
+
+    Ok(engine)
 }
 
-fn save_accounts_data(accounts: &HashMap<u16, Account>) -> Result<(), Box<dyn Error>> {
-    let mut writer = csv::Writer::from_writer(io::stdout());
-    for account in accounts {
-        writer.serialize(account.1)?;
+fn open_store(options: &Options) -> anyhow::Result<Option<Box<dyn AccountStore>>> {
+    if let Some(archive_dir) = &options.archive_dir {
+        let archive = Archive::open(archive_dir).context("can not open archive")?;
+        return Ok(Some(Box::new(archive)));
+    }
+    #[cfg(feature = "sqlite")]
+    if let Some(sqlite_store) = &options.sqlite_store {
+        let store = SqliteStore::open(sqlite_store).context("can not open SQLite store")?;
+        return Ok(Some(Box::new(store)));
     }
-    writer.flush()?;
 
+    Ok(None)
+}
+
+// Stores are migrated one after another, a backup of each store goes into its own entry of the
+// backup directory.
+fn migrate_stores(options: &Options) -> anyhow::Result<Vec<MigrationReport>> {
+    let backup_dir = options.backup_dir.as_ref().map(PathBuf::from);
+    if let (Some(backup_dir), false) = (&backup_dir, options.dry_run) {
+        fs::create_dir_all(backup_dir)
+            .with_context(|| format!("can not create backup directory {:?}", backup_dir))?;
+    }
+    let migration_options = |name: &str| MigrationOptions {
+        dry_run: options.dry_run,
+        backup: backup_dir.as_ref().map(|backup_dir| backup_dir.join(name)),
+    };
+
+    let mut reports = Vec::new();
+    if let Some(archive_dir) = &options.archive_dir {
+        reports.push(
+            Archive::migrate(archive_dir, &migration_options("archive"))
+                .context("can not migrate archive")?,
+        );
+    }
+    #[cfg(feature = "sqlite")]
+    if let Some(sqlite_store) = &options.sqlite_store {
+        reports.push(
+            SqliteStore::migrate(sqlite_store, &migration_options("store.sqlite"))
+                .context("can not migrate SQLite store")?,
+        );
+    }
+    #[cfg(feature = "sled")]
+    if let Some(history_dir) = &options.history_dir {
+        reports.push(
+            SledHistory::migrate(history_dir, &migration_options("history"))
+                .context("can not migrate transaction history")?,
+        );
+    }
+
+    Ok(reports)
+}
+
+// Engine state is written for a restart, also when processing was stopped by a shutdown.
+fn save_checkpoint(engine: &PaymentsEngine, options: &Options) -> anyhow::Result<()> {
+    if let Some(checkpoint) = &options.checkpoint {
+        let file = File::create(checkpoint)
+            .with_context(|| format!("can not create checkpoint {:?}", checkpoint))?;
+        state::save_state(&engine.export_state(), BufWriter::new(file))
+            .context("can not save checkpoint")?;
+    }
     Ok(())
 }
 
-fn process_payments(
-    file_path: OsString,
-    accounts: &mut HashMap<u16, Account>,
-) -> Result<(), Box<dyn Error>> {
-    let file = File::open(file_path)?;
-
-    let mut reader = ReaderBuilder::new()
-        .trim(Trim::All)
-        .delimiter(b',')
-        .from_reader(file);
-
-    // Here we have an opportunity to make a code to run in parallel.
-    // We would need to be careful that for each client only one thread would be in use otherwise
-    // it could happen that transactions would not be processed in a correct order.
-    // One solution would be that we will have a pool of threads and check if any thread is already
-    // processing transaction(s) for a client and if so, send to this thread transaction data
-    // (for example, we could use std::sync::mpsc to do that). If there is no thread currently
-    // processing client transaction(s) and if any thread is free, use a new thread from a pool
-    // to process transaction data for a client.
-    for result in reader.deserialize() {
-        let transaction: Transaction = match result {
-            Ok(transaction) => transaction,
-            Err(_) => {
-                error!("can not deserialize transaction. skipping it.");
-                continue;
+fn process(options: &Options, shutdown: &Shutdown) -> anyhow::Result<PaymentsEngine> {
+    let mut engine = create_engine(options)?;
+    engine.set_shutdown(shutdown.clone());
+    if let Some(max_rows_per_sec) = options.max_rows_per_sec {
+        engine.set_throttle(Throttle::new(max_rows_per_sec));
+    }
+    if let Some(reorder) = options.reorder {
+        engine.set_reorder(reorder);
+    }
+    if let Some(database) = &options.input_db {
+        let mut source = db_input::open(database, &options.input_table, &options.sequence_column)?;
+        engine
+            .process_source(&mut source)
+            .with_context(|| format!("can not process table {}", options.input_table))?;
+    } else if options.file_path == cli::STDIN {
+        // Standard input may wait for rows of a producer, reading it stops on a shutdown.
+        let input = StoppingReader::new(io::stdin(), shutdown.clone());
+        // Parsing of arguments makes sure more parse threads are used only with CSV input.
+        if options.parse_threads > 1 {
+            engine.process_csv_parallel(input, options.parse_threads)?;
+        } else {
+            engine.process_input(input, options.input_format)?;
+        }
+    } else {
+        // Engine state is shared by all files, rejections are attributed to their file.
+        for file_path in &options.input_files {
+            if options.input_files.len() > 1 {
+                engine.set_input_file(file_path);
             }
+            process_file(&mut engine, file_path, options)?;
+        }
+    }
+
+    Ok(engine)
+}
+
+fn process_file(
+    engine: &mut PaymentsEngine,
+    file_path: &OsString,
+    options: &Options,
+) -> anyhow::Result<()> {
+    if !options.progress && !options.mmap && options.parse_threads == 1 {
+        return engine.process_file_as(file_path, options.input_format);
+    }
+    let file = File::open(file_path)
+        .with_context(|| format!("can not open input file {:?}", file_path))?;
+    let progress = match options.progress {
+        false => None,
+        true => Some(Progress::for_file(&file).context("can not read size of input file")?),
+    };
+    let mut input = input(file, file_path, options.mmap)?;
+    if let Some(progress) = &progress {
+        input = Box::new(progress.wrap(input));
+    }
+    let processed = if options.parse_threads > 1 {
+        engine.process_csv_parallel(input, options.parse_threads)
+    } else {
+        engine.process_input(input, options.input_format)
+    };
+    if let Some(progress) = progress {
+        progress.finish();
+    }
+    processed.with_context(|| format!("can not process input file {:?}", file_path))
+}
+
+// Input file as it's read, or mapped into memory with `--mmap`, so it's parsed over the mapped
+// pages without read calls.
+fn input(file: File, file_path: &OsString, mmap: bool) -> anyhow::Result<Box<dyn Read>> {
+    if !mmap {
+        return Ok(Box::new(file));
+    }
+    // Safety: the input file isn't expected to be changed while it's processed, like with reading
+    // it, but a truncated mapped file would end processing with a signal instead of an error.
+    let mapped = unsafe { Mmap::map(&file) }
+        .with_context(|| format!("can not map input file {:?}", file_path))?;
+    Ok(Box::new(io::Cursor::new(mapped)))
+}
+
+// Parsing of arguments makes sure features which need all clients in one engine (audit log,
+// archive) and other than CSV input are not used with more workers.
+fn process_sharded(options: &Options, shutdown: &Shutdown) -> anyhow::Result<PaymentsEngine> {
+    let mut sharded =
+        ShardedEngine::with_hot_clients(options.workers, &options.hot_clients, || {
+            create_engine(options)
+        })?;
+    if let Some(max_rows_per_sec) = options.max_rows_per_sec {
+        sharded.set_throttle(Throttle::new(max_rows_per_sec));
+    }
+    if let Some(reorder) = options.reorder {
+        sharded.set_reorder(reorder);
+    }
+    sharded.set_shutdown(shutdown.clone());
+    if options.file_path == cli::STDIN {
+        sharded.process_csv(StoppingReader::new(io::stdin(), shutdown.clone()))?;
+    }
+    for file_path in options
+        .input_files
+        .iter()
+        .filter(|file_path| *file_path != cli::STDIN)
+    {
+        let file = File::open(file_path)
+            .with_context(|| format!("can not open input file {:?}", file_path))?;
+        let processed = if options.progress {
+            let progress = Progress::for_file(&file).context("can not read size of input file")?;
+            let processed =
+                sharded.process_csv(progress.wrap(input(file, file_path, options.mmap)?));
+            progress.finish();
+            processed
+        } else {
+            sharded.process_csv(input(file, file_path, options.mmap)?)
         };
-        // Currently if client doesn't exist a new entry is added regarding type of transaction.
-        // A discussion is needed if a new entry is added only if a transaction type is deposit and
-        // in other cases a transaction is just ignored.
-        let account = accounts
-            .entry(transaction.client_id())
-            .or_insert_with(|| Account::new(transaction.client_id()));
+        processed.with_context(|| format!("can not process input file {:?}", file_path))?;
+    }
 
-        transaction.tx_type.process(account);
+    let (engine, stats) = sharded.finish_with_stats()?;
+    shard::report_skew(&stats);
+    if let Some(shard_report) = &options.shard_report {
+        output::save_report(&stats, shard_report, "shard report")
+            .context("can not save shard report")?;
     }
 
-    Ok(())
+    Ok(engine)
 }
 
-fn main() -> Result<(), Box<dyn Error>> {
-    env_logger::init();
+fn run() -> anyhow::Result<()> {
+    let options = cli::parse_args(env::args_os().skip(1)).context(Failure::Usage)?;
+    if options.nice {
+        throttle::lower_priority()?;
+    }
+    // Inputs and outputs outside of engines, like reports, are read and written by the same
+    // config as engines.
+    let _config = engine_config(&options).enter();
+    let signing_key = match &options.signing_key_file {
+        None => None,
+        Some(signing_key_file) => Some(checksum::read_signing_key(signing_key_file)?),
+    };
+
+    if options.command == Command::Conformance {
+        if let Some(export_dir) = &options.export_dir {
+            return conformance::export(export_dir).context("can not export conformance vectors");
+        }
+        // By default this binary checks itself.
+        let implementation = match &options.implementation {
+            None => env::current_exe().context("can not find current executable")?,
+            Some(implementation) => PathBuf::from(implementation),
+        };
+        let failed = conformance::run(&implementation)?;
+        if failed > 0 {
+            bail!("{} conformance vectors failed", failed);
+        }
+        return Ok(());
+    }
+
+    if options.command == Command::BenchCompare {
+        let current = env::current_exe().context("can not find current executable")?;
+        let comparison = bench::compare(&current, Path::new(&options.file_path), &options.dataset)
+            .context("can not compare benchmarks")?;
+        // Report is written as JSON if its extension is `json`, as markdown otherwise.
+        match &options.bench_report {
+            None => print!("{}", comparison.to_markdown()),
+            Some(report) if Path::new(report).extension() == Some(OsStr::new("json")) => {
+                let file = File::create(report)
+                    .with_context(|| format!("can not create report {:?}", report))?;
+                serde_json::to_writer_pretty(file, &comparison)
+                    .with_context(|| format!("can not write report {:?}", report))?;
+            }
+            Some(report) => fs::write(report, comparison.to_markdown())
+                .with_context(|| format!("can not write report {:?}", report))?,
+        }
+        if !comparison.outputs_equal {
+            bail!("outputs of current and baseline binaries differ");
+        }
+        return Ok(());
+    }
 
-    let file_path = get_file_path().expect("file path not provided");
+    if options.command == Command::Generate {
+        return bench::generate(io::stdout().lock(), &options.dataset)
+            .context("can not generate dataset");
+    }
+
+    #[cfg(feature = "grpc")]
+    if options.command == Command::Serve {
+        let shutdown = Shutdown::install()?;
+        let engine = Arc::new(Mutex::new(create_engine(&options)?));
+        let runtime = tokio::runtime::Runtime::new().context("can not start async runtime")?;
+        runtime.block_on(grpc::serve(Arc::clone(&engine), options.listen, shutdown))?;
+        // Streams are finished by now, so accounts are complete.
+        let engine = engine
+            .lock()
+            .map_err(|_| anyhow::anyhow!("engine failed while processing a transaction"))?;
+        save_checkpoint(&engine, &options)?;
+        output::save_accounts_output(
+            engine.accounts(),
+            options.output_format,
+            options.checksum.as_ref(),
+            signing_key.as_deref(),
+        )?;
+        return Ok(());
+    }
+
+    if options.command == Command::Watch {
+        let shutdown = Shutdown::install()?;
+        let processed_dir = options.processed_dir.as_ref().map(PathBuf::from);
+        let mut watch =
+            DirectoryWatch::open(PathBuf::from(&options.file_path), processed_dir, || {
+                let mut engine = create_engine(&options)?;
+                engine.set_shutdown(shutdown.clone());
+                Ok(engine)
+            })?;
+        watch.set_shutdown(shutdown.clone());
+        watch.run()?;
+        save_checkpoint(watch.engine(), &options)?;
+        output::save_accounts_output(
+            watch.engine().accounts(),
+            options.output_format,
+            options.checksum.as_ref(),
+            signing_key.as_deref(),
+        )?;
+        return Ok(());
+    }
+
+    if options.command == Command::StateMachine {
+        return statemachine::export_json(io::stdout()).context("can not export state machine");
+    }
+
+    // Parsing of arguments makes sure the SQLite store is given with a query.
+    #[cfg(feature = "sqlite")]
+    if let (Some(sql), Some(sqlite_store)) = (&options.sql, &options.sqlite_store) {
+        return sqlite_store::query(sqlite_store, sql, io::stdout())
+            .context("can not query SQLite store");
+    }
+
+    if options.command == Command::StoreMigrate {
+        return output::save_rows(migrate_stores(&options)?, io::stdout());
+    }
+
+    if options.command == Command::StoreStats
+        || options.command == Command::StoreCompact
+        || options.command == Command::StoreQuery
+    {
+        // Parsing of arguments makes sure a store is given.
+        if let Some(mut store) = open_store(&options)? {
+            return match (&options.command, &options.account_query) {
+                (Command::StoreCompact, _) => {
+                    let report = store.compact().context("can not compact store")?;
+                    output::save_rows([report], io::stdout())
+                }
+                (Command::StoreQuery, Some(query)) => {
+                    let accounts = query
+                        .select(store.as_ref())
+                        .context("can not query store")?;
+                    output::save_accounts_output(
+                        &accounts,
+                        options.output_format,
+                        options.checksum.as_ref(),
+                        signing_key.as_deref(),
+                    )?;
+                    Ok(())
+                }
+                _ => {
+                    let stats = store.stats().context("can not read store statistics")?;
+                    output::save_rows([stats], io::stdout())
+                }
+            };
+        }
+    }
+
+    if options.command == Command::AuditVerify {
+        let (entries, digest) =
+            hash_chain::verify(&options.file_path).context("audit chain verification failed")?;
+        println!(
+            "{}: {} entries, digest {}: OK",
+            Path::new(&options.file_path).display(),
+            entries,
+            digest
+        );
+        return Ok(());
+    }
 
-    // In real world application this data won't be stored in memory (because we could have a lot of data)
-    // but in some database or even database + partially in memory to have a quick access.
-    let mut accounts: HashMap<u16, Account> = HashMap::new();
-    process_payments(file_path, &mut accounts).expect("critical error when processing payments");
+    if options.command == Command::Replay {
+        let accounts = event_log::replay(&options.file_path).context("can not replay event log")?;
+        output::save_accounts_output(
+            &accounts,
+            options.output_format,
+            options.checksum.as_ref(),
+            signing_key.as_deref(),
+        )?;
+        if let Some(state_hash) = &options.state_hash {
+            checksum::save_state_hash(&accounts, state_hash)?;
+        }
+        return Ok(());
+    }
+
+    if options.command == Command::ImportState {
+        let mut engine = PaymentsEngine::default();
+        engine
+            .import_state(state::load_state(&options.file_path)?)
+            .context("can not import state")?;
+        output::save_accounts_output(
+            engine.accounts(),
+            options.output_format,
+            options.checksum.as_ref(),
+            signing_key.as_deref(),
+        )?;
+        if let Some(state_hash) = &options.state_hash {
+            checksum::save_state_hash(engine.accounts(), state_hash)?;
+        }
+        return Ok(());
+    }
+
+    if options.command == Command::VerifyOutput {
+        // Parsing of arguments makes sure a checksum file is given.
+        if let Some(checksum) = &options.checksum {
+            checksum::verify_output(&options.file_path, checksum, signing_key.as_deref())
+                .context("output verification failed")?;
+            println!("{}: OK", Path::new(&options.file_path).display());
+        }
+        return Ok(());
+    }
+
+    // Accounts a dry run is compared with, those of the state it continues from.
+    let dry_run_base = if options.command == Command::Process && options.dry_run {
+        let mut engine = PaymentsEngine::default();
+        if let Some(state_in) = &options.state_in {
+            state::restore(&mut engine, state_in)?;
+        }
+        Some(engine.into_accounts())
+    } else {
+        None
+    };
 
-    save_accounts_data(&accounts).expect("can not serialize and save accounts data");
+    // Transactions of a database are reported under the table name.
+    let mut run_report = (options.run_report.is_some() || options.summary).then(|| {
+        let input = match &options.input_db {
+            Some(_) => options.input_table.clone(),
+            None => options
+                .input_files
+                .iter()
+                .map(|input_file| input_file.to_string_lossy())
+                .collect::<Vec<_>>()
+                .join(","),
+        };
+        RunReport::start(input, options.period.clone())
+    });
+    let shutdown = Shutdown::install()?;
+    let processed = if options.workers > 1 {
+        process_sharded(&options, &shutdown)
+    } else {
+        process(&options, &shutdown)
+    }
+    .context("critical error when processing payments");
+    let mut engine = match (processed, &mut run_report, &options.run_report) {
+        (Ok(engine), _, _) => engine,
+        (Err(err), Some(run_report), Some(file_path)) => {
+            run_report.failed(&err);
+            run_report.save(file_path)?;
+            return Err(err);
+        }
+        (Err(err), _, _) => return Err(err),
+    };
+    save_checkpoint(&engine, &options)?;
+
+    let output_digest = match options.command {
+        Command::Process => match &dry_run_base {
+            Some(before) => {
+                output::save_accounts_diff(before, engine.accounts(), io::stdout())
+                    .context("can not save accounts diff")?;
+                None
+            }
+            None => Some(output::save_accounts_output(
+                engine.accounts(),
+                options.output_format,
+                options.checksum.as_ref(),
+                signing_key.as_deref(),
+            )?),
+        },
+        Command::TrialBalance => {
+            engine
+                .books()
+                .ledger
+                .save_trial_balance(engine.accounts())
+                .context("trial balance integrity check failed")
+                .context(Failure::Internal)?;
+            None
+        }
+        Command::Archive => {
+            // Parsing of arguments makes sure a store is given.
+            let archived = engine
+                .archive_dormant(options.dormant_after)
+                .context("can not archive dormant accounts")?;
+            info!("{} dormant accounts archived", archived);
+            Some(output::save_accounts_output(
+                engine.accounts(),
+                options.output_format,
+                options.checksum.as_ref(),
+                signing_key.as_deref(),
+            )?)
+        }
+        Command::Lookup => {
+            // Parsing of arguments makes sure a transaction id is given.
+            let tx_id = options.lookup_tx.unwrap_or_default();
+            let found = engine
+                .find_transaction(tx_id)
+                .with_context(|| format!("transaction {} not found", tx_id))?;
+            let mut writer = csv::Writer::from_writer(io::stdout());
+            writer
+                .serialize(found)
+                .and_then(|_| Ok(writer.flush()?))
+                .context("can not save transaction lookup")?;
+            None
+        }
+        Command::ExportLedger => {
+            // Parsing of arguments makes sure a client is given.
+            let client_id = options.export_client.unwrap_or_default();
+            let transactions: Vec<_> = engine
+                .client_transactions(client_id)
+                .into_iter()
+                .filter(|transaction| {
+                    options.export_statuses.is_empty()
+                        || options
+                            .export_statuses
+                            .iter()
+                            .any(|status| status.name() == transaction.status)
+                })
+                .collect();
+            output::save_transactions(&transactions, options.output_format, io::stdout())
+                .context("can not save ledger export")?;
+            None
+        }
+        Command::Account => {
+            // Parsing of arguments makes sure a client is given.
+            let client_id = options.export_client.unwrap_or_default();
+            let account = engine
+                .accounts()
+                .get(&client_id)
+                .with_context(|| format!("client {} has no account", client_id))?;
+            output::save_account_history(
+                account,
+                &engine.client_transactions(client_id),
+                io::stdout(),
+            )
+            .context("can not save account history")?;
+            None
+        }
+        Command::ExportState => {
+            state::save_state(&engine.export_state(), io::stdout().lock())
+                .context("can not export state")?;
+            None
+        }
+        Command::Verify
+        | Command::VerifyOutput
+        | Command::AuditVerify
+        | Command::Conformance
+        | Command::StoreStats
+        | Command::StoreCompact
+        | Command::StoreSql
+        | Command::StoreQuery
+        | Command::StoreMigrate
+        | Command::StateMachine
+        | Command::Replay
+        | Command::BenchCompare
+        | Command::Generate
+        | Command::Serve
+        | Command::Watch
+        | Command::ImportState => None,
+    };
+    // Parsing of arguments makes sure accounts are output.
+    if let Some(state_hash) = &options.state_hash {
+        checksum::save_state_hash(engine.accounts(), state_hash)?;
+    }
+
+    if let Some(collections_report) = options.collections_report {
+        output::save_collections_report(engine.accounts(), &collections_report)
+            .context("can not save collections report")?;
+    }
+
+    if let Some(held_report) = options.held_report {
+        output::save_held_report(engine.accounts(), &held_report)
+            .context("can not save held report")?;
+    }
+
+    if let (Some(interest), Some(interest_report)) =
+        (&engine.books().interest, &options.interest_report)
+    {
+        interest
+            .save_report(interest_report)
+            .context("can not save interest report")?;
+    }
+
+    if let (Some(fees), Some(fee_report)) = (&engine.books().fees, &options.fee_report) {
+        fees.save_report(fee_report)
+            .context("can not save fee report")?;
+    }
+
+    if let (Some(fraud), Some(fraud_report)) = (&engine.books().fraud, &options.fraud_report) {
+        fraud
+            .save_report(fraud_report)
+            .context("can not save fraud report")?;
+    }
+
+    if let Some(period_report) = options.period_report {
+        engine
+            .books()
+            .periods
+            .save_report(&period_report)
+            .context("can not save period report")?;
+    }
+
+    if let Some(mut run_report) = run_report {
+        // Input file is hashed once more, as sources read it as a stream.
+        let input_files = (options.input_db.is_none() && options.file_path != cli::STDIN)
+            .then_some(options.input_files.as_slice());
+        run_report.succeeded(&engine, input_files, output_digest.as_ref())?;
+        if let Some(file_path) = &options.run_report {
+            run_report
+                .save(file_path)
+                .context("can not save run report")?;
+        }
+        // Accounts may be written to standard output, so the summary goes to standard error.
+        if options.summary {
+            eprint!("{}", run_report.summary());
+        }
+    }
+
+    // Accounts may be written to standard output, so the digest goes to standard error.
+    if let Some(hash_chain) = engine.hash_chain() {
+        eprintln!(
+            "audit chain digest: {} ({} entries)",
+            hash_chain.digest(),
+            hash_chain.entries()
+        );
+    }
+
+    // Rejections of verified input are reported by now, the command fails if there are any.
+    if options.command == Command::Verify {
+        let rejected = engine.rejections().total();
+        if rejected > 0 {
+            return Err(anyhow!(
+                "{} of {} records would be rejected",
+                rejected,
+                engine.rows()
+            )
+            .context(Failure::InvalidInput));
+        }
+        eprintln!("{} records verified: OK", engine.rows());
+    }
+
+    // Outputs are complete for transactions read until the shutdown, but not for the input.
+    if shutdown.requested() {
+        return Err(anyhow!(
+            "processing was stopped after {} records, before the end of input",
+            engine.rows()
+        )
+        .context(Failure::Interrupted));
+    }
 
     Ok(())
 }
 
+// Exit codes of failed runs, so scripts and orchestration can tell failures apart.
+const EXIT_FAILED: i32 = 1;
+const EXIT_USAGE: i32 = 2;
+const EXIT_IO: i32 = 3;
+const EXIT_INVALID_INPUT: i32 = 4;
+const EXIT_INTERNAL: i32 = 5;
+const EXIT_INTERRUPTED: i32 = 130;
+
+// A kind of failure given by the context decides the code, otherwise any I/O error in causes does.
+fn exit_code(err: &anyhow::Error) -> i32 {
+    match err.downcast_ref::<Failure>() {
+        Some(Failure::Usage) => EXIT_USAGE,
+        Some(Failure::InvalidInput) => EXIT_INVALID_INPUT,
+        Some(Failure::Internal) => EXIT_INTERNAL,
+        Some(Failure::Interrupted) => EXIT_INTERRUPTED,
+        Some(Failure::Unavailable) => EXIT_IO,
+        None if err.chain().any(|cause| cause.is::<io::Error>()) => EXIT_IO,
+        None => EXIT_FAILED,
+    }
+}
+
+fn main() {
+    env_logger::init();
+
+    // A panic is reported by the panic hook, it only needs its own exit code.
+    let result = match panic::catch_unwind(run) {
+        Ok(result) => result,
+        Err(_) => process::exit(EXIT_INTERNAL),
+    };
+    if let Err(err) = result {
+        eprintln!("error: {}", err);
+        for cause in err.chain().skip(1) {
+            eprintln!("  caused by: {}", cause);
+        }
+        process::exit(exit_code(&err));
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
-    use rust_decimal::prelude::*;
 
     #[test]
-    fn test_process_payments_1() {
-        let mut accounts: HashMap<u16, Account> = HashMap::new();
-        assert!(process_payments("transactions_1.csv".parse().unwrap(), &mut accounts).is_ok());
-        assert_eq!(accounts.len(), 2);
+    fn test_conformance_vectors() {
+        // Fixed-point amounts hold more integer digits, so they don't overflow where decimals do.
+        for vector in conformance::VECTORS
+            .iter()
+            .filter(|vector| !cfg!(feature = "fixed-point") || vector.name != "overflow")
+        {
+            let input = env::temp_dir().join(format!(
+                "coding_test_vector_{}_{}.csv",
+                vector.name,
+                process::id()
+            ));
+            std::fs::write(&input, vector.input).unwrap();
 
-        let account = accounts.get(&1).unwrap();
-        assert_eq!(account.available, Decimal::from_str("1.5").unwrap());
-        assert_eq!(account.held, Decimal::from_str("0").unwrap());
-        assert_eq!(account.total, Decimal::from_str("1.5").unwrap());
-        assert!(!account.locked);
+            // Vectors select policy presets by arguments, so engines are created like by the
+            // binary.
+            let args = [input.as_os_str()]
+                .into_iter()
+                .chain(vector.args.iter().map(OsStr::new))
+                .map(OsString::from);
+            let options = cli::parse_args(args).unwrap();
+            let _config = engine_config(&options).enter();
+            let mut engine = create_engine(&options).unwrap();
+            assert!(engine.process_file(&input.clone().into_os_string()).is_ok());
+            std::fs::remove_file(&input).unwrap();
 
-        let account = accounts.get(&2).unwrap();
-        assert_eq!(account.available, Decimal::from_str("2").unwrap());
-        assert_eq!(account.held, Decimal::from_str("0").unwrap());
-        assert_eq!(account.total, Decimal::from_str("2").unwrap());
-        assert!(!account.locked);
+            let mut output = Vec::new();
+            output::save_accounts(engine.accounts(), &mut output).unwrap();
+            let output = String::from_utf8(output).unwrap();
+            assert!(vector.check(&output).is_ok(), "{}", vector.name);
+        }
     }
 
     #[test]
-    fn test_process_payments_2() {
-        let mut accounts: HashMap<u16, Account> = HashMap::new();
-        assert!(process_payments("transactions_2.csv".parse().unwrap(), &mut accounts).is_ok());
-        assert_eq!(accounts.len(), 5);
-
-        let account = accounts.get(&1).unwrap();
-        assert_eq!(account.available, Decimal::from_str("1231.744").unwrap());
-        assert_eq!(account.held, Decimal::from_str("0").unwrap());
-        assert_eq!(account.total, Decimal::from_str("1231.744").unwrap());
-        assert!(!account.locked);
-
-        let account = accounts.get(&2).unwrap();
-        assert_eq!(account.available, Decimal::from_str("37.2624").unwrap());
-        assert_eq!(account.held, Decimal::from_str("0").unwrap());
-        assert_eq!(account.total, Decimal::from_str("37.2624").unwrap());
-        assert!(!account.locked);
-
-        let account = accounts.get(&3).unwrap();
-        assert_eq!(account.available, Decimal::from_str("249.8589").unwrap());
-        assert_eq!(account.held, Decimal::from_str("0").unwrap());
-        assert_eq!(account.total, Decimal::from_str("249.8589").unwrap());
-        assert!(!account.locked);
-
-        let account = accounts.get(&4).unwrap();
-        assert_eq!(account.available, Decimal::from_str("200.2442").unwrap());
-        assert_eq!(account.held, Decimal::from_str("0").unwrap());
-        assert_eq!(account.total, Decimal::from_str("200.2442").unwrap());
-        assert!(!account.locked);
-
-        let account = accounts.get(&5).unwrap();
-        assert_eq!(account.available, Decimal::from_str("616.7601").unwrap());
-        assert_eq!(account.held, Decimal::from_str("0").unwrap());
-        assert_eq!(account.total, Decimal::from_str("616.7601").unwrap());
-        assert!(!account.locked);
-    }
+    fn test_exit_codes() {
+        let mut engine = PaymentsEngine::default();
+        let err = engine
+            .process_json("[{".as_bytes())
+            .context("critical error")
+            .unwrap_err();
+        assert_eq!(exit_code(&err), EXIT_INVALID_INPUT);
 
-    #[test]
-    fn test_process_payments_3() {
-        let mut accounts: HashMap<u16, Account> = HashMap::new();
-        assert!(process_payments("transactions_3.csv".parse().unwrap(), &mut accounts).is_ok());
-        assert_eq!(accounts.len(), 5);
-
-        let account = accounts.get(&1).unwrap();
-        assert_eq!(account.available, Decimal::from_str("50").unwrap());
-        assert_eq!(account.held, Decimal::from_str("200").unwrap());
-        assert_eq!(account.total, Decimal::from_str("250").unwrap());
-        assert!(!account.locked);
-
-        let account = accounts.get(&2).unwrap();
-        assert_eq!(account.available, Decimal::from_str("250").unwrap());
-        assert_eq!(account.held, Decimal::from_str("0").unwrap());
-        assert_eq!(account.total, Decimal::from_str("250").unwrap());
-        assert!(!account.locked);
-
-        let account = accounts.get(&3).unwrap();
-        assert_eq!(account.available, Decimal::from_str("50").unwrap());
-        assert_eq!(account.held, Decimal::from_str("0").unwrap());
-        assert_eq!(account.total, Decimal::from_str("50").unwrap());
-        assert!(account.locked);
-
-        let account = accounts.get(&4).unwrap();
-        assert_eq!(account.available, Decimal::from_str("250").unwrap());
-        assert_eq!(account.held, Decimal::from_str("0").unwrap());
-        assert_eq!(account.total, Decimal::from_str("250").unwrap());
-        assert!(!account.locked);
-
-        let account = accounts.get(&5).unwrap();
-        assert_eq!(account.available, Decimal::from_str("100").unwrap());
-        assert_eq!(account.held, Decimal::from_str("0").unwrap());
-        assert_eq!(account.total, Decimal::from_str("100").unwrap());
-        assert!(!account.locked);
+        let err = cli::parse_args(["--unknown"].iter().map(OsString::from))
+            .context(Failure::Usage)
+            .err()
+            .unwrap();
+        assert_eq!(exit_code(&err), EXIT_USAGE);
+
+        let err = engine
+            .process_file(&OsString::from("missing.csv"))
+            .context("critical error")
+            .unwrap_err();
+        assert_eq!(exit_code(&err), EXIT_IO);
+
+        assert_eq!(exit_code(&anyhow!("failed")), EXIT_FAILED);
     }
 }
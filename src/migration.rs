@@ -0,0 +1,205 @@
+use anyhow::{bail, Context};
+use log::info;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+
+// A step which brings a store from the previous version to `version`. Steps are applied in order
+// and the version is recorded after every step, so an interrupted migration continues with the
+// first step which wasn't applied on next open.
+pub struct Migration<S> {
+    pub version: u32,
+    pub description: &'static str,
+    pub apply: fn(&mut S) -> anyhow::Result<()>,
+}
+
+// A persisted store with a versioned format. Stores are migrated to the latest version when they
+// are opened, so upgrading the crate never requires changing stored data by hand.
+pub trait Migrate: Sized + 'static {
+    // Name of the store in reports, for example `archive`.
+    fn name(&self) -> &'static str;
+
+    // Version of the format on disk, 0 for stores written before versions were recorded.
+    fn version(&self) -> anyhow::Result<u32>;
+
+    fn set_version(&mut self, version: u32) -> anyhow::Result<()>;
+
+    // Writes a copy of the store to a path which doesn't exist yet.
+    fn backup(&self, path: &Path) -> anyhow::Result<()>;
+
+    // All migrations of the store, ordered by version.
+    fn migrations() -> &'static [Migration<Self>];
+}
+
+#[derive(Default, Clone, PartialEq, Debug)]
+pub struct MigrationOptions {
+    // Pending migrations are only reported.
+    pub dry_run: bool,
+    // The store is copied here before the first pending migration is applied.
+    pub backup: Option<PathBuf>,
+}
+
+#[derive(Serialize, PartialEq, Debug)]
+pub struct MigrationReport {
+    pub store: &'static str,
+    pub from_version: u32,
+    pub to_version: u32,
+    // Descriptions of pending migrations separated by semicolons.
+    pub migrations: String,
+    pub applied: bool,
+}
+
+pub fn latest_version<S: Migrate>() -> u32 {
+    S::migrations()
+        .last()
+        .map_or(0, |migration| migration.version)
+}
+
+// Applies migrations which are newer than the version of the store. A store written by a newer
+// build is refused, as this build can't know its format.
+pub fn migrate<S: Migrate>(
+    store: &mut S,
+    options: &MigrationOptions,
+) -> anyhow::Result<MigrationReport> {
+    let from_version = store.version()?;
+    let to_version = latest_version::<S>();
+    if from_version > to_version {
+        bail!(
+            "{} has version {} which is newer than version {} supported by this build",
+            store.name(),
+            from_version,
+            to_version
+        );
+    }
+
+    let pending: Vec<&Migration<S>> = S::migrations()
+        .iter()
+        .filter(|migration| migration.version > from_version)
+        .collect();
+    let mut report = MigrationReport {
+        store: store.name(),
+        from_version,
+        to_version,
+        migrations: pending
+            .iter()
+            .map(|migration| migration.description)
+            .collect::<Vec<_>>()
+            .join("; "),
+        applied: false,
+    };
+    if options.dry_run || pending.is_empty() {
+        return Ok(report);
+    }
+
+    if let Some(backup) = &options.backup {
+        store
+            .backup(backup)
+            .with_context(|| format!("can not back up {} to {:?}", store.name(), backup))?;
+        info!("{} backed up to {:?}", store.name(), backup);
+    }
+    for migration in pending {
+        (migration.apply)(store).with_context(|| {
+            format!(
+                "can not migrate {} to version {}",
+                store.name(),
+                migration.version
+            )
+        })?;
+        store.set_version(migration.version)?;
+        info!(
+            "{} migrated to version {}: {}",
+            store.name(),
+            migration.version,
+            migration.description
+        );
+    }
+    report.applied = true;
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[derive(Default)]
+    struct Store {
+        version: u32,
+        applied: Vec<u32>,
+    }
+
+    static MIGRATIONS: [Migration<Store>; 2] = [
+        Migration {
+            version: 1,
+            description: "first",
+            apply: |store| {
+                store.applied.push(1);
+                Ok(())
+            },
+        },
+        Migration {
+            version: 2,
+            description: "second",
+            apply: |store| {
+                store.applied.push(2);
+                Ok(())
+            },
+        },
+    ];
+
+    impl Migrate for Store {
+        fn name(&self) -> &'static str {
+            "test store"
+        }
+
+        fn version(&self) -> anyhow::Result<u32> {
+            Ok(self.version)
+        }
+
+        fn set_version(&mut self, version: u32) -> anyhow::Result<()> {
+            self.version = version;
+            Ok(())
+        }
+
+        fn backup(&self, _: &Path) -> anyhow::Result<()> {
+            Ok(())
+        }
+
+        fn migrations() -> &'static [Migration<Store>] {
+            &MIGRATIONS
+        }
+    }
+
+    #[test]
+    fn test_pending_migrations_are_applied() {
+        let mut store = Store {
+            version: 1,
+            ..Store::default()
+        };
+        let dry_run = MigrationOptions {
+            dry_run: true,
+            ..MigrationOptions::default()
+        };
+        let report = migrate(&mut store, &dry_run).unwrap();
+        assert_eq!(report.migrations, "second");
+        assert!(!report.applied);
+        assert!(store.applied.is_empty());
+
+        let report = migrate(&mut store, &MigrationOptions::default()).unwrap();
+        assert!(report.applied);
+        assert_eq!(store.applied, vec![2]);
+        assert_eq!(store.version, 2);
+
+        let report = migrate(&mut store, &MigrationOptions::default()).unwrap();
+        assert!(!report.applied);
+        assert_eq!(store.applied, vec![2]);
+    }
+
+    #[test]
+    fn test_newer_store_is_refused() {
+        let mut store = Store {
+            version: 3,
+            ..Store::default()
+        };
+        assert!(migrate(&mut store, &MigrationOptions::default()).is_err());
+    }
+}
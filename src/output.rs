@@ -0,0 +1,548 @@
+use crate::account::Account;
+use crate::amount::Amount;
+use crate::checksum::{HashingWriter, OutputDigest};
+use crate::decimal_format;
+use crate::engine::TransactionInfo;
+use crate::sink::{AccountSink, CsvSink, JsonSink};
+
+use anyhow::Context;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{self, Write};
+use std::str::FromStr;
+
+// All CSV outputs (accounts, reports, audit trail) are written through this module, so headers,
+// quoting and row order are handled in one place and covered by the golden tests below.
+
+// CSV writer used for every output. The header is taken from the serialized type and it's written
+// once, before the first row.
+pub fn csv_writer<W: Write>(output: W) -> csv::Writer<W> {
+    csv::WriterBuilder::new()
+        .has_headers(true)
+        .from_writer(output)
+}
+
+pub fn save_rows<W, T, I>(rows: I, output: W) -> anyhow::Result<()>
+where
+    W: Write,
+    T: Serialize,
+    I: IntoIterator<Item = T>,
+{
+    let mut writer = csv_writer(output);
+    for row in rows {
+        writer.serialize(row)?;
+    }
+    writer.flush()?;
+
+    Ok(())
+}
+
+// Creates a report file and writes all rows into it. `name` is only used in error messages.
+pub fn save_report<T, I>(rows: I, file_path: &OsString, name: &str) -> anyhow::Result<()>
+where
+    T: Serialize,
+    I: IntoIterator<Item = T>,
+{
+    let file = File::create(file_path)
+        .with_context(|| format!("can not create {} {:?}", name, file_path))?;
+    save_rows(rows, file)
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum OutputFormat {
+    Csv,
+    // A JSON array of accounts with amounts as JSON numbers.
+    Json,
+}
+
+// Format is given as `csv` or `json`.
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<OutputFormat, String> {
+        match s {
+            "csv" => Ok(OutputFormat::Csv),
+            "json" => Ok(OutputFormat::Json),
+            _ => Err(format!("unknown output format: {}", s)),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct JsonTransaction<'a> {
+    client: u16,
+    tx: u32,
+    #[serde(serialize_with = "decimal_format::serialize_json_number")]
+    amount: &'a Amount,
+    status: &'static str,
+    #[serde(rename = "type")]
+    tx_type: &'static str,
+}
+
+#[derive(Serialize)]
+struct AccountHistory<'a> {
+    client: u16,
+    #[serde(serialize_with = "decimal_format::serialize_json_number")]
+    available: Amount,
+    #[serde(serialize_with = "decimal_format::serialize_json_number")]
+    held: Amount,
+    #[serde(serialize_with = "decimal_format::serialize_json_number")]
+    total: Amount,
+    locked: bool,
+    transactions: Vec<JsonTransaction<'a>>,
+}
+
+fn json_transaction(transaction: &TransactionInfo) -> JsonTransaction<'_> {
+    JsonTransaction {
+        client: transaction.client,
+        tx: transaction.tx,
+        amount: &transaction.amount,
+        status: transaction.status,
+        tx_type: transaction.tx_type,
+    }
+}
+
+// Balances of an account with its stored transactions and their dispute statuses, as a pretty
+// printed JSON object meant to be read by people debugging the account.
+pub fn save_account_history<W: Write>(
+    account: &Account,
+    transactions: &[TransactionInfo],
+    mut output: W,
+) -> anyhow::Result<()> {
+    let history = AccountHistory {
+        client: account.id(),
+        available: account.available(),
+        held: account.held(),
+        total: account.total(),
+        locked: account.is_locked(),
+        transactions: transactions.iter().map(json_transaction).collect(),
+    };
+    serde_json::to_writer_pretty(&mut output, &history)?;
+    output.write_all(b"\n")?;
+    Ok(output.flush()?)
+}
+
+// Stored deposits and withdrawals in CSV or as a JSON array with amounts as JSON numbers, like accounts.
+pub fn save_transactions<W: Write>(
+    transactions: &[TransactionInfo],
+    format: OutputFormat,
+    mut output: W,
+) -> anyhow::Result<()> {
+    if format == OutputFormat::Csv {
+        return save_rows(transactions, output);
+    }
+
+    output.write_all(b"[")?;
+    for (index, transaction) in transactions.iter().enumerate() {
+        output.write_all(if index == 0 { b"\n" } else { b",\n" })?;
+        serde_json::to_writer(&mut output, &json_transaction(transaction))?;
+    }
+    output.write_all(if transactions.is_empty() {
+        b"]\n"
+    } else {
+        b"\n]\n"
+    })?;
+    Ok(output.flush()?)
+}
+
+// Every client is written exactly once because accounts are keyed by client id. Accounts are
+// sorted by client id, so the same input always gives the same output. Only references are
+// sorted, which takes a fraction of writing time even for millions of accounts.
+pub fn write_accounts<S: AccountSink + ?Sized>(
+    accounts: &HashMap<u16, Account>,
+    sink: &mut S,
+) -> anyhow::Result<()> {
+    let mut sorted: Vec<&Account> = accounts.values().collect();
+    sorted.sort_unstable_by_key(|account| account.id());
+    for account in sorted {
+        sink.write(account)?;
+    }
+    sink.finish()
+}
+
+pub fn save_accounts<W: Write>(accounts: &HashMap<u16, Account>, output: W) -> anyhow::Result<()> {
+    write_accounts(accounts, &mut CsvSink::new(output))
+}
+
+// Writes accounts to standard output and a checksum of what was written, if a file for it is
+// given. Returns the checksum.
+pub fn save_accounts_output(
+    accounts: &HashMap<u16, Account>,
+    format: OutputFormat,
+    checksum: Option<&OsString>,
+    signing_key: Option<&[u8]>,
+) -> anyhow::Result<OutputDigest> {
+    let mut output = HashingWriter::new(io::stdout(), signing_key);
+    match format {
+        OutputFormat::Csv => save_accounts(accounts, &mut output),
+        OutputFormat::Json => write_accounts(accounts, &mut JsonSink::new(&mut output)),
+    }
+    .context("can not serialize and save accounts data")?;
+    let digest = output.digest();
+    if let Some(checksum) = checksum {
+        digest.save(checksum)?;
+    }
+
+    Ok(digest)
+}
+
+#[derive(Serialize)]
+struct CollectionsRow {
+    client: u16,
+    #[serde(serialize_with = "decimal_format::serialize")]
+    available: Amount,
+    #[serde(serialize_with = "decimal_format::serialize")]
+    held: Amount,
+    #[serde(serialize_with = "decimal_format::serialize")]
+    total: Amount,
+    locked: bool,
+    // Funds owed beyond available funds.
+    #[serde(serialize_with = "decimal_format::serialize")]
+    debt: Amount,
+}
+
+// Accounts with negative balances owe funds which need to be collected (or written off).
+pub fn save_collections_report(
+    accounts: &HashMap<u16, Account>,
+    file_path: &OsString,
+) -> anyhow::Result<()> {
+    let mut negative: Vec<&Account> = accounts
+        .values()
+        .filter(|account| account.has_negative_balance())
+        .collect();
+    negative.sort_by_key(|account| account.id());
+
+    let rows = negative.into_iter().map(|account| CollectionsRow {
+        client: account.id(),
+        available: account.available(),
+        held: account.held(),
+        total: account.total(),
+        locked: account.is_locked(),
+        debt: account.debt(),
+    });
+    save_report(rows, file_path, "collections report")
+}
+
+#[derive(Serialize)]
+struct HeldRow {
+    client: u16,
+    #[serde(serialize_with = "decimal_format::serialize")]
+    held: Amount,
+    #[serde(serialize_with = "decimal_format::serialize")]
+    held_deposits: Amount,
+    #[serde(serialize_with = "decimal_format::serialize")]
+    held_withdrawals: Amount,
+}
+
+// Held funds of accounts split by origin, as held funds of disputed deposits and of disputed
+// withdrawals are booked to different liability accounts.
+pub fn save_held_report(
+    accounts: &HashMap<u16, Account>,
+    file_path: &OsString,
+) -> anyhow::Result<()> {
+    let mut holding: Vec<&Account> = accounts
+        .values()
+        .filter(|account| !account.held().is_zero())
+        .collect();
+    holding.sort_by_key(|account| account.id());
+
+    let rows = holding.into_iter().map(|account| HeldRow {
+        client: account.id(),
+        held: account.held(),
+        held_deposits: account.held_deposits(),
+        held_withdrawals: account.held_withdrawals(),
+    });
+    save_report(rows, file_path, "held report")
+}
+
+#[derive(Serialize)]
+struct AccountDiff {
+    client: u16,
+    #[serde(serialize_with = "decimal_format::serialize")]
+    available_before: Amount,
+    #[serde(serialize_with = "decimal_format::serialize")]
+    available_after: Amount,
+    #[serde(serialize_with = "decimal_format::serialize")]
+    available_change: Amount,
+    #[serde(serialize_with = "decimal_format::serialize")]
+    held_before: Amount,
+    #[serde(serialize_with = "decimal_format::serialize")]
+    held_after: Amount,
+    #[serde(serialize_with = "decimal_format::serialize")]
+    held_change: Amount,
+    #[serde(serialize_with = "decimal_format::serialize")]
+    total_before: Amount,
+    #[serde(serialize_with = "decimal_format::serialize")]
+    total_after: Amount,
+    #[serde(serialize_with = "decimal_format::serialize")]
+    total_change: Amount,
+    locked_before: bool,
+    locked_after: bool,
+}
+
+// Writes accounts whose balances or lock changed between two states, sorted by client id. An
+// account missing in a state is taken as an empty unlocked one.
+pub fn save_accounts_diff<W: Write>(
+    before: &HashMap<u16, Account>,
+    after: &HashMap<u16, Account>,
+    output: W,
+) -> anyhow::Result<()> {
+    let mut clients: Vec<u16> = before.keys().chain(after.keys()).copied().collect();
+    clients.sort_unstable();
+    clients.dedup();
+
+    let balances = |accounts: &HashMap<u16, Account>, client| match accounts.get(&client) {
+        None => (Amount::from(0), Amount::from(0), Amount::from(0), false),
+        Some(account) => (
+            account.available(),
+            account.held(),
+            account.total(),
+            account.is_locked(),
+        ),
+    };
+    let rows = clients.into_iter().filter_map(|client| {
+        let (available_before, held_before, total_before, locked_before) = balances(before, client);
+        let (available_after, held_after, total_after, locked_after) = balances(after, client);
+        let diff = AccountDiff {
+            client,
+            available_before,
+            available_after,
+            available_change: available_after - available_before,
+            held_before,
+            held_after,
+            held_change: held_after - held_before,
+            total_before,
+            total_after,
+            total_change: total_after - total_before,
+            locked_before,
+            locked_after,
+        };
+        let changed = !diff.available_change.is_zero()
+            || !diff.held_change.is_zero()
+            || !diff.total_change.is_zero()
+            || locked_before != locked_after;
+        changed.then_some(diff)
+    });
+    save_rows(rows, output)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::account::BalanceSnapshot;
+    use crate::amount::Amount;
+    use std::str::FromStr;
+
+    fn account(id: u16, available: &str, held: &str, locked: bool) -> Account {
+        let (available, held) = (
+            Amount::from_str(available).unwrap(),
+            Amount::from_str(held).unwrap(),
+        );
+        let mut account = Account::new(id);
+        account
+            .restore_balances(BalanceSnapshot {
+                available,
+                held,
+                total: available + held,
+                locked,
+            })
+            .unwrap();
+        account
+    }
+
+    fn accounts_output(accounts: Vec<Account>) -> String {
+        let accounts = accounts
+            .into_iter()
+            .map(|account| (account.id(), account))
+            .collect();
+        let mut output = Vec::new();
+        save_accounts(&accounts, &mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn test_transactions_golden_output() {
+        let transactions = [TransactionInfo {
+            client: 3,
+            tx: 7,
+            amount: Amount::from_str("2.5").unwrap(),
+            status: "chargeback",
+            tx_type: "deposit",
+        }];
+
+        let mut output = Vec::new();
+        save_transactions(&transactions, OutputFormat::Csv, &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,tx,amount,status,type\n3,7,2.5000,chargeback,deposit\n"
+        );
+
+        let mut output = Vec::new();
+        save_transactions(&transactions, OutputFormat::Json, &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "[\n{\"client\":3,\"tx\":7,\"amount\":2.5000,\"status\":\"chargeback\",\"type\":\"deposit\"}\n]\n"
+        );
+    }
+
+    #[test]
+    fn test_account_history_output() {
+        let transactions = [TransactionInfo {
+            client: 3,
+            tx: 7,
+            amount: Amount::from_str("2.5").unwrap(),
+            status: "dispute",
+            tx_type: "deposit",
+        }];
+
+        let mut output = Vec::new();
+        save_account_history(&account(3, "1", "2.5", false), &transactions, &mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("\"held\": 2.5000,"), "{}", output);
+        let history: serde_json::Value = serde_json::from_str(&output).unwrap();
+        assert_eq!(history["client"], 3);
+        assert_eq!(history["transactions"][0]["tx"], 7);
+        assert_eq!(history["transactions"][0]["status"], "dispute");
+    }
+
+    #[test]
+    fn test_accounts_golden_output() {
+        assert_eq!(
+            accounts_output(vec![account(1, "1.5", "2", true)]),
+            "client,available,held,total,locked\n1,1.5000,2.0000,3.5000,true\n"
+        );
+    }
+
+    #[test]
+    fn test_accounts_precision() {
+        assert_eq!(
+            accounts_output(vec![account(7, "0.0001", "-12.3456", false)]),
+            "client,available,held,total,locked\n7,0.0001,-12.3456,-12.3455,false\n"
+        );
+    }
+
+    #[test]
+    fn test_accounts_one_header_and_sorted_rows() {
+        let output = accounts_output(
+            (1..=100)
+                .rev()
+                .map(|id| account(id, "1", "0", false))
+                .collect(),
+        );
+        let mut lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines.remove(0), "client,available,held,total,locked");
+
+        let clients: Vec<u16> = lines
+            .iter()
+            .map(|line| line.split(',').next().unwrap().parse().unwrap())
+            .collect();
+        assert_eq!(clients, (1..=100).collect::<Vec<u16>>());
+    }
+
+    #[test]
+    fn test_accounts_diff() {
+        let accounts = |accounts: Vec<Account>| -> HashMap<u16, Account> {
+            accounts
+                .into_iter()
+                .map(|account| (account.id(), account))
+                .collect()
+        };
+        let before = accounts(vec![
+            account(1, "10", "0", false),
+            account(2, "5", "0", false),
+        ]);
+        let after = accounts(vec![
+            account(1, "10", "0", false),
+            account(2, "3", "2", true),
+            account(3, "1", "0", false),
+        ]);
+
+        let mut output = Vec::new();
+        save_accounts_diff(&before, &after, &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,available_before,available_after,available_change,held_before,held_after,\
+             held_change,total_before,total_after,total_change,locked_before,locked_after\n\
+             2,5.0000,3.0000,-2.0000,0.0000,2.0000,2.0000,5.0000,5.0000,0.0000,false,true\n\
+             3,0.0000,1.0000,1.0000,0.0000,0.0000,0.0000,0.0000,1.0000,1.0000,false,false\n"
+        );
+    }
+
+    #[test]
+    fn test_string_columns_are_escaped() {
+        #[derive(Serialize)]
+        struct Row {
+            client: u16,
+            note: &'static str,
+        }
+
+        let mut output = Vec::new();
+        save_rows(
+            vec![
+                Row {
+                    client: 1,
+                    note: "refund, \"partial\"",
+                },
+                Row {
+                    client: 2,
+                    note: "line\nbreak",
+                },
+            ],
+            &mut output,
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,note\n1,\"refund, \"\"partial\"\"\"\n2,\"line\nbreak\"\n"
+        );
+    }
+
+    #[test]
+    fn test_collections_report_is_sorted() {
+        let accounts = vec![
+            account(3, "-1", "0", true),
+            account(1, "5", "0", false),
+            account(2, "0", "-2.5", false),
+        ]
+        .into_iter()
+        .map(|account| (account.id(), account))
+        .collect();
+        let file_path = std::env::temp_dir().join(format!(
+            "coding_test_collections_{}.csv",
+            std::process::id()
+        ));
+        let file_path = file_path.into_os_string();
+        save_collections_report(&accounts, &file_path).unwrap();
+
+        assert_eq!(
+            std::fs::read_to_string(&file_path).unwrap(),
+            "client,available,held,total,locked,debt\n\
+             2,0.0000,-2.5000,-2.5000,false,0.0000\n\
+             3,-1.0000,0.0000,-1.0000,true,1.0000\n"
+        );
+        std::fs::remove_file(&file_path).unwrap();
+    }
+
+    #[test]
+    fn test_held_report() {
+        let mut disputed = account(2, "0", "5", false);
+        disputed.restore_held_withdrawals(Amount::from(2)).unwrap();
+        let accounts = vec![account(1, "5", "0", false), disputed]
+            .into_iter()
+            .map(|account| (account.id(), account))
+            .collect();
+        let file_path =
+            std::env::temp_dir().join(format!("coding_test_held_{}.csv", std::process::id()));
+        let file_path = file_path.into_os_string();
+        save_held_report(&accounts, &file_path).unwrap();
+
+        assert_eq!(
+            std::fs::read_to_string(&file_path).unwrap(),
+            "client,held,held_deposits,held_withdrawals\n\
+             2,5.0000,3.0000,2.0000\n"
+        );
+        std::fs::remove_file(&file_path).unwrap();
+    }
+}
@@ -0,0 +1,146 @@
+use crate::amount::Amount;
+
+use std::cell::Cell;
+use std::str::FromStr;
+
+// What happens when balance arithmetic of a transaction overflows.
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub enum OverflowPolicy {
+    // The transaction is rejected and accounts stay unchanged.
+    #[default]
+    Reject,
+    // Balances are saturated at the largest (or smallest) amount and it's flagged in audit log.
+    Saturate,
+    // The transaction is rejected and processing of the whole run is stopped.
+    Abort,
+}
+
+thread_local! {
+    // Set when arithmetic of the transaction being processed on this thread overflowed.
+    static OVERFLOWED: Cell<bool> = const { Cell::new(false) };
+}
+
+// Policy is given as `reject`, `saturate` or `abort`.
+impl FromStr for OverflowPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<OverflowPolicy, String> {
+        match s {
+            "reject" => Ok(OverflowPolicy::Reject),
+            "saturate" => Ok(OverflowPolicy::Saturate),
+            "abort" => Ok(OverflowPolicy::Abort),
+            _ => Err(format!("unknown overflow policy: {}", s)),
+        }
+    }
+}
+
+fn overflowed(policy: OverflowPolicy, saturated: Amount) -> Option<Amount> {
+    OVERFLOWED.with(|overflowed| overflowed.set(true));
+    match policy {
+        OverflowPolicy::Saturate => Some(saturated),
+        OverflowPolicy::Reject | OverflowPolicy::Abort => None,
+    }
+}
+
+// Adds amounts under the policy. `None` means the transaction must be rejected.
+pub fn add(policy: OverflowPolicy, a: Amount, b: Amount) -> Option<Amount> {
+    match a.checked_add(b) {
+        Some(sum) => Some(sum),
+        None if b.is_sign_negative() => overflowed(policy, Amount::MIN),
+        None => overflowed(policy, Amount::MAX),
+    }
+}
+
+// Subtracts amounts under the policy. `None` means the transaction must be rejected.
+pub fn sub(policy: OverflowPolicy, a: Amount, b: Amount) -> Option<Amount> {
+    match a.checked_sub(b) {
+        Some(difference) => Some(difference),
+        None if b.is_sign_negative() => overflowed(policy, Amount::MAX),
+        None => overflowed(policy, Amount::MIN),
+    }
+}
+
+// Adds to totals over all accounts, like books. A transaction is already applied to its account
+// then, so totals which don't fit are saturated instead of rejecting it.
+pub fn saturating_add(a: Amount, b: Amount) -> Amount {
+    match a.checked_add(b) {
+        Some(sum) => sum,
+        None if b.is_sign_negative() => Amount::MIN,
+        None => Amount::MAX,
+    }
+}
+
+// Returns if arithmetic overflowed since the last call on this thread.
+pub fn take_overflowed() -> bool {
+    OVERFLOWED.with(|overflowed| overflowed.replace(false))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_policy() {
+        assert_eq!("reject".parse(), Ok(OverflowPolicy::Reject));
+        assert_eq!("saturate".parse(), Ok(OverflowPolicy::Saturate));
+        assert_eq!("abort".parse(), Ok(OverflowPolicy::Abort));
+        assert!("ignore".parse::<OverflowPolicy>().is_err());
+    }
+
+    #[test]
+    fn test_add_without_overflow() {
+        take_overflowed();
+        assert_eq!(
+            add(OverflowPolicy::Reject, Amount::from(1), Amount::from(2)),
+            Some(Amount::from(3))
+        );
+        assert_eq!(
+            sub(OverflowPolicy::Reject, Amount::from(1), Amount::from(2)),
+            Some(Amount::from(-1))
+        );
+        assert!(!take_overflowed());
+    }
+
+    #[test]
+    fn test_saturate_overflow() {
+        take_overflowed();
+        let policy = OverflowPolicy::Saturate;
+        assert_eq!(add(policy, Amount::MAX, Amount::from(1)), Some(Amount::MAX));
+        assert_eq!(
+            add(policy, Amount::MIN, Amount::from(-1)),
+            Some(Amount::MIN)
+        );
+        assert_eq!(sub(policy, Amount::MIN, Amount::from(1)), Some(Amount::MIN));
+        assert!(take_overflowed());
+
+        assert_eq!(
+            add(OverflowPolicy::Abort, Amount::MAX, Amount::from(1)),
+            None
+        );
+        assert!(take_overflowed());
+    }
+
+    #[test]
+    fn test_saturating_totals() {
+        take_overflowed();
+        assert_eq!(saturating_add(Amount::MAX, Amount::from(1)), Amount::MAX);
+        assert_eq!(saturating_add(Amount::MIN, Amount::from(-1)), Amount::MIN);
+        assert_eq!(
+            saturating_add(Amount::from(1), Amount::from(2)),
+            Amount::from(3)
+        );
+        // Totals don't flag the transaction, its account didn't overflow.
+        assert!(!take_overflowed());
+    }
+
+    #[test]
+    fn test_overflow_is_rejected_by_default() {
+        take_overflowed();
+        assert_eq!(
+            add(OverflowPolicy::default(), Amount::MAX, Amount::from(1)),
+            None
+        );
+        assert!(take_overflowed());
+        assert!(!take_overflowed());
+    }
+}
@@ -0,0 +1,241 @@
+use crate::engine_config::Scope;
+use crate::source::{CsvSource, SourceError, TransactionSource};
+use crate::transaction::Transaction;
+
+use anyhow::Context;
+use rayon::prelude::*;
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use std::io::{self, BufRead, BufReader, Read};
+use std::vec;
+
+// Size of a chunk parsed by one thread, extended to the end of its last row.
+const CHUNK_BYTES: u64 = 256 * 1024;
+// Chunks read for every thread at once, so threads with quick chunks don't wait for others.
+const CHUNKS_PER_THREAD: usize = 4;
+
+// Rows of an input split at line ends, with the header prepended, so every chunk is a CSV document
+// of its own.
+struct Chunk {
+    data: Vec<u8>,
+    // Line of the first row of the chunk in the input.
+    first_line: u64,
+}
+
+struct Parsed {
+    result: Result<Transaction, SourceError>,
+    line: u64,
+    // Raw row, kept only for invalid ones.
+    record: Option<String>,
+}
+
+fn parse_chunk(chunk: &Chunk) -> Vec<Parsed> {
+    let mut source = CsvSource::new(chunk.data.as_slice());
+    let mut parsed = Vec::new();
+    while let Some(result) = source.next() {
+        let failed = matches!(result, Err(SourceError::Failed(_)));
+        parsed.push(Parsed {
+            record: result.is_err().then(|| source.record()).flatten(),
+            // The header is the first line of a chunk.
+            line: chunk.first_line + source.position().saturating_sub(2),
+            result,
+        });
+        if failed {
+            break;
+        }
+    }
+    parsed
+}
+
+// CSV source which parses chunks of rows in parallel and returns transactions in input order, so
+// deserialization is spread over threads while the engine applies transactions in a single one.
+// Rows are split at line ends, so quoted fields can't span lines.
+pub struct ParallelCsvSource<R: Read> {
+    input: BufReader<R>,
+    pool: ThreadPool,
+    chunk_bytes: u64,
+    header: Option<Vec<u8>>,
+    next_line: u64,
+    parsed: vec::IntoIter<Parsed>,
+    line: u64,
+    record: Option<String>,
+}
+
+impl<R: Read> ParallelCsvSource<R> {
+    pub fn new(input: R, threads: usize) -> anyhow::Result<ParallelCsvSource<R>> {
+        ParallelCsvSource::with_chunk_bytes(input, threads, CHUNK_BYTES)
+    }
+
+    pub fn with_chunk_bytes(
+        input: R,
+        threads: usize,
+        chunk_bytes: u64,
+    ) -> anyhow::Result<ParallelCsvSource<R>> {
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .thread_name(|index| format!("parser-{}", index))
+            .build()
+            .context("can not start parser threads")?;
+        Ok(ParallelCsvSource {
+            input: BufReader::new(input),
+            pool,
+            chunk_bytes,
+            header: None,
+            next_line: 2,
+            parsed: Vec::new().into_iter(),
+            line: 0,
+            record: None,
+        })
+    }
+
+    fn read_chunk(&mut self) -> io::Result<Option<Chunk>> {
+        let header = match &self.header {
+            Some(header) => header,
+            None => {
+                let mut header = Vec::new();
+                self.input.read_until(b'\n', &mut header)?;
+                if !header.is_empty() && !header.ends_with(b"\n") {
+                    header.push(b'\n');
+                }
+                self.header.insert(header)
+            }
+        };
+
+        let mut data = header.clone();
+        let start = data.len();
+        (&mut self.input)
+            .take(self.chunk_bytes)
+            .read_to_end(&mut data)?;
+        if data.len() == start {
+            return Ok(None);
+        }
+        if !data.ends_with(b"\n") {
+            self.input.read_until(b'\n', &mut data)?;
+        }
+
+        let first_line = self.next_line;
+        self.next_line += data[start..].iter().filter(|byte| **byte == b'\n').count() as u64;
+        Ok(Some(Chunk { data, first_line }))
+    }
+
+    // Reads chunks for all threads and parses them. Returns false at the end of input.
+    fn parse_chunks(&mut self) -> io::Result<bool> {
+        let mut chunks = Vec::new();
+        while chunks.len() < self.pool.current_num_threads() * CHUNKS_PER_THREAD {
+            match self.read_chunk()? {
+                None => break,
+                Some(chunk) => chunks.push(chunk),
+            }
+        }
+        if chunks.is_empty() {
+            return Ok(false);
+        }
+
+        // Threads of the pool parse by the config of the engine reading the source.
+        let scope = Scope::current();
+        let parsed: Vec<Vec<Parsed>> = self.pool.install(|| {
+            chunks
+                .par_iter()
+                .map(|chunk| {
+                    let _scope = scope.enter();
+                    parse_chunk(chunk)
+                })
+                .collect()
+        });
+        self.parsed = parsed.into_iter().flatten().collect::<Vec<_>>().into_iter();
+        Ok(true)
+    }
+}
+
+impl<R: Read> TransactionSource for ParallelCsvSource<R> {
+    fn next(&mut self) -> Option<Result<Transaction, SourceError>> {
+        // Chunks of blank lines have no records.
+        while self.parsed.len() == 0 {
+            match self.parse_chunks() {
+                Ok(false) => return None,
+                Ok(true) => {}
+                Err(error) => return Some(Err(SourceError::Failed(error.into()))),
+            }
+        }
+        let parsed = self.parsed.next()?;
+        self.line = parsed.line;
+        self.record = parsed.record;
+        Some(parsed.result)
+    }
+
+    fn position(&self) -> u64 {
+        self.line
+    }
+
+    fn unit(&self) -> &'static str {
+        "line"
+    }
+
+    fn record(&self) -> Option<String> {
+        self.record.clone()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bench::{self, Dataset};
+    use crate::engine::PaymentsEngine;
+
+    fn records(source: &mut dyn TransactionSource) -> Vec<(u64, String, Option<String>)> {
+        let mut records = Vec::new();
+        while let Some(result) = source.next() {
+            // Rows are only needed for invalid records.
+            let (parsed, record) = match result {
+                Ok(transaction) => (
+                    format!("{} {}", transaction.tx_type.name(), transaction.tx_id()),
+                    None,
+                ),
+                Err(error) => (error.to_string(), source.record()),
+            };
+            records.push((source.position(), parsed, record));
+        }
+        records
+    }
+
+    #[test]
+    fn test_same_records_as_csv_source() {
+        let input = "type, client, tx, amount\n\
+                     deposit, 1, 1, 10\n\
+                     withdrawal, 1, 2, 2.5\n\
+                     deposit, 1, x, 1\n\
+                     dispute, 1, 1,\n\
+                     deposit, 2\n\
+                     resolve, 1, 1,";
+        let expected = records(&mut CsvSource::new(input.as_bytes()));
+        assert_eq!(expected.len(), 6);
+        for chunk_bytes in [1, 7, 30, 1024] {
+            let mut source =
+                ParallelCsvSource::with_chunk_bytes(input.as_bytes(), 3, chunk_bytes).unwrap();
+            assert_eq!(records(&mut source), expected);
+        }
+        let mut source = ParallelCsvSource::new(&b""[..], 2).unwrap();
+        assert!(source.next().is_none());
+    }
+
+    #[test]
+    fn test_same_accounts_as_sequential_parsing() {
+        let mut input = Vec::new();
+        bench::generate(&mut input, &Dataset::new(5_000)).unwrap();
+
+        let mut sequential = PaymentsEngine::default();
+        assert!(sequential.process_csv(input.as_slice()).is_ok());
+        let mut parallel = PaymentsEngine::default();
+        let mut source =
+            ParallelCsvSource::with_chunk_bytes(input.as_slice(), 4, 4 * 1024).unwrap();
+        assert!(parallel.process_source(&mut source).is_ok());
+
+        assert_eq!(parallel.rows(), sequential.rows());
+        assert_eq!(parallel.accounts().len(), sequential.accounts().len());
+        for (client_id, account) in sequential.accounts() {
+            let other = &parallel.accounts()[client_id];
+            assert_eq!(other.available(), account.available());
+            assert_eq!(other.held(), account.held());
+            assert_eq!(other.is_locked(), account.is_locked());
+        }
+    }
+}
@@ -0,0 +1,146 @@
+use crate::source::{self, SourceError, TransactionSource};
+use crate::transaction::Transaction;
+
+use anyhow::Context;
+use parquet::file::reader::SerializedFileReader;
+use parquet::record::reader::RowIter;
+use parquet::record::{Field, Row};
+use serde_json::{Map, Value};
+use std::fs::File;
+
+// Columns have the same names as CSV columns. Decimal columns are converted to text, so amounts
+// are read exactly, and timestamp columns to Unix time in seconds.
+fn to_json(row: &Row) -> Value {
+    let mut object = Map::new();
+    for (name, field) in row.get_column_iter() {
+        let value = match field {
+            Field::Null => continue,
+            Field::TimestampMillis(millis) => Value::from(millis / 1000),
+            Field::TimestampMicros(micros) => Value::from(micros / 1_000_000),
+            field => field.to_json_value(),
+        };
+        object.insert(name.clone(), value);
+    }
+    Value::Object(object)
+}
+
+// Transactions from a Parquet file. Position is the row number.
+pub struct ParquetSource {
+    rows: RowIter<'static>,
+    row: u64,
+}
+
+impl ParquetSource {
+    pub fn new(file: File) -> anyhow::Result<ParquetSource> {
+        let reader = SerializedFileReader::new(file).context("can not read Parquet file")?;
+        Ok(ParquetSource {
+            rows: RowIter::from_file_into(Box::new(reader)),
+            row: 0,
+        })
+    }
+}
+
+impl TransactionSource for ParquetSource {
+    fn next(&mut self) -> Option<Result<Transaction, SourceError>> {
+        let row = self.rows.next()?;
+        self.row += 1;
+        Some(match row {
+            Ok(row) => source::from_json(self.row, &to_json(&row)),
+            Err(error) => Err(SourceError::Failed(error.into())),
+        })
+    }
+
+    fn position(&self) -> u64 {
+        self.row
+    }
+
+    fn unit(&self) -> &'static str {
+        "row"
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::amount::Amount;
+    use crate::transaction::TransactionType;
+    use parquet::data_type::{ByteArray, ByteArrayType, Int32Type, Int64Type};
+    use parquet::file::writer::SerializedFileWriter;
+    use parquet::schema::parser::parse_message_type;
+    use std::str::FromStr;
+    use std::sync::Arc;
+
+    fn write_transactions(path: &std::path::Path) {
+        let schema = Arc::new(
+            parse_message_type(
+                "message transactions {
+                    REQUIRED BYTE_ARRAY type (UTF8);
+                    REQUIRED INT32 client;
+                    REQUIRED INT32 tx;
+                    OPTIONAL INT64 amount (DECIMAL(18, 4));
+                }",
+            )
+            .unwrap(),
+        );
+        let mut writer =
+            SerializedFileWriter::new(File::create(path).unwrap(), schema, Default::default())
+                .unwrap();
+        let mut row_group = writer.next_row_group().unwrap();
+
+        let mut column = row_group.next_column().unwrap().unwrap();
+        let types: Vec<ByteArray> = ["deposit", "deposit", "dispute"]
+            .iter()
+            .map(|value| ByteArray::from(*value))
+            .collect();
+        column
+            .typed::<ByteArrayType>()
+            .write_batch(&types, None, None)
+            .unwrap();
+        column.close().unwrap();
+
+        for values in [[1, 2, 1], [1, 2, 1]] {
+            let mut column = row_group.next_column().unwrap().unwrap();
+            column
+                .typed::<Int32Type>()
+                .write_batch(&values, None, None)
+                .unwrap();
+            column.close().unwrap();
+        }
+
+        // Amount of the dispute is missing.
+        let mut column = row_group.next_column().unwrap().unwrap();
+        column
+            .typed::<Int64Type>()
+            .write_batch(&[12345, 20000], Some(&[1, 1, 0]), None)
+            .unwrap();
+        column.close().unwrap();
+
+        row_group.close().unwrap();
+        writer.close().unwrap();
+    }
+
+    #[test]
+    fn test_parquet_source() {
+        let path = std::env::temp_dir().join("coding_test_transactions.parquet");
+        write_transactions(&path);
+
+        let mut source = ParquetSource::new(File::open(&path).unwrap()).unwrap();
+        let mut transactions = Vec::new();
+        while let Some(transaction) = source.next() {
+            transactions.push((source.position(), transaction.unwrap()));
+        }
+
+        assert_eq!(transactions.len(), 3);
+        match &transactions[0].1.tx_type {
+            TransactionType::Deposit(deposit) => {
+                assert_eq!(deposit.client_id, 1);
+                assert_eq!(deposit.amount, Amount::from_str("1.2345").unwrap());
+            }
+            _ => panic!("expected a deposit"),
+        }
+        assert!(matches!(
+            transactions[2].1.tx_type,
+            TransactionType::Dispute(_)
+        ));
+    }
+}
@@ -0,0 +1,226 @@
+use crate::account::Account;
+use crate::decimal_format;
+use crate::output;
+use crate::overflow;
+use crate::transaction::TransactionType;
+
+use crate::amount::Amount;
+use log::info;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::sync::Arc;
+
+// Accounting period a transaction was applied in. It's shared between many stored transactions
+// so it's reference counted instead of cloning a string for every transaction.
+pub type Period = Arc<str>;
+
+const SECONDS_PER_DAY: u64 = 86_400;
+
+#[derive(Serialize)]
+pub struct PeriodTotals {
+    period: Period,
+    #[serde(serialize_with = "decimal_format::serialize")]
+    pub deposits: Amount,
+    pub deposit_count: u64,
+    #[serde(serialize_with = "decimal_format::serialize")]
+    pub withdrawals: Amount,
+    pub withdrawal_count: u64,
+    #[serde(serialize_with = "decimal_format::serialize")]
+    pub chargebacks: Amount,
+    pub chargeback_count: u64,
+}
+
+pub struct Periods {
+    // Period of transactions without a timestamp, the run id or a period given explicitly.
+    current: Period,
+    pub totals: BTreeMap<Period, PeriodTotals>,
+    // Day and month period of the latest timestamp, as consecutive rows are mostly from the same
+    // day.
+    month: Option<(u64, Period)>,
+}
+
+// Month of a unix time as `YYYY-MM`, converted from days to a civil date by Howard Hinnant's
+// algorithm.
+fn month_of(timestamp: u64) -> String {
+    let days = (timestamp / SECONDS_PER_DAY) as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}", year, month)
+}
+
+impl PeriodTotals {
+    fn new(period: Period) -> PeriodTotals {
+        PeriodTotals {
+            period,
+            deposits: Amount::from(0),
+            deposit_count: 0,
+            withdrawals: Amount::from(0),
+            withdrawal_count: 0,
+            chargebacks: Amount::from(0),
+            chargeback_count: 0,
+        }
+    }
+}
+
+impl Periods {
+    pub fn new(current: &str) -> Periods {
+        Periods {
+            current: Period::from(current),
+            totals: BTreeMap::new(),
+            month: None,
+        }
+    }
+
+    pub fn current(&self) -> &Period {
+        &self.current
+    }
+
+    // Period of a transaction: the month of its timestamp, as books are closed by calendar months,
+    // or the current period if it has no timestamp.
+    pub fn period_of(&mut self, timestamp: Option<u64>) -> Period {
+        let Some(timestamp) = timestamp else {
+            return self.current.clone();
+        };
+        let day = timestamp / SECONDS_PER_DAY;
+        match &self.month {
+            Some((month_day, period)) if *month_day == day => period.clone(),
+            _ => {
+                let period = Period::from(month_of(timestamp));
+                self.month = Some((day, period.clone()));
+                period
+            }
+        }
+    }
+
+    fn totals_of(&mut self, period: &Period) -> &mut PeriodTotals {
+        self.totals
+            .entry(period.clone())
+            .or_insert_with(|| PeriodTotals::new(period.clone()))
+    }
+
+    pub fn record_deposit(&mut self, period: &Period, amount: Amount) {
+        let totals = self.totals_of(period);
+        totals.deposits = overflow::saturating_add(totals.deposits, amount);
+        totals.deposit_count += 1;
+    }
+
+    pub fn record_withdrawal(&mut self, period: &Period, amount: Amount) {
+        let totals = self.totals_of(period);
+        totals.withdrawals = overflow::saturating_add(totals.withdrawals, amount);
+        totals.withdrawal_count += 1;
+    }
+
+    pub fn record_chargeback(&mut self, period: &Period, amount: Amount) {
+        let totals = self.totals_of(period);
+        totals.chargebacks = overflow::saturating_add(totals.chargebacks, amount);
+        totals.chargeback_count += 1;
+    }
+
+    pub fn merge(&mut self, other: Periods) {
+        for (period, totals) in other.totals {
+            let merged = self
+                .totals
+                .entry(period.clone())
+                .or_insert_with(|| PeriodTotals::new(period));
+            merged.deposits = overflow::saturating_add(merged.deposits, totals.deposits);
+            merged.deposit_count += totals.deposit_count;
+            merged.withdrawals = overflow::saturating_add(merged.withdrawals, totals.withdrawals);
+            merged.withdrawal_count += totals.withdrawal_count;
+            merged.chargebacks = overflow::saturating_add(merged.chargebacks, totals.chargebacks);
+            merged.chargeback_count += totals.chargeback_count;
+        }
+    }
+
+    // Accounts an already applied transaction to its period. Amount of a chargeback is taken from
+    // the charged back deposit stored in account, as it may be only a part of it.
+    pub fn record(&mut self, period: &Period, tx_type: &TransactionType, account: &Account) {
+        match tx_type {
+            TransactionType::Deposit(deposit) => self.record_deposit(period, deposit.amount),
+            TransactionType::Withdrawal(withdrawal) => {
+                self.record_withdrawal(period, withdrawal.amount)
+            }
+            TransactionType::Chargeback(chargeback) => {
+                if let Some(transaction) = account.find_transaction(chargeback.tx_id) {
+                    if let Some(deposited) = transaction.period() {
+                        info!(
+                            "tx: {} deposited in period {} charged back in period {}",
+                            chargeback.tx_id, deposited, period
+                        );
+                    }
+                    self.record_chargeback(period, transaction.charged_back_amount());
+                }
+            }
+            TransactionType::Dispute(_)
+            | TransactionType::Resolve(_)
+            | TransactionType::Representment(_)
+            | TransactionType::WriteOff(_)
+            | TransactionType::Lock(_)
+            | TransactionType::Unlock(_)
+            | TransactionType::Adjust(_)
+            | TransactionType::Annotate(_)
+            | TransactionType::Approve(_)
+            | TransactionType::Transfer(_) => {}
+        }
+    }
+
+    pub fn save_report(&self, file_path: &OsString) -> anyhow::Result<()> {
+        info!("saving period report to {:?}", file_path);
+
+        output::save_report(self.totals.values(), file_path, "period report")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_new_periods_have_no_totals() {
+        let periods = Periods::new("2022-05");
+        assert_eq!(periods.current().as_ref(), "2022-05");
+        assert!(periods.totals.is_empty());
+    }
+
+    #[test]
+    fn test_record_totals_for_current_period() {
+        let mut periods = Periods::new("2022-05");
+        let current = periods.period_of(None);
+        periods.record_deposit(&current, Amount::from_str("100.5").unwrap());
+        periods.record_deposit(&current, Amount::from_str("0.5").unwrap());
+        periods.record_withdrawal(&current, Amount::from_str("20").unwrap());
+        periods.record_chargeback(&current, Amount::from_str("0.5").unwrap());
+
+        let totals = periods.totals.get("2022-05").unwrap();
+        assert_eq!(totals.deposits, Amount::from_str("101").unwrap());
+        assert_eq!(totals.deposit_count, 2);
+        assert_eq!(totals.withdrawals, Amount::from_str("20").unwrap());
+        assert_eq!(totals.withdrawal_count, 1);
+        assert_eq!(totals.chargebacks, Amount::from_str("0.5").unwrap());
+        assert_eq!(totals.chargeback_count, 1);
+    }
+
+    #[test]
+    fn test_period_of_timestamp() {
+        let mut periods = Periods::new("run");
+        assert_eq!(periods.period_of(None).as_ref(), "run");
+        assert_eq!(periods.period_of(Some(0)).as_ref(), "1970-01");
+        // 2024-02-29 23:59:59 and 2024-03-01 00:00:00 UTC.
+        assert_eq!(periods.period_of(Some(1_709_251_199)).as_ref(), "2024-02");
+        assert_eq!(periods.period_of(Some(1_709_251_200)).as_ref(), "2024-03");
+        assert_eq!(periods.period_of(Some(1_709_251_201)).as_ref(), "2024-03");
+        // 1999-12-31 12:00:00 UTC.
+        assert_eq!(periods.period_of(Some(946_641_600)).as_ref(), "1999-12");
+    }
+}
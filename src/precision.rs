@@ -0,0 +1,118 @@
+use crate::amount::{Amount, FIXED_SCALE};
+
+use rust_decimal::{Decimal, RoundingStrategy};
+use std::cell::Cell;
+use std::str::FromStr;
+
+// Amounts are documented and reported with four decimal places. Input amounts with more of them
+// are rounded or rejected, so accounts never hold more precision than they report.
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub enum PrecisionPolicy {
+    // Rounded to four decimal places, half to even.
+    #[default]
+    Round,
+    // The record is rejected as invalid.
+    Reject,
+}
+
+thread_local! {
+    // Policy of the engine reading input on this thread, see `EngineConfig::enter`.
+    static POLICY: Cell<PrecisionPolicy> = const { Cell::new(PrecisionPolicy::Round) };
+}
+
+impl PrecisionPolicy {
+    // Policy used when amounts are read on this thread.
+    pub fn current() -> PrecisionPolicy {
+        POLICY.with(Cell::get)
+    }
+
+    // Makes the policy used when amounts are read on this thread and returns the previous one.
+    pub(crate) fn replace_current(self) -> PrecisionPolicy {
+        POLICY.with(|policy| policy.replace(self))
+    }
+}
+
+// Policy is given as `round` or `reject`.
+impl FromStr for PrecisionPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<PrecisionPolicy, String> {
+        match s {
+            "round" => Ok(PrecisionPolicy::Round),
+            "reject" => Ok(PrecisionPolicy::Reject),
+            _ => Err(format!("unknown precision policy: {}", s)),
+        }
+    }
+}
+
+// Parses an input amount by the policy of this thread.
+pub fn parse(text: &str) -> Result<Amount, String> {
+    parse_with(text, PrecisionPolicy::current())
+}
+
+// Trailing zeros don't count as decimal places, so `1.50000` is read as it is. Values are quoted in
+// errors, so the field they came from can be found.
+pub fn parse_with(text: &str, policy: PrecisionPolicy) -> Result<Amount, String> {
+    let text = text.trim();
+    let invalid = || format!("invalid amount \"{}\"", text);
+    let places = text
+        .split_once('.')
+        .map_or(0, |(_, fraction)| fraction.trim_end_matches('0').len());
+    if places <= FIXED_SCALE as usize {
+        return Amount::from_str(text).map_err(|_| invalid());
+    }
+
+    match policy {
+        PrecisionPolicy::Reject => Err(format!(
+            "amount \"{}\" has more than {} decimal places",
+            text, FIXED_SCALE
+        )),
+        PrecisionPolicy::Round => {
+            let rounded = Decimal::from_str(text)
+                .map_err(|_| invalid())?
+                .round_dp_with_strategy(FIXED_SCALE, RoundingStrategy::MidpointNearestEven);
+            Amount::from_str(&rounded.to_string()).map_err(|_| invalid())
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn amount(text: &str) -> Amount {
+        Amount::from_str(text).unwrap()
+    }
+
+    #[test]
+    fn test_parse_precision_policy() {
+        assert_eq!("round".parse(), Ok(PrecisionPolicy::Round));
+        assert_eq!("reject".parse(), Ok(PrecisionPolicy::Reject));
+        assert!("truncate".parse::<PrecisionPolicy>().is_err());
+    }
+
+    #[test]
+    fn test_round_half_even() {
+        let round = |text| parse_with(text, PrecisionPolicy::Round);
+        assert_eq!(round("1.5"), Ok(amount("1.5")));
+        assert_eq!(round("1.50000"), Ok(amount("1.5")));
+        assert_eq!(round("1.00005"), Ok(amount("1.0000")));
+        assert_eq!(round("1.00015"), Ok(amount("1.0002")));
+        assert_eq!(round("1.000151"), Ok(amount("1.0002")));
+        assert_eq!(round("-2.99995"), Ok(amount("-3")));
+        assert!(round("1.x").is_err());
+    }
+
+    #[test]
+    fn test_reject() {
+        let reject = |text| parse_with(text, PrecisionPolicy::Reject);
+        assert_eq!(reject("1.2345"), Ok(amount("1.2345")));
+        assert_eq!(reject("1.23450"), Ok(amount("1.2345")));
+        assert_eq!(
+            reject("1.23456"),
+            Err(String::from(
+                "amount \"1.23456\" has more than 4 decimal places"
+            ))
+        );
+    }
+}
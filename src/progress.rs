@@ -0,0 +1,108 @@
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressState, ProgressStyle};
+use std::fmt::Write;
+use std::fs::File;
+use std::io::{self, IsTerminal, Read};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+const TEMPLATE: &str =
+    "[{elapsed_precise}] {wide_bar} {bytes}/{total_bytes} {rows_per_sec} ETA {eta}";
+
+// Progress of reading an input file, drawn on standard error. Rows are counted as lines read, so
+// the rate is exact for CSV and NDJSON input.
+pub struct Progress {
+    bar: ProgressBar,
+    lines: Arc<AtomicU64>,
+}
+
+impl Progress {
+    pub fn new(total_bytes: u64) -> Progress {
+        let lines = Arc::new(AtomicU64::new(0));
+        let counted = Arc::clone(&lines);
+        let style = ProgressStyle::with_template(TEMPLATE)
+            .unwrap_or_else(|_| ProgressStyle::default_bar())
+            .with_key(
+                "rows_per_sec",
+                move |state: &ProgressState, w: &mut dyn Write| {
+                    let seconds = state.elapsed().as_secs_f64();
+                    let rows = counted.load(Ordering::Relaxed) as f64;
+                    let rate = if seconds > 0.0 { rows / seconds } else { 0.0 };
+                    let _ = write!(w, "{:.0} rows/s", rate);
+                },
+            );
+        let bar = ProgressBar::with_draw_target(Some(total_bytes), ProgressDrawTarget::stderr())
+            .with_style(style);
+
+        Progress { bar, lines }
+    }
+
+    // Progress of the whole file, which is hidden if nobody would see it: when standard error
+    // isn't a terminal, or when output goes to the terminal and would be mixed with the bar.
+    pub fn for_file(file: &File) -> io::Result<Progress> {
+        let progress = Progress::new(file.metadata()?.len());
+        if !io::stderr().is_terminal() || io::stdout().is_terminal() {
+            progress.bar.set_draw_target(ProgressDrawTarget::hidden());
+        }
+        Ok(progress)
+    }
+
+    pub fn wrap<R: Read>(&self, input: R) -> ProgressReader<R> {
+        ProgressReader {
+            input,
+            bar: self.bar.clone(),
+            lines: Arc::clone(&self.lines),
+        }
+    }
+
+    pub fn lines(&self) -> u64 {
+        self.lines.load(Ordering::Relaxed)
+    }
+
+    pub fn position(&self) -> u64 {
+        self.bar.position()
+    }
+
+    pub fn finish(&self) {
+        self.bar.finish_and_clear();
+    }
+}
+
+// Reader which advances the progress by bytes and lines read.
+pub struct ProgressReader<R> {
+    input: R,
+    bar: ProgressBar,
+    lines: Arc<AtomicU64>,
+}
+
+impl<R: Read> Read for ProgressReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.input.read(buf)?;
+        let lines = buf[..read].iter().filter(|byte| **byte == b'\n').count();
+        self.lines.fetch_add(lines as u64, Ordering::Relaxed);
+        self.bar.inc(read as u64);
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_progress_reader() {
+        let input = "type,client,tx,amount\ndeposit,1,1,10\ndeposit,1,2,5\n";
+        let progress = Progress::new(input.len() as u64);
+        progress.bar.set_draw_target(ProgressDrawTarget::hidden());
+
+        let mut read = String::new();
+        progress
+            .wrap(input.as_bytes())
+            .read_to_string(&mut read)
+            .unwrap();
+        progress.finish();
+
+        assert_eq!(read, input);
+        assert_eq!(progress.position(), input.len() as u64);
+        assert_eq!(progress.lines(), 3);
+    }
+}
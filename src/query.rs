@@ -0,0 +1,212 @@
+use crate::account::Account;
+use crate::amount::Amount;
+use crate::store::AccountStore;
+
+use std::collections::HashMap;
+use std::str::FromStr;
+
+// Ad-hoc queries of stored accounts for operators, for stores which can't be queried with SQL
+// (like the archive). Stored accounts are read one by one, so a query of all accounts reads the
+// whole store.
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Balance {
+    Available,
+    Held,
+    Total,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Comparison {
+    Less,
+    LessOrEqual,
+    Equal,
+    GreaterOrEqual,
+    Greater,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum Condition {
+    Client(u16),
+    Locked(bool),
+    Balance(Balance, Comparison, Amount),
+}
+
+impl Condition {
+    fn matches(&self, account: &Account) -> bool {
+        match self {
+            Condition::Client(client_id) => account.id() == *client_id,
+            Condition::Locked(locked) => account.is_locked() == *locked,
+            Condition::Balance(balance, comparison, amount) => {
+                let value = match balance {
+                    Balance::Available => account.available(),
+                    Balance::Held => account.held(),
+                    Balance::Total => account.total(),
+                };
+                match comparison {
+                    Comparison::Less => value < *amount,
+                    Comparison::LessOrEqual => value <= *amount,
+                    Comparison::Equal => value == *amount,
+                    Comparison::GreaterOrEqual => value >= *amount,
+                    Comparison::Greater => value > *amount,
+                }
+            }
+        }
+    }
+}
+
+// Condition is given as `client=<id>`, `locked=<true|false>` or as a balance (`available`, `held`
+// or `total`) compared with an amount by `<`, `<=`, `=`, `>=` or `>`, for example `held>=100`.
+impl FromStr for Condition {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Condition, String> {
+        let invalid = || format!("invalid query condition: {}", s);
+        let at = s.find(['<', '>', '=']).ok_or_else(invalid)?;
+        let (field, rest) = (s[..at].trim(), &s[at..]);
+        let (comparison, value) = if let Some(value) = rest.strip_prefix("<=") {
+            (Comparison::LessOrEqual, value)
+        } else if let Some(value) = rest.strip_prefix(">=") {
+            (Comparison::GreaterOrEqual, value)
+        } else if let Some(value) = rest.strip_prefix('<') {
+            (Comparison::Less, value)
+        } else if let Some(value) = rest.strip_prefix('>') {
+            (Comparison::Greater, value)
+        } else {
+            (Comparison::Equal, &rest[1..])
+        };
+        let value = value.trim();
+
+        let balance = match field {
+            "client" if comparison == Comparison::Equal => {
+                return value.parse().map(Condition::Client).map_err(|_| invalid());
+            }
+            "locked" if comparison == Comparison::Equal => {
+                return value.parse().map(Condition::Locked).map_err(|_| invalid());
+            }
+            "available" => Balance::Available,
+            "held" => Balance::Held,
+            "total" => Balance::Total,
+            _ => return Err(invalid()),
+        };
+        let amount = Amount::from_str(value).map_err(|_| invalid())?;
+        Ok(Condition::Balance(balance, comparison, amount))
+    }
+}
+
+// Accounts matching all conditions, all stored accounts if there is none.
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct AccountQuery {
+    pub conditions: Vec<Condition>,
+}
+
+impl AccountQuery {
+    pub fn matches(&self, account: &Account) -> bool {
+        self.conditions
+            .iter()
+            .all(|condition| condition.matches(account))
+    }
+
+    // Stored accounts matching the query. If the query is for a client, only its account is read.
+    pub fn select(&self, store: &dyn AccountStore) -> anyhow::Result<HashMap<u16, Account>> {
+        let client = self
+            .conditions
+            .iter()
+            .find_map(|condition| match condition {
+                Condition::Client(client_id) => Some(*client_id),
+                _ => None,
+            });
+        let clients = match client {
+            Some(client_id) => vec![client_id],
+            None => store.clients(),
+        };
+
+        let mut selected = HashMap::new();
+        for client_id in clients {
+            if let Some(account) = store.get(client_id)? {
+                if self.matches(&account) {
+                    selected.insert(client_id, account);
+                }
+            }
+        }
+
+        Ok(selected)
+    }
+}
+
+// Query is given as conditions separated by commas, for example `locked=true,available<0`.
+impl FromStr for AccountQuery {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<AccountQuery, String> {
+        if s.trim().is_empty() {
+            return Ok(AccountQuery::default());
+        }
+        let conditions = s
+            .split(',')
+            .map(|condition| condition.trim().parse())
+            .collect::<Result<_, _>>()?;
+        Ok(AccountQuery { conditions })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::account::BalanceSnapshot;
+    use crate::archive::Archive;
+
+    #[test]
+    fn test_parse_query() {
+        assert_eq!(
+            "client=7, locked=true, held>=1.5".parse::<AccountQuery>(),
+            Ok(AccountQuery {
+                conditions: vec![
+                    Condition::Client(7),
+                    Condition::Locked(true),
+                    Condition::Balance(
+                        Balance::Held,
+                        Comparison::GreaterOrEqual,
+                        Amount::from_str("1.5").unwrap()
+                    ),
+                ]
+            })
+        );
+        assert_eq!("".parse::<AccountQuery>(), Ok(AccountQuery::default()));
+        assert!("client>7".parse::<AccountQuery>().is_err());
+        assert!("locked=yes".parse::<AccountQuery>().is_err());
+        assert!("balance<0".parse::<AccountQuery>().is_err());
+        assert!("total".parse::<AccountQuery>().is_err());
+    }
+
+    #[test]
+    fn test_select_from_archive() {
+        let dir = std::env::temp_dir().join("coding_test_query_archive");
+        let _ = std::fs::remove_dir_all(&dir);
+        let mut archive = Archive::open(&dir.into_os_string()).unwrap();
+        for (client_id, available, locked) in [(1, 5, false), (2, -3, true), (3, -1, false)] {
+            let mut account = Account::new(client_id);
+            account
+                .restore_balances(BalanceSnapshot {
+                    available: Amount::from(available),
+                    held: Amount::from(0),
+                    total: Amount::from(available),
+                    locked,
+                })
+                .unwrap();
+            archive.put(&account).unwrap();
+        }
+
+        let query: AccountQuery = "available<0".parse().unwrap();
+        let mut selected: Vec<u16> = query.select(&archive).unwrap().into_keys().collect();
+        selected.sort_unstable();
+        assert_eq!(selected, vec![2, 3]);
+
+        let query: AccountQuery = "client=2,locked=false".parse().unwrap();
+        assert!(query.select(&archive).unwrap().is_empty());
+        let query: AccountQuery = "client=9".parse().unwrap();
+        assert!(query.select(&archive).unwrap().is_empty());
+        assert_eq!(AccountQuery::default().select(&archive).unwrap().len(), 3);
+        assert_eq!(archive.len(), 3);
+    }
+}
@@ -0,0 +1,283 @@
+use crate::output;
+use crate::transaction::Transaction;
+
+use anyhow::Context;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::mem;
+use std::path::Path;
+
+// Code of a record which can't be deserialized into a transaction.
+pub const INVALID_RECORD: &str = "invalid_record";
+// Code of a transaction whose id was already used by another one, when duplicates are checked.
+pub const DUPLICATE_TRANSACTION: &str = "duplicate_transaction";
+// Code of a transaction of a client without an account which it can't open.
+pub const NO_ACCOUNT: &str = "no_account";
+// Code of an admin transaction without an operator or a reason.
+pub const MISSING_ADMIN_FIELDS: &str = "missing_admin_fields";
+// Code of an admin transaction of an unknown operator or of an operator without a required role.
+pub const UNAUTHORIZED: &str = "unauthorized";
+// Code of an approval of an adjustment which isn't pending, which was submitted by the same
+// operator or which needs a higher role.
+pub const APPROVAL_REJECTED: &str = "approval_rejected";
+// Code of a transaction which wasn't processed as a store of accounts was unavailable. Unlike
+// rejections by account logic it may be submitted again.
+pub const STORE_UNAVAILABLE: &str = "store_unavailable";
+
+// A skipped or failed input record. Transaction fields are empty if the record couldn't be
+// deserialized.
+#[derive(Serialize, Clone, PartialEq, Debug)]
+pub struct Rejection {
+    // Input file of the record, given only when several input files are processed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file: Option<String>,
+    // Position of the record in input, a line number for CSV and NDJSON input.
+    pub line: u64,
+    #[serde(rename = "type")]
+    pub tx_type: Option<&'static str>,
+    pub client: Option<u16>,
+    pub tx: Option<u32>,
+    pub code: &'static str,
+    pub reason: String,
+    // Raw text of a record which couldn't be deserialized.
+    pub record: Option<String>,
+}
+
+impl Rejection {
+    pub fn of(line: u64, transaction: &Transaction, code: &'static str, reason: String) -> Self {
+        Rejection {
+            file: None,
+            line,
+            tx_type: Some(transaction.tx_type.name()),
+            client: Some(transaction.client_id()),
+            tx: Some(transaction.tx_id()),
+            code,
+            reason,
+            record: None,
+        }
+    }
+
+    pub fn invalid(line: u64, reason: String, record: Option<String>) -> Self {
+        Rejection {
+            file: None,
+            line,
+            tx_type: None,
+            client: None,
+            tx: None,
+            code: INVALID_RECORD,
+            reason,
+            record,
+        }
+    }
+}
+
+enum Writer {
+    Csv(Box<csv::Writer<Box<dyn Write + Send>>>),
+    Jsonl(BufWriter<Box<dyn Write + Send>>),
+}
+
+// Report of rejected transactions, so they can be reconciled with input instead of only being
+// logged.
+pub struct RejectionReport {
+    writer: Writer,
+}
+
+impl RejectionReport {
+    // Format of the report is guessed from its extension, `jsonl` or `ndjson` for one JSON object
+    // per line, CSV otherwise.
+    pub fn create(file_path: &OsString) -> anyhow::Result<RejectionReport> {
+        let file = File::create(file_path)
+            .with_context(|| format!("can not create rejections report {:?}", file_path))?;
+        let file: Box<dyn Write + Send> = Box::new(file);
+        let writer = match Path::new(file_path)
+            .extension()
+            .and_then(|ext| ext.to_str())
+        {
+            Some("jsonl") | Some("ndjson") => Writer::Jsonl(BufWriter::new(file)),
+            _ => Writer::Csv(Box::new(output::csv_writer(file))),
+        };
+        Ok(RejectionReport { writer })
+    }
+
+    // Report in CSV format written to standard output.
+    pub fn stdout() -> RejectionReport {
+        let stdout: Box<dyn Write + Send> = Box::new(io::stdout());
+        RejectionReport {
+            writer: Writer::Csv(Box::new(output::csv_writer(stdout))),
+        }
+    }
+
+    pub fn record(&mut self, rejection: &Rejection) -> anyhow::Result<()> {
+        match &mut self.writer {
+            Writer::Csv(writer) => writer.serialize(rejection)?,
+            Writer::Jsonl(writer) => {
+                serde_json::to_writer(&mut *writer, rejection)?;
+                writer.write_all(b"\n")?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> anyhow::Result<()> {
+        match &mut self.writer {
+            Writer::Csv(writer) => writer.flush()?,
+            Writer::Jsonl(writer) => writer.flush()?,
+        }
+        Ok(())
+    }
+}
+
+// Rejected records of a run counted by their code, and written into a report if there is one.
+#[derive(Default)]
+pub struct Rejections {
+    report: Option<RejectionReport>,
+    counts: BTreeMap<&'static str, u64>,
+    input_file: Option<String>,
+    // Rejections recorded since they were last taken, if they are kept.
+    recent: Option<Vec<Rejection>>,
+}
+
+impl Rejections {
+    pub fn set_report(&mut self, report: RejectionReport) {
+        self.report = Some(report);
+    }
+
+    // Input file rejections are attributed to from now on. It's kept after the file is processed,
+    // so all rows of a CSV report have the same columns.
+    pub fn set_input_file(&mut self, input_file: String) {
+        self.input_file = Some(input_file);
+    }
+
+    pub fn input_file(&self) -> Option<&str> {
+        self.input_file.as_deref()
+    }
+
+    // Keeps rejections until they are taken, so a server can report them per transaction.
+    pub fn keep_recent(&mut self) {
+        self.recent.get_or_insert_with(Vec::new);
+    }
+
+    pub fn take_recent(&mut self) -> Vec<Rejection> {
+        self.recent.as_mut().map(mem::take).unwrap_or_default()
+    }
+
+    pub fn record(&mut self, rejection: &Rejection) -> anyhow::Result<()> {
+        self.count(rejection.code, 1);
+        if let Some(recent) = self.recent.as_mut() {
+            recent.push(rejection.clone());
+        }
+        match (self.report.as_mut(), &self.input_file) {
+            (None, _) => {}
+            (Some(report), None) => report.record(rejection)?,
+            (Some(report), Some(input_file)) => report.record(&Rejection {
+                file: Some(input_file.clone()),
+                ..rejection.clone()
+            })?,
+        }
+        Ok(())
+    }
+
+    // Counts rejections which aren't written into the report.
+    pub fn count(&mut self, code: &'static str, rejections: u64) {
+        if rejections > 0 {
+            *self.counts.entry(code).or_default() += rejections;
+        }
+    }
+
+    pub fn counts(&self) -> &BTreeMap<&'static str, u64> {
+        &self.counts
+    }
+
+    pub fn total(&self) -> u64 {
+        self.counts.values().sum()
+    }
+
+    // Takes over counts of rejections of another engine.
+    pub fn merge(&mut self, other: Rejections) {
+        for (code, rejections) in other.counts {
+            self.count(code, rejections);
+        }
+    }
+
+    pub fn flush(&mut self) -> anyhow::Result<()> {
+        match self.report.as_mut() {
+            None => Ok(()),
+            Some(report) => report.flush(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::engine::PaymentsEngine;
+
+    fn process(file_name: &str) -> String {
+        let path = std::env::temp_dir().join(file_name).into_os_string();
+        let mut engine = PaymentsEngine::default();
+        engine.set_rejections(RejectionReport::create(&path).unwrap());
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,10\n\
+                     withdrawal,1,2,20\n\
+                     deposit,1,x,1\n\
+                     dispute,2,9,\n\
+                     dispute,1,7,\n";
+        assert!(engine.process_csv(input.as_bytes()).is_ok());
+        std::fs::read_to_string(&path).unwrap()
+    }
+
+    #[test]
+    fn test_csv_report() {
+        let report = process("coding_test_rejections.csv");
+        let lines: Vec<&str> = report.lines().collect();
+        assert_eq!(lines[0], "line,type,client,tx,code,reason,record");
+        assert_eq!(
+            lines[1],
+            "3,withdrawal,1,2,insufficient_funds,insufficient funds,"
+        );
+        assert_eq!(
+            lines[2],
+            "4,,,,invalid_record,\"field `tx`: invalid type: string \"\"x\"\", expected u32\",\"deposit,1,x,1\""
+        );
+        assert!(lines[3].starts_with("5,dispute,2,9,no_account,"));
+        assert_eq!(
+            lines[4],
+            "6,dispute,1,7,unknown_transaction,transaction is not found,"
+        );
+        assert_eq!(lines.len(), 5);
+    }
+
+    #[test]
+    fn test_report_of_several_files() {
+        let path = std::env::temp_dir().join("coding_test_rejections_of_files.csv");
+        let path = path.into_os_string();
+        let mut engine = PaymentsEngine::default();
+        engine.set_rejections(RejectionReport::create(&path).unwrap());
+        engine.set_input_file(&OsString::from("part-1.csv"));
+        let input = "type,client,tx,amount\ndeposit,1,1,10\nwithdrawal,1,2,20\n";
+        assert!(engine.process_csv(input.as_bytes()).is_ok());
+        engine.set_input_file(&OsString::from("part-2.csv"));
+        let input = "type,client,tx,amount\ndispute,1,7,\n";
+        assert!(engine.process_csv(input.as_bytes()).is_ok());
+
+        let report = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = report.lines().collect();
+        assert_eq!(lines[0], "file,line,type,client,tx,code,reason,record");
+        assert!(lines[1].starts_with("part-1.csv,3,withdrawal,1,2,insufficient_funds,"));
+        assert!(lines[2].starts_with("part-2.csv,2,dispute,1,7,unknown_transaction,"));
+        assert_eq!(lines.len(), 3);
+    }
+
+    #[test]
+    fn test_jsonl_report() {
+        let report = process("coding_test_rejections.jsonl");
+        let first: serde_json::Value =
+            serde_json::from_str(report.lines().next().unwrap()).unwrap();
+        assert_eq!(first["line"], 3);
+        assert_eq!(first["code"], "insufficient_funds");
+        assert_eq!(report.lines().count(), 4);
+    }
+}
@@ -0,0 +1,217 @@
+use crate::source::{SourceError, TransactionSource};
+use crate::transaction::{Transaction, TransactionType};
+
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::str::FromStr;
+
+// What rows are sorted by.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ReorderKey {
+    // Rows without a timestamp keep the timestamp of the row before them.
+    Timestamp,
+    // A dispute, resolve, chargeback or representment goes right after the transaction it refers
+    // to, as they share the id.
+    TxId,
+}
+
+// Feeds whose records arrive slightly out of order are sorted within a window of rows before they
+// are applied, so for example a dispute which arrives just before its deposit isn't rejected.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Reorder {
+    pub key: ReorderKey,
+    pub window: usize,
+}
+
+// Reordering is given as a key and a window of rows, for example `timestamp:100` or `tx:100`.
+impl FromStr for Reorder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Reorder, String> {
+        let (key, window) = s
+            .split_once(':')
+            .ok_or_else(|| format!("reordering has no window: {}", s))?;
+        let key = match key {
+            "timestamp" => ReorderKey::Timestamp,
+            "tx" => ReorderKey::TxId,
+            _ => return Err(format!("unknown reordering key: {}", key)),
+        };
+        match window.parse() {
+            Ok(window) if window > 0 => Ok(Reorder { key, window }),
+            _ => Err(format!("invalid reordering window: {}", window)),
+        }
+    }
+}
+
+struct Buffered {
+    key: (u64, bool),
+    position: u64,
+    transaction: Transaction,
+}
+
+impl PartialEq for Buffered {
+    fn eq(&self, other: &Buffered) -> bool {
+        (self.key, self.position) == (other.key, other.position)
+    }
+}
+
+impl Eq for Buffered {}
+
+impl PartialOrd for Buffered {
+    fn partial_cmp(&self, other: &Buffered) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+// Rows with the same key keep their order in the source.
+impl Ord for Buffered {
+    fn cmp(&self, other: &Buffered) -> std::cmp::Ordering {
+        (self.key, self.position).cmp(&(other.key, other.position))
+    }
+}
+
+// Source which reads up to a window of rows ahead and returns the lowest of them, so a row is moved
+// less than the window earlier than where it was. Invalid records aren't buffered, they are
+// returned as soon as they are read.
+pub struct ReorderingSource<'a, S: TransactionSource + ?Sized> {
+    source: &'a mut S,
+    reorder: Reorder,
+    buffer: BinaryHeap<Reverse<Buffered>>,
+    last_timestamp: u64,
+    position: u64,
+}
+
+impl<'a, S: TransactionSource + ?Sized> ReorderingSource<'a, S> {
+    pub fn new(source: &'a mut S, reorder: Reorder) -> ReorderingSource<'a, S> {
+        ReorderingSource {
+            source,
+            reorder,
+            buffer: BinaryHeap::with_capacity(reorder.window),
+            last_timestamp: 0,
+            position: 0,
+        }
+    }
+
+    fn key(&mut self, transaction: &Transaction) -> (u64, bool) {
+        match self.reorder.key {
+            ReorderKey::Timestamp => {
+                if let Some(timestamp) = transaction.timestamp {
+                    self.last_timestamp = timestamp;
+                }
+                (self.last_timestamp, false)
+            }
+            ReorderKey::TxId => {
+                let refers = matches!(
+                    transaction.tx_type,
+                    TransactionType::Dispute(_)
+                        | TransactionType::Resolve(_)
+                        | TransactionType::Chargeback(_)
+                        | TransactionType::Representment(_)
+                );
+                (u64::from(transaction.tx_id()), refers)
+            }
+        }
+    }
+}
+
+impl<S: TransactionSource + ?Sized> TransactionSource for ReorderingSource<'_, S> {
+    fn next(&mut self) -> Option<Result<Transaction, SourceError>> {
+        while self.buffer.len() < self.reorder.window {
+            match self.source.next() {
+                None => break,
+                Some(Ok(transaction)) => {
+                    let key = self.key(&transaction);
+                    self.buffer.push(Reverse(Buffered {
+                        key,
+                        position: self.source.position(),
+                        transaction,
+                    }));
+                }
+                Some(Err(error)) => {
+                    self.position = self.source.position();
+                    return Some(Err(error));
+                }
+            }
+        }
+
+        self.buffer.pop().map(|Reverse(buffered)| {
+            self.position = buffered.position;
+            Ok(buffered.transaction)
+        })
+    }
+
+    fn position(&self) -> u64 {
+        self.position
+    }
+
+    fn unit(&self) -> &'static str {
+        self.source.unit()
+    }
+
+    fn record(&self) -> Option<String> {
+        self.source.record()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::source::CsvSource;
+
+    fn reordered(input: &str, reorder: &str) -> Vec<(u64, u32)> {
+        let mut source = CsvSource::new(input.as_bytes());
+        let mut source = ReorderingSource::new(&mut source, reorder.parse().unwrap());
+        let mut rows = Vec::new();
+        while let Some(result) = source.next() {
+            if let Ok(transaction) = result {
+                rows.push((source.position(), transaction.tx_id()));
+            }
+        }
+        rows
+    }
+
+    #[test]
+    fn test_parse_reorder() {
+        assert_eq!(
+            "tx:10".parse::<Reorder>(),
+            Ok(Reorder {
+                key: ReorderKey::TxId,
+                window: 10
+            })
+        );
+        assert!("timestamp".parse::<Reorder>().is_err());
+        assert!("timestamp:0".parse::<Reorder>().is_err());
+        assert!("amount:10".parse::<Reorder>().is_err());
+    }
+
+    #[test]
+    fn test_reorder_by_timestamp() {
+        let input = "type,client,tx,amount,timestamp\n\
+                     deposit,1,1,10,100\n\
+                     dispute,1,2,,300\n\
+                     deposit,1,2,5,200\n\
+                     withdrawal,1,3,1,\n\
+                     deposit,1,4,1,50\n";
+        assert_eq!(
+            reordered(input, "timestamp:2"),
+            vec![(2, 1), (4, 2), (5, 3), (6, 4), (3, 2)]
+        );
+        assert_eq!(
+            reordered(input, "timestamp:10"),
+            vec![(6, 4), (2, 1), (4, 2), (5, 3), (3, 2)]
+        );
+    }
+
+    #[test]
+    fn test_reorder_by_tx_id() {
+        let input = "type,client,tx,amount\n\
+                     dispute,1,2,\n\
+                     deposit,1,1,10\n\
+                     deposit,1,2,5\n\
+                     resolve,1,2,\n";
+        assert_eq!(
+            reordered(input, "tx:3"),
+            vec![(3, 1), (4, 2), (2, 2), (5, 2)]
+        );
+    }
+}
@@ -0,0 +1,252 @@
+use crate::checksum::{HashingWriter, OutputDigest};
+use crate::decimal_format;
+use crate::engine::PaymentsEngine;
+use crate::output;
+use crate::overflow;
+
+use crate::amount::Amount;
+use anyhow::Context;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+pub const SUCCEEDED: &str = "succeeded";
+pub const FAILED: &str = "failed";
+
+// Machine-readable summary of processing one input, so orchestration systems can branch on the
+// result of a run instead of scraping logs. A failed run has only its error and duration.
+#[derive(Serialize)]
+pub struct RunReport {
+    pub input: String,
+    pub period: String,
+    pub status: &'static str,
+    pub error: Option<String>,
+    // Seconds since Unix epoch.
+    pub started_at: u64,
+    pub duration_ms: u64,
+    // Records read from input, including invalid and rejected ones.
+    pub rows: u64,
+    // Valid records by their type.
+    pub rows_by_type: BTreeMap<&'static str, u64>,
+    pub rows_per_sec: f64,
+    pub applied: u64,
+    pub rejected: u64,
+    pub rejects_by_code: BTreeMap<&'static str, u64>,
+    // Checksum of the input file, or of all input files one after another, none for standard
+    // input and database tables.
+    pub input_sha256: Option<String>,
+    // Checksum of accounts output, none for commands which don't write accounts.
+    pub output_sha256: Option<String>,
+    pub accounts: usize,
+    pub locked_accounts: usize,
+    // Funds held by all accounts.
+    #[serde(serialize_with = "decimal_format::serialize")]
+    pub held: Amount,
+    // Hash of resulting accounts in CSV form. It's the same for the same state regardless of
+    // the output format, so runs can be compared by it.
+    pub snapshot_id: Option<String>,
+    #[serde(skip)]
+    started: Instant,
+}
+
+// Several files are hashed like they were concatenated.
+fn files_sha256(file_paths: &[OsString]) -> anyhow::Result<String> {
+    let mut sha256 = Sha256::new();
+    for file_path in file_paths {
+        let mut file =
+            File::open(file_path).with_context(|| format!("can not open {:?}", file_path))?;
+        io::copy(&mut file, &mut sha256)
+            .with_context(|| format!("can not read {:?}", file_path))?;
+    }
+    Ok(hex::encode(sha256.finalize()))
+}
+
+impl RunReport {
+    // Starts a report of a run, which is timed from now on.
+    pub fn start(input: String, period: String) -> RunReport {
+        RunReport {
+            input,
+            period,
+            status: FAILED,
+            error: None,
+            started_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |since| since.as_secs()),
+            duration_ms: 0,
+            rows: 0,
+            rows_by_type: BTreeMap::new(),
+            rows_per_sec: 0.0,
+            applied: 0,
+            rejected: 0,
+            rejects_by_code: BTreeMap::new(),
+            input_sha256: None,
+            output_sha256: None,
+            accounts: 0,
+            locked_accounts: 0,
+            held: Amount::from(0),
+            snapshot_id: None,
+            started: Instant::now(),
+        }
+    }
+
+    // Completes a report of a successful run from the engine which processed the input.
+    pub fn succeeded(
+        &mut self,
+        engine: &PaymentsEngine,
+        input_files: Option<&[OsString]>,
+        output: Option<&OutputDigest>,
+    ) -> anyhow::Result<()> {
+        let mut snapshot = HashingWriter::new(io::sink(), None);
+        output::save_accounts(engine.accounts(), &mut snapshot)?;
+
+        self.status = SUCCEEDED;
+        self.rows = engine.rows();
+        self.rows_by_type = engine.rows_by_type().clone();
+        self.rejected = engine.rejections().total();
+        self.applied = self.rows.saturating_sub(self.rejected);
+        self.rejects_by_code = engine.rejections().counts().clone();
+        self.input_sha256 = input_files.map(files_sha256).transpose()?;
+        self.output_sha256 = output.map(|output| output.sha256.clone());
+        self.accounts = engine.accounts().len();
+        self.locked_accounts = engine
+            .accounts()
+            .values()
+            .filter(|account| account.is_locked())
+            .count();
+        // Held funds of all accounts may not fit into an amount, then they are saturated.
+        self.held = engine
+            .accounts()
+            .values()
+            .map(|account| account.held())
+            .fold(Amount::from(0), overflow::saturating_add);
+        self.snapshot_id = Some(snapshot.digest().sha256);
+        let elapsed = self.started.elapsed();
+        self.duration_ms = elapsed.as_millis() as u64;
+        if !elapsed.is_zero() {
+            self.rows_per_sec = self.rows as f64 / elapsed.as_secs_f64();
+        }
+        Ok(())
+    }
+
+    // Short human-readable summary of a successful run, for operators watching batches.
+    pub fn summary(&self) -> String {
+        let mut summary = format!(
+            "processed {} rows in {} ms ({:.0} rows/sec)\n",
+            self.rows, self.duration_ms, self.rows_per_sec
+        );
+        for (tx_type, rows) in &self.rows_by_type {
+            summary.push_str(&format!("  {}: {}\n", tx_type, rows));
+        }
+        summary.push_str(&format!(
+            "applied {}, rejected {}\n",
+            self.applied, self.rejected
+        ));
+        for (code, rows) in &self.rejects_by_code {
+            summary.push_str(&format!("  {}: {}\n", code, rows));
+        }
+        summary.push_str(&format!(
+            "{} accounts, {} locked, {} held\n",
+            self.accounts, self.locked_accounts, self.held
+        ));
+        summary
+    }
+
+    pub fn failed(&mut self, error: &anyhow::Error) {
+        self.status = FAILED;
+        self.error = Some(format!("{:#}", error));
+        self.duration_ms = self.started.elapsed().as_millis() as u64;
+    }
+
+    pub fn save(&self, file_path: &OsString) -> anyhow::Result<()> {
+        let file = File::create(file_path)
+            .with_context(|| format!("can not create run report {:?}", file_path))?;
+        let mut writer = BufWriter::new(file);
+        serde_json::to_writer_pretty(&mut writer, self)?;
+        writer.write_all(b"\n")?;
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_report_of_run() {
+        let input = std::env::temp_dir().join("coding_test_run_report.csv");
+        fs::write(
+            &input,
+            "type,client,tx,amount\n\
+             deposit,1,1,10\n\
+             withdrawal,1,2,20\n\
+             deposit,1,x,1\n\
+             deposit,2,3,5\n",
+        )
+        .unwrap();
+        let input = input.into_os_string();
+
+        let mut report = RunReport::start(String::from("input.csv"), String::from("input"));
+        let mut engine = PaymentsEngine::default();
+        engine.process_file(&input).unwrap();
+        report
+            .succeeded(&engine, Some(std::slice::from_ref(&input)), None)
+            .unwrap();
+        assert_eq!(report.status, SUCCEEDED);
+        assert_eq!((report.rows, report.applied, report.rejected), (4, 2, 2));
+        assert_eq!(report.rejects_by_code.get("invalid_record"), Some(&1));
+        assert_eq!(report.rejects_by_code.get("insufficient_funds"), Some(&1));
+        assert_eq!(report.accounts, 2);
+        assert_eq!(report.rows_by_type.get("deposit"), Some(&2));
+        assert_eq!(report.rows_by_type.get("withdrawal"), Some(&1));
+        assert_eq!(report.locked_accounts, 0);
+        assert!(report.summary().contains("insufficient_funds: 1"));
+        assert_eq!(report.input_sha256.as_ref().map(String::len), Some(64));
+
+        // The same state gives the same snapshot id.
+        let snapshot_id = report.snapshot_id.clone();
+        let mut engine = PaymentsEngine::default();
+        engine.process_file(&input).unwrap();
+        report.succeeded(&engine, None, None).unwrap();
+        assert_eq!(report.snapshot_id, snapshot_id);
+
+        report.failed(&anyhow::anyhow!("input is broken"));
+        let json: serde_json::Value = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["status"], "failed");
+        assert_eq!(json["error"], "input is broken");
+    }
+
+    #[test]
+    fn test_held_funds_of_largest_disputes() {
+        let input = std::env::temp_dir().join(format!(
+            "coding_test_run_report_held_{}.csv",
+            std::process::id()
+        ));
+        fs::write(
+            &input,
+            format!(
+                "type,client,tx,amount\n\
+                 deposit,1,1,{max}\n\
+                 deposit,2,2,{max}\n\
+                 dispute,1,1,\n\
+                 dispute,2,2,\n",
+                max = Amount::MAX
+            ),
+        )
+        .unwrap();
+        let input = input.into_os_string();
+
+        let mut report = RunReport::start(String::from("input.csv"), String::from("input"));
+        let mut engine = PaymentsEngine::default();
+        engine.process_file(&input).unwrap();
+        fs::remove_file(&input).unwrap();
+        report.succeeded(&engine, None, None).unwrap();
+        assert_eq!(report.rejected, 0);
+        assert_eq!(report.held, Amount::MAX);
+    }
+}
@@ -0,0 +1,187 @@
+use log::warn;
+use std::cell::Cell;
+use std::str::FromStr;
+
+// Fields a transaction type requires and may have, besides `type`, `tx` and the optional
+// `timestamp`, `operator` and `reason` which any transaction may have.
+struct Schema {
+    tx_type: &'static str,
+    required: &'static [&'static str],
+    optional: &'static [&'static str],
+}
+
+const SCHEMAS: &[Schema] = &[
+    Schema::new("deposit", &["client", "amount"], &[]),
+    Schema::new("withdrawal", &["client", "amount"], &[]),
+    // An amount of a dispute or a chargeback is a part of the transaction.
+    Schema::new("dispute", &["client"], &["amount"]),
+    Schema::new("resolve", &["client"], &[]),
+    Schema::new("chargeback", &["client"], &["amount"]),
+    Schema::new("representment", &["client"], &[]),
+    Schema::new("write_off", &["client"], &[]),
+    Schema::new("lock", &["client"], &[]),
+    Schema::new("unlock", &["client"], &[]),
+    Schema::new("adjust", &["client", "amount"], &[]),
+    Schema::new("annotate", &["client"], &[]),
+    Schema::new("approve", &["client"], &[]),
+    Schema::new("transfer", &["from_client", "to_client", "amount"], &[]),
+];
+
+// Fields which are checked whether a transaction type allows them. Other unknown fields are
+// ignored.
+const CHECKED: &[&str] = &["client", "amount", "from_client", "to_client"];
+
+impl Schema {
+    const fn new(
+        tx_type: &'static str,
+        required: &'static [&'static str],
+        optional: &'static [&'static str],
+    ) -> Schema {
+        Schema {
+            tx_type,
+            required,
+            optional,
+        }
+    }
+}
+
+// What happens with a record which has a field its transaction type doesn't use, like a resolve
+// with an amount. A record without a required field is always invalid.
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub enum SchemaPolicy {
+    // The field is ignored with a warning.
+    #[default]
+    Lenient,
+    // The record is rejected as invalid.
+    Strict,
+}
+
+thread_local! {
+    // Policy of the engine reading input on this thread, see `EngineConfig::enter`.
+    static POLICY: Cell<SchemaPolicy> = const { Cell::new(SchemaPolicy::Lenient) };
+}
+
+impl SchemaPolicy {
+    // Policy used when records are read on this thread.
+    pub fn current() -> SchemaPolicy {
+        POLICY.with(Cell::get)
+    }
+
+    // Makes the policy used when records are read on this thread and returns the previous one.
+    pub(crate) fn replace_current(self) -> SchemaPolicy {
+        POLICY.with(|policy| policy.replace(self))
+    }
+}
+
+// Policy is given as `lenient` or `strict`.
+impl FromStr for SchemaPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<SchemaPolicy, String> {
+        match s {
+            "lenient" => Ok(SchemaPolicy::Lenient),
+            "strict" => Ok(SchemaPolicy::Strict),
+            _ => Err(format!("unknown schema policy: {}", s)),
+        }
+    }
+}
+
+// Validates fields of a record at `position` by the policy of this thread. `present` tells whether
+// a field is given and not empty.
+pub fn validate(
+    position: u64,
+    tx_type: &str,
+    present: impl Fn(&str) -> bool,
+) -> Result<(), String> {
+    validate_with(position, tx_type, present, SchemaPolicy::current())
+}
+
+// Records of unknown types pass, deserialization reports them with all known types.
+pub fn validate_with(
+    position: u64,
+    tx_type: &str,
+    present: impl Fn(&str) -> bool,
+    policy: SchemaPolicy,
+) -> Result<(), String> {
+    let schema = match SCHEMAS.iter().find(|schema| schema.tx_type == tx_type) {
+        None => return Ok(()),
+        Some(schema) => schema,
+    };
+    if !present("tx") {
+        return Err(format!("field `tx`: required by {}", tx_type));
+    }
+    if let Some(field) = schema.required.iter().find(|field| !present(field)) {
+        return Err(format!("field `{}`: required by {}", field, tx_type));
+    }
+
+    for field in CHECKED {
+        if schema.required.contains(field) || schema.optional.contains(field) || !present(field) {
+            continue;
+        }
+        match policy {
+            SchemaPolicy::Strict => {
+                return Err(format!("field `{}`: not allowed in {}", field, tx_type))
+            }
+            SchemaPolicy::Lenient => warn!(
+                "field `{}` of {} in record {} is ignored",
+                field, tx_type, position
+            ),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn validate(tx_type: &str, fields: &[&str], policy: SchemaPolicy) -> Result<(), String> {
+        validate_with(1, tx_type, |field| fields.contains(&field), policy)
+    }
+
+    #[test]
+    fn test_parse_schema_policy() {
+        assert_eq!("lenient".parse(), Ok(SchemaPolicy::Lenient));
+        assert_eq!("strict".parse(), Ok(SchemaPolicy::Strict));
+        assert!("off".parse::<SchemaPolicy>().is_err());
+    }
+
+    #[test]
+    fn test_required_fields() {
+        for policy in [SchemaPolicy::Lenient, SchemaPolicy::Strict] {
+            assert_eq!(
+                validate("deposit", &["client", "tx"], policy),
+                Err(String::from("field `amount`: required by deposit"))
+            );
+            assert_eq!(
+                validate("resolve", &["client"], policy),
+                Err(String::from("field `tx`: required by resolve"))
+            );
+            assert!(validate("deposit", &["client", "tx", "amount"], policy).is_ok());
+            assert!(validate("dispute", &["client", "tx"], policy).is_ok());
+            assert!(validate("dispute", &["client", "tx", "amount"], policy).is_ok());
+            assert!(validate(
+                "transfer",
+                &["tx", "from_client", "to_client", "amount"],
+                policy
+            )
+            .is_ok());
+            assert!(validate("unknown", &[], policy).is_ok());
+        }
+    }
+
+    #[test]
+    fn test_fields_not_allowed() {
+        let resolve = ["client", "tx", "amount"];
+        assert!(validate("resolve", &resolve, SchemaPolicy::Lenient).is_ok());
+        assert_eq!(
+            validate("resolve", &resolve, SchemaPolicy::Strict),
+            Err(String::from("field `amount`: not allowed in resolve"))
+        );
+        let transfer = ["client", "tx", "from_client", "to_client", "amount"];
+        assert_eq!(
+            validate("transfer", &transfer, SchemaPolicy::Strict),
+            Err(String::from("field `client`: not allowed in transfer"))
+        );
+    }
+}
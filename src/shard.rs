@@ -0,0 +1,402 @@
+use crate::engine::PaymentsEngine;
+use crate::engine_config::Scope;
+use crate::error::Failure;
+use crate::reorder::{Reorder, ReorderingSource};
+use crate::shutdown::{Shutdown, StoppingSource};
+use crate::source::{CsvSource, SourceError, TransactionSource};
+use crate::throttle::Throttle;
+use crate::transaction::{Transaction, TransactionType};
+
+use anyhow::{anyhow, bail, Context};
+use log::{error, info, warn};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::io::Read;
+use std::sync::mpsc::{self, SyncSender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+// Transactions waiting for a worker. A bounded queue keeps a fast reader from buffering a whole
+// input in memory when workers fall behind.
+const QUEUE_CAPACITY: usize = 4096;
+
+// A shard is skewed if it has this many times more rows than an average shard.
+const SKEW_FACTOR: u64 = 2;
+
+// Rows and processing time of one shard, reported once all shards finish.
+#[derive(Serialize, Clone, PartialEq, Debug)]
+pub struct ShardStats {
+    pub shard: usize,
+    // Client of a dedicated lane, none for regular shards.
+    pub lane_client: Option<u16>,
+    pub rows: u64,
+    pub busy_ms: u64,
+    // Client with the most rows in the shard.
+    pub top_client: Option<u16>,
+    pub top_client_rows: u64,
+}
+
+struct Worker {
+    sender: SyncSender<(u64, Transaction)>,
+    handle: JoinHandle<anyhow::Result<(PaymentsEngine, ShardStats)>>,
+}
+
+fn run_worker(
+    mut engine: PaymentsEngine,
+    receiver: mpsc::Receiver<(u64, Transaction)>,
+    shard: usize,
+    lane_client: Option<u16>,
+) -> anyhow::Result<(PaymentsEngine, ShardStats)> {
+    let mut rows = 0;
+    let mut busy = Duration::ZERO;
+    let mut client_rows: HashMap<u16, u64> = HashMap::new();
+    for (line, transaction) in receiver {
+        rows += 1;
+        *client_rows.entry(transaction.client_id()).or_default() += 1;
+        let started = Instant::now();
+        engine
+            .process_transaction_at(line, transaction)
+            .with_context(|| format!("can not process line {}", line))?;
+        busy += started.elapsed();
+    }
+    engine.flush()?;
+
+    let top = client_rows.into_iter().max_by_key(|(_, rows)| *rows);
+    let stats = ShardStats {
+        shard,
+        lane_client,
+        rows,
+        busy_ms: busy.as_millis() as u64,
+        top_client: top.map(|(client, _)| client),
+        top_client_rows: top.map_or(0, |(_, rows)| rows),
+    };
+    Ok((engine, stats))
+}
+
+// Logs rows and processing time of every shard and warns if some shard has many more rows than
+// others, which limits the speedup of parallel processing.
+pub fn report_skew(stats: &[ShardStats]) {
+    for shard in stats {
+        info!(
+            "shard {} processed {} rows in {} ms",
+            shard.shard, shard.rows, shard.busy_ms
+        );
+    }
+
+    let regular: Vec<&ShardStats> = stats
+        .iter()
+        .filter(|shard| shard.lane_client.is_none())
+        .collect();
+    if regular.len() < 2 {
+        return;
+    }
+    let average = regular.iter().map(|shard| shard.rows).sum::<u64>() / regular.len() as u64;
+    for shard in regular {
+        if shard.rows <= average.max(1) * SKEW_FACTOR {
+            continue;
+        }
+        warn!(
+            "shard {} has {} rows while an average shard has {}",
+            shard.shard, shard.rows, average
+        );
+        if let Some(client) = shard.top_client {
+            if shard.top_client_rows * 2 > shard.rows {
+                warn!(
+                    "client {} has {} rows of shard {}, it may get a dedicated lane with --hot-clients",
+                    client, shard.top_client_rows, shard.shard
+                );
+            }
+        }
+    }
+}
+
+// Processes transactions on a pool of worker threads. Transactions are routed by
+// `client_id % shards`, so all transactions of a client are processed by the same worker in input
+// order while different clients are processed concurrently. Every worker has its own engine and
+// they are merged once the input is processed.
+//
+// Hot clients, which would dominate their shard, may get dedicated lanes: a worker which processes
+// only transactions of that client.
+pub struct ShardedEngine {
+    workers: Vec<Option<Worker>>,
+    shards: usize,
+    lanes: HashMap<u16, usize>,
+    throttle: Option<Throttle>,
+    reorder: Option<Reorder>,
+    shutdown: Option<Shutdown>,
+    // Records skipped as invalid, they are counted in the merged engine.
+    invalid: u64,
+    // Records are read in the caller's thread, by the config of the created engines.
+    scope: Scope,
+}
+
+impl ShardedEngine {
+    // `create_engine` is called once for every shard.
+    pub fn new<F>(shards: usize, create_engine: F) -> anyhow::Result<ShardedEngine>
+    where
+        F: Fn() -> anyhow::Result<PaymentsEngine>,
+    {
+        ShardedEngine::with_hot_clients(shards, &HashSet::new(), create_engine)
+    }
+
+    // Every hot client gets a dedicated lane next to `shards` regular shards.
+    pub fn with_hot_clients<F>(
+        shards: usize,
+        hot_clients: &HashSet<u16>,
+        create_engine: F,
+    ) -> anyhow::Result<ShardedEngine>
+    where
+        F: Fn() -> anyhow::Result<PaymentsEngine>,
+    {
+        let mut hot_clients: Vec<u16> = hot_clients.iter().copied().collect();
+        hot_clients.sort_unstable();
+        let lanes = hot_clients
+            .iter()
+            .enumerate()
+            .map(|(lane, client)| (*client, shards + lane))
+            .collect();
+
+        let mut workers = Vec::with_capacity(shards + hot_clients.len());
+        let mut scope = Scope::current();
+        for shard in 0..shards + hot_clients.len() {
+            let engine = create_engine()?;
+            scope = engine.config().scope();
+            let lane_client = shard.checked_sub(shards).map(|lane| hot_clients[lane]);
+            let name = match lane_client {
+                None => format!("shard-{}", shard),
+                Some(client) => format!("lane-{}", client),
+            };
+            let (sender, receiver) = mpsc::sync_channel::<(u64, Transaction)>(QUEUE_CAPACITY);
+            let handle = thread::Builder::new()
+                .name(name)
+                .spawn(move || run_worker(engine, receiver, shard, lane_client))
+                .context("can not start worker thread")?;
+            workers.push(Some(Worker { sender, handle }));
+        }
+
+        Ok(ShardedEngine {
+            workers,
+            shards,
+            lanes,
+            throttle: None,
+            shutdown: None,
+            reorder: None,
+            invalid: 0,
+            scope,
+        })
+    }
+
+    fn shard(&self, client_id: u16) -> usize {
+        match self.lanes.get(&client_id) {
+            Some(lane) => *lane,
+            None => client_id as usize % self.shards,
+        }
+    }
+
+    // A worker only stops early if processing failed, so its error is returned.
+    fn stopped(&mut self, shard: usize) -> anyhow::Error {
+        match self.workers[shard].take() {
+            None => anyhow!("worker of shard {} already stopped", shard),
+            Some(worker) => match worker.handle.join() {
+                Ok(Err(err)) => err.context(format!("worker of shard {} failed", shard)),
+                Ok(Ok(_)) => anyhow!("worker of shard {} stopped", shard),
+                Err(_) => anyhow!("worker of shard {} panicked", shard).context(Failure::Internal),
+            },
+        }
+    }
+
+    pub fn process_transaction(
+        &mut self,
+        line: u64,
+        transaction: Transaction,
+    ) -> anyhow::Result<()> {
+        let shard = self.shard(transaction.client_id());
+        // Both legs of a transfer are applied by one engine, so its accounts must be in one shard.
+        if let TransactionType::Transfer(transfer) = &transaction.tx_type {
+            if self.shard(transfer.to_client) != shard {
+                bail!(
+                    "transfer {} between clients {} and {} of different shards is not supported",
+                    transfer.tx_id,
+                    transfer.from_client,
+                    transfer.to_client
+                );
+            }
+        }
+        let sent = match &self.workers[shard] {
+            None => false,
+            Some(worker) => worker.sender.send((line, transaction)).is_ok(),
+        };
+        if !sent {
+            return Err(self.stopped(shard));
+        }
+
+        Ok(())
+    }
+
+    // Limits rows read from sources by all workers together.
+    pub fn set_throttle(&mut self, throttle: Throttle) {
+        self.throttle = Some(throttle);
+    }
+
+    // Sources are read until a shutdown is requested, transactions read until then are applied.
+    pub fn set_shutdown(&mut self, shutdown: Shutdown) {
+        self.shutdown = Some(shutdown);
+    }
+
+    pub fn process_csv<R: Read>(&mut self, input: R) -> anyhow::Result<()> {
+        self.process_source(&mut CsvSource::new(input))
+    }
+
+    // Rows are reordered before they are routed to workers.
+    pub fn set_reorder(&mut self, reorder: Reorder) {
+        self.reorder = Some(reorder);
+    }
+
+    // Routes all transactions of a source to workers. Invalid records are skipped.
+    pub fn process_source<S: TransactionSource + ?Sized>(
+        &mut self,
+        source: &mut S,
+    ) -> anyhow::Result<()> {
+        let _scope = self.scope.enter();
+        match self.shutdown.clone() {
+            None => self.process_reordered(source),
+            Some(shutdown) => self.process_reordered(&mut StoppingSource::new(source, shutdown)),
+        }
+    }
+
+    fn process_reordered<S: TransactionSource + ?Sized>(
+        &mut self,
+        source: &mut S,
+    ) -> anyhow::Result<()> {
+        match self.reorder {
+            None => self.process_records(source),
+            Some(reorder) => self.process_records(&mut ReorderingSource::new(source, reorder)),
+        }
+    }
+
+    fn process_records<S: TransactionSource + ?Sized>(
+        &mut self,
+        source: &mut S,
+    ) -> anyhow::Result<()> {
+        while let Some(result) = source.next() {
+            if let Some(throttle) = &mut self.throttle {
+                throttle.wait();
+            }
+            let position = source.position();
+            match result {
+                Ok(transaction) => self.process_transaction(position, transaction)?,
+                Err(SourceError::Invalid(message)) => {
+                    error!(
+                        "can not deserialize transaction at {} {}: {}. record: {}. skipping it.",
+                        source.unit(),
+                        position,
+                        message,
+                        source.record().as_deref().unwrap_or("-")
+                    );
+                    self.invalid += 1;
+                }
+                Err(SourceError::Failed(error)) => {
+                    return Err(error.context(format!(
+                        "can not read {} {}",
+                        source.unit(),
+                        position
+                    )))
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // Waits until workers process all queued transactions and merges their engines into one.
+    pub fn finish(self) -> anyhow::Result<PaymentsEngine> {
+        let (engine, stats) = self.finish_with_stats()?;
+        report_skew(&stats);
+        Ok(engine)
+    }
+
+    // Same as `finish`, but statistics of shards are returned instead of being logged.
+    pub fn finish_with_stats(self) -> anyhow::Result<(PaymentsEngine, Vec<ShardStats>)> {
+        let mut merged: Option<PaymentsEngine> = None;
+        let mut stats = Vec::with_capacity(self.workers.len());
+        for (shard, worker) in self.workers.into_iter().enumerate() {
+            let worker = worker.ok_or_else(|| anyhow!("worker of shard {} failed", shard))?;
+            drop(worker.sender);
+            let (engine, shard_stats) = worker
+                .handle
+                .join()
+                .map_err(|_| {
+                    anyhow!("worker of shard {} panicked", shard).context(Failure::Internal)
+                })?
+                .with_context(|| format!("worker of shard {} failed", shard))?;
+            stats.push(shard_stats);
+            match merged.as_mut() {
+                None => merged = Some(engine),
+                Some(merged) => merged.merge(engine),
+            }
+        }
+
+        let mut merged = merged.ok_or_else(|| anyhow!("no shards to process transactions"))?;
+        merged.count_invalid(self.invalid);
+        Ok((merged, stats))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::amount::Amount;
+    use std::fs::File;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_sharded_result_matches_single_engine() {
+        let mut single = PaymentsEngine::default();
+        single.process_file(&"transactions_3.csv".into()).unwrap();
+
+        let mut sharded = ShardedEngine::new(3, || Ok(PaymentsEngine::default())).unwrap();
+        sharded
+            .process_csv(File::open("transactions_3.csv").unwrap())
+            .unwrap();
+        let sharded = sharded.finish().unwrap();
+
+        assert_eq!(sharded.accounts().len(), single.accounts().len());
+        for (client_id, account) in single.accounts() {
+            let other = sharded.accounts().get(client_id).unwrap();
+            assert_eq!(other.available(), account.available());
+            assert_eq!(other.held(), account.held());
+            assert_eq!(other.is_locked(), account.is_locked());
+        }
+        assert!(sharded.books().ledger.is_balanced());
+        assert!(sharded.books().ledger.matches_accounts(sharded.accounts()));
+        let totals = sharded.books().periods.totals.get("default").unwrap();
+        assert_eq!(totals.deposits, Amount::from_str("1500").unwrap());
+        assert_eq!(totals.deposit_count, 10);
+    }
+
+    #[test]
+    fn test_hot_client_lane_and_stats() {
+        let mut input = String::from("type,client,tx,amount\n");
+        for tx in 1..=40 {
+            let client = if tx % 4 == 0 { 2 } else { 1 };
+            input.push_str(&format!("deposit,{},{},1.0\n", client, tx));
+        }
+
+        let mut sharded = ShardedEngine::with_hot_clients(2, &HashSet::from([1]), || {
+            Ok(PaymentsEngine::default())
+        })
+        .unwrap();
+        sharded.process_csv(input.as_bytes()).unwrap();
+        let (engine, stats) = sharded.finish_with_stats().unwrap();
+
+        assert_eq!(engine.accounts()[&1].available(), Amount::from(30));
+        assert_eq!(engine.accounts()[&2].available(), Amount::from(10));
+        let rows: Vec<(Option<u16>, u64)> = stats
+            .iter()
+            .map(|shard| (shard.lane_client, shard.rows))
+            .collect();
+        assert_eq!(rows, vec![(None, 10), (None, 0), (Some(1), 30)]);
+        assert_eq!(stats[2].top_client, Some(1));
+        assert_eq!(stats[1].top_client, None);
+    }
+}
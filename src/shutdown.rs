@@ -0,0 +1,195 @@
+use crate::source::{SourceError, TransactionSource};
+use crate::transaction::Transaction;
+
+use anyhow::Context;
+use log::warn;
+use signal_hook::consts::{SIGINT, SIGTERM};
+use signal_hook::flag;
+use std::io::{self, Read};
+use std::mem;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+// Exit code of a process stopped by a second signal, while it's still shutting down.
+const FORCED_EXIT: i32 = 130;
+// How often waiting for input or events checks whether a shutdown was requested.
+pub const SHUTDOWN_POLL: Duration = Duration::from_millis(100);
+// Size of chunks read from a stream by its reading thread.
+const CHUNK_BYTES: usize = 64 * 1024;
+
+// Request to stop long-running processing (input streams, the gRPC server and directory watch) at
+// the next transaction, so transactions already read are applied and state can be written out
+// instead of being lost mid-batch. Clones share the request.
+#[derive(Clone, Default)]
+pub struct Shutdown(Arc<AtomicBool>);
+
+impl Shutdown {
+    // Requests a shutdown on SIGINT or SIGTERM. A second signal ends the process right away.
+    pub fn install() -> anyhow::Result<Shutdown> {
+        let shutdown = Shutdown::default();
+        for signal in [SIGINT, SIGTERM] {
+            flag::register_conditional_shutdown(signal, FORCED_EXIT, Arc::clone(&shutdown.0))
+                .context("can not handle shutdown signals")?;
+            flag::register(signal, Arc::clone(&shutdown.0))
+                .context("can not handle shutdown signals")?;
+        }
+        Ok(shutdown)
+    }
+
+    pub fn request(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn requested(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+// Source which ends when a shutdown is requested, so rows buffered by sources around it (for
+// example reordering) are still applied.
+pub struct StoppingSource<'a, S: TransactionSource + ?Sized> {
+    source: &'a mut S,
+    shutdown: Shutdown,
+}
+
+impl<'a, S: TransactionSource + ?Sized> StoppingSource<'a, S> {
+    pub fn new(source: &'a mut S, shutdown: Shutdown) -> StoppingSource<'a, S> {
+        StoppingSource { source, shutdown }
+    }
+}
+
+impl<S: TransactionSource + ?Sized> TransactionSource for StoppingSource<'_, S> {
+    fn next(&mut self) -> Option<Result<Transaction, SourceError>> {
+        if self.shutdown.requested() {
+            warn!(
+                "shutdown requested, input is read until {} {}",
+                self.source.unit(),
+                self.source.position()
+            );
+            return None;
+        }
+        self.source.next()
+    }
+
+    fn position(&self) -> u64 {
+        self.source.position()
+    }
+
+    fn unit(&self) -> &'static str {
+        self.source.unit()
+    }
+
+    fn record(&self) -> Option<String> {
+        self.source.record()
+    }
+}
+
+// Reader of a stream which may wait for input for a long time, like standard input fed by another
+// process. The stream is read by a thread of its own, so reading ends once a shutdown is requested
+// instead of waiting for more input. Input is passed on in whole lines, so a row cut by the
+// shutdown is never parsed, only the last line may be without a line end at the end of input.
+pub struct StoppingReader {
+    chunks: Receiver<io::Result<Vec<u8>>>,
+    shutdown: Shutdown,
+    // Complete lines which aren't read yet.
+    lines: Vec<u8>,
+    position: usize,
+    // Input after the last line end.
+    tail: Vec<u8>,
+}
+
+impl StoppingReader {
+    pub fn new<R: Read + Send + 'static>(mut input: R, shutdown: Shutdown) -> StoppingReader {
+        let (sender, chunks) = mpsc::sync_channel(4);
+        thread::spawn(move || loop {
+            let mut chunk = vec![0; CHUNK_BYTES];
+            let read = match input.read(&mut chunk) {
+                Ok(0) => break,
+                Ok(read) => read,
+                Err(error) if error.kind() == io::ErrorKind::Interrupted => continue,
+                Err(error) => {
+                    let _ = sender.send(Err(error));
+                    break;
+                }
+            };
+            chunk.truncate(read);
+            if sender.send(Ok(chunk)).is_err() {
+                break;
+            }
+        });
+        StoppingReader {
+            chunks,
+            shutdown,
+            lines: Vec::new(),
+            position: 0,
+            tail: Vec::new(),
+        }
+    }
+}
+
+impl Read for StoppingReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.lines.len() {
+            if self.shutdown.requested() {
+                return Ok(0);
+            }
+            match self.chunks.recv_timeout(SHUTDOWN_POLL) {
+                Ok(chunk) => self.tail.extend_from_slice(&chunk?),
+                Err(RecvTimeoutError::Timeout) => continue,
+                Err(RecvTimeoutError::Disconnected) if self.tail.is_empty() => return Ok(0),
+                Err(RecvTimeoutError::Disconnected) => self.tail.push(b'\n'),
+            }
+            if let Some(end) = self.tail.iter().rposition(|byte| *byte == b'\n') {
+                let tail = self.tail.split_off(end + 1);
+                self.lines = mem::replace(&mut self.tail, tail);
+                self.position = 0;
+            }
+        }
+
+        let read = buf.len().min(self.lines.len() - self.position);
+        buf[..read].copy_from_slice(&self.lines[self.position..self.position + read]);
+        self.position += read;
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::engine::PaymentsEngine;
+    use crate::source::CsvSource;
+
+    #[test]
+    fn test_stops_at_next_transaction() {
+        let input = "type,client,tx,amount\ndeposit,1,1,10\ndeposit,1,2,5\n";
+        let shutdown = Shutdown::default();
+        let mut source = CsvSource::new(input.as_bytes());
+        let mut source = StoppingSource::new(&mut source, shutdown.clone());
+        assert!(source.next().is_some());
+        shutdown.request();
+        assert!(source.next().is_none());
+        assert_eq!(source.position(), 2);
+
+        let mut engine = PaymentsEngine::default();
+        engine.set_shutdown(shutdown);
+        assert!(engine.process_csv(input.as_bytes()).is_ok());
+        assert_eq!(engine.rows(), 0);
+    }
+
+    #[test]
+    fn test_reader_passes_whole_lines() {
+        let input = "type,client,tx,amount\ndeposit,1,1,10\ndeposit,1,2,5";
+        let mut read = String::new();
+        let mut reader = StoppingReader::new(io::Cursor::new(input), Shutdown::default());
+        reader.read_to_string(&mut read).unwrap();
+        assert_eq!(read, format!("{}\n", input));
+
+        let shutdown = Shutdown::default();
+        shutdown.request();
+        let mut reader = StoppingReader::new(io::Cursor::new(input), shutdown);
+        assert_eq!(reader.read(&mut [0; 16]).unwrap(), 0);
+    }
+}
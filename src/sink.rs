@@ -0,0 +1,174 @@
+use crate::account::Account;
+use crate::amount::Amount;
+use crate::decimal_format;
+use crate::output;
+
+use anyhow::Context;
+use serde::Serialize;
+use std::io::Write;
+
+// Anything final account states can be written to: CSV on standard output, JSON files, database
+// tables or in-memory collections in tests. Accounts are written one by one and `finish` is
+// called after the last one.
+pub trait AccountSink {
+    fn write(&mut self, account: &Account) -> anyhow::Result<()>;
+
+    fn finish(&mut self) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+// Accounts as CSV rows with a header.
+pub struct CsvSink<W: Write> {
+    writer: csv::Writer<W>,
+}
+
+impl<W: Write> CsvSink<W> {
+    pub fn new(output: W) -> CsvSink<W> {
+        CsvSink {
+            writer: output::csv_writer(output),
+        }
+    }
+}
+
+impl<W: Write> AccountSink for CsvSink<W> {
+    fn write(&mut self, account: &Account) -> anyhow::Result<()> {
+        Ok(self.writer.serialize(account)?)
+    }
+
+    fn finish(&mut self) -> anyhow::Result<()> {
+        Ok(self.writer.flush()?)
+    }
+}
+
+#[derive(Serialize)]
+struct JsonAccount {
+    client: u16,
+    #[serde(serialize_with = "decimal_format::serialize_json_number")]
+    available: Amount,
+    #[serde(serialize_with = "decimal_format::serialize_json_number")]
+    held: Amount,
+    #[serde(serialize_with = "decimal_format::serialize_json_number")]
+    total: Amount,
+    locked: bool,
+}
+
+// Accounts as a JSON array of objects with the same fields as CSV columns. Amounts are JSON numbers
+// with the configured decimal format.
+pub struct JsonSink<W: Write> {
+    output: W,
+    count: usize,
+}
+
+impl<W: Write> JsonSink<W> {
+    pub fn new(output: W) -> JsonSink<W> {
+        JsonSink { output, count: 0 }
+    }
+}
+
+impl<W: Write> AccountSink for JsonSink<W> {
+    fn write(&mut self, account: &Account) -> anyhow::Result<()> {
+        self.output
+            .write_all(if self.count == 0 { b"[\n" } else { b",\n" })?;
+        let account = JsonAccount {
+            client: account.id(),
+            available: account.available(),
+            held: account.held(),
+            total: account.total(),
+            locked: account.is_locked(),
+        };
+        serde_json::to_writer(&mut self.output, &account).context("can not serialize account")?;
+        self.count += 1;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> anyhow::Result<()> {
+        self.output
+            .write_all(if self.count == 0 { b"[]\n" } else { b"\n]\n" })?;
+        Ok(self.output.flush()?)
+    }
+}
+
+// Values of an account as they were written to a sink.
+#[derive(Clone, PartialEq, Debug)]
+pub struct AccountRow {
+    pub client: u16,
+    pub available: Amount,
+    pub held: Amount,
+    pub total: Amount,
+    pub locked: bool,
+}
+
+// Keeps written accounts in memory, for example to check them in tests.
+#[derive(Default)]
+pub struct MemorySink {
+    pub accounts: Vec<AccountRow>,
+}
+
+impl AccountSink for MemorySink {
+    fn write(&mut self, account: &Account) -> anyhow::Result<()> {
+        self.accounts.push(AccountRow {
+            client: account.id(),
+            available: account.available(),
+            held: account.held(),
+            total: account.total(),
+            locked: account.is_locked(),
+        });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::account::BalanceSnapshot;
+    use std::str::FromStr;
+
+    fn account() -> Account {
+        let available = Amount::from_str("1.5").unwrap();
+        let mut account = Account::new(2);
+        account
+            .restore_balances(BalanceSnapshot {
+                available,
+                total: available,
+                ..BalanceSnapshot::default()
+            })
+            .unwrap();
+        account
+    }
+
+    #[test]
+    fn test_json_sink() {
+        let mut output = Vec::new();
+        let mut sink = JsonSink::new(&mut output);
+        sink.write(&account()).unwrap();
+        sink.write(&account()).unwrap();
+        sink.finish().unwrap();
+
+        let row = r#"{"client":2,"available":1.5000,"held":0.0000,"total":1.5000,"locked":false}"#;
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            format!("[\n{},\n{}\n]\n", row, row)
+        );
+
+        let mut output = Vec::new();
+        JsonSink::new(&mut output).finish().unwrap();
+        assert_eq!(output, b"[]\n");
+    }
+
+    #[test]
+    fn test_memory_sink() {
+        let mut sink = MemorySink::default();
+        sink.write(&account()).unwrap();
+        assert_eq!(
+            sink.accounts,
+            vec![AccountRow {
+                client: 2,
+                available: Amount::from_str("1.5").unwrap(),
+                held: Amount::from(0),
+                total: Amount::from_str("1.5").unwrap(),
+                locked: false,
+            }]
+        );
+    }
+}
@@ -0,0 +1,204 @@
+use crate::account::DepositedTransaction;
+use crate::history::TransactionHistory;
+use crate::migration::{self, Migrate, Migration, MigrationOptions, MigrationReport};
+
+use anyhow::{bail, Context};
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+// Tree with the version of the history format, it's missing in histories written before versions
+// were recorded.
+const META_TREE: &str = "meta";
+const VERSION_KEY: &str = "version";
+
+static MIGRATIONS: [Migration<SledHistory>; 1] = [Migration {
+    version: 1,
+    description: "rewrite transactions with defaults of added fields",
+    apply: SledHistory::rewrite_transactions,
+}];
+
+// Transaction history in a `sled` database. Keys are big-endian client and transaction ids, so
+// deposits of a client are kept together in the tree and a lookup takes O(log n).
+pub struct SledHistory {
+    path: PathBuf,
+    db: sled::Db,
+}
+
+fn key(client_id: u16, tx_id: u32) -> [u8; 6] {
+    let mut key = [0; 6];
+    key[..2].copy_from_slice(&client_id.to_be_bytes());
+    key[2..].copy_from_slice(&tx_id.to_be_bytes());
+    key
+}
+
+impl SledHistory {
+    // Opens a history and migrates it to the current format.
+    pub fn open(path: &OsString) -> anyhow::Result<SledHistory> {
+        let mut history = SledHistory::connect(path)?;
+        migration::migrate(&mut history, &MigrationOptions::default())?;
+        Ok(history)
+    }
+
+    pub fn migrate(path: &OsString, options: &MigrationOptions) -> anyhow::Result<MigrationReport> {
+        let mut history = SledHistory::connect(path)?;
+        let report = migration::migrate(&mut history, options)?;
+        history.flush()?;
+        Ok(report)
+    }
+
+    fn connect(path: &OsString) -> anyhow::Result<SledHistory> {
+        let path = PathBuf::from(path);
+        let db = sled::open(&path)
+            .with_context(|| format!("can not open transaction history {:?}", path))?;
+        Ok(SledHistory { path, db })
+    }
+
+    fn rewrite_transactions(&mut self) -> anyhow::Result<()> {
+        for entry in self.db.iter() {
+            let (key, value) = entry?;
+            let transaction: DepositedTransaction = serde_json::from_slice(&value)?;
+            self.db.insert(key, serde_json::to_vec(&transaction)?)?;
+        }
+        Ok(())
+    }
+}
+
+impl Migrate for SledHistory {
+    fn name(&self) -> &'static str {
+        "transaction history"
+    }
+
+    fn version(&self) -> anyhow::Result<u32> {
+        match self.db.open_tree(META_TREE)?.get(VERSION_KEY)? {
+            None => Ok(0),
+            Some(value) => match <[u8; 4]>::try_from(value.as_ref()) {
+                Ok(bytes) => Ok(u32::from_be_bytes(bytes)),
+                Err(_) => bail!("invalid version of transaction history {:?}", self.path),
+            },
+        }
+    }
+
+    fn set_version(&mut self, version: u32) -> anyhow::Result<()> {
+        self.db
+            .open_tree(META_TREE)?
+            .insert(VERSION_KEY, &version.to_be_bytes())?;
+        Ok(())
+    }
+
+    // Copies transactions and the version into a new database.
+    fn backup(&self, path: &Path) -> anyhow::Result<()> {
+        if path.exists() {
+            bail!("backup {:?} already exists", path);
+        }
+        let backup = sled::open(path)?;
+        for entry in self.db.iter() {
+            let (key, value) = entry?;
+            backup.insert(key, value)?;
+        }
+        let meta = backup.open_tree(META_TREE)?;
+        for entry in self.db.open_tree(META_TREE)?.iter() {
+            let (key, value) = entry?;
+            meta.insert(key, value)?;
+        }
+        backup.flush()?;
+        Ok(())
+    }
+
+    fn migrations() -> &'static [Migration<SledHistory>] {
+        &MIGRATIONS
+    }
+}
+
+impl TransactionHistory for SledHistory {
+    fn get(&self, client_id: u16, tx_id: u32) -> anyhow::Result<Option<DepositedTransaction>> {
+        let value = self
+            .db
+            .get(key(client_id, tx_id))
+            .with_context(|| format!("can not read transaction history {:?}", self.path))?;
+        match value {
+            None => Ok(None),
+            Some(value) => Ok(Some(serde_json::from_slice(&value).with_context(|| {
+                format!("invalid tx {} of client {} in history", tx_id, client_id)
+            })?)),
+        }
+    }
+
+    fn put(&mut self, client_id: u16, transaction: &DepositedTransaction) -> anyhow::Result<()> {
+        let value = serde_json::to_vec(transaction)?;
+        self.db
+            .insert(key(client_id, transaction.tx_id()), value)
+            .with_context(|| format!("can not write transaction history {:?}", self.path))?;
+        Ok(())
+    }
+
+    fn len(&self) -> usize {
+        self.db.len()
+    }
+
+    fn flush(&mut self) -> anyhow::Result<()> {
+        self.db
+            .flush()
+            .with_context(|| format!("can not flush transaction history {:?}", self.path))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::account::{Account, DepositedTransactionStatus};
+    use crate::amount::Amount;
+    use crate::history;
+    use crate::transaction::Deposit;
+
+    #[test]
+    fn test_sled_history_survives_reopening() {
+        let path = std::env::temp_dir().join("coding_test_sled_history");
+        let _ = std::fs::remove_dir_all(&path);
+        let path = path.into_os_string();
+
+        let mut account = Account::new(3);
+        for tx_id in [7, 300] {
+            assert!(account
+                .deposit(&Deposit {
+                    client_id: 3,
+                    tx_id,
+                    amount: Amount::from(5),
+                })
+                .is_ok());
+        }
+        assert!(account.set_transaction_as_dispute(300).is_ok());
+        {
+            let mut history = SledHistory::open(&path).unwrap();
+            history::offload(&mut history, &mut account).unwrap();
+            history.flush().unwrap();
+        }
+        assert!(account.transactions().is_empty());
+
+        let history = SledHistory::open(&path).unwrap();
+        assert_eq!(history.len(), 2);
+        assert!(history.get(4, 300).unwrap().is_none());
+        history::restore(&history, &mut account, 300).unwrap();
+        assert_eq!(
+            account.find_transaction(300).unwrap().status(),
+            DepositedTransactionStatus::Dispute
+        );
+        assert!(account.set_transaction_as_resolved(300).is_ok());
+    }
+
+    #[test]
+    fn test_sled_history_is_versioned() {
+        let path = std::env::temp_dir().join("coding_test_sled_history_migrate");
+        let _ = std::fs::remove_dir_all(&path);
+        let path = path.into_os_string();
+
+        // The history is migrated through one handle, as `sled` releases its lock on the database
+        // after the handle is dropped and reopening it right away fails now and then.
+        let mut history = SledHistory::connect(&path).unwrap();
+        let report = migration::migrate(&mut history, &MigrationOptions::default()).unwrap();
+        assert_eq!((report.from_version, report.to_version), (0, 1));
+        assert!(report.applied);
+        assert_eq!(history.version().unwrap(), 1);
+        assert_eq!(history.len(), 0);
+    }
+}
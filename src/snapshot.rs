@@ -0,0 +1,119 @@
+use crate::account::{Account, BalanceSnapshot};
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+// Views are split into shards by client id, so a change after a snapshot copies only one shard.
+const SHARDS: usize = 64;
+
+type Shard = HashMap<u16, BalanceSnapshot>;
+
+fn shard_of(client_id: u16) -> usize {
+    usize::from(client_id) % SHARDS
+}
+
+// Balances and lock of every account, kept next to accounts by an engine. Shards are shared with
+// snapshots taken of them and copied on write, so taking a snapshot only clones pointers and
+// applying transactions never waits for an export of a snapshot.
+pub struct AccountViews {
+    shards: Vec<Arc<Shard>>,
+}
+
+impl AccountViews {
+    pub fn new<'a>(accounts: impl Iterator<Item = &'a Account>) -> AccountViews {
+        let mut views = AccountViews {
+            shards: (0..SHARDS).map(|_| Arc::new(Shard::new())).collect(),
+        };
+        for account in accounts {
+            views.update(account.id(), Some(account));
+        }
+        views
+    }
+
+    // Sets the view of an account, or removes it if the account is gone.
+    pub fn update(&mut self, client_id: u16, account: Option<&Account>) {
+        let shard = Arc::make_mut(&mut self.shards[shard_of(client_id)]);
+        match account {
+            None => shard.remove(&client_id),
+            Some(account) => shard.insert(client_id, account.balances()),
+        };
+    }
+
+    pub fn snapshot(&self) -> AccountsSnapshot {
+        AccountsSnapshot {
+            shards: self.shards.clone(),
+        }
+    }
+}
+
+// Point-in-time consistent copy of balances and locks of all accounts of an engine. It doesn't
+// change when the engine applies more transactions.
+#[derive(Clone)]
+pub struct AccountsSnapshot {
+    shards: Vec<Arc<Shard>>,
+}
+
+impl AccountsSnapshot {
+    pub fn get(&self, client_id: u16) -> Option<&BalanceSnapshot> {
+        self.shards[shard_of(client_id)].get(&client_id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.shards.iter().map(|shard| shard.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Views of all accounts sorted by client id, like accounts output.
+    pub fn sorted(&self) -> Vec<(u16, BalanceSnapshot)> {
+        let mut views: Vec<(u16, BalanceSnapshot)> = self
+            .shards
+            .iter()
+            .flat_map(|shard| shard.iter().map(|(client_id, view)| (*client_id, *view)))
+            .collect();
+        views.sort_unstable_by_key(|(client_id, _)| *client_id);
+        views
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::amount::Amount;
+    use crate::transaction::Deposit;
+
+    fn deposit(account: &mut Account, tx_id: u32, amount: i64) {
+        let deposit = Deposit {
+            client_id: account.id(),
+            tx_id,
+            amount: Amount::from(amount),
+        };
+        assert!(account.deposit(&deposit).is_ok());
+    }
+
+    #[test]
+    fn test_snapshot_is_not_changed_by_updates() {
+        let mut account = Account::new(1);
+        deposit(&mut account, 1, 10);
+        let mut views = AccountViews::new([&account].into_iter());
+
+        let snapshot = views.snapshot();
+        deposit(&mut account, 2, 5);
+        views.update(1, Some(&account));
+        views.update(2, Some(&Account::new(2)));
+        views.update(65, Some(&Account::new(65)));
+
+        assert_eq!(snapshot.len(), 1);
+        assert_eq!(snapshot.get(1).unwrap().total, Amount::from(10));
+        let current = views.snapshot();
+        assert_eq!(current.get(1).unwrap().total, Amount::from(15));
+        let clients: Vec<u16> = current.sorted().iter().map(|(client, _)| *client).collect();
+        assert_eq!(clients, vec![1, 2, 65]);
+
+        views.update(1, None);
+        assert!(views.snapshot().get(1).is_none());
+        assert!(snapshot.get(1).is_some());
+    }
+}
@@ -0,0 +1,626 @@
+use crate::amount::Amount;
+use crate::amount_cache;
+use crate::schema;
+use crate::transaction::{
+    Chargeback, Deposit, Dispute, Resolve, Transaction, TransactionType, Withdrawal,
+};
+
+use csv::{ByteRecord, ReaderBuilder, StringRecord, Trim};
+use serde::Deserialize;
+use std::fmt;
+use std::io::{BufRead, BufReader, Lines, Read};
+use std::str::{self, FromStr};
+use std::vec;
+
+#[derive(Debug)]
+pub enum SourceError {
+    // A record isn't a valid transaction. It's skipped and the source is read further.
+    Invalid(String),
+    // The source can't be read any more.
+    Failed(anyhow::Error),
+}
+
+impl fmt::Display for SourceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SourceError::Invalid(message) => write!(f, "invalid transaction: {}", message),
+            SourceError::Failed(error) => write!(f, "can not read source: {:#}", error),
+        }
+    }
+}
+
+impl std::error::Error for SourceError {}
+
+// Anything transactions can be read from: input files, message queues, database cursors or
+// in-memory fixtures. The engine processes all sources the same way.
+pub trait TransactionSource {
+    fn next(&mut self) -> Option<Result<Transaction, SourceError>>;
+
+    // Position of the last returned record, used in messages.
+    fn position(&self) -> u64;
+
+    // What a position counts, for example `line` or `row`.
+    fn unit(&self) -> &'static str {
+        "record"
+    }
+
+    // Raw text of the last returned record, so an invalid one can be found in input.
+    fn record(&self) -> Option<String> {
+        None
+    }
+}
+
+// Serde errors of flattened transactions don't name the field, but they quote the value, so the
+// field is the first one whose value is quoted in the message.
+fn field_of<'a>(
+    message: &str,
+    headers: &'a StringRecord,
+    record: &StringRecord,
+) -> Option<&'a str> {
+    headers
+        .iter()
+        .zip(record.iter())
+        .find(|(_, value)| {
+            !value.is_empty()
+                && (message.contains(&format!("\"{}\"", value))
+                    || message.contains(&format!("`{}`", value)))
+        })
+        .map(|(header, _)| header)
+}
+
+// Value of a field of a CSV record, empty if there's no such column.
+fn value_of<'a>(headers: &StringRecord, record: &'a StringRecord, field: &str) -> &'a str {
+    headers
+        .iter()
+        .position(|header| header == field)
+        .and_then(|index| record.get(index))
+        .unwrap_or_default()
+}
+
+// Validates a JSON record by the schema of its type and deserializes it.
+pub fn from_json(position: u64, record: &serde_json::Value) -> Result<Transaction, SourceError> {
+    let present = |field: &str| match record.get(field) {
+        None | Some(serde_json::Value::Null) => false,
+        Some(value) => value != "",
+    };
+    if let Some(tx_type) = record.get("type").and_then(|tx_type| tx_type.as_str()) {
+        schema::validate(position, tx_type, present).map_err(SourceError::Invalid)?;
+    }
+    Transaction::deserialize(record).map_err(|error| SourceError::Invalid(error.to_string()))
+}
+
+// Fields of a CSV record as a JSON object. `csv` guesses integers above `u64` as `u128`, which serde
+// can't buffer for flattened transactions, so only integers which fit `u64` or `i64` are numbers.
+// Other values are kept as text, which is also how amounts are parsed exactly.
+fn json_of(headers: &StringRecord, record: &StringRecord) -> serde_json::Value {
+    let value = |field: &str| {
+        if field.is_empty() {
+            serde_json::Value::Null
+        } else if let Ok(number) = field.parse::<u64>() {
+            number.into()
+        } else if let Ok(number) = field.parse::<i64>() {
+            number.into()
+        } else {
+            field.into()
+        }
+    };
+    headers
+        .iter()
+        .zip(record.iter())
+        .map(|(header, field)| (header.to_string(), value(field)))
+        .collect::<serde_json::Map<_, _>>()
+        .into()
+}
+
+// Validates a CSV record by the schema of its type and deserializes it with serde.
+pub fn from_csv(
+    position: u64,
+    headers: &StringRecord,
+    record: &StringRecord,
+) -> Result<Transaction, SourceError> {
+    let value = |field: &str| value_of(headers, record, field);
+    schema::validate(position, value("type"), |field| !value(field).is_empty())
+        .map_err(SourceError::Invalid)?;
+    Transaction::deserialize(json_of(headers, record)).map_err(|error| {
+        // Position is reported by the engine, so only the cause is kept.
+        let message = error.to_string();
+        match field_of(&message, headers, record) {
+            None => SourceError::Invalid(message),
+            Some(field) => SourceError::Invalid(format!("field `{}`: {}", field, message)),
+        }
+    })
+}
+
+// Transactions in CSV format. Position is the line number.
+// Columns of most inputs, whose rows are parsed without serde.
+const USUAL_HEADERS: [&str; 4] = ["type", "client", "tx", "amount"];
+
+fn parse_number<T: FromStr>(field: &[u8]) -> Option<T> {
+    str::from_utf8(field).ok()?.parse().ok()
+}
+
+fn parse_amount(field: &[u8]) -> Option<Amount> {
+    amount_cache::parse_text(str::from_utf8(field).ok()?).ok()
+}
+
+// Part of a dispute or a chargeback, missing if the field is empty.
+fn parse_part(field: &[u8]) -> Option<Option<Amount>> {
+    match field.is_empty() {
+        true => Some(None),
+        false => parse_amount(field).map(Some),
+    }
+}
+
+// Parses a valid row of usual columns into one of the common transactions. Anything else is left
+// to serde, so other types and invalid rows are reported the same way.
+fn parse_usual(record: &ByteRecord) -> Option<Transaction> {
+    let client_id = parse_number(record.get(1)?)?;
+    let tx_id = parse_number(record.get(2)?)?;
+    let amount = record.get(3)?;
+    let tx_type = match record.get(0)? {
+        b"deposit" => TransactionType::Deposit(Deposit {
+            client_id,
+            tx_id,
+            amount: parse_amount(amount)?,
+        }),
+        b"withdrawal" => TransactionType::Withdrawal(Withdrawal {
+            client_id,
+            tx_id,
+            amount: parse_amount(amount)?,
+        }),
+        b"dispute" => TransactionType::Dispute(Dispute {
+            client_id,
+            tx_id,
+            amount: parse_part(amount)?,
+        }),
+        // A resolve with an amount is handled by the schema policy.
+        b"resolve" if amount.is_empty() => TransactionType::Resolve(Resolve { client_id, tx_id }),
+        b"chargeback" => TransactionType::Chargeback(Chargeback {
+            client_id,
+            tx_id,
+            amount: parse_part(amount)?,
+        }),
+        _ => return None,
+    };
+    Some(Transaction {
+        tx_type,
+        timestamp: None,
+        operator: None,
+        reason: None,
+        approved_by: None,
+    })
+}
+
+// Rows are deserialized by serde, which handles any order of columns and optional ones. Inputs with
+// just the usual columns are read as raw bytes and common transactions are parsed field by field,
+// which is several times faster.
+pub struct CsvSource<R: Read> {
+    reader: csv::Reader<R>,
+    headers: Option<StringRecord>,
+    record: StringRecord,
+    // Rows of usual columns, and whether the last one was parsed from it.
+    usual: Option<ByteRecord>,
+    parsed_usual: bool,
+    line: u64,
+}
+
+impl<R: Read> CsvSource<R> {
+    pub fn new(input: R) -> CsvSource<R> {
+        let reader = ReaderBuilder::new()
+            .trim(Trim::All)
+            .delimiter(b',')
+            .from_reader(input);
+        CsvSource {
+            reader,
+            headers: None,
+            record: StringRecord::new(),
+            usual: None,
+            parsed_usual: false,
+            line: 0,
+        }
+    }
+}
+
+impl<R: Read> CsvSource<R> {
+    fn read_error(&mut self, error: csv::Error) -> SourceError {
+        self.line = error
+            .position()
+            .map_or(self.line, |position| position.line());
+        match error.kind() {
+            csv::ErrorKind::Io(_) => SourceError::Failed(error.into()),
+            csv::ErrorKind::UnequalLengths {
+                expected_len, len, ..
+            } => SourceError::Invalid(format!(
+                "record has {} fields, but the header has {}",
+                len, expected_len
+            )),
+            _ => {
+                self.record.clear();
+                SourceError::Invalid(error.to_string())
+            }
+        }
+    }
+
+    // Reads the next row of usual columns and parses it if it's a common transaction. Other rows
+    // are kept as text for serde. Returns None at the end of input.
+    fn read_usual(
+        &mut self,
+        usual: &mut ByteRecord,
+    ) -> Option<Result<Option<Transaction>, SourceError>> {
+        match self.reader.read_byte_record(usual) {
+            Ok(false) => return None,
+            Ok(true) => {}
+            Err(error) => {
+                // Fields of a row with a wrong number of them are reported.
+                self.record = StringRecord::from_byte_record_lossy(usual.clone());
+                self.record.trim();
+                return Some(Err(self.read_error(error)));
+            }
+        }
+        self.line = usual.position().map_or(0, |position| position.line());
+        if let Some(transaction) = parse_usual(usual) {
+            return Some(Ok(Some(transaction)));
+        }
+        match StringRecord::from_byte_record(usual.clone()) {
+            Ok(record) => self.record = record,
+            Err(error) => {
+                self.record.clear();
+                return Some(Err(SourceError::Invalid(error.utf8_error().to_string())));
+            }
+        }
+        Some(Ok(None))
+    }
+}
+
+impl<R: Read> TransactionSource for CsvSource<R> {
+    fn next(&mut self) -> Option<Result<Transaction, SourceError>> {
+        if self.headers.is_none() {
+            match self.reader.headers() {
+                Ok(headers) => {
+                    if headers.iter().eq(USUAL_HEADERS) {
+                        self.usual = Some(ByteRecord::new());
+                    }
+                    self.headers = Some(headers.clone());
+                }
+                Err(error) => return Some(Err(SourceError::Failed(error.into()))),
+            }
+        }
+        self.parsed_usual = false;
+        match self.usual.take() {
+            Some(mut usual) => {
+                let read = self.read_usual(&mut usual);
+                self.usual = Some(usual);
+                match read? {
+                    Ok(Some(transaction)) => {
+                        self.parsed_usual = true;
+                        return Some(Ok(transaction));
+                    }
+                    Ok(None) => {}
+                    Err(error) => return Some(Err(error)),
+                }
+            }
+            None => match self.reader.read_record(&mut self.record) {
+                Ok(false) => return None,
+                Ok(true) => {}
+                Err(error) => return Some(Err(self.read_error(error))),
+            },
+        }
+        self.line = self.record.position().map_or(0, |position| position.line());
+        // Headers were read before the first record.
+        let headers = self.headers.as_ref()?;
+        Some(from_csv(self.line, headers, &self.record))
+    }
+
+    fn position(&self) -> u64 {
+        self.line
+    }
+
+    fn unit(&self) -> &'static str {
+        "line"
+    }
+
+    // Fields of the row as they were read, without quotes.
+    fn record(&self) -> Option<String> {
+        if let (true, Some(usual)) = (self.parsed_usual, &self.usual) {
+            let fields: Vec<_> = usual.iter().map(String::from_utf8_lossy).collect();
+            return Some(fields.join(","));
+        }
+        match self.record.is_empty() {
+            true => None,
+            false => Some(self.record.iter().collect::<Vec<_>>().join(",")),
+        }
+    }
+}
+
+// An array of JSON objects with the same fields as CSV columns. The whole array is parsed up front.
+pub struct JsonSource {
+    records: vec::IntoIter<serde_json::Value>,
+    record: u64,
+    // Last record which isn't a valid transaction.
+    invalid: Option<serde_json::Value>,
+}
+
+impl JsonSource {
+    pub fn new<R: Read>(input: R) -> anyhow::Result<JsonSource> {
+        let records: Vec<serde_json::Value> = serde_json::from_reader(BufReader::new(input))?;
+        Ok(JsonSource {
+            records: records.into_iter(),
+            record: 0,
+            invalid: None,
+        })
+    }
+}
+
+impl TransactionSource for JsonSource {
+    fn next(&mut self) -> Option<Result<Transaction, SourceError>> {
+        let record = self.records.next()?;
+        self.record += 1;
+        self.invalid = None;
+        let transaction = from_json(self.record, &record);
+        if transaction.is_err() {
+            self.invalid = Some(record);
+        }
+        Some(transaction)
+    }
+
+    fn position(&self) -> u64 {
+        self.record
+    }
+
+    fn record(&self) -> Option<String> {
+        self.invalid.as_ref().map(|record| record.to_string())
+    }
+}
+
+// One JSON object per line. Empty lines are skipped.
+pub struct NdjsonSource<R: Read> {
+    lines: Lines<BufReader<R>>,
+    line: u64,
+    last: String,
+}
+
+impl<R: Read> NdjsonSource<R> {
+    pub fn new(input: R) -> NdjsonSource<R> {
+        NdjsonSource {
+            lines: BufReader::new(input).lines(),
+            line: 0,
+            last: String::new(),
+        }
+    }
+}
+
+impl<R: Read> TransactionSource for NdjsonSource<R> {
+    fn next(&mut self) -> Option<Result<Transaction, SourceError>> {
+        loop {
+            let line = self.lines.next()?;
+            self.line += 1;
+            let line = match line {
+                Ok(line) => line,
+                Err(error) => return Some(Err(SourceError::Failed(error.into()))),
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+            self.last = line;
+            return Some(
+                serde_json::from_str(&self.last)
+                    .map_err(|error| SourceError::Invalid(error.to_string()))
+                    .and_then(|record| from_json(self.line, &record)),
+            );
+        }
+    }
+
+    fn position(&self) -> u64 {
+        self.line
+    }
+
+    fn unit(&self) -> &'static str {
+        "line"
+    }
+
+    fn record(&self) -> Option<String> {
+        Some(self.last.clone())
+    }
+}
+
+// Transactions kept in memory, for example test fixtures.
+pub struct MemorySource {
+    transactions: vec::IntoIter<Transaction>,
+    index: u64,
+}
+
+impl From<Vec<Transaction>> for MemorySource {
+    fn from(transactions: Vec<Transaction>) -> MemorySource {
+        MemorySource {
+            transactions: transactions.into_iter(),
+            index: 0,
+        }
+    }
+}
+
+impl TransactionSource for MemorySource {
+    fn next(&mut self) -> Option<Result<Transaction, SourceError>> {
+        let transaction = self.transactions.next()?;
+        self.index += 1;
+        Some(Ok(transaction))
+    }
+
+    fn position(&self) -> u64 {
+        self.index
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn collect<S: TransactionSource>(mut source: S) -> Vec<(u64, Option<u32>)> {
+        let mut records = Vec::new();
+        while let Some(result) = source.next() {
+            records.push((
+                source.position(),
+                result.ok().map(|transaction| transaction.tx_id()),
+            ));
+        }
+        records
+    }
+
+    #[test]
+    fn test_csv_source() {
+        let input = "type, client, tx, amount\n\
+                     deposit, 1, 1, 1.0\n\
+                     deposit, x, 2, 1.0\n\
+                     withdrawal, 1, 3, 0.5\n";
+        let source = CsvSource::new(input.as_bytes());
+        assert_eq!(source.unit(), "line");
+        assert_eq!(collect(source), vec![(2, Some(1)), (3, None), (4, Some(3))]);
+    }
+
+    #[test]
+    fn test_usual_columns_are_parsed_like_others() {
+        let rows = [
+            "deposit, 1, 1, 1.00005",
+            "withdrawal,1,2,0.5",
+            "dispute,1,1,",
+            "dispute,1,1,0.25",
+            "resolve,1,1,",
+            "resolve,1,1,1",
+            "chargeback,1,1,",
+            "lock,1,3,",
+            "deposit,1,4,-1",
+            "deposit,70000,5,1",
+            "deposit,1,x,1",
+        ];
+        let parse = |header: &str, suffix: &str| {
+            let mut input = String::from(header);
+            for row in rows {
+                input.push_str(&format!("\n{}{}", row, suffix));
+            }
+            let mut source = CsvSource::new(input.as_bytes());
+            let mut parsed = Vec::new();
+            while let Some(result) = source.next() {
+                parsed.push(match result {
+                    Ok(transaction) => {
+                        let amount = match &transaction.tx_type {
+                            TransactionType::Deposit(deposit) => Some(deposit.amount),
+                            TransactionType::Withdrawal(withdrawal) => Some(withdrawal.amount),
+                            TransactionType::Dispute(dispute) => dispute.amount,
+                            TransactionType::Chargeback(chargeback) => chargeback.amount,
+                            _ => None,
+                        };
+                        format!(
+                            "{} {} {:?}",
+                            transaction.client_id(),
+                            transaction.tx_id(),
+                            amount
+                        )
+                    }
+                    Err(error) => error.to_string(),
+                });
+            }
+            parsed
+        };
+
+        let usual = parse("type,client,tx,amount", "");
+        assert_eq!(usual.len(), rows.len());
+        assert_eq!(usual, parse("type,client,tx,amount,timestamp", ","));
+
+        let mut source = CsvSource::new("type,client,tx,amount\ndeposit,1,1,2".as_bytes());
+        assert!(source.next().unwrap().is_ok());
+        assert_eq!(source.record().as_deref(), Some("deposit,1,1,2"));
+    }
+
+    #[test]
+    fn test_columns_in_other_order() {
+        let amounts = ["1.00005", "10000000000000000000000000", "-1", ""];
+        let parse = |header: &str, row: &dyn Fn(&str) -> String| {
+            let mut input = String::from(header);
+            for amount in amounts {
+                input.push_str(&format!("\n{}", row(amount)));
+            }
+            let mut source = CsvSource::new(input.as_bytes());
+            let mut parsed = Vec::new();
+            while let Some(result) = source.next() {
+                parsed.push(match result {
+                    Ok(transaction) => match transaction.tx_type {
+                        TransactionType::Deposit(deposit) => deposit.amount.to_string(),
+                        _ => String::from("not a deposit"),
+                    },
+                    Err(error) => error.to_string(),
+                });
+            }
+            parsed
+        };
+
+        let usual = parse("type,client,tx,amount", &|amount| {
+            format!("deposit,1,1,{}", amount)
+        });
+        let other = parse("client,type,tx,amount", &|amount| {
+            format!("1,deposit,1,{}", amount)
+        });
+        assert_eq!(usual, other);
+        assert_eq!(other[1], "10000000000000000000000000");
+    }
+
+    #[test]
+    fn test_json_sources() {
+        let input =
+            r#"[{"type": "deposit", "client": 1, "tx": 1, "amount": "1.0"}, {"type": "?"}]"#;
+        let source = JsonSource::new(input.as_bytes()).unwrap();
+        assert_eq!(collect(source), vec![(1, Some(1)), (2, None)]);
+        assert!(JsonSource::new("[".as_bytes()).is_err());
+
+        let input = "{\"type\": \"deposit\", \"client\": 1, \"tx\": 1, \"amount\": \"1.0\"}\n\n\
+                     {\"type\": \"dispute\", \"client\": 1, \"tx\": 1}\n";
+        let source = NdjsonSource::new(input.as_bytes());
+        assert_eq!(collect(source), vec![(1, Some(1)), (3, Some(1))]);
+    }
+
+    #[test]
+    fn test_invalid_record_context() {
+        let input = "type,client,tx,amount
+                     deposit,1,1,1.0
+                     deposit,1,2,1.x
+                     deposit,1
+";
+        let mut source = CsvSource::new(input.as_bytes());
+        assert!(source.next().unwrap().is_ok());
+        match source.next() {
+            Some(Err(SourceError::Invalid(message))) => {
+                assert!(message.starts_with("field `amount`: "), "{}", message)
+            }
+            _ => panic!("record should be invalid"),
+        }
+        assert_eq!(source.record().as_deref(), Some("deposit,1,2,1.x"));
+        assert!(source.next().unwrap().is_err());
+        assert_eq!(source.record().as_deref(), Some("deposit,1"));
+
+        let mut source = JsonSource::new(r#"[{"type": "?"}]"#.as_bytes()).unwrap();
+        assert!(source.next().unwrap().is_err());
+        assert_eq!(source.record().as_deref(), Some(r#"{"type":"?"}"#));
+    }
+
+    #[test]
+    fn test_schema_validation() {
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,\n\
+                     resolve,1,1,1.0\n";
+        let mut source = CsvSource::new(input.as_bytes());
+        match source.next() {
+            Some(Err(SourceError::Invalid(message))) => {
+                assert_eq!(message, "field `amount`: required by deposit")
+            }
+            _ => panic!("record should be invalid"),
+        }
+        assert!(source.next().unwrap().is_ok());
+
+        let mut source =
+            NdjsonSource::new("{\"type\": \"withdrawal\", \"client\": 1, \"tx\": 1}".as_bytes());
+        match source.next() {
+            Some(Err(SourceError::Invalid(message))) => {
+                assert_eq!(message, "field `amount`: required by withdrawal")
+            }
+            _ => panic!("record should be invalid"),
+        }
+    }
+}
@@ -0,0 +1,164 @@
+use crate::account::DepositedTransaction;
+use crate::history::TransactionHistory;
+
+use anyhow::Context;
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// Spilled transactions are written into the file in chunks of this size.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+// Numbers spill files of one process, every shard has its own.
+static SPILL_FILES: AtomicUsize = AtomicUsize::new(0);
+
+// Transaction history in a temporary file, used for transactions spilled by the entry limit when
+// no history directory is given. Transactions are appended to the file as JSON and only their
+// positions are kept in memory, so a dispute of an old deposit reads it back from disk. A
+// transaction stored again is appended once more, the file is removed when the history is dropped.
+pub struct SpillFile {
+    path: PathBuf,
+    file: File,
+    // Bytes in the file, later transactions are still in `pending`.
+    written: u64,
+    pending: Vec<u8>,
+    // Offset and length of the latest state of every transaction.
+    index: HashMap<(u16, u32), (u64, usize)>,
+}
+
+impl SpillFile {
+    // Creates a spill file in the temporary directory.
+    pub fn create() -> anyhow::Result<SpillFile> {
+        let path = std::env::temp_dir().join(format!(
+            "coding_test_spill_{}_{}",
+            process::id(),
+            SPILL_FILES.fetch_add(1, Ordering::Relaxed)
+        ));
+        SpillFile::create_at(&path)
+    }
+
+    pub fn create_at(path: &Path) -> anyhow::Result<SpillFile> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)
+            .with_context(|| format!("can not create spill file {:?}", path))?;
+        Ok(SpillFile {
+            path: path.to_path_buf(),
+            file,
+            written: 0,
+            pending: Vec::new(),
+            index: HashMap::new(),
+        })
+    }
+
+    fn write_pending(&mut self) -> anyhow::Result<()> {
+        (&self.file)
+            .seek(SeekFrom::Start(self.written))
+            .and_then(|_| (&self.file).write_all(&self.pending))
+            .with_context(|| format!("can not write spill file {:?}", self.path))?;
+        self.written += self.pending.len() as u64;
+        self.pending.clear();
+        Ok(())
+    }
+}
+
+impl TransactionHistory for SpillFile {
+    fn get(&self, client_id: u16, tx_id: u32) -> anyhow::Result<Option<DepositedTransaction>> {
+        let (offset, len) = match self.index.get(&(client_id, tx_id)) {
+            None => return Ok(None),
+            Some(position) => *position,
+        };
+        let mut data = vec![0; len];
+        if offset >= self.written {
+            let start = (offset - self.written) as usize;
+            data.copy_from_slice(&self.pending[start..start + len]);
+        } else {
+            (&self.file)
+                .seek(SeekFrom::Start(offset))
+                .and_then(|_| (&self.file).read_exact(&mut data))
+                .with_context(|| format!("can not read spill file {:?}", self.path))?;
+        }
+        let transaction = serde_json::from_slice(&data).with_context(|| {
+            format!("invalid tx {} of client {} in spill file", tx_id, client_id)
+        })?;
+        Ok(Some(transaction))
+    }
+
+    fn put(&mut self, client_id: u16, transaction: &DepositedTransaction) -> anyhow::Result<()> {
+        let data = serde_json::to_vec(transaction)?;
+        let offset = self.written + self.pending.len() as u64;
+        self.pending.extend_from_slice(&data);
+        self.index
+            .insert((client_id, transaction.tx_id()), (offset, data.len()));
+        if self.pending.len() >= CHUNK_SIZE {
+            self.write_pending()?;
+        }
+        Ok(())
+    }
+
+    fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    fn flush(&mut self) -> anyhow::Result<()> {
+        self.write_pending()
+    }
+}
+
+impl Drop for SpillFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::account::{Account, DepositedTransactionStatus};
+    use crate::amount::Amount;
+    use crate::history;
+    use crate::transaction::Deposit;
+
+    #[test]
+    fn test_spilled_transactions_are_read_back() {
+        let path = std::env::temp_dir().join("coding_test_spill_test");
+        let mut spill = SpillFile::create_at(&path).unwrap();
+
+        // Enough deposits to write some of them into the file.
+        let mut account = Account::new(3);
+        for tx_id in 1..=2000 {
+            let deposit = Deposit {
+                client_id: 3,
+                tx_id,
+                amount: Amount::from(tx_id),
+            };
+            assert!(account.deposit(&deposit).is_ok());
+        }
+        history::offload(&mut spill, &mut account).unwrap();
+        assert!(spill.written > 0);
+        assert_eq!(spill.len(), 2000);
+
+        history::restore(&spill, &mut account, 1).unwrap();
+        assert!(account.set_transaction_as_dispute(1).is_ok());
+        history::restore(&spill, &mut account, 2000).unwrap();
+        assert_eq!(
+            account.find_transaction(2000).unwrap().amount(),
+            Amount::from(2000)
+        );
+        history::offload(&mut spill, &mut account).unwrap();
+        assert_eq!(
+            spill.get(3, 1).unwrap().unwrap().status(),
+            DepositedTransactionStatus::Dispute
+        );
+        assert!(spill.get(3, 2001).unwrap().is_none());
+
+        drop(spill);
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,623 @@
+use crate::account::{Account, Adjustment, BalanceSnapshot, DepositedTransaction};
+use crate::migration::{self, Migrate, Migration, MigrationOptions, MigrationReport};
+use crate::output;
+use crate::store::{AccountStore, CompactionReport, StoreStats};
+
+use crate::amount::Amount;
+use anyhow::{bail, Context};
+use log::info;
+use rusqlite::types::ValueRef;
+use rusqlite::{params, Connection, OpenFlags, OptionalExtension};
+use std::collections::HashSet;
+use std::ffi::OsString;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS accounts (
+        client INTEGER PRIMARY KEY,
+        available TEXT NOT NULL,
+        held TEXT NOT NULL,
+        total TEXT NOT NULL,
+        locked INTEGER NOT NULL,
+        last_activity INTEGER NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS transactions (
+        client INTEGER NOT NULL,
+        position INTEGER NOT NULL,
+        tx INTEGER NOT NULL,
+        data TEXT NOT NULL,
+        PRIMARY KEY (client, position)
+    );
+    CREATE INDEX IF NOT EXISTS transactions_tx ON transactions (client, tx);
+    CREATE TABLE IF NOT EXISTS adjustments (
+        client INTEGER NOT NULL,
+        position INTEGER NOT NULL,
+        data TEXT NOT NULL,
+        PRIMARY KEY (client, position)
+    );
+";
+
+// Version of a store is kept in `PRAGMA user_version`, which is 0 in stores written before
+// versions were recorded.
+static MIGRATIONS: [Migration<SqliteStore>; 2] = [
+    Migration {
+        version: 1,
+        description: "create tables of accounts, transactions and adjustments",
+        apply: SqliteStore::create_schema,
+    },
+    Migration {
+        version: 2,
+        description: "rewrite stored transactions with defaults of added fields",
+        apply: SqliteStore::rewrite_transactions,
+    },
+];
+
+// Account store in an SQLite database file. Balances are kept as decimal text, so they are read
+// back exactly with any amount backend, and every deposited transaction and adjustment is its
+// own row keyed by client.
+pub struct SqliteStore {
+    path: PathBuf,
+    connection: Connection,
+    stored: HashSet<u16>,
+}
+
+fn parse_amount(text: &str) -> anyhow::Result<Amount> {
+    Amount::from_str(text).map_err(|_| anyhow::anyhow!("invalid amount {:?} in store", text))
+}
+
+impl SqliteStore {
+    // Opens a store and migrates it to the current schema.
+    pub fn open(path: &OsString) -> anyhow::Result<SqliteStore> {
+        let mut store = SqliteStore::connect(path)?;
+        migration::migrate(&mut store, &MigrationOptions::default())?;
+
+        {
+            let mut statement = store.connection.prepare("SELECT client FROM accounts")?;
+            for client in statement.query_map([], |row| row.get(0))? {
+                store.stored.insert(client?);
+            }
+        }
+
+        Ok(store)
+    }
+
+    pub fn migrate(path: &OsString, options: &MigrationOptions) -> anyhow::Result<MigrationReport> {
+        migration::migrate(&mut SqliteStore::connect(path)?, options)
+    }
+
+    fn connect(path: &OsString) -> anyhow::Result<SqliteStore> {
+        let path = PathBuf::from(path);
+        let connection = Connection::open(&path)
+            .with_context(|| format!("can not open SQLite store {:?}", path))?;
+
+        Ok(SqliteStore {
+            path,
+            connection,
+            stored: HashSet::new(),
+        })
+    }
+
+    fn create_schema(&mut self) -> anyhow::Result<()> {
+        self.connection
+            .execute_batch(SCHEMA)
+            .with_context(|| format!("can not create schema of SQLite store {:?}", self.path))
+    }
+
+    fn rewrite_transactions(&mut self) -> anyhow::Result<()> {
+        let transaction = self.connection.transaction()?;
+        {
+            let mut select =
+                transaction.prepare("SELECT client, position, data FROM transactions")?;
+            let mut update = transaction
+                .prepare("UPDATE transactions SET data = ?3 WHERE client = ?1 AND position = ?2")?;
+            let rows = select.query_map([], |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    row.get::<_, i64>(1)?,
+                    row.get::<_, String>(2)?,
+                ))
+            })?;
+            for row in rows {
+                let (client, position, data) = row?;
+                let deposited: DepositedTransaction = serde_json::from_str(&data)?;
+                update.execute(params![
+                    client,
+                    position,
+                    serde_json::to_string(&deposited)?
+                ])?;
+            }
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+
+    fn read(&self, client_id: u16) -> anyhow::Result<Option<Account>> {
+        let row = self
+            .connection
+            .query_row(
+                "SELECT available, held, total, locked, last_activity FROM accounts
+                 WHERE client = ?1",
+                [client_id],
+                |row| {
+                    Ok((
+                        row.get::<_, String>(0)?,
+                        row.get::<_, String>(1)?,
+                        row.get::<_, String>(2)?,
+                        row.get::<_, bool>(3)?,
+                        row.get::<_, i64>(4)?,
+                    ))
+                },
+            )
+            .optional()?;
+        let (available, held, total, locked, last_activity) = match row {
+            None => return Ok(None),
+            Some(row) => row,
+        };
+
+        let mut account = Account::new(client_id);
+        account.restore_balances(BalanceSnapshot {
+            available: parse_amount(&available)?,
+            held: parse_amount(&held)?,
+            total: parse_amount(&total)?,
+            locked,
+        })?;
+        account.set_last_activity(last_activity as u64);
+
+        let mut statement = self
+            .connection
+            .prepare("SELECT data FROM transactions WHERE client = ?1 ORDER BY position")?;
+        for data in statement.query_map([client_id], |row| row.get::<_, String>(0))? {
+            let transaction: DepositedTransaction = serde_json::from_str(&data?)?;
+            account.add_transaction(transaction);
+        }
+        let mut statement = self
+            .connection
+            .prepare("SELECT data FROM adjustments WHERE client = ?1 ORDER BY position")?;
+        let mut adjustments = Vec::new();
+        for data in statement.query_map([client_id], |row| row.get::<_, String>(0))? {
+            let adjustment: Adjustment = serde_json::from_str(&data?)?;
+            adjustments.push(adjustment);
+        }
+        account.restore_adjustments(adjustments);
+
+        Ok(Some(account))
+    }
+
+    // Loads accounts into an empty store. Rows are inserted in order of their primary keys and the
+    // index of transactions is dropped during the load and built once at the end. An interrupted
+    // load is rolled back and leaves the store empty.
+    fn bulk_load(&mut self, accounts: &[&Account]) -> anyhow::Result<()> {
+        let mut sorted = accounts.to_vec();
+        sorted.sort_unstable_by_key(|account| account.id());
+
+        let transaction = self.connection.transaction()?;
+        transaction.execute_batch("DROP INDEX IF EXISTS transactions_tx")?;
+        for account in sorted {
+            insert(&transaction, account)?;
+        }
+        transaction.execute_batch(
+            "CREATE INDEX IF NOT EXISTS transactions_tx ON transactions (client, tx)",
+        )?;
+        transaction.commit()?;
+        info!("SQLite store {:?} bulk loaded", self.path);
+
+        Ok(())
+    }
+
+    fn disk_bytes(&self) -> anyhow::Result<u64> {
+        let metadata = std::fs::metadata(&self.path)
+            .with_context(|| format!("can not read SQLite store {:?}", self.path))?;
+        Ok(metadata.len())
+    }
+}
+
+impl Migrate for SqliteStore {
+    fn name(&self) -> &'static str {
+        "SQLite store"
+    }
+
+    fn version(&self) -> anyhow::Result<u32> {
+        Ok(self
+            .connection
+            .query_row("PRAGMA user_version", [], |row| row.get(0))?)
+    }
+
+    fn set_version(&mut self, version: u32) -> anyhow::Result<()> {
+        // Pragmas don't take parameters.
+        self.connection
+            .execute_batch(&format!("PRAGMA user_version = {}", version))?;
+        Ok(())
+    }
+
+    fn backup(&self, path: &Path) -> anyhow::Result<()> {
+        if path.exists() {
+            bail!("backup {:?} already exists", path);
+        }
+        let path = path
+            .to_str()
+            .with_context(|| format!("backup path {:?} is not valid UTF-8", path))?;
+        self.connection.execute("VACUUM INTO ?1", [path])?;
+        Ok(())
+    }
+
+    fn migrations() -> &'static [Migration<SqliteStore>] {
+        &MIGRATIONS
+    }
+}
+
+// Runs an ad-hoc query of an operator against a store and writes its rows as CSV with column names
+// as the header. The store is opened read-only, so a query can never change stored accounts.
+pub fn query<W: Write>(path: &OsString, sql: &str, output: W) -> anyhow::Result<()> {
+    let connection = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .with_context(|| format!("can not open SQLite store {:?}", path))?;
+    let mut statement = connection.prepare(sql).context("invalid SQL query")?;
+    if !statement.readonly() {
+        bail!("only queries which don't change the store are allowed");
+    }
+
+    let mut writer = output::csv_writer(output);
+    let names: Vec<String> = statement
+        .column_names()
+        .iter()
+        .map(|name| name.to_string())
+        .collect();
+    writer.write_record(&names)?;
+    let mut rows = statement.query([])?;
+    while let Some(row) = rows.next()? {
+        let mut record = Vec::with_capacity(names.len());
+        for index in 0..names.len() {
+            record.push(match row.get_ref(index)? {
+                ValueRef::Null => String::new(),
+                ValueRef::Integer(value) => value.to_string(),
+                ValueRef::Real(value) => value.to_string(),
+                ValueRef::Text(text) => String::from_utf8_lossy(text).into_owned(),
+                ValueRef::Blob(blob) => blob.iter().map(|byte| format!("{:02x}", byte)).collect(),
+            });
+        }
+        writer.write_record(&record)?;
+    }
+    writer.flush()?;
+
+    Ok(())
+}
+
+// Removes an account with its transactions and adjustments within a database transaction.
+fn delete(transaction: &rusqlite::Transaction, client_id: u16) -> rusqlite::Result<()> {
+    transaction.execute("DELETE FROM accounts WHERE client = ?1", [client_id])?;
+    transaction.execute("DELETE FROM transactions WHERE client = ?1", [client_id])?;
+    transaction.execute("DELETE FROM adjustments WHERE client = ?1", [client_id])?;
+    Ok(())
+}
+
+// Inserts an account with its transactions and adjustments, which must not be stored yet.
+fn insert(transaction: &rusqlite::Transaction, account: &Account) -> anyhow::Result<()> {
+    let client_id = account.id();
+    transaction.execute(
+        "INSERT INTO accounts (client, available, held, total, locked, last_activity)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        params![
+            client_id,
+            account.available().to_string(),
+            account.held().to_string(),
+            account.total().to_string(),
+            account.is_locked(),
+            account.last_activity() as i64,
+        ],
+    )?;
+    // Statements are cached by the connection, so they are prepared once per batch of accounts.
+    let mut statement = transaction.prepare_cached(
+        "INSERT INTO transactions (client, position, tx, data) VALUES (?1, ?2, ?3, ?4)",
+    )?;
+    for (position, deposited) in account.transactions().iter().enumerate() {
+        statement.execute(params![
+            client_id,
+            position as i64,
+            deposited.tx_id(),
+            serde_json::to_string(deposited)?,
+        ])?;
+    }
+    let mut statement = transaction
+        .prepare_cached("INSERT INTO adjustments (client, position, data) VALUES (?1, ?2, ?3)")?;
+    for (position, adjustment) in account.adjustments().iter().enumerate() {
+        statement.execute(params![
+            client_id,
+            position as i64,
+            serde_json::to_string(adjustment)?,
+        ])?;
+    }
+    Ok(())
+}
+
+impl AccountStore for SqliteStore {
+    fn contains(&self, client_id: u16) -> bool {
+        self.stored.contains(&client_id)
+    }
+
+    fn take(&mut self, client_id: u16) -> anyhow::Result<Option<Account>> {
+        if !self.contains(client_id) {
+            return Ok(None);
+        }
+
+        let account = self
+            .read(client_id)
+            .with_context(|| format!("can not read account {} from SQLite store", client_id))?;
+        let transaction = self.connection.transaction()?;
+        delete(&transaction, client_id)?;
+        transaction
+            .commit()
+            .with_context(|| format!("can not remove account {} from SQLite store", client_id))?;
+        self.stored.remove(&client_id);
+        info!("account: {} taken from SQLite store", client_id);
+
+        Ok(account)
+    }
+
+    // An account is replaced within a database transaction, so an interrupted write never leaves
+    // a partial account behind.
+    fn put(&mut self, account: &Account) -> anyhow::Result<()> {
+        let client_id = account.id();
+        let transaction = self.connection.transaction()?;
+        delete(&transaction, client_id)?;
+        insert(&transaction, account)?;
+        transaction
+            .commit()
+            .with_context(|| format!("can not store account {} in SQLite store", client_id))?;
+        self.stored.insert(client_id);
+        info!("account: {} stored in SQLite store", client_id);
+
+        Ok(())
+    }
+
+    // All accounts are written within one database transaction, so the file is synced once. The
+    // first ingestion into an empty store is a bulk load which doesn't replace anything and builds
+    // the index of transactions only after all rows are inserted.
+    fn put_all(&mut self, accounts: &[&Account]) -> anyhow::Result<()> {
+        if accounts.is_empty() {
+            return Ok(());
+        }
+        if !self.is_empty() {
+            let transaction = self.connection.transaction()?;
+            for account in accounts {
+                delete(&transaction, account.id())?;
+                insert(&transaction, account)?;
+            }
+            transaction.commit().with_context(|| {
+                format!("can not store accounts in SQLite store {:?}", self.path)
+            })?;
+        } else {
+            self.bulk_load(accounts)
+                .with_context(|| format!("can not bulk load SQLite store {:?}", self.path))?;
+        }
+        self.stored
+            .extend(accounts.iter().map(|account| account.id()));
+        info!("{} accounts stored in SQLite store", accounts.len());
+
+        Ok(())
+    }
+
+    fn get(&self, client_id: u16) -> anyhow::Result<Option<Account>> {
+        if !self.contains(client_id) {
+            return Ok(None);
+        }
+
+        self.read(client_id)
+            .with_context(|| format!("can not read account {} from SQLite store", client_id))
+    }
+
+    fn clients(&self) -> Vec<u16> {
+        let mut clients: Vec<u16> = self.stored.iter().copied().collect();
+        clients.sort_unstable();
+        clients
+    }
+
+    fn len(&self) -> usize {
+        self.stored.len()
+    }
+
+    fn stats(&self) -> anyhow::Result<StoreStats> {
+        let count = |table: &str| -> anyhow::Result<usize> {
+            let count: i64 = self.connection.query_row(
+                &format!("SELECT COUNT(*) FROM {}", table),
+                [],
+                |row| row.get(0),
+            )?;
+            Ok(count as usize)
+        };
+
+        Ok(StoreStats {
+            accounts: count("accounts")?,
+            transactions: count("transactions")?,
+            adjustments: count("adjustments")?,
+            disk_bytes: self.disk_bytes()?,
+        })
+    }
+
+    // Rebuilds the database file without pages freed by taken accounts.
+    fn compact(&mut self) -> anyhow::Result<CompactionReport> {
+        let disk_bytes_before = self.disk_bytes()?;
+        self.connection
+            .execute_batch("VACUUM")
+            .with_context(|| format!("can not vacuum SQLite store {:?}", self.path))?;
+        let report = CompactionReport {
+            rewritten_accounts: self.stored.len(),
+            removed_files: 0,
+            disk_bytes_before,
+            disk_bytes_after: self.disk_bytes()?,
+        };
+        info!(
+            "SQLite store compacted from {} to {} bytes",
+            report.disk_bytes_before, report.disk_bytes_after
+        );
+
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::transaction::Deposit;
+
+    fn store_path(name: &str) -> OsString {
+        let path = std::env::temp_dir().join(format!("coding_test_sqlite_{}.db", name));
+        let _ = std::fs::remove_file(&path);
+        path.into_os_string()
+    }
+
+    #[test]
+    fn test_sqlite_store_survives_reopening() {
+        let path = store_path("reopen");
+        let mut store = SqliteStore::open(&path).unwrap();
+
+        let mut account = Account::new(7);
+        for tx_id in 1..=2 {
+            assert!(account
+                .deposit(&Deposit {
+                    client_id: 7,
+                    tx_id,
+                    amount: Amount::from_str("1.2345").unwrap(),
+                })
+                .is_ok());
+        }
+        assert!(account.set_transaction_as_dispute(2).is_ok());
+        store.put(&account).unwrap();
+        assert!(store.contains(7));
+
+        let mut store = SqliteStore::open(&path).unwrap();
+        assert_eq!(store.len(), 1);
+        let stats = store.stats().unwrap();
+        assert_eq!(stats.accounts, 1);
+        assert_eq!(stats.transactions, 2);
+
+        let mut account = store.take(7).unwrap().unwrap();
+        assert_eq!(account.available(), Amount::from_str("1.2345").unwrap());
+        assert_eq!(account.held(), Amount::from_str("1.2345").unwrap());
+        assert!(account.set_transaction_as_chargeback(2).is_ok());
+        assert!(store.is_empty());
+        assert!(store.take(7).unwrap().is_none());
+
+        let report = store.compact().unwrap();
+        assert!(report.disk_bytes_after <= report.disk_bytes_before);
+    }
+
+    #[test]
+    fn test_bulk_load_into_empty_store() {
+        let path = store_path("bulk_load");
+        let mut store = SqliteStore::open(&path).unwrap();
+        let mut accounts = Vec::new();
+        for client_id in [5, 2, 9] {
+            let mut account = Account::new(client_id);
+            assert!(account
+                .deposit(&Deposit {
+                    client_id,
+                    tx_id: u32::from(client_id),
+                    amount: Amount::from(1),
+                })
+                .is_ok());
+            accounts.push(account);
+        }
+        store.put_all(&accounts.iter().collect::<Vec<_>>()).unwrap();
+        assert_eq!(store.clients(), vec![2, 5, 9]);
+
+        // The index of transactions is built again after the load.
+        let indexes: i64 = store
+            .connection
+            .query_row(
+                "SELECT COUNT(*) FROM sqlite_master WHERE type = 'index' AND name = 'transactions_tx'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(indexes, 1);
+
+        // Later writes replace stored accounts.
+        assert!(accounts[0].set_transaction_as_dispute(5).is_ok());
+        store.put_all(&[&accounts[0], &Account::new(1)]).unwrap();
+        let mut store = SqliteStore::open(&path).unwrap();
+        assert_eq!(store.clients(), vec![1, 2, 5, 9]);
+        let stats = store.stats().unwrap();
+        assert_eq!((stats.accounts, stats.transactions), (4, 3));
+        assert_eq!(store.take(5).unwrap().unwrap().held(), Amount::from(1));
+    }
+
+    #[test]
+    fn test_query_store() {
+        let path = store_path("query");
+        let mut store = SqliteStore::open(&path).unwrap();
+        let mut account = Account::new(3);
+        account
+            .restore_balances(BalanceSnapshot {
+                available: Amount::from_str("-1.5").unwrap(),
+                held: Amount::from(0),
+                total: Amount::from_str("-1.5").unwrap(),
+                locked: true,
+            })
+            .unwrap();
+        store.put(&account).unwrap();
+        store.put(&Account::new(4)).unwrap();
+        assert_eq!(store.clients(), vec![3, 4]);
+        assert!(store.get(3).unwrap().unwrap().is_locked());
+        assert!(store.contains(3));
+
+        let mut output = Vec::new();
+        query(
+            &path,
+            "SELECT client, available, locked FROM accounts WHERE locked ORDER BY client",
+            &mut output,
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,available,locked\n3,-1.5,1\n"
+        );
+
+        assert!(query(&path, "DELETE FROM accounts", Vec::new()).is_err());
+        assert_eq!(SqliteStore::open(&path).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_unversioned_store_is_migrated() {
+        let path = store_path("migrate");
+        {
+            // Store written before versions were recorded, with a transaction without a
+            // direction and a time.
+            let connection = Connection::open(&path).unwrap();
+            connection.execute_batch(SCHEMA).unwrap();
+            connection
+                .execute_batch(
+                    "INSERT INTO accounts VALUES (2, '5', '0', '5', 0, 0);
+                     INSERT INTO transactions VALUES
+                         (2, 0, 1, '{\"tx_id\":1,\"amount\":\"5\",\"status\":\"Accepted\",\"period\":null}');",
+                )
+                .unwrap();
+        }
+
+        let dry_run = MigrationOptions {
+            dry_run: true,
+            ..MigrationOptions::default()
+        };
+        let report = SqliteStore::migrate(&path, &dry_run).unwrap();
+        assert_eq!((report.from_version, report.to_version), (0, 2));
+        assert!(!report.applied);
+
+        let backup = std::env::temp_dir().join("coding_test_sqlite_migrate_backup.db");
+        let _ = std::fs::remove_file(&backup);
+        let options = MigrationOptions {
+            backup: Some(backup.clone()),
+            ..MigrationOptions::default()
+        };
+        assert!(SqliteStore::migrate(&path, &options).unwrap().applied);
+        assert_eq!(
+            SqliteStore::open(&backup.into_os_string()).unwrap().len(),
+            1
+        );
+
+        let mut store = SqliteStore::open(&path).unwrap();
+        assert_eq!(store.version().unwrap(), 2);
+        let mut account = store.take(2).unwrap().unwrap();
+        assert!(account.set_transaction_as_dispute(1).is_ok());
+        assert!(!SqliteStore::migrate(&path, &dry_run).unwrap().applied);
+    }
+}
@@ -0,0 +1,237 @@
+use crate::account::{Account, Adjustment, BalanceSnapshot, DepositedTransaction, Transactions};
+use crate::amount::Amount;
+use crate::engine::PaymentsEngine;
+
+use anyhow::{bail, Context};
+use csv::{ReaderBuilder, Trim};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{BufReader, Read, Write};
+use std::path::Path;
+
+// Version of the state file format, increased on every change of it.
+pub const STATE_VERSION: u32 = 1;
+
+// Complete state of an engine. Unlike accounts output it keeps deposits with their dispute
+// statuses and ids of all seen transactions, so a restarted engine gives the same results as one
+// which never stopped.
+#[derive(Serialize, Deserialize)]
+pub struct EngineState {
+    pub version: u32,
+    // Time of the engine clock, so dispute windows continue where they stopped.
+    pub now: u64,
+    pub accounts: Vec<AccountState>,
+    // Transactions dropped by the entry limit.
+    pub evicted: Vec<(u16, u32)>,
+    // Client of every stored transaction.
+    pub tx_clients: BTreeMap<u32, u16>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct AccountState {
+    pub client: u16,
+    pub available: Amount,
+    pub held: Amount,
+    pub total: Amount,
+    pub locked: bool,
+    pub held_withdrawals: Amount,
+    pub last_activity: u64,
+    #[serde(default)]
+    pub withdrawn_today: Amount,
+    #[serde(default)]
+    pub withdrawal_day: u64,
+    pub transactions: Vec<DepositedTransaction>,
+    pub adjustments: Vec<Adjustment>,
+}
+
+impl AccountState {
+    pub fn from_account(account: &Account) -> AccountState {
+        AccountState {
+            client: account.id(),
+            available: account.available(),
+            held: account.held(),
+            total: account.total(),
+            locked: account.is_locked(),
+            held_withdrawals: account.held_withdrawals(),
+            last_activity: account.last_activity(),
+            withdrawn_today: account.withdrawn_today(),
+            withdrawal_day: account.withdrawal_day(),
+            transactions: account.transactions().to_vec(),
+            adjustments: account.adjustments().to_vec(),
+        }
+    }
+
+    pub fn into_account(self) -> anyhow::Result<Account> {
+        let mut account = Account::new(self.client);
+        account.restore_balances(BalanceSnapshot {
+            available: self.available,
+            held: self.held,
+            total: self.total,
+            locked: self.locked,
+        })?;
+        account.restore_held_withdrawals(self.held_withdrawals)?;
+        account.set_last_activity(self.last_activity);
+        account.restore_withdrawn_today(self.withdrawn_today, self.withdrawal_day);
+        *account.transactions_mut() = Transactions::from(self.transactions);
+        account.restore_adjustments(self.adjustments);
+        Ok(account)
+    }
+}
+
+pub fn save_state<W: Write>(state: &EngineState, mut output: W) -> anyhow::Result<()> {
+    serde_json::to_writer_pretty(&mut output, state).context("can not serialize state")?;
+    writeln!(output)?;
+    output.flush()?;
+    Ok(())
+}
+
+pub fn load_state(file_path: &OsString) -> anyhow::Result<EngineState> {
+    let file =
+        File::open(file_path).with_context(|| format!("can not open state {:?}", file_path))?;
+    serde_json::from_reader(BufReader::new(file))
+        .with_context(|| format!("can not read state {:?}", file_path))
+}
+
+// Starts an engine from a state file if its extension is `json` and from accounts output
+// otherwise.
+pub fn restore(engine: &mut PaymentsEngine, file_path: &OsString) -> anyhow::Result<()> {
+    match Path::new(file_path).extension() {
+        Some(extension) if extension == "json" => engine.import_state(load_state(file_path)?),
+        _ => engine.seed_accounts(load(file_path)?),
+    }
+}
+
+// Row of accounts output as written by `output::save_accounts`.
+#[derive(Deserialize)]
+struct StateRow {
+    client: u16,
+    available: Amount,
+    held: Amount,
+    total: Amount,
+    locked: bool,
+}
+
+// Reads accounts output of a previous run, so a new batch of transactions continues from the
+// state where that run stopped. Deposits of earlier runs aren't part of the output, they are
+// found in the transaction history if it's persisted.
+pub fn load_accounts<R: Read>(input: R) -> anyhow::Result<Vec<Account>> {
+    let mut reader = ReaderBuilder::new().trim(Trim::All).from_reader(input);
+    let mut clients = HashSet::new();
+    let mut accounts = Vec::new();
+    for (index, row) in reader.deserialize::<StateRow>().enumerate() {
+        // Line 1 is the header.
+        let line = index + 2;
+        let row = row.with_context(|| format!("invalid account on line {}", line))?;
+        if !clients.insert(row.client) {
+            bail!("client {} is repeated on line {}", row.client, line);
+        }
+        if row.available + row.held != row.total {
+            bail!(
+                "total of client {} on line {} is not a sum of available and held funds",
+                row.client,
+                line
+            );
+        }
+
+        let mut account = Account::new(row.client);
+        account.restore_balances(BalanceSnapshot {
+            available: row.available,
+            held: row.held,
+            total: row.total,
+            locked: row.locked,
+        })?;
+        accounts.push(account);
+    }
+
+    Ok(accounts)
+}
+
+pub fn load(file_path: &OsString) -> anyhow::Result<Vec<Account>> {
+    let file =
+        File::open(file_path).with_context(|| format!("can not open state {:?}", file_path))?;
+    load_accounts(file).with_context(|| format!("can not read state {:?}", file_path))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::engine::PaymentsEngine;
+    use crate::output;
+
+    #[test]
+    fn test_state_continues_previous_run() {
+        let mut engine = PaymentsEngine::default();
+        engine
+            .process_csv(
+                "type,client,tx,amount\n\
+                 deposit,1,1,10\n\
+                 deposit,2,2,5\n\
+                 dispute,1,1,\n\
+                 deposit,3,3,1\n\
+                 dispute,3,3,\n\
+                 chargeback,3,3,\n"
+                    .as_bytes(),
+            )
+            .unwrap();
+        let mut state = Vec::new();
+        output::save_accounts(engine.accounts(), &mut state).unwrap();
+
+        let mut engine = PaymentsEngine::default();
+        engine
+            .seed_accounts(load_accounts(state.as_slice()).unwrap())
+            .unwrap();
+        engine
+            .process_csv("type,client,tx,amount\nwithdrawal,2,4,2\ndeposit,3,5,1\n".as_bytes())
+            .unwrap();
+        let accounts = engine.accounts();
+        assert_eq!(accounts[&1].held(), Amount::from(10));
+        assert_eq!(accounts[&2].available(), Amount::from(3));
+        // A locked account stays locked.
+        assert!(accounts[&3].is_locked());
+        assert!(accounts[&3].total().is_zero());
+    }
+
+    #[test]
+    fn test_engine_state_round_trip() {
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,10\n\
+                     deposit,1,2,5\n\
+                     dispute,1,1,\n";
+        let mut engine = PaymentsEngine::default();
+        engine.process_csv(input.as_bytes()).unwrap();
+        let mut saved = Vec::new();
+        save_state(&engine.export_state(), &mut saved).unwrap();
+
+        let mut restored = PaymentsEngine::default();
+        restored
+            .import_state(serde_json::from_slice(&saved).unwrap())
+            .unwrap();
+        // Dispute of the first deposit continues after the restart.
+        let next = "type,client,tx,amount\nchargeback,1,1,\n";
+        restored.process_csv(next.as_bytes()).unwrap();
+        engine.process_csv(next.as_bytes()).unwrap();
+        for engine in [&engine, &restored] {
+            let account = &engine.accounts()[&1];
+            assert_eq!(account.total(), Amount::from(5));
+            assert!(account.is_locked());
+        }
+        assert!(restored.find_transaction(2).is_some());
+
+        let mut state = restored.export_state();
+        state.version = STATE_VERSION + 1;
+        assert!(PaymentsEngine::default().import_state(state).is_err());
+    }
+
+    #[test]
+    fn test_inconsistent_state_is_refused() {
+        let header = "client,available,held,total,locked\n";
+        assert!(load_accounts(format!("{}1,1,2,3,false\n", header).as_bytes()).is_ok());
+        assert!(load_accounts(format!("{}1,1,2,4,false\n", header).as_bytes()).is_err());
+        assert!(
+            load_accounts(format!("{}1,1,0,1,false\n1,1,0,1,false\n", header).as_bytes()).is_err()
+        );
+        assert!(load_accounts(format!("{}1,x,0,1,false\n", header).as_bytes()).is_err());
+    }
+}
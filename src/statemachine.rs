@@ -0,0 +1,209 @@
+use crate::account::DepositedTransactionStatus;
+
+use serde::Serialize;
+use std::io::Write;
+
+// Dispute handling of a deposited transaction as an explicit transition table. `Account` looks up
+// every dispute, resolve and chargeback in it, the same table is exported as JSON for model
+// checking and as a Markdown table for the README. An event without a transition from the current
+// state is rejected and leaves the account unchanged.
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Event {
+    Dispute,
+    Resolve,
+    Chargeback,
+    Representment,
+}
+
+// Change of account balances made by a transition, the amount is the disputed deposit or a part of
+// it. Disputed withdrawals go through the same states, but their funds already left the account, so
+// `Account` holds them without taking available funds and a chargeback returns them to available
+// funds.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Effect {
+    // Available funds are moved to held, requires enough available funds.
+    Hold,
+    // Held funds are moved back to available, requires enough held funds.
+    Release,
+    // Held funds and total are decreased and the account is locked, requires enough held funds.
+    Reverse,
+    // Charged back funds are returned to available funds and total.
+    Recredit,
+}
+
+pub struct Transition {
+    pub from: DepositedTransactionStatus,
+    pub event: Event,
+    pub to: DepositedTransactionStatus,
+    pub effect: Effect,
+}
+
+pub const STATES: [DepositedTransactionStatus; 5] = [
+    DepositedTransactionStatus::Accepted,
+    DepositedTransactionStatus::Dispute,
+    DepositedTransactionStatus::Resolved,
+    DepositedTransactionStatus::Chargeback,
+    DepositedTransactionStatus::Represented,
+];
+
+pub const EVENTS: [Event; 4] = [
+    Event::Dispute,
+    Event::Resolve,
+    Event::Chargeback,
+    Event::Representment,
+];
+
+// Resolved and represented transactions are final, a transaction can be disputed only once. A
+// chargeback is final unless the merchant wins it back with a representment.
+pub const TRANSITIONS: [Transition; 4] = [
+    Transition {
+        from: DepositedTransactionStatus::Accepted,
+        event: Event::Dispute,
+        to: DepositedTransactionStatus::Dispute,
+        effect: Effect::Hold,
+    },
+    Transition {
+        from: DepositedTransactionStatus::Dispute,
+        event: Event::Resolve,
+        to: DepositedTransactionStatus::Resolved,
+        effect: Effect::Release,
+    },
+    Transition {
+        from: DepositedTransactionStatus::Dispute,
+        event: Event::Chargeback,
+        to: DepositedTransactionStatus::Chargeback,
+        effect: Effect::Reverse,
+    },
+    Transition {
+        from: DepositedTransactionStatus::Chargeback,
+        event: Event::Representment,
+        to: DepositedTransactionStatus::Represented,
+        effect: Effect::Recredit,
+    },
+];
+
+impl Event {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Event::Dispute => "dispute",
+            Event::Resolve => "resolve",
+            Event::Chargeback => "chargeback",
+            Event::Representment => "representment",
+        }
+    }
+}
+
+impl Effect {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Effect::Hold => "hold",
+            Effect::Release => "release",
+            Effect::Reverse => "reverse",
+            Effect::Recredit => "recredit",
+        }
+    }
+}
+
+pub fn transition(from: DepositedTransactionStatus, event: Event) -> Option<&'static Transition> {
+    TRANSITIONS
+        .iter()
+        .find(|transition| transition.from == from && transition.event == event)
+}
+
+#[derive(Serialize)]
+struct TransitionRow {
+    from: &'static str,
+    event: &'static str,
+    to: &'static str,
+    effect: &'static str,
+}
+
+#[derive(Serialize)]
+struct Model {
+    states: Vec<&'static str>,
+    events: Vec<&'static str>,
+    transitions: Vec<TransitionRow>,
+}
+
+fn model() -> Model {
+    Model {
+        states: STATES.iter().map(|state| state.name()).collect(),
+        events: EVENTS.iter().map(|event| event.name()).collect(),
+        transitions: TRANSITIONS
+            .iter()
+            .map(|transition| TransitionRow {
+                from: transition.from.name(),
+                event: transition.event.name(),
+                to: transition.to.name(),
+                effect: transition.effect.name(),
+            })
+            .collect(),
+    }
+}
+
+// Writes the transition table as JSON with lists of states, events and transitions.
+pub fn export_json<W: Write>(output: W) -> anyhow::Result<()> {
+    serde_json::to_writer_pretty(output, &model())?;
+    Ok(())
+}
+
+// Transition table as documented in the README.
+pub fn markdown_table() -> String {
+    let mut table = String::from("| from | event | to | effect |\n|---|---|---|---|\n");
+    for transition in &TRANSITIONS {
+        table.push_str(&format!(
+            "| {} | {} | {} | {} |\n",
+            transition.from.name(),
+            transition.event.name(),
+            transition.to.name(),
+            transition.effect.name()
+        ));
+    }
+    table
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_transitions_are_unique() {
+        for state in STATES {
+            for event in EVENTS {
+                let count = TRANSITIONS
+                    .iter()
+                    .filter(|transition| transition.from == state && transition.event == event)
+                    .count();
+                assert!(count <= 1, "{} {}", state.name(), event.name());
+            }
+        }
+    }
+
+    #[test]
+    fn test_final_states() {
+        for state in [
+            DepositedTransactionStatus::Resolved,
+            DepositedTransactionStatus::Represented,
+        ] {
+            assert!(EVENTS
+                .iter()
+                .all(|event| transition(state, *event).is_none()));
+        }
+    }
+
+    #[test]
+    fn test_export_json() {
+        let mut output = Vec::new();
+        export_json(&mut output).unwrap();
+        let model: serde_json::Value = serde_json::from_slice(&output).unwrap();
+        assert_eq!(model["states"].as_array().unwrap().len(), STATES.len());
+        assert_eq!(model["transitions"][0]["from"], "accepted");
+        assert_eq!(model["transitions"][0]["effect"], "hold");
+    }
+
+    #[test]
+    fn test_readme_documents_transition_table() {
+        assert!(include_str!("../README.md").contains(&markdown_table()));
+    }
+}
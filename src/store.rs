@@ -0,0 +1,64 @@
+use crate::account::Account;
+
+use serde::Serialize;
+
+// Size of a store, reported to operators of long-lived deployments.
+#[derive(Serialize, Default, PartialEq, Debug)]
+pub struct StoreStats {
+    pub accounts: usize,
+    // Deposited transactions and adjustments kept with the accounts.
+    pub transactions: usize,
+    pub adjustments: usize,
+    pub disk_bytes: u64,
+}
+
+// Result of a compaction, disk usage is measured before and after it.
+#[derive(Serialize, Default, PartialEq, Debug)]
+pub struct CompactionReport {
+    pub rewritten_accounts: usize,
+    pub removed_files: usize,
+    pub disk_bytes_before: u64,
+    pub disk_bytes_after: u64,
+}
+
+// Storage of accounts which are not kept in memory. An account is put into a store when it's moved
+// out of memory and it's taken out of the store when a new transaction for the client arrives.
+// `Archive` keeps every account in its own JSON file, other backends (for example a database)
+// implement the same trait.
+pub trait AccountStore: Send {
+    fn contains(&self, client_id: u16) -> bool;
+
+    // Takes an account out of the store, so it's kept in memory again.
+    fn take(&mut self, client_id: u16) -> anyhow::Result<Option<Account>>;
+
+    // Stores complete state of an account, including deposited transactions which may still be
+    // disputed.
+    fn put(&mut self, account: &Account) -> anyhow::Result<()>;
+
+    // Stores many accounts at once, sorted by client id. Backends which can write them faster
+    // together than one by one override it.
+    fn put_all(&mut self, accounts: &[&Account]) -> anyhow::Result<()> {
+        for account in accounts {
+            self.put(account)?;
+        }
+        Ok(())
+    }
+
+    // Reads a stored account without taking it out of the store, for queries of operators.
+    fn get(&self, client_id: u16) -> anyhow::Result<Option<Account>>;
+
+    // Ids of stored accounts in ascending order.
+    fn clients(&self) -> Vec<u16>;
+
+    // Number of stored accounts.
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn stats(&self) -> anyhow::Result<StoreStats>;
+
+    // Reclaims space left by removed accounts and interrupted writes.
+    fn compact(&mut self) -> anyhow::Result<CompactionReport>;
+}
@@ -0,0 +1,112 @@
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Historical backfills may share a host with a latency sensitive instance, so they can be slowed
+// down to a number of rows per second and run with a lower CPU and I/O priority.
+
+// Waits shorter than this are skipped, so processing isn't slowed down by sleeping for every
+// row. Rows are still limited on average.
+const MIN_WAIT: Duration = Duration::from_millis(1);
+
+// CPU niceness of a process in nice mode.
+#[cfg(unix)]
+const NICE_LEVEL: libc::c_int = 10;
+
+pub struct Throttle {
+    max_rows_per_sec: u32,
+    started: Option<Instant>,
+    rows: u64,
+}
+
+impl Throttle {
+    pub fn new(max_rows_per_sec: u32) -> Throttle {
+        Throttle {
+            max_rows_per_sec,
+            started: None,
+            rows: 0,
+        }
+    }
+
+    // How long to wait before the next row, given the time elapsed since the first row.
+    fn delay(&self, elapsed: Duration) -> Duration {
+        let due = Duration::from_secs_f64(self.rows as f64 / self.max_rows_per_sec as f64);
+        due.saturating_sub(elapsed)
+    }
+
+    // Called before every row is processed.
+    pub fn wait(&mut self) {
+        let started = *self.started.get_or_insert_with(Instant::now);
+        let delay = self.delay(started.elapsed());
+        if delay >= MIN_WAIT {
+            thread::sleep(delay);
+        }
+        self.rows += 1;
+    }
+}
+
+// Lowers CPU priority of the process and moves its I/O into idle class, where the kernel supports
+// it. Threads inherit priorities, so it should be done before any worker thread is started.
+#[cfg(unix)]
+pub fn lower_priority() -> anyhow::Result<()> {
+    // Safe: the call only changes scheduling priority of the calling process.
+    if unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, NICE_LEVEL) } != 0 {
+        return Err(anyhow::Error::new(std::io::Error::last_os_error())
+            .context("can not lower CPU priority"));
+    }
+
+    #[cfg(target_os = "linux")]
+    {
+        const IOPRIO_WHO_PROCESS: libc::c_long = 1;
+        const IOPRIO_CLASS_IDLE: libc::c_long = 3;
+        const IOPRIO_CLASS_SHIFT: libc::c_long = 13;
+        // Safe: the call only changes I/O scheduling class of the calling process.
+        let result = unsafe {
+            libc::syscall(
+                libc::SYS_ioprio_set,
+                IOPRIO_WHO_PROCESS,
+                0,
+                IOPRIO_CLASS_IDLE << IOPRIO_CLASS_SHIFT,
+            )
+        };
+        if result != 0 {
+            return Err(anyhow::Error::new(std::io::Error::last_os_error())
+                .context("can not lower I/O priority"));
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn lower_priority() -> anyhow::Result<()> {
+    anyhow::bail!("lowering of priority is supported only on Unix")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_throttle_delay() {
+        let mut throttle = Throttle::new(100);
+        assert_eq!(throttle.delay(Duration::ZERO), Duration::ZERO);
+
+        throttle.rows = 50;
+        assert_eq!(
+            throttle.delay(Duration::from_millis(200)),
+            Duration::from_millis(300)
+        );
+        assert_eq!(throttle.delay(Duration::from_secs(1)), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_throttle_limits_rows() {
+        let mut throttle = Throttle::new(1000);
+        let started = Instant::now();
+        for _ in 0..51 {
+            throttle.wait();
+        }
+        // The 51st row is due after 50 ms, short waits are skipped.
+        assert!(started.elapsed() + MIN_WAIT >= Duration::from_millis(50));
+    }
+}
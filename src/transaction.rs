@@ -1,8 +1,15 @@
-use crate::account::Account;
-
+use crate::account::{Account, DepositedTransactionStatus};
+use crate::amount_cache;
+use crate::charged_back;
+use crate::engine_config::EngineConfig;
+use crate::error::TransactionError;
+use crate::statemachine::Event;
+
+use crate::amount::Amount;
 use log::{info, warn};
-use rust_decimal::prelude::*;
+use serde::de::{self, Deserializer, IntoDeserializer, Visitor};
 use serde::Deserialize;
+use std::fmt;
 
 #[derive(Deserialize)]
 pub struct Deposit {
@@ -10,7 +17,8 @@ pub struct Deposit {
     pub client_id: u16,
     #[serde(rename(deserialize = "tx"))]
     pub tx_id: u32,
-    pub amount: Decimal,
+    #[serde(deserialize_with = "amount_cache::deserialize")]
+    pub amount: Amount,
 }
 
 #[derive(Deserialize)]
@@ -19,37 +27,256 @@ pub struct Withdrawal {
     pub client_id: u16,
     #[serde(rename(deserialize = "tx"))]
     pub tx_id: u32,
-    pub amount: Decimal,
+    #[serde(deserialize_with = "amount_cache::deserialize")]
+    pub amount: Amount,
 }
 
 #[derive(Deserialize)]
 pub struct Dispute {
     #[serde(rename(deserialize = "client"))]
-    client_id: u16,
+    pub client_id: u16,
     #[serde(rename(deserialize = "tx"))]
-    tx_id: u32,
+    pub tx_id: u32,
+    // Part of the transaction, the whole transaction if it's not given.
+    #[serde(default, deserialize_with = "deserialize_part")]
+    pub amount: Option<Amount>,
 }
 
 #[derive(Deserialize)]
 pub struct Resolve {
     #[serde(rename(deserialize = "client"))]
-    client_id: u16,
+    pub client_id: u16,
     #[serde(rename(deserialize = "tx"))]
-    tx_id: u32,
+    pub tx_id: u32,
 }
 
 #[derive(Deserialize)]
 pub struct Chargeback {
     #[serde(rename(deserialize = "client"))]
-    client_id: u16,
+    pub client_id: u16,
+    #[serde(rename(deserialize = "tx"))]
+    pub tx_id: u32,
+    // Part of the disputed amount, all of it if it's not given.
+    #[serde(default, deserialize_with = "deserialize_part")]
+    pub amount: Option<Amount>,
+}
+
+// Reverses a chargeback won back by the merchant, sent by payment networks like a chargeback.
+#[derive(Deserialize)]
+pub struct Representment {
+    #[serde(rename(deserialize = "client"))]
+    pub client_id: u16,
+    #[serde(rename(deserialize = "tx"))]
+    pub tx_id: u32,
+}
+
+#[derive(Deserialize)]
+pub struct WriteOff {
+    #[serde(rename(deserialize = "client"))]
+    pub client_id: u16,
+    #[serde(rename(deserialize = "tx"))]
+    pub tx_id: u32,
+}
+
+#[derive(Deserialize)]
+pub struct Lock {
+    #[serde(rename(deserialize = "client"))]
+    pub client_id: u16,
+    #[serde(rename(deserialize = "tx"))]
+    pub tx_id: u32,
+}
+
+#[derive(Deserialize)]
+pub struct Unlock {
+    #[serde(rename(deserialize = "client"))]
+    pub client_id: u16,
+    #[serde(rename(deserialize = "tx"))]
+    pub tx_id: u32,
+}
+
+// Amount is signed, a negative adjustment takes funds from an account.
+#[derive(Deserialize)]
+pub struct Adjust {
+    #[serde(rename(deserialize = "client"))]
+    pub client_id: u16,
+    #[serde(rename(deserialize = "tx"))]
+    pub tx_id: u32,
+    #[serde(deserialize_with = "amount_cache::deserialize")]
+    pub amount: Amount,
+}
+
+#[derive(Deserialize)]
+pub struct Annotate {
+    #[serde(rename(deserialize = "client"))]
+    pub client_id: u16,
+    #[serde(rename(deserialize = "tx"))]
+    pub tx_id: u32,
+}
+
+// Approves a pending adjustment with the same client and tx id.
+#[derive(Deserialize)]
+pub struct Approve {
+    #[serde(rename(deserialize = "client"))]
+    pub client_id: u16,
     #[serde(rename(deserialize = "tx"))]
-    tx_id: u32,
+    pub tx_id: u32,
+}
+
+// Moves funds between two accounts of the platform. The engine applies it as a withdrawal from
+// one account and a deposit to the other with the same tx id, so both legs are recorded and the
+// deposit leg may be disputed like any other deposit.
+#[derive(Deserialize)]
+pub struct Transfer {
+    pub from_client: u16,
+    pub to_client: u16,
+    #[serde(rename(deserialize = "tx"))]
+    pub tx_id: u32,
+    #[serde(deserialize_with = "amount_cache::deserialize")]
+    pub amount: Amount,
+}
+
+impl Transfer {
+    pub fn legs(&self) -> (Withdrawal, Deposit) {
+        (
+            Withdrawal {
+                client_id: self.from_client,
+                tx_id: self.tx_id,
+                amount: self.amount,
+            },
+            Deposit {
+                client_id: self.to_client,
+                tx_id: self.tx_id,
+                amount: self.amount,
+            },
+        )
+    }
 }
 
 #[derive(Deserialize)]
 pub struct Transaction {
     #[serde(flatten)]
     pub tx_type: TransactionType,
+    // Unix time in seconds. It's optional as time is needed only by time based features.
+    #[serde(default)]
+    pub timestamp: Option<u64>,
+    // Operator and reason are mandatory for admin transactions only.
+    #[serde(default, deserialize_with = "deserialize_text")]
+    pub operator: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_text")]
+    pub reason: Option<String>,
+    // Operator who approved a pending adjustment.
+    #[serde(skip)]
+    pub approved_by: Option<String>,
+}
+
+// Flattened CSV fields are deserialized by guessing their type, so an operator id like `1001`
+// comes as a number. Any scalar is taken as text and an empty field as a missing one.
+fn deserialize_text<'de, D>(deserializer: D) -> Result<Option<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    struct TextVisitor;
+
+    impl<'de> Visitor<'de> for TextVisitor {
+        type Value = Option<String>;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("text")
+        }
+
+        fn visit_str<E: de::Error>(self, value: &str) -> Result<Option<String>, E> {
+            match value.trim() {
+                "" => Ok(None),
+                value => Ok(Some(value.to_string())),
+            }
+        }
+
+        fn visit_u64<E: de::Error>(self, value: u64) -> Result<Option<String>, E> {
+            Ok(Some(value.to_string()))
+        }
+
+        fn visit_i64<E: de::Error>(self, value: i64) -> Result<Option<String>, E> {
+            Ok(Some(value.to_string()))
+        }
+
+        fn visit_f64<E: de::Error>(self, value: f64) -> Result<Option<String>, E> {
+            Ok(Some(value.to_string()))
+        }
+
+        fn visit_bool<E: de::Error>(self, value: bool) -> Result<Option<String>, E> {
+            Ok(Some(value.to_string()))
+        }
+
+        fn visit_none<E: de::Error>(self) -> Result<Option<String>, E> {
+            Ok(None)
+        }
+
+        fn visit_unit<E: de::Error>(self) -> Result<Option<String>, E> {
+            Ok(None)
+        }
+
+        fn visit_some<D: Deserializer<'de>>(
+            self,
+            deserializer: D,
+        ) -> Result<Option<String>, D::Error> {
+            deserializer.deserialize_any(TextVisitor)
+        }
+    }
+
+    deserializer.deserialize_any(TextVisitor)
+}
+
+// Amount of a dispute or a chargeback is optional, an empty field means the whole amount.
+fn deserialize_part<'de, D>(deserializer: D) -> Result<Option<Amount>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    struct PartVisitor;
+
+    impl<'de> Visitor<'de> for PartVisitor {
+        type Value = Option<Amount>;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("a decimal number or nothing")
+        }
+
+        fn visit_str<E: de::Error>(self, value: &str) -> Result<Option<Amount>, E> {
+            match value.trim() {
+                "" => Ok(None),
+                value => amount_cache::deserialize(IntoDeserializer::<E>::into_deserializer(value))
+                    .map(Some),
+            }
+        }
+
+        fn visit_u64<E: de::Error>(self, value: u64) -> Result<Option<Amount>, E> {
+            Ok(Some(Amount::from(value)))
+        }
+
+        fn visit_i64<E: de::Error>(self, value: i64) -> Result<Option<Amount>, E> {
+            Ok(Some(Amount::from(value)))
+        }
+
+        fn visit_f64<E: de::Error>(self, value: f64) -> Result<Option<Amount>, E> {
+            amount_cache::deserialize(IntoDeserializer::<E>::into_deserializer(value)).map(Some)
+        }
+
+        fn visit_none<E: de::Error>(self) -> Result<Option<Amount>, E> {
+            Ok(None)
+        }
+
+        fn visit_unit<E: de::Error>(self) -> Result<Option<Amount>, E> {
+            Ok(None)
+        }
+
+        fn visit_some<D: Deserializer<'de>>(
+            self,
+            deserializer: D,
+        ) -> Result<Option<Amount>, D::Error> {
+            deserializer.deserialize_any(PartVisitor)
+        }
+    }
+
+    deserializer.deserialize_any(PartVisitor)
 }
 
 #[derive(Deserialize)]
@@ -65,10 +292,35 @@ pub enum TransactionType {
     Resolve(Resolve),
     #[serde(rename(deserialize = "chargeback"))]
     Chargeback(Chargeback),
+    #[serde(rename(deserialize = "representment"))]
+    Representment(Representment),
+    #[serde(rename(deserialize = "write_off"))]
+    WriteOff(WriteOff),
+    #[serde(rename(deserialize = "lock"))]
+    Lock(Lock),
+    #[serde(rename(deserialize = "unlock"))]
+    Unlock(Unlock),
+    #[serde(rename(deserialize = "adjust"))]
+    Adjust(Adjust),
+    #[serde(rename(deserialize = "annotate"))]
+    Annotate(Annotate),
+    #[serde(rename(deserialize = "approve"))]
+    Approve(Approve),
+    #[serde(rename(deserialize = "transfer"))]
+    Transfer(Transfer),
 }
 
 pub trait Process {
-    fn process(&self, account: &mut Account);
+    fn process_with(
+        &self,
+        account: &mut Account,
+        config: &EngineConfig,
+    ) -> Result<(), TransactionError>;
+
+    // Applies a transaction by default policies of an engine.
+    fn process(&self, account: &mut Account) -> Result<(), TransactionError> {
+        self.process_with(account, &EngineConfig::default())
+    }
 }
 
 impl Transaction {
@@ -79,152 +331,587 @@ impl Transaction {
             TransactionType::Dispute(transaction) => transaction.client_id,
             TransactionType::Resolve(transaction) => transaction.client_id,
             TransactionType::Chargeback(transaction) => transaction.client_id,
+            TransactionType::Representment(transaction) => transaction.client_id,
+            TransactionType::WriteOff(transaction) => transaction.client_id,
+            TransactionType::Lock(transaction) => transaction.client_id,
+            TransactionType::Unlock(transaction) => transaction.client_id,
+            TransactionType::Adjust(transaction) => transaction.client_id,
+            TransactionType::Annotate(transaction) => transaction.client_id,
+            TransactionType::Approve(transaction) => transaction.client_id,
+            TransactionType::Transfer(transaction) => transaction.from_client,
+        }
+    }
+
+    pub fn tx_id(&self) -> u32 {
+        match &self.tx_type {
+            TransactionType::Deposit(transaction) => transaction.tx_id,
+            TransactionType::Withdrawal(transaction) => transaction.tx_id,
+            TransactionType::Dispute(transaction) => transaction.tx_id,
+            TransactionType::Resolve(transaction) => transaction.tx_id,
+            TransactionType::Chargeback(transaction) => transaction.tx_id,
+            TransactionType::Representment(transaction) => transaction.tx_id,
+            TransactionType::WriteOff(transaction) => transaction.tx_id,
+            TransactionType::Lock(transaction) => transaction.tx_id,
+            TransactionType::Unlock(transaction) => transaction.tx_id,
+            TransactionType::Adjust(transaction) => transaction.tx_id,
+            TransactionType::Annotate(transaction) => transaction.tx_id,
+            TransactionType::Approve(transaction) => transaction.tx_id,
+            TransactionType::Transfer(transaction) => transaction.tx_id,
+        }
+    }
+
+    // Admin transactions must tell who made them and why, otherwise they are rejected.
+    pub fn has_admin_fields(&self) -> bool {
+        if !self.tx_type.is_admin() {
+            return true;
+        }
+
+        if self.operator.is_none() || self.reason.is_none() {
+            warn!(
+                "admin transaction for account {} has no operator or reason. ignoring processing tx.",
+                self.client_id()
+            );
+            return false;
+        }
+
+        true
+    }
+}
+
+impl TransactionType {
+    // Name of the type as it's given in input.
+    pub fn name(&self) -> &'static str {
+        match self {
+            TransactionType::Deposit(_) => "deposit",
+            TransactionType::Withdrawal(_) => "withdrawal",
+            TransactionType::Dispute(_) => "dispute",
+            TransactionType::Resolve(_) => "resolve",
+            TransactionType::Chargeback(_) => "chargeback",
+            TransactionType::Representment(_) => "representment",
+            TransactionType::WriteOff(_) => "write_off",
+            TransactionType::Lock(_) => "lock",
+            TransactionType::Unlock(_) => "unlock",
+            TransactionType::Adjust(_) => "adjust",
+            TransactionType::Annotate(_) => "annotate",
+            TransactionType::Approve(_) => "approve",
+            TransactionType::Transfer(_) => "transfer",
+        }
+    }
+
+    pub fn is_admin(&self) -> bool {
+        matches!(
+            self,
+            TransactionType::WriteOff(_)
+                | TransactionType::Lock(_)
+                | TransactionType::Unlock(_)
+                | TransactionType::Adjust(_)
+                | TransactionType::Annotate(_)
+                | TransactionType::Approve(_)
+        )
+    }
+
+    // Transactions which may open an account of an unknown client: deposits and credits by
+    // operators, which are used to migrate balances, and approvals, which may release such a
+    // credit. Any other transaction of an unknown client can't be applied.
+    pub fn opens_account(&self) -> bool {
+        match self {
+            TransactionType::Deposit(_) | TransactionType::Approve(_) => true,
+            TransactionType::Adjust(adjust) => adjust.amount > Amount::from(0),
+            _ => false,
         }
     }
 }
 
 impl Process for TransactionType {
-    fn process(&self, account: &mut Account) {
+    fn process_with(
+        &self,
+        account: &mut Account,
+        config: &EngineConfig,
+    ) -> Result<(), TransactionError> {
         match self {
-            TransactionType::Deposit(transaction) => transaction.process(account),
-            TransactionType::Withdrawal(transaction) => transaction.process(account),
-            TransactionType::Dispute(transaction) => transaction.process(account),
-            TransactionType::Resolve(transaction) => transaction.process(account),
-            TransactionType::Chargeback(transaction) => transaction.process(account),
+            TransactionType::Deposit(transaction) => transaction.process_with(account, config),
+            TransactionType::Withdrawal(transaction) => transaction.process_with(account, config),
+            TransactionType::Dispute(transaction) => transaction.process_with(account, config),
+            TransactionType::Resolve(transaction) => transaction.process_with(account, config),
+            TransactionType::Chargeback(transaction) => transaction.process_with(account, config),
+            TransactionType::Representment(transaction) => {
+                transaction.process_with(account, config)
+            }
+            TransactionType::WriteOff(transaction) => transaction.process_with(account, config),
+            TransactionType::Lock(transaction) => transaction.process_with(account, config),
+            TransactionType::Unlock(transaction) => transaction.process_with(account, config),
+            TransactionType::Adjust(transaction) => transaction.process_with(account, config),
+            TransactionType::Annotate(transaction) => transaction.process_with(account, config),
+            TransactionType::Approve(transaction) => transaction.process_with(account, config),
+            TransactionType::Transfer(transaction) => transaction.process_with(account, config),
         }
     }
 }
 
 impl Process for Deposit {
-    fn process(&self, account: &mut Account) {
+    fn process_with(
+        &self,
+        account: &mut Account,
+        config: &EngineConfig,
+    ) -> Result<(), TransactionError> {
         info!(
             "processing tx: {} (deposit) for account: {}",
             self.tx_id,
             account.id()
         );
 
-        if !account.is_locked() {
-            if !account.deposit(self) {
-                warn!("can not process deposit for account {}.", account.id());
-            }
-        } else {
+        if account.is_locked() && !config.locked_policy.deposits {
             warn!(
                 "account {} is locked. ignoring processing tx.",
                 account.id()
             );
+            return Err(TransactionError::AccountLocked);
+        }
+
+        if let Err(error) = account.deposit_with(self, config) {
+            warn!("can not process deposit for account {}.", account.id());
+            return Err(error);
         }
+
+        Ok(())
     }
 }
 
 impl Process for Withdrawal {
-    fn process(&self, account: &mut Account) {
+    fn process_with(
+        &self,
+        account: &mut Account,
+        config: &EngineConfig,
+    ) -> Result<(), TransactionError> {
         info!(
             "processing tx: {} (withdrawal) for account: {}",
             self.tx_id,
             account.id()
         );
 
-        if !account.is_locked() {
-            if !account.withdrawal(self) {
-                warn!("can not process withdrawal for account {}.", account.id());
-            }
-        } else {
+        if account.is_locked() && !config.locked_policy.withdrawals {
             warn!(
                 "account {} is locked. ignoring processing tx.",
                 account.id()
             );
+            return Err(TransactionError::AccountLocked);
         }
+
+        if let Err(error) = account.withdrawal_with(self, config) {
+            warn!("can not process withdrawal for account {}.", account.id());
+            return Err(error);
+        }
+
+        Ok(())
     }
 }
 
-// Currently it's possible only to dispute deposit type of transactions.
-// It should be discussed if support for disputing withdrawals is also needed and implement it accordingly.
+// Withdrawals can be disputed only if withdrawal disputes are enabled, otherwise they aren't
+// recorded.
 impl Process for Dispute {
-    fn process(&self, account: &mut Account) {
+    fn process_with(
+        &self,
+        account: &mut Account,
+        config: &EngineConfig,
+    ) -> Result<(), TransactionError> {
         info!(
             "processing tx: {} (dispute) for account: {}",
             self.tx_id,
             account.id()
         );
 
-        if !account.is_locked() {
-            if !account.set_transaction_as_dispute(self.tx_id) {
-                warn!(
-                    "tx {} can not be set to in dispute mode. ignoring processing tx.",
-                    self.tx_id
-                );
-            }
-        } else {
+        // A chargeback locks the account, so a dispute of a charged back transaction is handled
+        // by charged back policy before the account lock.
+        if account
+            .find_transaction(self.tx_id)
+            .map(|transaction| transaction.status())
+            == Some(DepositedTransactionStatus::Chargeback)
+        {
+            charged_back::dispute_rejected(config.charged_back_policy, account.id(), self.tx_id);
+            return Err(TransactionError::ChargedBack);
+        }
+
+        if account.is_locked()
+            && !config.locked_policy.disputes
+            && !config.dispute_policy.locked_accounts
+        {
             warn!(
                 "account {} is locked. ignoring processing tx.",
                 account.id()
             );
+            return Err(TransactionError::AccountLocked);
         }
+
+        let disputed = account.apply_event_with(self.tx_id, Event::Dispute, self.amount, config);
+        if let Err(error) = disputed {
+            warn!(
+                "tx {} can not be set to in dispute mode. ignoring processing tx.",
+                self.tx_id
+            );
+            return Err(error);
+        }
+
+        Ok(())
     }
 }
 
 impl Process for Resolve {
-    fn process(&self, account: &mut Account) {
+    fn process_with(
+        &self,
+        account: &mut Account,
+        config: &EngineConfig,
+    ) -> Result<(), TransactionError> {
         info!(
             "processing tx: {} (resolve) for account: {}",
             self.tx_id,
             account.id()
         );
 
-        if !account.is_locked() {
-            if !account.set_transaction_as_resolved(self.tx_id) {
-                warn!(
-                    "tx {} can not be set to resolved mode. ignoring processing tx.",
-                    self.tx_id
-                );
-            }
-        } else {
+        if account.is_locked()
+            && !config.locked_policy.resolves
+            && !config.dispute_policy.locked_accounts
+        {
             warn!(
                 "account {} is locked. ignoring processing tx.",
                 account.id()
             );
+            return Err(TransactionError::AccountLocked);
+        }
+
+        if let Err(error) = account.apply_event_with(self.tx_id, Event::Resolve, None, config) {
+            warn!(
+                "tx {} can not be set to resolved mode. ignoring processing tx.",
+                self.tx_id
+            );
+            return Err(error);
         }
+
+        Ok(())
     }
 }
 
 impl Process for Chargeback {
-    fn process(&self, account: &mut Account) {
+    fn process_with(
+        &self,
+        account: &mut Account,
+        config: &EngineConfig,
+    ) -> Result<(), TransactionError> {
         info!(
             "processing tx: {} (chargeback) for account: {}",
             self.tx_id,
             account.id()
         );
 
-        if !account.is_locked() {
-            if !account.set_transaction_as_chargeback(self.tx_id) {
-                warn!(
-                    "tx {} can not be set to chargeback mode. ignoring processing tx.",
-                    self.tx_id
-                );
-            }
-        } else {
+        if account.is_locked()
+            && !config.locked_policy.chargebacks
+            && !config.dispute_policy.locked_accounts
+        {
             warn!(
                 "account {} is locked. ignoring processing tx.",
                 account.id()
             );
+            return Err(TransactionError::AccountLocked);
+        }
+
+        let charged_back =
+            account.apply_event_with(self.tx_id, Event::Chargeback, self.amount, config);
+        if let Err(error) = charged_back {
+            warn!(
+                "tx {} can not be set to chargeback mode. ignoring processing tx.",
+                self.tx_id
+            );
+            return Err(error);
+        }
+
+        Ok(())
+    }
+}
+
+// A chargeback locks the account, so a representment is processed also on locked accounts.
+impl Process for Representment {
+    fn process_with(
+        &self,
+        account: &mut Account,
+        config: &EngineConfig,
+    ) -> Result<(), TransactionError> {
+        info!(
+            "processing tx: {} (representment) for account: {}",
+            self.tx_id,
+            account.id()
+        );
+
+        if let Err(error) = account.apply_event_with(self.tx_id, Event::Representment, None, config)
+        {
+            warn!(
+                "tx {} can not be set to represented mode. ignoring processing tx.",
+                self.tx_id
+            );
+            return Err(error);
+        }
+
+        Ok(())
+    }
+}
+
+// Write-off is an admin transaction so it's processed also on locked accounts, which are the ones
+// most likely to end up owing funds after a chargeback.
+impl Process for WriteOff {
+    fn process_with(
+        &self,
+        account: &mut Account,
+        _: &EngineConfig,
+    ) -> Result<(), TransactionError> {
+        info!(
+            "processing tx: {} (write_off) for account: {}",
+            self.tx_id,
+            account.id()
+        );
+
+        if let Err(error) = account.write_off(self) {
+            warn!(
+                "tx {} can not write off balance. ignoring processing tx.",
+                self.tx_id
+            );
+            return Err(error);
+        }
+
+        Ok(())
+    }
+}
+
+// Other admin transactions are also processed on locked accounts, as remediation is mostly needed
+// for them.
+impl Process for Lock {
+    fn process_with(
+        &self,
+        account: &mut Account,
+        _: &EngineConfig,
+    ) -> Result<(), TransactionError> {
+        info!(
+            "processing tx: {} (lock) for account: {}",
+            self.tx_id,
+            account.id()
+        );
+
+        if let Err(error) = account.lock(self) {
+            warn!(
+                "tx {} can not lock account. ignoring processing tx.",
+                self.tx_id
+            );
+            return Err(error);
+        }
+
+        Ok(())
+    }
+}
+
+impl Process for Unlock {
+    fn process_with(
+        &self,
+        account: &mut Account,
+        _: &EngineConfig,
+    ) -> Result<(), TransactionError> {
+        info!(
+            "processing tx: {} (unlock) for account: {}",
+            self.tx_id,
+            account.id()
+        );
+
+        if let Err(error) = account.unlock(self) {
+            warn!(
+                "tx {} can not unlock account. ignoring processing tx.",
+                self.tx_id
+            );
+            return Err(error);
+        }
+
+        Ok(())
+    }
+}
+
+impl Process for Adjust {
+    fn process_with(
+        &self,
+        account: &mut Account,
+        config: &EngineConfig,
+    ) -> Result<(), TransactionError> {
+        info!(
+            "processing tx: {} (adjust) for account: {}",
+            self.tx_id,
+            account.id()
+        );
+
+        if let Err(error) = account.adjust_with(self, config) {
+            warn!(
+                "tx {} can not adjust balance. ignoring processing tx.",
+                self.tx_id
+            );
+            return Err(error);
+        }
+
+        Ok(())
+    }
+}
+
+impl Process for Annotate {
+    fn process_with(
+        &self,
+        account: &mut Account,
+        _: &EngineConfig,
+    ) -> Result<(), TransactionError> {
+        info!(
+            "processing tx: {} (annotate) for account: {}",
+            self.tx_id,
+            account.id()
+        );
+
+        if let Err(error) = account.annotate(self) {
+            warn!(
+                "tx {} can not annotate account. ignoring processing tx.",
+                self.tx_id
+            );
+            return Err(error);
         }
+
+        Ok(())
+    }
+}
+
+// Approvals are handled before transactions are processed and they are replaced with an approved
+// adjustment, so an approval which gets here has nothing to approve.
+impl Process for Approve {
+    fn process_with(
+        &self,
+        account: &mut Account,
+        _: &EngineConfig,
+    ) -> Result<(), TransactionError> {
+        warn!(
+            "tx {} for account {} is not pending approval. ignoring processing tx.",
+            self.tx_id,
+            account.id()
+        );
+        Err(TransactionError::NotPendingApproval)
+    }
+}
+
+// A transfer changes two accounts, so the engine applies its legs instead. A transfer which gets
+// here can't be applied to a single account.
+impl Process for Transfer {
+    fn process_with(
+        &self,
+        account: &mut Account,
+        _: &EngineConfig,
+    ) -> Result<(), TransactionError> {
+        warn!(
+            "tx {} is a transfer which can't be applied to account {} alone. ignoring processing tx.",
+            self.tx_id,
+            account.id()
+        );
+        Err(TransactionError::WrongAccount {
+            client: self.to_client,
+            account: account.id(),
+        })
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::account::BalanceSnapshot;
+    use csv::{ReaderBuilder, Trim};
+    use std::str::FromStr;
+
+    fn set_locked(account: &mut Account, locked: bool) {
+        let balances = BalanceSnapshot {
+            locked,
+            ..account.balances()
+        };
+        account.restore_balances(balances).unwrap();
+    }
+
+    #[test]
+    fn test_deserialize_optional_timestamp() {
+        let data = "type,client,tx,amount,timestamp\n\
+                    deposit,1,1,1.5,1651363200\n\
+                    dispute,1,1,,\n";
+        let mut reader = ReaderBuilder::new()
+            .trim(Trim::All)
+            .from_reader(data.as_bytes());
+        let transactions: Vec<Transaction> = reader
+            .deserialize()
+            .collect::<Result<_, csv::Error>>()
+            .unwrap();
+
+        assert_eq!(transactions.len(), 2);
+        assert_eq!(transactions[0].timestamp, Some(1651363200));
+        assert_eq!(transactions[1].timestamp, None);
+    }
+
+    #[test]
+    fn test_deserialize_without_timestamp_column() {
+        let data = "type,client,tx,amount\ndeposit,1,1,1.5\n";
+        let mut reader = ReaderBuilder::new()
+            .trim(Trim::All)
+            .from_reader(data.as_bytes());
+        let transaction: Transaction = reader.deserialize().next().unwrap().unwrap();
+
+        assert_eq!(transaction.client_id(), 1);
+        assert_eq!(transaction.timestamp, None);
+    }
+
+    #[test]
+    fn test_deserialize_admin_transactions() {
+        let data = "type,client,tx,amount,operator,reason\n\
+                    lock,1,1,,1001,fraud suspected\n\
+                    adjust,1,2,-2.5,ops,fee refund\n\
+                    unlock,1,3,,,\n";
+        let mut reader = ReaderBuilder::new()
+            .trim(Trim::All)
+            .from_reader(data.as_bytes());
+        let transactions: Vec<Transaction> = reader
+            .deserialize()
+            .collect::<Result<_, csv::Error>>()
+            .unwrap();
+
+        assert_eq!(transactions[0].operator.as_deref(), Some("1001"));
+        assert_eq!(transactions[0].reason.as_deref(), Some("fraud suspected"));
+        assert!(transactions[0].has_admin_fields());
+        match &transactions[1].tx_type {
+            TransactionType::Adjust(adjust) => {
+                assert_eq!(adjust.amount, Amount::from_str("-2.5").unwrap())
+            }
+            _ => panic!("expected adjust transaction"),
+        }
+        assert_eq!(transactions[2].operator, None);
+        assert!(!transactions[2].has_admin_fields());
+    }
+
+    #[test]
+    fn test_client_transaction_needs_no_admin_fields() {
+        let data = "type,client,tx,amount\ndeposit,1,1,1.5\n";
+        let mut reader = ReaderBuilder::new()
+            .trim(Trim::All)
+            .from_reader(data.as_bytes());
+        let transaction: Transaction = reader.deserialize().next().unwrap().unwrap();
+
+        assert!(!transaction.tx_type.is_admin());
+        assert!(transaction.has_admin_fields());
+    }
 
     #[test]
     fn test_get_client_id_from_transaction() {
         let withdrawal = Withdrawal {
             client_id: 12345,
             tx_id: 22334456,
-            amount: Decimal::from_str("125.9999").unwrap(),
+            amount: Amount::from_str("125.9999").unwrap(),
         };
 
         let transaction = Transaction {
             tx_type: TransactionType::Withdrawal(withdrawal),
+            timestamp: None,
+            operator: None,
+            reason: None,
+            approved_by: None,
         };
 
         assert_eq!(transaction.client_id(), 12345);
@@ -237,20 +924,24 @@ mod test {
         let deposit = Deposit {
             client_id: 12345,
             tx_id: 22334456,
-            amount: Decimal::from_str("125.9999").unwrap(),
+            amount: Amount::from_str("125.9999").unwrap(),
         };
 
         let transaction = Transaction {
             tx_type: TransactionType::Deposit(deposit),
+            timestamp: None,
+            operator: None,
+            reason: None,
+            approved_by: None,
         };
 
-        transaction.tx_type.process(&mut account);
+        assert!(transaction.tx_type.process(&mut account).is_ok());
 
-        assert_eq!(account.available, Decimal::from_str("125.9999").unwrap());
-        assert_eq!(account.held, Decimal::from_str("0").unwrap());
-        assert_eq!(account.total, Decimal::from_str("125.9999").unwrap());
+        assert_eq!(account.available(), Amount::from_str("125.9999").unwrap());
+        assert_eq!(account.held(), Amount::from_str("0").unwrap());
+        assert_eq!(account.total(), Amount::from_str("125.9999").unwrap());
         assert!(!account.is_locked());
-        assert_eq!(account.transactions.len(), 1);
+        assert_eq!(account.transactions().len(), 1);
     }
 
     #[test]
@@ -260,21 +951,28 @@ mod test {
         let deposit = Deposit {
             client_id: 12345,
             tx_id: 22334456,
-            amount: Decimal::from_str("125.9999").unwrap(),
+            amount: Amount::from_str("125.9999").unwrap(),
         };
 
         let transaction = Transaction {
             tx_type: TransactionType::Deposit(deposit),
+            timestamp: None,
+            operator: None,
+            reason: None,
+            approved_by: None,
         };
 
-        account.locked = true;
-        transaction.tx_type.process(&mut account);
+        set_locked(&mut account, true);
+        assert_eq!(
+            transaction.tx_type.process(&mut account),
+            Err(TransactionError::AccountLocked)
+        );
 
-        assert_eq!(account.available, Decimal::from_str("0").unwrap());
-        assert_eq!(account.held, Decimal::from_str("0").unwrap());
-        assert_eq!(account.total, Decimal::from_str("0").unwrap());
+        assert_eq!(account.available(), Amount::from_str("0").unwrap());
+        assert_eq!(account.held(), Amount::from_str("0").unwrap());
+        assert_eq!(account.total(), Amount::from_str("0").unwrap());
         assert!(account.is_locked());
-        assert_eq!(account.transactions.len(), 0);
+        assert_eq!(account.transactions().len(), 0);
     }
 
     #[test]
@@ -284,28 +982,36 @@ mod test {
         let deposit = Deposit {
             client_id: 12345,
             tx_id: 22334456,
-            amount: Decimal::from_str("125.9999").unwrap(),
+            amount: Amount::from_str("125.9999").unwrap(),
         };
         let transaction = Transaction {
             tx_type: TransactionType::Deposit(deposit),
+            timestamp: None,
+            operator: None,
+            reason: None,
+            approved_by: None,
         };
-        transaction.tx_type.process(&mut account);
+        assert!(transaction.tx_type.process(&mut account).is_ok());
 
         let withdrawal = Withdrawal {
             client_id: 12345,
             tx_id: 22334456,
-            amount: Decimal::from_str("125.9999").unwrap(),
+            amount: Amount::from_str("125.9999").unwrap(),
         };
         let transaction = Transaction {
             tx_type: TransactionType::Withdrawal(withdrawal),
+            timestamp: None,
+            operator: None,
+            reason: None,
+            approved_by: None,
         };
-        transaction.tx_type.process(&mut account);
+        assert!(transaction.tx_type.process(&mut account).is_ok());
 
-        assert_eq!(account.available, Decimal::from_str("0").unwrap());
-        assert_eq!(account.held, Decimal::from_str("0").unwrap());
-        assert_eq!(account.total, Decimal::from_str("0").unwrap());
+        assert_eq!(account.available(), Amount::from_str("0").unwrap());
+        assert_eq!(account.held(), Amount::from_str("0").unwrap());
+        assert_eq!(account.total(), Amount::from_str("0").unwrap());
         assert!(!account.is_locked());
-        assert_eq!(account.transactions.len(), 1);
+        assert_eq!(account.transactions().len(), 1);
     }
 
     #[test]
@@ -315,29 +1021,40 @@ mod test {
         let deposit = Deposit {
             client_id: 12345,
             tx_id: 22334456,
-            amount: Decimal::from_str("125.9999").unwrap(),
+            amount: Amount::from_str("125.9999").unwrap(),
         };
         let transaction = Transaction {
             tx_type: TransactionType::Deposit(deposit),
+            timestamp: None,
+            operator: None,
+            reason: None,
+            approved_by: None,
         };
-        transaction.tx_type.process(&mut account);
+        assert!(transaction.tx_type.process(&mut account).is_ok());
 
         let withdrawal = Withdrawal {
             client_id: 12345,
             tx_id: 22334456,
-            amount: Decimal::from_str("125.9999").unwrap(),
+            amount: Amount::from_str("125.9999").unwrap(),
         };
         let transaction = Transaction {
             tx_type: TransactionType::Withdrawal(withdrawal),
+            timestamp: None,
+            operator: None,
+            reason: None,
+            approved_by: None,
         };
-        account.locked = true;
-        transaction.tx_type.process(&mut account);
+        set_locked(&mut account, true);
+        assert_eq!(
+            transaction.tx_type.process(&mut account),
+            Err(TransactionError::AccountLocked)
+        );
 
-        assert_eq!(account.available, Decimal::from_str("125.9999").unwrap());
-        assert_eq!(account.held, Decimal::from_str("0").unwrap());
-        assert_eq!(account.total, Decimal::from_str("125.9999").unwrap());
+        assert_eq!(account.available(), Amount::from_str("125.9999").unwrap());
+        assert_eq!(account.held(), Amount::from_str("0").unwrap());
+        assert_eq!(account.total(), Amount::from_str("125.9999").unwrap());
         assert!(account.is_locked());
-        assert_eq!(account.transactions.len(), 1);
+        assert_eq!(account.transactions().len(), 1);
     }
 
     #[test]
@@ -347,27 +1064,36 @@ mod test {
         let deposit = Deposit {
             client_id: 12345,
             tx_id: 22334456,
-            amount: Decimal::from_str("125.9999").unwrap(),
+            amount: Amount::from_str("125.9999").unwrap(),
         };
         let transaction = Transaction {
             tx_type: TransactionType::Deposit(deposit),
+            timestamp: None,
+            operator: None,
+            reason: None,
+            approved_by: None,
         };
-        transaction.tx_type.process(&mut account);
+        assert!(transaction.tx_type.process(&mut account).is_ok());
 
         let dispute = Dispute {
             client_id: 12345,
             tx_id: 22334456,
+            amount: None,
         };
         let transaction = Transaction {
             tx_type: TransactionType::Dispute(dispute),
+            timestamp: None,
+            operator: None,
+            reason: None,
+            approved_by: None,
         };
-        transaction.tx_type.process(&mut account);
+        assert!(transaction.tx_type.process(&mut account).is_ok());
 
-        assert_eq!(account.available, Decimal::from_str("0").unwrap());
-        assert_eq!(account.held, Decimal::from_str("125.9999").unwrap());
-        assert_eq!(account.total, Decimal::from_str("125.9999").unwrap());
+        assert_eq!(account.available(), Amount::from_str("0").unwrap());
+        assert_eq!(account.held(), Amount::from_str("125.9999").unwrap());
+        assert_eq!(account.total(), Amount::from_str("125.9999").unwrap());
         assert!(!account.is_locked());
-        assert_eq!(account.transactions.len(), 1);
+        assert_eq!(account.transactions().len(), 1);
     }
 
     #[test]
@@ -377,28 +1103,40 @@ mod test {
         let deposit = Deposit {
             client_id: 12345,
             tx_id: 22334456,
-            amount: Decimal::from_str("125.9999").unwrap(),
+            amount: Amount::from_str("125.9999").unwrap(),
         };
         let transaction = Transaction {
             tx_type: TransactionType::Deposit(deposit),
+            timestamp: None,
+            operator: None,
+            reason: None,
+            approved_by: None,
         };
-        transaction.tx_type.process(&mut account);
+        assert!(transaction.tx_type.process(&mut account).is_ok());
 
         let dispute = Dispute {
             client_id: 12345,
             tx_id: 22334456,
+            amount: None,
         };
         let transaction = Transaction {
             tx_type: TransactionType::Dispute(dispute),
+            timestamp: None,
+            operator: None,
+            reason: None,
+            approved_by: None,
         };
-        account.locked = true;
-        transaction.tx_type.process(&mut account);
+        set_locked(&mut account, true);
+        assert_eq!(
+            transaction.tx_type.process(&mut account),
+            Err(TransactionError::AccountLocked)
+        );
 
-        assert_eq!(account.available, Decimal::from_str("125.9999").unwrap());
-        assert_eq!(account.held, Decimal::from_str("0").unwrap());
-        assert_eq!(account.total, Decimal::from_str("125.9999").unwrap());
+        assert_eq!(account.available(), Amount::from_str("125.9999").unwrap());
+        assert_eq!(account.held(), Amount::from_str("0").unwrap());
+        assert_eq!(account.total(), Amount::from_str("125.9999").unwrap());
         assert!(account.is_locked());
-        assert_eq!(account.transactions.len(), 1);
+        assert_eq!(account.transactions().len(), 1);
     }
 
     #[test]
@@ -408,21 +1146,30 @@ mod test {
         let deposit = Deposit {
             client_id: 12345,
             tx_id: 22334456,
-            amount: Decimal::from_str("125.9999").unwrap(),
+            amount: Amount::from_str("125.9999").unwrap(),
         };
         let transaction = Transaction {
             tx_type: TransactionType::Deposit(deposit),
+            timestamp: None,
+            operator: None,
+            reason: None,
+            approved_by: None,
         };
-        transaction.tx_type.process(&mut account);
+        assert!(transaction.tx_type.process(&mut account).is_ok());
 
         let dispute = Dispute {
             client_id: 12345,
             tx_id: 22334456,
+            amount: None,
         };
         let transaction = Transaction {
             tx_type: TransactionType::Dispute(dispute),
+            timestamp: None,
+            operator: None,
+            reason: None,
+            approved_by: None,
         };
-        transaction.tx_type.process(&mut account);
+        assert!(transaction.tx_type.process(&mut account).is_ok());
 
         let resolve = Resolve {
             client_id: 12345,
@@ -430,14 +1177,18 @@ mod test {
         };
         let transaction = Transaction {
             tx_type: TransactionType::Resolve(resolve),
+            timestamp: None,
+            operator: None,
+            reason: None,
+            approved_by: None,
         };
-        transaction.tx_type.process(&mut account);
+        assert!(transaction.tx_type.process(&mut account).is_ok());
 
-        assert_eq!(account.available, Decimal::from_str("125.9999").unwrap());
-        assert_eq!(account.held, Decimal::from_str("0").unwrap());
-        assert_eq!(account.total, Decimal::from_str("125.9999").unwrap());
+        assert_eq!(account.available(), Amount::from_str("125.9999").unwrap());
+        assert_eq!(account.held(), Amount::from_str("0").unwrap());
+        assert_eq!(account.total(), Amount::from_str("125.9999").unwrap());
         assert!(!account.is_locked());
-        assert_eq!(account.transactions.len(), 1);
+        assert_eq!(account.transactions().len(), 1);
     }
 
     #[test]
@@ -447,21 +1198,30 @@ mod test {
         let deposit = Deposit {
             client_id: 12345,
             tx_id: 22334456,
-            amount: Decimal::from_str("125.9999").unwrap(),
+            amount: Amount::from_str("125.9999").unwrap(),
         };
         let transaction = Transaction {
             tx_type: TransactionType::Deposit(deposit),
+            timestamp: None,
+            operator: None,
+            reason: None,
+            approved_by: None,
         };
-        transaction.tx_type.process(&mut account);
+        assert!(transaction.tx_type.process(&mut account).is_ok());
 
         let dispute = Dispute {
             client_id: 12345,
             tx_id: 22334456,
+            amount: None,
         };
         let transaction = Transaction {
             tx_type: TransactionType::Dispute(dispute),
+            timestamp: None,
+            operator: None,
+            reason: None,
+            approved_by: None,
         };
-        transaction.tx_type.process(&mut account);
+        assert!(transaction.tx_type.process(&mut account).is_ok());
 
         let resolve = Resolve {
             client_id: 12345,
@@ -469,15 +1229,22 @@ mod test {
         };
         let transaction = Transaction {
             tx_type: TransactionType::Resolve(resolve),
+            timestamp: None,
+            operator: None,
+            reason: None,
+            approved_by: None,
         };
-        account.locked = true;
-        transaction.tx_type.process(&mut account);
+        set_locked(&mut account, true);
+        assert_eq!(
+            transaction.tx_type.process(&mut account),
+            Err(TransactionError::AccountLocked)
+        );
 
-        assert_eq!(account.available, Decimal::from_str("0").unwrap());
-        assert_eq!(account.held, Decimal::from_str("125.9999").unwrap());
-        assert_eq!(account.total, Decimal::from_str("125.9999").unwrap());
+        assert_eq!(account.available(), Amount::from_str("0").unwrap());
+        assert_eq!(account.held(), Amount::from_str("125.9999").unwrap());
+        assert_eq!(account.total(), Amount::from_str("125.9999").unwrap());
         assert!(account.is_locked());
-        assert_eq!(account.transactions.len(), 1);
+        assert_eq!(account.transactions().len(), 1);
     }
 
     #[test]
@@ -487,36 +1254,50 @@ mod test {
         let deposit = Deposit {
             client_id: 12345,
             tx_id: 22334456,
-            amount: Decimal::from_str("125.9999").unwrap(),
+            amount: Amount::from_str("125.9999").unwrap(),
         };
         let transaction = Transaction {
             tx_type: TransactionType::Deposit(deposit),
+            timestamp: None,
+            operator: None,
+            reason: None,
+            approved_by: None,
         };
-        transaction.tx_type.process(&mut account);
+        assert!(transaction.tx_type.process(&mut account).is_ok());
 
         let dispute = Dispute {
             client_id: 12345,
             tx_id: 22334456,
+            amount: None,
         };
         let transaction = Transaction {
             tx_type: TransactionType::Dispute(dispute),
+            timestamp: None,
+            operator: None,
+            reason: None,
+            approved_by: None,
         };
-        transaction.tx_type.process(&mut account);
+        assert!(transaction.tx_type.process(&mut account).is_ok());
 
         let chargeback = Chargeback {
             client_id: 12345,
             tx_id: 22334456,
+            amount: None,
         };
         let transaction = Transaction {
             tx_type: TransactionType::Chargeback(chargeback),
+            timestamp: None,
+            operator: None,
+            reason: None,
+            approved_by: None,
         };
-        transaction.tx_type.process(&mut account);
+        assert!(transaction.tx_type.process(&mut account).is_ok());
 
-        assert_eq!(account.available, Decimal::from_str("0").unwrap());
-        assert_eq!(account.held, Decimal::from_str("0").unwrap());
-        assert_eq!(account.total, Decimal::from_str("0").unwrap());
+        assert_eq!(account.available(), Amount::from_str("0").unwrap());
+        assert_eq!(account.held(), Amount::from_str("0").unwrap());
+        assert_eq!(account.total(), Amount::from_str("0").unwrap());
         assert!(account.is_locked());
-        assert_eq!(account.transactions.len(), 1);
+        assert_eq!(account.transactions().len(), 1);
     }
 
     #[test]
@@ -526,36 +1307,120 @@ mod test {
         let deposit = Deposit {
             client_id: 12345,
             tx_id: 22334456,
-            amount: Decimal::from_str("125.9999").unwrap(),
+            amount: Amount::from_str("125.9999").unwrap(),
         };
         let transaction = Transaction {
             tx_type: TransactionType::Deposit(deposit),
+            timestamp: None,
+            operator: None,
+            reason: None,
+            approved_by: None,
         };
-        transaction.tx_type.process(&mut account);
+        assert!(transaction.tx_type.process(&mut account).is_ok());
 
         let dispute = Dispute {
             client_id: 12345,
             tx_id: 22334456,
+            amount: None,
         };
         let transaction = Transaction {
             tx_type: TransactionType::Dispute(dispute),
+            timestamp: None,
+            operator: None,
+            reason: None,
+            approved_by: None,
         };
-        transaction.tx_type.process(&mut account);
+        assert!(transaction.tx_type.process(&mut account).is_ok());
 
         let chargeback = Chargeback {
             client_id: 12345,
             tx_id: 22334456,
+            amount: None,
         };
         let transaction = Transaction {
             tx_type: TransactionType::Chargeback(chargeback),
+            timestamp: None,
+            operator: None,
+            reason: None,
+            approved_by: None,
+        };
+        set_locked(&mut account, true);
+        assert_eq!(
+            transaction.tx_type.process(&mut account),
+            Err(TransactionError::AccountLocked)
+        );
+
+        assert_eq!(account.available(), Amount::from_str("0").unwrap());
+        assert_eq!(account.held(), Amount::from_str("125.9999").unwrap());
+        assert_eq!(account.total(), Amount::from_str("125.9999").unwrap());
+        assert!(account.is_locked());
+        assert_eq!(account.transactions().len(), 1);
+    }
+
+    #[test]
+    fn test_process_dispute_charged_back() {
+        let mut account = Account::new(12345);
+        assert!(account
+            .deposit(&Deposit {
+                client_id: 12345,
+                tx_id: 22334456,
+                amount: Amount::from_str("125.9999").unwrap(),
+            })
+            .is_ok());
+        assert!(account.set_transaction_as_dispute(22334456).is_ok());
+        assert!(account.set_transaction_as_chargeback(22334456).is_ok());
+
+        let dispute = TransactionType::Dispute(Dispute {
+            client_id: 12345,
+            tx_id: 22334456,
+            amount: None,
+        });
+        assert_eq!(
+            dispute.process(&mut account),
+            Err(TransactionError::ChargedBack)
+        );
+        // Also after the account is unlocked again.
+        set_locked(&mut account, false);
+        assert_eq!(
+            dispute.process(&mut account),
+            Err(TransactionError::ChargedBack)
+        );
+        assert_eq!(account.held(), Amount::from_str("0").unwrap());
+        assert_eq!(
+            account.find_transaction(22334456).unwrap().status(),
+            DepositedTransactionStatus::Chargeback
+        );
+    }
+
+    #[test]
+    fn test_process_write_off_account_locked() {
+        let mut account = Account::new(12345);
+        account
+            .restore_balances(BalanceSnapshot {
+                available: Amount::from_str("-125.9999").unwrap(),
+                held: Amount::from(0),
+                total: Amount::from_str("-125.9999").unwrap(),
+                locked: true,
+            })
+            .unwrap();
+
+        let write_off = WriteOff {
+            client_id: 12345,
+            tx_id: 22334456,
+        };
+        let transaction = Transaction {
+            tx_type: TransactionType::WriteOff(write_off),
+            timestamp: None,
+            operator: None,
+            reason: None,
+            approved_by: None,
         };
-        account.locked = true;
-        transaction.tx_type.process(&mut account);
+        assert!(transaction.tx_type.process(&mut account).is_ok());
 
-        assert_eq!(account.available, Decimal::from_str("0").unwrap());
-        assert_eq!(account.held, Decimal::from_str("125.9999").unwrap());
-        assert_eq!(account.total, Decimal::from_str("125.9999").unwrap());
+        assert_eq!(account.available(), Amount::from_str("0").unwrap());
+        assert_eq!(account.held(), Amount::from_str("0").unwrap());
+        assert_eq!(account.total(), Amount::from_str("0").unwrap());
         assert!(account.is_locked());
-        assert_eq!(account.transactions.len(), 1);
+        assert_eq!(account.adjustments().len(), 1);
     }
 }
@@ -0,0 +1,288 @@
+use crate::engine::PaymentsEngine;
+use crate::shutdown::{Shutdown, SHUTDOWN_POLL};
+use crate::state;
+
+use anyhow::Context;
+use log::{error, info};
+use notify::event::{AccessKind, AccessMode, ModifyKind, RenameMode};
+use notify::{Event, EventKind, RecursiveMode, Watcher};
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError};
+
+// Directory into which processed files are moved if no other is given, inside the watched one.
+pub const DEFAULT_PROCESSED_DIR: &str = "processed";
+// Directory inside the processed one into which files which can't be processed are moved.
+pub const FAILED_DIR: &str = "failed";
+// File of the engine state inside the processed directory.
+pub const STATE_FILE: &str = "state.json";
+
+// Extensions of files which are processed, other files in the directory are left alone.
+const EXTENSIONS: &[&str] = &[
+    "csv",
+    "json",
+    "ndjson",
+    "jsonl",
+    #[cfg(feature = "parquet")]
+    "parquet",
+];
+
+fn is_input_file(path: &Path) -> bool {
+    let hidden = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .is_none_or(|name| name.starts_with('.'));
+    let extension = path.extension().and_then(|ext| ext.to_str());
+    !hidden && extension.is_some_and(|ext| EXTENSIONS.contains(&ext)) && path.is_file()
+}
+
+// A file is moved under its own name, or with a number appended if a file of the same name was
+// moved before.
+fn move_file(path: &Path, dir: &Path) -> anyhow::Result<PathBuf> {
+    let name = path.file_name().unwrap_or_default();
+    let mut target = dir.join(name);
+    let mut copy = 0;
+    while target.exists() {
+        copy += 1;
+        let mut numbered = name.to_os_string();
+        numbered.push(format!(".{}", copy));
+        target = dir.join(numbered);
+    }
+    fs::rename(path, &target).with_context(|| format!("can not move {:?} to {:?}", path, dir))?;
+    Ok(target)
+}
+
+// Ingestion of a directory: files which appear in it are processed one by one by the same engine,
+// the engine state is saved after every file and the file is moved into the processed directory.
+// A file which can't be processed is moved into the failed directory and the engine goes back to
+// the state saved before it, so a half processed file never changes the state.
+pub struct DirectoryWatch<F> {
+    dir: PathBuf,
+    processed_dir: PathBuf,
+    failed_dir: PathBuf,
+    state_file: PathBuf,
+    create_engine: F,
+    engine: PaymentsEngine,
+    shutdown: Shutdown,
+}
+
+impl<F> DirectoryWatch<F>
+where
+    F: FnMut() -> anyhow::Result<PaymentsEngine>,
+{
+    // Continues from the saved state if there is one.
+    pub fn open(
+        dir: PathBuf,
+        processed_dir: Option<PathBuf>,
+        mut create_engine: F,
+    ) -> anyhow::Result<DirectoryWatch<F>> {
+        let processed_dir = processed_dir.unwrap_or_else(|| dir.join(DEFAULT_PROCESSED_DIR));
+        let failed_dir = processed_dir.join(FAILED_DIR);
+        fs::create_dir_all(&failed_dir)
+            .with_context(|| format!("can not create directory {:?}", failed_dir))?;
+        let state_file = processed_dir.join(STATE_FILE);
+        let engine = load_engine(&mut create_engine, &state_file)?;
+        Ok(DirectoryWatch {
+            dir,
+            processed_dir,
+            failed_dir,
+            state_file,
+            create_engine,
+            engine,
+            shutdown: Shutdown::default(),
+        })
+    }
+
+    // The watch stops when a shutdown is requested. A file which was being processed then is left
+    // in the directory and the engine goes back to the state before it, so it's processed whole
+    // after a restart. Engines should be created with the same shutdown.
+    pub fn set_shutdown(&mut self, shutdown: Shutdown) {
+        self.shutdown = shutdown;
+    }
+
+    pub fn engine(&self) -> &PaymentsEngine {
+        &self.engine
+    }
+
+    // Input files waiting in the directory, in the order of their names.
+    pub fn pending(&self) -> anyhow::Result<Vec<PathBuf>> {
+        let entries = fs::read_dir(&self.dir)
+            .with_context(|| format!("can not read directory {:?}", self.dir))?;
+        let mut pending = Vec::new();
+        for entry in entries {
+            let path = entry?.path();
+            if is_input_file(&path) {
+                pending.push(path);
+            }
+        }
+        pending.sort();
+        Ok(pending)
+    }
+
+    // Processes all files waiting in the directory. Returns the number of processed files.
+    pub fn process_pending(&mut self) -> anyhow::Result<usize> {
+        let pending = self.pending()?;
+        let mut processed = 0;
+        for path in &pending {
+            if self.shutdown.requested() {
+                break;
+            }
+            self.process(path)?;
+            processed += 1;
+        }
+        Ok(processed)
+    }
+
+    // Only a failure to save the state or to move the file stops the watch.
+    fn process(&mut self, path: &Path) -> anyhow::Result<()> {
+        let file_path = OsString::from(path);
+        let processed = self.engine.process_file(&file_path);
+        if self.shutdown.requested() {
+            info!(
+                "shutdown requested, {:?} is left to be processed again",
+                path
+            );
+            self.engine = load_engine(&mut self.create_engine, &self.state_file)?;
+            return Ok(());
+        }
+        match processed {
+            Ok(()) => {
+                self.save_state()?;
+                let target = move_file(path, &self.processed_dir)?;
+                info!("processed {:?}, moved to {:?}", path, target);
+            }
+            Err(err) => {
+                error!("can not process {:?}: {:#}", path, err);
+                self.engine = load_engine(&mut self.create_engine, &self.state_file)?;
+                let target = move_file(path, &self.failed_dir)?;
+                info!("moved {:?} to {:?}", path, target);
+            }
+        }
+        Ok(())
+    }
+
+    // State is written next to the state file and renamed over it, so it's never half written.
+    fn save_state(&self) -> anyhow::Result<()> {
+        let saved = self.state_file.with_extension("json.tmp");
+        let file =
+            File::create(&saved).with_context(|| format!("can not create state {:?}", saved))?;
+        state::save_state(&self.engine.export_state(), BufWriter::new(file))?;
+        fs::rename(&saved, &self.state_file)
+            .with_context(|| format!("can not save state {:?}", self.state_file))
+    }
+
+    // Processes files already in the directory and then every file which is closed after writing
+    // or moved into it, until the watch fails or a shutdown is requested.
+    pub fn run(&mut self) -> anyhow::Result<()> {
+        let (sender, receiver) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(sender).context("can not start watcher")?;
+        watcher
+            .watch(&self.dir, RecursiveMode::NonRecursive)
+            .with_context(|| format!("can not watch directory {:?}", self.dir))?;
+        info!("watching {:?}", self.dir);
+        self.process_pending()?;
+
+        while !self.shutdown.requested() {
+            let event: Event = match receiver.recv_timeout(SHUTDOWN_POLL) {
+                Ok(event) => event.context("can not watch directory")?,
+                Err(RecvTimeoutError::Timeout) => continue,
+                Err(RecvTimeoutError::Disconnected) => break,
+            };
+            let path = match (event.kind, event.paths.as_slice()) {
+                (EventKind::Access(AccessKind::Close(AccessMode::Write)), [path])
+                | (EventKind::Modify(ModifyKind::Name(RenameMode::To)), [path])
+                | (EventKind::Modify(ModifyKind::Name(RenameMode::Both)), [_, path]) => path,
+                _ => continue,
+            };
+            // Files moved out of the directory have events as well.
+            if path.parent() == Some(self.dir.as_path()) && is_input_file(path) {
+                self.process(path)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn load_engine<F>(create_engine: &mut F, state_file: &Path) -> anyhow::Result<PaymentsEngine>
+where
+    F: FnMut() -> anyhow::Result<PaymentsEngine>,
+{
+    let mut engine = create_engine()?;
+    if state_file.exists() {
+        state::restore(&mut engine, &state_file.as_os_str().to_os_string())
+            .context("can not restore state of watched directory")?;
+    }
+    Ok(engine)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::amount::Amount;
+
+    fn engine() -> anyhow::Result<PaymentsEngine> {
+        Ok(PaymentsEngine::default())
+    }
+
+    #[test]
+    fn test_process_pending_files() {
+        let dir = std::env::temp_dir().join("coding_test_watch");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("day-2.csv"),
+            "type,client,tx,amount\nwithdrawal,1,2,4\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("day-1.csv"),
+            "type,client,tx,amount\ndeposit,1,1,10\n",
+        )
+        .unwrap();
+        fs::write(dir.join("broken.json"), "{").unwrap();
+        fs::write(dir.join("notes.txt"), "not an input").unwrap();
+        fs::write(
+            dir.join(".day-3.csv"),
+            "type,client,tx,amount\ndeposit,1,3,10\n",
+        )
+        .unwrap();
+
+        let mut watch = DirectoryWatch::open(dir.clone(), None, engine).unwrap();
+        assert_eq!(watch.process_pending().unwrap(), 3);
+        assert_eq!(watch.engine().accounts()[&1].available(), Amount::from(6));
+        assert!(watch.pending().unwrap().is_empty());
+        assert!(dir.join("processed").join("day-1.csv").exists());
+        assert!(dir
+            .join("processed")
+            .join("failed")
+            .join("broken.json")
+            .exists());
+        assert!(dir.join("notes.txt").exists());
+
+        // A restarted watch continues from the saved state.
+        fs::write(
+            dir.join("day-1.csv"),
+            "type,client,tx,amount\ndeposit,1,4,1\n",
+        )
+        .unwrap();
+        let mut watch = DirectoryWatch::open(dir.clone(), None, engine).unwrap();
+        assert_eq!(watch.engine().accounts()[&1].available(), Amount::from(6));
+        assert_eq!(watch.process_pending().unwrap(), 1);
+        assert_eq!(watch.engine().accounts()[&1].available(), Amount::from(7));
+        assert!(dir.join("processed").join("day-1.csv.1").exists());
+
+        // Files are left in the directory after a shutdown.
+        fs::write(
+            dir.join("day-3.csv"),
+            "type,client,tx,amount\ndeposit,1,5,1\n",
+        )
+        .unwrap();
+        let shutdown = Shutdown::default();
+        shutdown.request();
+        watch.set_shutdown(shutdown);
+        assert_eq!(watch.process_pending().unwrap(), 0);
+        assert_eq!(watch.pending().unwrap().len(), 1);
+    }
+}